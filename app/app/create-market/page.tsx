@@ -1,6 +1,6 @@
 'use client';
 
-import { useState, useEffect } from 'react';
+import { useState } from 'react';
 import Link from 'next/link';
 import { useRouter } from 'next/navigation';
 import toast from 'react-hot-toast';
@@ -14,20 +14,11 @@ export default function CreateMarketPage() {
   const { initializeMarket } = usePredictionMarketProgram();
   const [loading, setLoading] = useState(false);
   const [formData, setFormData] = useState({
-    marketId: 0,
     question: '',
     daysUntilResolution: 7,
     initialLiquidity: 1,
   });
 
-  useEffect(() => {
-    // Generate market ID based on current timestamp
-    setFormData(prev => ({
-      ...prev,
-      marketId: Math.floor(Date.now() / 1000)
-    }));
-  }, []);
-
   const handleSubmit = async (e: React.FormEvent) => {
     e.preventDefault();
 
@@ -43,7 +34,6 @@ export default function CreateMarketPage() {
 
       await toast.promise(
         initializeMarket(
-          formData.marketId,
           formData.question,
           resolutionTime,
           formData.initialLiquidity * 1e9
@@ -57,7 +47,6 @@ export default function CreateMarketPage() {
 
       // Reset form for next market
       setFormData({
-        marketId: Math.floor(Date.now() / 1000),
         question: '',
         daysUntilResolution: 7,
         initialLiquidity: 1,
@@ -103,22 +92,6 @@ export default function CreateMarketPage() {
             <h2 className="text-3xl font-bold text-white mb-8">Create New Market</h2>
 
             <form onSubmit={handleSubmit} className="space-y-6">
-              {/* Market ID */}
-              <div>
-                <label className="block text-sm font-medium text-slate-300 mb-2">
-                  Market ID
-                </label>
-                <input
-                  type="text"
-                  value={formData.marketId}
-                  disabled
-                  className="w-full px-4 py-2 bg-slate-600 border border-slate-600 rounded-lg text-slate-400 cursor-not-allowed opacity-75"
-                />
-                <p className="text-xs text-slate-500 mt-1">
-                  Auto-generated based on current timestamp
-                </p>
-              </div>
-
               {/* Question */}
               <div>
                 <label className="block text-sm font-medium text-slate-300 mb-2">
@@ -4,12 +4,86 @@ import { Program, AnchorProvider, BN, Idl } from '@coral-xyz/anchor';
 import { PublicKey, SystemProgram } from '@solana/web3.js';
 import IDL from '../idl/prediction_market.json';
 import toast from 'react-hot-toast';
-import { getMarketPDA, getVaultPDA, getPredictionPDA } from '../utils/pda';
+import {
+  REGISTRY_PAGE_SIZE,
+  USER_INDEX_PAGE_SIZE,
+  getConfigPDA,
+  getCounterPDA,
+  getCreatorAllowPDA,
+  getCreatorStatsPDA,
+  getEpochStatsPDA,
+  getInsuranceFundPDA,
+  getMarketPDA,
+  getPredictionPDA,
+  getProfilePDA,
+  getQuestionRegistryPDA,
+  getRegistryPagePDA,
+  getResolutionPDA,
+  getSelfExclusionPDA,
+  getTreasuryPDA,
+  getUserIndexPagePDA,
+  getUserMarketIndexPDA,
+  getVaultPDA,
+} from '../utils/pda';
 
 type PredictionMarketIDL = Idl & { account: { market: unknown[] } };
 
 const PROGRAM_ID = new PublicKey('6ya283kCp8zAet2hnHQAokhDrBw1DiCdvPtWK3gWXVgp');
 
+const TOKEN_PROGRAM_ID = new PublicKey('TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA');
+const TOKEN_2022_PROGRAM_ID = new PublicKey('TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb');
+const NATIVE_MINT_2022 = new PublicKey('9pan9bMn5HatX4EJdBwg9VgCa7Uz5HL8N1m5D3NdXejP');
+
+// Market options for a plain AMM market with the protocol defaults
+const DEFAULT_MARKET_OPTIONS = {
+  timeDecay: false,
+  timeDecayFloorBps: 0,
+  oracleKey: null,
+  oracleEthAddress: null,
+  betRevealWindow: new BN(0),
+  priceOracle: null,
+  correctionWindowSecs: new BN(0),
+  claimPeriodSecs: new BN(0),
+  imbalanceRebateBps: 0,
+  feeCurve: null,
+  feeSchedule: null,
+  freezeBeforeCloseSecs: null,
+  exitsDuringFreeze: null,
+  wsolVault: false,
+  subjective: false,
+  cooloffSecs: new BN(0),
+  earlyExitPenaltyBps: 0,
+  mode: { amm: {} },
+  yesOddsBps: 0,
+  noOddsBps: 0,
+  winningsFeeBps: 0,
+  loserRebateBps: 0,
+  keeperBudget: new BN(0),
+  category: 0,
+  disputeWindowSecs: new BN(0),
+  openAuctionSecs: new BN(0),
+  maxMovePerTradeBps: 0,
+  maxMovePerWindowBps: 0,
+  tenant: Array(16).fill(0),
+  claimWindowSecs: new BN(0),
+  maxBet: new BN(0),
+  maxBetRampSecs: new BN(0),
+  maxBetRampStartBps: 0,
+  parent: null,
+  compressed: false,
+  reduceOnlyWindowSecs: new BN(0),
+  escrowDisputedClaims: false,
+  poolHistoryLen: 0,
+  resolutionProgram: null,
+  pushPolicy: { voidOnPush: {} },
+  maxParticipants: 0,
+  roundingPolicy: { favorPool: {} },
+};
+
+// Token program owning a wSOL market's collateral mint
+const collateralTokenProgram = (collateralMint: PublicKey) =>
+  collateralMint.equals(NATIVE_MINT_2022) ? TOKEN_2022_PROGRAM_ID : TOKEN_PROGRAM_ID;
+
 export function usePredictionMarketProgram() {
   const { connection } = useConnection();
   const wallet = useWallet();
@@ -30,10 +104,11 @@ export function usePredictionMarketProgram() {
     );
     return new Program(IDL as PredictionMarketIDL, provider);
   }, [connection, wallet.publicKey, wallet.signTransaction, wallet.signAllTransactions]);
-  
+
+  // Creates a market under the next ID from the market counter, seeded
+  // 50/50 at the config's default fee; returns the new market's ID
   const initializeMarket = useCallback(
     async (
-      marketId: number,
       question: string,
       resolutionTime: number,
       initialLiquidity: number
@@ -42,28 +117,55 @@ export function usePredictionMarketProgram() {
       if (!program) throw new Error('Wallet not connected');
       if (!wallet.publicKey) throw new Error('Wallet not connected');
 
-      const marketIdBN = new BN(marketId);
+      const [counterPDA] = getCounterPDA(PROGRAM_ID);
+      const [configPDA] = getConfigPDA(PROGRAM_ID);
+      const counter = await (program.account as any).marketCounter.fetch(counterPDA);
+      const config = await (program.account as any).config.fetch(configPDA);
+      const marketIdBN: BN = counter.nextId;
+      const resolutionTimeBN = new BN(resolutionTime);
       const [marketPDA] = getMarketPDA(marketIdBN, PROGRAM_ID);
       const [vaultPDA] = getVaultPDA(marketIdBN, PROGRAM_ID);
+      const [registryPagePDA] = getRegistryPagePDA(
+        config.registryLen.divn(REGISTRY_PAGE_SIZE),
+        PROGRAM_ID
+      );
+      const [creatorAllowPDA] = getCreatorAllowPDA(wallet.publicKey, PROGRAM_ID);
+      const [treasuryPDA] = getTreasuryPDA(PROGRAM_ID);
+      const approved = await connection.getAccountInfo(creatorAllowPDA);
+      const treasury = await connection.getAccountInfo(treasuryPDA);
 
-      return program.methods
+      await program.methods
         .initializeMarket(
-          marketIdBN,
           question,
-          new BN(resolutionTime),
-          new BN(initialLiquidity)
+          resolutionTimeBN,
+          new BN(initialLiquidity),
+          5_000,
+          0,
+          DEFAULT_MARKET_OPTIONS
         )
         .accounts({
+          marketCounter: counterPDA,
+          yesMint: null,
+          noMint: null,
           market: marketPDA,
+          config: configPDA,
+          registryPage: registryPagePDA,
           creator: wallet.publicKey,
           marketVault: vaultPDA,
-          yesTokenVault: vaultPDA,
-          noTokenVault: vaultPDA,
+          questionRegistry: getQuestionRegistryPDA(question, resolutionTimeBN, PROGRAM_ID)[0],
+          duplicateApprover: null,
+          series: null,
+          parentMarket: null,
+          creatorAllow: approved ? creatorAllowPDA : null,
+          creatorStats: getCreatorStatsPDA(wallet.publicKey, PROGRAM_ID)[0],
+          treasury: treasury ? treasuryPDA : null,
           systemProgram: SystemProgram.programId,
         })
         .rpc();
+
+      return marketIdBN.toNumber();
     },
-    [getProgram, wallet.publicKey]
+    [connection, getProgram, wallet.publicKey]
   );
 
   const placePrediction = useCallback(
@@ -75,15 +177,42 @@ export function usePredictionMarketProgram() {
       const marketIdBN = new BN(marketId);
       const [marketPDA] = getMarketPDA(marketIdBN, PROGRAM_ID);
       const [vaultPDA] = getVaultPDA(marketIdBN, PROGRAM_ID);
-      const [predictionPDA] = getPredictionPDA(marketIdBN, wallet.publicKey, PROGRAM_ID);
+      const [configPDA] = getConfigPDA(PROGRAM_ID);
+      const [profilePDA] = getProfilePDA(wallet.publicKey, PROGRAM_ID);
+      const market = await (program.account as any).market.fetch(marketPDA);
+      const config = await (program.account as any).config.fetch(configPDA);
+      const profile = await (program.account as any).userProfile.fetchNullable(profilePDA);
+      const [predictionPDA] = getPredictionPDA(
+        marketIdBN,
+        wallet.publicKey,
+        PROGRAM_ID,
+        0,
+        market.generation
+      );
+      const indexPage = profile ? Math.floor(profile.betsPlaced / USER_INDEX_PAGE_SIZE) : 0;
 
       return program.methods
-        .placePrediction(marketIdBN, predictionType, new BN(amount))
+        .placePrediction(marketIdBN, predictionType, new BN(amount), 0, '', false)
         .accounts({
           market: marketPDA,
           marketVault: vaultPDA,
+          config: configPDA,
           predictionAccount: predictionPDA,
+          userProfile: profilePDA,
+          userMarketIndex: getUserMarketIndexPDA(marketPDA, wallet.publicKey, PROGRAM_ID)[0],
+          userIndexPage: getUserIndexPagePDA(wallet.publicKey, indexPage, PROGRAM_ID)[0],
+          epochStats: getEpochStatsPDA(wallet.publicKey, config.currentEpoch, PROGRAM_ID)[0],
+          referrer: null,
+          nftTokenAccount: null,
+          nftMetadata: null,
+          series: market.series,
+          insuranceFund: getInsuranceFundPDA(PROGRAM_ID)[0],
+          wsolVault: market.wsolVault ? market.collateralVault : null,
+          creatorStats: getCreatorStatsPDA(market.liabilityCreator, PROGRAM_ID)[0],
+          selfExclusion: getSelfExclusionPDA(wallet.publicKey, PROGRAM_ID)[0],
           predictor: wallet.publicKey,
+          rentPayer: wallet.publicKey,
+          tokenProgram: market.wsolVault ? collateralTokenProgram(market.collateralMint) : null,
           systemProgram: SystemProgram.programId,
         })
         .rpc();
@@ -92,25 +221,34 @@ export function usePredictionMarketProgram() {
   );
 
   const resolveMarket = useCallback(
-    async (marketId: number, outcome: boolean) => {
+    async (marketId: number, outcome: boolean, evidence = '') => {
       const program = getProgram();
       if (!program) throw new Error('Wallet not connected');
       if (!wallet.publicKey) throw new Error('Wallet not connected');
 
       const marketIdBN = new BN(marketId);
       const [marketPDA] = getMarketPDA(marketIdBN, PROGRAM_ID);
+      const [vaultPDA] = getVaultPDA(marketIdBN, PROGRAM_ID);
+      const market = await (program.account as any).market.fetch(marketPDA);
 
       return program.methods
-        .resolveMarket(marketIdBN, outcome)
+        .resolveMarket(marketIdBN, outcome, evidence)
         .accounts({
           market: marketPDA,
+          marketVault: vaultPDA,
+          wsolVault: market.wsolVault ? market.collateralVault : null,
+          resolution: getResolutionPDA(marketIdBN, PROGRAM_ID)[0],
           admin: wallet.publicKey,
+          payer: wallet.publicKey,
+          systemProgram: SystemProgram.programId,
         })
         .rpc();
     },
     [getProgram, wallet.publicKey]
   );
 
+  // Claims the wallet's whole first position on the market, paid to the
+  // wallet; wSOL markets are claimed through the wallet's token account
   const claimReward = useCallback(
     async (marketId: number) => {
       const program = getProgram();
@@ -120,15 +258,39 @@ export function usePredictionMarketProgram() {
       const marketIdBN = new BN(marketId);
       const [marketPDA] = getMarketPDA(marketIdBN, PROGRAM_ID);
       const [vaultPDA] = getVaultPDA(marketIdBN, PROGRAM_ID);
-      const [predictionPDA] = getPredictionPDA(marketIdBN, wallet.publicKey, PROGRAM_ID);
+      const [configPDA] = getConfigPDA(PROGRAM_ID);
+      const market = await (program.account as any).market.fetch(marketPDA);
+      const config = await (program.account as any).config.fetch(configPDA);
+      const [predictionPDA] = getPredictionPDA(
+        marketIdBN,
+        wallet.publicKey,
+        PROGRAM_ID,
+        0,
+        market.generation
+      );
+      if (market.wsolVault) throw new Error('wSOL market claims are not supported here');
+      if (market.disputed && market.escrowDisputedClaims) {
+        throw new Error('Claims are escrowed while the market is disputed');
+      }
 
       return program.methods
-        .claimReward(marketIdBN)
+        .claimReward(marketIdBN, null, false, false)
         .accounts({
           market: marketPDA,
           marketVault: vaultPDA,
+          config: configPDA,
           predictionAccount: predictionPDA,
+          userProfile: getProfilePDA(wallet.publicKey, PROGRAM_ID)[0],
+          epochStats: getEpochStatsPDA(wallet.publicKey, config.currentEpoch, PROGRAM_ID)[0],
           claimer: wallet.publicKey,
+          recipient: null,
+          wsolVault: null,
+          payoutTokenAccount: null,
+          collateralMint: null,
+          tokenProgram: null,
+          claimReceipt: null,
+          escrowedClaim: null,
+          systemProgram: SystemProgram.programId,
         })
         .rpc();
     },
@@ -143,8 +305,8 @@ export function usePredictionMarketProgram() {
       // Get all accounts for the Market type
       if (!("market" in program.account)) return [];
       const markets = await (program.account as any).market.all();
-      
-      return markets.map((account: { account: { marketId: BN; question: string; creator: PublicKey; createdAt: BN; resolutionTime: BN; yesPool: BN; noPool: BN; totalLiquidity: BN; resolved: boolean; outcome: boolean | null; yesTokenVault: PublicKey; noTokenVault: PublicKey; feeCollected: BN } }) => ({
+
+      return markets.map((account: { account: { marketId: BN; question: string; creator: PublicKey; createdAt: BN; resolutionTime: BN; yesPool: BN; noPool: BN; totalLiquidity: BN; resolved: boolean; outcome: Record<string, object>; generation: BN; feeCollected: BN } }) => ({
         marketId: account.account.marketId.toNumber(),
         question: account.account.question,
         creator: account.account.creator,
@@ -154,9 +316,9 @@ export function usePredictionMarketProgram() {
         noPool: account.account.noPool.toNumber(),
         totalLiquidity: account.account.totalLiquidity.toNumber(),
         resolved: account.account.resolved,
-        outcome: account.account.outcome,
-        yesTokenVault: account.account.yesTokenVault,
-        noTokenVault: account.account.noTokenVault,
+        // "unresolved", "yes", "no" or "void"
+        outcome: Object.keys(account.account.outcome)[0],
+        generation: account.account.generation.toNumber(),
         feeCollected: account.account.feeCollected.toNumber(),
         initialLiquidity: account.account.totalLiquidity.toNumber() / 2,
       }));
@@ -174,4 +336,4 @@ export function usePredictionMarketProgram() {
     fetchMarkets,
     program: getProgram(),
   };
-}
\ No newline at end of file
+}
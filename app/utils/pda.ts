@@ -1,5 +1,42 @@
 import { PublicKey } from '@solana/web3.js';
 import BN from 'bn.js';
+import { keccak_256 } from '@noble/hashes/sha3';
+
+/** Markets per registry page, as REGISTRY_PAGE_SIZE in the program */
+export const REGISTRY_PAGE_SIZE = 32;
+
+/** Positions per user index page, as USER_INDEX_PAGE_SIZE in the program */
+export const USER_INDEX_PAGE_SIZE = 32;
+
+/** Seconds per question registry bucket, as QUESTION_BUCKET_SECS in the program */
+const QUESTION_BUCKET_SECS = 24 * 60 * 60;
+
+/**
+ * Derives the protocol Config PDA
+ * Seeds: ["config"]
+ */
+export const getConfigPDA = (programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync([Buffer.from("config")], programId);
+};
+
+/**
+ * Derives the MarketCounter PDA assigning market IDs
+ * Seeds: ["counter"]
+ */
+export const getCounterPDA = (programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync([Buffer.from("counter")], programId);
+};
+
+/**
+ * Derives a market registry page PDA
+ * Seeds: ["registry", page_index as u64 LE bytes]
+ */
+export const getRegistryPagePDA = (pageIndex: BN, programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("registry"), pageIndex.toArrayLike(Buffer, "le", 8)],
+    programId
+  );
+};
 
 /**
  * Derives the Market PDA for a given market ID
@@ -25,19 +62,179 @@ export const getVaultPDA = (marketId: BN, programId: PublicKey) => {
 
 /**
  * Derives the Prediction PDA for a given market ID and predictor
- * Seeds: ["prediction", market_id as u64 LE bytes, predictor pubkey]
+ * Seeds: ["prediction", market_id as u64 LE bytes, predictor pubkey,
+ * position_index as u32 LE bytes unless 0, generation as u64 LE bytes
+ * unless 0]. generation is the market's, nonzero only under a reused
+ * explicit ID
  */
 export const getPredictionPDA = (
   marketId: BN,
   predictor: PublicKey,
+  programId: PublicKey,
+  positionIndex = 0,
+  generation = new BN(0)
+) => {
+  const seeds = [
+    Buffer.from("prediction"),
+    marketId.toArrayLike(Buffer, "le", 8),
+    predictor.toBuffer(),
+  ];
+  if (positionIndex > 0) {
+    const indexBytes = Buffer.alloc(4);
+    indexBytes.writeUInt32LE(positionIndex);
+    seeds.push(indexBytes);
+  }
+  if (!generation.isZero()) {
+    seeds.push(generation.toArrayLike(Buffer, "le", 8));
+  }
+  return PublicKey.findProgramAddressSync(seeds, programId);
+};
+
+/**
+ * Derives a user's UserProfile PDA
+ * Seeds: ["profile", user pubkey]
+ */
+export const getProfilePDA = (user: PublicKey, programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("profile"), user.toBuffer()],
+    programId
+  );
+};
+
+/**
+ * Derives the UserMarketIndex PDA of a user's positions on a market
+ * Seeds: ["index", market pubkey, user pubkey]
+ */
+export const getUserMarketIndexPDA = (
+  market: PublicKey,
+  user: PublicKey,
+  programId: PublicKey
+) => {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("index"), market.toBuffer(), user.toBuffer()],
+    programId
+  );
+};
+
+/**
+ * Derives a page of a user's position index; a new prediction goes on
+ * page bets_placed / USER_INDEX_PAGE_SIZE of the user's profile
+ * Seeds: ["user_index", user pubkey, page as u32 LE bytes]
+ */
+export const getUserIndexPagePDA = (
+  user: PublicKey,
+  page: number,
+  programId: PublicKey
+) => {
+  const pageBytes = Buffer.alloc(4);
+  pageBytes.writeUInt32LE(page);
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("user_index"), user.toBuffer(), pageBytes],
+    programId
+  );
+};
+
+/**
+ * Derives a user's stats PDA for a leaderboard epoch
+ * Seeds: ["epoch_stats", user pubkey, epoch as u64 LE bytes]
+ */
+export const getEpochStatsPDA = (
+  user: PublicKey,
+  epoch: BN,
   programId: PublicKey
 ) => {
   return PublicKey.findProgramAddressSync(
     [
-      Buffer.from("prediction"),
-      marketId.toArrayLike(Buffer, "le", 8),
-      predictor.toBuffer(),
+      Buffer.from("epoch_stats"),
+      user.toBuffer(),
+      epoch.toArrayLike(Buffer, "le", 8),
     ],
     programId
   );
 };
+
+/**
+ * Derives a creator's CreatorStats PDA
+ * Seeds: ["creator_stats", creator pubkey]
+ */
+export const getCreatorStatsPDA = (creator: PublicKey, programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("creator_stats"), creator.toBuffer()],
+    programId
+  );
+};
+
+/**
+ * Derives a creator's allowlist entry PDA
+ * Seeds: ["creator_allow", creator pubkey]
+ */
+export const getCreatorAllowPDA = (creator: PublicKey, programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("creator_allow"), creator.toBuffer()],
+    programId
+  );
+};
+
+/**
+ * Derives a user's self-exclusion PDA
+ * Seeds: ["self_exclusion", user pubkey]
+ */
+export const getSelfExclusionPDA = (user: PublicKey, programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("self_exclusion"), user.toBuffer()],
+    programId
+  );
+};
+
+/**
+ * Derives the protocol Treasury PDA
+ * Seeds: ["treasury"]
+ */
+export const getTreasuryPDA = (programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync([Buffer.from("treasury")], programId);
+};
+
+/**
+ * Derives the InsuranceFund PDA
+ * Seeds: ["insurance"]
+ */
+export const getInsuranceFundPDA = (programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync([Buffer.from("insurance")], programId);
+};
+
+/**
+ * Derives the resolution record PDA of a market
+ * Seeds: ["resolution", market_id as u64 LE bytes]
+ */
+export const getResolutionPDA = (marketId: BN, programId: PublicKey) => {
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("resolution"), marketId.toArrayLike(Buffer, "le", 8)],
+    programId
+  );
+};
+
+/** Mirrors normalize_question in the program */
+export const normalizeQuestion = (question: string) =>
+  question.trim().split(/\s+/).join(" ").toLowerCase();
+
+/**
+ * Derives the QuestionRegistry PDA for a question and resolution day
+ * Seeds: ["question", keccak(normalized question, day as u64 LE bytes)]
+ */
+export const getQuestionRegistryPDA = (
+  question: string,
+  resolutionTime: BN,
+  programId: PublicKey
+) => {
+  const bucket = new BN(Math.floor(resolutionTime.toNumber() / QUESTION_BUCKET_SECS));
+  const hash = keccak_256(
+    Buffer.concat([
+      Buffer.from(normalizeQuestion(question)),
+      bucket.toArrayLike(Buffer, "le", 8),
+    ])
+  );
+  return PublicKey.findProgramAddressSync(
+    [Buffer.from("question"), Buffer.from(hash)],
+    programId
+  );
+};
@@ -0,0 +1,335 @@
+// Minimal integration program: places predictions on the prediction market
+// via CPI, betting from a PDA it signs for, and resolves markets as a PDA
+// resolver the way a DAO or multisig vault would. It also stands in for a
+// yield adapter, holding a market's idle funds in a position PDA, and for an
+// adjudication program that markets hand their resolution to
+
+// Anchor 0.31's generated IDL instructions call the deprecated
+// AccountInfo::realloc, outside any item an allow could sit on
+#![allow(deprecated)]
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use prediction_market::cpi::accounts::{PlacePrediction, ResolveMarket, ResolveViaProgram};
+use prediction_market::program::PredictionMarket;
+
+declare_id!("CJBy65XRGgcGBV5yvTpVYfFCv29ifSk34eqbbsEtof4P");
+
+#[program]
+pub mod prediction_caller {
+    use super::*;
+
+    /// Fund the user's bettor PDA and place a prediction with it
+    /// `funding` must cover the stake plus rent for the accounts the
+    /// prediction market creates for a first bet
+    pub fn place_prediction(
+        ctx: Context<PlaceViaCpi>,
+        market_id: u64,
+        prediction_type: bool,
+        amount: u64,
+        funding: u64,
+    ) -> Result<()> {
+        system_program::transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                system_program::Transfer {
+                    from: ctx.accounts.user.to_account_info(),
+                    to: ctx.accounts.bettor.to_account_info(),
+                },
+            ),
+            funding,
+        )?;
+
+        // The bettor PDA is the predictor, so it signs (and pays) via seeds
+        let user = ctx.accounts.user.key();
+        let seeds: &[&[u8]] = &[b"bettor", user.as_ref(), &[ctx.bumps.bettor]];
+
+        prediction_market::instructions::place_prediction(
+            ctx.accounts.prediction_market_program.to_account_info(),
+            PlacePrediction {
+                market: ctx.accounts.market.to_account_info(),
+                market_vault: ctx.accounts.market_vault.to_account_info(),
+                config: ctx.accounts.config.to_account_info(),
+                prediction_account: ctx.accounts.prediction_account.to_account_info(),
+                user_profile: ctx.accounts.user_profile.to_account_info(),
+                user_market_index: ctx.accounts.user_market_index.to_account_info(),
+                user_index_page: ctx.accounts.user_index_page.to_account_info(),
+                epoch_stats: ctx.accounts.epoch_stats.to_account_info(),
+                referrer: None,
+                nft_token_account: None,
+                nft_metadata: None,
+                series: None,
+                insurance_fund: ctx.accounts.insurance_fund.to_account_info(),
+                wsol_vault: None,
+                creator_stats: ctx.accounts.creator_stats.to_account_info(),
+                self_exclusion: ctx.accounts.self_exclusion.to_account_info(),
+                predictor: ctx.accounts.bettor.to_account_info(),
+                rent_payer: ctx.accounts.bettor.to_account_info(),
+                token_program: None,
+                system_program: ctx.accounts.system_program.to_account_info(),
+                event_authority: ctx.accounts.event_authority.to_account_info(),
+                program: ctx.accounts.prediction_market_program.to_account_info(),
+            },
+            &[seeds],
+            market_id,
+            prediction_type,
+            amount,
+            0,
+            String::new(),
+        )
+    }
+
+    /// Resolve a market whose resolver is this program's resolver PDA,
+    /// signing for it with its seeds. The user pays for the resolution
+    /// account, so the PDA holds no lamports
+    pub fn resolve_market(ctx: Context<ResolveViaCpi>, market_id: u64, outcome: bool) -> Result<()> {
+        let seeds: &[&[u8]] = &[b"resolver", &[ctx.bumps.resolver]];
+
+        prediction_market::instructions::resolve_market(
+            ctx.accounts.prediction_market_program.to_account_info(),
+            ResolveMarket {
+                market: ctx.accounts.market.to_account_info(),
+                market_vault: ctx.accounts.market_vault.to_account_info(),
+                wsol_vault: None,
+                resolution: ctx.accounts.resolution.to_account_info(),
+                admin: ctx.accounts.resolver.to_account_info(),
+                payer: ctx.accounts.user.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                event_authority: ctx.accounts.event_authority.to_account_info(),
+                program: ctx.accounts.prediction_market_program.to_account_info(),
+            },
+            &[seeds],
+            market_id,
+            outcome,
+            String::new(),
+        )
+    }
+
+    /// Resolve a market whose resolution_program is this program, as an
+    /// adjudicator would once it had settled on `outcome`
+    pub fn adjudicate(ctx: Context<Adjudicate>, market_id: u64, outcome: bool) -> Result<()> {
+        prediction_market::instructions::resolve_via_program(
+            ctx.accounts.prediction_market_program.to_account_info(),
+            ResolveViaProgram {
+                market: ctx.accounts.market.to_account_info(),
+                market_vault: ctx.accounts.market_vault.to_account_info(),
+                wsol_vault: None,
+                resolution: ctx.accounts.resolution.to_account_info(),
+                payer: ctx.accounts.user.to_account_info(),
+                instructions: ctx.accounts.instructions.to_account_info(),
+                system_program: ctx.accounts.system_program.to_account_info(),
+                event_authority: ctx.accounts.event_authority.to_account_info(),
+                program: ctx.accounts.prediction_market_program.to_account_info(),
+            },
+            market_id,
+            outcome,
+            String::new(),
+        )
+    }
+
+    /// Open the position PDA a market's idle funds are deposited into
+    pub fn open_yield_position(ctx: Context<OpenYieldPosition>) -> Result<()> {
+        let position = &mut ctx.accounts.position;
+        position.market = ctx.accounts.market.key();
+        position.principal = 0;
+        position.bump = ctx.bumps.position;
+        Ok(())
+    }
+
+    /// Adapter side of deposit_idle: the market has already moved `amount`
+    /// into the position, so only the principal is booked
+    pub fn yield_deposit(ctx: Context<YieldAdapterCall>, amount: u64) -> Result<()> {
+        let position = &mut ctx.accounts.position;
+        position.principal = position.principal.saturating_add(amount);
+        Ok(())
+    }
+
+    /// Adapter side of withdraw_idle: pays `amount` of principal back to the
+    /// market's vault, with its pro-rata share of whatever the position
+    /// holds above principal and rent as yield
+    pub fn yield_withdraw(ctx: Context<YieldAdapterCall>, amount: u64) -> Result<()> {
+        let position = &mut ctx.accounts.position;
+        require!(
+            amount > 0 && amount <= position.principal,
+            CallerError::ExceedsPrincipal
+        );
+        let info = position.to_account_info();
+        let rent = Rent::get()?.minimum_balance(info.data_len());
+        let surplus = info
+            .lamports()
+            .saturating_sub(rent)
+            .saturating_sub(position.principal);
+        let earned = ((surplus as u128) * (amount as u128) / (position.principal as u128)) as u64;
+        position.principal -= amount;
+
+        **info.try_borrow_mut_lamports()? -= amount + earned;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? += amount + earned;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct PlaceViaCpi<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// System-owned PDA that holds the stake and bets for the user
+    #[account(mut, seeds = [b"bettor", user.key().as_ref()], bump)]
+    pub bettor: SystemAccount<'info>,
+
+    /// CHECK: Validated by the prediction market
+    #[account(mut)]
+    pub market: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the prediction market
+    #[account(mut)]
+    pub market_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the prediction market
+    pub config: UncheckedAccount<'info>,
+
+    /// CHECK: Created by the prediction market
+    #[account(mut)]
+    pub prediction_account: UncheckedAccount<'info>,
+
+    /// CHECK: Created or updated by the prediction market
+    #[account(mut)]
+    pub user_profile: UncheckedAccount<'info>,
+
+    /// CHECK: Created or updated by the prediction market
+    #[account(mut)]
+    pub user_market_index: UncheckedAccount<'info>,
+
+    /// CHECK: Created or updated by the prediction market
+    #[account(mut)]
+    pub user_index_page: UncheckedAccount<'info>,
+
+    /// CHECK: Created or updated by the prediction market
+    #[account(mut)]
+    pub epoch_stats: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the prediction market
+    #[account(mut)]
+    pub insurance_fund: UncheckedAccount<'info>,
+
+    /// CHECK: Created or updated by the prediction market
+    #[account(mut)]
+    pub creator_stats: UncheckedAccount<'info>,
+
+    /// CHECK: The bettor PDA's self-exclusion, read by the prediction market
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    /// CHECK: The prediction market's event authority PDA
+    pub event_authority: UncheckedAccount<'info>,
+
+    pub prediction_market_program: Program<'info, PredictionMarket>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ResolveViaCpi<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: PDA set as a market resolver; signs via seeds and holds nothing
+    #[account(seeds = [b"resolver"], bump)]
+    pub resolver: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the prediction market
+    #[account(mut)]
+    pub market: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the prediction market
+    pub market_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Created or updated by the prediction market
+    #[account(mut)]
+    pub resolution: UncheckedAccount<'info>,
+
+    /// CHECK: The prediction market's event authority PDA
+    pub event_authority: UncheckedAccount<'info>,
+
+    pub prediction_market_program: Program<'info, PredictionMarket>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Adjudicate<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// CHECK: Validated by the prediction market
+    #[account(mut)]
+    pub market: UncheckedAccount<'info>,
+
+    /// CHECK: Validated by the prediction market
+    pub market_vault: UncheckedAccount<'info>,
+
+    /// CHECK: Created or updated by the prediction market
+    #[account(mut)]
+    pub resolution: UncheckedAccount<'info>,
+
+    /// CHECK: Instructions sysvar, checked by the prediction market
+    pub instructions: UncheckedAccount<'info>,
+
+    /// CHECK: The prediction market's event authority PDA
+    pub event_authority: UncheckedAccount<'info>,
+
+    pub prediction_market_program: Program<'info, PredictionMarket>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct OpenYieldPosition<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Any prediction market; only its key is recorded
+    pub market: UncheckedAccount<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + YieldPosition::INIT_SPACE,
+        seeds = [b"yield_position", market.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, YieldPosition>,
+
+    pub system_program: Program<'info, System>,
+}
+
+/// Accounts the prediction market passes to a yield adapter
+#[derive(Accounts)]
+pub struct YieldAdapterCall<'info> {
+    /// The market PDA, signing through the prediction market
+    pub market: Signer<'info>,
+
+    /// CHECK: The market's vault, credited on withdraw
+    #[account(mut)]
+    pub market_vault: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"yield_position", market.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, YieldPosition>,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct YieldPosition {
+    pub market: Pubkey,
+    pub principal: u64,
+    pub bump: u8,
+}
+
+#[error_code]
+pub enum CallerError {
+    #[msg("Withdrawal exceeds the position's principal")]
+    ExceedsPrincipal,
+}
@@ -2,466 +2,22455 @@
 // Built with Anchor Framework
 // Portfolio Project: Sports Prediction Market on Solana
 
+// Anchor 0.31's generated IDL instructions call the deprecated
+// AccountInfo::realloc, outside any item an allow could sit on
+#![allow(deprecated)]
+// The cpi module's wrappers copy each handler's arguments without its allows
+#![cfg_attr(feature = "cpi", allow(clippy::too_many_arguments))]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::instruction::{
+    get_stack_height, Instruction, TRANSACTION_LEVEL_STACK_HEIGHT,
+};
+use anchor_lang::solana_program::entrypoint::MAX_PERMITTED_DATA_INCREASE;
+use anchor_lang::solana_program::keccak;
+use anchor_lang::solana_program::{bpf_loader_upgradeable, ed25519_program, secp256k1_program};
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_spl::token::spl_token::native_mint;
+use anchor_spl::token::spl_token::instruction::AuthorityType;
+use anchor_spl::token::{self, Burn, Mint, SetAuthority, Token, TokenAccount, Transfer};
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{BaseStateWithExtensions, ExtensionType, StateWithExtensions},
+};
+use anchor_spl::token_interface::{self, SyncNative, TokenInterface, TransferChecked};
 use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::metadata::mpl_token_metadata::{self, accounts::Metadata, types::DataV2};
+use anchor_spl::metadata::{
+    create_metadata_accounts_v3, CreateMetadataAccountsV3, Metadata as TokenMetadata,
+};
 
 declare_id!("6ya283kCp8zAet2hnHQAokhDrBw1DiCdvPtWK3gWXVgp");
 
+/// Default minimum time between market creation and resolution (10 minutes)
+pub const DEFAULT_MIN_MARKET_DURATION: i64 = 10 * 60;
+/// Default maximum time between market creation and resolution (2 years)
+pub const DEFAULT_MAX_MARKET_DURATION: i64 = 2 * 365 * 24 * 60 * 60;
+/// Basis-point denominator used by every fee and share calculation
+pub const BPS_DENOMINATOR: u64 = 10_000;
+/// Fixed-point scale for intermediate pricing values (1e9 = one token)
+pub const PRICE_SCALE: u128 = 1_000_000_000;
+/// Default trading fee charged on each prediction (1%)
+pub const DEFAULT_TRADING_FEE_BPS: u16 = 100;
+/// Bounds (bps) on the starting YES probability a creator may seed
+pub const MIN_INITIAL_PROBABILITY_BPS: u16 = 200;
+pub const MAX_INITIAL_PROBABILITY_BPS: u16 = 9_800;
+/// Starting YES probability of markets created from a template (50%)
+pub const EVEN_ODDS_BPS: u16 = 5_000;
+/// Default ceiling on any fee a market's fee schedule may charge (10%)
+pub const DEFAULT_MAX_FEE_BPS: u16 = 1_000;
+/// Default floor on a creator-chosen market fee (0.1%)
+pub const DEFAULT_MIN_FEE_BPS: u16 = 10;
+/// Default minimum time between a market's last trade and its resolution
+pub const DEFAULT_MIN_SETTLE_DELAY: i64 = 60;
+/// Default time past betting close after which an unresolved market can be
+/// flagged stale (7 days)
+pub const DEFAULT_STALE_AFTER_SECS: i64 = 7 * 24 * 60 * 60;
+/// Default share of the trading fee paid to a bettor's referrer (20%)
+pub const DEFAULT_REFERRAL_FEE_BPS: u16 = 2_000;
+/// Default share of kept trading fees paid into the insurance fund (10%)
+pub const DEFAULT_INSURANCE_BPS: u16 = 1_000;
+/// Maximum length (bytes) of the reason given for a shortfall cover
+pub const MAX_SHORTFALL_REASON_LEN: usize = 128;
+/// Maximum length (bytes) of the reason given for an emergency cancel
+pub const MAX_CANCEL_REASON_LEN: usize = 128;
+/// Most owners an admin multisig can have
+pub const MAX_MULTISIG_OWNERS: usize = 5;
+/// Most accounts, and bytes of instruction data, an admin multisig
+/// Invoke action may carry
+pub const MAX_ADMIN_TX_ACCOUNTS: usize = 16;
+pub const MAX_ADMIN_TX_DATA: usize = 512;
+/// Maximum length (bytes) of a sponsor's display name
+pub const MAX_SPONSOR_NAME_LEN: usize = 32;
+/// Default delay between proposing and executing a config change (2 days)
+pub const DEFAULT_CONFIG_DELAY: i64 = 2 * 24 * 60 * 60;
+/// Default leaderboard epoch length (1 week)
+pub const DEFAULT_EPOCH_LENGTH: i64 = 7 * 24 * 60 * 60;
+/// Number of market pubkeys held by each registry page
+pub const REGISTRY_PAGE_SIZE: usize = 32;
+/// Number of market IDs held by each page of a user's market index
+pub const USER_INDEX_PAGE_SIZE: usize = 32;
+/// Number of resting-order slots in a market's order book
+pub const ORDER_BOOK_CAPACITY: usize = 64;
+/// Marks the end of the order book's free list
+pub const ORDER_SLOT_NONE: u16 = u16::MAX;
+/// Maximum number of legs in a parlay
+pub const MAX_PARLAY_LEGS: usize = 5;
+/// Maximum length (bytes) of a market series name
+pub const MAX_SERIES_NAME_LEN: usize = 32;
+/// Maximum number of `{N}` placeholders in a template question
+pub const MAX_TEMPLATE_ARGS: usize = 4;
+/// Length of an oracle attestation message: market_id, outcome, timestamp,
+/// expires_at
+pub const ATTESTATION_MESSAGE_LEN: usize = 8 + 1 + 8 + 8;
+/// Length of an EVM oracle attestation: program id, market_id, outcome, deadline
+pub const ETH_ATTESTATION_MESSAGE_LEN: usize = 32 + 8 + 1 + 8;
+/// Minimum seconds between commit_resolution and reveal_resolution
+pub const RESOLUTION_REVEAL_DELAY: i64 = 5;
+/// Seconds after set_resolvers reassigns a revoked market's resolvers
+/// before they may act, so a revocation can be repeated against a bad
+/// reassignment
+pub const RESOLVER_REASSIGN_DELAY: i64 = 60 * 60;
+/// Share of escrow (bps) forfeited by a bet commitment left unrevealed
+pub const BET_COMMIT_PENALTY_BPS: u16 = 100;
+/// Share of a limit entry (bps) paid to the keeper that fills it
+pub const LIMIT_ENTRY_TIP_BPS: u16 = 10;
+/// Share of stop-loss proceeds (bps) paid to the executing keeper
+pub const STOP_LOSS_TIP_BPS: u16 = 50;
+/// Most a keeper is paid for executing a stop-loss (0.01 SOL)
+pub const MAX_STOP_LOSS_TIP: u64 = 10_000_000;
+/// Price samples kept per market for TWAP resolution
+pub const TWAP_SAMPLE_CAPACITY: usize = 32;
+/// Lamports paid from market fees to whoever records a price sample
+pub const PRICE_SAMPLE_TIP: u64 = 5_000;
+/// Slots in the config's per-category defaults table
+pub const MAX_CATEGORY_DEFAULTS: usize = 8;
+/// Seconds over which max_move_per_window_bps bounds a market's cumulative
+/// implied-probability move
+pub const CIRCUIT_BREAKER_WINDOW_SECS: i64 = 60 * 60;
+/// Number of keeper actions with a configurable tip; see KeeperAction
+pub const KEEPER_ACTION_COUNT: usize = 6;
+/// Vault shortfall (lamports) beyond which reconcile_vault halts betting
+pub const MAX_VAULT_DRIFT: u64 = 10_000;
+/// Fixed-point scale of the incentive reward-per-share accumulator
+pub const INCENTIVE_ACC_SCALE: u128 = 1_000_000_000_000;
+/// Fixed-point scale of the staking vault's reward-per-token accumulator
+pub const STAKING_ACC_SCALE: u128 = 1_000_000_000_000;
+/// Width of the resolution-time buckets within which equal questions are
+/// treated as duplicates (1 day)
+pub const QUESTION_BUCKET_SECS: i64 = 24 * 60 * 60;
+/// Maximum length (bytes) of a market's question, and of a template's
+/// question format
+pub const MAX_QUESTION_LEN: usize = 256;
+/// Maximum length (bytes) of a market's resolution-criteria description
+pub const MAX_DESCRIPTION_LEN: usize = 1024;
+/// Symbol shared by every market's YES and NO outcome tokens
+pub const OUTCOME_TOKEN_SYMBOL: &str = "MKT";
+/// Maximum length (bytes) of an outcome token's off-chain metadata URI
+pub const MAX_METADATA_URI_LEN: usize = 200;
+/// Maximum length (bytes) of the evidence a resolver records, e.g. a URL
+pub const MAX_EVIDENCE_LEN: usize = 200;
+/// Maximum length (bytes) of the note a bettor attaches to a prediction
+pub const MAX_NOTE_LEN: usize = 64;
+/// Current layout version of Market accounts
+/// Version 6 adds generation and live_predictions after net_volume;
+/// version 5 moves creator, resolution_time, resolved, outcome and
+/// category ahead of the question, to fixed offsets; version 4 replaces
+/// the two token vault keys with optional outcome mints; version 3 adds
+/// the tenant after market_id; version 2 stores the outcome as a
+/// MarketOutcome where version 1 stored an Option<bool>. Older versions
+/// are rewritten by migrate_market
+pub const MARKET_VERSION: u8 = 6;
+/// Byte offset of Market::tenant in the account (discriminator, version,
+/// market_id), for memcmp filters selecting one tenant's markets
+pub const MARKET_TENANT_OFFSET: usize = 8 + 1 + 8;
+/// Byte offsets of the fixed-size fields Market keeps ahead of the
+/// question, for memcmp filters by creator, resolution time, status and
+/// category. The outcome byte is the MarketOutcome variant index
+pub const MARKET_CREATOR_OFFSET: usize = MARKET_TENANT_OFFSET + 16;
+pub const MARKET_RESOLUTION_TIME_OFFSET: usize = MARKET_CREATOR_OFFSET + 32;
+pub const MARKET_RESOLVED_OFFSET: usize = MARKET_RESOLUTION_TIME_OFFSET + 8;
+pub const MARKET_OUTCOME_OFFSET: usize = MARKET_RESOLVED_OFFSET + 1;
+pub const MARKET_CATEGORY_OFFSET: usize = MARKET_OUTCOME_OFFSET + 1;
+/// Byte offset of Market::question, the first variable-length field;
+/// offsets from here on differ between markets
+pub const MARKET_QUESTION_OFFSET: usize = MARKET_CATEGORY_OFFSET + 1;
+/// Slots in the config's tenant table
+pub const MAX_TENANTS: usize = 8;
+/// Window over which a tenant's market creation quota is counted
+pub const TENANT_QUOTA_WINDOW_SECS: i64 = 24 * 60 * 60;
+/// Length of the UTC day a creator's daily market creation limit counts
+pub const CREATION_DAY_SECS: i64 = 24 * 60 * 60;
+/// How long a ClaimReceipt must stand before its claimer may close it
+pub const CLAIM_RECEIPT_LOCK_SECS: i64 = 90 * 24 * 60 * 60;
+/// Most pool checkpoints a market may keep for replay_market_events
+pub const MAX_POOL_HISTORY: u8 = 64;
+/// Most house accounts a market may name
+pub const MAX_HOUSE_ACCOUNTS: usize = 4;
+/// Decimals of native SOL collateral, counted in lamports
+pub const SOL_DECIMALS: u8 = 9;
+/// Decimals events normalize collateral amounts to, whatever the collateral
+pub const NORMALIZED_DECIMALS: u8 = 9;
+/// Config::feature_flags bits, one per gated instruction. Betting,
+/// claiming and refunds have no bit, so no flag setting can stop them
+pub const FEATURE_EARLY_EXIT: u64 = 1 << 0;
+pub const FEATURE_LIMIT_ENTRIES: u64 = 1 << 1;
+pub const FEATURE_ORDER_BOOK: u64 = 1 << 2;
+pub const FEATURE_PARLAYS: u64 = 1 << 3;
+/// Every defined feature bit; set_feature_flags rejects any other
+pub const ALL_FEATURES: u64 =
+    FEATURE_EARLY_EXIT | FEATURE_LIMIT_ENTRIES | FEATURE_ORDER_BOOK | FEATURE_PARLAYS;
+/// Instructions a yield adapter implements, called by deposit_idle and
+/// withdraw_idle through invoke_yield_adapter
+pub const YIELD_ADAPTER_DEPOSIT: &str = "yield_deposit";
+pub const YIELD_ADAPTER_WITHDRAW: &str = "yield_withdraw";
+/// Most of a market's collateral Config::max_idle_bps may let a yield
+/// adapter hold
+pub const MAX_IDLE_BPS: u16 = 5_000;
+/// Bounds on how far out a self-exclusion may run, from when it is set
+pub const MIN_SELF_EXCLUSION_SECS: i64 = 24 * 60 * 60;
+pub const MAX_SELF_EXCLUSION_SECS: i64 = 365 * 24 * 60 * 60;
+/// Depth of a compressed market's receipt tree, which holds 2^depth bets
+pub const RECEIPT_TREE_DEPTH: usize = 20;
+/// Bytes in one page of a compressed market's claimed-receipt bitmap
+pub const RECEIPT_CLAIMS_PAGE_BYTES: usize = 1024;
+/// Current layout version of Prediction accounts
+/// Version 2 records the entry snapshot; version 1 accounts read it as zero
+pub const PREDICTION_VERSION: u8 = 2;
+/// Caller-chosen market IDs live in the upper half of the ID space
+pub const EXPLICIT_MARKET_ID_START: u64 = 1 << 63;
+
 #[program]
 pub mod prediction_market {
     use super::*;
 
-    /// Initialize a new prediction market
-    /// Admin creates a market with:
-    /// - question: "Will SOL price exceed $200 by end of week?"
-    /// - resolution_time: timestamp when market resolves
-    /// - yes_token_mint: mint for YES positions
-    /// - no_token_mint: mint for NO positions
-    pub fn initialize_market(
-        ctx: Context<InitializeMarket>,
-        market_id: u64,
-        question: String,
-        resolution_time: i64,
-        initial_liquidity: u64,
+    /// Initialize the global protocol config
+    /// Called once after deployment by the program's upgrade authority,
+    /// which becomes the config admin
+    pub fn initialize_config(ctx: Context<InitializeConfig>, params: ConfigParams) -> Result<()> {
+        params.validate()?;
+
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.bump = ctx.bumps.config;
+        config.current_epoch = 0;
+        config.epoch_started_at = Clock::get()?.unix_timestamp;
+        config.paused_at = 0;
+        config.feature_flags = ALL_FEATURES;
+        params.apply(config);
+
+        let counter = &mut ctx.accounts.market_counter;
+        counter.next_id = 0;
+        counter.bump = ctx.bumps.market_counter;
+
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_deposited = 0;
+        fund.total_covered = 0;
+        fund.bump = ctx.bumps.insurance_fund;
+
+        emit_cpi!(log_event(ConfigUpdated {
+            admin: config.admin,
+            min_market_duration: config.min_market_duration,
+            max_market_duration: config.max_market_duration,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin updates protocol parameters directly. Only allowed
+    /// while no timelock is set; after that, use propose_config_update
+    pub fn update_config(ctx: Context<UpdateConfig>, params: ConfigParams) -> Result<()> {
+        let event = update_config_now(&mut ctx.accounts.config, &params)?;
+        emit_cpi!(log_event(event));
+
+        Ok(())
+    }
+
+    /// Config admin queues a full set of new parameters, executable once
+    /// the config's timelock has passed. One proposal may be pending at a time
+    pub fn propose_config_update(
+        ctx: Context<ProposeConfigUpdate>,
+        params: ConfigParams,
     ) -> Result<()> {
+        params.validate()?;
+
+        let now = Clock::get()?.unix_timestamp;
+        let pending = &mut ctx.accounts.pending_config;
+        pending.params = params;
+        pending.proposed_at = now;
+        pending.executable_at = now.saturating_add(ctx.accounts.config.config_delay_secs);
+        pending.bump = ctx.bumps.pending_config;
+
+        emit_cpi!(log_event(ConfigUpdateProposed {
+            admin: ctx.accounts.admin.key(),
+            proposed_at: now,
+            executable_at: pending.executable_at,
+        }));
+
+        Ok(())
+    }
+
+    /// Apply the pending proposal once its timelock has passed
+    /// A proposal made before the latest pause cannot unpause the protocol
+    pub fn execute_config_update(ctx: Context<ExecuteConfigUpdate>) -> Result<()> {
+        let pending = &ctx.accounts.pending_config;
         require!(
-            question.len() > 0 && question.len() <= 256,
-            MarketError::InvalidQuestion
+            Clock::get()?.unix_timestamp >= pending.executable_at,
+            MarketError::TimelockNotElapsed
         );
+        pending.params.validate()?;
+
+        let config = &mut ctx.accounts.config;
         require!(
-            resolution_time > Clock::get()?.unix_timestamp,
-            MarketError::InvalidResolutionTime
+            pending.params.paused || !config.paused || pending.proposed_at >= config.paused_at,
+            MarketError::ProposalPredatesPause
         );
+        pending.params.apply(config);
 
-        let market = &mut ctx.accounts.market;
-        market.market_id = market_id;
-        market.question = question;
-        market.creator = ctx.accounts.creator.key();
-        market.created_at = Clock::get()?.unix_timestamp;
-        market.resolution_time = resolution_time;
-        market.yes_pool = initial_liquidity.saturating_div(2);
-        market.no_pool = initial_liquidity.saturating_div(2);
-        market.total_liquidity = initial_liquidity;
-        market.resolved = false;
-        market.outcome = None;
-        market.yes_token_vault = ctx.accounts.yes_token_vault.key();
-        market.no_token_vault = ctx.accounts.no_token_vault.key();
-        market.fee_collected = 0;
+        emit_cpi!(log_event(ConfigUpdateExecuted {
+            admin: config.admin,
+            proposed_at: pending.proposed_at,
+        }));
+        emit_cpi!(log_event(ConfigUpdated {
+            admin: config.admin,
+            min_market_duration: config.min_market_duration,
+            max_market_duration: config.max_market_duration,
+        }));
 
-        emit!(MarketCreated {
-            market_id,
-            creator: ctx.accounts.creator.key(),
-            question: market.question.clone(),
-            resolution_time,
-        });
+        Ok(())
+    }
+
+    /// Config admin withdraws the pending proposal
+    pub fn cancel_config_update(ctx: Context<CancelConfigUpdate>) -> Result<()> {
+        emit_cpi!(log_event(ConfigUpdateCancelled {
+            admin: ctx.accounts.admin.key(),
+            proposed_at: ctx.accounts.pending_config.proposed_at,
+        }));
 
         Ok(())
     }
 
-    /// User places a prediction
-    /// Deposits SOL as collateral, receives either YES or NO tokens
-    /// Prices determined by Automated Market Maker (AMM) formula
-    pub fn place_prediction(
-        ctx: Context<PlacePrediction>,
-        market_id: u64,
-        prediction_type: bool, // true = YES, false = NO
-        amount: u64,
-    ) -> Result<()> {
-        require!(amount > 0, MarketError::InvalidAmount);
+    /// Config admin halts market creation and betting immediately
+    /// Unpausing goes through the config timelock
+    pub fn pause_protocol(ctx: Context<UpdateConfig>) -> Result<()> {
+        let event = pause(&mut ctx.accounts.config)?;
+        emit_cpi!(log_event(event));
 
-        let market = &mut ctx.accounts.market;
-        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        Ok(())
+    }
+
+    /// Config admin replaces the per-category defaults table. At most
+    /// MAX_CATEGORY_DEFAULTS entries, with distinct nonzero categories;
+    /// markets of other categories use the global defaults. Only while
+    /// the config timelock is off, like update_config
+    pub fn set_category_defaults(
+        ctx: Context<UpdateConfig>,
+        defaults: Vec<CategoryDefaults>,
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(config.config_delay_secs == 0, MarketError::TimelockRequired);
         require!(
-            Clock::get()?.unix_timestamp < market.resolution_time,
-            MarketError::MarketExpired
+            defaults.len() <= MAX_CATEGORY_DEFAULTS,
+            MarketError::InvalidCategoryDefaults
         );
+        for (i, entry) in defaults.iter().enumerate() {
+            require!(
+                entry.category != 0
+                    && !defaults[..i].iter().any(|d| d.category == entry.category)
+                    && entry.dispute_window_secs >= 0,
+                MarketError::InvalidCategoryDefaults
+            );
+            market_fee(config, entry.fee_bps)?;
+        }
 
-        // Calculate tokens to mint using constant product formula (x * y = k)
-        // tokens_out = (amount * pool_size) / (pool_size + amount)
-        let tokens_to_mint = if prediction_type {
-            let denominator = market.yes_pool.saturating_add(amount);
-            (amount as u128)
-                .saturating_mul(market.yes_pool as u128)
-                .saturating_div(denominator as u128) as u64
-        } else {
-            let denominator = market.no_pool.saturating_add(amount);
-            (amount as u128)
-                .saturating_mul(market.no_pool as u128)
-                .saturating_div(denominator as u128) as u64
+        config.category_defaults = [CategoryDefaults::default(); MAX_CATEGORY_DEFAULTS];
+        config.category_defaults[..defaults.len()].copy_from_slice(&defaults);
+
+        emit_cpi!(log_event(CategoryDefaultsSet {
+            admin: config.admin,
+            defaults,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin sets the tip each keeper action pays out of a market's
+    /// keeper budget, indexed by KeeperAction. Only while the config
+    /// timelock is off
+    pub fn set_keeper_tips(
+        ctx: Context<UpdateConfig>,
+        tips: [u64; KEEPER_ACTION_COUNT],
+    ) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(config.config_delay_secs == 0, MarketError::TimelockRequired);
+        config.keeper_tips = tips;
+
+        emit_cpi!(log_event(KeeperTipsSet {
+            admin: config.admin,
+            tips,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin registers a tenant, or changes its quota: at most
+    /// `max_markets_per_window` new markets per TENANT_QUOTA_WINDOW_SECS,
+    /// 0 for no cap. Counters carry over on a change. Only while the
+    /// config timelock is off
+    pub fn set_tenant_quota(
+        ctx: Context<UpdateConfig>,
+        tenant: [u8; 16],
+        max_markets_per_window: u32,
+    ) -> Result<()> {
+        require!(tenant != [0; 16], MarketError::InvalidTenant);
+        let config = &mut ctx.accounts.config;
+        require!(config.config_delay_secs == 0, MarketError::TimelockRequired);
+        let quota = match config.tenants.iter().position(|t| t.tenant == tenant) {
+            Some(slot) => &mut config.tenants[slot],
+            None => {
+                let slot = config
+                    .tenants
+                    .iter()
+                    .position(|t| t.tenant == [0; 16])
+                    .ok_or(MarketError::TenantTableFull)?;
+                config.tenants[slot] = TenantQuota {
+                    tenant,
+                    ..TenantQuota::default()
+                };
+                &mut config.tenants[slot]
+            }
         };
+        quota.max_markets_per_window = max_markets_per_window;
 
-        require!(tokens_to_mint > 0, MarketError::InsufficientOutput);
+        emit_cpi!(log_event(TenantQuotaSet {
+            admin: config.admin,
+            tenant,
+            max_markets_per_window,
+        }));
 
-        // Update pools
-        if prediction_type {
-            market.yes_pool = market.yes_pool.saturating_add(amount);
-        } else {
-            market.no_pool = market.no_pool.saturating_add(amount);
-        }
+        Ok(())
+    }
 
-        // Transfer SOL to vault
-        let transfer_instruction = anchor_lang::solana_program::system_instruction::transfer(
-            ctx.accounts.predictor.key,
-            ctx.accounts.market_vault.key,
-            amount,
-        );
-        anchor_lang::solana_program::program::invoke(
-            &transfer_instruction,
-            &[
-                ctx.accounts.predictor.to_account_info(),
-                ctx.accounts.market_vault.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-        )?;
+    /// Config admin turns gated instructions on and off, one FEATURE_* bit
+    /// each. A new config starts with every feature on. Only while the
+    /// config timelock is off
+    pub fn set_feature_flags(ctx: Context<UpdateConfig>, flags: u64) -> Result<()> {
+        require!(flags & !ALL_FEATURES == 0, MarketError::InvalidConfig);
+        let config = &mut ctx.accounts.config;
+        require!(config.config_delay_secs == 0, MarketError::TimelockRequired);
+        let previous = config.feature_flags;
+        config.feature_flags = flags;
 
-        // Store prediction
-        let prediction = &mut ctx.accounts.prediction_account;
-        prediction.market_id = market_id;
-        prediction.predictor = ctx.accounts.predictor.key();
-        prediction.prediction_type = prediction_type;
-        prediction.amount_deposited = amount;
-        prediction.tokens_received = tokens_to_mint;
-        prediction.created_at = Clock::get()?.unix_timestamp;
-        prediction.claimed = false;
+        emit_cpi!(log_event(FeatureFlagsSet {
+            admin: config.admin,
+            previous,
+            flags,
+        }));
 
-        emit!(PredictionPlaced {
-            market_id,
-            predictor: ctx.accounts.predictor.key(),
-            prediction_type,
-            amount,
-            tokens_received: tokens_to_mint,
-        });
+        Ok(())
+    }
+
+    /// Config admin sets how escheated winnings are split in the unclaimed
+    /// rewards pool: `rebate_bps` of each amount is kept for loser rebates,
+    /// the rest for keeper tips. Only while the config timelock is off
+    pub fn set_escheat_split(ctx: Context<UpdateConfig>, rebate_bps: u16) -> Result<()> {
+        require!(
+            rebate_bps as u64 <= BPS_DENOMINATOR,
+            MarketError::InvalidConfig
+        );
+        let config = &mut ctx.accounts.config;
+        require!(config.config_delay_secs == 0, MarketError::TimelockRequired);
+        config.escheat_rebate_bps = rebate_bps;
+
+        emit_cpi!(log_event(EscheatSplitSet {
+            admin: config.admin,
+            rebate_bps,
+        }));
 
         Ok(())
     }
 
-    /// Admin resolves the market with the outcome
-    /// Can only be called after resolution_time has passed
-    /// outcome: true = YES won, false = NO won
-    pub fn resolve_market(
-        ctx: Context<ResolveMarket>,
-        market_id: u64,
-        outcome: bool,
+    /// Config admin hands the admin role to a new N-of-M multisig of
+    /// `owners`, created under `nonce` so a later multisig can replace it.
+    /// The multisig's signer PDA becomes the admin; from then on admin
+    /// actions go through create_admin_tx, approve_admin_tx and
+    /// execute_admin_tx. The signer pays for the accounts admin
+    /// instructions create, so it needs funding first
+    pub fn create_admin_multisig(
+        ctx: Context<CreateAdminMultisig>,
+        nonce: u64,
+        owners: Vec<Pubkey>,
+        threshold: u8,
     ) -> Result<()> {
-        let market = &mut ctx.accounts.market;
-        require!(market.creator == ctx.accounts.admin.key(), MarketError::Unauthorized);
-        require!(!market.resolved, MarketError::MarketAlreadyResolved);
         require!(
-            Clock::get()?.unix_timestamp >= market.resolution_time,
-            MarketError::MarketNotExpired
+            !owners.is_empty()
+                && owners.len() <= MAX_MULTISIG_OWNERS
+                && threshold > 0
+                && threshold as usize <= owners.len(),
+            MarketError::InvalidMultisig
         );
+        for (i, owner) in owners.iter().enumerate() {
+            require!(!owners[..i].contains(owner), MarketError::InvalidMultisig);
+        }
 
-        market.resolved = true;
-        market.outcome = Some(outcome);
+        let multisig = &mut ctx.accounts.multisig;
+        multisig.owners[..owners.len()].copy_from_slice(&owners);
+        multisig.owner_count = owners.len() as u8;
+        multisig.threshold = threshold;
+        multisig.tx_count = 0;
+        multisig.nonce = nonce;
+        multisig.bump = ctx.bumps.multisig;
+        multisig.signer_bump = ctx.bumps.multisig_signer;
+        ctx.accounts.config.admin = ctx.accounts.multisig_signer.key();
 
-        emit!(MarketResolved {
-            market_id,
-            outcome,
-            yes_pool: market.yes_pool,
-            no_pool: market.no_pool,
-        });
+        emit_cpi!(log_event(AdminMultisigCreated {
+            multisig: multisig.key(),
+            signer: ctx.accounts.multisig_signer.key(),
+            owners,
+            threshold,
+        }));
 
         Ok(())
     }
 
-    /// Winners claim their rewards
-    /// Formula: (user_tokens / winning_pool_total) * (yes_pool + no_pool)
-    pub fn claim_reward(ctx: Context<ClaimReward>, market_id: u64) -> Result<()> {
-        let market = &ctx.accounts.market;
-        let prediction = &mut ctx.accounts.prediction_account;
+    /// A multisig owner proposes an admin action, approving it as well
+    pub fn create_admin_tx(ctx: Context<CreateAdminTx>, action: AdminAction) -> Result<()> {
+        let multisig = &mut ctx.accounts.multisig;
+        let owner = multisig
+            .owner_index(&ctx.accounts.owner.key())
+            .ok_or(MarketError::NotMultisigOwner)?;
+        action.validate()?;
 
-        require!(market.resolved, MarketError::MarketNotResolved);
-        require!(
-            prediction.predictor == ctx.accounts.claimer.key(),
-            MarketError::Unauthorized
-        );
-        require!(!prediction.claimed, MarketError::AlreadyClaimed);
+        let admin_tx = &mut ctx.accounts.admin_tx;
+        admin_tx.index = multisig.tx_count;
+        admin_tx.proposer = ctx.accounts.owner.key();
+        admin_tx.action = action;
+        admin_tx.approvals = 1 << owner;
+        admin_tx.executed = false;
+        admin_tx.created_at = Clock::get()?.unix_timestamp;
+        admin_tx.bump = ctx.bumps.admin_tx;
+        multisig.tx_count = multisig.tx_count.saturating_add(1);
 
-        let outcome = market.outcome.ok_or(MarketError::InvalidOutcome)?;
+        emit_cpi!(log_event(AdminTxCreated {
+            index: admin_tx.index,
+            proposer: admin_tx.proposer,
+        }));
 
-        // Check if prediction was correct
-        let prediction_won = prediction.prediction_type == outcome;
-        require!(prediction_won, MarketError::PredictionLost);
+        Ok(())
+    }
 
-        // Calculate reward
-        let winning_pool = if outcome { market.yes_pool } else { market.no_pool };
-        let total_winnings = market.yes_pool.saturating_add(market.no_pool);
+    /// A multisig owner approves a pending admin action, once
+    pub fn approve_admin_tx(ctx: Context<ApproveAdminTx>, index: u64) -> Result<()> {
+        let owner = ctx
+            .accounts
+            .multisig
+            .owner_index(&ctx.accounts.owner.key())
+            .ok_or(MarketError::NotMultisigOwner)?;
+        let admin_tx = &mut ctx.accounts.admin_tx;
+        require!(!admin_tx.executed, MarketError::AdminTxExecuted);
+        require!(admin_tx.approvals & (1 << owner) == 0, MarketError::AlreadyApproved);
+        admin_tx.approvals |= 1 << owner;
 
-        let reward = if winning_pool > 0 {
-            (prediction.tokens_received as u128)
-                .saturating_mul(total_winnings as u128)
-                .saturating_div(winning_pool as u128) as u64
-        } else {
-            0
-        };
+        emit_cpi!(log_event(AdminTxApproved {
+            index,
+            owner: ctx.accounts.owner.key(),
+            approvals: admin_tx.approvals.count_ones() as u8,
+        }));
 
-        require!(reward > 0, MarketError::NoReward);
+        Ok(())
+    }
 
-        prediction.claimed = true;
+    /// A multisig owner carries out an admin action once it has the
+    /// threshold of approvals, through the same logic as the single-key
+    /// instruction. Each action runs at most once. `market` is required
+    /// by EmergencyCancelMarket only; an Invoke action takes the accounts
+    /// of its instruction as remaining accounts
+    pub fn execute_admin_tx<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ExecuteAdminTx<'info>>,
+        index: u64,
+    ) -> Result<()> {
+        let multisig = &ctx.accounts.multisig;
+        require!(
+            multisig.owner_index(&ctx.accounts.owner.key()).is_some(),
+            MarketError::NotMultisigOwner
+        );
+        let admin_tx = &mut ctx.accounts.admin_tx;
+        require!(!admin_tx.executed, MarketError::AdminTxExecuted);
+        require!(
+            admin_tx.approvals.count_ones() >= multisig.threshold as u32,
+            MarketError::ThresholdNotMet
+        );
+        admin_tx.executed = true;
 
-        // Transfer reward from vault to claimer
-        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= reward;
-        **ctx.accounts.claimer.try_borrow_mut_lamports()? += reward;
+        let signer = ctx.accounts.multisig_signer.key();
+        let config = &mut ctx.accounts.config;
+        match admin_tx.action.clone() {
+            AdminAction::UpdateConfig { params } => {
+                emit_cpi!(log_event(update_config_now(config, &params)?));
+            }
+            AdminAction::PauseProtocol => {
+                emit_cpi!(log_event(pause(config)?));
+            }
+            AdminAction::EmergencyCancelMarket { market_id, reason } => {
+                let market = ctx
+                    .accounts
+                    .market
+                    .as_mut()
+                    .ok_or(MarketError::InvalidMarketAccounts)?;
+                require!(market.market_id == market_id, MarketError::MarketMismatch);
+                let event = cancel_market(market, signer, reason)?;
+                emit_cpi!(log_event(event));
+            }
+            AdminAction::SetAdmin { admin } => {
+                config.admin = admin;
+            }
+            AdminAction::Invoke { accounts, data } => {
+                let multisig_key = multisig.key();
+                let seeds: &[&[u8]] = &[
+                    b"multisig_signer",
+                    multisig_key.as_ref(),
+                    &[multisig.signer_bump],
+                ];
+                let mut infos = ctx.remaining_accounts.to_vec();
+                infos.push(ctx.accounts.multisig_signer.to_account_info());
+                infos.push(ctx.accounts.program.to_account_info());
+                anchor_lang::solana_program::program::invoke_signed(
+                    &Instruction {
+                        program_id: crate::ID,
+                        accounts: accounts
+                            .iter()
+                            .map(|meta| AccountMeta {
+                                pubkey: meta.pubkey,
+                                is_signer: meta.is_signer || meta.pubkey == signer,
+                                is_writable: meta.is_writable,
+                            })
+                            .collect(),
+                        data,
+                    },
+                    &infos,
+                    &[seeds],
+                )?;
+                // The instruction may have written these; don't write
+                // stale copies back over it
+                ctx.accounts.config.reload()?;
+                if let Some(market) = ctx.accounts.market.as_mut() {
+                    market.reload()?;
+                }
+            }
+        }
 
-        emit!(RewardClaimed {
-            market_id,
-            claimer: ctx.accounts.claimer.key(),
-            reward,
-        });
+        emit_cpi!(log_event(AdminTxExecuted {
+            index,
+            admin: ctx.accounts.config.admin,
+        }));
 
         Ok(())
     }
 
-    /// Admin withdraws collected fees
-    pub fn withdraw_fees(ctx: Context<WithdrawFees>, market_id: u64, amount: u64) -> Result<()> {
-        let market = &mut ctx.accounts.market;
-        require!(market.creator == ctx.accounts.admin.key(), MarketError::Unauthorized);
-        require!(market.fee_collected >= amount, MarketError::InsufficientFees);
+    /// Config admin lets `creator` create markets while creation is
+    /// whitelisted, optionally without paying the creation fee.
+    /// `max_markets_per_day` raises the creator's daily creation limit
+    /// above the config's; 0 leaves it at the config's
+    pub fn approve_creator(
+        ctx: Context<ApproveCreator>,
+        creator: Pubkey,
+        fee_waived: bool,
+        max_markets_per_day: u32,
+    ) -> Result<()> {
+        let allow = &mut ctx.accounts.creator_allow;
+        allow.creator = creator;
+        allow.approved_at = Clock::get()?.unix_timestamp;
+        allow.fee_waived = fee_waived;
+        allow.max_markets_per_day = max_markets_per_day;
+        allow.bump = ctx.bumps.creator_allow;
 
-        market.fee_collected = market.fee_collected.saturating_sub(amount);
+        emit_cpi!(log_event(CreatorApprovalChanged {
+            creator,
+            approved: true,
+        }));
 
-        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= amount;
-        **ctx.accounts.admin.try_borrow_mut_lamports()? += amount;
+        Ok(())
+    }
 
-        emit!(FeesWithdrawn {
-            market_id,
-            admin: ctx.accounts.admin.key(),
-            amount,
-        });
+    /// Config admin approves `maker` as a market maker, or changes its rate.
+    /// `rebate_bps` is the share of the taker fee on each fill of its
+    /// resting orders that is rebated to it; 0 suspends rebates while
+    /// keeping what has accrued claimable
+    pub fn approve_market_maker(
+        ctx: Context<ApproveMarketMaker>,
+        maker: Pubkey,
+        rebate_bps: u16,
+    ) -> Result<()> {
+        require!(rebate_bps as u64 <= BPS_DENOMINATOR, MarketError::InvalidConfig);
+        let registration = &mut ctx.accounts.registration;
+        if registration.maker == Pubkey::default() {
+            registration.maker = maker;
+            registration.approved_at = Clock::get()?.unix_timestamp;
+            registration.bump = ctx.bumps.registration;
+        }
+        registration.rebate_bps = rebate_bps;
+
+        emit_cpi!(log_event(MarketMakerApprovalChanged {
+            maker,
+            rebate_bps,
+            approved: true,
+        }));
 
         Ok(())
     }
-}
 
-// ==================== ACCOUNTS ====================
+    /// Config admin withdraws a market maker's approval. Rebates it hasn't
+    /// claimed are paid out to it before the registration closes
+    pub fn revoke_market_maker(ctx: Context<RevokeMarketMaker>) -> Result<()> {
+        let registration = &ctx.accounts.registration;
+        let unclaimed = registration.unclaimed();
+        **registration.to_account_info().try_borrow_mut_lamports()? -= unclaimed;
+        **ctx.accounts.maker.try_borrow_mut_lamports()? += unclaimed;
 
-#[derive(Accounts)]
-#[instruction(market_id: u64)]
-pub struct InitializeMarket<'info> {
-    #[account(
-        init,
-        payer = creator,
-        space = 8 + Market::INIT_SPACE,
-        seeds = [b"market", market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub market: Account<'info, Market>,
+        emit_cpi!(log_event(MarketMakerApprovalChanged {
+            maker: registration.maker,
+            rebate_bps: 0,
+            approved: false,
+        }));
 
-    #[account(mut)]
-    pub creator: Signer<'info>,
+        Ok(())
+    }
 
-    /// CHECK: We're just storing the key, not dereferencing
-    /// CHECK: Market vault for holding SOL, initialized with zero space
-    #[account(
-        init,
-        payer = creator,
-        space = 0,
-        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub market_vault: AccountInfo<'info>,
+    /// Market maker withdraws the fee rebates its registration has accrued
+    pub fn claim_mm_rebates(ctx: Context<ClaimMmRebates>) -> Result<()> {
+        let registration = &mut ctx.accounts.registration;
+        let amount = registration.unclaimed();
+        require!(amount > 0, MarketError::NoRebate);
+        registration.rebates_claimed = registration.rebates_accrued;
 
-    /// CHECK: Token vault for YES positions
-    pub yes_token_vault: AccountInfo<'info>,
+        **registration.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.maker.try_borrow_mut_lamports()? += amount;
 
-    /// CHECK: Token vault for NO positions
-    pub no_token_vault: AccountInfo<'info>,
+        emit_cpi!(log_event(MmRebatesClaimed {
+            maker: registration.maker,
+            amount,
+            total_claimed: registration.rebates_claimed,
+        }));
 
-    pub system_program: Program<'info, System>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(market_id: u64)]
-pub struct PlacePrediction<'info> {
-    #[account(
-        mut,
-        seeds = [b"market", market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub market: Account<'info, Market>,
+    /// Config admin withdraws a creator's approval; markets it already
+    /// created are unaffected
+    pub fn revoke_creator(ctx: Context<RevokeCreator>, creator: Pubkey) -> Result<()> {
+        emit_cpi!(log_event(CreatorApprovalChanged {
+            creator,
+            approved: false,
+        }));
 
-    /// CHECK: Market vault for holding SOL
-    #[account(
-        mut,
-        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub market_vault: AccountInfo<'info>,
+        Ok(())
+    }
 
-    #[account(
-        init,
-        payer = predictor,
-        space = 8 + Prediction::INIT_SPACE,
-        seeds = [b"prediction", market_id.to_le_bytes().as_ref(), predictor.key().as_ref()],
-        bump
-    )]
-    pub prediction_account: Account<'info, Prediction>,
+    /// Close the current leaderboard epoch once its length has elapsed
+    /// Permissionless; records the closed epoch's time range so scores can
+    /// be submitted against it. The next epoch starts at the time of the call
+    pub fn advance_epoch(ctx: Context<AdvanceEpoch>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let config = &mut ctx.accounts.config;
+        require!(
+            now >= config.epoch_started_at.saturating_add(config.epoch_length),
+            MarketError::EpochNotOver
+        );
 
-    #[account(mut)]
-    pub predictor: Signer<'info>,
+        let leaderboard = &mut ctx.accounts.leaderboard;
+        leaderboard.epoch = config.current_epoch;
+        leaderboard.started_at = config.epoch_started_at;
+        leaderboard.ended_at = now;
+        leaderboard.leader = Pubkey::default();
+        leaderboard.leader_score = 0;
+        leaderboard.bump = ctx.bumps.leaderboard;
 
-    pub system_program: Program<'info, System>,
-}
+        config.current_epoch = config.current_epoch.saturating_add(1);
+        config.epoch_started_at = now;
 
-#[derive(Accounts)]
-#[instruction(market_id: u64)]
-pub struct ResolveMarket<'info> {
-    #[account(
-        mut,
-        seeds = [b"market", market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub market: Account<'info, Market>,
+        emit_cpi!(log_event(EpochAdvanced {
+            closed_epoch: leaderboard.epoch,
+            new_epoch: config.current_epoch,
+            started_at: config.epoch_started_at,
+        }));
 
-    pub admin: Signer<'info>,
-}
+        Ok(())
+    }
 
-#[derive(Accounts)]
-#[instruction(market_id: u64)]
-pub struct ClaimReward<'info> {
-    #[account(
-        seeds = [b"market", market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub market: Account<'info, Market>,
+    /// Submit a user's stats for a closed epoch; becomes the epoch leader
+    /// if its winnings beat the current leader. Permissionless, since the
+    /// score is read straight from the user's on-chain epoch stats
+    pub fn submit_epoch_score(ctx: Context<SubmitEpochScore>, epoch: u64) -> Result<()> {
+        let stats = &ctx.accounts.epoch_stats;
+        let leaderboard = &mut ctx.accounts.leaderboard;
 
-    /// CHECK: Market vault for holding SOL and rewards
-    #[account(
-        mut,
-        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub market_vault: AccountInfo<'info>,
+        if stats.total_won > leaderboard.leader_score {
+            leaderboard.leader = stats.user;
+            leaderboard.leader_score = stats.total_won;
 
-    #[account(
-        mut,
-        seeds = [b"prediction", market_id.to_le_bytes().as_ref(), claimer.key().as_ref()],
-        bump
-    )]
-    pub prediction_account: Account<'info, Prediction>,
+            emit_cpi!(log_event(EpochLeaderUpdated {
+                epoch,
+                leader: stats.user,
+                score: stats.total_won,
+            }));
+        }
 
-    #[account(mut)]
-    pub claimer: Signer<'info>,
-}
+        Ok(())
+    }
+
+    /// Initialize a new prediction market
+    /// The market ID is assigned from the global market counter and
+    /// returned to the caller (also emitted in MarketCreated)
+    /// Admin creates a market with:
+    /// - question: "Will SOL price exceed $200 by end of week?"
+    /// - resolution_time: timestamp when market resolves
+    /// - initial_yes_probability_bps: starting YES price; the seed is split
+    ///   between the pools in this ratio
+    /// - fee_bps: trading fee, within the config's min/max; 0 uses the
+    ///   config default
+    /// - yes_token_mint: mint for YES positions
+    /// - no_token_mint: mint for NO positions
+    pub fn initialize_market(
+        ctx: Context<InitializeMarket>,
+        question: String,
+        resolution_time: i64,
+        initial_liquidity: u64,
+        initial_yes_probability_bps: u16,
+        fee_bps: u16,
+        options: MarketOptions,
+    ) -> Result<u64> {
+        require_approved_creator(&ctx.accounts.config, &ctx.accounts.creator_allow)?;
+        count_creator_market(
+            &mut ctx.accounts.creator_stats,
+            ctx.accounts.creator.key(),
+            ctx.bumps.creator_stats,
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+        )?;
+        validate_parent(options.parent, &ctx.accounts.parent_market, resolution_time)?;
+        let counter = &mut ctx.accounts.market_counter;
+        let market_id = counter.next_id;
+        counter.next_id = counter.next_id.checked_add(1).ok_or(MarketError::MarketIdOverflow)?;
+
+        let series = join_series(&mut ctx.accounts.series, &ctx.accounts.creator.key())?;
+        let creation_fee = charge_creation_fee(
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity,
+        )?;
+        let mut created = init_market(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            ctx.accounts.creator.key(),
+            outcome_mints(&ctx.accounts.yes_mint, &ctx.accounts.no_mint)?,
+            market_id,
+            question,
+            resolution_time,
+            initial_liquidity,
+            initial_yes_probability_bps,
+            fee_bps,
+            &options,
+            series,
+            ctx.bumps.market,
+            ctx.bumps.market_vault,
+        )?;
+        created.creation_fee = creation_fee;
+        let created = log_event(created);
+        emit_cpi!(created);
+        ctx.accounts.market.question = created.question;
+
+        // Seed liquidity backs the initial pools, so it must be in the vault,
+        // as must the keeper budget
+        transfer_lamports(
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity.saturating_add(ctx.accounts.market.keeper_budget),
+        )?;
+
+        register_question(
+            &mut ctx.accounts.question_registry,
+            ctx.bumps.question_registry,
+            &ctx.accounts.config,
+            &ctx.accounts.duplicate_approver,
+            ctx.accounts.market.key(),
+            market_id,
+        )?;
+        register_market(
+            &mut ctx.accounts.config,
+            &mut ctx.accounts.registry_page,
+            ctx.bumps.registry_page,
+            &ctx.accounts.market,
+        )?;
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) =
+            solvency_violation(&ctx.accounts.market, vault_lamports(&ctx.accounts.market_vault)?)
+        {
+            emit_cpi!(log_event(violation));
+        }
+
+        Ok(market_id)
+    }
+
+    /// Creator creates a market and places its own first prediction in one
+    /// instruction, so no other bet can land between the two. Takes the
+    /// arguments of initialize_market plus the bet's side and amount; the
+    /// bet is priced, charged and logged exactly as place_prediction would
+    /// with no price impact limit and no note. Lamport vault markets only,
+    /// as a wsol_vault market has no collateral vault until init_wsol_vault
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize_market_and_predict(
+        ctx: Context<InitializeMarketAndPredict>,
+        question: String,
+        resolution_time: i64,
+        initial_liquidity: u64,
+        initial_yes_probability_bps: u16,
+        fee_bps: u16,
+        options: MarketOptions,
+        prediction_type: bool,
+        amount: u64,
+    ) -> Result<u64> {
+        require!(!options.wsol_vault, MarketError::WsolVaultRequired);
+        require_approved_creator(&ctx.accounts.config, &ctx.accounts.creator_allow)?;
+        count_creator_market(
+            &mut ctx.accounts.creator_stats,
+            ctx.accounts.creator.key(),
+            ctx.bumps.creator_stats,
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+        )?;
+        validate_parent(options.parent, &ctx.accounts.parent_market, resolution_time)?;
+        let counter = &mut ctx.accounts.market_counter;
+        let market_id = counter.next_id;
+        counter.next_id = counter.next_id.checked_add(1).ok_or(MarketError::MarketIdOverflow)?;
+
+        let series = join_series(&mut ctx.accounts.series, &ctx.accounts.creator.key())?;
+        let creation_fee = charge_creation_fee(
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity,
+        )?;
+        let mut created = init_market(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            ctx.accounts.creator.key(),
+            outcome_mints(&ctx.accounts.yes_mint, &ctx.accounts.no_mint)?,
+            market_id,
+            question,
+            resolution_time,
+            initial_liquidity,
+            initial_yes_probability_bps,
+            fee_bps,
+            &options,
+            series,
+            ctx.bumps.market,
+            ctx.bumps.market_vault,
+        )?;
+        created.creation_fee = creation_fee;
+        let created = log_event(created);
+        emit_cpi!(created);
+        ctx.accounts.market.question = created.question;
+
+        transfer_lamports(
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity.saturating_add(ctx.accounts.market.keeper_budget),
+        )?;
+
+        register_question(
+            &mut ctx.accounts.question_registry,
+            ctx.bumps.question_registry,
+            &ctx.accounts.config,
+            &ctx.accounts.duplicate_approver,
+            ctx.accounts.market.key(),
+            market_id,
+        )?;
+        register_market(
+            &mut ctx.accounts.config,
+            &mut ctx.accounts.registry_page,
+            ctx.bumps.registry_page,
+            &ctx.accounts.market,
+        )?;
+
+        // From here on, place_prediction's path for the creator's bet
+        require!(!ctx.accounts.market.needs_attention, MarketError::NeedsAttention);
+        require!(
+            ctx.accounts.market.bet_reveal_window == 0,
+            MarketError::CommitRevealRequired
+        );
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        require!(
+            !ctx.accounts.market.reduce_only(now),
+            MarketError::ReduceOnlyWindow
+        );
+        require_not_self_excluded(&ctx.accounts.self_exclusion, now)?;
+        let predictor = ctx.accounts.creator.key();
+        let discounted = discount_nft_verified(
+            &ctx.accounts.config,
+            &predictor,
+            &ctx.accounts.nft_token_account,
+            &ctx.accounts.nft_metadata,
+        )?;
+        let (yes_pool_at_entry, no_pool_at_entry) =
+            (ctx.accounts.market.yes_pool, ctx.accounts.market.no_pool);
+        let PredictionQuote {
+            tokens_out: tokens_to_mint,
+            effective_price_bps,
+            fee,
+            referral_fee: referral_cut,
+            insurance_fee,
+            net_amount,
+            weight_bps,
+            fee_bps,
+            rebate,
+            ..
+        } = price_prediction(
+            &ctx.accounts.market,
+            &ctx.accounts.config,
+            &predictor,
+            ctx.accounts.referrer.as_deref(),
+            discounted,
+            prediction_type,
+            amount,
+            now,
+        )?;
+
+        if let Some(series) = &mut ctx.accounts.series {
+            series.total_volume = series.total_volume.saturating_add(amount);
+        }
+
+        let stats = &mut ctx.accounts.creator_stats;
+        let open_liability = stats.open_liability.saturating_add(amount);
+        let cap = ctx.accounts.config.max_open_liability;
+        require_bet(
+            cap == 0 || open_liability <= cap,
+            market_id,
+            MarketError::CreatorLiabilityCapReached,
+            cap,
+            open_liability,
+        )?;
+        stats.open_liability = open_liability;
+        let market = &mut ctx.accounts.market;
+        market.creator_liability = market.creator_liability.saturating_add(amount);
+
+        apply_prediction(
+            &mut ctx.accounts.market,
+            prediction_type,
+            amount,
+            net_amount,
+            tokens_to_mint,
+            fee - referral_cut - insurance_fee,
+            rebate,
+            now,
+        )?;
+
+        transfer_lamports(
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount - referral_cut - insurance_fee - rebate,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.insurance_fund.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            insurance_fee,
+        )?;
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_deposited = fund.total_deposited.saturating_add(insurance_fee);
+
+        if let Some(referrer) = &mut ctx.accounts.referrer {
+            transfer_lamports(
+                &ctx.accounts.creator.to_account_info(),
+                &referrer.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                referral_cut,
+            )?;
+            referrer.accrued_fees = referrer.accrued_fees.saturating_add(referral_cut);
+            referrer.total_referred_volume =
+                referrer.total_referred_volume.saturating_add(amount);
+
+            emit_cpi!(log_event(ReferralAttributed {
+                market_id,
+                market: ctx.accounts.market.key(),
+                tenant: ctx.accounts.market.tenant,
+                event_seq: ctx.accounts.market.next_event_seq(),
+                predictor,
+                referrer: referrer.owner,
+                amount,
+                referral_fee: referral_cut,
+            }));
+        }
+
+        let house = ctx.accounts.market.is_house(&predictor);
+        let prediction = &mut ctx.accounts.prediction_account;
+        prediction.version = PREDICTION_VERSION;
+        prediction.market_id = market_id;
+        prediction.predictor = predictor;
+        prediction.prediction_type = prediction_type;
+        prediction.amount_deposited = amount;
+        prediction.tokens_received = tokens_to_mint;
+        prediction.created_at = now;
+        prediction.claimed = false;
+        prediction.bump = ctx.bumps.prediction_account;
+        prediction.slot = clock.slot;
+        prediction.yes_pool_at_entry = yes_pool_at_entry;
+        prediction.no_pool_at_entry = no_pool_at_entry;
+        prediction.entry_price_bps = effective_price_bps;
+        prediction.entry_probability_bps =
+            side_probability_bps(yes_pool_at_entry, no_pool_at_entry, prediction_type);
+        prediction.trade_sequence = ctx.accounts.market.sequence;
+        prediction.cancel_refund = amount - referral_cut - insurance_fee - rebate;
+        prediction.is_house = ReserveFlag(house);
+        ctx.accounts.market.open_prediction(prediction);
+        prediction.note = String::new();
+        prediction.rent_payer = predictor;
+        prediction.referrer = ctx
+            .accounts
+            .referrer
+            .as_ref()
+            .map(|referrer| referrer.owner)
+            .unwrap_or_default();
+
+        let full = admit_participant(
+            &mut ctx.accounts.market,
+            &ctx.accounts.user_market_index,
+            false,
+        )?;
+        if let Some(full) = full {
+            emit_cpi!(log_event(full));
+        }
+        index_position(
+            &mut ctx.accounts.user_market_index,
+            ctx.bumps.user_market_index,
+            &mut ctx.accounts.user_index_page,
+            ctx.bumps.user_index_page,
+            &ctx.accounts.user_profile,
+            market_id,
+            predictor,
+        );
+
+        let profile = &mut ctx.accounts.user_profile;
+        profile.init_if_new(predictor, ctx.bumps.user_profile);
+        profile.bets_placed = profile.bets_placed.saturating_add(1);
+        if !house {
+            profile.total_wagered = profile.total_wagered.saturating_add(amount);
+        }
+        emit_cpi!(log_event(profile.update_event()));
+
+        let epoch_stats = &mut ctx.accounts.epoch_stats;
+        epoch_stats.init_if_new(
+            predictor,
+            ctx.accounts.config.current_epoch,
+            ctx.bumps.epoch_stats,
+        );
+        if !house {
+            epoch_stats.bets_placed = epoch_stats.bets_placed.saturating_add(1);
+            epoch_stats.total_wagered = epoch_stats.total_wagered.saturating_add(amount);
+        }
+
+        let market = &mut ctx.accounts.market;
+        emit_cpi!(log_event(PredictionPlaced {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            predictor,
+            prediction_type,
+            amount,
+            normalized_amount: normalized_amount(amount, market.collateral_decimals),
+            tokens_received: tokens_to_mint,
+            weight_bps,
+            fee_bps,
+            rebate,
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+            implied_yes_probability_bps: market.implied_yes_probability_bps(),
+            sequence: market.sequence,
+            note: String::new(),
+        }));
+
+        emit_cpi!(log_event(MarketStats {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            total_volume: market.total_volume,
+            trade_count: market.trade_count,
+            unique_bettors: market.unique_bettors,
+        }));
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) =
+            solvency_violation(&ctx.accounts.market, vault_lamports(&ctx.accounts.market_vault)?)
+        {
+            emit_cpi!(log_event(violation));
+        }
+
+        Ok(market_id)
+    }
+
+    /// Initialize a market with a caller-chosen ID
+    /// Kept for integrations that need deterministic IDs. Explicit IDs must
+    /// have the high bit set so they can never collide with counter-assigned IDs
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize_market_with_id(
+        ctx: Context<InitializeMarketWithId>,
+        market_id: u64,
+        question: String,
+        resolution_time: i64,
+        initial_liquidity: u64,
+        initial_yes_probability_bps: u16,
+        fee_bps: u16,
+        options: MarketOptions,
+    ) -> Result<u64> {
+        require!(
+            market_id >= EXPLICIT_MARKET_ID_START,
+            MarketError::InvalidMarketId
+        );
+        require_approved_creator(&ctx.accounts.config, &ctx.accounts.creator_allow)?;
+        count_creator_market(
+            &mut ctx.accounts.creator_stats,
+            ctx.accounts.creator.key(),
+            ctx.bumps.creator_stats,
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+        )?;
+        validate_parent(options.parent, &ctx.accounts.parent_market, resolution_time)?;
+
+        let series = join_series(&mut ctx.accounts.series, &ctx.accounts.creator.key())?;
+        let creation_fee = charge_creation_fee(
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity,
+        )?;
+        let mut created = init_market(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            ctx.accounts.creator.key(),
+            outcome_mints(&ctx.accounts.yes_mint, &ctx.accounts.no_mint)?,
+            market_id,
+            question,
+            resolution_time,
+            initial_liquidity,
+            initial_yes_probability_bps,
+            fee_bps,
+            &options,
+            series,
+            ctx.bumps.market,
+            ctx.bumps.market_vault,
+        )?;
+        created.creation_fee = creation_fee;
+        let created = log_event(created);
+        emit_cpi!(created);
+        ctx.accounts.market.question = created.question;
+
+        // Seed liquidity backs the initial pools, so it must be in the vault,
+        // as must the keeper budget
+        transfer_lamports(
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity.saturating_add(ctx.accounts.market.keeper_budget),
+        )?;
+
+        register_question(
+            &mut ctx.accounts.question_registry,
+            ctx.bumps.question_registry,
+            &ctx.accounts.config,
+            &ctx.accounts.duplicate_approver,
+            ctx.accounts.market.key(),
+            market_id,
+        )?;
+        register_market(
+            &mut ctx.accounts.config,
+            &mut ctx.accounts.registry_page,
+            ctx.bumps.registry_page,
+            &ctx.accounts.market,
+        )?;
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) =
+            solvency_violation(&ctx.accounts.market, vault_lamports(&ctx.accounts.market_vault)?)
+        {
+            emit_cpi!(log_event(violation));
+        }
+
+        Ok(market_id)
+    }
+
+    /// Create a reusable template for near-identical markets
+    pub fn create_template(
+        ctx: Context<CreateTemplate>,
+        name: String,
+        settings: TemplateSettings,
+    ) -> Result<()> {
+        require!(
+            !name.is_empty() && name.len() <= MAX_SERIES_NAME_LEN,
+            MarketError::InvalidTemplate
+        );
+        settings.validate()?;
+
+        let template = &mut ctx.accounts.template;
+        template.creator = ctx.accounts.creator.key();
+        template.name = name;
+        settings.apply(template);
+        template.instance_count = 0;
+        template.bump = ctx.bumps.template;
+
+        emit_cpi!(log_event(TemplateCreated {
+            template: template.key(),
+            creator: template.creator,
+            name: template.name.clone(),
+        }));
+
+        Ok(())
+    }
+
+    /// Template creator changes its settings
+    /// Templates freeze once a market has been created from them
+    pub fn update_template(ctx: Context<UpdateTemplate>, settings: TemplateSettings) -> Result<()> {
+        let template = &mut ctx.accounts.template;
+        require!(template.instance_count == 0, MarketError::TemplateLocked);
+        settings.validate()?;
+        settings.apply(template);
+        Ok(())
+    }
+
+    /// Template creator instantiates a market, filling the question's
+    /// placeholders from `params` and inheriting every other setting
+    pub fn create_market_from_template(
+        ctx: Context<CreateMarketFromTemplate>,
+        params: TemplateParams,
+    ) -> Result<u64> {
+        require_approved_creator(&ctx.accounts.config, &ctx.accounts.creator_allow)?;
+        count_creator_market(
+            &mut ctx.accounts.creator_stats,
+            ctx.accounts.creator.key(),
+            ctx.bumps.creator_stats,
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+        )?;
+        let counter = &mut ctx.accounts.market_counter;
+        let market_id = counter.next_id;
+        counter.next_id = counter.next_id.checked_add(1).ok_or(MarketError::MarketIdOverflow)?;
+
+        let template = &mut ctx.accounts.template;
+        let question = render_question(&template.question_format, &params.args)?;
+        let resolution_time = params
+            .start_time
+            .checked_add(template.resolution_delay)
+            .ok_or(MarketError::InvalidResolutionTime)?;
+        template.instance_count = template.instance_count.saturating_add(1);
+        let initial_liquidity = template.initial_liquidity;
+
+        let creation_fee = charge_creation_fee(
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity,
+        )?;
+        let mut created = init_market(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            ctx.accounts.creator.key(),
+            outcome_mints(&ctx.accounts.yes_mint, &ctx.accounts.no_mint)?,
+            market_id,
+            question,
+            resolution_time,
+            initial_liquidity,
+            EVEN_ODDS_BPS,
+            0,
+            &MarketOptions {
+                category: template.category,
+                ..template.options.clone()
+            },
+            None,
+            ctx.bumps.market,
+            ctx.bumps.market_vault,
+        )?;
+        created.creation_fee = creation_fee;
+        let created = log_event(created);
+        emit_cpi!(created);
+        let market = &mut ctx.accounts.market;
+        market.question = created.question;
+        market.fee_bps = template.trading_fee_bps.or(market.fee_bps);
+
+        // Seed liquidity backs the initial pools, so it must be in the vault,
+        // as must the keeper budget
+        transfer_lamports(
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity.saturating_add(ctx.accounts.market.keeper_budget),
+        )?;
+
+        register_market(
+            &mut ctx.accounts.config,
+            &mut ctx.accounts.registry_page,
+            ctx.bumps.registry_page,
+            &ctx.accounts.market,
+        )?;
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) =
+            solvency_violation(&ctx.accounts.market, vault_lamports(&ctx.accounts.market_vault)?)
+        {
+            emit_cpi!(log_event(violation));
+        }
+
+        Ok(market_id)
+    }
+
+    /// Re-run an existing market under a new explicit ID
+    /// Question, category, fee settings, oracle configuration and market
+    /// options are copied from the source; the caller becomes the clone's
+    /// creator and pays its seed liquidity, split at even odds
+    pub fn clone_market(
+        ctx: Context<CloneMarket>,
+        source_market_id: u64,
+        new_market_id: u64,
+        new_resolution_time: i64,
+        initial_liquidity: u64,
+    ) -> Result<u64> {
+        require!(
+            new_market_id >= EXPLICIT_MARKET_ID_START,
+            MarketError::InvalidMarketId
+        );
+        require_approved_creator(&ctx.accounts.config, &ctx.accounts.creator_allow)?;
+        count_creator_market(
+            &mut ctx.accounts.creator_stats,
+            ctx.accounts.creator.key(),
+            ctx.bumps.creator_stats,
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+        )?;
+        let source = &ctx.accounts.source_market;
+        let question = source.question.clone();
+        let options = source.options();
+        let fee_bps = source.fee_bps;
+        validate_parent(options.parent, &ctx.accounts.parent_market, new_resolution_time)?;
+
+        let creation_fee = charge_creation_fee(
+            &ctx.accounts.config,
+            &ctx.accounts.creator_allow,
+            &mut ctx.accounts.treasury,
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity,
+        )?;
+        let mut created = init_market(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            ctx.accounts.creator.key(),
+            outcome_mints(&ctx.accounts.yes_mint, &ctx.accounts.no_mint)?,
+            new_market_id,
+            question,
+            new_resolution_time,
+            initial_liquidity,
+            EVEN_ODDS_BPS,
+            0,
+            &options,
+            None,
+            ctx.bumps.market,
+            ctx.bumps.market_vault,
+        )?;
+        created.creation_fee = creation_fee;
+        let created = log_event(created);
+        emit_cpi!(created);
+        let market = &mut ctx.accounts.market;
+        market.question = created.question;
+        market.fee_bps = fee_bps;
+
+        // Seed liquidity backs the initial pools, so it must be in the vault,
+        // as must the keeper budget
+        transfer_lamports(
+            &ctx.accounts.creator.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            initial_liquidity.saturating_add(ctx.accounts.market.keeper_budget),
+        )?;
+
+        register_question(
+            &mut ctx.accounts.question_registry,
+            ctx.bumps.question_registry,
+            &ctx.accounts.config,
+            &ctx.accounts.duplicate_approver,
+            ctx.accounts.market.key(),
+            new_market_id,
+        )?;
+        register_market(
+            &mut ctx.accounts.config,
+            &mut ctx.accounts.registry_page,
+            ctx.bumps.registry_page,
+            &ctx.accounts.market,
+        )?;
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) =
+            solvency_violation(&ctx.accounts.market, vault_lamports(&ctx.accounts.market_vault)?)
+        {
+            emit_cpi!(log_event(violation));
+        }
+
+        emit_cpi!(log_event(MarketCloned {
+            source_market_id,
+            market_id: new_market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            creator: ctx.accounts.creator.key(),
+        }));
+
+        Ok(new_market_id)
+    }
+
+    /// Create a named series for grouping a creator's related markets
+    pub fn create_series(ctx: Context<CreateSeries>, name: String) -> Result<()> {
+        require!(
+            !name.is_empty() && name.len() <= MAX_SERIES_NAME_LEN,
+            MarketError::InvalidSeriesName
+        );
+
+        let series = &mut ctx.accounts.series;
+        series.creator = ctx.accounts.creator.key();
+        series.name = name;
+        series.member_count = 0;
+        series.total_volume = 0;
+        series.bump = ctx.bumps.series;
+
+        emit_cpi!(log_event(SeriesCreated {
+            series: series.key(),
+            creator: series.creator,
+            name: series.name.clone(),
+        }));
+
+        Ok(())
+    }
+
+    /// Series creator adds one of their existing markets to the series
+    pub fn add_to_series(ctx: Context<SeriesMembership>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let series = &mut ctx.accounts.series;
+        require!(market.creator == series.creator, MarketError::Unauthorized);
+        require!(market.series.is_none(), MarketError::SeriesMismatch);
+
+        market.series = Some(series.key());
+        series.member_count = series.member_count.saturating_add(1);
+        series.total_volume = series.total_volume.saturating_add(market.total_volume);
+
+        emit_cpi!(log_event(SeriesMembershipChanged {
+            series: series.key(),
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            added: true,
+            member_count: series.member_count,
+        }));
+
+        Ok(())
+    }
+
+    /// Series creator removes a market from the series
+    /// Only allowed before the market's first bet
+    pub fn remove_from_series(ctx: Context<SeriesMembership>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let series = &mut ctx.accounts.series;
+        require!(
+            market.series == Some(series.key()),
+            MarketError::SeriesMismatch
+        );
+        require!(market.trade_count == 0, MarketError::MarketHasBets);
+
+        market.series = None;
+        series.member_count = series.member_count.saturating_sub(1);
+
+        emit_cpi!(log_event(SeriesMembershipChanged {
+            series: series.key(),
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            added: false,
+            member_count: series.member_count,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator attaches a description spelling out resolution criteria
+    /// The market account is resized to fit it (the creator pays or is
+    /// refunded the rent difference); an empty description removes it.
+    /// Only allowed before the first bet
+    pub fn set_description(
+        ctx: Context<SetDescription>,
+        market_id: u64,
+        description: String,
+    ) -> Result<()> {
+        require!(
+            description.len() <= MAX_DESCRIPTION_LEN,
+            MarketError::DescriptionTooLong
+        );
+        let market = &mut ctx.accounts.market;
+        require!(market.trade_count == 0, MarketError::DescriptionLocked);
+
+        market.description = description;
+
+        emit_cpi!(log_event(DescriptionSet {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            length: market.description.len() as u32,
+            account_size: market.to_account_info().data_len() as u32,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator changes the market's trading fee; zero reverts to the config
+    /// default. Only allowed before the first bet
+    pub fn set_market_fee(ctx: Context<SetMarketFee>, market_id: u64, fee_bps: u16) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.trade_count == 0, MarketError::FeeLocked);
+        market.fee_bps = market_fee(&ctx.accounts.config, fee_bps)?;
+
+        emit_cpi!(log_event(MarketFeeSet {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            fee_bps: market.fee_bps.unwrap_or(ctx.accounts.config.trading_fee_bps),
+        }));
+
+        Ok(())
+    }
+
+    /// Creator changes what a push resolves the market to. Only allowed
+    /// before the first bet, so every bettor knows the rule going in
+    pub fn set_push_policy(
+        ctx: Context<SetPushPolicy>,
+        market_id: u64,
+        push_policy: PushPolicy,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.trade_count == 0, MarketError::PushPolicyLocked);
+        market.push_policy = push_policy;
+
+        emit_cpi!(log_event(PushPolicySet {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            push_policy,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator of a bookmaker market changes its fixed odds. Bets already
+    /// placed keep the payout they were quoted
+    pub fn update_odds(
+        ctx: Context<UpdateOdds>,
+        market_id: u64,
+        yes_odds_bps: u32,
+        no_odds_bps: u32,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(
+            market.mode == MarketMode::Bookmaker,
+            MarketError::UnsupportedMarketMode
+        );
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(
+            yes_odds_bps as u64 > BPS_DENOMINATOR && no_odds_bps as u64 > BPS_DENOMINATOR,
+            MarketError::InvalidOdds
+        );
+        market.yes_odds_bps = yes_odds_bps;
+        market.no_odds_bps = no_odds_bps;
+
+        emit_cpi!(log_event(OddsUpdated {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            yes_odds_bps,
+            no_odds_bps,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator restarts the circuit breaker's window from the current
+    /// implied probability, letting a legitimate news-driven move continue
+    /// past max_move_per_window_bps. The per-bet limit still applies
+    pub fn reset_circuit_breaker(ctx: Context<ResetCircuitBreaker>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        market.move_window_start = Clock::get()?.unix_timestamp;
+        market.move_window_open_bps = market.implied_yes_probability_bps();
+
+        emit_cpi!(log_event(CircuitBreakerReset {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            window_start: market.move_window_start,
+            implied_yes_probability_bps: market.move_window_open_bps,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator sets who may resolve the market and when, before resolution
+    /// time. Tier i may resolve from resolution_time + delays[i]: the
+    /// primary first, then a backup key, then typically the protocol admin.
+    /// Unused tiers are Pubkey::default(); an unset primary is the creator.
+    /// After revoke_resolver this reassigns them, even past resolution
+    /// time, and they act only RESOLVER_REASSIGN_DELAY later
+    pub fn set_resolvers(
+        ctx: Context<UpdateResolvers>,
+        market_id: u64,
+        resolvers: [Pubkey; 3],
+        delays: [i64; 3],
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let now = Clock::get()?.unix_timestamp;
+        let reassigning = now < market.resolvers_active_at;
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(
+            now < market.resolution_time || reassigning,
+            MarketError::MarketExpired
+        );
+        require!(
+            delays[0] >= 0 && delays[0] <= delays[1] && delays[1] <= delays[2],
+            MarketError::InvalidResolverTiers
+        );
+        market.resolvers = resolvers;
+        market.resolver_delays = delays;
+        if reassigning {
+            market.resolvers_active_at = now.saturating_add(RESOLVER_REASSIGN_DELAY);
+        }
+
+        emit_cpi!(log_event(ResolversSet {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            resolvers,
+            delays,
+            active_at: market.resolvers_active_at,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator names a house account: a wallet seeding liquidity through
+    /// balancing bets, which may exceed max_bet and its ramp. House bets
+    /// pay fees and claim like any other, but are flagged on the
+    /// Prediction and kept out of profile stats and leaderboards
+    pub fn add_house_account(
+        ctx: Context<UpdateHouseAccounts>,
+        market_id: u64,
+        account: Pubkey,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(!market.is_house(&account), MarketError::HouseAccountExists);
+        require!(
+            market.house_accounts.len() < MAX_HOUSE_ACCOUNTS,
+            MarketError::TooManyHouseAccounts
+        );
+        market.house_accounts.push(account);
+
+        emit_cpi!(log_event(HouseAccountsChanged {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            account,
+            added: true,
+            house_accounts: market.house_accounts.clone(),
+        }));
+
+        Ok(())
+    }
+
+    /// Creator drops a house account. Its bets from here on are ordinary;
+    /// those already placed stay flagged
+    pub fn remove_house_account(
+        ctx: Context<UpdateHouseAccounts>,
+        market_id: u64,
+        account: Pubkey,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let index = market
+            .house_accounts
+            .iter()
+            .position(|house| *house == account)
+            .ok_or(MarketError::NotHouseAccount)?;
+        market.house_accounts.remove(index);
+
+        emit_cpi!(log_event(HouseAccountsChanged {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            account,
+            added: false,
+            house_accounts: market.house_accounts.clone(),
+        }));
+
+        Ok(())
+    }
+
+    /// Settle a conditional market's dependency once its parent's outcome
+    /// is final. If the parent went the required way the market can then
+    /// resolve as usual; otherwise it is voided and every deposit becomes
+    /// refundable through claim_refund. Permissionless
+    pub fn settle_parent_condition(
+        ctx: Context<SettleParentCondition>,
+        market_id: u64,
+    ) -> Result<()> {
+        let parent = &ctx.accounts.parent_market;
+        let market = &mut ctx.accounts.market;
+        let condition = market.parent.ok_or(MarketError::InvalidParentMarket)?;
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(!market.parent_met, MarketError::ParentConditionSettled);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            parent.resolved && !parent.disputed && now >= parent.claims_open_at(),
+            MarketError::ParentUnresolved
+        );
+
+        if parent.outcome.winner() == Some(condition.outcome) {
+            market.parent_met = true;
+            emit_cpi!(log_event(ParentConditionMet {
+                market_id,
+                market: market.key(),
+                tenant: market.tenant,
+                event_seq: market.next_event_seq(),
+                parent: condition.market,
+                outcome: condition.outcome,
+            }));
+        } else {
+            void_market(market, now);
+            emit_cpi!(log_event(MarketVoided {
+                market_id,
+                market: market.key(),
+                tenant: market.tenant,
+                event_seq: market.next_event_seq(),
+                reason: VoidReason::ParentOutcome,
+                refundable: market.liabilities(),
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Creator or config admin strips a compromised `resolver` from the
+    /// market's tiers and voids any pending resolution commitment. Nobody
+    /// can resolve the market until set_resolvers reassigns its resolvers
+    /// and RESOLVER_REASSIGN_DELAY has passed
+    pub fn revoke_resolver(
+        ctx: Context<RevokeResolver>,
+        market_id: u64,
+        resolver: Pubkey,
+    ) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        let market = &mut ctx.accounts.market;
+        require!(
+            authority == market.creator || authority == ctx.accounts.config.admin,
+            MarketError::Unauthorized
+        );
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(
+            market.resolver_tier(&resolver).is_some(),
+            MarketError::UnknownResolver
+        );
+        for key in market.resolvers.iter_mut() {
+            if *key == resolver {
+                *key = Pubkey::default();
+            }
+        }
+        let commitment_voided = market.resolution_commitment.take().is_some();
+        market.resolution_committed_at = 0;
+        market.resolvers_active_at = i64::MAX;
+
+        emit_cpi!(log_event(ResolverRevoked {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            resolver,
+            revoked_by: authority,
+            commitment_voided,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator nominates a new owner for the market. Nothing changes until
+    /// the nominee accepts; a new proposal replaces the pending one
+    pub fn propose_market_transfer(
+        ctx: Context<MarketTransfer>,
+        market_id: u64,
+        new_owner: Pubkey,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(new_owner != market.creator, MarketError::InvalidNewOwner);
+        market.pending_owner = Some(new_owner);
+
+        emit_cpi!(log_event(MarketTransferProposed {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            owner: market.creator,
+            pending_owner: new_owner,
+        }));
+
+        Ok(())
+    }
+
+    /// The nominated owner takes over the market's creator role
+    pub fn accept_market_transfer(
+        ctx: Context<AcceptMarketTransfer>,
+        market_id: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let previous_owner = market.creator;
+        market.creator = ctx.accounts.new_owner.key();
+        market.pending_owner = None;
+
+        emit_cpi!(log_event(MarketTransferAccepted {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            previous_owner,
+            new_owner: market.creator,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator withdraws a pending ownership transfer
+    pub fn cancel_market_transfer(ctx: Context<MarketTransfer>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let pending_owner = market
+            .pending_owner
+            .take()
+            .ok_or(MarketError::NoPendingTransfer)?;
+
+        emit_cpi!(log_event(MarketTransferCancelled {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            owner: market.creator,
+            pending_owner,
+        }));
+
+        Ok(())
+    }
+
+    /// Create Token Metadata for a market's YES and NO mints, named
+    /// "MKT-<id> YES" and "MKT-<id> NO" and pointing at the off-chain JSON at
+    /// `uri`. Both mints must have the market as mint authority, which also
+    /// becomes the update authority. The metadata is created immutable.
+    /// Creator only; the creator pays the rent
+    pub fn create_outcome_metadata(
+        ctx: Context<CreateOutcomeMetadata>,
+        market_id: u64,
+        uri: String,
+    ) -> Result<()> {
+        require!(uri.len() <= MAX_METADATA_URI_LEN, MarketError::InvalidMetadataUri);
+
+        let id_bytes = market_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] =
+            &[&[b"market", id_bytes.as_ref(), &[ctx.accounts.market.bump]]];
+        let sides = [
+            ("YES", &ctx.accounts.yes_mint, &ctx.accounts.yes_metadata),
+            ("NO", &ctx.accounts.no_mint, &ctx.accounts.no_metadata),
+        ];
+        for (side, mint, metadata) in sides {
+            create_metadata_accounts_v3(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_metadata_program.to_account_info(),
+                    CreateMetadataAccountsV3 {
+                        metadata: metadata.to_account_info(),
+                        mint: mint.to_account_info(),
+                        mint_authority: ctx.accounts.market.to_account_info(),
+                        payer: ctx.accounts.creator.to_account_info(),
+                        update_authority: ctx.accounts.market.to_account_info(),
+                        system_program: ctx.accounts.system_program.to_account_info(),
+                        rent: ctx.accounts.rent.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                DataV2 {
+                    name: format!("{}-{} {}", OUTCOME_TOKEN_SYMBOL, market_id, side),
+                    symbol: OUTCOME_TOKEN_SYMBOL.to_string(),
+                    uri: uri.clone(),
+                    seller_fee_basis_points: 0,
+                    creators: None,
+                    collection: None,
+                    uses: None,
+                },
+                false, // is_mutable
+                true,  // update_authority_is_signer
+                None,
+            )?;
+        }
+
+        emit_cpi!(log_event(OutcomeMetadataCreated {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            yes_metadata: ctx.accounts.yes_metadata.key(),
+            no_metadata: ctx.accounts.no_metadata.key(),
+            uri,
+        }));
+
+        Ok(())
+    }
+
+    /// Remove the mint authority of a resolved market's YES and NO mints so
+    /// no outcome tokens can ever be minted again. Nothing is frozen.
+    /// Permissionless
+    pub fn finalize_mints(ctx: Context<FinalizeMints>, market_id: u64) -> Result<()> {
+        require!(ctx.accounts.market.resolved, MarketError::MarketNotResolved);
+
+        let id_bytes = market_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] =
+            &[&[b"market", id_bytes.as_ref(), &[ctx.accounts.market.bump]]];
+        for mint in [&ctx.accounts.yes_mint, &ctx.accounts.no_mint] {
+            token::set_authority(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    SetAuthority {
+                        current_authority: ctx.accounts.market.to_account_info(),
+                        account_or_mint: mint.to_account_info(),
+                    },
+                    signer_seeds,
+                ),
+                AuthorityType::MintTokens,
+                None,
+            )?;
+        }
+
+        emit_cpi!(log_event(MintsFinalized {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            yes_mint: ctx.accounts.yes_mint.key(),
+            no_mint: ctx.accounts.no_mint.key(),
+        }));
+
+        Ok(())
+    }
+
+    /// Create the wSOL collateral account of a market initialized with
+    /// `wsol_vault` and wrap the seed liquidity into it. Permissionless; the
+    /// payer funds the token account's rent. Bets need this to have run
+    pub fn init_wsol_vault(ctx: Context<InitWsolVault>, market_id: u64) -> Result<()> {
+        require!(ctx.accounts.market.wsol_vault, MarketError::LamportVaultRequired);
+        require!(
+            ctx.accounts.market.collateral_vault == Pubkey::default(),
+            MarketError::WsolVaultExists
+        );
+        validate_collateral_mint(&ctx.accounts.collateral_mint.to_account_info())?;
+
+        let vault = ctx.accounts.wsol_vault.to_account_info();
+        let amount = vault_lamports(&ctx.accounts.market_vault)?;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= amount;
+        **vault.try_borrow_mut_lamports()? += amount;
+        token_interface::sync_native(CpiContext::new(
+            ctx.accounts.token_program.to_account_info(),
+            SyncNative {
+                account: vault.clone(),
+            },
+        ))?;
+        require!(
+            token::accessor::amount(&vault)? >= amount,
+            MarketError::CollateralShortfall
+        );
+
+        let market = &mut ctx.accounts.market;
+        market.collateral_mint = ctx.accounts.collateral_mint.key();
+        market.collateral_vault = vault.key();
+        market.collateral_decimals = ctx.accounts.collateral_mint.decimals;
+
+        emit_cpi!(log_event(WsolVaultInitialized {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            wsol_vault: ctx.accounts.wsol_vault.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// User places a prediction
+    /// Deposits SOL as collateral, receives either YES or NO tokens
+    /// Prices determined by Automated Market Maker (AMM) formula
+    /// `max_price_impact_bps` caps how far the bet may move the implied
+    /// probability; 0 means no limit. `note` is the bettor's own reference
+    /// for the bet, up to MAX_NOTE_LEN bytes; empty for none
+    /// In the market's reduce-only window, remaining_accounts must hold
+    /// every open position of the predictor's on the market, and the bet
+    /// must take their net toward flat; see require_reduces_exposure
+    /// With `dry_run` the bet runs in full and then fails with
+    /// DryRunComplete after logging DryRunResult; see dry_run_complete
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_prediction<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PlacePrediction<'info>>,
+        market_id: u64,
+        prediction_type: bool, // true = YES, false = NO
+        amount: u64,
+        max_price_impact_bps: u16,
+        note: String,
+        dry_run: bool,
+    ) -> Result<()> {
+        require!(note.len() <= MAX_NOTE_LEN, MarketError::NoteTooLong);
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        require_open_to_bets(
+            &ctx.accounts.config,
+            &ctx.accounts.market,
+            &ctx.accounts.self_exclusion,
+            now,
+        )?;
+        let predictor = ctx.accounts.predictor.key();
+        let discounted = discount_nft_verified(
+            &ctx.accounts.config,
+            &predictor,
+            &ctx.accounts.nft_token_account,
+            &ctx.accounts.nft_metadata,
+        )?;
+        let (yes_pool_at_entry, no_pool_at_entry) =
+            (ctx.accounts.market.yes_pool, ctx.accounts.market.no_pool);
+        let PredictionQuote {
+            tokens_out: tokens_to_mint,
+            effective_price_bps,
+            fee,
+            referral_fee: referral_cut,
+            insurance_fee,
+            net_amount,
+            weight_bps,
+            fee_bps,
+            price_impact_bps,
+            rebate,
+            ..
+        } = price_prediction(
+            &ctx.accounts.market,
+            &ctx.accounts.config,
+            &predictor,
+            ctx.accounts.referrer.as_deref(),
+            discounted,
+            prediction_type,
+            amount,
+            now,
+        )?;
+        require_bet(
+            max_price_impact_bps == 0 || price_impact_bps <= max_price_impact_bps,
+            market_id,
+            MarketError::PriceImpactTooHigh,
+            max_price_impact_bps as u64,
+            price_impact_bps as u64,
+        )?;
+        if ctx.accounts.market.reduce_only(now) {
+            require_reduces_exposure(
+                &ctx.accounts.market,
+                &predictor,
+                &ctx.accounts.user_market_index,
+                ctx.remaining_accounts,
+                prediction_type,
+                tokens_to_mint,
+            )?;
+        }
+
+        require!(
+            ctx.accounts.series.as_ref().map(|series| series.key()) == ctx.accounts.market.series,
+            MarketError::SeriesMismatch
+        );
+        if let Some(series) = &mut ctx.accounts.series {
+            series.total_volume = series.total_volume.saturating_add(amount);
+        }
+
+        // Counted against the creator until the market resolves and
+        // release_creator_liability runs
+        let stats = &mut ctx.accounts.creator_stats;
+        stats.init_if_new(ctx.accounts.market.liability_creator, ctx.bumps.creator_stats);
+        let open_liability = stats.open_liability.saturating_add(amount);
+        let cap = ctx.accounts.config.max_open_liability;
+        require_bet(
+            cap == 0 || open_liability <= cap,
+            market_id,
+            MarketError::CreatorLiabilityCapReached,
+            cap,
+            open_liability,
+        )?;
+        stats.open_liability = open_liability;
+        let market = &mut ctx.accounts.market;
+        market.creator_liability = market.creator_liability.saturating_add(amount);
+
+        apply_prediction(
+            &mut ctx.accounts.market,
+            prediction_type,
+            amount,
+            net_amount,
+            tokens_to_mint,
+            fee - referral_cut - insurance_fee,
+            rebate,
+            now,
+        )?;
+
+        // Transfer SOL to vault (less any rebate), the insurance cut to the
+        // fund, and the referral cut to the referrer
+        deposit_collateral(
+            &ctx.accounts.market,
+            &ctx.accounts.predictor.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.wsol_vault,
+            &ctx.accounts.token_program,
+            &ctx.accounts.system_program.to_account_info(),
+            amount - referral_cut - insurance_fee - rebate,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.predictor.to_account_info(),
+            &ctx.accounts.insurance_fund.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            insurance_fee,
+        )?;
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_deposited = fund.total_deposited.saturating_add(insurance_fee);
+
+        if let Some(referrer) = &mut ctx.accounts.referrer {
+            transfer_lamports(
+                &ctx.accounts.predictor.to_account_info(),
+                &referrer.to_account_info(),
+                &ctx.accounts.system_program.to_account_info(),
+                referral_cut,
+            )?;
+            referrer.accrued_fees = referrer.accrued_fees.saturating_add(referral_cut);
+            referrer.total_referred_volume =
+                referrer.total_referred_volume.saturating_add(amount);
+
+            emit_cpi!(log_event(ReferralAttributed {
+                market_id,
+                market: ctx.accounts.market.key(),
+                tenant: ctx.accounts.market.tenant,
+                event_seq: ctx.accounts.market.next_event_seq(),
+                predictor,
+                referrer: referrer.owner,
+                amount,
+                referral_fee: referral_cut,
+            }));
+        }
+
+        // Store prediction
+        let house = ctx.accounts.market.is_house(&predictor);
+        let prediction = &mut ctx.accounts.prediction_account;
+        prediction.version = PREDICTION_VERSION;
+        prediction.market_id = ctx.accounts.market.market_id;
+        prediction.predictor = predictor;
+        prediction.prediction_type = prediction_type;
+        prediction.amount_deposited = amount;
+        prediction.tokens_received = tokens_to_mint;
+        prediction.created_at = now;
+        prediction.claimed = false;
+        prediction.bump = ctx.bumps.prediction_account;
+        prediction.slot = clock.slot;
+        prediction.yes_pool_at_entry = yes_pool_at_entry;
+        prediction.no_pool_at_entry = no_pool_at_entry;
+        prediction.entry_price_bps = effective_price_bps;
+        prediction.entry_probability_bps =
+            side_probability_bps(yes_pool_at_entry, no_pool_at_entry, prediction_type);
+        prediction.trade_sequence = ctx.accounts.market.sequence;
+        prediction.cancel_refund = amount - referral_cut - insurance_fee - rebate;
+        prediction.is_house = ReserveFlag(house);
+        ctx.accounts.market.open_prediction(prediction);
+        prediction.rent_payer = ctx.accounts.rent_payer.key();
+        // Recorded once at the user's first bet on this market and never
+        // switched afterwards
+        prediction.referrer = ctx
+            .accounts
+            .referrer
+            .as_ref()
+            .map(|referrer| referrer.owner)
+            .unwrap_or_default();
+
+        let full = admit_participant(
+            &mut ctx.accounts.market,
+            &ctx.accounts.user_market_index,
+            false,
+        )?;
+        if let Some(full) = full {
+            emit_cpi!(log_event(full));
+        }
+        index_position(
+            &mut ctx.accounts.user_market_index,
+            ctx.bumps.user_market_index,
+            &mut ctx.accounts.user_index_page,
+            ctx.bumps.user_index_page,
+            &ctx.accounts.user_profile,
+            market_id,
+            predictor,
+        );
+
+        // Lifetime stats, created lazily on the user's first bet
+        let profile = &mut ctx.accounts.user_profile;
+        profile.init_if_new(predictor, ctx.bumps.user_profile);
+        // House bets still page the market index, but add no volume
+        profile.bets_placed = profile.bets_placed.saturating_add(1);
+        if !house {
+            profile.total_wagered = profile.total_wagered.saturating_add(amount);
+        }
+        emit_cpi!(log_event(profile.update_event()));
+
+        let epoch_stats = &mut ctx.accounts.epoch_stats;
+        epoch_stats.init_if_new(
+            predictor,
+            ctx.accounts.config.current_epoch,
+            ctx.bumps.epoch_stats,
+        );
+        if !house {
+            epoch_stats.bets_placed = epoch_stats.bets_placed.saturating_add(1);
+            epoch_stats.total_wagered = epoch_stats.total_wagered.saturating_add(amount);
+        }
+
+        // The note moves into the event and then onto the prediction, never
+        // cloned
+        let market = &mut ctx.accounts.market;
+        let placed = log_event(PredictionPlaced {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            predictor,
+            prediction_type,
+            amount,
+            normalized_amount: normalized_amount(amount, market.collateral_decimals),
+            tokens_received: tokens_to_mint,
+            weight_bps,
+            fee_bps,
+            rebate,
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+            implied_yes_probability_bps: market.implied_yes_probability_bps(),
+            sequence: market.sequence,
+            note,
+        });
+        emit_cpi!(placed);
+        ctx.accounts.prediction_account.note = placed.note;
+        let market = &mut ctx.accounts.market;
+
+        emit_cpi!(log_event(MarketStats {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            total_volume: market.total_volume,
+            trade_count: market.trade_count,
+            unique_bettors: market.unique_bettors,
+        }));
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) = solvency_violation(
+            &ctx.accounts.market,
+            collateral_balance(
+                &ctx.accounts.market,
+                &ctx.accounts.market_vault,
+                &ctx.accounts.wsol_vault,
+            )?,
+        ) {
+            emit_cpi!(log_event(violation));
+        }
+
+        if dry_run {
+            let market = &ctx.accounts.market;
+            return dry_run_complete(DryRunResult {
+                market_id,
+                user: predictor,
+                side: prediction_type,
+                paid: amount,
+                received: 0,
+                tokens: tokens_to_mint,
+                fee,
+                yes_pool: market.yes_pool,
+                no_pool: market.no_pool,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Creator opens the receipt tree of a `compressed` market, which
+    /// compressed bets are appended to
+    pub fn init_receipt_tree(ctx: Context<InitReceiptTree>, _market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.compressed, MarketError::NotCompressed);
+        let tree = &mut ctx.accounts.receipt_tree;
+        tree.market = market.key();
+        tree.frontier = [[0; 32]; RECEIPT_TREE_DEPTH];
+        tree.bump = ctx.bumps.receipt_tree;
+        market.receipt_root = empty_receipt_root();
+        market.receipt_count = 0;
+        Ok(())
+    }
+
+    /// Bet on a `compressed` market without opening a Prediction account.
+    /// The position becomes the next leaf of the market's receipt tree, and
+    /// its full data goes out in CompressedPredictionAppended for indexers
+    /// to build claim proofs from. `expected_root` is the root the client
+    /// last saw; if another bet was appended since, this fails with
+    /// StaleReceiptRoot and the client retries against the new root
+    pub fn place_compressed_prediction(
+        ctx: Context<PlaceCompressedPrediction>,
+        market_id: u64,
+        prediction_type: bool,
+        amount: u64,
+        max_price_impact_bps: u16,
+        expected_root: [u8; 32],
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let now = Clock::get()?.unix_timestamp;
+        require_open_to_bets(&ctx.accounts.config, market, &ctx.accounts.self_exclusion, now)?;
+        // A receipt has no UserMarketIndex to admit its wallet against
+        // max_participants with
+        require!(
+            market.compressed && market.max_participants == 0,
+            MarketError::NotCompressed
+        );
+        require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+        require!(market.receipt_root == expected_root, MarketError::StaleReceiptRoot);
+        require!(
+            market.receipt_count < 1 << RECEIPT_TREE_DEPTH,
+            MarketError::ReceiptTreeFull
+        );
+        require!(!market.reduce_only(now), MarketError::ReduceOnlyWindow);
+        let predictor = ctx.accounts.predictor.key();
+        let PredictionQuote {
+            tokens_out,
+            fee,
+            referral_fee,
+            insurance_fee,
+            net_amount,
+            price_impact_bps,
+            rebate,
+            ..
+        } = price_prediction(
+            market,
+            &ctx.accounts.config,
+            &predictor,
+            None,
+            false,
+            prediction_type,
+            amount,
+            now,
+        )?;
+        require_bet(
+            max_price_impact_bps == 0 || price_impact_bps <= max_price_impact_bps,
+            market_id,
+            MarketError::PriceImpactTooHigh,
+            max_price_impact_bps as u64,
+            price_impact_bps as u64,
+        )?;
+
+        let stats = &mut ctx.accounts.creator_stats;
+        stats.init_if_new(ctx.accounts.market.liability_creator, ctx.bumps.creator_stats);
+        let open_liability = stats.open_liability.saturating_add(amount);
+        let cap = ctx.accounts.config.max_open_liability;
+        require_bet(
+            cap == 0 || open_liability <= cap,
+            market_id,
+            MarketError::CreatorLiabilityCapReached,
+            cap,
+            open_liability,
+        )?;
+        stats.open_liability = open_liability;
+        let market = &mut ctx.accounts.market;
+        market.creator_liability = market.creator_liability.saturating_add(amount);
+
+        apply_prediction(
+            market,
+            prediction_type,
+            amount,
+            net_amount,
+            tokens_out,
+            fee - referral_fee - insurance_fee,
+            rebate,
+            now,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.predictor.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount - referral_fee - insurance_fee - rebate,
+        )?;
+        transfer_lamports(
+            &ctx.accounts.predictor.to_account_info(),
+            &ctx.accounts.insurance_fund.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            insurance_fee,
+        )?;
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_deposited = fund.total_deposited.saturating_add(insurance_fee);
+
+        let market = &mut ctx.accounts.market;
+        let receipt = CompressedReceipt {
+            index: market.receipt_count,
+            owner: predictor,
+            prediction_type,
+            amount_deposited: amount,
+            tokens_received: tokens_out,
+        };
+        let leaf = receipt.leaf(market_id);
+        market.receipt_root = ctx.accounts.receipt_tree.append(leaf, receipt.index);
+        market.receipt_count += 1;
+
+        emit_cpi!(log_event(CompressedPredictionAppended {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            receipt,
+            leaf,
+            root: market.receipt_root,
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+        }));
+
+        Ok(())
+    }
+
+    /// Claim a compressed bet by proving its receipt is a leaf of the
+    /// market's receipt tree, `proof` being its sibling path from the leaf
+    /// level up. A winning receipt is paid in full as claim_reward would
+    /// pay it; on a voided market any receipt gets its deposit back, net of
+    /// fees. Each
+    /// leaf pays once, tracked by its bit in a ReceiptClaims page
+    pub fn claim_compressed(
+        ctx: Context<ClaimCompressed>,
+        market_id: u64,
+        receipt: CompressedReceipt,
+        proof: Vec<[u8; 32]>,
+    ) -> Result<()> {
+        require!(
+            receipt.owner == ctx.accounts.claimer.key(),
+            MarketError::Unauthorized
+        );
+        require!(ctx.accounts.market.compressed, MarketError::NotCompressed);
+        require!(
+            receipt_root_from_proof(receipt.leaf(market_id), receipt.index, &proof)
+                == Some(ctx.accounts.market.receipt_root),
+            MarketError::InvalidReceiptProof
+        );
+        let claims = &mut ctx.accounts.receipt_claims;
+        claims.market_id = market_id;
+        claims.page = receipt.claims_page();
+        claims.bump = ctx.bumps.receipt_claims;
+        require!(claims.mark(receipt.index), MarketError::AlreadyClaimed);
+
+        let refunded = ctx.accounts.market.outcome == MarketOutcome::Void;
+        let amount = if refunded {
+            let market = &mut ctx.accounts.market;
+            let refund = receipt.amount_deposited;
+            market.total_claimed = market.total_claimed.saturating_add(refund);
+            market.claims_count = market.claims_count.saturating_add(1);
+            market.eligible_claimed = market.eligible_claimed.saturating_add(1);
+            refund
+        } else {
+            let available = winner_funds(
+                &ctx.accounts.market,
+                collateral_balance(&ctx.accounts.market, &ctx.accounts.market_vault, &None)?,
+            );
+            let redemption = redeem_winnings(
+                &mut ctx.accounts.market,
+                &mut receipt.to_prediction(market_id),
+                None,
+                available,
+                Clock::get()?.unix_timestamp,
+                false,
+            )?;
+            if redemption.published {
+                emit_cpi!(log_event(payout_params_published(&mut ctx.accounts.market)));
+            }
+            redemption.reward
+        };
+
+        pay_out_collateral(
+            &ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &None,
+            &None,
+            &None,
+            &None,
+            &ctx.accounts.claimer.to_account_info(),
+            amount,
+        )?;
+
+        let market = &mut ctx.accounts.market;
+        emit_cpi!(log_event(CompressedReceiptClaimed {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            index: receipt.index,
+            owner: receipt.owner,
+            amount,
+            refunded,
+        }));
+        if let Some(settled) = mark_fully_settled(market, false) {
+            emit_cpi!(log_event(settled));
+        }
+
+        Ok(())
+    }
+
+    /// Undo a bet placed within the market's cooling-off window, as long as
+    /// nothing has traded since: later bets were priced off its liquidity.
+    /// The pools, outstanding tokens and market stats go back to where they
+    /// were, the vault returns everything it took, and the position closes.
+    /// Insurance and referral cuts were paid out at bet time and stay paid
+    pub fn cancel_prediction(ctx: Context<CancelPrediction>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let prediction = &ctx.accounts.prediction_account;
+        let now = Clock::get()?.unix_timestamp;
+        require!(market.cooloff_secs > 0, MarketError::CooloffDisabled);
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(
+            now <= prediction.created_at.saturating_add(market.cooloff_secs),
+            MarketError::CooloffExpired
+        );
+        require!(
+            market.sequence == prediction.trade_sequence,
+            MarketError::TradedSinceBet
+        );
+        require!(
+            prediction.cancel_refund > 0 && prediction.tokens_claimed == 0,
+            MarketError::NotCancellable
+        );
+
+        let refund = prediction.cancel_refund;
+        let net_amount = if prediction.prediction_type {
+            market.yes_tokens = market.yes_tokens.saturating_sub(prediction.tokens_received);
+            market.yes_deposits = market.yes_deposits.saturating_sub(prediction.amount_deposited);
+            market.yes_pool.saturating_sub(prediction.yes_pool_at_entry)
+        } else {
+            market.no_tokens = market.no_tokens.saturating_sub(prediction.tokens_received);
+            market.no_deposits = market.no_deposits.saturating_sub(prediction.amount_deposited);
+            market.no_pool.saturating_sub(prediction.no_pool_at_entry)
+        };
+        market.yes_pool = prediction.yes_pool_at_entry;
+        market.no_pool = prediction.no_pool_at_entry;
+        market.fee_collected = market
+            .fee_collected
+            .saturating_sub(refund.saturating_sub(net_amount));
+        market.total_volume = market.total_volume.saturating_sub(prediction.amount_deposited);
+        market.net_volume = market.net_volume.saturating_sub(net_amount);
+        market.trade_count = market.trade_count.saturating_sub(1);
+        market.unique_bettors = market.unique_bettors.saturating_sub(1);
+        market.remove_position(prediction.prediction_type);
+        market.close_prediction(prediction);
+        market.sequence = market.sequence.saturating_add(1);
+        market.last_trade_at = now;
+        market.record_pool_checkpoint(now);
+        market.last_activity_at = now;
+
+        let index = &mut ctx.accounts.user_market_index;
+        index.open_positions = index.open_positions.saturating_sub(1);
+
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= refund;
+        **ctx.accounts.predictor.to_account_info().try_borrow_mut_lamports()? += refund;
+
+        emit_cpi!(log_event(PredictionCancelled {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            predictor: prediction.predictor,
+            refund,
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+            sequence: market.sequence,
+        }));
+
+        Ok(())
+    }
+
+    /// Cash a position out for its deposit less the market's early exit
+    /// penalty, closing it. The refund comes out of the bettor's side of
+    /// the pool along with their tokens, so the penalty stays in the pot
+    /// for the eventual winners. Refused once betting closes, and during the
+    /// pre-close freeze unless the market allows exits
+    pub fn early_exit(ctx: Context<CancelPrediction>, _market_id: u64) -> Result<()> {
+        ctx.accounts.config.require_feature(FEATURE_EARLY_EXIT)?;
+        let now = Clock::get()?.unix_timestamp;
+        let exited = exit_position(
+            &mut ctx.accounts.market,
+            &ctx.accounts.prediction_account,
+            now,
+        )?;
+        ctx.accounts.market.close_prediction(&ctx.accounts.prediction_account);
+
+        let index = &mut ctx.accounts.user_market_index;
+        index.open_positions = index.open_positions.saturating_sub(1);
+
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= exited.refund;
+        **ctx.accounts.predictor.to_account_info().try_borrow_mut_lamports()? += exited.refund;
+
+        emit_cpi!(log_event(exited));
+
+        Ok(())
+    }
+
+    /// Predictor sets the implied probability of their side below which a
+    /// keeper may exit the position for them; 0 clears it. Stops execute
+    /// through early_exit, so the market must allow early exits
+    pub fn set_stop_loss(
+        ctx: Context<SetStopLoss>,
+        _market_id: u64,
+        threshold_bps: u16,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.market.early_exit_penalty_bps > 0,
+            MarketError::EarlyExitDisabled
+        );
+        require!(
+            (threshold_bps as u64) < BPS_DENOMINATOR,
+            MarketError::InvalidStopLoss
+        );
+        ctx.accounts.prediction_account.stop_loss_bps = threshold_bps;
+        Ok(())
+    }
+
+    /// Keeper exits a position whose side has fallen below its stop-loss,
+    /// judged against the live pools. The proceeds go to the predictor less
+    /// the keeper's tip, STOP_LOSS_TIP_BPS of them up to MAX_STOP_LOSS_TIP
+    pub fn execute_stop_loss(ctx: Context<ExecuteStopLoss>, market_id: u64) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let prediction = &ctx.accounts.prediction_account;
+        require!(prediction.stop_loss_bps > 0, MarketError::StopLossNotSet);
+        let probability_bps =
+            side_probability_bps(market.yes_pool, market.no_pool, prediction.prediction_type);
+        require!(
+            probability_bps < prediction.stop_loss_bps,
+            MarketError::StopLossNotBreached
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let exited = exit_position(
+            &mut ctx.accounts.market,
+            &ctx.accounts.prediction_account,
+            now,
+        )?;
+        ctx.accounts.market.close_prediction(&ctx.accounts.prediction_account);
+        let rounding = ctx.accounts.market.rounding_policy;
+        let tip = rounding.charge_bps(exited.refund, STOP_LOSS_TIP_BPS).min(MAX_STOP_LOSS_TIP);
+
+        let index = &mut ctx.accounts.user_market_index;
+        index.open_positions = index.open_positions.saturating_sub(1);
+
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= exited.refund;
+        **ctx.accounts.predictor.to_account_info().try_borrow_mut_lamports()? +=
+            exited.refund - tip;
+        **ctx.accounts.keeper.to_account_info().try_borrow_mut_lamports()? += tip;
+
+        // exit_position numbered the exit first, so it goes out first
+        let predictor = exited.predictor;
+        let proceeds = exited.refund - tip;
+        emit_cpi!(log_event(exited));
+        emit_cpi!(log_event(StopLossExecuted {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            predictor,
+            keeper: ctx.accounts.keeper.key(),
+            probability_bps,
+            proceeds,
+            tip,
+        }));
+        if let Some(paid) = pay_keeper_tip(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            KeeperAction::ExecuteStopLoss,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.keeper.to_account_info(),
+        )? {
+            emit_cpi!(log_event(paid));
+        }
+
+        Ok(())
+    }
+
+    /// Lock lamports against a hidden bet on a commit-reveal market
+    /// `commitment` is keccak(side (u8) || amount (LE) || salt); the bet is
+    /// priced only when revealed, against the pools at that time
+    pub fn commit_bet(
+        ctx: Context<CommitBet>,
+        market_id: u64,
+        commitment: [u8; 32],
+        escrow_amount: u64,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(market.bet_reveal_window > 0, MarketError::CommitRevealDisabled);
+        require!(escrow_amount > 0, MarketError::InvalidAmount);
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < market.betting_close_time(), MarketError::MarketExpired);
+        require!(!market.betting_frozen(now), MarketError::BettingFrozen);
+        require!(!market.reduce_only(now), MarketError::ReduceOnlyWindow);
+        require_not_self_excluded(&ctx.accounts.self_exclusion, now)?;
+
+        transfer_lamports(
+            &ctx.accounts.bettor.to_account_info(),
+            &ctx.accounts.bet_commit.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            escrow_amount,
+        )?;
+
+        let reveal_deadline = now.saturating_add(market.bet_reveal_window);
+        let bet_commit = &mut ctx.accounts.bet_commit;
+        bet_commit.market_id = market_id;
+        bet_commit.bettor = ctx.accounts.bettor.key();
+        bet_commit.commitment = commitment;
+        bet_commit.escrow = escrow_amount;
+        bet_commit.committed_at = now;
+        bet_commit.bump = ctx.bumps.bet_commit;
+
+        emit_cpi!(log_event(BetCommitted {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            bettor: bet_commit.bettor,
+            escrow: escrow_amount,
+            reveal_deadline,
+        }));
+
+        Ok(())
+    }
+
+    /// Reveal a committed bet within the market's reveal window
+    /// Prices the bet against the current pools, moves `amount` from escrow
+    /// into the vault and returns the rest of the escrow to the bettor
+    pub fn reveal_bet(
+        ctx: Context<RevealBet>,
+        market_id: u64,
+        prediction_type: bool,
+        amount: u64,
+        salt: [u8; 32],
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, MarketError::ProtocolPaused);
+        require!(!ctx.accounts.market.needs_attention, MarketError::NeedsAttention);
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        let bettor = ctx.accounts.bettor.key();
+        let bet_commit = &ctx.accounts.bet_commit;
+        require!(
+            now <= bet_commit
+                .committed_at
+                .saturating_add(ctx.accounts.market.bet_reveal_window),
+            MarketError::RevealWindowClosed
+        );
+        require!(
+            keccak::hashv(&[&[prediction_type as u8], &amount.to_le_bytes(), &salt]).to_bytes()
+                == bet_commit.commitment,
+            MarketError::CommitmentMismatch
+        );
+        require_bet(
+            amount <= bet_commit.escrow,
+            market_id,
+            MarketError::RevealExceedsEscrow,
+            bet_commit.escrow,
+            amount,
+        )?;
+
+        let (yes_pool_at_entry, no_pool_at_entry) =
+            (ctx.accounts.market.yes_pool, ctx.accounts.market.no_pool);
+        let PredictionQuote {
+            tokens_out,
+            effective_price_bps,
+            fee,
+            insurance_fee,
+            net_amount,
+            weight_bps,
+            fee_bps,
+            rebate,
+            ..
+        } = price_prediction(
+            &ctx.accounts.market,
+            &ctx.accounts.config,
+            &bettor,
+            None,
+            false,
+            prediction_type,
+            amount,
+            now,
+        )?;
+
+        require!(
+            ctx.accounts.series.as_ref().map(|series| series.key()) == ctx.accounts.market.series,
+            MarketError::SeriesMismatch
+        );
+        if let Some(series) = &mut ctx.accounts.series {
+            series.total_volume = series.total_volume.saturating_add(amount);
+        }
+
+        apply_prediction(
+            &mut ctx.accounts.market,
+            prediction_type,
+            amount,
+            net_amount,
+            tokens_out,
+            fee - insurance_fee,
+            rebate,
+            now,
+        )?;
+
+        // The remaining escrow, any rebate and rent go back when bet_commit
+        // closes
+        **ctx.accounts.bet_commit.to_account_info().try_borrow_mut_lamports()? -=
+            amount - rebate;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? +=
+            amount - insurance_fee - rebate;
+        **ctx.accounts.insurance_fund.to_account_info().try_borrow_mut_lamports()? +=
+            insurance_fee;
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_deposited = fund.total_deposited.saturating_add(insurance_fee);
+
+        let house = ctx.accounts.market.is_house(&bettor);
+        let prediction = &mut ctx.accounts.prediction_account;
+        prediction.version = PREDICTION_VERSION;
+        prediction.market_id = ctx.accounts.market.market_id;
+        prediction.predictor = bettor;
+        prediction.prediction_type = prediction_type;
+        prediction.amount_deposited = amount;
+        prediction.tokens_received = tokens_out;
+        prediction.created_at = now;
+        prediction.claimed = false;
+        prediction.referrer = Pubkey::default();
+        prediction.bump = ctx.bumps.prediction_account;
+        prediction.slot = clock.slot;
+        prediction.yes_pool_at_entry = yes_pool_at_entry;
+        prediction.no_pool_at_entry = no_pool_at_entry;
+        prediction.entry_price_bps = effective_price_bps;
+        prediction.entry_probability_bps =
+            side_probability_bps(yes_pool_at_entry, no_pool_at_entry, prediction_type);
+        prediction.trade_sequence = ctx.accounts.market.sequence;
+        prediction.cancel_refund = 0;
+        prediction.is_house = ReserveFlag(house);
+        ctx.accounts.market.open_prediction(prediction);
+
+        let full = admit_participant(
+            &mut ctx.accounts.market,
+            &ctx.accounts.user_market_index,
+            true,
+        )?;
+        if let Some(full) = full {
+            emit_cpi!(log_event(full));
+        }
+        index_position(
+            &mut ctx.accounts.user_market_index,
+            ctx.bumps.user_market_index,
+            &mut ctx.accounts.user_index_page,
+            ctx.bumps.user_index_page,
+            &ctx.accounts.user_profile,
+            market_id,
+            bettor,
+        );
+
+        let profile = &mut ctx.accounts.user_profile;
+        profile.init_if_new(bettor, ctx.bumps.user_profile);
+        profile.bets_placed = profile.bets_placed.saturating_add(1);
+        if !house {
+            profile.total_wagered = profile.total_wagered.saturating_add(amount);
+        }
+        emit_cpi!(log_event(profile.update_event()));
+
+        let epoch_stats = &mut ctx.accounts.epoch_stats;
+        epoch_stats.init_if_new(
+            bettor,
+            ctx.accounts.config.current_epoch,
+            ctx.bumps.epoch_stats,
+        );
+        if !house {
+            epoch_stats.bets_placed = epoch_stats.bets_placed.saturating_add(1);
+            epoch_stats.total_wagered = epoch_stats.total_wagered.saturating_add(amount);
+        }
+
+        let market = &mut ctx.accounts.market;
+        emit_cpi!(log_event(PredictionPlaced {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            predictor: bettor,
+            prediction_type,
+            amount,
+            normalized_amount: normalized_amount(amount, market.collateral_decimals),
+            tokens_received: tokens_out,
+            weight_bps,
+            fee_bps,
+            rebate,
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+            implied_yes_probability_bps: market.implied_yes_probability_bps(),
+            sequence: market.sequence,
+            note: String::new(),
+        }));
+
+        emit_cpi!(log_event(MarketStats {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            total_volume: market.total_volume,
+            trade_count: market.trade_count,
+            unique_bettors: market.unique_bettors,
+        }));
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) =
+            solvency_violation(&ctx.accounts.market, vault_lamports(&ctx.accounts.market_vault)?)
+        {
+            emit_cpi!(log_event(violation));
+        }
+
+        Ok(())
+    }
+
+    /// Reclaim the escrow of a bet commitment that was never revealed
+    /// Available once the reveal window has passed. BET_COMMIT_PENALTY_BPS
+    /// of the escrow goes to the market's fees, unless betting closed (or
+    /// froze) before the window ended
+    pub fn refund_bet_commit(ctx: Context<RefundBetCommit>, market_id: u64) -> Result<()> {
+        let escrow = ctx.accounts.bet_commit.escrow;
+        let reveal_deadline = ctx
+            .accounts
+            .bet_commit
+            .committed_at
+            .saturating_add(ctx.accounts.market.bet_reveal_window);
+        require!(
+            Clock::get()?.unix_timestamp > reveal_deadline,
+            MarketError::RevealWindowOpen
+        );
+
+        let market = &mut ctx.accounts.market;
+        let penalty = if !market.betting_frozen(reveal_deadline) {
+            market.rounding_policy.charge_bps(escrow, BET_COMMIT_PENALTY_BPS)
+        } else {
+            0
+        };
+        market.fee_collected = market.fee_collected.saturating_add(penalty);
+        **ctx.accounts.bet_commit.to_account_info().try_borrow_mut_lamports()? -= penalty;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? += penalty;
+
+        emit_cpi!(log_event(BetCommitRefunded {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            bettor: ctx.accounts.bettor.key(),
+            refunded: escrow.saturating_sub(penalty),
+            penalty,
+        }));
+
+        Ok(())
+    }
+
+    /// Rest an order to bet `amount` on `side` once that side's implied
+    /// probability is at or below `limit_probability_bps`. The amount is
+    /// escrowed in the LimitEntry along with the rent a fill will need, and
+    /// the fill opens the owner's position, so the owner must not already
+    /// hold one in this market
+    pub fn place_limit_entry(
+        ctx: Context<PlaceLimitEntry>,
+        market_id: u64,
+        side: bool, // true = YES, false = NO
+        limit_probability_bps: u16,
+        amount: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.require_feature(FEATURE_LIMIT_ENTRIES)?;
+        let market = &ctx.accounts.market;
+        let tip = market.rounding_policy.charge_bps(amount, LIMIT_ENTRY_TIP_BPS);
+        require_bet(
+            amount > tip,
+            market_id,
+            MarketError::LimitEntryBelowTip,
+            tip,
+            amount,
+        )?;
+        require!(
+            limit_probability_bps > 0 && (limit_probability_bps as u64) < BPS_DENOMINATOR,
+            MarketError::InvalidOrderPrice
+        );
+        require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+        require!(
+            market.bet_reveal_window == 0,
+            MarketError::CommitRevealRequired
+        );
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < market.betting_close_time(), MarketError::MarketExpired);
+        require_not_self_excluded(&ctx.accounts.self_exclusion, now)?;
+
+        let rent = Rent::get()?;
+        let fill_rent = [
+            Prediction::INIT_SPACE,
+            UserProfile::INIT_SPACE,
+            UserMarketIndex::INIT_SPACE,
+            UserIndexPage::INIT_SPACE,
+            UserEpochStats::INIT_SPACE,
+        ]
+        .iter()
+        .map(|space| rent.minimum_balance(8 + space))
+        .sum::<u64>();
+        transfer_lamports(
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.limit_entry.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount.saturating_add(fill_rent),
+        )?;
+
+        let entry = &mut ctx.accounts.limit_entry;
+        entry.market_id = market_id;
+        entry.owner = ctx.accounts.owner.key();
+        entry.side = side;
+        entry.limit_probability_bps = limit_probability_bps;
+        entry.amount = amount;
+        entry.placed_at = now;
+        entry.bump = ctx.bumps.limit_entry;
+
+        emit_cpi!(log_event(LimitEntryPlaced {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            owner: entry.owner,
+            side,
+            limit_probability_bps,
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Fill a resting limit entry, callable by anyone once the side's
+    /// implied probability is at or below the limit. The bet is priced by
+    /// the same path as place_prediction at the moment of the fill; the
+    /// keeper is paid LIMIT_ENTRY_TIP_BPS of the entry and reimbursed the
+    /// rent of the accounts it creates, and the rest of the escrow returns
+    /// to the owner
+    pub fn trigger_limit_entry(ctx: Context<TriggerLimitEntry>, market_id: u64) -> Result<()> {
+        require!(!ctx.accounts.config.paused, MarketError::ProtocolPaused);
+        require!(!ctx.accounts.market.needs_attention, MarketError::NeedsAttention);
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        let entry = &ctx.accounts.limit_entry;
+        let (owner, side) = (entry.owner, entry.side);
+        let market = &ctx.accounts.market;
+        require!(now < market.betting_close_time(), MarketError::MarketExpired);
+        require!(!market.reduce_only(now), MarketError::ReduceOnlyWindow);
+        let probability_bps = side_probability_bps(market.yes_pool, market.no_pool, side);
+        require!(
+            probability_bps <= entry.limit_probability_bps,
+            MarketError::LimitNotReached
+        );
+
+        let tip = market.rounding_policy.charge_bps(entry.amount, LIMIT_ENTRY_TIP_BPS);
+        let amount = entry.amount - tip;
+        let (yes_pool_at_entry, no_pool_at_entry) = (market.yes_pool, market.no_pool);
+        let PredictionQuote {
+            tokens_out,
+            effective_price_bps,
+            fee,
+            insurance_fee,
+            net_amount,
+            weight_bps,
+            fee_bps,
+            rebate,
+            ..
+        } = price_prediction(
+            market,
+            &ctx.accounts.config,
+            &owner,
+            None,
+            false,
+            side,
+            amount,
+            now,
+        )?;
+
+        require!(
+            ctx.accounts.series.as_ref().map(|series| series.key()) == market.series,
+            MarketError::SeriesMismatch
+        );
+        if let Some(series) = &mut ctx.accounts.series {
+            series.total_volume = series.total_volume.saturating_add(amount);
+        }
+
+        // Rent of the accounts created for this fill, which the keeper paid
+        let mut created_rent = ctx.accounts.prediction_account.to_account_info().lamports();
+        if ctx.accounts.user_profile.user == Pubkey::default() {
+            created_rent += ctx.accounts.user_profile.to_account_info().lamports();
+        }
+        if ctx.accounts.user_market_index.user == Pubkey::default() {
+            created_rent += ctx.accounts.user_market_index.to_account_info().lamports();
+        }
+        if ctx.accounts.user_index_page.user == Pubkey::default() {
+            created_rent += ctx.accounts.user_index_page.to_account_info().lamports();
+        }
+        if ctx.accounts.epoch_stats.user == Pubkey::default() {
+            created_rent += ctx.accounts.epoch_stats.to_account_info().lamports();
+        }
+
+        apply_prediction(
+            &mut ctx.accounts.market,
+            side,
+            amount,
+            net_amount,
+            tokens_out,
+            fee - insurance_fee,
+            rebate,
+            now,
+        )?;
+
+        // Any rebate and unused rent go back to the owner when the entry
+        // closes
+        **ctx.accounts.limit_entry.to_account_info().try_borrow_mut_lamports()? -=
+            amount - rebate + tip + created_rent;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? +=
+            amount - insurance_fee - rebate;
+        **ctx.accounts.insurance_fund.to_account_info().try_borrow_mut_lamports()? +=
+            insurance_fee;
+        **ctx.accounts.keeper.to_account_info().try_borrow_mut_lamports()? +=
+            tip + created_rent;
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_deposited = fund.total_deposited.saturating_add(insurance_fee);
+
+        let house = ctx.accounts.market.is_house(&owner);
+        let prediction = &mut ctx.accounts.prediction_account;
+        prediction.version = PREDICTION_VERSION;
+        prediction.market_id = ctx.accounts.market.market_id;
+        prediction.predictor = owner;
+        prediction.prediction_type = side;
+        prediction.amount_deposited = amount;
+        prediction.tokens_received = tokens_out;
+        prediction.created_at = now;
+        prediction.claimed = false;
+        prediction.referrer = Pubkey::default();
+        prediction.bump = ctx.bumps.prediction_account;
+        prediction.slot = clock.slot;
+        prediction.yes_pool_at_entry = yes_pool_at_entry;
+        prediction.no_pool_at_entry = no_pool_at_entry;
+        prediction.entry_price_bps = effective_price_bps;
+        prediction.entry_probability_bps = probability_bps;
+        prediction.trade_sequence = ctx.accounts.market.sequence;
+        prediction.cancel_refund = 0;
+        prediction.is_house = ReserveFlag(house);
+        ctx.accounts.market.open_prediction(prediction);
+
+        let full = admit_participant(
+            &mut ctx.accounts.market,
+            &ctx.accounts.user_market_index,
+            false,
+        )?;
+        if let Some(full) = full {
+            emit_cpi!(log_event(full));
+        }
+        index_position(
+            &mut ctx.accounts.user_market_index,
+            ctx.bumps.user_market_index,
+            &mut ctx.accounts.user_index_page,
+            ctx.bumps.user_index_page,
+            &ctx.accounts.user_profile,
+            market_id,
+            owner,
+        );
+
+        let profile = &mut ctx.accounts.user_profile;
+        profile.init_if_new(owner, ctx.bumps.user_profile);
+        profile.bets_placed = profile.bets_placed.saturating_add(1);
+        if !house {
+            profile.total_wagered = profile.total_wagered.saturating_add(amount);
+        }
+        emit_cpi!(log_event(profile.update_event()));
+
+        let epoch_stats = &mut ctx.accounts.epoch_stats;
+        epoch_stats.init_if_new(owner, ctx.accounts.config.current_epoch, ctx.bumps.epoch_stats);
+        if !house {
+            epoch_stats.bets_placed = epoch_stats.bets_placed.saturating_add(1);
+            epoch_stats.total_wagered = epoch_stats.total_wagered.saturating_add(amount);
+        }
+
+        let market = &mut ctx.accounts.market;
+        emit_cpi!(log_event(PredictionPlaced {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            predictor: owner,
+            prediction_type: side,
+            amount,
+            normalized_amount: normalized_amount(amount, market.collateral_decimals),
+            tokens_received: tokens_out,
+            weight_bps,
+            fee_bps,
+            rebate,
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+            implied_yes_probability_bps: market.implied_yes_probability_bps(),
+            sequence: market.sequence,
+            note: String::new(),
+        }));
+
+        emit_cpi!(log_event(LimitEntryFilled {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            owner,
+            keeper: ctx.accounts.keeper.key(),
+            probability_bps,
+            amount,
+            tokens_received: tokens_out,
+            tip,
+        }));
+        if let Some(paid) = pay_keeper_tip(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            KeeperAction::TriggerLimitEntry,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.keeper.to_account_info(),
+        )? {
+            emit_cpi!(log_event(paid));
+        }
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) =
+            solvency_violation(&ctx.accounts.market, vault_lamports(&ctx.accounts.market_vault)?)
+        {
+            emit_cpi!(log_event(violation));
+        }
+
+        Ok(())
+    }
+
+    /// Owner withdraws an unfilled limit entry, refunding the escrow
+    pub fn cancel_limit_entry(ctx: Context<CancelLimitEntry>, market_id: u64) -> Result<()> {
+        emit_cpi!(log_event(LimitEntryClosed {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            owner: ctx.accounts.owner.key(),
+            refunded: ctx.accounts.limit_entry.to_account_info().lamports(),
+            expired: false,
+        }));
+
+        Ok(())
+    }
+
+    /// Refund an unfilled limit entry to its owner once betting has
+    /// closed, callable by anyone
+    pub fn expire_limit_entry(ctx: Context<ExpireLimitEntry>, market_id: u64) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(
+            market.resolved || Clock::get()?.unix_timestamp >= market.betting_close_time(),
+            MarketError::LimitEntryActive
+        );
+
+        emit_cpi!(log_event(LimitEntryClosed {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            owner: ctx.accounts.owner.key(),
+            refunded: ctx.accounts.limit_entry.to_account_info().lamports(),
+            expired: true,
+        }));
+        // The entry is closed on return, so each one tips once
+        if let Some(paid) = pay_keeper_tip(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            KeeperAction::ExpireLimitEntry,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.keeper.to_account_info(),
+        )? {
+            emit_cpi!(log_event(paid));
+        }
+
+        Ok(())
+    }
+
+    /// Let `delegate` (a trading bot's key) bet on the owner's behalf without
+    /// holding the owner's funds: `deposit` is escrowed in the
+    /// TradingDelegate, and each delegated bet is capped at `max_bet`, all of
+    /// them together at `budget`, and none may be placed from `expires_at`
+    pub fn create_trading_delegate(
+        ctx: Context<CreateTradingDelegate>,
+        delegate: Pubkey,
+        max_bet: u64,
+        budget: u64,
+        expires_at: i64,
+        deposit: u64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            max_bet > 0 && budget >= max_bet && expires_at > now && deposit > 0,
+            MarketError::InvalidTradingDelegate
+        );
+        transfer_lamports(
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.trading_delegate.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            deposit,
+        )?;
+
+        let grant = &mut ctx.accounts.trading_delegate;
+        grant.owner = ctx.accounts.owner.key();
+        grant.delegate = delegate;
+        grant.max_bet = max_bet;
+        grant.budget = budget;
+        grant.spent = 0;
+        grant.expires_at = expires_at;
+        grant.bump = ctx.bumps.trading_delegate;
+
+        emit_cpi!(log_event(TradingDelegateCreated {
+            owner: grant.owner,
+            delegate,
+            max_bet,
+            budget,
+            expires_at,
+            deposit,
+        }));
+
+        Ok(())
+    }
+
+    /// Place a prediction as an owner's trading delegate. The stake comes
+    /// out of the TradingDelegate's escrow and the position belongs to the
+    /// owner. The delegate pays the rent of the accounts the bet creates
+    /// and is reimbursed from the escrow, so it needs no funds beyond
+    /// transaction fees. Lamport vault markets only
+    pub fn place_prediction_delegated(
+        ctx: Context<PlacePredictionDelegated>,
+        market_id: u64,
+        prediction_type: bool, // true = YES, false = NO
+        amount: u64,
+        max_price_impact_bps: u16,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, MarketError::ProtocolPaused);
+        require!(!ctx.accounts.market.needs_attention, MarketError::NeedsAttention);
+        require!(
+            ctx.accounts.market.bet_reveal_window == 0,
+            MarketError::CommitRevealRequired
+        );
+        require!(!ctx.accounts.market.wsol_vault, MarketError::LamportVaultRequired);
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        require!(
+            !ctx.accounts.market.reduce_only(now),
+            MarketError::ReduceOnlyWindow
+        );
+        require_not_self_excluded(&ctx.accounts.self_exclusion, now)?;
+        let grant = &ctx.accounts.trading_delegate;
+        require!(now < grant.expires_at, MarketError::DelegateExpired);
+        require!(amount <= grant.max_bet, MarketError::DelegateBetTooLarge);
+        require!(
+            grant.spent.saturating_add(amount) <= grant.budget,
+            MarketError::DelegateBudgetExceeded
+        );
+
+        let owner = grant.owner;
+        let (yes_pool_at_entry, no_pool_at_entry) =
+            (ctx.accounts.market.yes_pool, ctx.accounts.market.no_pool);
+        let PredictionQuote {
+            tokens_out,
+            effective_price_bps,
+            fee,
+            insurance_fee,
+            net_amount,
+            weight_bps,
+            fee_bps,
+            price_impact_bps,
+            rebate,
+            ..
+        } = price_prediction(
+            &ctx.accounts.market,
+            &ctx.accounts.config,
+            &owner,
+            None,
+            false,
+            prediction_type,
+            amount,
+            now,
+        )?;
+        require_bet(
+            max_price_impact_bps == 0 || price_impact_bps <= max_price_impact_bps,
+            market_id,
+            MarketError::PriceImpactTooHigh,
+            max_price_impact_bps as u64,
+            price_impact_bps as u64,
+        )?;
+
+        require!(
+            ctx.accounts.series.as_ref().map(|series| series.key()) == ctx.accounts.market.series,
+            MarketError::SeriesMismatch
+        );
+        if let Some(series) = &mut ctx.accounts.series {
+            series.total_volume = series.total_volume.saturating_add(amount);
+        }
+
+        // Rent of the accounts created for this bet, which the delegate paid
+        let mut created_rent = ctx.accounts.prediction_account.to_account_info().lamports();
+        if ctx.accounts.user_profile.user == Pubkey::default() {
+            created_rent += ctx.accounts.user_profile.to_account_info().lamports();
+        }
+        if ctx.accounts.user_market_index.user == Pubkey::default() {
+            created_rent += ctx.accounts.user_market_index.to_account_info().lamports();
+        }
+        if ctx.accounts.user_index_page.user == Pubkey::default() {
+            created_rent += ctx.accounts.user_index_page.to_account_info().lamports();
+        }
+        if ctx.accounts.epoch_stats.user == Pubkey::default() {
+            created_rent += ctx.accounts.epoch_stats.to_account_info().lamports();
+        }
+        if ctx.accounts.creator_stats.creator == Pubkey::default() {
+            created_rent += ctx.accounts.creator_stats.to_account_info().lamports();
+        }
+
+        let grant_info = ctx.accounts.trading_delegate.to_account_info();
+        let escrow = grant_info
+            .lamports()
+            .saturating_sub(Rent::get()?.minimum_balance(grant_info.data_len()));
+        require!(
+            escrow >= (amount - rebate).saturating_add(created_rent),
+            MarketError::DelegateEscrowInsufficient
+        );
+
+        // Counted against the creator until the market resolves and
+        // release_creator_liability runs
+        let stats = &mut ctx.accounts.creator_stats;
+        stats.init_if_new(ctx.accounts.market.liability_creator, ctx.bumps.creator_stats);
+        let open_liability = stats.open_liability.saturating_add(amount);
+        let cap = ctx.accounts.config.max_open_liability;
+        require_bet(
+            cap == 0 || open_liability <= cap,
+            market_id,
+            MarketError::CreatorLiabilityCapReached,
+            cap,
+            open_liability,
+        )?;
+        stats.open_liability = open_liability;
+        let market = &mut ctx.accounts.market;
+        market.creator_liability = market.creator_liability.saturating_add(amount);
+
+        apply_prediction(
+            &mut ctx.accounts.market,
+            prediction_type,
+            amount,
+            net_amount,
+            tokens_out,
+            fee - insurance_fee,
+            rebate,
+            now,
+        )?;
+
+        // The rebate stays in the escrow
+        **grant_info.try_borrow_mut_lamports()? -= amount - rebate + created_rent;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? +=
+            amount - insurance_fee - rebate;
+        **ctx.accounts.insurance_fund.to_account_info().try_borrow_mut_lamports()? +=
+            insurance_fee;
+        **ctx.accounts.delegate.to_account_info().try_borrow_mut_lamports()? += created_rent;
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_deposited = fund.total_deposited.saturating_add(insurance_fee);
+
+        let grant = &mut ctx.accounts.trading_delegate;
+        grant.spent = grant.spent.saturating_add(amount);
+
+        let house = ctx.accounts.market.is_house(&owner);
+        let prediction = &mut ctx.accounts.prediction_account;
+        prediction.version = PREDICTION_VERSION;
+        prediction.market_id = ctx.accounts.market.market_id;
+        prediction.predictor = owner;
+        prediction.prediction_type = prediction_type;
+        prediction.amount_deposited = amount;
+        prediction.tokens_received = tokens_out;
+        prediction.created_at = now;
+        prediction.claimed = false;
+        prediction.referrer = Pubkey::default();
+        prediction.bump = ctx.bumps.prediction_account;
+        prediction.slot = clock.slot;
+        prediction.yes_pool_at_entry = yes_pool_at_entry;
+        prediction.no_pool_at_entry = no_pool_at_entry;
+        prediction.entry_price_bps = effective_price_bps;
+        prediction.entry_probability_bps =
+            side_probability_bps(yes_pool_at_entry, no_pool_at_entry, prediction_type);
+        prediction.trade_sequence = ctx.accounts.market.sequence;
+        prediction.cancel_refund = amount - insurance_fee - rebate;
+        prediction.is_house = ReserveFlag(house);
+        ctx.accounts.market.open_prediction(prediction);
+
+        let full = admit_participant(
+            &mut ctx.accounts.market,
+            &ctx.accounts.user_market_index,
+            false,
+        )?;
+        if let Some(full) = full {
+            emit_cpi!(log_event(full));
+        }
+        index_position(
+            &mut ctx.accounts.user_market_index,
+            ctx.bumps.user_market_index,
+            &mut ctx.accounts.user_index_page,
+            ctx.bumps.user_index_page,
+            &ctx.accounts.user_profile,
+            market_id,
+            owner,
+        );
+
+        let profile = &mut ctx.accounts.user_profile;
+        profile.init_if_new(owner, ctx.bumps.user_profile);
+        profile.bets_placed = profile.bets_placed.saturating_add(1);
+        if !house {
+            profile.total_wagered = profile.total_wagered.saturating_add(amount);
+        }
+        emit_cpi!(log_event(profile.update_event()));
+
+        let epoch_stats = &mut ctx.accounts.epoch_stats;
+        epoch_stats.init_if_new(owner, ctx.accounts.config.current_epoch, ctx.bumps.epoch_stats);
+        if !house {
+            epoch_stats.bets_placed = epoch_stats.bets_placed.saturating_add(1);
+            epoch_stats.total_wagered = epoch_stats.total_wagered.saturating_add(amount);
+        }
+
+        let market = &mut ctx.accounts.market;
+        emit_cpi!(log_event(PredictionPlaced {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            predictor: owner,
+            prediction_type,
+            amount,
+            normalized_amount: normalized_amount(amount, market.collateral_decimals),
+            tokens_received: tokens_out,
+            weight_bps,
+            fee_bps,
+            rebate,
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+            implied_yes_probability_bps: market.implied_yes_probability_bps(),
+            sequence: market.sequence,
+            note: String::new(),
+        }));
+
+        emit_cpi!(log_event(DelegatedPredictionPlaced {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            owner,
+            delegate: ctx.accounts.delegate.key(),
+            amount,
+            spent: ctx.accounts.trading_delegate.spent,
+        }));
+
+        Ok(())
+    }
+
+    /// Owner revokes a trading delegate, taking back the unspent escrow
+    /// along with the account's rent
+    pub fn revoke_trading_delegate(ctx: Context<RevokeTradingDelegate>) -> Result<()> {
+        let grant = &ctx.accounts.trading_delegate;
+        emit_cpi!(log_event(TradingDelegateRevoked {
+            owner: grant.owner,
+            delegate: grant.delegate,
+            spent: grant.spent,
+            refunded: grant.to_account_info().lamports(),
+        }));
+
+        Ok(())
+    }
+
+    /// Exclude the signer from betting until `until`, at least a day and at
+    /// most a year from now. Calling again while excluded can only push
+    /// `until` later. Claims, refunds and exits stay open throughout
+    pub fn self_exclude(ctx: Context<SelfExclude>, until: i64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            until >= now.saturating_add(MIN_SELF_EXCLUSION_SECS)
+                && until <= now.saturating_add(MAX_SELF_EXCLUSION_SECS),
+            MarketError::InvalidExclusionPeriod
+        );
+        let exclusion = &mut ctx.accounts.self_exclusion;
+        let extended = exclusion.active(now);
+        require!(until > exclusion.until, MarketError::SelfExclusionActive);
+        if !extended {
+            exclusion.user = ctx.accounts.user.key();
+            exclusion.created_at = now;
+            exclusion.bump = ctx.bumps.self_exclusion;
+        }
+        exclusion.until = until;
+
+        emit_cpi!(log_event(SelfExclusionSet {
+            user: exclusion.user,
+            until,
+            created_at: exclusion.created_at,
+            extended,
+        }));
+
+        Ok(())
+    }
+
+    /// Close an expired self-exclusion, returning its rent
+    pub fn close_self_exclusion(ctx: Context<CloseSelfExclusion>) -> Result<()> {
+        let exclusion = &ctx.accounts.self_exclusion;
+        require!(
+            !exclusion.active(Clock::get()?.unix_timestamp),
+            MarketError::SelfExclusionActive
+        );
+
+        emit_cpi!(log_event(SelfExclusionClosed {
+            user: exclusion.user,
+            until: exclusion.until,
+        }));
+
+        Ok(())
+    }
+
+    /// Price a prediction without placing it
+    /// Runs the exact pricing path of place_prediction and returns the quote
+    /// as return data, for simulateTransaction or CPI callers
+    pub fn quote_prediction(
+        ctx: Context<QuotePrediction>,
+        _market_id: u64,
+        prediction_type: bool,
+        amount: u64,
+    ) -> Result<PredictionQuote> {
+        let discounted = discount_nft_verified(
+            &ctx.accounts.config,
+            &ctx.accounts.predictor.key(),
+            &ctx.accounts.nft_token_account,
+            &ctx.accounts.nft_metadata,
+        )?;
+        price_prediction(
+            &ctx.accounts.market,
+            &ctx.accounts.config,
+            &ctx.accounts.predictor.key(),
+            ctx.accounts.referrer.as_deref(),
+            discounted,
+            prediction_type,
+            amount,
+            Clock::get()?.unix_timestamp,
+        )
+    }
+
+    /// A listed resolver resolves the market with the outcome
+    /// Can only be called after resolution_time has passed, and by each
+    /// resolver tier only once its delay has run; see set_resolvers
+    /// outcome: true = YES won, false = NO won. A market with no bets on
+    /// one side is voided instead, whatever the outcome; see settle_market
+    /// evidence: why the market resolved this way (a URL or short note);
+    /// required on subjective markets
+    pub fn resolve_market(
+        ctx: Context<ResolveMarket>,
+        market_id: u64,
+        outcome: bool,
+        evidence: String,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let resolver = ctx.accounts.admin.key();
+        let tier = open_resolver_tier(market, &resolver, Clock::get()?.unix_timestamp)?;
+        record_resolution(
+            &mut ctx.accounts.resolution,
+            ctx.bumps.resolution,
+            market,
+            resolver,
+            &evidence,
+        )?;
+        match settle_market(
+            market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            market_id,
+            outcome,
+            evidence,
+        )? {
+            Settlement::Resolved(mut event) => {
+                event.resolver_tier = Some(tier as u8);
+                emit_cpi!(log_event(event));
+            }
+            Settlement::Voided(event) => emit_cpi!(log_event(event)),
+        }
+
+        Ok(())
+    }
+
+    /// A listed resolver reports that the event ended in a push or draw,
+    /// under the same rules as resolve_market. The market's push_policy
+    /// maps it to a YES or NO win, or voids the market so every bet is
+    /// refunded through claim_refund
+    pub fn resolve_push(
+        ctx: Context<ResolveMarket>,
+        market_id: u64,
+        evidence: String,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(
+            market.resolution_program.is_none(),
+            MarketError::ResolutionProgramOnly
+        );
+        let resolver = ctx.accounts.admin.key();
+        let tier = open_resolver_tier(market, &resolver, Clock::get()?.unix_timestamp)?;
+        record_resolution(
+            &mut ctx.accounts.resolution,
+            ctx.bumps.resolution,
+            market,
+            resolver,
+            &evidence,
+        )?;
+        let outcome = market.push_policy.outcome();
+        match settle_outcome(
+            market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            market_id,
+            outcome,
+            evidence,
+        )? {
+            Settlement::Resolved(mut event) => {
+                event.resolver_tier = Some(tier as u8);
+                event.push = true;
+                emit_cpi!(log_event(event));
+            }
+            Settlement::Voided(event) => emit_cpi!(log_event(event)),
+        }
+
+        Ok(())
+    }
+
+    /// Creator corrects the outcome during the market's correction window,
+    /// as long as no prediction has been settled yet. The new evidence
+    /// replaces the recorded one
+    pub fn amend_resolution(
+        ctx: Context<CreatorResolution>,
+        market_id: u64,
+        new_outcome: bool,
+        evidence: String,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(
+            market.resolution_program.is_none(),
+            MarketError::ResolutionProgramOnly
+        );
+        require!(market.resolved, MarketError::MarketNotResolved);
+        require!(!market.disputed, MarketError::DisputeOpen);
+        let previous_outcome = market.outcome;
+        require!(previous_outcome.winner().is_some(), MarketError::InvalidOutcome);
+        require!(
+            Clock::get()?.unix_timestamp < market.claims_open_at(),
+            MarketError::CorrectionWindowClosed
+        );
+        require!(market.claims_count == 0, MarketError::ClaimsAlreadyMade);
+        record_resolution(
+            &mut ctx.accounts.resolution,
+            ctx.bumps.resolution,
+            market,
+            ctx.accounts.admin.key(),
+            &evidence,
+        )?;
+
+        market.outcome = new_outcome.into();
+        market.outstanding_entitlement = market.winner_entitlement(new_outcome);
+        market.reset_eligible_claims();
+        market.payout_params = PayoutParams::default();
+
+        emit_cpi!(log_event(ResolutionAmended {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            previous_outcome,
+            new_outcome: market.outcome,
+            claims_open_at: market.claims_open_at(),
+            evidence,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator commits to keccak(outcome || salt) ahead of resolving
+    /// Hides the outcome from anyone watching for resolve_market until the
+    /// reveal. Committing again before the reveal replaces the commitment
+    pub fn commit_resolution(
+        ctx: Context<CommitResolution>,
+        market_id: u64,
+        commitment: [u8; 32],
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(
+            market.resolution_program.is_none(),
+            MarketError::ResolutionProgramOnly
+        );
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= market.resolution_time, MarketError::MarketNotExpired);
+        require!(now >= market.resolvers_active_at, MarketError::ResolversNotActive);
+
+        market.resolution_commitment = Some(commitment);
+        market.resolution_committed_at = now;
+
+        emit_cpi!(log_event(ResolutionCommitted {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            commitment,
+            committed_at: now,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator reveals a committed outcome, resolving the market
+    /// Must come at least RESOLUTION_REVEAL_DELAY seconds after the commit.
+    /// The evidence is not part of the commitment
+    pub fn reveal_resolution(
+        ctx: Context<CreatorResolution>,
+        market_id: u64,
+        outcome: bool,
+        salt: [u8; 32],
+        evidence: String,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let commitment = market
+            .resolution_commitment
+            .ok_or(MarketError::NoResolutionCommitment)?;
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= market.resolvers_active_at, MarketError::ResolversNotActive);
+        require!(
+            now >= market.resolution_committed_at.saturating_add(RESOLUTION_REVEAL_DELAY),
+            MarketError::RevealTooEarly
+        );
+        require!(
+            keccak::hashv(&[&[outcome as u8], &salt]).to_bytes() == commitment,
+            MarketError::CommitmentMismatch
+        );
+
+        record_resolution(
+            &mut ctx.accounts.resolution,
+            ctx.bumps.resolution,
+            market,
+            ctx.accounts.admin.key(),
+            &evidence,
+        )?;
+        market.resolution_commitment = None;
+        match settle_market(
+            market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            market_id,
+            outcome,
+            evidence,
+        )? {
+            Settlement::Resolved(event) => emit_cpi!(log_event(event)),
+            Settlement::Voided(event) => emit_cpi!(log_event(event)),
+        }
+
+        Ok(())
+    }
+
+    /// The market's resolution_program resolves it with `outcome`, calling
+    /// in by CPI straight from the transaction's top-level instruction. The
+    /// only way such a market resolves. `payer` funds the resolution
+    /// account, which records the program as the resolver
+    pub fn resolve_via_program(
+        ctx: Context<ResolveViaProgram>,
+        market_id: u64,
+        outcome: bool,
+        evidence: String,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let program = market
+            .resolution_program
+            .ok_or(MarketError::NotResolutionProgram)?;
+        require_cpi_from(&ctx.accounts.instructions, &program)?;
+        record_resolution(
+            &mut ctx.accounts.resolution,
+            ctx.bumps.resolution,
+            market,
+            program,
+            &evidence,
+        )?;
+        match settle_outcome(
+            market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            market_id,
+            Some(outcome),
+            evidence,
+        )? {
+            Settlement::Resolved(event) => emit_cpi!(log_event(event)),
+            Settlement::Voided(event) => emit_cpi!(log_event(event)),
+        }
+
+        Ok(())
+    }
+
+    /// Create a price feed that `publisher` pushes prices into
+    pub fn create_price_feed(ctx: Context<CreatePriceFeed>, feed_id: u64) -> Result<()> {
+        let feed = &mut ctx.accounts.price_feed;
+        feed.publisher = ctx.accounts.publisher.key();
+        feed.feed_id = feed_id;
+        feed.price = 0;
+        feed.confidence = 0;
+        feed.publish_time = 0;
+        feed.bump = ctx.bumps.price_feed;
+        Ok(())
+    }
+
+    /// Publisher pushes a price print with its confidence interval
+    pub fn publish_price(
+        ctx: Context<PublishPrice>,
+        _feed_id: u64,
+        price: i64,
+        confidence: u64,
+        publish_time: i64,
+    ) -> Result<()> {
+        require!(
+            publish_time <= Clock::get()?.unix_timestamp,
+            MarketError::InvalidPriceFeed
+        );
+        let feed = &mut ctx.accounts.price_feed;
+        feed.price = price;
+        feed.confidence = confidence;
+        feed.publish_time = publish_time;
+        Ok(())
+    }
+
+    /// Resolve a price-threshold market from its feed: YES when the price
+    /// is at or above the strike. Anyone may call. Fails with StaleOracle
+    /// or LowConfidence when the print is too old or too uncertain
+    pub fn resolve_with_oracle(ctx: Context<ResolveWithOracle>, market_id: u64) -> Result<()> {
+        let oracle = ctx
+            .accounts
+            .market
+            .price_oracle
+            .clone()
+            .ok_or(MarketError::OracleNotConfigured)?;
+        require!(oracle.twap_window_secs == 0, MarketError::TwapResolutionRequired);
+        require_keys_eq!(
+            ctx.accounts.price_feed.key(),
+            oracle.feed,
+            MarketError::InvalidPriceFeed
+        );
+        let price = checked_oracle_price(
+            &ctx.accounts.price_feed,
+            &oracle,
+            ctx.accounts.market.resolution_time,
+            Clock::get()?.unix_timestamp,
+        )?;
+
+        match settle_market(
+            &mut ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            market_id,
+            price >= oracle.strike_price,
+            String::new(),
+        )? {
+            Settlement::Resolved(event) => emit_cpi!(log_event(event)),
+            Settlement::Voided(event) => emit_cpi!(log_event(event)),
+        }
+
+        Ok(())
+    }
+
+    /// Record the feed's current price for a TWAP market
+    /// Permissionless crank, open during the final twap_window_secs before
+    /// resolution time, at most once per slot. Pays the caller
+    /// PRICE_SAMPLE_TIP out of the market's collected fees
+    pub fn record_price_sample(ctx: Context<RecordPriceSample>, market_id: u64) -> Result<()> {
+        let oracle = ctx
+            .accounts
+            .market
+            .price_oracle
+            .clone()
+            .ok_or(MarketError::OracleNotConfigured)?;
+        require!(oracle.twap_window_secs > 0, MarketError::OracleNotConfigured);
+        require_keys_eq!(
+            ctx.accounts.price_feed.key(),
+            oracle.feed,
+            MarketError::InvalidPriceFeed
+        );
+
+        let clock = Clock::get()?;
+        let resolution_time = ctx.accounts.market.resolution_time;
+        let window_start = resolution_time.saturating_sub(oracle.twap_window_secs as i64);
+        require!(
+            clock.unix_timestamp >= window_start && clock.unix_timestamp < resolution_time,
+            MarketError::OutsideSampleWindow
+        );
+        let price = checked_oracle_price(
+            &ctx.accounts.price_feed,
+            &oracle,
+            window_start,
+            clock.unix_timestamp,
+        )?;
+
+        let samples = &mut ctx.accounts.price_samples;
+        if samples.count == 0 {
+            samples.market_id = market_id;
+            samples.bump = ctx.bumps.price_samples;
+        } else {
+            require!(clock.slot > samples.last_slot, MarketError::DuplicateSample);
+        }
+        samples.push(PriceSample {
+            price,
+            timestamp: clock.unix_timestamp,
+        });
+        samples.last_slot = clock.slot;
+
+        let market = &mut ctx.accounts.market;
+        let tip = PRICE_SAMPLE_TIP.min(market.fee_collected);
+        market.fee_collected -= tip;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= tip;
+        **ctx.accounts.cranker.try_borrow_mut_lamports()? += tip;
+
+        emit_cpi!(log_event(PriceSampled {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            price,
+            timestamp: clock.unix_timestamp,
+            tip,
+        }));
+        // One sample per slot, so each paid call recorded a new one
+        if let Some(paid) = pay_keeper_tip(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            KeeperAction::RecordPriceSample,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.cranker.to_account_info(),
+        )? {
+            emit_cpi!(log_event(paid));
+        }
+
+        Ok(())
+    }
+
+    /// Resolve a TWAP market: YES when the time-weighted average of the
+    /// recorded samples is at or above the strike. Anyone may call
+    pub fn resolve_with_twap(ctx: Context<ResolveWithTwap>, market_id: u64) -> Result<()> {
+        let oracle = ctx
+            .accounts
+            .market
+            .price_oracle
+            .clone()
+            .ok_or(MarketError::OracleNotConfigured)?;
+        require!(oracle.twap_window_secs > 0, MarketError::OracleNotConfigured);
+        let samples = &ctx.accounts.price_samples;
+        require!(
+            samples.count >= oracle.min_twap_samples.max(1) as u32,
+            MarketError::InsufficientSamples
+        );
+        let twap = samples.twap(ctx.accounts.market.resolution_time);
+
+        match settle_market(
+            &mut ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            market_id,
+            twap >= oracle.strike_price,
+            String::new(),
+        )? {
+            Settlement::Resolved(event) => emit_cpi!(log_event(event)),
+            Settlement::Voided(event) => emit_cpi!(log_event(event)),
+        }
+
+        Ok(())
+    }
+
+    /// Creator resolves a feed-backed market by hand once the feed has
+    /// failed to produce a usable print for the fallback grace period
+    /// after resolution time, so a dead feed cannot strand the market
+    pub fn force_manual_fallback(
+        ctx: Context<CreatorResolution>,
+        market_id: u64,
+        outcome: bool,
+        evidence: String,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let oracle = market
+            .price_oracle
+            .as_ref()
+            .ok_or(MarketError::OracleNotConfigured)?;
+        require!(
+            Clock::get()?.unix_timestamp
+                >= market
+                    .resolution_time
+                    .saturating_add(oracle.fallback_grace_secs as i64),
+            MarketError::FallbackNotAvailable
+        );
+        record_resolution(
+            &mut ctx.accounts.resolution,
+            ctx.bumps.resolution,
+            market,
+            ctx.accounts.admin.key(),
+            &evidence,
+        )?;
+
+        match settle_market(
+
+            market,
+
+            &ctx.accounts.market_vault,
+
+            &ctx.accounts.wsol_vault,
+
+            market_id,
+
+            outcome,
+
+            evidence,
+
+        )? {
+            Settlement::Resolved(event) => emit_cpi!(log_event(event)),
+            Settlement::Voided(event) => emit_cpi!(log_event(event)),
+        }
+
+        Ok(())
+    }
+
+    /// Resolve from an outcome signed off-chain by the market's oracle
+    /// The transaction must carry an ed25519 program instruction, right
+    /// before this one, verifying the oracle's signature over
+    /// market_id (LE) || outcome (u8) || timestamp (LE) || expires_at (LE).
+    /// Anyone may submit, until `expires_at`. Each attestation is recorded
+    /// under its hash, so it resolves at most once
+    pub fn resolve_with_attestation(
+        ctx: Context<ResolveWithAttestation>,
+        market_id: u64,
+        outcome: bool,
+        timestamp: i64,
+        expires_at: i64,
+    ) -> Result<()> {
+        let oracle = ctx.accounts.market.oracle_key.ok_or(MarketError::OracleNotConfigured)?;
+        let now = Clock::get()?.unix_timestamp;
+        require!(timestamp <= now, MarketError::InvalidAttestation);
+        require!(now <= expires_at, MarketError::AttestationExpired);
+
+        let message = attestation_message(market_id, outcome, timestamp, expires_at);
+        verify_ed25519_attestation(&ctx.accounts.instructions, &oracle, &message)?;
+
+        let record = &mut ctx.accounts.attestation_record;
+        record.market = ctx.accounts.market.key();
+        record.market_id = market_id;
+        record.oracle = oracle;
+        record.outcome = outcome;
+        record.expires_at = expires_at;
+        record.recorded_at = now;
+        record.payer = ctx.accounts.payer.key();
+        record.bump = ctx.bumps.attestation_record;
+
+        match settle_market(
+            &mut ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            market_id,
+            outcome,
+            String::new(),
+        )? {
+            Settlement::Resolved(event) => emit_cpi!(log_event(event)),
+            Settlement::Voided(event) => emit_cpi!(log_event(event)),
+        }
+
+        Ok(())
+    }
+
+    /// Resolve from an outcome signed by an Ethereum-style oracle key
+    /// The transaction must carry a secp256k1 program instruction, right
+    /// before this one, recovering the market's oracle address from a
+    /// signature over program_id || market_id (LE) || outcome (u8) ||
+    /// deadline (LE). The attestation is void after `deadline`
+    pub fn resolve_with_eth_attestation(
+        ctx: Context<ResolveWithEthAttestation>,
+        market_id: u64,
+        outcome: bool,
+        deadline: i64,
+    ) -> Result<()> {
+        let oracle = ctx
+            .accounts
+            .market
+            .oracle_eth_address
+            .ok_or(MarketError::OracleNotConfigured)?;
+        require!(
+            Clock::get()?.unix_timestamp <= deadline,
+            MarketError::AttestationExpired
+        );
+
+        let mut message = [0u8; ETH_ATTESTATION_MESSAGE_LEN];
+        message[..32].copy_from_slice(crate::ID.as_ref());
+        message[32..40].copy_from_slice(&market_id.to_le_bytes());
+        message[40] = outcome as u8;
+        message[41..].copy_from_slice(&deadline.to_le_bytes());
+        verify_secp256k1_attestation(&ctx.accounts.instructions, &oracle, &message)?;
+
+        match settle_market(
+            &mut ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            market_id,
+            outcome,
+            String::new(),
+        )? {
+            Settlement::Resolved(event) => emit_cpi!(log_event(event)),
+            Settlement::Voided(event) => emit_cpi!(log_event(event)),
+        }
+
+        Ok(())
+    }
+
+    /// Config admin closes attestation records, refunding their rent to
+    /// whoever relayed the attestation. remaining_accounts holds a
+    /// writable (record, payer) pair per record; each must have expired and
+    /// be at least `max_age_days` old, so it can no longer be replayed
+    pub fn purge_attestation_records<'info>(
+        ctx: Context<'_, '_, 'info, 'info, PurgeAttestationRecords<'info>>,
+        max_age_days: u16,
+    ) -> Result<()> {
+        require!(
+            !ctx.remaining_accounts.is_empty() && ctx.remaining_accounts.len().is_multiple_of(2),
+            MarketError::InvalidAttestationRecords
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        let max_age = max_age_days as i64 * 24 * 60 * 60;
+        let mut lamports = 0u64;
+        for pair in ctx.remaining_accounts.chunks(2) {
+            let record = Account::<AttestationRecord>::try_from(&pair[0])?;
+            require_keys_eq!(pair[1].key(), record.payer, MarketError::InvalidAttestationRecords);
+            require!(
+                now > record.expires_at && now.saturating_sub(record.recorded_at) >= max_age,
+                MarketError::AttestationRecordActive
+            );
+            lamports = lamports.saturating_add(pair[0].lamports());
+            record.close(pair[1].clone())?;
+        }
+
+        emit_cpi!(log_event(AttestationRecordsPurged {
+            admin: ctx.accounts.admin.key(),
+            records: (ctx.remaining_accounts.len() / 2) as u32,
+            max_age_days,
+            lamports,
+        }));
+
+        Ok(())
+    }
+
+    /// Fix a resolved market's payout params ahead of the first claim, which
+    /// would otherwise fix them, and emit them so anyone can check every
+    /// claim with compute_payout. Permissionless; once per outcome
+    pub fn publish_payout_params(ctx: Context<PublishPayoutParams>, _market_id: u64) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(market.resolved, MarketError::MarketNotResolved);
+        require!(!market.disputed, MarketError::DisputeOpen);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= market.claims_open_at(), MarketError::ClaimWindowNotOpen);
+        let outcome = market.outcome.winner().ok_or(MarketError::InvalidOutcome)?;
+        require!(
+            market.payout_params.published_at == 0,
+            MarketError::PayoutParamsAlreadyPublished
+        );
+
+        let available = winner_funds(
+            market,
+            collateral_balance(market, &ctx.accounts.market_vault, &ctx.accounts.wsol_vault)?,
+        );
+        let market = &mut ctx.accounts.market;
+        market.payout_params = PayoutParams::capture(market, outcome, available, now);
+        emit_cpi!(log_event(payout_params_published(market)));
+
+        Ok(())
+    }
+
+    /// Winners claim their rewards
+    /// Formula: (user_tokens / winning_pool_total) * total_winnings
+    /// `tokens_to_claim` redeems part of the position (None redeems the
+    /// rest); the prediction counts as claimed once every token is redeemed.
+    /// `with_receipt` records the claim in a ClaimReceipt the claimer pays
+    /// for, passed as `claim_receipt`; a prediction gets at most one.
+    /// While a dispute is open on an `escrow_disputed_claims` market the
+    /// reward is paid into the `escrowed_claim` account instead, for
+    /// release_escrowed_claim to settle once the dispute does.
+    /// `dry_run` works as in place_prediction
+    pub fn claim_reward(
+        ctx: Context<ClaimReward>,
+        market_id: u64,
+        tokens_to_claim: Option<u64>,
+        with_receipt: bool,
+        dry_run: bool,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.prediction_account.predictor == ctx.accounts.claimer.key(),
+            MarketError::Unauthorized
+        );
+        require!(
+            with_receipt == ctx.accounts.claim_receipt.is_some(),
+            MarketError::ClaimReceiptMismatch
+        );
+        let market = &ctx.accounts.market;
+        let escrowing = market.disputed && market.escrow_disputed_claims;
+        require!(
+            escrowing == ctx.accounts.escrowed_claim.is_some(),
+            MarketError::EscrowedClaimMismatch
+        );
+        // A receipt would outlive a claim the dispute may reverse
+        require!(!(escrowing && with_receipt), MarketError::ClaimReceiptMismatch);
+        let tokens_before = ctx.accounts.prediction_account.tokens_claimed;
+        // A vault short of what winners are owed pays every claim the
+        // same fraction, rather than paying early claimers in full
+        let available = winner_funds(
+            &ctx.accounts.market,
+            collateral_balance(
+                &ctx.accounts.market,
+                &ctx.accounts.market_vault,
+                &ctx.accounts.wsol_vault,
+            )?,
+        );
+        let Redemption {
+            reward,
+            gross_reward,
+            winnings_fee,
+            entitled,
+            payout_bps,
+            pnl_lamports,
+            return_bps,
+            first_claim,
+            published,
+        } = redeem_winnings(
+            &mut ctx.accounts.market,
+            &mut ctx.accounts.prediction_account,
+            tokens_to_claim,
+            available,
+            Clock::get()?.unix_timestamp,
+            escrowing,
+        )?;
+        if published {
+            emit_cpi!(log_event(payout_params_published(&mut ctx.accounts.market)));
+        }
+
+        // House winnings stay out of the profile and the leaderboard
+        let house = ctx.accounts.prediction_account.is_house.0;
+        let profile = &mut ctx.accounts.user_profile;
+        profile.init_if_new(ctx.accounts.claimer.key(), ctx.bumps.user_profile);
+        if !house {
+            if first_claim {
+                profile.markets_won = profile.markets_won.saturating_add(1);
+            }
+            profile.total_won = profile.total_won.saturating_add(reward);
+        }
+        emit_cpi!(log_event(profile.update_event()));
+
+        let epoch_stats = &mut ctx.accounts.epoch_stats;
+        epoch_stats.init_if_new(
+            ctx.accounts.claimer.key(),
+            ctx.accounts.config.current_epoch,
+            ctx.bumps.epoch_stats,
+        );
+        if !house {
+            if first_claim {
+                epoch_stats.markets_won = epoch_stats.markets_won.saturating_add(1);
+            }
+            epoch_stats.total_won = epoch_stats.total_won.saturating_add(reward);
+        }
+
+        // Transfer reward from vault to the recipient, the claimer by default
+        let recipient = match &ctx.accounts.recipient {
+            Some(recipient) => recipient.to_account_info(),
+            None => ctx.accounts.claimer.to_account_info(),
+        };
+        let recipient = if let Some(escrow) = &mut ctx.accounts.escrowed_claim {
+            let prediction = &ctx.accounts.prediction_account;
+            escrow.prediction = prediction.key();
+            escrow.claimer = ctx.accounts.claimer.key();
+            escrow.recipient = recipient.key();
+            escrow.market_id = market_id;
+            escrow.outcome = prediction.prediction_type;
+            escrow.tokens = prediction.tokens_claimed - tokens_before;
+            escrow.amount = reward;
+            escrow.gross_reward = gross_reward;
+            escrow.winnings_fee = winnings_fee;
+            escrow.first_claim = first_claim;
+            escrow.epoch = ctx.accounts.config.current_epoch;
+            escrow.bump = ctx.bumps.escrowed_claim.unwrap_or_default();
+
+            let market = &mut ctx.accounts.market;
+            market.escrowed_claims = market.escrowed_claims.saturating_add(reward);
+            pay_out_collateral(
+                market,
+                &ctx.accounts.market_vault,
+                &ctx.accounts.wsol_vault,
+                &ctx.accounts.payout_token_account,
+                &ctx.accounts.collateral_mint,
+                &ctx.accounts.token_program,
+                &escrow.to_account_info(),
+                reward,
+            )?
+        } else {
+            pay_out_collateral(
+                &ctx.accounts.market,
+                &ctx.accounts.market_vault,
+                &ctx.accounts.wsol_vault,
+                &ctx.accounts.payout_token_account,
+                &ctx.accounts.collateral_mint,
+                &ctx.accounts.token_program,
+                &recipient,
+                reward,
+            )?
+        };
+
+        emit_cpi!(log_event(RewardClaimed {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            claimer: ctx.accounts.claimer.key(),
+            recipient,
+            reward,
+            normalized_amount: normalized_amount(reward, ctx.accounts.market.collateral_decimals),
+            gross_reward,
+            winnings_fee,
+            entitled,
+            payout_bps,
+            pnl_lamports,
+            return_bps,
+        }));
+        if let Some(settled) = mark_fully_settled(&mut ctx.accounts.market, false) {
+            emit_cpi!(log_event(settled));
+        }
+
+        if let Some(receipt) = &mut ctx.accounts.claim_receipt {
+            receipt.prediction = ctx.accounts.prediction_account.key();
+            receipt.claimer = ctx.accounts.claimer.key();
+            receipt.market_id = market_id;
+            receipt.amount = reward;
+            receipt.fee = winnings_fee;
+            receipt.claimed_at = Clock::get()?.unix_timestamp;
+            receipt.snapshot_hash = ctx.accounts.market.resolution_snapshot.hash();
+            receipt.bump = ctx.bumps.claim_receipt.unwrap_or_default();
+        }
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) = solvency_violation(
+            &ctx.accounts.market,
+            collateral_balance(
+                &ctx.accounts.market,
+                &ctx.accounts.market_vault,
+                &ctx.accounts.wsol_vault,
+            )?,
+        ) {
+            emit_cpi!(log_event(violation));
+        }
+
+        if dry_run {
+            let market = &ctx.accounts.market;
+            let prediction = &ctx.accounts.prediction_account;
+            return dry_run_complete(DryRunResult {
+                market_id,
+                user: ctx.accounts.claimer.key(),
+                side: prediction.prediction_type,
+                paid: 0,
+                received: reward,
+                tokens: prediction.tokens_claimed - tokens_before,
+                fee: winnings_fee,
+                yes_pool: market.yes_pool,
+                no_pool: market.no_pool,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Claimer closes its ClaimReceipt once CLAIM_RECEIPT_LOCK_SECS have
+    /// passed since the claim, recovering the rent
+    pub fn close_claim_receipt(ctx: Context<CloseClaimReceipt>) -> Result<()> {
+        let unlocks_at = ctx
+            .accounts
+            .claim_receipt
+            .claimed_at
+            .saturating_add(CLAIM_RECEIPT_LOCK_SECS);
+        require!(
+            Clock::get()?.unix_timestamp >= unlocks_at,
+            MarketError::ClaimReceiptLocked
+        );
+        Ok(())
+    }
+
+    /// Settle a claim escrowed during a dispute, once the dispute has been
+    /// settled. If the outcome stood the reward goes to the recipient the
+    /// claim named; if it was overturned the reward goes back to the vault
+    /// and the claim is undone, reopening the prediction. Either way the
+    /// escrow's rent goes back to the claimer. Permissionless
+    pub fn release_escrowed_claim(
+        ctx: Context<ReleaseEscrowedClaim>,
+        market_id: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.market.disputed, MarketError::DisputeOpen);
+        let escrow = &ctx.accounts.escrowed_claim;
+        let stood = ctx.accounts.market.outcome.winner() == Some(escrow.outcome);
+        let amount = escrow.amount;
+
+        let escrow_info = escrow.to_account_info();
+        **escrow_info.try_borrow_mut_lamports()? -= amount;
+        if stood {
+            **ctx.accounts.recipient.try_borrow_mut_lamports()? += amount;
+        } else {
+            **ctx.accounts.market_vault.try_borrow_mut_lamports()? += amount;
+
+            let prediction = &mut ctx.accounts.prediction_account;
+            prediction.tokens_claimed = prediction.tokens_claimed.saturating_sub(escrow.tokens);
+            prediction.claimed = false;
+
+            let market = &mut ctx.accounts.market;
+            market.escrowed_claims = market.escrowed_claims.saturating_sub(amount);
+            market.total_claimed = market.total_claimed.saturating_sub(escrow.gross_reward);
+            market.fee_collected = market.fee_collected.saturating_sub(escrow.winnings_fee);
+            if escrow.outcome {
+                market.yes_tokens = market.yes_tokens.saturating_add(escrow.tokens);
+            } else {
+                market.no_tokens = market.no_tokens.saturating_add(escrow.tokens);
+            }
+            if escrow.first_claim {
+                market.claims_count = market.claims_count.saturating_sub(1);
+            }
+
+            // A house claim was never counted, so there is nothing to undo
+            let house = ctx.accounts.prediction_account.is_house.0;
+            let profile = &mut ctx.accounts.user_profile;
+            if !house {
+                if escrow.first_claim {
+                    profile.markets_won = profile.markets_won.saturating_sub(1);
+                }
+                profile.total_won = profile.total_won.saturating_sub(amount);
+            }
+            emit_cpi!(log_event(profile.update_event()));
+
+            let epoch_stats = &mut ctx.accounts.epoch_stats;
+            if !house {
+                if escrow.first_claim {
+                    epoch_stats.markets_won = epoch_stats.markets_won.saturating_sub(1);
+                }
+                epoch_stats.total_won = epoch_stats.total_won.saturating_sub(amount);
+            }
+        }
+
+        emit_cpi!(log_event(EscrowedClaimReleased {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            prediction: escrow.prediction,
+            claimer: escrow.claimer,
+            recipient: escrow.recipient,
+            amount,
+            clawed_back: !stood,
+        }));
+        if stood {
+            if let Some(settled) = mark_fully_settled(&mut ctx.accounts.market, false) {
+                emit_cpi!(log_event(settled));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Claim winnings and bet them on the next market of the same series
+    /// in one transaction. `keep_back` of the reward goes to the wallet and
+    /// the rest is placed on `side` of the target market, priced as
+    /// place_prediction would; the whole instruction fails if the target
+    /// isn't taking bets or would pay fewer than `min_tokens_out`
+    #[allow(clippy::too_many_arguments)]
+    pub fn claim_and_roll(
+        ctx: Context<ClaimAndRoll>,
+        source_market_id: u64,
+        target_market_id: u64,
+        side: bool, // true = YES, false = NO
+        min_tokens_out: u64,
+        keep_back: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, MarketError::ProtocolPaused);
+        require!(
+            !ctx.accounts.source_market.wsol_vault && !ctx.accounts.target_market.wsol_vault,
+            MarketError::LamportVaultRequired
+        );
+        require!(
+            ctx.accounts.source_prediction.predictor == ctx.accounts.claimer.key(),
+            MarketError::Unauthorized
+        );
+        let series = ctx.accounts.series.key();
+        require!(
+            ctx.accounts.source_market.series == Some(series)
+                && ctx.accounts.target_market.series == Some(series),
+            MarketError::SeriesMismatch
+        );
+        let target = &ctx.accounts.target_market;
+        require!(!target.needs_attention, MarketError::NeedsAttention);
+        require!(
+            target.bet_reveal_window == 0,
+            MarketError::CommitRevealRequired
+        );
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        require!(now < target.betting_close_time(), MarketError::MarketExpired);
+        require!(!target.reduce_only(now), MarketError::ReduceOnlyWindow);
+        require_not_self_excluded(&ctx.accounts.self_exclusion, now)?;
+
+        let available = winner_funds(
+            &ctx.accounts.source_market,
+            vault_lamports(&ctx.accounts.source_vault)?
+                .saturating_add(ctx.accounts.source_market.idle_deployed),
+        );
+        let Redemption {
+            reward,
+            gross_reward,
+            winnings_fee,
+            entitled,
+            payout_bps,
+            pnl_lamports,
+            return_bps,
+            first_claim,
+            published,
+        } = redeem_winnings(
+            &mut ctx.accounts.source_market,
+            &mut ctx.accounts.source_prediction,
+            None,
+            available,
+            now,
+            false,
+        )?;
+        if published {
+            emit_cpi!(log_event(payout_params_published(&mut ctx.accounts.source_market)));
+        }
+        require!(keep_back < reward, MarketError::InvalidAmount);
+        let amount = reward - keep_back;
+
+        let claimer = ctx.accounts.claimer.key();
+        let target = &ctx.accounts.target_market;
+        let (yes_pool_at_entry, no_pool_at_entry) = (target.yes_pool, target.no_pool);
+        let PredictionQuote {
+            tokens_out,
+            effective_price_bps,
+            fee,
+            insurance_fee,
+            net_amount,
+            weight_bps,
+            fee_bps,
+            rebate,
+            ..
+        } = price_prediction(
+            target,
+            &ctx.accounts.config,
+            &claimer,
+            None,
+            false,
+            side,
+            amount,
+            now,
+        )?;
+        require!(tokens_out >= min_tokens_out, MarketError::SlippageExceeded);
+        let probability_bps = side_probability_bps(target.yes_pool, target.no_pool, side);
+
+        let series = &mut ctx.accounts.series;
+        series.total_volume = series.total_volume.saturating_add(amount);
+
+        apply_prediction(
+            &mut ctx.accounts.target_market,
+            side,
+            amount,
+            net_amount,
+            tokens_out,
+            fee - insurance_fee,
+            rebate,
+            now,
+        )?;
+
+        // The reward moves vault to vault; only the keep-back and any
+        // rebate reach the wallet
+        require_liquid(&ctx.accounts.source_market, &ctx.accounts.source_vault, reward)?;
+        **ctx.accounts.source_vault.try_borrow_mut_lamports()? -= reward;
+        **ctx.accounts.target_vault.try_borrow_mut_lamports()? +=
+            amount - insurance_fee - rebate;
+        **ctx.accounts.insurance_fund.to_account_info().try_borrow_mut_lamports()? +=
+            insurance_fee;
+        **ctx.accounts.claimer.to_account_info().try_borrow_mut_lamports()? += keep_back + rebate;
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_deposited = fund.total_deposited.saturating_add(insurance_fee);
+
+        let house = ctx.accounts.target_market.is_house(&claimer);
+        let prediction = &mut ctx.accounts.target_prediction;
+        prediction.version = PREDICTION_VERSION;
+        prediction.market_id = ctx.accounts.target_market.market_id;
+        prediction.predictor = claimer;
+        prediction.prediction_type = side;
+        prediction.amount_deposited = amount;
+        prediction.tokens_received = tokens_out;
+        prediction.created_at = now;
+        prediction.claimed = false;
+        prediction.referrer = Pubkey::default();
+        prediction.bump = ctx.bumps.target_prediction;
+        prediction.slot = clock.slot;
+        prediction.yes_pool_at_entry = yes_pool_at_entry;
+        prediction.no_pool_at_entry = no_pool_at_entry;
+        prediction.entry_price_bps = effective_price_bps;
+        prediction.entry_probability_bps = probability_bps;
+        prediction.trade_sequence = ctx.accounts.target_market.sequence;
+        prediction.cancel_refund = 0;
+        prediction.is_house = ReserveFlag(house);
+        ctx.accounts.target_market.open_prediction(prediction);
+
+        let full = admit_participant(
+            &mut ctx.accounts.target_market,
+            &ctx.accounts.user_market_index,
+            false,
+        )?;
+        if let Some(full) = full {
+            emit_cpi!(log_event(full));
+        }
+        index_position(
+            &mut ctx.accounts.user_market_index,
+            ctx.bumps.user_market_index,
+            &mut ctx.accounts.user_index_page,
+            ctx.bumps.user_index_page,
+            &ctx.accounts.user_profile,
+            target_market_id,
+            claimer,
+        );
+
+        let source_house = ctx.accounts.source_prediction.is_house.0;
+        let profile = &mut ctx.accounts.user_profile;
+        profile.init_if_new(claimer, ctx.bumps.user_profile);
+        if !source_house {
+            if first_claim {
+                profile.markets_won = profile.markets_won.saturating_add(1);
+            }
+            profile.total_won = profile.total_won.saturating_add(reward);
+        }
+        profile.bets_placed = profile.bets_placed.saturating_add(1);
+        if !house {
+            profile.total_wagered = profile.total_wagered.saturating_add(amount);
+        }
+        emit_cpi!(log_event(profile.update_event()));
+
+        let epoch_stats = &mut ctx.accounts.epoch_stats;
+        epoch_stats.init_if_new(claimer, ctx.accounts.config.current_epoch, ctx.bumps.epoch_stats);
+        if !source_house {
+            if first_claim {
+                epoch_stats.markets_won = epoch_stats.markets_won.saturating_add(1);
+            }
+            epoch_stats.total_won = epoch_stats.total_won.saturating_add(reward);
+        }
+        if !house {
+            epoch_stats.bets_placed = epoch_stats.bets_placed.saturating_add(1);
+            epoch_stats.total_wagered = epoch_stats.total_wagered.saturating_add(amount);
+        }
+
+        emit_cpi!(log_event(RewardClaimed {
+            market_id: source_market_id,
+            market: ctx.accounts.source_market.key(),
+            tenant: ctx.accounts.source_market.tenant,
+            event_seq: ctx.accounts.source_market.next_event_seq(),
+            claimer,
+            recipient: ctx.accounts.target_vault.key(),
+            reward,
+            normalized_amount: normalized_amount(
+                reward,
+                ctx.accounts.source_market.collateral_decimals,
+            ),
+            gross_reward,
+            winnings_fee,
+            entitled,
+            payout_bps,
+            pnl_lamports,
+            return_bps,
+        }));
+
+        let target = &mut ctx.accounts.target_market;
+        emit_cpi!(log_event(PredictionPlaced {
+            market_id: target_market_id,
+            market: target.key(),
+            tenant: target.tenant,
+            event_seq: target.next_event_seq(),
+            predictor: claimer,
+            prediction_type: side,
+            amount,
+            normalized_amount: normalized_amount(amount, target.collateral_decimals),
+            tokens_received: tokens_out,
+            weight_bps,
+            fee_bps,
+            rebate,
+            yes_pool: target.yes_pool,
+            no_pool: target.no_pool,
+            implied_yes_probability_bps: target.implied_yes_probability_bps(),
+            sequence: target.sequence,
+            note: String::new(),
+        }));
+
+        emit_cpi!(log_event(WinningsRolled {
+            source_market_id,
+            target_market_id,
+            market: ctx.accounts.target_market.key(),
+            tenant: ctx.accounts.target_market.tenant,
+            event_seq: ctx.accounts.target_market.next_event_seq(),
+            owner: claimer,
+            reward,
+            kept_back: keep_back,
+            amount,
+            tokens_received: tokens_out,
+        }));
+        if let Some(settled) = mark_fully_settled(&mut ctx.accounts.source_market, false) {
+            emit_cpi!(log_event(settled));
+        }
+
+        #[cfg(feature = "solvency-checks")]
+        for (market, vault) in [
+            (&ctx.accounts.source_market, &ctx.accounts.source_vault),
+            (&ctx.accounts.target_market, &ctx.accounts.target_vault),
+        ] {
+            if let Some(violation) = solvency_violation(market, vault_lamports(vault)?) {
+                emit_cpi!(log_event(violation));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Report what a prediction is owed without claiming it
+    /// Uses the same reward calculation as claim_reward, net of the
+    /// winnings fee; the result is returned as return data
+    pub fn get_claimable(ctx: Context<GetClaimable>, _market_id: u64) -> Result<Claimable> {
+        let market = &ctx.accounts.market;
+        let prediction = &ctx.accounts.prediction_account;
+
+        let status = match market.outcome {
+            _ if prediction.claimed => ClaimStatus::Claimed,
+            _ if market.disputed => ClaimStatus::Unresolved,
+            MarketOutcome::Unresolved => ClaimStatus::Unresolved,
+            MarketOutcome::Void => ClaimStatus::Refundable,
+            outcome if outcome.winner() == Some(prediction.prediction_type) => ClaimStatus::Won,
+            _ => ClaimStatus::Lost,
+        };
+        let amount = match (status, market.outcome.winner()) {
+            (ClaimStatus::Won, Some(outcome)) => {
+                // Until the first claim fixes them, as if the vault covers everyone
+                let params = if market.payout_params.published_at > 0 {
+                    market.payout_params
+                } else {
+                    PayoutParams::capture(market, outcome, u64::MAX, 0)
+                };
+                let rounding = market.rounding_policy;
+                let full = params.entitlement(prediction.tokens_received, rounding);
+                let unclaimed = |total: u64| {
+                    total
+                        - partial_reward(
+                            total,
+                            prediction.tokens_received,
+                            0,
+                            prediction.tokens_claimed,
+                        )
+                };
+                let reward = params.haircut(unclaimed(full)).0;
+                let profit = reward.saturating_sub(unclaimed(prediction.amount_deposited));
+                reward - rounding.charge_bps(profit, params.winnings_fee_bps)
+            }
+            (ClaimStatus::Refundable, _) => prediction.amount_deposited,
+            _ => 0,
+        };
+
+        Ok(Claimable {
+            status,
+            amount,
+            deadline: None,
+        })
+    }
+
+    /// Report a market's vault against what it owes, for solvency
+    /// monitoring. Liabilities come from Market::liabilities and its parts,
+    /// the same figures the solvency checks use; the result is returned as
+    /// return data
+    pub fn market_health(ctx: Context<GetMarketHealth>, _market_id: u64) -> Result<MarketHealth> {
+        let market = &ctx.accounts.market;
+        let vault = &ctx.accounts.market_vault;
+        let collateral = collateral_balance(market, vault, &ctx.accounts.wsol_vault)?;
+        let liabilities = market.liabilities();
+        let health_bps = if liabilities == 0 {
+            u64::MAX
+        } else {
+            ((collateral as u128) * (BPS_DENOMINATOR as u128) / (liabilities as u128))
+                .min(u64::MAX as u128) as u64
+        };
+
+        Ok(MarketHealth {
+            vault_lamports: vault.lamports(),
+            rent_floor: Rent::get()?.minimum_balance(vault.data_len()),
+            collateral,
+            outcome_liabilities: market.outcome_liabilities(),
+            fee_collected: market.fee_collected,
+            rebate_outstanding: market.rebate_outstanding(),
+            keeper_budget: market.keeper_budget,
+            auction_escrow: market.auction_escrow(),
+            creator_tips: market.creator_tips,
+            liabilities,
+            health_bps,
+        })
+    }
+
+    /// Sum a wallet's exposure across markets. remaining_accounts holds a
+    /// (prediction, market) pair per position; pairs that aren't the
+    /// wallet's position on that market are flagged and left out, as are
+    /// positions on resolved markets. Open positions are marked at the
+    /// live pools with Market::mark_value; realized PnL comes from the
+    /// wallet's profile. The result is returned as return data
+    pub fn compute_exposure<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ComputeExposure<'info>>,
+    ) -> Result<Exposure> {
+        require!(
+            ctx.remaining_accounts.len().is_multiple_of(2),
+            MarketError::InvalidPositionAccounts
+        );
+        let wallet = ctx.accounts.wallet.key();
+        let mut exposure = Exposure {
+            wallet,
+            ..Exposure::default()
+        };
+        for (index, pair) in ctx.remaining_accounts.chunks(2).enumerate() {
+            let Some((prediction, market)) = exposure_position(&wallet, &pair[0], &pair[1]) else {
+                exposure.flagged.push(index as u16);
+                continue;
+            };
+            if market.resolved {
+                exposure.skipped = exposure.skipped.saturating_add(1);
+                continue;
+            }
+            let tokens = prediction.tokens_received.saturating_sub(prediction.tokens_claimed);
+            exposure.open_positions = exposure.open_positions.saturating_add(1);
+            exposure.total_deposited = exposure
+                .total_deposited
+                .saturating_add(prediction.amount_deposited);
+            exposure.mark_value = exposure
+                .mark_value
+                .saturating_add(market.mark_value(prediction.prediction_type, tokens));
+        }
+        exposure.unrealized_pnl = realized_pnl(exposure.mark_value, exposure.total_deposited).0;
+        // Every wager outside the open positions passed counts as settled
+        let profile = &ctx.accounts.user_profile;
+        let settled = profile.total_wagered.saturating_sub(exposure.total_deposited);
+        exposure.realized_pnl = realized_pnl(profile.total_won, settled).0;
+
+        Ok(exposure)
+    }
+
+    /// Re-emit a market's current state as one MarketStateSnapshot, for an
+    /// indexer rebuilding history it can no longer fetch: pools, counters
+    /// and the pool checkpoints of trades from sequence `from_seq` on, as
+    /// far back as the market's ring buffer reaches. Permissionless
+    pub fn replay_market_events(
+        ctx: Context<ReplayMarketEvents>,
+        market_id: u64,
+        from_seq: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let checkpoints: Vec<PoolCheckpoint> = market
+            .pool_checkpoints()
+            .filter(|checkpoint| checkpoint.sequence >= from_seq)
+            .copied()
+            .collect();
+        // Complete when no trade since from_seq fell out of the buffer
+        let history_complete = from_seq > market.sequence
+            || checkpoints.first().is_some_and(|oldest| oldest.sequence <= from_seq);
+
+        emit_cpi!(log_event(MarketStateSnapshot {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            from_seq,
+            sequence: market.sequence,
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+            total_liquidity: market.total_liquidity,
+            yes_tokens: market.yes_tokens,
+            no_tokens: market.no_tokens,
+            total_volume: market.total_volume,
+            fee_collected: market.fee_collected,
+            total_claimed: market.total_claimed,
+            trade_count: market.trade_count,
+            unique_bettors: market.unique_bettors,
+            claims_count: market.claims_count,
+            resolved: market.resolved,
+            outcome: market.outcome,
+            disputed: market.disputed,
+            history_complete,
+            checkpoints,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin voids a market that cannot or should not resolve,
+    /// whatever its timing. Betting stops at once and every deposit,
+    /// including the creator's seed liquidity, becomes refundable in full.
+    /// Collected fees are given up to fund the refunds
+    pub fn emergency_cancel_market(
+        ctx: Context<EmergencyCancelMarket>,
+        _market_id: u64,
+        reason: String,
+    ) -> Result<()> {
+        let admin = ctx.accounts.admin.key();
+        let event = cancel_market(&mut ctx.accounts.market, admin, reason)?;
+        emit_cpi!(log_event(event));
+
+        Ok(())
+    }
+
+    /// Bettor takes back the full amount deposited into a voided market
+    pub fn claim_refund(ctx: Context<ClaimRefund>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let prediction = &mut ctx.accounts.prediction_account;
+        require!(
+            market.outcome == MarketOutcome::Void,
+            MarketError::MarketNotVoided
+        );
+        require!(!prediction.claimed, MarketError::AlreadyClaimed);
+
+        let amount = prediction.amount_deposited;
+        prediction.claimed = true;
+        market.total_claimed = market.total_claimed.saturating_add(amount);
+        market.claims_count = market.claims_count.saturating_add(1);
+        market.eligible_claimed = market.eligible_claimed.saturating_add(1);
+
+        let recipient = pay_out_collateral(
+            &ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            &ctx.accounts.payout_token_account,
+            &ctx.accounts.collateral_mint,
+            &ctx.accounts.token_program,
+            &ctx.accounts.claimer.to_account_info(),
+            amount,
+        )?;
+
+        emit_cpi!(log_event(RefundClaimed {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            recipient,
+            amount,
+        }));
+        if let Some(settled) = mark_fully_settled(&mut ctx.accounts.market, false) {
+            emit_cpi!(log_event(settled));
+        }
+
+        Ok(())
+    }
+
+    /// Creator takes back the seed liquidity of a voided market
+    pub fn refund_liquidity(ctx: Context<RefundLiquidity>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(
+            market.outcome == MarketOutcome::Void,
+            MarketError::MarketNotVoided
+        );
+        let amount = market.total_liquidity;
+        require!(amount > 0, MarketError::NoReward);
+        market.total_liquidity = 0;
+
+        let recipient = pay_out_collateral(
+            &ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            &ctx.accounts.payout_token_account,
+            &ctx.accounts.collateral_mint,
+            &ctx.accounts.token_program,
+            &ctx.accounts.creator.to_account_info(),
+            amount,
+        )?;
+
+        emit_cpi!(log_event(RefundClaimed {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            recipient,
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator of a resolved bookmaker market takes back what is left of
+    /// the bankroll plus the losing stakes, once claims are open. Winners'
+    /// payouts stay in the vault whether or not they have been claimed. On a
+    /// pari-mutuel market, the seed comes back untouched
+    pub fn withdraw_bankroll(ctx: Context<RefundLiquidity>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(
+            market.mode != MarketMode::Amm,
+            MarketError::UnsupportedMarketMode
+        );
+        require!(
+            market.outcome.winner().is_some(),
+            MarketError::MarketNotResolved
+        );
+        require!(!market.disputed, MarketError::DisputeOpen);
+        require!(
+            Clock::get()?.unix_timestamp >= market.claims_open_at(),
+            MarketError::ClaimWindowNotOpen
+        );
+        let amount = market.bankroll_due();
+        require!(amount > 0, MarketError::NoReward);
+        if market.mode == MarketMode::Parimutuel {
+            market.bankroll = 0;
+        } else {
+            market.total_claimed = market.total_claimed.saturating_add(amount);
+        }
+
+        let recipient = pay_out_collateral(
+            &ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            &ctx.accounts.payout_token_account,
+            &ctx.accounts.collateral_mint,
+            &ctx.accounts.token_program,
+            &ctx.accounts.creator.to_account_info(),
+            amount,
+        )?;
+
+        emit_cpi!(log_event(BankrollWithdrawn {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            recipient,
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Donate to an unresolved market's winners without taking a position
+    /// Repeat sponsorships from the same wallet add up; a name, if given,
+    /// replaces the recorded one
+    pub fn sponsor_market(
+        ctx: Context<SponsorMarket>,
+        market_id: u64,
+        amount: u64,
+        sponsor_name: Option<String>,
+    ) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+        require!(!ctx.accounts.market.resolved, MarketError::MarketAlreadyResolved);
+        require!(!ctx.accounts.market.wsol_vault, MarketError::LamportVaultRequired);
+        // Fixed-odds payouts have no share of the pot to sponsor
+        require!(
+            ctx.accounts.market.mode != MarketMode::Bookmaker,
+            MarketError::UnsupportedMarketMode
+        );
+        if let Some(name) = &sponsor_name {
+            require!(
+                name.len() <= MAX_SPONSOR_NAME_LEN,
+                MarketError::InvalidSponsorName
+            );
+        }
+
+        transfer_lamports(
+            &ctx.accounts.sponsor.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        let market = &mut ctx.accounts.market;
+        market.sponsored_amount = market.sponsored_amount.saturating_add(amount);
+        market.last_activity_at = Clock::get()?.unix_timestamp;
+
+        let sponsorship = &mut ctx.accounts.sponsorship;
+        sponsorship.market_id = market_id;
+        sponsorship.sponsor = ctx.accounts.sponsor.key();
+        sponsorship.amount = sponsorship.amount.saturating_add(amount);
+        if let Some(name) = sponsor_name {
+            sponsorship.name = name;
+        }
+        sponsorship.bump = ctx.bumps.sponsorship;
+
+        emit_cpi!(log_event(MarketSponsored {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            sponsor: sponsorship.sponsor,
+            sponsor_name: sponsorship.name.clone(),
+            amount,
+            sponsored_amount: market.sponsored_amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Add lamports to an unresolved market's keeper budget, which pays the
+    /// config's keeper_tips to whoever cranks flag_stale, limit entries,
+    /// stop losses, price samples and losing predictions. The first funder (the creator, if
+    /// the market was created with a budget) is the only one who may top
+    /// it up, and gets back what is unused
+    pub fn fund_keeper_budget(
+        ctx: Context<FundKeeperBudget>,
+        market_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(amount > 0, MarketError::InvalidAmount);
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+        let funder = ctx.accounts.funder.key();
+        require!(
+            market.keeper_funder == Pubkey::default() || market.keeper_funder == funder,
+            MarketError::Unauthorized
+        );
+
+        transfer_lamports(
+            &ctx.accounts.funder.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        let market = &mut ctx.accounts.market;
+        market.keeper_funder = funder;
+        market.keeper_budget = market.keeper_budget.saturating_add(amount);
+
+        emit_cpi!(log_event(KeeperBudgetFunded {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            funder,
+            amount,
+            budget: market.keeper_budget,
+        }));
+
+        Ok(())
+    }
+
+    /// Return the unused keeper budget of a resolved market to its funder.
+    /// Permissionless
+    pub fn reclaim_keeper_budget(ctx: Context<ReclaimKeeperBudget>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.resolved, MarketError::MarketNotResolved);
+        let amount = market.keeper_budget;
+        require!(amount > 0, MarketError::NoKeeperBudget);
+        market.keeper_budget = 0;
+
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.funder.try_borrow_mut_lamports()? += amount;
+
+        emit_cpi!(log_event(KeeperBudgetReclaimed {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            funder: ctx.accounts.funder.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Tip a market's creator `amount` lamports. Tips wait in the vault,
+    /// outside every payout, fee and sweep, until the creator withdraws them
+    pub fn tip_creator(ctx: Context<TipCreator>, market_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+        require!(!ctx.accounts.market.wsol_vault, MarketError::LamportVaultRequired);
+
+        transfer_lamports(
+            &ctx.accounts.tipper.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        let market = &mut ctx.accounts.market;
+        market.creator_tips = market.creator_tips.saturating_add(amount);
+
+        emit_cpi!(log_event(CreatorTipped {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            tipper: ctx.accounts.tipper.key(),
+            amount,
+            creator_tips: market.creator_tips,
+        }));
+
+        Ok(())
+    }
+
+    /// Creator takes the tips a market holds for them, at any point in
+    /// its life
+    pub fn withdraw_tips(ctx: Context<WithdrawTips>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let amount = market.creator_tips;
+        require!(amount > 0, MarketError::NoTips);
+        market.creator_tips = 0;
+
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.creator.try_borrow_mut_lamports()? += amount;
+
+        emit_cpi!(log_event(TipsWithdrawn {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            creator: ctx.accounts.creator.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Sponsor takes back their sponsorship of a voided market
+    /// The sponsorship account is closed to the sponsor
+    pub fn refund_sponsorship(ctx: Context<RefundSponsorship>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(
+            market.outcome == MarketOutcome::Void,
+            MarketError::MarketNotVoided
+        );
+        let amount = ctx.accounts.sponsorship.amount;
+        market.sponsored_amount = market.sponsored_amount.saturating_sub(amount);
+
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.sponsor.try_borrow_mut_lamports()? += amount;
+
+        emit_cpi!(log_event(RefundClaimed {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            recipient: ctx.accounts.sponsor.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Close a fully settled market whose vault owes nothing, paying the
+    /// vault's and the market's lamports to the creator. Every prediction
+    /// account on it must be closed first. Its final state is fingerprinted
+    /// in a permanent MarketArchive, compute_archive_hash over its
+    /// resolution snapshot, payout params, fee totals and claim counts,
+    /// with the creator funding the archive's rent. An explicit ID can then
+    /// be taken again, under a new generation
+    pub fn close_market(ctx: Context<CloseMarket>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+        require!(market.fully_settled, MarketError::MarketNotSettled);
+        require!(
+            market.live_predictions == 0,
+            MarketError::PredictionsOutstanding
+        );
+        require!(
+            market.liabilities() == 0 && market.idle_deployed == 0 && market.escrowed_claims == 0,
+            MarketError::VaultNotDrained
+        );
+
+        let hash = compute_archive_hash(market);
+        let archive = &mut ctx.accounts.archive;
+        archive.market_id = market_id;
+        archive.generation = market.generation;
+        archive.outcome = market.outcome;
+        archive.hash = hash;
+        archive.archived_at = Clock::get()?.unix_timestamp;
+        archive.bump = ctx.bumps.archive;
+
+        // Left empty, the vault is reclaimed with the market
+        let vault = &ctx.accounts.market_vault;
+        let amount = vault.lamports();
+        **vault.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.creator.to_account_info().try_borrow_mut_lamports()? += amount;
+
+        emit_cpi!(log_event(MarketClosed {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            creator: market.creator,
+            generation: market.generation,
+            vault_lamports: amount,
+            archive: archive.key(),
+            outcome: market.outcome,
+            hash,
+        }));
+
+        Ok(())
+    }
+
+    /// Record a losing prediction against its owner's profile
+    /// Permissionless: anyone may settle a resolved, losing prediction. The
+    /// prediction is marked claimed so the loss can only be counted once
+    pub fn record_loss(ctx: Context<RecordLoss>, _market_id: u64) -> Result<()> {
+        let update = settle_loss(
+            &mut ctx.accounts.market,
+            &mut ctx.accounts.prediction_account,
+            &mut ctx.accounts.user_profile,
+            ctx.bumps.user_profile,
+        )?;
+        emit_cpi!(log_event(update));
+
+        Ok(())
+    }
+
+    /// Keeper crank over record_loss for positions whose owners never come
+    /// back: counts the loss on the owner's profile and, with `close`, also
+    /// closes the position with its rent going to whoever paid it. Tips the
+    /// keeper from the market's keeper budget
+    pub fn settle_losing_prediction(
+        ctx: Context<SettleLosingPrediction>,
+        market_id: u64,
+        close: bool,
+    ) -> Result<()> {
+        let update = settle_loss(
+            &mut ctx.accounts.market,
+            &mut ctx.accounts.prediction_account,
+            &mut ctx.accounts.user_profile,
+            ctx.bumps.user_profile,
+        )?;
+        emit_cpi!(log_event(update));
+
+        let prediction = &ctx.accounts.prediction_account;
+        if close {
+            // Closing would strand the owner's rebate
+            require!(
+                prediction.rebate_claimed || ctx.accounts.market.rebate_pot == 0,
+                MarketError::RebateUnclaimed
+            );
+            ctx.accounts.market.close_prediction(prediction);
+            let index = &mut ctx.accounts.user_market_index;
+            index.open_positions = index.open_positions.saturating_sub(1);
+
+            let (pnl_lamports, return_bps) = realized_pnl(0, prediction.amount_deposited);
+            emit_cpi!(log_event(PositionClosed {
+                market_id,
+                market: ctx.accounts.market.key(),
+                tenant: ctx.accounts.market.tenant,
+                event_seq: ctx.accounts.market.next_event_seq(),
+                predictor: prediction.predictor,
+                position_index: prediction.position_index,
+                amount_deposited: prediction.amount_deposited,
+                pnl_lamports,
+                return_bps,
+            }));
+        }
+
+        emit_cpi!(log_event(LossSettled {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            predictor: prediction.predictor,
+            position_index: prediction.position_index,
+            keeper: ctx.accounts.keeper.key(),
+            closed: close,
+        }));
+        // The prediction is settled now, so each one tips once
+        if let Some(paid) = pay_keeper_tip(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            KeeperAction::SettleLoss,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.keeper.to_account_info(),
+        )? {
+            emit_cpi!(log_event(paid));
+        }
+
+        if close {
+            ctx.accounts
+                .prediction_account
+                .close(ctx.accounts.rent_payer.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    /// Losing bettors claim their share of the rebate pot
+    /// Formula: (amount_deposited / losing side's deposits) * rebate_pot,
+    /// rounded down so the shares never add up to more than the pot
+    /// Once per prediction, independent of record_loss; claim it before
+    /// closing the position
+    pub fn claim_rebate(ctx: Context<ClaimRebate>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        let prediction = &mut ctx.accounts.prediction_account;
+        require!(market.resolved, MarketError::MarketNotResolved);
+        require!(!market.disputed, MarketError::DisputeOpen);
+        require!(
+            Clock::get()?.unix_timestamp >= market.claims_open_at(),
+            MarketError::ClaimWindowNotOpen
+        );
+        let outcome = market.outcome.winner().ok_or(MarketError::InvalidOutcome)?;
+        require!(prediction.prediction_type != outcome, MarketError::PredictionWon);
+        require!(!prediction.rebate_claimed, MarketError::RebateAlreadyClaimed);
+
+        let losing_deposits = market.resolution_snapshot.deposits(!outcome);
+        let rebate = loser_rebate(prediction.amount_deposited, losing_deposits, market.rebate_pot)
+            .min(market.rebate_outstanding());
+        require!(rebate > 0, MarketError::NoRebate);
+
+        prediction.rebate_claimed = true;
+        market.rebate_paid = market.rebate_paid.saturating_add(rebate);
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= rebate;
+        **ctx.accounts.predictor.try_borrow_mut_lamports()? += rebate;
+
+        emit_cpi!(log_event(RebateClaimed {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            predictor: prediction.predictor,
+            position_index: prediction.position_index,
+            amount: rebate,
+            rebate_outstanding: market.rebate_outstanding(),
+        }));
+
+        Ok(())
+    }
+
+    /// Close a settled prediction, returning its rent to whoever paid it
+    /// Settled means claimed, refunded or recorded as a loss. Closing a
+    /// losing position reports its realized loss
+    pub fn close_prediction(ctx: Context<ClosePrediction>, market_id: u64) -> Result<()> {
+        let prediction = &ctx.accounts.prediction_account;
+        require!(prediction.claimed, MarketError::PredictionNotSettled);
+        ctx.accounts.market.close_prediction(prediction);
+        let index = &mut ctx.accounts.user_market_index;
+        index.open_positions = index.open_positions.saturating_sub(1);
+
+        if let Some(outcome) = ctx.accounts.market.outcome.winner() {
+            if prediction.prediction_type != outcome {
+                let (pnl_lamports, return_bps) = realized_pnl(0, prediction.amount_deposited);
+                emit_cpi!(log_event(PositionClosed {
+                    market_id,
+                    market: ctx.accounts.market.key(),
+                    tenant: ctx.accounts.market.tenant,
+                    event_seq: ctx.accounts.market.next_event_seq(),
+                    predictor: prediction.predictor,
+                    position_index: prediction.position_index,
+                    amount_deposited: prediction.amount_deposited,
+                    pnl_lamports,
+                    return_bps,
+                }));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move `amount_tokens` of a position into a new position of its own,
+    /// with the matching share of the deposit. Each half claims on its own
+    pub fn split_position(
+        ctx: Context<SplitPosition>,
+        _market_id: u64,
+        amount_tokens: u64,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let source = &mut ctx.accounts.prediction_account;
+        require!(!source.claimed, MarketError::AlreadyClaimed);
+        require!(source.tokens_claimed == 0, MarketError::AlreadyClaimed);
+        if let Some(outcome) = market.outcome.winner() {
+            require!(source.prediction_type == outcome, MarketError::PredictionLost);
+        }
+        require!(
+            amount_tokens > 0 && amount_tokens < source.tokens_received,
+            MarketError::InvalidAmount
+        );
+
+        let deposit = ((source.amount_deposited as u128) * (amount_tokens as u128)
+            / (source.tokens_received as u128)) as u64;
+        source.tokens_received -= amount_tokens;
+        source.amount_deposited -= deposit;
+
+        // Positions opened before the index existed count as position 0
+        let index = &mut ctx.accounts.user_market_index;
+        if index.next_position_index == 0 {
+            index.user = source.predictor;
+            index.market_id = source.market_id;
+            index.next_position_index = 1;
+            index.open_positions = 1;
+            index.bump = ctx.bumps.user_market_index;
+        }
+
+        let split = &mut ctx.accounts.new_prediction;
+        split.version = PREDICTION_VERSION;
+        split.market_id = source.market_id;
+        split.predictor = source.predictor;
+        split.prediction_type = source.prediction_type;
+        split.amount_deposited = deposit;
+        split.tokens_received = amount_tokens;
+        split.created_at = source.created_at;
+        split.claimed = false;
+        split.referrer = source.referrer;
+        split.bump = ctx.bumps.new_prediction;
+        split.slot = source.slot;
+        split.yes_pool_at_entry = source.yes_pool_at_entry;
+        split.no_pool_at_entry = source.no_pool_at_entry;
+        split.entry_price_bps = source.entry_price_bps;
+        split.entry_probability_bps = source.entry_probability_bps;
+        split.trade_sequence = source.trade_sequence;
+        split.note = source.note.clone();
+        split.is_house = source.is_house;
+        ctx.accounts.market.open_prediction(split);
+        split.cancel_refund = 0;
+        source.cancel_refund = 0;
+        split.tokens_claimed = 0;
+        split.position_index = index.next_position_index;
+
+        index.next_position_index = index.next_position_index.saturating_add(1);
+        index.open_positions = index.open_positions.saturating_add(1);
+
+        // A winning position split after resolution owes one more claim
+        let side = split.prediction_type;
+        let market = &mut ctx.accounts.market;
+        market.add_position(side);
+        if market.resolved {
+            market.eligible_claims = market.eligible_claims.saturating_add(1);
+        }
+
+        Ok(())
+    }
+
+    /// Register the signer as a referrer so bets can credit them a share
+    /// of the trading fee
+    pub fn register_referrer(ctx: Context<RegisterReferrer>) -> Result<()> {
+        let referrer = &mut ctx.accounts.referrer;
+        referrer.owner = ctx.accounts.owner.key();
+        referrer.accrued_fees = 0;
+        referrer.total_referred_volume = 0;
+        referrer.bump = ctx.bumps.referrer;
+
+        emit_cpi!(log_event(ReferrerRegistered {
+            referrer: referrer.owner,
+        }));
+
+        Ok(())
+    }
+
+    /// Referrer withdraws all accrued referral fees
+    pub fn claim_referral_fees(ctx: Context<ClaimReferralFees>) -> Result<()> {
+        let referrer = &mut ctx.accounts.referrer;
+        let amount = referrer.accrued_fees;
+        require!(amount > 0, MarketError::NoReward);
+
+        referrer.accrued_fees = 0;
+
+        **referrer.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.owner.try_borrow_mut_lamports()? += amount;
+
+        emit_cpi!(log_event(ReferralFeesClaimed {
+            referrer: ctx.accounts.owner.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Admin withdraws collected fees. Refused once the market is voided, as
+    /// its fees then fund the refunds
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>, market_id: u64, amount: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.outcome != MarketOutcome::Void, MarketError::FeesRefundable);
+        require!(market.fee_collected >= amount, MarketError::InsufficientFees);
+
+        market.fee_collected = market.fee_collected.saturating_sub(amount);
+
+        pay_out_collateral(
+            &ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.wsol_vault,
+            &ctx.accounts.payout_token_account,
+            &ctx.accounts.collateral_mint,
+            &ctx.accounts.token_program,
+            &ctx.accounts.admin.to_account_info(),
+            amount,
+        )?;
+
+        emit_cpi!(log_event(FeesWithdrawn {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            admin: ctx.accounts.admin.key(),
+            amount,
+        }));
+
+        #[cfg(feature = "solvency-checks")]
+        if let Some(violation) = solvency_violation(
+            &ctx.accounts.market,
+            collateral_balance(
+                &ctx.accounts.market,
+                &ctx.accounts.market_vault,
+                &ctx.accounts.wsol_vault,
+            )?,
+        ) {
+            emit_cpi!(log_event(violation));
+        }
+
+        Ok(())
+    }
+
+    /// Config admin creates the protocol treasury PDA and points the config
+    /// at it, so swept dust and collected fees land in one account
+    pub fn init_treasury(ctx: Context<InitTreasury>) -> Result<()> {
+        let treasury = &mut ctx.accounts.treasury;
+        treasury.total_collected = 0;
+        treasury.total_withdrawn = 0;
+        treasury.bump = ctx.bumps.treasury;
+        ctx.accounts.config.treasury = treasury.key();
+
+        emit_cpi!(log_event(TreasuryInitialized {
+            treasury: treasury.key(),
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin moves the collected fees of many markets into the
+    /// treasury in one transaction. remaining_accounts holds a writable
+    /// (market, vault) pair per market; each market's fee counter is zeroed
+    /// Once the staking vault exists it must be passed, and receives its
+    /// fee share of the total for stakers. Likewise the buyback escrow,
+    /// whose share is taken from what is left for the treasury
+    pub fn collect_fees_many<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CollectFeesMany<'info>>,
+    ) -> Result<()> {
+        require!(
+            !ctx.remaining_accounts.is_empty() && ctx.remaining_accounts.len().is_multiple_of(2),
+            MarketError::InvalidMarketAccounts
+        );
+
+        let treasury_info = ctx.accounts.treasury.to_account_info();
+        let mut total = 0u64;
+        for pair in ctx.remaining_accounts.chunks(2) {
+            let mut market = Account::<Market>::try_from(&pair[0])?;
+            require!(
+                market.version == MARKET_VERSION,
+                MarketError::UnsupportedAccountVersion
+            );
+            require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+            let vault = Pubkey::create_program_address(
+                &[b"vault", market.market_id.to_le_bytes().as_ref(), &[market.vault_bump]],
+                &crate::ID,
+            )
+            .map_err(|_| error!(MarketError::InvalidMarketAccounts))?;
+            require_keys_eq!(pair[1].key(), vault, MarketError::InvalidMarketAccounts);
+
+            let amount = market.fee_collected;
+            if amount == 0 {
+                continue;
+            }
+            market.fee_collected = 0;
+            let event_seq = market.next_event_seq();
+            **pair[1].try_borrow_mut_lamports()? -= amount;
+            **treasury_info.try_borrow_mut_lamports()? += amount;
+            market.exit(&crate::ID)?;
+            total = total.saturating_add(amount);
+
+            emit_cpi!(log_event(FeesCollected {
+                market_id: market.market_id,
+                market: market.key(),
+                tenant: market.tenant,
+                event_seq,
+                amount,
+            }));
+        }
+
+        // The treasury belongs to no market; like a FavorPool market, it
+        // keeps the remainders of its splits
+        let rounding = RoundingPolicy::FavorPool;
+        let to_stakers = match ctx.accounts.staking_vault.as_mut() {
+            Some(staking_vault) => {
+                let share = rounding.payout_bps(total, staking_vault.fee_share_bps);
+                **treasury_info.try_borrow_mut_lamports()? -= share;
+                **staking_vault.to_account_info().try_borrow_mut_lamports()? += share;
+                staking_vault.distribute(share);
+                share
+            }
+            None => {
+                require!(
+                    ctx.accounts.config.staking_vault == Pubkey::default(),
+                    MarketError::StakingVaultRequired
+                );
+                0
+            }
+        };
+
+        let to_buyback = match ctx.accounts.buyback_escrow.as_mut() {
+            Some(escrow) => {
+                let share = rounding.payout_bps(total - to_stakers, escrow.buyback_bps);
+                **treasury_info.try_borrow_mut_lamports()? -= share;
+                **escrow.to_account_info().try_borrow_mut_lamports()? += share;
+                escrow.escrowed = escrow.escrowed.saturating_add(share);
+                share
+            }
+            None => {
+                require!(
+                    ctx.accounts.config.buyback_escrow == Pubkey::default(),
+                    MarketError::BuybackEscrowRequired
+                );
+                0
+            }
+        };
+
+        let treasury = &mut ctx.accounts.treasury;
+        treasury.total_collected = treasury
+            .total_collected
+            .saturating_add(total - to_stakers - to_buyback);
+
+        emit_cpi!(log_event(TreasuryFeesCollected {
+            markets: (ctx.remaining_accounts.len() / 2) as u32,
+            amount: total,
+            total_collected: treasury.total_collected,
+            to_stakers,
+            to_buyback,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin queues a withdrawal from the treasury, executable once
+    /// the config's timelock has passed. One may be pending at a time
+    pub fn propose_treasury_withdrawal(
+        ctx: Context<ProposeTreasuryWithdrawal>,
+        amount: u64,
+        recipient: Pubkey,
+    ) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+
+        let now = Clock::get()?.unix_timestamp;
+        let pending = &mut ctx.accounts.pending_withdrawal;
+        pending.amount = amount;
+        pending.recipient = recipient;
+        pending.proposed_at = now;
+        pending.executable_at = now.saturating_add(ctx.accounts.config.config_delay_secs);
+        pending.bump = ctx.bumps.pending_withdrawal;
+
+        emit_cpi!(log_event(TreasuryWithdrawalProposed {
+            amount,
+            recipient,
+            executable_at: pending.executable_at,
+        }));
+
+        Ok(())
+    }
+
+    /// Pay out the pending treasury withdrawal once its timelock has passed
+    pub fn execute_treasury_withdrawal(ctx: Context<ExecuteTreasuryWithdrawal>) -> Result<()> {
+        let pending = &ctx.accounts.pending_withdrawal;
+        require!(
+            Clock::get()?.unix_timestamp >= pending.executable_at,
+            MarketError::TimelockNotElapsed
+        );
+
+        let amount = pending.amount;
+        let treasury_info = ctx.accounts.treasury.to_account_info();
+        let rent = Rent::get()?.minimum_balance(treasury_info.data_len());
+        require!(
+            treasury_info.lamports().saturating_sub(rent) >= amount,
+            MarketError::TreasuryInsufficient
+        );
+        **treasury_info.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.recipient.try_borrow_mut_lamports()? += amount;
+
+        let treasury = &mut ctx.accounts.treasury;
+        treasury.total_withdrawn = treasury.total_withdrawn.saturating_add(amount);
+
+        emit_cpi!(log_event(TreasuryWithdrawn {
+            recipient: ctx.accounts.recipient.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin withdraws the pending treasury withdrawal
+    pub fn cancel_treasury_withdrawal(ctx: Context<CancelTreasuryWithdrawal>) -> Result<()> {
+        emit_cpi!(log_event(TreasuryWithdrawalCancelled {
+            amount: ctx.accounts.pending_withdrawal.amount,
+            recipient: ctx.accounts.pending_withdrawal.recipient,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin sets the share of collected fees paid to stakers and
+    /// the unbonding delay. The first call creates the staking vault for
+    /// `staking_mint`, which can't change afterwards
+    pub fn configure_staking(
+        ctx: Context<ConfigureStaking>,
+        fee_share_bps: u16,
+        unbonding_secs: i64,
+    ) -> Result<()> {
+        require!(
+            fee_share_bps as u64 <= BPS_DENOMINATOR && unbonding_secs >= 0,
+            MarketError::InvalidConfig
+        );
+
+        let staking_vault = &mut ctx.accounts.staking_vault;
+        staking_vault.mint = ctx.accounts.staking_mint.key();
+        staking_vault.fee_share_bps = fee_share_bps;
+        staking_vault.unbonding_secs = unbonding_secs;
+        staking_vault.bump = ctx.bumps.staking_vault;
+        staking_vault.tokens_bump = ctx.bumps.staking_tokens;
+        ctx.accounts.config.staking_vault = staking_vault.key();
+
+        emit_cpi!(log_event(StakingConfigured {
+            mint: staking_vault.mint,
+            fee_share_bps,
+            unbonding_secs,
+        }));
+
+        Ok(())
+    }
+
+    /// Stake protocol tokens for a share of the fees routed to stakers
+    /// Rewards accrue from the next fee collection on
+    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.owner_token_account.to_account_info(),
+                    to: ctx.accounts.staking_tokens.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        let staking_vault = &mut ctx.accounts.staking_vault;
+        let position = &mut ctx.accounts.position;
+        position.settle(staking_vault.acc_reward_per_token);
+        position.owner = ctx.accounts.owner.key();
+        position.staked = position.staked.saturating_add(amount);
+        position.reset_debt(staking_vault.acc_reward_per_token);
+        position.bump = ctx.bumps.position;
+        staking_vault.total_staked = staking_vault.total_staked.saturating_add(amount);
+
+        emit_cpi!(log_event(Staked {
+            owner: position.owner,
+            amount,
+            staked: position.staked,
+            total_staked: staking_vault.total_staked,
+        }));
+
+        Ok(())
+    }
+
+    /// Start unbonding staked tokens. They stop earning at once and can be
+    /// withdrawn after the unbonding delay, which restarts for everything
+    /// still unbonding
+    pub fn unstake(ctx: Context<Unstake>, amount: u64) -> Result<()> {
+        let staking_vault = &mut ctx.accounts.staking_vault;
+        let position = &mut ctx.accounts.position;
+        require!(
+            amount > 0 && amount <= position.staked,
+            MarketError::InvalidAmount
+        );
+
+        position.settle(staking_vault.acc_reward_per_token);
+        position.staked -= amount;
+        position.reset_debt(staking_vault.acc_reward_per_token);
+        position.unbonding = position.unbonding.saturating_add(amount);
+        position.unbonding_ends_at = Clock::get()?
+            .unix_timestamp
+            .saturating_add(staking_vault.unbonding_secs);
+        staking_vault.total_staked = staking_vault.total_staked.saturating_sub(amount);
+
+        emit_cpi!(log_event(Unstaked {
+            owner: position.owner,
+            amount,
+            staked: position.staked,
+            unbonding: position.unbonding,
+            unbonding_ends_at: position.unbonding_ends_at,
+        }));
+
+        Ok(())
+    }
+
+    /// Return unbonded tokens to their owner once the delay has passed
+    pub fn withdraw_unstaked(ctx: Context<WithdrawUnstaked>) -> Result<()> {
+        let position = &mut ctx.accounts.position;
+        let amount = position.unbonding;
+        require!(amount > 0, MarketError::InvalidAmount);
+        require!(
+            Clock::get()?.unix_timestamp >= position.unbonding_ends_at,
+            MarketError::UnbondingActive
+        );
+        position.unbonding = 0;
+
+        let signer_seeds: &[&[&[u8]]] =
+            &[&[b"staking_vault", &[ctx.accounts.staking_vault.bump]]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.staking_tokens.to_account_info(),
+                    to: ctx.accounts.owner_token_account.to_account_info(),
+                    authority: ctx.accounts.staking_vault.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+        )?;
+
+        emit_cpi!(log_event(UnstakedWithdrawn {
+            owner: position.owner,
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Withdraw the fee lamports a staking position has earned
+    pub fn claim_staking_rewards(ctx: Context<ClaimStakingRewards>) -> Result<()> {
+        let staking_vault = &mut ctx.accounts.staking_vault;
+        let position = &mut ctx.accounts.position;
+        position.settle(staking_vault.acc_reward_per_token);
+        let amount = position.unclaimed;
+        require!(amount > 0, MarketError::NoReward);
+        position.unclaimed = 0;
+        staking_vault.total_claimed = staking_vault.total_claimed.saturating_add(amount);
+
+        **staking_vault.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.owner.try_borrow_mut_lamports()? += amount;
+
+        emit_cpi!(log_event(StakingRewardsClaimed {
+            owner: position.owner,
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin sets the share of collected fees escrowed for buying
+    /// back and burning the protocol token, and the executor allowed to
+    /// swap it. The first call creates the escrow for `protocol_mint`,
+    /// which can't change afterwards
+    pub fn configure_buyback(
+        ctx: Context<ConfigureBuyback>,
+        buyback_bps: u16,
+        executor: Pubkey,
+    ) -> Result<()> {
+        require!(
+            buyback_bps as u64 <= BPS_DENOMINATOR && executor != Pubkey::default(),
+            MarketError::InvalidConfig
+        );
+
+        let escrow = &mut ctx.accounts.buyback_escrow;
+        escrow.protocol_mint = ctx.accounts.protocol_mint.key();
+        escrow.executor = executor;
+        escrow.buyback_bps = buyback_bps;
+        escrow.bump = ctx.bumps.buyback_escrow;
+        escrow.tokens_bump = ctx.bumps.buyback_tokens;
+        ctx.accounts.config.buyback_escrow = escrow.key();
+
+        emit_cpi!(log_event(BuybackConfigured {
+            protocol_mint: escrow.protocol_mint,
+            executor,
+            buyback_bps,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin releases escrowed SOL to the executor to buy protocol
+    /// tokens. The same transaction must end in settle_buyback, which
+    /// burns at least `min_tokens` delivered to the escrow's token
+    /// account, so the SOL can't leave without the burn
+    pub fn execute_buyback(
+        ctx: Context<ExecuteBuyback>,
+        sol_amount: u64,
+        min_tokens: u64,
+    ) -> Result<()> {
+        let escrow = &mut ctx.accounts.buyback_escrow;
+        require!(
+            sol_amount > 0 && sol_amount <= escrow.escrowed && min_tokens > 0,
+            MarketError::InvalidAmount
+        );
+        require!(escrow.pending_sol == 0, MarketError::BuybackPending);
+        require!(
+            later_instruction_calls(
+                &ctx.accounts.instructions,
+                crate::instruction::SettleBuyback::DISCRIMINATOR
+            )?,
+            MarketError::BuybackNotSettled
+        );
+
+        escrow.escrowed -= sol_amount;
+        escrow.pending_sol = sol_amount;
+        escrow.pending_min_tokens = min_tokens;
+        escrow.pending_start_balance = ctx.accounts.buyback_tokens.amount;
+        **escrow.to_account_info().try_borrow_mut_lamports()? -= sol_amount;
+        **ctx.accounts.executor.try_borrow_mut_lamports()? += sol_amount;
+
+        Ok(())
+    }
+
+    /// Burn the protocol tokens delivered for a buyback, checking that at
+    /// least the promised amount arrived since execute_buyback released the
+    /// SOL. Permissionless; only runs after execute_buyback in the same
+    /// transaction
+    pub fn settle_buyback(ctx: Context<SettleBuyback>) -> Result<()> {
+        let escrow = &mut ctx.accounts.buyback_escrow;
+        require!(escrow.pending_sol > 0, MarketError::NoBuybackPending);
+        let tokens = &ctx.accounts.buyback_tokens;
+        let received = tokens.amount.saturating_sub(escrow.pending_start_balance);
+        require!(
+            received >= escrow.pending_min_tokens,
+            MarketError::BuybackUnderDelivered
+        );
+
+        let burned = tokens.amount;
+        let signer_seeds: &[&[&[u8]]] = &[&[b"buyback_escrow", &[escrow.bump]]];
+        token::burn(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.protocol_mint.to_account_info(),
+                    from: tokens.to_account_info(),
+                    authority: escrow.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            burned,
+        )?;
+
+        let sol_spent = escrow.pending_sol;
+        escrow.pending_sol = 0;
+        escrow.pending_min_tokens = 0;
+        escrow.pending_start_balance = 0;
+        escrow.total_sol_spent = escrow.total_sol_spent.saturating_add(sol_spent);
+        escrow.total_burned = escrow.total_burned.saturating_add(burned);
+
+        emit_cpi!(log_event(BuybackExecuted {
+            executor: escrow.executor,
+            sol_spent,
+            tokens_received: received,
+            tokens_burned: burned,
+        }));
+
+        Ok(())
+    }
+
+    /// Create the zero-copy order book holding a market's resting orders
+    /// Permissionless; the payer funds the account's rent
+    pub fn initialize_order_book(ctx: Context<InitializeOrderBook>, market_id: u64) -> Result<()> {
+        let mut book = ctx.accounts.order_book.load_init()?;
+        book.init(market_id, ctx.bumps.order_book);
+        Ok(())
+    }
+
+    /// Rest an order on the market's book, escrowing its lamports in the
+    /// order book account until it is cancelled
+    pub fn post_order(
+        ctx: Context<PostOrder>,
+        market_id: u64,
+        side: bool, // true = YES, false = NO
+        price_bps: u16,
+        amount: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.require_feature(FEATURE_ORDER_BOOK)?;
+        require!(amount > 0, MarketError::InvalidAmount);
+        require!(
+            price_bps > 0 && (price_bps as u64) < BPS_DENOMINATOR,
+            MarketError::InvalidOrderPrice
+        );
+        let market = &ctx.accounts.market;
+        let now = Clock::get()?.unix_timestamp;
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(now < market.betting_close_time(), MarketError::MarketExpired);
+        require!(!market.betting_frozen(now), MarketError::BettingFrozen);
+        require_not_self_excluded(&ctx.accounts.self_exclusion, now)?;
+
+        let owner = ctx.accounts.owner.key();
+        let slot = ctx.accounts.order_book.load_mut()?.insert(Order {
+            owner,
+            amount,
+            posted_at: now,
+            price_bps,
+            next_free: ORDER_SLOT_NONE,
+            side: side as u8,
+            in_use: 1,
+            _padding: [0; 2],
+        })?;
+
+        transfer_lamports(
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.order_book.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        emit_cpi!(log_event(OrderPosted {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            slot,
+            owner,
+            side,
+            price_bps,
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Cancel a resting order, refunding its escrow to the owner and
+    /// returning the slot to the free list
+    /// Refused during the pre-close freeze unless the market allows exits
+    pub fn cancel_order(ctx: Context<CancelOrder>, market_id: u64, slot: u16) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            market.exits_during_freeze
+                || now >= market.betting_close_time()
+                || !market.betting_frozen(now),
+            MarketError::BettingFrozen
+        );
+
+        let order = ctx
+            .accounts
+            .order_book
+            .load_mut()?
+            .remove(slot, &ctx.accounts.owner.key())?;
+
+        **ctx.accounts.order_book.to_account_info().try_borrow_mut_lamports()? -= order.amount;
+        **ctx.accounts.owner.try_borrow_mut_lamports()? += order.amount;
+
+        emit_cpi!(log_event(OrderCancelled {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            slot,
+            owner: order.owner,
+            amount: order.amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Take `amount` lamports of a resting order at its price. The taker
+    /// stakes the other side, sized so that the pair's pot pays out at the
+    /// order's price, and both stakes move into a new Fill until the market
+    /// settles; see settle_fill. The taker also pays the market's trading
+    /// fee on its stake. If the order's owner is an approved market maker,
+    /// its rebate_bps of that fee goes to its registration, the rest to the
+    /// market's collected fees. `fill_id` is the taker's own, so each of
+    /// its fills gets its own account. Like a bet, refused while the
+    /// protocol is paused or the market needs attention
+    pub fn fill_order(
+        ctx: Context<FillOrder>,
+        market_id: u64,
+        slot: u16,
+        amount: u64,
+        _fill_id: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.require_feature(FEATURE_ORDER_BOOK)?;
+        require!(amount > 0, MarketError::InvalidAmount);
+        let market = &ctx.accounts.market;
+        require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+        let now = Clock::get()?.unix_timestamp;
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(now < market.betting_close_time(), MarketError::MarketExpired);
+        require!(!market.betting_frozen(now), MarketError::BettingFrozen);
+        require_open_to_bets(&ctx.accounts.config, market, &ctx.accounts.self_exclusion, now)?;
+
+        let order = ctx.accounts.order_book.load_mut()?.take(slot, amount)?;
+        let price_bps = order.price_bps as u128;
+        let taker_stake =
+            ((amount as u128) * (BPS_DENOMINATOR as u128 - price_bps) / price_bps) as u64;
+        require!(taker_stake > 0, MarketError::InvalidAmount);
+        let rounding = market.rounding_policy;
+        let fee = rounding.charge_bps(
+            taker_stake,
+            market.fee_bps.unwrap_or(ctx.accounts.config.trading_fee_bps),
+        );
+        let mm_rebate =
+            credit_mm_rebate(&ctx.accounts.maker_registration, &order.owner, fee, rounding)?;
+
+        let fill = &mut ctx.accounts.fill;
+        fill.market_id = market_id;
+        fill.maker = order.owner;
+        fill.taker = ctx.accounts.taker.key();
+        fill.maker_side = order.side == 1;
+        fill.maker_stake = amount;
+        fill.taker_stake = taker_stake;
+        fill.price_bps = order.price_bps;
+        fill.filled_at = now;
+        fill.bump = ctx.bumps.fill;
+
+        let taker = ctx.accounts.taker.to_account_info();
+        let system_program = ctx.accounts.system_program.to_account_info();
+        for (to, lamports) in [
+            (ctx.accounts.fill.to_account_info(), taker_stake),
+            (ctx.accounts.market_vault.to_account_info(), fee - mm_rebate),
+            (ctx.accounts.maker_registration.to_account_info(), mm_rebate),
+        ] {
+            transfer_lamports(&taker, &to, &system_program, lamports)?;
+        }
+        **ctx.accounts.order_book.to_account_info().try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.fill.to_account_info().try_borrow_mut_lamports()? += amount;
+        let market = &mut ctx.accounts.market;
+        market.fee_collected = market.fee_collected.saturating_add(fee - mm_rebate);
+
+        emit_cpi!(log_event(OrderFilled {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            slot,
+            fill: ctx.accounts.fill.key(),
+            maker: order.owner,
+            taker: ctx.accounts.taker.key(),
+            maker_side: order.side == 1,
+            price_bps: order.price_bps,
+            maker_stake: amount,
+            taker_stake,
+            fee,
+            mm_rebate,
+        }));
+
+        Ok(())
+    }
+
+    /// Pay out a fill once its market has settled: the whole pot to the
+    /// side that won, or each stake back to its owner if the market was
+    /// voided. The fill's rent goes back to the taker. Permissionless
+    pub fn settle_fill(ctx: Context<SettleFill>, market_id: u64, _fill_id: u64) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(market.resolved, MarketError::MarketNotResolved);
+        let fill = &ctx.accounts.fill;
+        let pot = fill.maker_stake + fill.taker_stake;
+        let (maker_payout, taker_payout) = match market.outcome.winner() {
+            Some(winner) => {
+                require!(!market.disputed, MarketError::DisputeOpen);
+                require!(
+                    Clock::get()?.unix_timestamp >= market.claims_open_at(),
+                    MarketError::ClaimWindowNotOpen
+                );
+                if winner == fill.maker_side {
+                    (pot, 0)
+                } else {
+                    (0, pot)
+                }
+            }
+            None => (fill.maker_stake, fill.taker_stake),
+        };
+
+        // The taker's payout leaves with the rent when the fill closes
+        **ctx.accounts.fill.to_account_info().try_borrow_mut_lamports()? -= maker_payout;
+        **ctx.accounts.maker.try_borrow_mut_lamports()? += maker_payout;
+
+        emit_cpi!(log_event(FillSettled {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            fill: ctx.accounts.fill.key(),
+            maker: ctx.accounts.fill.maker,
+            taker: ctx.accounts.fill.taker,
+            maker_payout,
+            taker_payout,
+        }));
+
+        Ok(())
+    }
+
+    /// Donate lamports to the insurance fund. Permissionless
+    pub fn fund_insurance(ctx: Context<FundInsurance>, amount: u64) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+        transfer_lamports(
+            &ctx.accounts.donor.to_account_info(),
+            &ctx.accounts.insurance_fund.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_deposited = fund.total_deposited.saturating_add(amount);
+
+        emit_cpi!(log_event(InsuranceFunded {
+            donor: ctx.accounts.donor.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin pays `amount` of its own lamports into a lamport
+    /// market vault, typically to make up a shortfall that keeps the
+    /// market from resolving
+    pub fn top_up_vault(ctx: Context<TopUpVault>, market_id: u64, amount: u64) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+        require!(!ctx.accounts.market.wsol_vault, MarketError::LamportVaultRequired);
+
+        transfer_lamports(
+            &ctx.accounts.admin.to_account_info(),
+            &ctx.accounts.market_vault,
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        emit_cpi!(log_event(VaultToppedUp {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            admin: ctx.accounts.admin.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin moves insurance lamports into a market vault that
+    /// cannot cover its claims. This is the fund's only outflow: it pays
+    /// into a named market's vault and nowhere else
+    pub fn cover_shortfall(
+        ctx: Context<CoverShortfall>,
+        market_id: u64,
+        amount: u64,
+        reason: String,
+    ) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+        require!(
+            reason.len() <= MAX_SHORTFALL_REASON_LEN,
+            MarketError::InvalidShortfallReason
+        );
+        require!(!ctx.accounts.market.wsol_vault, MarketError::LamportVaultRequired);
+
+        let fund_info = ctx.accounts.insurance_fund.to_account_info();
+        let rent = Rent::get()?.minimum_balance(fund_info.data_len());
+        require!(
+            fund_info.lamports().saturating_sub(rent) >= amount,
+            MarketError::InsuranceFundInsufficient
+        );
+        **fund_info.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? += amount;
+
+        let fund = &mut ctx.accounts.insurance_fund;
+        fund.total_covered = fund.total_covered.saturating_add(amount);
+
+        emit_cpi!(log_event(ShortfallCovered {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            amount,
+            reason,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin sets the governance mint, arbiter and timings used to
+    /// settle disputed outcomes. The first call creates the dispute config
+    pub fn configure_disputes(
+        ctx: Context<ConfigureDisputes>,
+        params: DisputeParams,
+    ) -> Result<()> {
+        require!(
+            params.dispute_window_secs > 0 && params.voting_period_secs > 0,
+            MarketError::InvalidConfig
+        );
+
+        let dispute_config = &mut ctx.accounts.dispute_config;
+        dispute_config.governance_mint = ctx.accounts.governance_mint.key();
+        dispute_config.arbiter = params.arbiter;
+        dispute_config.dispute_window_secs = params.dispute_window_secs;
+        dispute_config.voting_period_secs = params.voting_period_secs;
+        dispute_config.quorum = params.quorum;
+        dispute_config.dispute_bond = params.dispute_bond;
+        dispute_config.bump = ctx.bumps.dispute_config;
+
+        Ok(())
+    }
+
+    /// Challenge a market's outcome within the dispute window, escrowing
+    /// the dispute bond. Claims are held until the dispute is settled, so
+    /// it can only be raised before any prediction has been settled
+    pub fn raise_dispute(ctx: Context<RaiseDispute>, market_id: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let dispute_config = &ctx.accounts.dispute_config;
+        let market = &mut ctx.accounts.market;
+
+        // Disputes belong to the adjudication program, not to this one
+        require!(
+            market.resolution_program.is_none(),
+            MarketError::ResolutionProgramOnly
+        );
+        require!(market.resolved, MarketError::MarketNotResolved);
+        let original_outcome = market.outcome.winner().ok_or(MarketError::InvalidOutcome)?;
+        let window = if market.dispute_window_secs > 0 {
+            market.dispute_window_secs
+        } else {
+            dispute_config.dispute_window_secs
+        };
+        require!(
+            now <= market.resolved_at.saturating_add(window),
+            MarketError::DisputeWindowClosed
+        );
+        require!(market.claims_count == 0, MarketError::DisputeAfterClaims);
+
+        transfer_lamports(
+            &ctx.accounts.raiser.to_account_info(),
+            &ctx.accounts.dispute.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            dispute_config.dispute_bond,
+        )?;
+
+        market.disputed = true;
+
+        let dispute = &mut ctx.accounts.dispute;
+        dispute.market_id = market_id;
+        dispute.raiser = ctx.accounts.raiser.key();
+        dispute.original_outcome = original_outcome;
+        dispute.bond = dispute_config.dispute_bond;
+        dispute.voting_ends_at = now.saturating_add(dispute_config.voting_period_secs);
+        dispute.yes_votes = 0;
+        dispute.no_votes = 0;
+        dispute.status = DisputeStatus::Voting;
+        dispute.bump = ctx.bumps.dispute;
+        dispute.vault_bump = ctx.bumps.dispute_vault;
+
+        emit_cpi!(log_event(DisputeRaised {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            raiser: dispute.raiser,
+            original_outcome: market.outcome,
+            voting_ends_at: dispute.voting_ends_at,
+        }));
+
+        Ok(())
+    }
+
+    /// Vote on a disputed outcome by escrowing governance tokens until the
+    /// dispute is settled. One vote per holder; the stake is the weight
+    pub fn cast_dispute_vote(
+        ctx: Context<CastDisputeVote>,
+        market_id: u64,
+        outcome: bool,
+        amount: u64,
+    ) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+        let dispute = &mut ctx.accounts.dispute;
+        require!(
+            dispute.status == DisputeStatus::Voting
+                && Clock::get()?.unix_timestamp < dispute.voting_ends_at,
+            MarketError::VotingClosed
+        );
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.voter_token_account.to_account_info(),
+                    to: ctx.accounts.dispute_vault.to_account_info(),
+                    authority: ctx.accounts.voter.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        if outcome {
+            dispute.yes_votes = dispute.yes_votes.saturating_add(amount);
+        } else {
+            dispute.no_votes = dispute.no_votes.saturating_add(amount);
+        }
+
+        let vote = &mut ctx.accounts.dispute_vote;
+        vote.voter = ctx.accounts.voter.key();
+        vote.outcome = outcome;
+        vote.amount = amount;
+        vote.bump = ctx.bumps.dispute_vote;
+
+        emit_cpi!(log_event(DisputeVoteCast {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            voter: vote.voter,
+            outcome,
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Close voting once the window has passed. With quorum, the
+    /// stake-weighted majority becomes the outcome (a tie keeps the original);
+    /// below quorum the dispute waits for the arbiter. Permissionless
+    pub fn tally_dispute(ctx: Context<TallyDispute>, market_id: u64) -> Result<()> {
+        let dispute = &mut ctx.accounts.dispute;
+        require!(
+            dispute.status == DisputeStatus::Voting,
+            MarketError::DisputeNotVoting
+        );
+        require!(
+            Clock::get()?.unix_timestamp >= dispute.voting_ends_at,
+            MarketError::VotingStillOpen
+        );
+
+        let turnout = dispute.yes_votes.saturating_add(dispute.no_votes);
+        if turnout < ctx.accounts.dispute_config.quorum {
+            dispute.status = DisputeStatus::AwaitingArbiter;
+            emit_cpi!(log_event(DisputeEscalated {
+                market_id,
+                market: ctx.accounts.market.key(),
+                tenant: ctx.accounts.market.tenant,
+                event_seq: ctx.accounts.market.next_event_seq(),
+                yes_votes: dispute.yes_votes,
+                no_votes: dispute.no_votes,
+            }));
+            return Ok(());
+        }
+
+        let outcome = if dispute.yes_votes == dispute.no_votes {
+            dispute.original_outcome
+        } else {
+            dispute.yes_votes > dispute.no_votes
+        };
+        let event = finalize_dispute(
+            &mut ctx.accounts.market,
+            dispute,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.raiser,
+            outcome,
+        )?;
+        emit_cpi!(log_event(event));
+
+        Ok(())
+    }
+
+    /// Arbiter settles a dispute whose vote fell short of quorum
+    pub fn arbitrate_dispute(
+        ctx: Context<ArbitrateDispute>,
+        _market_id: u64,
+        outcome: bool,
+    ) -> Result<()> {
+        require!(
+            ctx.accounts.dispute.status == DisputeStatus::AwaitingArbiter,
+            MarketError::DisputeNotAwaitingArbiter
+        );
+
+        let event = finalize_dispute(
+            &mut ctx.accounts.market,
+            &mut ctx.accounts.dispute,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.raiser,
+            outcome,
+        )?;
+        emit_cpi!(log_event(event));
+
+        Ok(())
+    }
+
+    /// Return a voter's escrowed governance tokens once voting is over
+    /// The vote account is closed to the voter
+    pub fn withdraw_dispute_vote(
+        ctx: Context<WithdrawDisputeVote>,
+        market_id: u64,
+    ) -> Result<()> {
+        let dispute = &ctx.accounts.dispute;
+        require!(
+            dispute.status != DisputeStatus::Voting,
+            MarketError::VotingStillOpen
+        );
+
+        let id_bytes = market_id.to_le_bytes();
+        let signer_seeds: &[&[&[u8]]] = &[&[b"dispute", id_bytes.as_ref(), &[dispute.bump]]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.dispute_vault.to_account_info(),
+                    to: ctx.accounts.voter_token_account.to_account_info(),
+                    authority: ctx.accounts.dispute.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            ctx.accounts.dispute_vote.amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Deposit lamports into the reserve that backs parlay winnings
+    /// Permissionless; the first deposit creates the reserve
+    pub fn fund_parlay_reserve(ctx: Context<FundParlayReserve>, amount: u64) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+        let reserve = &mut ctx.accounts.parlay_reserve;
+        reserve.bump = ctx.bumps.parlay_reserve;
+
+        transfer_lamports(
+            &ctx.accounts.funder.to_account_info(),
+            &reserve.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Open a parlay: a single stake on several market outcomes that pays
+    /// only if every leg hits. Each leg's Market account is passed, in leg
+    /// order, as a remaining account. The stake is escrowed in the parlay
+    /// account and the most it can win on top of that is reserved from the
+    /// parlay reserve
+    pub fn create_parlay<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CreateParlay<'info>>,
+        parlay_id: u64,
+        legs: Vec<ParlayLeg>,
+        stake: u64,
+        max_payout: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.require_feature(FEATURE_PARLAYS)?;
+        require!(stake > 0, MarketError::InvalidAmount);
+        require!(max_payout >= stake, MarketError::InvalidParlay);
+        require!(
+            (2..=MAX_PARLAY_LEGS).contains(&legs.len())
+                && ctx.remaining_accounts.len() == legs.len(),
+            MarketError::InvalidParlay
+        );
+
+        let now = Clock::get()?.unix_timestamp;
+        for (i, (leg, info)) in legs.iter().zip(ctx.remaining_accounts).enumerate() {
+            require!(
+                legs[..i].iter().all(|other| other.market_id != leg.market_id),
+                MarketError::InvalidParlay
+            );
+            let market = Account::<Market>::try_from(info)?;
+            require!(
+                market.version == MARKET_VERSION,
+                MarketError::UnsupportedAccountVersion
+            );
+            require!(market.market_id == leg.market_id, MarketError::InvalidParlay);
+            // Legs pay pool odds, which a fixed-odds market doesn't have
+            require!(
+                market.mode != MarketMode::Bookmaker,
+                MarketError::UnsupportedMarketMode
+            );
+            require!(!market.resolved, MarketError::MarketAlreadyResolved);
+            require!(now < market.betting_close_time(), MarketError::MarketExpired);
+        }
+
+        let exposure = max_payout - stake;
+        let reserve = &mut ctx.accounts.parlay_reserve;
+        let reserve_info = reserve.to_account_info();
+        let rent = Rent::get()?.minimum_balance(reserve_info.data_len());
+        require!(
+            reserve.free_liquidity(reserve_info.lamports(), rent) >= exposure,
+            MarketError::ParlayReserveInsufficient
+        );
+        reserve.committed = reserve.committed.saturating_add(exposure);
+
+        let parlay = &mut ctx.accounts.parlay;
+        parlay.owner = ctx.accounts.owner.key();
+        parlay.parlay_id = parlay_id;
+        parlay.stake = stake;
+        parlay.max_payout = max_payout;
+        parlay.leg_count = legs.len() as u8;
+        parlay.legs = [ParlayLeg::default(); MAX_PARLAY_LEGS];
+        parlay.legs[..legs.len()].copy_from_slice(&legs);
+        parlay.created_at = now;
+        parlay.bump = ctx.bumps.parlay;
+
+        transfer_lamports(
+            &ctx.accounts.owner.to_account_info(),
+            &ctx.accounts.parlay.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            stake,
+        )?;
+
+        emit_cpi!(log_event(ParlayCreated {
+            owner: ctx.accounts.owner.key(),
+            parlay_id,
+            legs,
+            stake,
+            max_payout,
+        }));
+
+        Ok(())
+    }
+
+    /// Settle a parlay once all of its markets are resolved
+    /// Remaining accounts are each leg's Market and market vault, in leg
+    /// order. Voided legs drop out of the odds. If every other leg hit, the
+    /// owner receives stake * product(total_pool / winning_pool), capped at
+    /// max_payout, with winnings above the stake paid from the parlay
+    /// reserve. Otherwise the stake is added to the losing pools of the
+    /// legs' markets, split in proportion to their pots. Permissionless; the
+    /// parlay is closed to its owner
+    pub fn settle_parlay<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SettleParlay<'info>>,
+        parlay_id: u64,
+    ) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let parlay = &ctx.accounts.parlay;
+        let legs = &parlay.legs[..parlay.leg_count as usize];
+        require!(
+            ctx.remaining_accounts.len() == legs.len() * 2,
+            MarketError::InvalidParlay
+        );
+
+        // Non-voided legs, with their market, vault and outcome
+        let mut live = Vec::with_capacity(legs.len());
+        let mut won = true;
+        for (leg, pair) in legs.iter().zip(ctx.remaining_accounts.chunks(2)) {
+            let market = Account::<Market>::try_from(&pair[0])?;
+            require!(
+                market.version == MARKET_VERSION,
+                MarketError::UnsupportedAccountVersion
+            );
+            require!(market.market_id == leg.market_id, MarketError::InvalidParlay);
+            require!(market.resolved, MarketError::MarketNotResolved);
+            require!(!market.disputed, MarketError::DisputeOpen);
+            require!(now >= market.claims_open_at(), MarketError::ClaimWindowNotOpen);
+            let vault = Pubkey::create_program_address(
+                &[b"vault", leg.market_id.to_le_bytes().as_ref(), &[market.vault_bump]],
+                &crate::ID,
+            )
+            .map_err(|_| error!(MarketError::InvalidParlay))?;
+            require_keys_eq!(pair[1].key(), vault, MarketError::InvalidParlay);
+
+            if let Some(outcome) = market.outcome.winner() {
+                won &= outcome == leg.outcome;
+                live.push((market, &pair[1], outcome));
+            }
+        }
+
+        let stake = parlay.stake;
+        let max_payout = parlay.max_payout;
+        let reserve = &mut ctx.accounts.parlay_reserve;
+        reserve.committed = reserve.committed.saturating_sub(max_payout - stake);
+
+        let payout = if won {
+            let odds: Vec<(u64, u64)> = live
+                .iter()
+                .map(|(market, _, outcome)| {
+                    let pot = market.yes_pool.saturating_add(market.no_pool);
+                    (pot, if *outcome { market.yes_pool } else { market.no_pool })
+                })
+                .collect();
+            let payout = parlay_payout(stake, max_payout, &odds);
+
+            // The stake leaves escrow when the parlay closes to its owner
+            let winnings = payout - stake;
+            **reserve.to_account_info().try_borrow_mut_lamports()? -= winnings;
+            **ctx.accounts.owner.try_borrow_mut_lamports()? += winnings;
+            payout
+        } else {
+            let total_pot: u128 = live
+                .iter()
+                .map(|(market, _, _)| market.yes_pool as u128 + market.no_pool as u128)
+                .sum();
+            let parlay_info = ctx.accounts.parlay.to_account_info();
+            let mut remaining = stake;
+            let last = live.len() - 1;
+            for (i, (market, vault, outcome)) in live.iter_mut().enumerate() {
+                let pot = market.yes_pool as u128 + market.no_pool as u128;
+                let share = if i == last {
+                    remaining
+                } else {
+                    ((stake as u128) * pot / total_pot.max(1)) as u64
+                };
+                remaining -= share;
+
+                **parlay_info.try_borrow_mut_lamports()? -= share;
+                **vault.try_borrow_mut_lamports()? += share;
+                // The stake joins the losing pool after resolution, so it
+                // goes into the snapshot winners are paid from as well
+                if *outcome {
+                    market.no_pool = market.no_pool.saturating_add(share);
+                    market.resolution_snapshot.no_pool =
+                        market.resolution_snapshot.no_pool.saturating_add(share);
+                } else {
+                    market.yes_pool = market.yes_pool.saturating_add(share);
+                    market.resolution_snapshot.yes_pool =
+                        market.resolution_snapshot.yes_pool.saturating_add(share);
+                }
+                market.outstanding_entitlement = market.winner_entitlement(*outcome);
+                market.exit(&crate::ID)?;
+            }
+            0
+        };
+
+        emit_cpi!(log_event(ParlaySettled {
+            owner: ctx.accounts.owner.key(),
+            parlay_id,
+            won,
+            payout,
+        }));
+
+        Ok(())
+    }
+
+    /// Check that a market's vault covers its liabilities
+    /// Permissionless; fails with InsolventMarket otherwise. Builds with the
+    /// `solvency-checks` feature run the same check after every instruction
+    /// that moves market lamports, emitting InvariantViolation instead
+    pub fn assert_solvent(ctx: Context<AssertSolvent>, _market_id: u64) -> Result<()> {
+        if let Some(violation) = solvency_violation(
+            &ctx.accounts.market,
+            collateral_balance(
+                &ctx.accounts.market,
+                &ctx.accounts.market_vault,
+                &ctx.accounts.wsol_vault,
+            )?,
+        ) {
+            msg!(
+                "Vault holds {} lamports, liabilities are {}",
+                violation.vault_balance,
+                violation.liabilities
+            );
+            return err!(MarketError::InsolventMarket);
+        }
+        Ok(())
+    }
+
+    /// Record how far the vault's balance has drifted from the market's
+    /// liabilities. Permissionless. A shortfall above MAX_VAULT_DRIFT flags
+    /// the market for attention, which halts betting until the config admin
+    /// clears it; a surplus (e.g. from donations) is only recorded
+    pub fn reconcile_vault(ctx: Context<ReconcileVault>, market_id: u64) -> Result<()> {
+        require!(!ctx.accounts.market.wsol_vault, MarketError::LamportVaultRequired);
+        let vault = &ctx.accounts.market_vault;
+        let rent = Rent::get()?.minimum_balance(vault.data_len());
+        let vault_balance = vault.lamports().saturating_sub(rent);
+
+        let market = &mut ctx.accounts.market;
+        let liabilities = market.liabilities();
+        market.drift_lamports = (vault_balance as i64).saturating_sub(liabilities as i64);
+        market.last_reconciled_at = Clock::get()?.unix_timestamp;
+        if liabilities.saturating_sub(vault_balance) > MAX_VAULT_DRIFT {
+            market.needs_attention = true;
+        }
+
+        emit_cpi!(log_event(VaultReconciled {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            vault_balance,
+            liabilities,
+            drift_lamports: market.drift_lamports,
+            needs_attention: market.needs_attention,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin reopens betting on a market flagged by reconcile_vault
+    pub fn clear_attention(ctx: Context<ClearAttention>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.needs_attention, MarketError::NoAttentionNeeded);
+        market.needs_attention = false;
+
+        emit_cpi!(log_event(AttentionCleared {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            admin: ctx.accounts.admin.key(),
+            drift_lamports: market.drift_lamports,
+        }));
+
+        Ok(())
+    }
+
+    /// Mark a market that is still unresolved config.stale_after_secs past
+    /// betting close as stale, so frontends can tell it apart from live
+    /// ones. Resolving or voiding the market clears the flag.
+    /// Permissionless
+    pub fn flag_stale(ctx: Context<FlagStale>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(!market.stale, MarketError::AlreadyStale);
+        require!(
+            Clock::get()?.unix_timestamp
+                >= market
+                    .betting_close_time()
+                    .saturating_add(ctx.accounts.config.stale_after_secs),
+            MarketError::MarketNotStale
+        );
+        market.stale = true;
+
+        emit_cpi!(log_event(MarketFlaggedStale {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            last_activity_at: market.last_activity_at,
+        }));
+        if let Some(paid) = pay_keeper_tip(
+            &mut ctx.accounts.market,
+            &ctx.accounts.config,
+            KeeperAction::FlagStale,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.keeper.to_account_info(),
+        )? {
+            emit_cpi!(log_event(paid));
+        }
+
+        Ok(())
+    }
+
+    /// Take a resolved or voided market's deposits off its creator's open
+    /// liability, making room under config.max_open_liability for bets on
+    /// their other markets. Permissionless; runs once per market
+    pub fn release_creator_liability(
+        ctx: Context<ReleaseCreatorLiability>,
+        market_id: u64,
+    ) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.resolved, MarketError::MarketNotResolved);
+        let amount = market.creator_liability;
+        require!(amount > 0, MarketError::NoOpenLiability);
+        market.creator_liability = 0;
+
+        let stats = &mut ctx.accounts.creator_stats;
+        stats.open_liability = stats.open_liability.saturating_sub(amount);
+
+        emit_cpi!(log_event(CreatorLiabilityReleased {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            creator: stats.creator,
+            amount,
+            open_liability: stats.open_liability,
+        }));
+
+        Ok(())
+    }
+
+    /// Bid `amount` on `side` in a market's open auction. The bid is
+    /// escrowed in the vault until settle_open_auction fills every bid at
+    /// its side's clearing price. One bid per wallet, and its fill opens
+    /// the bidder's position, so claim it before betting here again
+    pub fn bid_open_auction(
+        ctx: Context<BidOpenAuction>,
+        market_id: u64,
+        side: bool, // true = YES, false = NO
+        amount: u64,
+    ) -> Result<()> {
+        require!(!ctx.accounts.config.paused, MarketError::ProtocolPaused);
+        require!(amount > 0, MarketError::InvalidAmount);
+        let market = &ctx.accounts.market;
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now < market.auction_end, MarketError::AuctionClosed);
+        require_not_self_excluded(&ctx.accounts.self_exclusion, now)?;
+
+        transfer_lamports(
+            &ctx.accounts.bidder.to_account_info(),
+            &ctx.accounts.market_vault.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            amount,
+        )?;
+
+        let bid = &mut ctx.accounts.auction_bid;
+        bid.market_id = market_id;
+        bid.bidder = ctx.accounts.bidder.key();
+        bid.side = side;
+        bid.amount = amount;
+        bid.placed_at = now;
+        bid.bump = ctx.bumps.auction_bid;
+
+        let market = &mut ctx.accounts.market;
+        if side {
+            market.auction_yes = market.auction_yes.saturating_add(amount);
+        } else {
+            market.auction_no = market.auction_no.saturating_add(amount);
+        }
+        market.last_activity_at = now;
+
+        emit_cpi!(log_event(OpenAuctionBid {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            bidder: bid.bidder,
+            side,
+            amount,
+            yes_total: market.auction_yes,
+            no_total: market.auction_no,
+        }));
+
+        Ok(())
+    }
+
+    /// Close a market's open auction once it has ended, filling both
+    /// sides' bids at once: each side is cleared against the opening pools
+    /// by clear_auction_side, so every bid on a side gets the same price
+    /// whatever its size or timing. Normal trading starts here.
+    /// Permissionless; must run before betting close
+    pub fn settle_open_auction(ctx: Context<SettleOpenAuction>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.auction_pending(), MarketError::NoPendingAuction);
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= market.auction_end, MarketError::AuctionActive);
+        require!(now < market.betting_close_time(), MarketError::MarketExpired);
+
+        let fee_bps = market.fee_bps.unwrap_or(ctx.accounts.config.trading_fee_bps);
+        let rounding = market.rounding_policy;
+        let (yes_fee, yes_tokens) =
+            clear_auction_side(market.auction_yes, market.yes_pool, fee_bps, rounding)?;
+        let (no_fee, no_tokens) =
+            clear_auction_side(market.auction_no, market.no_pool, fee_bps, rounding)?;
+        market.yes_pool = market.yes_pool.saturating_add(market.auction_yes - yes_fee);
+        market.no_pool = market.no_pool.saturating_add(market.auction_no - no_fee);
+        market.yes_tokens = market.yes_tokens.saturating_add(yes_tokens);
+        market.no_tokens = market.no_tokens.saturating_add(no_tokens);
+        market.yes_deposits = market.yes_deposits.saturating_add(market.auction_yes);
+        market.no_deposits = market.no_deposits.saturating_add(market.auction_no);
+        market.fee_collected = market.fee_collected.saturating_add(yes_fee + no_fee);
+        market.total_volume = market
+            .total_volume
+            .saturating_add(market.auction_yes)
+            .saturating_add(market.auction_no);
+        market.net_volume = market
+            .net_volume
+            .saturating_add(market.auction_yes - yes_fee)
+            .saturating_add(market.auction_no - no_fee);
+        market.sequence = market.sequence.saturating_add(1);
+        market.last_trade_at = now;
+        market.record_pool_checkpoint(now);
+        market.last_activity_at = now;
+        market.auction_yes_tokens = yes_tokens;
+        market.auction_no_tokens = no_tokens;
+        market.auction_settled = true;
+
+        emit_cpi!(log_event(OpenAuctionSettled {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            yes_total: market.auction_yes,
+            no_total: market.auction_no,
+            yes_tokens,
+            no_tokens,
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+            implied_yes_probability_bps: market.implied_yes_probability_bps(),
+        }));
+
+        Ok(())
+    }
+
+    /// Turn a settled auction bid into the bidder's position: its share of
+    /// the side's cleared tokens, at the side's clearing price. The bid
+    /// account closes to the bidder
+    pub fn claim_auction_allocation(
+        ctx: Context<ClaimAuctionAllocation>,
+        market_id: u64,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(market.auction_settled, MarketError::AuctionNotSettled);
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        let bidder = ctx.accounts.bidder.key();
+        let bid = &ctx.accounts.auction_bid;
+        let (side, amount) = (bid.side, bid.amount);
+        let tokens = if side {
+            auction_allocation(amount, market.auction_yes, market.auction_yes_tokens)
+        } else {
+            auction_allocation(amount, market.auction_no, market.auction_no_tokens)
+        };
+        require!(tokens > 0, MarketError::InsufficientOutput);
+        let entry_price_bps =
+            u64::try_from((amount as u128) * (BPS_DENOMINATOR as u128) / (tokens as u128))
+                .map_err(|_| error!(MarketError::MathOverflow))?;
+
+        let house = market.is_house(&bidder);
+        let prediction = &mut ctx.accounts.prediction_account;
+        prediction.version = PREDICTION_VERSION;
+        prediction.market_id = ctx.accounts.market.market_id;
+        prediction.predictor = bidder;
+        prediction.prediction_type = side;
+        prediction.amount_deposited = amount;
+        prediction.tokens_received = tokens;
+        prediction.created_at = now;
+        prediction.claimed = false;
+        prediction.referrer = Pubkey::default();
+        prediction.bump = ctx.bumps.prediction_account;
+        prediction.slot = clock.slot;
+        prediction.yes_pool_at_entry = market.yes_pool;
+        prediction.no_pool_at_entry = market.no_pool;
+        prediction.entry_price_bps = entry_price_bps;
+        prediction.entry_probability_bps =
+            side_probability_bps(market.yes_pool, market.no_pool, side);
+        prediction.trade_sequence = market.sequence;
+        prediction.cancel_refund = 0;
+        prediction.is_house = ReserveFlag(house);
+        ctx.accounts.market.open_prediction(prediction);
+
+        let full = admit_participant(
+            &mut ctx.accounts.market,
+            &ctx.accounts.user_market_index,
+            true,
+        )?;
+        if let Some(full) = full {
+            emit_cpi!(log_event(full));
+        }
+        index_position(
+            &mut ctx.accounts.user_market_index,
+            ctx.bumps.user_market_index,
+            &mut ctx.accounts.user_index_page,
+            ctx.bumps.user_index_page,
+            &ctx.accounts.user_profile,
+            market_id,
+            bidder,
+        );
+
+        let profile = &mut ctx.accounts.user_profile;
+        profile.init_if_new(bidder, ctx.bumps.user_profile);
+        profile.bets_placed = profile.bets_placed.saturating_add(1);
+        if !house {
+            profile.total_wagered = profile.total_wagered.saturating_add(amount);
+        }
+        emit_cpi!(log_event(profile.update_event()));
+
+        let epoch_stats = &mut ctx.accounts.epoch_stats;
+        epoch_stats.init_if_new(bidder, ctx.accounts.config.current_epoch, ctx.bumps.epoch_stats);
+        if !house {
+            epoch_stats.bets_placed = epoch_stats.bets_placed.saturating_add(1);
+            epoch_stats.total_wagered = epoch_stats.total_wagered.saturating_add(amount);
+        }
+
+        let market = &mut ctx.accounts.market;
+        market.trade_count = market.trade_count.saturating_add(1);
+        market.unique_bettors = market.unique_bettors.saturating_add(1);
+        market.add_position(side);
+
+        emit_cpi!(log_event(AuctionAllocationClaimed {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            bidder,
+            side,
+            amount,
+            tokens_received: tokens,
+            entry_price_bps,
+        }));
+
+        Ok(())
+    }
+
+    /// Take back a bid from an open auction that was never settled: the
+    /// market reached betting close or was resolved (or voided) first.
+    /// The bid account closes to the bidder
+    pub fn refund_auction_bid(ctx: Context<RefundAuctionBid>, market_id: u64) -> Result<()> {
+        let market = &mut ctx.accounts.market;
+        require!(market.auction_pending(), MarketError::AuctionNotRefundable);
+        let now = Clock::get()?.unix_timestamp;
+        require!(
+            market.resolved || now >= market.betting_close_time(),
+            MarketError::AuctionNotRefundable
+        );
+        let bid = &ctx.accounts.auction_bid;
+        let amount = bid.amount;
+        if bid.side {
+            market.auction_yes = market.auction_yes.saturating_sub(amount);
+        } else {
+            market.auction_no = market.auction_no.saturating_sub(amount);
+        }
+
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.bidder.try_borrow_mut_lamports()? += amount;
+
+        emit_cpi!(log_event(AuctionBidRefunded {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            bidder: ctx.accounts.bidder.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Move what's left in a resolved market's vault to the treasury once
+    /// the claim deadline has passed or every eligible claim has landed:
+    /// rounding remainders and pot no winner can claim. Collected fees,
+    /// unclaimed winner entitlements and unclaimed loser rebates stay
+    /// behind.
+    /// Permissionless; runs once per market
+    pub fn sweep_dust(ctx: Context<SweepDust>, market_id: u64) -> Result<()> {
+        require!(
+            ctx.accounts.config.treasury != Pubkey::default(),
+            MarketError::TreasuryNotSet
+        );
+        let market = &ctx.accounts.market;
+        require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+        require!(market.resolved, MarketError::MarketNotResolved);
+        require!(!market.disputed, MarketError::DisputeOpen);
+        let outcome = market.outcome.winner().ok_or(MarketError::InvalidOutcome)?;
+        // Once every winner has claimed, the deadline has nothing to protect
+        let deadline_passed = match market.claim_deadline() {
+            Some(deadline) => Clock::get()?.unix_timestamp > deadline,
+            None => false,
+        };
+        require!(
+            market.fully_settled || market.claim_deadline().is_some(),
+            MarketError::SweepNotAvailable
+        );
+        require!(
+            market.fully_settled || deadline_passed,
+            MarketError::ClaimPeriodActive
+        );
+        require!(market.dust_swept == 0, MarketError::DustAlreadySwept);
+        require!(market.idle_deployed == 0, MarketError::IdleFundsNotRecalled);
+
+        // Entitlement rounded up, as in liabilities(), so no winner is left
+        // short; nothing is owed once every winning token is redeemed
+        let vault = &ctx.accounts.market_vault;
+        let rent = Rent::get()?.minimum_balance(vault.data_len());
+        let entitlement = if market.unredeemed_tokens(outcome) == 0 {
+            market.outstanding_entitlement
+        } else {
+            market
+                .outstanding_entitlement
+                .max(market.winner_entitlement(outcome).saturating_add(1))
+        };
+        let amount = vault
+            .lamports()
+            .saturating_sub(rent)
+            .saturating_sub(market.fee_collected)
+            .saturating_sub(market.rebate_outstanding())
+            .saturating_sub(market.keeper_budget)
+            .saturating_sub(market.auction_escrow())
+            .saturating_sub(market.creator_tips)
+            .saturating_sub(entitlement)
+            .saturating_sub(market.bankroll_due());
+        require!(amount > 0, MarketError::NothingToSweep);
+
+        ctx.accounts.market.dust_swept = amount;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.treasury.try_borrow_mut_lamports()? += amount;
+
+        emit_cpi!(log_event(DustSwept {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            treasury: ctx.accounts.treasury.key(),
+            amount,
+        }));
+        // Claims still outstanding at the deadline are given up on
+        if let Some(settled) = mark_fully_settled(&mut ctx.accounts.market, deadline_passed) {
+            emit_cpi!(log_event(settled));
+        }
+
+        Ok(())
+    }
+
+    /// Move a resolved market's unclaimed winnings into the unclaimed
+    /// rewards pool once its claim window has closed. The amount is what
+    /// the unredeemed winning tokens are entitled to under the resolution
+    /// snapshot, capped at what the vault holds beyond rent, fees, rebates,
+    /// keeper budget, creator tips and the creator's seed.
+    /// Permissionless; runs once per market. The caller pays for the pool
+    /// account the first time
+    pub fn escheat_unclaimed(ctx: Context<EscheatUnclaimed>, market_id: u64) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+        require!(market.resolved, MarketError::MarketNotResolved);
+        require!(!market.disputed, MarketError::DisputeOpen);
+        let outcome = market.outcome.winner().ok_or(MarketError::InvalidOutcome)?;
+        let end = market.claim_window_end().ok_or(MarketError::EscheatNotAvailable)?;
+        require!(
+            Clock::get()?.unix_timestamp > end,
+            MarketError::ClaimWindowOpen
+        );
+        require!(market.escheated == 0, MarketError::AlreadyEscheated);
+        require!(market.idle_deployed == 0, MarketError::IdleFundsNotRecalled);
+
+        let vault = &ctx.accounts.market_vault;
+        let rent = Rent::get()?.minimum_balance(vault.data_len());
+        let available = vault
+            .lamports()
+            .saturating_sub(rent)
+            .saturating_sub(market.fee_collected)
+            .saturating_sub(market.rebate_outstanding())
+            .saturating_sub(market.keeper_budget)
+            .saturating_sub(market.auction_escrow())
+            .saturating_sub(market.creator_tips)
+            .saturating_sub(market.bankroll_due());
+        let amount = market.winner_entitlement(outcome).min(available);
+        require!(amount > 0, MarketError::NothingToSweep);
+
+        let market = &mut ctx.accounts.market;
+        market.escheated = amount;
+        market.outstanding_entitlement = 0;
+        if outcome {
+            market.yes_tokens = 0;
+        } else {
+            market.no_tokens = 0;
+        }
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.unclaimed_rewards_pool.to_account_info().try_borrow_mut_lamports()? += amount;
+
+        let to_rebates =
+            market.rounding_policy.payout_bps(amount, ctx.accounts.config.escheat_rebate_bps);
+        let pool = &mut ctx.accounts.unclaimed_rewards_pool;
+        pool.total_escheated = pool.total_escheated.saturating_add(amount);
+        pool.rebate_funds = pool.rebate_funds.saturating_add(to_rebates);
+        pool.bump = ctx.bumps.unclaimed_rewards_pool;
+
+        emit_cpi!(log_event(WinningsEscheated {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            amount,
+            to_rebates,
+            to_keeper_tips: amount - to_rebates,
+        }));
+        // Winners who never claimed are given up on
+        if let Some(settled) = mark_fully_settled(&mut ctx.accounts.market, true) {
+            emit_cpi!(log_event(settled));
+        }
+
+        Ok(())
+    }
+
+    /// Config admin spends the unclaimed rewards pool on an unresolved
+    /// market: `rebates` go into its loser rebate pot, paid out at
+    /// resolution alongside the fee share, and `keeper_tips` into its
+    /// keeper budget. Unused keeper budget is reclaimed to the pool, so the
+    /// market's budget must be the pool's alone
+    pub fn grant_unclaimed_rewards(
+        ctx: Context<GrantUnclaimedRewards>,
+        market_id: u64,
+        rebates: u64,
+        keeper_tips: u64,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let pool_key = ctx.accounts.unclaimed_rewards_pool.key();
+        require!(rebates > 0 || keeper_tips > 0, MarketError::InvalidAmount);
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+        require!(
+            keeper_tips == 0
+                || market.keeper_funder == Pubkey::default()
+                || market.keeper_funder == pool_key,
+            MarketError::Unauthorized
+        );
+
+        let pool_info = ctx.accounts.unclaimed_rewards_pool.to_account_info();
+        let rent = Rent::get()?.minimum_balance(pool_info.data_len());
+        let pool = &mut ctx.accounts.unclaimed_rewards_pool;
+        require!(
+            rebates <= pool.rebate_funds
+                && keeper_tips <= pool.keeper_funds(pool_info.lamports(), rent),
+            MarketError::InsufficientUnclaimedRewards
+        );
+        pool.rebate_funds -= rebates;
+        pool.total_granted = pool.total_granted.saturating_add(rebates + keeper_tips);
+        **pool_info.try_borrow_mut_lamports()? -= rebates + keeper_tips;
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? += rebates + keeper_tips;
+
+        let market = &mut ctx.accounts.market;
+        market.rebate_pot = market.rebate_pot.saturating_add(rebates);
+        if keeper_tips > 0 {
+            market.keeper_funder = pool_key;
+            market.keeper_budget = market.keeper_budget.saturating_add(keeper_tips);
+        }
+
+        emit_cpi!(log_event(UnclaimedRewardsGranted {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            rebates,
+            keeper_tips,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin deposits `amount` of an unresolved market's idle
+    /// collateral with the whitelisted yield adapter, into the adapter's
+    /// `adapter_position` account for the market. At most max_idle_bps of
+    /// the collateral may be out, and the vault keeps its liquid
+    /// liabilities. Remaining accounts are passed through to the adapter
+    pub fn deposit_idle<'info>(
+        ctx: Context<'_, '_, 'info, 'info, DepositIdle<'info>>,
+        market_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        let adapter = ctx.accounts.config.yield_adapter;
+        require!(amount > 0, MarketError::InvalidAmount);
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+        require!(!market.wsol_vault, MarketError::LamportVaultRequired);
+        require!(
+            market.idle_deployed == 0 || market.idle_adapter == adapter,
+            MarketError::InvalidYieldAdapter
+        );
+
+        let liquid = vault_lamports(&ctx.accounts.market_vault)?;
+        let collateral = liquid.saturating_add(market.idle_deployed);
+        let deployed = market.idle_deployed.saturating_add(amount);
+        let cap = market.rounding_policy.payout_bps(collateral, ctx.accounts.config.max_idle_bps);
+        require!(
+            deployed <= cap
+                && amount <= liquid
+                && liquid - amount >= market.liquid_liabilities(),
+            MarketError::IdleLimitExceeded
+        );
+
+        **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= amount;
+        **ctx.accounts.adapter_position.try_borrow_mut_lamports()? += amount;
+        invoke_yield_adapter(
+            YIELD_ADAPTER_DEPOSIT,
+            &ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.adapter_position,
+            &ctx.accounts.adapter_program,
+            ctx.remaining_accounts,
+            amount,
+        )?;
+
+        let market = &mut ctx.accounts.market;
+        market.idle_adapter = adapter;
+        market.idle_deployed = deployed;
+
+        emit_cpi!(log_event(IdleFundsDeposited {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            adapter,
+            amount,
+            idle_deployed: deployed,
+        }));
+
+        Ok(())
+    }
+
+    /// Recall `amount` of a market's deposited principal from its yield
+    /// adapter. Whatever the adapter returns above the principal is yield
+    /// and goes to the treasury. The config admin can recall at any time;
+    /// once the market is resolved or voided anyone can, so claims never
+    /// wait on the admin
+    pub fn withdraw_idle<'info>(
+        ctx: Context<'_, '_, 'info, 'info, WithdrawIdle<'info>>,
+        market_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        let market = &ctx.accounts.market;
+        require!(
+            ctx.accounts.authority.key() == ctx.accounts.config.admin || market.resolved,
+            MarketError::Unauthorized
+        );
+        require!(
+            amount > 0 && amount <= market.idle_deployed,
+            MarketError::InvalidAmount
+        );
+
+        let before = ctx.accounts.market_vault.lamports();
+        invoke_yield_adapter(
+            YIELD_ADAPTER_WITHDRAW,
+            &ctx.accounts.market,
+            &ctx.accounts.market_vault,
+            &ctx.accounts.adapter_position,
+            &ctx.accounts.adapter_program,
+            ctx.remaining_accounts,
+            amount,
+        )?;
+        // Measured, not trusted: the adapter must return the principal
+        let received = ctx.accounts.market_vault.lamports().saturating_sub(before);
+        require!(received >= amount, MarketError::YieldAdapterShortfall);
+
+        let yield_amount = received - amount;
+        if yield_amount > 0 {
+            require!(
+                ctx.accounts.config.treasury != Pubkey::default(),
+                MarketError::TreasuryNotSet
+            );
+            **ctx.accounts.market_vault.try_borrow_mut_lamports()? -= yield_amount;
+            **ctx.accounts.treasury.try_borrow_mut_lamports()? += yield_amount;
+        }
+
+        let market = &mut ctx.accounts.market;
+        market.idle_deployed -= amount;
+
+        emit_cpi!(log_event(IdleFundsWithdrawn {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            adapter: market.idle_adapter,
+            principal: amount,
+            yield_amount,
+            idle_deployed: market.idle_deployed,
+        }));
+
+        Ok(())
+    }
+
+    /// Config admin sets the reward token and per-market emission rate
+    /// The first call creates the reward vault for `reward_mint`
+    pub fn configure_rewards(ctx: Context<ConfigureRewards>, emission_rate: u64) -> Result<()> {
+        let rewards = &mut ctx.accounts.rewards_config;
+        rewards.reward_mint = ctx.accounts.reward_mint.key();
+        rewards.emission_rate = emission_rate;
+        rewards.bump = ctx.bumps.rewards_config;
+        rewards.vault_bump = ctx.bumps.reward_vault;
+
+        Ok(())
+    }
+
+    /// Config admin tops up the reward vault
+    pub fn fund_rewards(ctx: Context<FundRewards>, amount: u64) -> Result<()> {
+        require!(amount > 0, MarketError::InvalidAmount);
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.admin_token_account.to_account_info(),
+                    to: ctx.accounts.reward_vault.to_account_info(),
+                    authority: ctx.accounts.admin.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+
+        Ok(())
+    }
+
+    /// Enter (or resize) a market position in liquidity mining
+    /// Shares are the owner's deposit plus, for the creator, the market's
+    /// liquidity. Call again after adding to a bet to count the new stake
+    pub fn stake_incentives(ctx: Context<StakeIncentives>, market_id: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let market = &ctx.accounts.market;
+        require!(!market.resolved, MarketError::MarketAlreadyResolved);
+
+        let owner = ctx.accounts.owner.key();
+        let mut shares = ctx
+            .accounts
+            .prediction_account
+            .as_ref()
+            .map_or(0, |prediction| prediction.amount_deposited);
+        if owner == market.creator {
+            shares = shares.saturating_add(market.total_liquidity);
+        }
+        require!(shares > 0, MarketError::NoIncentiveShares);
+
+        let incentives = &mut ctx.accounts.market_incentives;
+        if incentives.last_accrued_at == 0 {
+            incentives.market_id = market_id;
+            incentives.last_accrued_at = now;
+            incentives.bump = ctx.bumps.market_incentives;
+        }
+        incentives.accrue(
+            &mut ctx.accounts.rewards_config,
+            ctx.accounts.reward_vault.amount,
+            now.min(market.incentives_end()),
+        );
+
+        let position = &mut ctx.accounts.position;
+        position.settle(incentives.acc_reward_per_share);
+        incentives.total_shares = incentives
+            .total_shares
+            .saturating_sub(position.shares)
+            .saturating_add(shares);
+        position.owner = owner;
+        position.market_id = market_id;
+        position.shares = shares;
+        position.reset_debt(incentives.acc_reward_per_share);
+        position.bump = ctx.bumps.position;
+
+        emit_cpi!(log_event(IncentivesStaked {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            owner,
+            shares,
+            total_shares: incentives.total_shares,
+        }));
+
+        Ok(())
+    }
+
+    /// Withdraw mined reward tokens for a market position
+    /// Rewards only accrue while the vault holds unowed tokens, so a claim
+    /// never asks for more than the vault has set aside
+    pub fn claim_incentives(ctx: Context<ClaimIncentives>, market_id: u64) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let rewards = &mut ctx.accounts.rewards_config;
+        let incentives = &mut ctx.accounts.market_incentives;
+        incentives.accrue(
+            rewards,
+            ctx.accounts.reward_vault.amount,
+            now.min(ctx.accounts.market.incentives_end()),
+        );
+
+        let position = &mut ctx.accounts.position;
+        position.settle(incentives.acc_reward_per_share);
+        let amount = position.unclaimed.min(rewards.total_owed);
+        require!(amount > 0, MarketError::NoReward);
+        position.unclaimed = 0;
+        rewards.total_owed -= amount;
+
+        let signer_seeds: &[&[&[u8]]] = &[&[b"rewards_config", &[rewards.bump]]];
+        token::transfer(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.reward_vault.to_account_info(),
+                    to: ctx.accounts.owner_token_account.to_account_info(),
+                    authority: rewards.to_account_info(),
+                },
+                signer_seeds,
+            ),
+            amount,
+        )?;
+
+        emit_cpi!(log_event(IncentivesClaimed {
+            market_id,
+            market: ctx.accounts.market.key(),
+            tenant: ctx.accounts.market.tenant,
+            event_seq: ctx.accounts.market.next_event_seq(),
+            owner: ctx.accounts.owner.key(),
+            amount,
+        }));
+
+        Ok(())
+    }
+
+    /// Upgrade a market account to the current layout version
+    /// Legacy (unversioned) accounts are grown to the current size, their
+    /// fields carried over and new fields defaulted. Version 1 accounts have
+    /// their outcome rewritten as a MarketOutcome, versions 1 and 2 get an
+    /// empty tenant, and versions 1 to 3 keep their token vault keys as
+    /// outcome mints only if they could be mints (see carried_outcome_mints).
+    /// Versions 1 to 4 have their filter fields moved ahead of the question.
+    /// Permissionless and idempotent; the caller pays the extra rent
+    pub fn migrate_market(ctx: Context<MigrateMarket>, market_id: u64) -> Result<()> {
+        let info = ctx.accounts.market.to_account_info();
+
+        // Versioned accounts only ever grow past the legacy size
+        if info.data_len() > 8 + LegacyMarket::INIT_SPACE {
+            let version = info.try_borrow_data()?[8];
+            require!(
+                (1..=MARKET_VERSION).contains(&version),
+                MarketError::UnsupportedAccountVersion
+            );
+
+            // Accounts created before fields outgrew the reserve are too
+            // short to deserialize. Zero-pad so the new fields read as
+            // None/0, then trim back to the current size
+            let current_len = info.data_len();
+            info.resize(current_len + Market::INIT_SPACE)?;
+            if version == 1 {
+                upgrade_outcome_v1(&info)?;
+            }
+            if version <= 2 {
+                upgrade_tenant_v2(&info)?;
+            }
+            if version <= 3 {
+                upgrade_outcome_mints_v3(&info, market_id)?;
+            }
+            if version <= 4 {
+                upgrade_filter_fields_v4(&info)?;
+            }
+            let mut market = Market::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+            let target_len = (Market::space(
+                market.outcome_mints.is_some(),
+                market.pool_history_len,
+            ) + market.description.len())
+            .max(current_len);
+            info.resize(target_len)?;
+            let missing_snapshot = market.resolved && market.resolution_snapshot.taken_at == 0;
+            if missing_snapshot {
+                market.resolution_snapshot =
+                    ResolutionSnapshot::capture(&market, 0, market.resolved_at);
+            }
+            // Markets from before collateral_decimals all hold SOL or wSOL
+            let missing_decimals = market.collateral_decimals == 0;
+            if missing_decimals {
+                market.collateral_decimals = SOL_DECIMALS;
+            }
+            // Version 5 accounts end at the reserve, and past it a shortened
+            // house_accounts list leaves stale bytes rather than zeros where
+            // generation and live_predictions now sit. Every position the
+            // market still counts may have its account open, so all of
+            // them hold close_market off; one already closed keeps it
+            // waiting rather than strand an open account's rent
+            let from_v5 = version <= 5;
+            if from_v5 {
+                market.version = 6;
+                market.generation = 0;
+                market.live_predictions = market.yes_positions.saturating_add(market.no_positions);
+            }
+            if missing_snapshot || missing_decimals || from_v5 {
+                market.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+            }
+
+            let rent = Rent::get()?.minimum_balance(target_len);
+            transfer_lamports(
+                &ctx.accounts.payer.to_account_info(),
+                &info,
+                &ctx.accounts.system_program.to_account_info(),
+                rent.saturating_sub(info.lamports()),
+            )?;
+            return Ok(());
+        }
+
+        let legacy = LegacyMarket::deserialize(&mut &info.try_borrow_data()?[8..])?;
+        let outcome_mints =
+            carried_outcome_mints(legacy.yes_token_vault, legacy.no_token_vault, market_id);
+        let new_len = Market::space(outcome_mints.is_some(), 0);
+
+        let rent = Rent::get()?.minimum_balance(new_len);
+        transfer_lamports(
+            &ctx.accounts.payer.to_account_info(),
+            &info,
+            &ctx.accounts.system_program.to_account_info(),
+            rent.saturating_sub(info.lamports()),
+        )?;
+        info.resize(new_len)?;
+
+        let (_, vault_bump) =
+            Pubkey::find_program_address(&[b"vault", market_id.to_le_bytes().as_ref()], &crate::ID);
+        let mut market = Market {
+            version: MARKET_VERSION,
+            market_id: legacy.market_id,
+            tenant: [0; 16],
+            question: legacy.question,
+            creator: legacy.creator,
+            created_at: legacy.created_at,
+            resolution_time: legacy.resolution_time,
+            yes_pool: legacy.yes_pool,
+            no_pool: legacy.no_pool,
+            total_liquidity: legacy.total_liquidity,
+            resolved: legacy.resolved,
+            outcome: MarketOutcome::from_legacy(legacy.resolved, legacy.outcome),
+            outcome_mints,
+            fee_collected: legacy.fee_collected,
+            total_volume: 0,
+            net_volume: 0,
+            generation: 0,
+            live_predictions: 0,
+            trade_count: 0,
+            unique_bettors: 0,
+            time_decay: false,
+            time_decay_floor_bps: 0,
+            sequence: 0,
+            yes_tokens: 0,
+            no_tokens: 0,
+            total_claimed: 0,
+            bump: ctx.bumps.market,
+            vault_bump,
+            description: String::new(),
+            series: None,
+            category: 0,
+            fee_bps: None,
+            oracle_key: None,
+            oracle_eth_address: None,
+            resolution_commitment: None,
+            resolution_committed_at: 0,
+            bet_reveal_window: 0,
+            price_oracle: None,
+            resolved_at: 0,
+            disputed: false,
+            claims_count: 0,
+            pending_owner: None,
+            correction_window_secs: 0,
+            outstanding_entitlement: 0,
+            last_reconciled_at: 0,
+            drift_lamports: 0,
+            needs_attention: false,
+            claim_period_secs: 0,
+            dust_swept: 0,
+            sponsored_amount: 0,
+            imbalance_rebate_bps: 0,
+            fee_curve: None,
+            fee_schedule: None,
+            freeze_before_close_secs: 0,
+            exits_during_freeze: true,
+            wsol_vault: false,
+            collateral_mint: Pubkey::default(),
+            collateral_vault: Pubkey::default(),
+            subjective: false,
+            cooloff_secs: 0,
+            early_exit_penalty_bps: 0,
+            last_trade_at: 0,
+            min_settle_delay: 0,
+            mode: MarketMode::Amm,
+            bankroll: 0,
+            locked_liability: 0,
+            yes_odds_bps: 0,
+            no_odds_bps: 0,
+            event_seq: 0,
+            winnings_fee_bps: 0,
+            resolvers: [Pubkey::default(); 3],
+            resolver_delays: [0; 3],
+            loser_rebate_bps: 0,
+            yes_deposits: 0,
+            no_deposits: 0,
+            rebate_pot: 0,
+            rebate_paid: 0,
+            last_activity_at: legacy.created_at,
+            stale: false,
+            keeper_budget: 0,
+            keeper_funder: Pubkey::default(),
+            dispute_window_secs: 0,
+            liability_creator: legacy.creator,
+            creator_liability: 0,
+            auction_end: 0,
+            auction_yes: 0,
+            auction_no: 0,
+            auction_yes_tokens: 0,
+            auction_no_tokens: 0,
+            auction_settled: false,
+            resolution_snapshot: ResolutionSnapshot::default(),
+            yes_positions: 0,
+            no_positions: 0,
+            eligible_claims: 0,
+            eligible_claimed: 0,
+            fully_settled: false,
+            max_move_per_trade_bps: 0,
+            max_move_per_window_bps: 0,
+            move_window_start: 0,
+            move_window_open_bps: 0,
+            claim_window_secs: 0,
+            escheated: 0,
+            payout_params: PayoutParams::default(),
+            idle_adapter: Pubkey::default(),
+            idle_deployed: 0,
+            max_bet: 0,
+            max_bet_ramp_secs: 0,
+            max_bet_ramp_start_bps: 0,
+            resolvers_active_at: 0,
+            parent: None,
+            parent_met: false,
+            compressed: false,
+            receipt_root: [0; 32],
+            receipt_count: 0,
+            reduce_only_window_secs: 0,
+            escrow_disputed_claims: false,
+            escrowed_claims: 0,
+            pool_history_len: 0,
+            pool_history_head: 0,
+            pool_history: Vec::new(),
+            collateral_decimals: SOL_DECIMALS,
+            resolution_program: None,
+            push_policy: PushPolicy::VoidOnPush,
+            max_participants: 0,
+            participants: 0,
+            rounding_dust_accrued: 0,
+            creator_tips: 0,
+            rounding_policy: RoundingPolicy::FavorPool,
+            house_accounts: Vec::new(),
+            _reserved: Reserved,
+        };
+        // Claims on a market resolved before migrating are paid from its
+        // pools as they stand
+        if market.resolved {
+            market.resolution_snapshot =
+                ResolutionSnapshot::capture(&market, 0, market.resolved_at);
+        }
+        market.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+
+        Ok(())
+    }
+
+    /// Grow a prediction created before its reserve to the current size.
+    /// Those accounts end where the reserve starts, so one whose note is
+    /// near MAX_NOTE_LEN has no room to write the fields past it back.
+    /// Permissionless and idempotent; the caller pays the extra rent
+    pub fn migrate_prediction(ctx: Context<MigratePrediction>, market_id: u64) -> Result<()> {
+        let _ = market_id;
+        let info = ctx.accounts.prediction_account.to_account_info();
+        let space = 8 + Prediction::INIT_SPACE;
+        if info.data_len() >= space {
+            return Ok(());
+        }
+
+        let rent = Rent::get()?.minimum_balance(space);
+        transfer_lamports(
+            &ctx.accounts.payer.to_account_info(),
+            &info,
+            &ctx.accounts.system_program.to_account_info(),
+            rent.saturating_sub(info.lamports()),
+        )?;
+        info.resize(space)?;
+
+        Ok(())
+    }
+}
+
+// ==================== HELPERS ====================
+
+/// Validates creation parameters and writes the initial market state
+/// Returns the MarketCreated event for the caller to emit. The question is
+/// moved into the event rather than cloned; the caller stores it on the
+/// market after emitting
+#[allow(clippy::too_many_arguments)]
+fn init_market(
+    market: &mut Account<Market>,
+    config: &Config,
+    creator: Pubkey,
+    outcome_mints: Option<OutcomeMints>,
+    market_id: u64,
+    question: String,
+    resolution_time: i64,
+    initial_liquidity: u64,
+    initial_yes_probability_bps: u16,
+    fee_bps: u16,
+    options: &MarketOptions,
+    series: Option<Pubkey>,
+    bump: u8,
+    vault_bump: u8,
+) -> Result<MarketCreated> {
+    require!(!config.paused, MarketError::ProtocolPaused);
+    require!(
+        (MIN_INITIAL_PROBABILITY_BPS..=MAX_INITIAL_PROBABILITY_BPS)
+            .contains(&initial_yes_probability_bps),
+        MarketError::InvalidInitialProbability
+    );
+    require!(
+        !question.is_empty() && question.len() <= MAX_QUESTION_LEN,
+        MarketError::InvalidQuestion
+    );
+    require!(
+        options.time_decay_floor_bps as u64 <= BPS_DENOMINATOR
+            && options.bet_reveal_window >= 0
+            && options.correction_window_secs >= 0
+            && options.claim_period_secs >= 0
+            && options.imbalance_rebate_bps as u64 <= BPS_DENOMINATOR
+            && options.freeze_before_close_secs.unwrap_or(0) >= 0
+            && options.cooloff_secs >= 0
+            && options.early_exit_penalty_bps as u64 <= BPS_DENOMINATOR
+            && options.winnings_fee_bps as u64 <= BPS_DENOMINATOR
+            && options.loser_rebate_bps as u64 <= BPS_DENOMINATOR
+            && (options.loser_rebate_bps == 0 || !options.wsol_vault)
+            && (options.keeper_budget == 0 || !options.wsol_vault)
+            && options.dispute_window_secs >= 0
+            && options.open_auction_secs >= 0
+            && options.max_move_per_trade_bps as u64 <= BPS_DENOMINATOR
+            && options.max_move_per_window_bps as u64 <= BPS_DENOMINATOR
+            && options.claim_window_secs >= 0
+            && (options.claim_window_secs == 0 || !options.wsol_vault)
+            && options.max_bet_ramp_secs >= 0
+            && (options.max_bet_ramp_secs == 0
+                || (options.max_bet > 0
+                    && options.max_bet_ramp_start_bps > 0
+                    && options.max_bet_ramp_start_bps as u64 <= BPS_DENOMINATOR))
+            && (!options.compressed
+                || (!options.wsol_vault
+                    && options.loser_rebate_bps == 0
+                    && options.bet_reveal_window == 0
+                    && options.max_participants == 0))
+            && options.reduce_only_window_secs >= 0
+            && (!options.escrow_disputed_claims || !options.wsol_vault)
+            && options.pool_history_len <= MAX_POOL_HISTORY
+            && options.resolution_program != Some(crate::ID),
+        MarketError::InvalidMarketOptions
+    );
+    let category = config.category_defaults(options.category);
+    if category.is_some_and(|c| c.requires_oracle) {
+        require!(
+            options.oracle_key.is_some()
+                || options.oracle_eth_address.is_some()
+                || options.price_oracle.is_some(),
+            MarketError::OracleRequired
+        );
+    }
+    if let Some(oracle) = &options.price_oracle {
+        require!(
+            oracle.max_confidence_bps as u64 <= BPS_DENOMINATOR,
+            MarketError::InvalidMarketOptions
+        );
+    }
+    if let Some(curve) = &options.fee_curve {
+        require!(
+            curve.cap_bps as u64 <= BPS_DENOMINATOR,
+            MarketError::InvalidMarketOptions
+        );
+    }
+    if let Some(schedule) = &options.fee_schedule {
+        require!(
+            schedule.end_fee_bps >= schedule.start_fee_bps
+                && schedule.end_fee_bps <= config.max_fee_bps,
+            MarketError::InvalidMarketOptions
+        );
+    }
+    // Bet commitments, TWAP sampling tips, cooling-off refunds and early exits
+    // move lamports through the vault PDA
+    require!(
+        !options.wsol_vault
+            || (options.bet_reveal_window == 0
+                && options.price_oracle.is_none()
+                && options.cooloff_secs == 0
+                && options.early_exit_penalty_bps == 0),
+        MarketError::LamportVaultRequired
+    );
+    // Fixed odds leave nothing for weights, rebates or a fee curve to act
+    // on, and no pool for a cancelled or exited bet to come out of
+    if options.mode == MarketMode::Bookmaker {
+        require!(
+            options.yes_odds_bps as u64 > BPS_DENOMINATOR
+                && options.no_odds_bps as u64 > BPS_DENOMINATOR,
+            MarketError::InvalidOdds
+        );
+        require!(
+            !options.time_decay
+                && options.imbalance_rebate_bps == 0
+                && options.fee_curve.is_none()
+                && options.bet_reveal_window == 0
+                && options.cooloff_secs == 0
+                && options.early_exit_penalty_bps == 0,
+            MarketError::InvalidMarketOptions
+        );
+    }
+    // A pari-mutuel stake is its share of the pot, unweighted
+    require!(
+        options.mode != MarketMode::Parimutuel || !options.time_decay,
+        MarketError::InvalidMarketOptions
+    );
+    let now = Clock::get()?.unix_timestamp;
+    require!(resolution_time > now, MarketError::InvalidResolutionTime);
+    // Auction bids are escrowed in the vault and cleared against the
+    // opening pools, with time left to trade once it settles
+    if options.open_auction_secs > 0 {
+        require!(
+            options.mode == MarketMode::Amm
+                && !options.wsol_vault
+                && options.bet_reveal_window == 0
+                && now.saturating_add(options.open_auction_secs) < resolution_time,
+            MarketError::InvalidMarketOptions
+        );
+    }
+
+    // Bound how soon and how far out a market may resolve
+    let duration = resolution_time.saturating_sub(now);
+    require!(
+        duration >= config.min_market_duration,
+        MarketError::ResolutionTooSoon
+    );
+    require!(
+        duration <= config.max_market_duration,
+        MarketError::ResolutionTooFar
+    );
+
+    market.version = MARKET_VERSION;
+    market.market_id = market_id;
+    // An explicit ID can be taken again once close_market frees it; the
+    // creation slot keeps the new market's prediction addresses apart from
+    // any left under the old one
+    market.generation = if market_id >= EXPLICIT_MARKET_ID_START {
+        Clock::get()?.slot.max(1)
+    } else {
+        0
+    };
+    market.tenant = options.tenant;
+    market.creator = creator;
+    market.created_at = now;
+    market.resolution_time = resolution_time;
+    market.mode = options.mode;
+    if options.mode != MarketMode::Amm {
+        // The seed is held for the creator (as the house's bankroll, for a
+        // bookmaker); the pools only hold stakes
+        market.yes_pool = 0;
+        market.no_pool = 0;
+        market.bankroll = initial_liquidity;
+    } else {
+        // The implied YES probability is the YES pool's share of the total
+        market.yes_pool =
+            options.rounding_policy.payout_bps(initial_liquidity, initial_yes_probability_bps);
+        market.no_pool = initial_liquidity - market.yes_pool;
+        market.bankroll = 0;
+    }
+    market.locked_liability = 0;
+    market.yes_odds_bps = options.yes_odds_bps;
+    market.no_odds_bps = options.no_odds_bps;
+    market.total_liquidity = initial_liquidity;
+    market.resolved = false;
+    market.outcome = MarketOutcome::Unresolved;
+    market.outcome_mints = outcome_mints;
+    market.fee_collected = 0;
+    market.total_volume = 0;
+    market.net_volume = 0;
+    market.trade_count = 0;
+    market.unique_bettors = 0;
+    market.time_decay = options.time_decay;
+    market.time_decay_floor_bps = options.time_decay_floor_bps;
+    market.sequence = 0;
+    market.yes_tokens = 0;
+    market.no_tokens = 0;
+    market.total_claimed = 0;
+    market.bump = bump;
+    market.vault_bump = vault_bump;
+    market.description = String::new();
+    market.series = series;
+    market.category = options.category;
+    market.fee_bps = match category {
+        Some(defaults) if fee_bps == 0 => market_fee(config, defaults.fee_bps)?,
+        _ => market_fee(config, fee_bps)?,
+    };
+    market.dispute_window_secs = match category {
+        Some(defaults) if options.dispute_window_secs == 0 => defaults.dispute_window_secs,
+        _ => options.dispute_window_secs,
+    };
+    market.oracle_key = options.oracle_key;
+    market.oracle_eth_address = options.oracle_eth_address;
+    market.resolution_commitment = None;
+    market.resolution_committed_at = 0;
+    market.bet_reveal_window = options.bet_reveal_window;
+    market.price_oracle = options.price_oracle.clone();
+    market.resolved_at = 0;
+    market.disputed = false;
+    market.claims_count = 0;
+    market.pending_owner = None;
+    market.correction_window_secs = options.correction_window_secs;
+    market.outstanding_entitlement = 0;
+    market.last_reconciled_at = 0;
+    market.drift_lamports = 0;
+    market.needs_attention = false;
+    market.claim_period_secs = options.claim_period_secs;
+    market.dust_swept = 0;
+    market.sponsored_amount = 0;
+    market.imbalance_rebate_bps = options.imbalance_rebate_bps;
+    market.fee_curve = options.fee_curve;
+    market.fee_schedule = options.fee_schedule;
+    market.freeze_before_close_secs = options
+        .freeze_before_close_secs
+        .unwrap_or(config.freeze_before_close_secs);
+    market.exits_during_freeze = options
+        .exits_during_freeze
+        .unwrap_or(config.exits_during_freeze);
+    market.wsol_vault = options.wsol_vault;
+    market.collateral_mint = Pubkey::default();
+    market.collateral_vault = Pubkey::default();
+    market.subjective = options.subjective;
+    market.cooloff_secs = options.cooloff_secs;
+    market.early_exit_penalty_bps = options.early_exit_penalty_bps;
+    market.winnings_fee_bps = options.winnings_fee_bps;
+    market.loser_rebate_bps = options.loser_rebate_bps;
+    market.keeper_budget = options.keeper_budget;
+    market.liability_creator = creator;
+    market.creator_liability = 0;
+    market.keeper_funder = if options.keeper_budget > 0 {
+        creator
+    } else {
+        Pubkey::default()
+    };
+    market.last_trade_at = 0;
+    market.last_activity_at = now;
+    market.stale = false;
+    market.auction_end = if options.open_auction_secs > 0 {
+        now.saturating_add(options.open_auction_secs)
+    } else {
+        0
+    };
+    market.auction_yes = 0;
+    market.auction_no = 0;
+    market.auction_yes_tokens = 0;
+    market.auction_no_tokens = 0;
+    market.auction_settled = false;
+    market.resolution_snapshot = ResolutionSnapshot::default();
+    market.yes_positions = 0;
+    market.no_positions = 0;
+    market.eligible_claims = 0;
+    market.eligible_claimed = 0;
+    market.fully_settled = false;
+    market.max_move_per_trade_bps = options.max_move_per_trade_bps;
+    market.max_move_per_window_bps = options.max_move_per_window_bps;
+    market.move_window_start = 0;
+    market.move_window_open_bps = 0;
+    market.claim_window_secs = options.claim_window_secs;
+    market.escheated = 0;
+    market.payout_params = PayoutParams::default();
+    market.idle_adapter = Pubkey::default();
+    market.idle_deployed = 0;
+    market.max_bet = options.max_bet;
+    market.max_bet_ramp_secs = options.max_bet_ramp_secs;
+    market.max_bet_ramp_start_bps = options.max_bet_ramp_start_bps;
+    market.resolvers_active_at = 0;
+    market.parent = options.parent;
+    market.parent_met = false;
+    market.compressed = options.compressed;
+    market.receipt_root = [0; 32];
+    market.receipt_count = 0;
+    market.reduce_only_window_secs = options.reduce_only_window_secs;
+    market.escrow_disputed_claims = options.escrow_disputed_claims;
+    market.escrowed_claims = 0;
+    market.pool_history_len = options.pool_history_len;
+    market.pool_history_head = 0;
+    market.pool_history = Vec::new();
+    market.collateral_decimals = SOL_DECIMALS;
+    market.resolution_program = options.resolution_program;
+    market.push_policy = options.push_policy;
+    market.max_participants = options.max_participants;
+    market.participants = 0;
+    market.rounding_dust_accrued = 0;
+    market.creator_tips = 0;
+    market.rounding_policy = options.rounding_policy;
+    market.house_accounts = Vec::new();
+    market.min_settle_delay = config.min_settle_delay;
+    market.event_seq = 0;
+
+    Ok(MarketCreated {
+        market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.next_event_seq(),
+        creator,
+        question,
+        resolution_time,
+        time_decay: options.time_decay,
+        time_decay_floor_bps: options.time_decay_floor_bps,
+        series,
+        initial_yes_probability_bps: market.implied_yes_probability_bps(),
+        creation_fee: 0,
+        mode: market.mode,
+        max_bet: market.max_bet,
+        max_bet_ramp_secs: market.max_bet_ramp_secs,
+        max_bet_ramp_start_bps: market.max_bet_ramp_start_bps,
+        parent: market.parent,
+        collateral_decimals: market.collateral_decimals,
+        push_policy: market.push_policy,
+        max_participants: market.max_participants,
+        rounding_policy: market.rounding_policy,
+    })
+}
+
+/// Lowercases a question, trims it and collapses runs of whitespace to a
+/// single space, so trivially different spellings compare equal
+pub fn normalize_question(question: &str) -> String {
+    question
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Question registry key: keccak(normalized question || resolution day)
+pub fn question_hash(question: &str, resolution_time: i64) -> [u8; 32] {
+    let bucket = resolution_time.div_euclid(QUESTION_BUCKET_SECS);
+    keccak::hashv(&[
+        normalize_question(question).as_bytes(),
+        &bucket.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Fingerprint of a settled market kept in its MarketArchive: keccak, in
+/// this order and little-endian, of market_id; generation; the outcome's
+/// variant index; ResolutionSnapshot::hash; the payout params' fields in
+/// declaration order, bools as one byte; fee_collected, rebate_pot,
+/// rebate_paid; then claims_count, eligible_claims, eligible_claimed,
+/// total_claimed, rounding_dust_accrued, dust_swept and escheated
+pub fn compute_archive_hash(market: &Market) -> [u8; 32] {
+    let params = &market.payout_params;
+    keccak::hashv(&[
+        &market.market_id.to_le_bytes(),
+        &market.generation.to_le_bytes(),
+        &[market.outcome as u8],
+        &market.resolution_snapshot.hash(),
+        &params.published_at.to_le_bytes(),
+        &[params.winning_side as u8, params.fixed_odds as u8],
+        &params.winning_pool.to_le_bytes(),
+        &params.total_pot.to_le_bytes(),
+        &params.winnings_fee_bps.to_le_bytes(),
+        &params.covered.to_le_bytes(),
+        &params.owed.to_le_bytes(),
+        &market.fee_collected.to_le_bytes(),
+        &market.rebate_pot.to_le_bytes(),
+        &market.rebate_paid.to_le_bytes(),
+        &market.claims_count.to_le_bytes(),
+        &market.eligible_claims.to_le_bytes(),
+        &market.eligible_claimed.to_le_bytes(),
+        &market.total_claimed.to_le_bytes(),
+        &market.rounding_dust_accrued.to_le_bytes(),
+        &market.dust_swept.to_le_bytes(),
+        &market.escheated.to_le_bytes(),
+    ])
+    .to_bytes()
+}
+
+/// Claims a question's registry entry for a new market. An entry already
+/// claimed makes the market a duplicate, allowed only when the config
+/// disables the check or its admin approves the market
+fn register_question(
+    registry: &mut QuestionRegistry,
+    bump: u8,
+    config: &Config,
+    duplicate_approver: &Option<Signer>,
+    market: Pubkey,
+    market_id: u64,
+) -> Result<()> {
+    if registry.market != Pubkey::default() {
+        require!(
+            config.allow_duplicate_markets || duplicate_approver.is_some(),
+            MarketError::DuplicateMarket
+        );
+        return Ok(());
+    }
+    registry.market = market;
+    registry.market_id = market_id;
+    registry.bump = bump;
+    Ok(())
+}
+
+/// Appends a market to the current registry page, rolling over to a fresh
+/// page (created via init_if_needed) once the previous one is full, and
+/// counts it against its tenant's quota
+fn register_market(
+    config: &mut Config,
+    page: &mut MarketRegistryPage,
+    page_bump: u8,
+    market: &Account<Market>,
+) -> Result<()> {
+    if market.tenant != [0; 16] {
+        let now = Clock::get()?.unix_timestamp;
+        config
+            .tenant_quota_mut(&market.tenant)
+            .ok_or(MarketError::UnknownTenant)?
+            .count_market(now)?;
+    }
+
+    let page_index = config.next_registry_page();
+    let slot = (config.registry_len % REGISTRY_PAGE_SIZE as u64) as usize;
+
+    page.page_index = page_index;
+    page.bump = page_bump;
+    page.markets[slot] = market.key();
+    page.count = (slot + 1) as u32;
+
+    config.registry_len = config
+        .registry_len
+        .checked_add(1)
+        .ok_or(MarketError::MarketIdOverflow)?;
+    config.registry_head = page_index;
+
+    Ok(())
+}
+
+/// Constant-product output `net * pool / (pool + net)` scaled by a time
+/// weight, computed in PRICE_SCALE fixed point. The AMM division keeps its
+/// remainder and the result is rounded once, at the end, by `rounding`.
+/// Rounded down it never exceeds the exact rational value and is at most
+/// one token below it (only when the exact value lies within 1e-9 above an
+/// integer)
+pub fn weighted_tokens_out(
+    net_amount: u64,
+    pool: u64,
+    weight_bps: u64,
+    rounding: RoundingPolicy,
+) -> Result<u64> {
+    let denominator = (pool as u128) + (net_amount as u128);
+    if denominator == 0 {
+        return Ok(0);
+    }
+    let numerator = (net_amount as u128) * (pool as u128);
+    let whole = numerator / denominator;
+    let fraction = (numerator % denominator) * PRICE_SCALE / denominator;
+
+    let fixed = whole
+        .checked_mul(PRICE_SCALE)
+        .and_then(|scaled| scaled.checked_add(fraction))
+        .and_then(|scaled| scaled.checked_mul(weight_bps as u128))
+        .ok_or(MarketError::MathOverflow)?;
+    let tokens = rounding.payout(fixed, (BPS_DENOMINATOR as u128) * PRICE_SCALE);
+    u64::try_from(tokens).map_err(|_| error!(MarketError::MathOverflow))
+}
+
+/// Clears one side of an open auction: the side's bids are charged the
+/// market fee and priced together against its opening pool at full
+/// weight, so every bidder pays the same price. Returns (fee, tokens)
+pub fn clear_auction_side(
+    total: u64,
+    pool: u64,
+    fee_bps: u16,
+    rounding: RoundingPolicy,
+) -> Result<(u64, u64)> {
+    let fee = rounding.charge_bps(total, fee_bps);
+    let tokens = weighted_tokens_out(total - fee, pool, BPS_DENOMINATOR, rounding)?;
+    Ok((fee, tokens))
+}
+
+/// A bid's pro-rata share of the tokens its side cleared for, rounded
+/// down so the shares never exceed the side's total
+pub fn auction_allocation(amount: u64, side_total: u64, side_tokens: u64) -> u64 {
+    if side_total == 0 {
+        return 0;
+    }
+    ((amount as u128) * (side_tokens as u128) / (side_total as u128)) as u64
+}
+
+/// Gross payout for claiming `tokens` winning tokens in one go, before
+/// the winnings fee: (tokens / winning_pool) * total_pot, rounded by the
+/// market's `rounding` policy, then any haircut. Pure, so clients can
+/// check every claim off-chain; a partial claim pays the difference of
+/// two floors, see partial_reward
+pub fn compute_payout(params: &PayoutParams, rounding: RoundingPolicy, tokens: u64) -> u64 {
+    params.haircut(params.entitlement(tokens, rounding)).0
+}
+
+/// Share of a full reward for redeeming tokens `claimed_before` up to
+/// `claimed_after` of a `tokens` position. Each share is the difference of
+/// two floors, so the shares of any split sum to exactly the full reward
+fn partial_reward(full: u64, tokens: u64, claimed_before: u64, claimed_after: u64) -> u64 {
+    if tokens == 0 {
+        return 0;
+    }
+    let upto = |claimed: u64| (full as u128) * (claimed as u128) / (tokens as u128);
+    (upto(claimed_after) - upto(claimed_before)) as u64
+}
+
+/// A losing stake's share of the rebate pot: stake / losing_deposits of
+/// it, rounded down
+fn loser_rebate(stake: u64, losing_deposits: u64, pot: u64) -> u64 {
+    if losing_deposits == 0 {
+        return 0;
+    }
+    ((stake as u128) * (pot as u128) / (losing_deposits as u128)) as u64
+}
+
+/// Scales a claim by available / outstanding when the vault can't cover
+/// every remaining winner. The ratio is unchanged by each payout, so all
+/// claimers take the same haircut. Returns the payout and the ratio in bps
+fn pro_rata_payout(entitled: u64, available: u64, outstanding: u64) -> (u64, u16) {
+    if available >= outstanding {
+        return (entitled, BPS_DENOMINATOR as u16);
+    }
+    let paid = (entitled as u128) * (available as u128) / (outstanding as u128);
+    let ratio = (available as u128) * (BPS_DENOMINATOR as u128) / (outstanding as u128);
+    (paid as u64, ratio as u16)
+}
+
+/// Adds a priced bet to the market's pools, outstanding tokens and stats
+/// The prediction PDA is always freshly created alongside, so every call
+/// is a new bettor on this market. Fails if the bet trips the market's
+/// circuit breaker
+#[allow(clippy::too_many_arguments)]
+fn apply_prediction(
+    market: &mut Market,
+    prediction_type: bool,
+    amount: u64,
+    net_amount: u64,
+    tokens: u64,
+    fee_kept: u64,
+    rebate: u64,
+    now: i64,
+) -> Result<()> {
+    let probability_before = market.implied_yes_probability_bps();
+    if prediction_type {
+        market.yes_pool = market.yes_pool.saturating_add(net_amount);
+        market.yes_tokens = market.yes_tokens.saturating_add(tokens);
+        market.yes_deposits = market.yes_deposits.saturating_add(amount);
+    } else {
+        market.no_pool = market.no_pool.saturating_add(net_amount);
+        market.no_tokens = market.no_tokens.saturating_add(tokens);
+        market.no_deposits = market.no_deposits.saturating_add(amount);
+    }
+    if market.mode == MarketMode::Bookmaker {
+        // The stake covers its own part of the payout; the bankroll the rest
+        market.locked_liability = market
+            .locked_liability
+            .saturating_add(tokens.saturating_sub(net_amount));
+    }
+    market.fee_collected = market
+        .fee_collected
+        .saturating_add(fee_kept)
+        .saturating_sub(rebate);
+
+    market.total_volume = market.total_volume.saturating_add(amount);
+    market.net_volume = market.net_volume.saturating_add(net_amount);
+    market.trade_count = market.trade_count.saturating_add(1);
+    market.unique_bettors = market.unique_bettors.saturating_add(1);
+    market.add_position(prediction_type);
+    market.sequence = market.sequence.saturating_add(1);
+    market.last_trade_at = now;
+    market.record_pool_checkpoint(now);
+    market.last_activity_at = now;
+
+    market.check_circuit_breaker(probability_before, now)
+}
+
+/// Counts a newly opened position in the user's per-market index and, on
+/// the user's first position in the market, appends the market to their
+/// paged market list. Must run before the profile's bets_placed is bumped,
+/// since that count picks the page
+fn index_position(
+    market_index: &mut UserMarketIndex,
+    market_index_bump: u8,
+    page: &mut UserIndexPage,
+    page_bump: u8,
+    profile: &UserProfile,
+    market_id: u64,
+    user: Pubkey,
+) {
+    if market_index.next_position_index == 0 {
+        let slot = profile.bets_placed as usize % USER_INDEX_PAGE_SIZE;
+        page.user = user;
+        page.page_index = profile.next_index_page();
+        page.market_ids[slot] = market_id;
+        page.count = (slot + 1) as u32;
+        page.bump = page_bump;
+    }
+    market_index.user = user;
+    market_index.market_id = market_id;
+    market_index.next_position_index = market_index.next_position_index.saturating_add(1);
+    market_index.open_positions = market_index.open_positions.saturating_add(1);
+    market_index.bump = market_index_bump;
+}
+
+/// Counts the wallet behind `market_index` as a participant when it opens
+/// its first position in the market, refusing it with MarketFull once
+/// max_participants wallets are in. `escrowed` stakes, taken before the
+/// market filled, are counted but never refused. Must run before
+/// index_position. Returns the event when this wallet takes the last place
+fn admit_participant(
+    market: &mut Account<Market>,
+    market_index: &UserMarketIndex,
+    escrowed: bool,
+) -> Result<Option<MarketFull>> {
+    if market_index.next_position_index > 0 {
+        return Ok(None);
+    }
+    let cap = market.max_participants;
+    require_bet(
+        cap == 0 || market.participants < cap || escrowed,
+        market.market_id,
+        MarketError::MarketFull,
+        cap as u64,
+        market.participants.saturating_add(1) as u64,
+    )?;
+    market.participants = market.participants.saturating_add(1);
+    if market.participants != cap {
+        return Ok(None);
+    }
+    Ok(Some(MarketFull {
+        market_id: market.market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.next_event_seq(),
+        participants: market.participants,
+    }))
+}
+
+/// What redeeming part of a winning prediction paid out
+pub struct Redemption {
+    pub reward: u64, // paid to the claimer, net of the winnings fee
+    pub gross_reward: u64,
+    pub winnings_fee: u64,
+    pub entitled: u64,
+    pub payout_bps: u16,
+    pub pnl_lamports: i64,
+    pub return_bps: i64,
+    pub first_claim: bool,
+    pub published: bool, // this claim fixed the market's payout params
+}
+
+/// Redeems `tokens_to_claim` of a winning prediction (None redeems the
+/// rest) and records the claim on the prediction and market. The first
+/// claim fixes the market's payout params, taking `available` as what the
+/// vault can pay; a vault short of what winners are owed then pays every
+/// claim the same fraction. The market's winnings fee is taken from the
+/// profit over the deposit behind the redeemed tokens and kept with its
+/// fees. The caller moves the net reward out of the vault; an `escrowed`
+/// claim, whose reward is held in an EscrowedClaim, may go ahead while a
+/// dispute is open
+fn redeem_winnings(
+    market: &mut Market,
+    prediction: &mut Prediction,
+    tokens_to_claim: Option<u64>,
+    available: u64,
+    now: i64,
+    escrowed: bool,
+) -> Result<Redemption> {
+    require!(market.resolved, MarketError::MarketNotResolved);
+    require!(!market.disputed || escrowed, MarketError::DisputeOpen);
+    require!(now >= market.claims_open_at(), MarketError::ClaimWindowNotOpen);
+    if let Some(end) = market.claim_window_end() {
+        require!(now <= end, MarketError::ClaimWindowExpired);
+    }
+    require!(!prediction.claimed, MarketError::AlreadyClaimed);
+
+    let outcome = market.outcome.winner().ok_or(MarketError::InvalidOutcome)?;
+
+    // Check if prediction was correct
+    let prediction_won = prediction.prediction_type == outcome;
+    require!(prediction_won, MarketError::PredictionLost);
+
+    let remaining = prediction
+        .tokens_received
+        .saturating_sub(prediction.tokens_claimed);
+    let tokens = tokens_to_claim.unwrap_or(remaining);
+    require!(tokens > 0 && tokens <= remaining, MarketError::InvalidAmount);
+    let first_claim = prediction.tokens_claimed == 0;
+
+    let published = market.payout_params.published_at == 0;
+    if published {
+        market.payout_params = PayoutParams::capture(market, outcome, available, now);
+    }
+    let params = market.payout_params;
+    let rounding = market.rounding_policy;
+    let entitled = partial_reward(
+        params.entitlement(prediction.tokens_received, rounding),
+        prediction.tokens_received,
+        prediction.tokens_claimed,
+        prediction.tokens_claimed + tokens,
+    );
+    require!(entitled > 0, MarketError::NoReward);
+
+    let (reward, payout_bps) = params.haircut(entitled);
+    require!(reward > 0, MarketError::NoReward);
+    let cost = partial_reward(
+        prediction.amount_deposited,
+        prediction.tokens_received,
+        prediction.tokens_claimed,
+        prediction.tokens_claimed + tokens,
+    );
+    // Only profit is charged, so a claim at or below break-even pays nothing
+    let winnings_fee = rounding.charge_bps(reward.saturating_sub(cost), params.winnings_fee_bps);
+    let (pnl_lamports, return_bps) = realized_pnl(reward - winnings_fee, cost);
+
+    prediction.tokens_claimed += tokens;
+    prediction.claimed = prediction.tokens_claimed == prediction.tokens_received;
+    if prediction.claimed {
+        market.eligible_claimed = market.eligible_claimed.saturating_add(1);
+    }
+
+    market.outstanding_entitlement = market.outstanding_entitlement.saturating_sub(entitled);
+    if outcome {
+        market.yes_tokens = market.yes_tokens.saturating_sub(tokens);
+    } else {
+        market.no_tokens = market.no_tokens.saturating_sub(tokens);
+    }
+    market.total_claimed = market.total_claimed.saturating_add(reward);
+    market.fee_collected = market.fee_collected.saturating_add(winnings_fee);
+    if first_claim {
+        market.claims_count = market.claims_count.saturating_add(1);
+    }
+
+    Ok(Redemption {
+        reward: reward - winnings_fee,
+        gross_reward: reward,
+        winnings_fee,
+        entitled,
+        payout_bps,
+        pnl_lamports,
+        return_bps,
+        first_claim,
+        published,
+    })
+}
+
+/// What a market's vault holds for winners: its collateral less fees,
+/// loser rebates, keeper budget, auction escrow and creator tips
+fn winner_funds(market: &Market, collateral: u64) -> u64 {
+    collateral
+        .saturating_sub(market.fee_collected)
+        .saturating_sub(market.rebate_outstanding())
+        .saturating_sub(market.keeper_budget)
+        .saturating_sub(market.auction_escrow())
+        .saturating_sub(market.creator_tips)
+}
+
+fn payout_params_published(market: &mut Account<Market>) -> PayoutParamsPublished {
+    PayoutParamsPublished {
+        market_id: market.market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.next_event_seq(),
+        params: market.payout_params,
+    }
+}
+
+/// Marks a resolved market fully settled once its last eligible claim has
+/// landed, or once `deadline_passed`, unless a dispute is open. Once every
+/// winning token is redeemed, what's left of the winner entitlement is
+/// what rounding each claim down left behind; it moves to
+/// rounding_dust_accrued for sweep_dust. Returns the event the first time
+fn mark_fully_settled(
+    market: &mut Account<Market>,
+    deadline_passed: bool,
+) -> Option<MarketFullySettled> {
+    if market.fully_settled
+        || market.disputed
+        || !(deadline_passed || market.eligible_claimed >= market.eligible_claims)
+    {
+        return None;
+    }
+    market.fully_settled = true;
+    if let Some(outcome) = market.outcome.winner() {
+        if market.unredeemed_tokens(outcome) == 0 {
+            market.rounding_dust_accrued = market
+                .rounding_dust_accrued
+                .saturating_add(market.outstanding_entitlement);
+            market.outstanding_entitlement = 0;
+        }
+    }
+    Some(MarketFullySettled {
+        market_id: market.market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.next_event_seq(),
+        eligible_claims: market.eligible_claims,
+        eligible_claimed: market.eligible_claimed,
+        claims_count: market.claims_count,
+        total_claimed: market.total_claimed,
+        rounding_dust: market.rounding_dust_accrued,
+        deadline_passed,
+    })
+}
+
+/// Takes a position out of the market for its deposit less the early
+/// exit penalty: the refund leaves the bettor's side of the pool with their
+/// tokens, so the penalty stays in the pot. Returns the EarlyExited event;
+/// the caller moves the refund out of the vault and emits it
+fn exit_position(
+    market: &mut Account<Market>,
+    prediction: &Prediction,
+    now: i64,
+) -> Result<EarlyExited> {
+    require!(market.early_exit_penalty_bps > 0, MarketError::EarlyExitDisabled);
+    require!(!market.resolved, MarketError::MarketAlreadyResolved);
+    require!(now < market.betting_close_time(), MarketError::MarketExpired);
+    require!(
+        market.exits_during_freeze || !market.betting_frozen(now),
+        MarketError::BettingFrozen
+    );
+    require!(
+        !prediction.claimed && prediction.tokens_claimed == 0,
+        MarketError::AlreadyClaimed
+    );
+
+    let penalty = market
+        .rounding_policy
+        .charge_bps(prediction.amount_deposited, market.early_exit_penalty_bps);
+    let refund = prediction.amount_deposited - penalty;
+    let side_pool = if prediction.prediction_type {
+        market.yes_pool
+    } else {
+        market.no_pool
+    };
+    require!(refund <= side_pool, MarketError::EarlyExitUnderfunded);
+    if prediction.prediction_type {
+        market.yes_pool -= refund;
+        market.yes_tokens = market.yes_tokens.saturating_sub(prediction.tokens_received);
+        market.yes_deposits = market.yes_deposits.saturating_sub(prediction.amount_deposited);
+    } else {
+        market.no_pool -= refund;
+        market.no_tokens = market.no_tokens.saturating_sub(prediction.tokens_received);
+        market.no_deposits = market.no_deposits.saturating_sub(prediction.amount_deposited);
+    }
+    // The deposit no longer counts towards refunds if the market is voided
+    market.total_volume = market.total_volume.saturating_sub(prediction.amount_deposited);
+    market.net_volume = market.net_volume.saturating_sub(refund);
+    market.remove_position(prediction.prediction_type);
+    market.sequence = market.sequence.saturating_add(1);
+    market.last_trade_at = now;
+    market.record_pool_checkpoint(now);
+    market.last_activity_at = now;
+
+    Ok(EarlyExited {
+        market_id: market.market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.next_event_seq(),
+        predictor: prediction.predictor,
+        refund,
+        penalty,
+        yes_pool: market.yes_pool,
+        no_pool: market.no_pool,
+        sequence: market.sequence,
+    })
+}
+
+/// Event for the caller to emit once settle_market has run
+pub enum Settlement {
+    Resolved(MarketResolved),
+    Voided(MarketVoided),
+}
+
+/// Marks an expired market resolved with `outcome`, or voids it when one
+/// side holds no tokens: with no counterparty, winners would only split
+/// their own stakes and the seed, and losers would lose everything
+/// Refuses while a resolution commitment awaits its reveal, and refuses an
+/// outcome the vault can't pay every winner of; the market stays open
+/// to resolution once top_up_vault has made up the shortfall. `evidence` is
+/// the resolver's justification, empty for oracle resolutions. Markets
+/// with a resolution_program settle only through resolve_via_program
+fn settle_market(
+    market: &mut Account<Market>,
+    vault: &AccountInfo,
+    wsol_vault: &Option<InterfaceAccount<token_interface::TokenAccount>>,
+    market_id: u64,
+    outcome: bool,
+    evidence: String,
+) -> Result<Settlement> {
+    require!(
+        market.resolution_program.is_none(),
+        MarketError::ResolutionProgramOnly
+    );
+    settle_outcome(market, vault, wsol_vault, market_id, Some(outcome), evidence)
+}
+
+/// settle_market without the resolution_program check, for
+/// resolve_via_program and resolve_push. A push `outcome` of None voids
+/// the market
+fn settle_outcome(
+    market: &mut Account<Market>,
+    vault: &AccountInfo,
+    wsol_vault: &Option<InterfaceAccount<token_interface::TokenAccount>>,
+    market_id: u64,
+    outcome: Option<bool>,
+    evidence: String,
+) -> Result<Settlement> {
+    require!(!market.resolved, MarketError::MarketAlreadyResolved);
+    require!(
+        market.resolution_commitment.is_none(),
+        MarketError::ResolutionPending
+    );
+    require!(
+        market.parent.is_none() || market.parent_met,
+        MarketError::ParentUnresolved
+    );
+    let now = Clock::get()?.unix_timestamp;
+    require!(now >= market.resolution_time, MarketError::MarketNotExpired);
+    // Nobody can bet on a result they've seen and settle in the same breath
+    require!(
+        now >= market.last_trade_at.saturating_add(market.min_settle_delay),
+        MarketError::SettleDelayActive
+    );
+
+    // The house is the counterparty of every bookmaker bet
+    if market.mode != MarketMode::Bookmaker && (market.yes_tokens == 0 || market.no_tokens == 0) {
+        void_market(market, now);
+        return Ok(Settlement::Voided(MarketVoided {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            reason: VoidReason::NoCounterparty,
+            refundable: market.liabilities(),
+        }));
+    }
+    let Some(outcome) = outcome else {
+        void_market(market, now);
+        return Ok(Settlement::Voided(MarketVoided {
+            market_id,
+            market: market.key(),
+            tenant: market.tenant,
+            event_seq: market.next_event_seq(),
+            reason: VoidReason::Push,
+            refundable: market.liabilities(),
+        }));
+    };
+
+    market.resolved = true;
+    market.outcome = outcome.into();
+    market.resolved_at = now;
+    market.stale = false;
+    // Set aside before anyone can collect the fees it comes from, on top
+    // of anything granted from the unclaimed rewards pool
+    let rebate_fees =
+        market.rounding_policy.payout_bps(market.fee_collected, market.loser_rebate_bps);
+    market.rebate_pot = market.rebate_pot.saturating_add(rebate_fees);
+    market.fee_collected -= rebate_fees;
+    let collateral = collateral_balance(market, vault, wsol_vault)?;
+    let vault_balance = if market.wsol_vault {
+        collateral
+    } else {
+        vault.lamports()
+    };
+    market.resolution_snapshot = ResolutionSnapshot::capture(market, vault_balance, now);
+    market.outstanding_entitlement = market.winner_entitlement(outcome);
+    market.reset_eligible_claims();
+    require!(
+        winner_funds(market, collateral) >= market.outstanding_entitlement,
+        MarketError::ResolutionWouldBeInsolvent
+    );
+
+    Ok(Settlement::Resolved(MarketResolved {
+        market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.next_event_seq(),
+        outcome: market.outcome,
+        yes_pool: market.yes_pool,
+        no_pool: market.no_pool,
+        implied_yes_probability_bps: market.implied_yes_probability_bps(),
+        sequence: market.sequence,
+        evidence,
+        resolver_tier: None,
+        rebate_pot: market.rebate_pot,
+        push: false,
+    }))
+}
+
+/// Inner node of a receipt tree: keccak(0x01 || left || right)
+pub fn receipt_node(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    keccak::hashv(&[&[1], left, right]).to_bytes()
+}
+
+/// Root of a receipt tree with no leaves
+pub fn empty_receipt_root() -> [u8; 32] {
+    (0..RECEIPT_TREE_DEPTH).fold([0; 32], |zero, _| receipt_node(&zero, &zero))
+}
+
+/// Root implied by `leaf` at `index` and its sibling path `proof`, leaf
+/// level first. None unless the proof spans the whole tree and the index
+/// fits in it, so no receipt can be proven at a second position
+pub fn receipt_root_from_proof(leaf: [u8; 32], index: u64, proof: &[[u8; 32]]) -> Option<[u8; 32]> {
+    if proof.len() != RECEIPT_TREE_DEPTH || index >= 1 << RECEIPT_TREE_DEPTH {
+        return None;
+    }
+    let root = proof.iter().enumerate().fold(leaf, |node, (level, sibling)| {
+        if (index >> level) & 1 == 0 {
+            receipt_node(&node, sibling)
+        } else {
+            receipt_node(sibling, &node)
+        }
+    });
+    Some(root)
+}
+
+/// Checks a conditional market's parent against `parent_market`, the
+/// account passed for it: it must be the named market, resolve before the
+/// child, and not already have ruled the condition out
+fn validate_parent(
+    parent: Option<ParentCondition>,
+    parent_market: &Option<Account<Market>>,
+    resolution_time: i64,
+) -> Result<()> {
+    let Some(condition) = parent else {
+        return Ok(());
+    };
+    let parent_market = parent_market
+        .as_ref()
+        .ok_or(MarketError::InvalidParentMarket)?;
+    require!(
+        parent_market.key() == condition.market && parent_market.resolution_time < resolution_time,
+        MarketError::InvalidParentMarket
+    );
+    require!(
+        !parent_market.resolved || parent_market.outcome.winner() == Some(condition.outcome),
+        MarketError::ParentConditionFailed
+    );
+    Ok(())
+}
+
+/// While creation is whitelisted, only creators holding a CreatorAllow
+/// (checked against the creator by its seeds) may create markets
+fn require_approved_creator(
+    config: &Config,
+    creator_allow: &Option<Account<CreatorAllow>>,
+) -> Result<()> {
+    require!(
+        config.creation_mode == CreationMode::Open || creator_allow.is_some(),
+        MarketError::CreatorNotApproved
+    );
+    Ok(())
+}
+
+/// Counts a new market against the creator's daily creation limit: the
+/// config's max_markets_per_day, or the creator's own if its approval
+/// carries a higher one. No limit while the config's is 0
+fn count_creator_market(
+    stats: &mut Account<CreatorStats>,
+    creator: Pubkey,
+    bump: u8,
+    config: &Config,
+    creator_allow: &Option<Account<CreatorAllow>>,
+) -> Result<()> {
+    stats.init_if_new(creator, bump);
+    let personal = creator_allow.as_ref().map_or(0, |allow| allow.max_markets_per_day);
+    let limit = match config.max_markets_per_day {
+        0 => 0,
+        max => max.max(personal),
+    };
+    stats.count_market(Clock::get()?.unix_timestamp, limit)
+}
+
+/// Moves the config's creation fee from the creator to the treasury,
+/// unless the creator's approval waives it. Checked up front against the
+/// seed liquidity so a creator who can't cover both fails cleanly.
+/// Returns the fee charged
+fn charge_creation_fee<'info>(
+    config: &Config,
+    creator_allow: &Option<Account<CreatorAllow>>,
+    treasury: &mut Option<Account<'info, Treasury>>,
+    creator: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    initial_liquidity: u64,
+) -> Result<u64> {
+    let waived = creator_allow.as_ref().is_some_and(|allow| allow.fee_waived);
+    let fee = if waived { 0 } else { config.creation_fee_lamports };
+    require!(
+        creator.lamports() >= initial_liquidity.saturating_add(fee),
+        MarketError::InsufficientCreatorFunds
+    );
+    if fee == 0 {
+        return Ok(0);
+    }
+
+    let treasury = treasury.as_mut().ok_or(MarketError::TreasuryNotSet)?;
+    require_keys_eq!(treasury.key(), config.treasury, MarketError::TreasuryNotSet);
+    transfer_lamports(creator, &treasury.to_account_info(), system_program, fee)?;
+    treasury.total_collected = treasury.total_collected.saturating_add(fee);
+    Ok(fee)
+}
+
+/// The tier `resolver` is listed in on the market, once that tier's
+/// window to resolve has opened
+fn open_resolver_tier(market: &Market, resolver: &Pubkey, now: i64) -> Result<usize> {
+    require!(now >= market.resolvers_active_at, MarketError::ResolversNotActive);
+    let tier = market
+        .resolver_tier(resolver)
+        .ok_or(MarketError::Unauthorized)?;
+    require!(
+        now >= market.resolution_time.saturating_add(market.resolver_delays[tier]),
+        MarketError::ResolverTierNotOpen
+    );
+    Ok(tier)
+}
+
+/// Records the creator's evidence for a manual resolution in the market's
+/// Resolution account. Subjective markets must give some
+fn record_resolution(
+    resolution: &mut Resolution,
+    bump: u8,
+    market: &Market,
+    resolver: Pubkey,
+    evidence: &str,
+) -> Result<()> {
+    require!(
+        evidence.len() <= MAX_EVIDENCE_LEN,
+        MarketError::EvidenceTooLong
+    );
+    require!(
+        !market.subjective || !evidence.trim().is_empty(),
+        MarketError::EvidenceRequired
+    );
+    resolution.market_id = market.market_id;
+    resolution.resolver = resolver;
+    resolution.evidence = evidence.to_string();
+    resolution.recorded_at = Clock::get()?.unix_timestamp;
+    resolution.bump = bump;
+    Ok(())
+}
+
+/// The outcome mints a market is created with: both or neither, and two
+/// different mints. The accounts' constraints check the mint authority
+fn outcome_mints(
+    yes_mint: &Option<Account<Mint>>,
+    no_mint: &Option<Account<Mint>>,
+) -> Result<Option<OutcomeMints>> {
+    match (yes_mint, no_mint) {
+        (None, None) => Ok(None),
+        (Some(yes), Some(no)) if yes.key() != no.key() => Ok(Some(OutcomeMints {
+            yes: yes.key(),
+            no: no.key(),
+        })),
+        _ => err!(MarketError::InvalidOutcomeMint),
+    }
+}
+
+/// The token vault keys a market created before version 4 stored unchecked,
+/// kept as its outcome mints only if they could be: two different keys,
+/// neither the default nor the market's vault, which clients passed for
+/// markets without outcome tokens. Kept mints are still checked wherever
+/// they're used
+fn carried_outcome_mints(yes: Pubkey, no: Pubkey, market_id: u64) -> Option<OutcomeMints> {
+    let (vault, _) =
+        Pubkey::find_program_address(&[b"vault", market_id.to_le_bytes().as_ref()], &crate::ID);
+    let placeholder = |key: Pubkey| key == Pubkey::default() || key == vault;
+    (yes != no && !placeholder(yes) && !placeholder(no)).then_some(OutcomeMints { yes, no })
+}
+
+/// Rewrites a version 3 market's yes_token_vault and no_token_vault as an
+/// Option<OutcomeMints> in place and marks the account version 4. Dropped
+/// keys leave a None tag and everything after moves down 63 bytes; kept
+/// ones gain a Some tag and everything moves up a byte into the zero
+/// padding migrate_market adds first
+fn upgrade_outcome_mints_v3(info: &AccountInfo, market_id: u64) -> Result<()> {
+    let mut data = info.try_borrow_mut_data()?;
+    let len = data.len();
+    let question_at = MARKET_TENANT_OFFSET + 16;
+    let question_len = read_legacy::<u32>(&data, question_at)? as usize;
+    // question, creator, created_at, resolution_time, yes_pool, no_pool,
+    // total_liquidity, resolved, outcome
+    let mints_at = question_at + 4 + question_len + 32 + 8 * 5 + 1 + 1;
+    let yes = read_legacy::<Pubkey>(&data, mints_at)?;
+    let no = read_legacy::<Pubkey>(&data, mints_at + 32)?;
+
+    if carried_outcome_mints(yes, no, market_id).is_some() {
+        data.copy_within(mints_at..len - 1, mints_at + 1);
+        data[mints_at] = 1;
+    } else {
+        data.copy_within(mints_at + 64.., mints_at + 1);
+        data[mints_at] = 0;
+        data[len - 63..].fill(0);
+    }
+    data[8] = 4;
+    Ok(())
+}
+
+/// Moves a version 4 market's creator, resolution_time, resolved, outcome
+/// and category ahead of its question and marks the account version 5.
+/// The fields in between shift along; the account keeps its length
+fn upgrade_filter_fields_v4(info: &AccountInfo) -> Result<()> {
+    let mut data = info.try_borrow_mut_data()?;
+    let read_len = |data: &[u8], at: usize| read_legacy::<u32>(data, at).map(|len| len as usize);
+    let question_at = MARKET_CREATOR_OFFSET;
+    let creator_at = question_at + 4 + read_len(&data, question_at)?;
+    // creator, created_at
+    let resolution_time_at = creator_at + 32 + 8;
+    // resolution_time, yes_pool, no_pool, total_liquidity
+    let resolved_at = resolution_time_at + 8 * 4;
+    let mints_at = resolved_at + 2;
+    let mints_len = if read_legacy::<u8>(&data, mints_at)? == 0 {
+        1
+    } else {
+        1 + OutcomeMints::INIT_SPACE
+    };
+    // outcome_mints, fee_collected, total_volume, trade_count,
+    // unique_bettors, time_decay, time_decay_floor_bps, sequence,
+    // yes_tokens, no_tokens, total_claimed, bump, vault_bump
+    let description_at = mints_at + mints_len + 8 + 8 + 4 + 4 + 1 + 2 + 8 * 4 + 1 + 1;
+    let series_at = description_at + 4 + read_len(&data, description_at)?;
+    let series_len = if read_legacy::<u8>(&data, series_at)? == 0 { 1 } else { 1 + 32 };
+    let category_at = series_at + series_len;
+    // Every offset above comes before the category, so reading it bounds
+    // the slices below
+    let category = read_legacy::<u8>(&data, category_at)?;
+
+    let mut moved = Vec::with_capacity(category_at + 1 - question_at);
+    moved.extend_from_slice(&data[creator_at..creator_at + 32]);
+    moved.extend_from_slice(&data[resolution_time_at..resolution_time_at + 8]);
+    moved.extend_from_slice(&data[resolved_at..resolved_at + 2]);
+    moved.push(category);
+    moved.extend_from_slice(&data[question_at..creator_at]);
+    moved.extend_from_slice(&data[creator_at + 32..resolution_time_at]);
+    moved.extend_from_slice(&data[resolution_time_at + 8..resolved_at]);
+    moved.extend_from_slice(&data[mints_at..category_at]);
+    data[question_at..=category_at].copy_from_slice(&moved);
+    data[8] = 5;
+    Ok(())
+}
+
+/// Reads a `T` at `at` in a legacy account's data, failing with
+/// MalformedLegacyAccount where the account is too short to hold it
+fn read_legacy<T: AnchorDeserialize>(data: &[u8], at: usize) -> Result<T> {
+    let mut bytes = data.get(at..).ok_or(MarketError::MalformedLegacyAccount)?;
+    T::deserialize(&mut bytes).map_err(|_| error!(MarketError::MalformedLegacyAccount))
+}
+
+/// Rewrites a version 1 market's Option<bool> outcome as a MarketOutcome
+/// in place and marks the account version 2. Some(_) took two bytes where
+/// the enum takes one, so everything after it moves down a byte and the
+/// account ends in one more byte of padding
+fn upgrade_outcome_v1(info: &AccountInfo) -> Result<()> {
+    let mut data = info.try_borrow_mut_data()?;
+    let question_len = read_legacy::<u32>(&data, 17)? as usize;
+    // discriminator, version, market_id, question, creator, created_at,
+    // resolution_time, yes_pool, no_pool, total_liquidity
+    let resolved_at = 8 + 1 + 8 + 4 + question_len + 32 + 8 * 5;
+    let outcome_at = resolved_at + 1;
+    let resolved = read_legacy::<u8>(&data, resolved_at)? != 0;
+    let legacy = read_legacy::<Option<bool>>(&data, outcome_at)?;
+    let outcome = MarketOutcome::from_legacy(resolved, legacy);
+
+    if legacy.is_some() {
+        let len = data.len();
+        data.copy_within(outcome_at + 2.., outcome_at + 1);
+        data[len - 1] = 0;
+    }
+    outcome.serialize(&mut &mut data[outcome_at..outcome_at + 1])?;
+    data[8] = 2;
+    Ok(())
+}
+
+/// Inserts an empty tenant after a version 2 market's market_id and marks
+/// the account version 3. Everything after it moves up 16 bytes into the
+/// zero padding migrate_market adds first
+fn upgrade_tenant_v2(info: &AccountInfo) -> Result<()> {
+    let mut data = info.try_borrow_mut_data()?;
+    let len = data.len();
+    let tenant_end = MARKET_TENANT_OFFSET + 16;
+    data.copy_within(MARKET_TENANT_OFFSET..len - 16, tenant_end);
+    data[MARKET_TENANT_OFFSET..tenant_end].fill(0);
+    data[8] = 3;
+    Ok(())
+}
+
+/// Applies new config parameters directly, as allowed while the config
+/// timelock is off
+fn update_config_now(config: &mut Config, params: &ConfigParams) -> Result<ConfigUpdated> {
+    params.validate()?;
+    require!(config.config_delay_secs == 0, MarketError::TimelockRequired);
+    params.apply(config);
+
+    Ok(ConfigUpdated {
+        admin: config.admin,
+        min_market_duration: config.min_market_duration,
+        max_market_duration: config.max_market_duration,
+    })
+}
+
+/// Halts market creation and betting
+fn pause(config: &mut Config) -> Result<ProtocolPaused> {
+    require!(!config.paused, MarketError::ProtocolPaused);
+    config.paused = true;
+    config.paused_at = Clock::get()?.unix_timestamp;
+
+    Ok(ProtocolPaused {
+        admin: config.admin,
+    })
+}
+
+/// Voids an unresolved market on the admin's authority; see
+/// emergency_cancel_market
+fn cancel_market(
+    market: &mut Account<Market>,
+    admin: Pubkey,
+    reason: String,
+) -> Result<MarketCancelled> {
+    require!(
+        reason.len() <= MAX_CANCEL_REASON_LEN,
+        MarketError::InvalidCancelReason
+    );
+    require!(!market.resolved, MarketError::MarketAlreadyResolved);
+
+    void_market(market, Clock::get()?.unix_timestamp);
+
+    Ok(MarketCancelled {
+        market_id: market.market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.next_event_seq(),
+        admin,
+        reason,
+        refundable: market.liabilities(),
+        trade_count: market.trade_count,
+    })
+}
+
+/// Marks a resolved, losing prediction settled and counts the loss on its
+/// owner's profile, unless it was a house bet. Returns the profile's
+/// update event
+fn settle_loss(
+    market: &mut Market,
+    prediction: &mut Prediction,
+    profile: &mut UserProfile,
+    profile_bump: u8,
+) -> Result<ProfileUpdated> {
+    require!(market.resolved, MarketError::MarketNotResolved);
+    require!(!market.disputed, MarketError::DisputeOpen);
+    require!(
+        Clock::get()?.unix_timestamp >= market.claims_open_at(),
+        MarketError::ClaimWindowNotOpen
+    );
+    require!(!prediction.claimed, MarketError::AlreadyClaimed);
+
+    let outcome = market.outcome.winner().ok_or(MarketError::InvalidOutcome)?;
+    require!(prediction.prediction_type != outcome, MarketError::PredictionWon);
+
+    prediction.claimed = true;
+    market.claims_count = market.claims_count.saturating_add(1);
+
+    profile.init_if_new(prediction.predictor, profile_bump);
+    if !prediction.is_house.0 {
+        profile.markets_lost = profile.markets_lost.saturating_add(1);
+    }
+    Ok(profile.update_event())
+}
+
+/// Pays the keeper the config's tip for `action` out of the market's keeper
+/// budget, or what is left of it. Only call once the instruction has
+/// changed state, so no-op calls can't drain the budget
+fn pay_keeper_tip(
+    market: &mut Account<Market>,
+    config: &Config,
+    action: KeeperAction,
+    market_vault: &AccountInfo,
+    keeper: &AccountInfo,
+) -> Result<Option<KeeperTipPaid>> {
+    let tip = config.keeper_tips[action as usize].min(market.keeper_budget);
+    if tip == 0 {
+        return Ok(None);
+    }
+    market.keeper_budget -= tip;
+    **market_vault.try_borrow_mut_lamports()? -= tip;
+    **keeper.try_borrow_mut_lamports()? += tip;
+
+    Ok(Some(KeeperTipPaid {
+        market_id: market.market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.next_event_seq(),
+        keeper: keeper.key(),
+        action,
+        tip,
+        budget_left: market.keeper_budget,
+    }))
+}
+
+/// Resolves a market with no outcome, making every deposit, the seed and
+/// sponsorships refundable. Collected fees are given up to fund the refunds;
+/// any shortfall left by fees withdrawn earlier, or by referral and
+/// insurance cuts that never reached the vault, is the admin's to cover
+fn void_market(market: &mut Market, now: i64) {
+    market.resolved = true;
+    market.outcome = MarketOutcome::Void;
+    market.resolved_at = now;
+    market.stale = false;
+    market.resolution_commitment = None;
+    market.fee_collected = 0;
+    market.reset_eligible_claims();
+}
+
+/// Writes the dispute's final outcome to the market and releases its claims
+/// The bond goes back to the raiser if the outcome changed; otherwise it is
+/// forfeited to the market's collected fees
+fn finalize_dispute<'info>(
+    market: &mut Account<'info, Market>,
+    dispute: &mut Account<'info, Dispute>,
+    market_vault: &AccountInfo<'info>,
+    raiser: &AccountInfo<'info>,
+    outcome: bool,
+) -> Result<DisputeSettled> {
+    let overturned = outcome != dispute.original_outcome;
+    **dispute.to_account_info().try_borrow_mut_lamports()? -= dispute.bond;
+    if overturned {
+        **raiser.try_borrow_mut_lamports()? += dispute.bond;
+    } else {
+        **market_vault.try_borrow_mut_lamports()? += dispute.bond;
+        market.fee_collected = market.fee_collected.saturating_add(dispute.bond);
+    }
+
+    // Claims escrowed during the dispute count if the outcome stands, and
+    // are owed back to the vault if it doesn't
+    let escrow_claimed = market.eligible_claimed;
+    market.outcome = outcome.into();
+    market.outstanding_entitlement = market.winner_entitlement(outcome);
+    market.reset_eligible_claims();
+    if !overturned {
+        market.eligible_claimed = escrow_claimed;
+        market.escrowed_claims = 0;
+    }
+    market.payout_params = PayoutParams::default();
+    market.disputed = false;
+    dispute.status = DisputeStatus::Settled;
+
+    Ok(DisputeSettled {
+        market_id: dispute.market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.next_event_seq(),
+        outcome: market.outcome,
+        overturned,
+        yes_votes: dispute.yes_votes,
+        no_votes: dispute.no_votes,
+    })
+}
+
+/// Returns the feed's price if the print is usable: taken at or after
+/// `not_before`, no older than max_price_age_secs, and with a confidence
+/// interval within max_confidence_bps of the price
+fn checked_oracle_price(
+    feed: &PriceFeed,
+    oracle: &PriceOracle,
+    not_before: i64,
+    now: i64,
+) -> Result<i64> {
+    require!(
+        feed.publish_time >= not_before
+            && now.saturating_sub(feed.publish_time) <= oracle.max_price_age_secs as i64,
+        MarketError::StaleOracle
+    );
+    let max_confidence = feed.price.unsigned_abs() as u128 * oracle.max_confidence_bps as u128
+        / BPS_DENOMINATOR as u128;
+    require!(
+        feed.confidence as u128 <= max_confidence,
+        MarketError::LowConfidence
+    );
+    Ok(feed.price)
+}
+
+/// Oracle attestation message:
+/// market_id (LE) || outcome (u8) || timestamp (LE) || expires_at (LE)
+pub fn attestation_message(
+    market_id: u64,
+    outcome: bool,
+    timestamp: i64,
+    expires_at: i64,
+) -> [u8; ATTESTATION_MESSAGE_LEN] {
+    let mut message = [0u8; ATTESTATION_MESSAGE_LEN];
+    message[..8].copy_from_slice(&market_id.to_le_bytes());
+    message[8] = outcome as u8;
+    message[9..17].copy_from_slice(&timestamp.to_le_bytes());
+    message[17..].copy_from_slice(&expires_at.to_le_bytes());
+    message
+}
+
+/// Attestation record key: keccak(oracle || message)
+pub fn attestation_hash(oracle: &Pubkey, message: &[u8]) -> [u8; 32] {
+    keccak::hashv(&[oracle.as_ref(), message]).to_bytes()
+}
+
+/// Checks that the instruction before the current one is an ed25519
+/// program instruction verifying exactly one signature by `signer` over
+/// exactly `message`, with all offsets pointing into its own data
+fn verify_ed25519_attestation(
+    instructions: &AccountInfo,
+    signer: &Pubkey,
+    message: &[u8],
+) -> Result<()> {
+    let (_, ix) = preceding_instruction(instructions, &ed25519_program::ID)?;
+
+    // Layout: count (u8), padding (u8), then one 14-byte offsets record
+    let data = &ix.data;
+    require!(data.len() >= 16 && data[0] == 1, MarketError::InvalidAttestation);
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let pubkey_offset = read_u16(6) as usize;
+    let message_offset = read_u16(10) as usize;
+    let message_size = read_u16(12) as usize;
+    // u16::MAX means "this instruction"; anything else could point the
+    // precompile at data we never inspect
+    require!(
+        read_u16(4) == u16::MAX && read_u16(8) == u16::MAX && read_u16(14) == u16::MAX,
+        MarketError::InvalidAttestation
+    );
+
+    let signed_by = data
+        .get(pubkey_offset..pubkey_offset + 32)
+        .ok_or(MarketError::InvalidAttestation)?;
+    let signed = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(MarketError::InvalidAttestation)?;
+    require!(
+        signed_by == signer.as_ref() && signed == message,
+        MarketError::InvalidAttestation
+    );
+    Ok(())
+}
+
+/// Checks that the instruction before the current one is a secp256k1
+/// program instruction recovering exactly one signature by `eth_address`
+/// over exactly `message`, with all offsets pointing into its own data
+fn verify_secp256k1_attestation(
+    instructions: &AccountInfo,
+    eth_address: &[u8; 20],
+    message: &[u8],
+) -> Result<()> {
+    let (index, ix) = preceding_instruction(instructions, &secp256k1_program::ID)?;
+
+    // Layout: count (u8), then one 11-byte offsets record whose
+    // instruction indices are absolute, so they must name this instruction
+    let data = &ix.data;
+    require!(data.len() >= 12 && data[0] == 1, MarketError::InvalidAttestation);
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let address_offset = read_u16(4) as usize;
+    let message_offset = read_u16(7) as usize;
+    let message_size = read_u16(9) as usize;
+    let own_index = u8::try_from(index).map_err(|_| MarketError::InvalidAttestation)?;
+    require!(
+        data[3] == own_index && data[6] == own_index && data[11] == own_index,
+        MarketError::InvalidAttestation
+    );
+
+    let recovered = data
+        .get(address_offset..address_offset + 20)
+        .ok_or(MarketError::InvalidAttestation)?;
+    let signed = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(MarketError::InvalidAttestation)?;
+    require!(
+        recovered == eth_address.as_slice() && signed == message,
+        MarketError::InvalidAttestation
+    );
+    Ok(())
+}
+
+/// Loads the instruction just before the current one, requiring it to be
+/// an account-less call to `program_id` (a signature-verification precompile)
+fn preceding_instruction(
+    instructions: &AccountInfo,
+    program_id: &Pubkey,
+) -> Result<(usize, Instruction)> {
+    let current = load_current_index_checked(instructions)? as usize;
+    require!(current > 0, MarketError::InvalidAttestation);
+    let ix = load_instruction_at_checked(current - 1, instructions)?;
+    require!(
+        ix.program_id == *program_id && ix.accounts.is_empty(),
+        MarketError::InvalidAttestation
+    );
+    Ok((current - 1, ix))
+}
+
+/// Requires the current instruction to be a CPI made directly by
+/// `program_id`: the top-level instruction calls it, and this program runs
+/// one level below
+fn require_cpi_from(instructions: &AccountInfo, program_id: &Pubkey) -> Result<()> {
+    let current = load_current_index_checked(instructions)? as usize;
+    let top_level = load_instruction_at_checked(current, instructions)?;
+    require!(
+        top_level.program_id == *program_id
+            && get_stack_height() == TRANSACTION_LEVEL_STACK_HEIGHT + 1,
+        MarketError::NotResolutionProgram
+    );
+    Ok(())
+}
+
+/// Whether a top-level instruction after the current one calls this
+/// program with the given discriminator
+fn later_instruction_calls(instructions: &AccountInfo, discriminator: &[u8]) -> Result<bool> {
+    let mut index = load_current_index_checked(instructions)? as usize + 1;
+    while let Ok(ix) = load_instruction_at_checked(index, instructions) {
+        if ix.program_id == crate::ID && ix.data.starts_with(discriminator) {
+            return Ok(true);
+        }
+        index += 1;
+    }
+    Ok(false)
+}
+
+/// Fills `{0}`..`{N}` placeholders in a template question
+fn render_question(format: &str, args: &[String]) -> Result<String> {
+    require!(args.len() <= MAX_TEMPLATE_ARGS, MarketError::InvalidTemplate);
+    let mut question = format.to_string();
+    for (i, arg) in args.iter().enumerate() {
+        question = question.replace(&format!("{{{}}}", i), arg);
+    }
+    Ok(question)
+}
+
+/// Counts a new market into its creator's series, if one was passed
+/// Returns the series key to store on the market
+fn join_series(
+    series: &mut Option<Account<MarketSeries>>,
+    creator: &Pubkey,
+) -> Result<Option<Pubkey>> {
+    let Some(series) = series else {
+        return Ok(None);
+    };
+    require_keys_eq!(series.creator, *creator, MarketError::Unauthorized);
+    series.member_count = series.member_count.saturating_add(1);
+    Ok(Some(series.key()))
+}
+
+/// Scales `amount` of a collateral with `decimals` decimals to
+/// NORMALIZED_DECIMALS, so amounts of different collaterals compare
+/// directly. Scaling down rounds down; scaling up saturates at u64::MAX
+pub fn normalized_amount(amount: u64, decimals: u8) -> u64 {
+    rescale_decimals(amount, decimals, NORMALIZED_DECIMALS)
+}
+
+/// Inverse of normalized_amount. Exact for collateral of at most
+/// NORMALIZED_DECIMALS decimals whose normalized amount didn't saturate
+pub fn denormalized_amount(normalized: u64, decimals: u8) -> u64 {
+    rescale_decimals(normalized, NORMALIZED_DECIMALS, decimals)
+}
+
+fn rescale_decimals(amount: u64, from: u8, to: u8) -> u64 {
+    let factor = |exponent: u8| 10u128.checked_pow(exponent as u32).unwrap_or(u128::MAX);
+    let scaled = if to >= from {
+        (amount as u128).saturating_mul(factor(to - from))
+    } else {
+        (amount as u128) / factor(from - to)
+    };
+    scaled.min(u64::MAX as u128) as u64
+}
+
+/// `numerator / denominator` rounded to the nearest integer, ties to even
+pub fn div_half_even(numerator: u128, denominator: u128) -> u128 {
+    let (quotient, remainder) = (numerator / denominator, numerator % denominator);
+    let above_half = remainder > denominator - remainder;
+    let tie_to_odd = remainder == denominator - remainder && quotient % 2 == 1;
+    if above_half || tie_to_odd {
+        quotient + 1
+    } else {
+        quotient
+    }
+}
+
+/// Fails a bet with `error` unless `ok`, first logging BetRejected with the
+/// bound it ran into and the bet's value against it
+fn require_bet(
+    ok: bool,
+    market_id: u64,
+    error: MarketError,
+    limit: u64,
+    provided: u64,
+) -> Result<()> {
+    if ok {
+        return Ok(());
+    }
+    emit!(BetRejected {
+        market_id,
+        error_code: error.into(),
+        limit,
+        provided,
+    });
+    Err(error!(error))
+}
+
+/// Ends a dry run once the instruction has done everything a real one
+/// would: logs DryRunResult, then fails with DryRunComplete so the runtime
+/// discards every change, including accounts created and lamports moved.
+/// Simulate the transaction and read the result from its logs
+fn dry_run_complete(result: DryRunResult) -> Result<()> {
+    emit!(result);
+    err!(MarketError::DryRunComplete)
+}
+
+/// Prices a prediction: trading fee, referral cut and AMM output
+/// Shared by place_prediction and quote_prediction so a quote always
+/// matches execution. Validates the market is open and the output nonzero
+#[allow(clippy::too_many_arguments)]
+fn price_prediction(
+    market: &Market,
+    config: &Config,
+    predictor: &Pubkey,
+    referrer: Option<&Referrer>,
+    discounted: bool,
+    prediction_type: bool,
+    amount: u64,
+    now: i64,
+) -> Result<PredictionQuote> {
+    let market_id = market.market_id;
+    require_bet(amount > 0, market_id, MarketError::BetAmountZero, 1, 0)?;
+    require!(!market.resolved, MarketError::MarketAlreadyResolved);
+    require_bet(
+        now < market.resolution_time,
+        market_id,
+        MarketError::MarketExpired,
+        market.resolution_time as u64,
+        now as u64,
+    )?;
+    require!(!market.betting_frozen(now), MarketError::BettingFrozen);
+    require!(!market.auction_pending(), MarketError::OpenAuctionPending);
+    // House accounts balance the book with bets of any size
+    if let Some(cap) = market.max_bet_at(now).filter(|_| !market.is_house(predictor)) {
+        require_bet(amount <= cap, market_id, MarketError::BetAboveCap, cap, amount)?;
+    }
+
+    // Trading fee, part of which may go to the bettor's referrer
+    let rounding = market.rounding_policy;
+    let fee_bps = effective_fee_bps(market, config, prediction_type, amount, now);
+    let mut fee = rounding.charge_bps(amount, fee_bps);
+    if discounted {
+        fee = fee.saturating_sub(rounding.payout_bps(fee, config.discount_bps));
+    }
+    let referral_fee = match referrer {
+        Some(referrer) => {
+            require!(referrer.owner != *predictor, MarketError::SelfReferral);
+            rounding.payout_bps(fee, config.referral_fee_bps)
+        }
+        None => 0,
+    };
+    let insurance_fee = rounding.payout_bps(fee - referral_fee, config.insurance_bps);
+    let net_amount = amount.saturating_sub(fee);
+
+    let weight_bps = market.time_weight_bps(now);
+    let tokens_out = if market.mode == MarketMode::Bookmaker {
+        // Fixed payout, as long as the bankroll can cover its share of it
+        let odds_bps = if prediction_type {
+            market.yes_odds_bps
+        } else {
+            market.no_odds_bps
+        };
+        let payout = u64::try_from(rounding.payout(
+            (net_amount as u128) * (odds_bps as u128),
+            BPS_DENOMINATOR as u128,
+        ))
+        .map_err(|_| error!(MarketError::MathOverflow))?;
+        let exposure = market
+            .locked_liability
+            .saturating_add(payout.saturating_sub(net_amount));
+        require_bet(
+            exposure <= market.bankroll,
+            market_id,
+            MarketError::BankrollExceeded,
+            market.bankroll,
+            exposure,
+        )?;
+        payout
+    } else if market.mode == MarketMode::Parimutuel {
+        // One token per lamport added to the pool
+        net_amount
+    } else {
+        // Calculate tokens to mint using constant product formula (x * y = k),
+        // with early bettors getting full weight, decaying to the floor at close
+        let pool = if prediction_type { market.yes_pool } else { market.no_pool };
+        weighted_tokens_out(net_amount, pool, weight_bps, rounding)?
+    };
+
+    require!(tokens_out > 0, MarketError::InsufficientOutput);
+
+    let effective_price_bps =
+        u64::try_from((amount as u128) * (BPS_DENOMINATOR as u128) / (tokens_out as u128))
+            .map_err(|_| error!(MarketError::MathOverflow))?;
+
+    Ok(PredictionQuote {
+        tokens_out,
+        effective_price_bps,
+        fee,
+        referral_fee,
+        insurance_fee,
+        net_amount,
+        weight_bps,
+        fee_bps,
+        price_impact_bps: price_impact_bps(market, prediction_type, net_amount),
+        rebate: imbalance_rebate(
+            market,
+            prediction_type,
+            net_amount,
+            fee - referral_fee - insurance_fee,
+        ),
+    })
+}
+
+/// Implied probability of YES (bps) for the given pools
+pub fn implied_yes_probability_bps(yes_pool: u64, no_pool: u64) -> u16 {
+    let total = (yes_pool as u128) + (no_pool as u128);
+    if total == 0 {
+        return (BPS_DENOMINATOR / 2) as u16;
+    }
+    ((yes_pool as u128) * (BPS_DENOMINATOR as u128) / total) as u16
+}
+
+/// Implied probability (bps) of one side, from the pools it was priced on
+fn side_probability_bps(yes_pool: u64, no_pool: u64, prediction_type: bool) -> u16 {
+    let yes = implied_yes_probability_bps(yes_pool, no_pool);
+    if prediction_type {
+        yes
+    } else {
+        BPS_DENOMINATOR as u16 - yes
+    }
+}
+
+/// Prediction PDA seed for `generation`: empty for 0, its bytes otherwise.
+/// Seeds computed by a call, like this one, are borrowed as slices in
+/// account constraints so IDL resolution leaves them to the client rather
+/// than mistaking them for the account's key
+fn generation_seed(generation: u64) -> Vec<u8> {
+    match generation {
+        0 => Vec::new(),
+        generation => generation.to_le_bytes().to_vec(),
+    }
+}
+
+/// The wallet's position and its market from one compute_exposure pair,
+/// or None unless the prediction is the wallet's position PDA on that
+/// market and both accounts are current program accounts
+fn exposure_position<'info>(
+    wallet: &Pubkey,
+    prediction: &'info AccountInfo<'info>,
+    market: &'info AccountInfo<'info>,
+) -> Option<(Account<'info, Prediction>, Account<'info, Market>)> {
+    let prediction = Account::<Prediction>::try_from(prediction).ok()?;
+    let market = Account::<Market>::try_from(market).ok()?;
+    let market_id = prediction.market_id.to_le_bytes();
+    let prediction_address = Pubkey::create_program_address(
+        &[
+            b"prediction",
+            &market_id,
+            wallet.as_ref(),
+            &prediction.position_seed(),
+            &prediction.generation_seed(),
+            &[prediction.bump],
+        ],
+        &crate::ID,
+    )
+    .ok()?;
+    let market_address =
+        Pubkey::create_program_address(&[b"market", &market_id, &[market.bump]], &crate::ID)
+            .ok()?;
+    let valid = prediction.predictor == *wallet
+        && prediction.version <= PREDICTION_VERSION
+        && prediction.key() == prediction_address
+        && prediction.generation.0 == market.generation
+        && market.version == MARKET_VERSION
+        && market.market_id == prediction.market_id
+        && market.key() == market_address;
+    valid.then_some((prediction, market))
+}
+
+/// Profit of a payout against its cost basis, in lamports and as a
+/// return on the cost in bps (-10_000 is a total loss)
+fn realized_pnl(payout: u64, cost: u64) -> (i64, i64) {
+    let pnl = payout as i128 - cost as i128;
+    let return_bps = match cost {
+        0 => 0,
+        cost => pnl * BPS_DENOMINATOR as i128 / cost as i128,
+    };
+    (
+        pnl.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+        return_bps.clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+    )
+}
+
+/// How far (bps) a bet moves the implied YES probability
+fn price_impact_bps(market: &Market, prediction_type: bool, net_amount: u64) -> u16 {
+    // Neither fixed odds nor pari-mutuel shares are priced off the pools
+    if market.mode != MarketMode::Amm {
+        return 0;
+    }
+    let (yes, no) = if prediction_type {
+        (market.yes_pool.saturating_add(net_amount), market.no_pool)
+    } else {
+        (market.yes_pool, market.no_pool.saturating_add(net_amount))
+    };
+    market
+        .implied_yes_probability_bps()
+        .abs_diff(implied_yes_probability_bps(yes, no))
+}
+
+/// Fee rate (bps, before any NFT discount) charged on a bet
+/// The base fee is the market's schedule at `now`, or its flat fee. On a
+/// fee-curve market, k times the imbalance left once the full amount joins
+/// its side is added to it
+/// A creator-chosen trading fee, checked against the config's bounds
+/// Zero leaves the market on config.trading_fee_bps
+fn market_fee(config: &Config, fee_bps: u16) -> Result<Option<u16>> {
+    if fee_bps == 0 {
+        return Ok(None);
+    }
+    require!(
+        (config.min_fee_bps..=config.max_fee_bps).contains(&fee_bps),
+        MarketError::FeeOutOfBounds
+    );
+    Ok(Some(fee_bps))
+}
+
+fn effective_fee_bps(
+    market: &Market,
+    config: &Config,
+    prediction_type: bool,
+    amount: u64,
+    now: i64,
+) -> u16 {
+    let base = match market.fee_schedule {
+        Some(schedule) => {
+            schedule.fee_bps_at(market.created_at, market.betting_close_time(), now)
+        }
+        None => market.fee_bps.unwrap_or(config.trading_fee_bps),
+    };
+    let Some(curve) = market.fee_curve else {
+        return base;
+    };
+    let (yes, no) = if prediction_type {
+        (market.yes_pool.saturating_add(amount), market.no_pool)
+    } else {
+        (market.yes_pool, market.no_pool.saturating_add(amount))
+    };
+    let total = yes as u128 + no as u128;
+    let imbalance_bps = (yes.abs_diff(no) as u128 * BPS_DENOMINATOR as u128)
+        .checked_div(total)
+        .unwrap_or(0);
+    let fee = base as u128 + curve.k_bps as u128 * imbalance_bps / BPS_DENOMINATOR as u128;
+    fee.min(curve.cap_bps.max(base) as u128) as u16
+}
+
+/// Fee rebate for a bet that narrows the gap between the pools
+/// imbalance_rebate_bps of the fee the market keeps, scaled by how much of
+/// the bet went to closing the gap: a bet that overshoots earns less, and
+/// one that widens the gap earns nothing. Paid from fees the market has
+/// already kept
+fn imbalance_rebate(
+    market: &Market,
+    prediction_type: bool,
+    net_amount: u64,
+    fee_kept: u64,
+) -> u64 {
+    if market.imbalance_rebate_bps == 0 || net_amount == 0 {
+        return 0;
+    }
+    let before = market.yes_pool.abs_diff(market.no_pool);
+    let after = if prediction_type {
+        market.yes_pool.saturating_add(net_amount).abs_diff(market.no_pool)
+    } else {
+        market.no_pool.saturating_add(net_amount).abs_diff(market.yes_pool)
+    };
+    let reduction = before.saturating_sub(after);
+    let rebate = market.rounding_policy.payout_bps(fee_kept, market.imbalance_rebate_bps);
+    let rebate = (rebate as u128) * (reduction as u128) / (net_amount as u128);
+    (rebate as u64).min(market.fee_collected)
+}
+
+/// Credits a fill's market-maker rebate, `fee` times the maker's
+/// rebate_bps, to `account`, which must be the registration PDA of the
+/// order's owner. Most owners aren't market makers, so an uncreated
+/// registration rebates nothing. Returns the rebate, which the taker then
+/// pays into the account
+fn credit_mm_rebate(
+    account: &AccountInfo,
+    maker: &Pubkey,
+    fee: u64,
+    rounding: RoundingPolicy,
+) -> Result<u64> {
+    let (address, _) =
+        Pubkey::find_program_address(&[b"market_maker", maker.as_ref()], &crate::ID);
+    require_keys_eq!(account.key(), address, MarketError::MarketMakerMismatch);
+    if account.owner != &crate::ID || account.data_is_empty() {
+        return Ok(0);
+    }
+    let mut registration =
+        MarketMakerRegistration::try_deserialize(&mut &account.try_borrow_data()?[..])?;
+    let rebate = rounding.payout_bps(fee, registration.rebate_bps);
+    registration.rebates_accrued = registration.rebates_accrued.saturating_add(rebate);
+    registration.try_serialize(&mut &mut account.try_borrow_mut_data()?[..])?;
+    Ok(rebate)
+}
+
+/// Checks shared by every way of placing a bet, pooled or compressed: the
+/// protocol and market are taking bets, the market isn't commit-reveal
+/// only, and the bettor isn't self-excluded. The max_bet ramp is applied
+/// by price_prediction
+fn require_open_to_bets(
+    config: &Config,
+    market: &Market,
+    self_exclusion: &AccountInfo,
+    now: i64,
+) -> Result<()> {
+    require!(!config.paused, MarketError::ProtocolPaused);
+    require!(!market.needs_attention, MarketError::NeedsAttention);
+    require!(market.bet_reveal_window == 0, MarketError::CommitRevealRequired);
+    require_not_self_excluded(self_exclusion, now)
+}
+
+/// Fails while `account`, a user's self-exclusion PDA, holds an exclusion
+/// that hasn't expired. Most users never create one, so an empty or
+/// foreign-owned account excludes nothing
+fn require_not_self_excluded(account: &AccountInfo, now: i64) -> Result<()> {
+    if account.owner != &crate::ID || account.data_is_empty() {
+        return Ok(());
+    }
+    let exclusion = SelfExclusion::try_deserialize(&mut &account.try_borrow_data()?[..])?;
+    require!(!exclusion.active(now), MarketError::SelfExcluded);
+    Ok(())
+}
+
+/// In a market's reduce-only window, a bet must shrink the predictor's net
+/// position without flipping it. `positions` must be every position
+/// `index` counts open, each once; the net is YES tokens less NO tokens
+/// across them, so a flat wallet can't bet at all
+fn require_reduces_exposure<'info>(
+    market: &Market,
+    predictor: &Pubkey,
+    index: &UserMarketIndex,
+    positions: &'info [AccountInfo<'info>],
+    prediction_type: bool,
+    tokens_out: u64,
+) -> Result<()> {
+    require!(
+        positions.len() == index.open_positions as usize,
+        MarketError::ReduceOnlyWindow
+    );
+    let mut net: i128 = 0;
+    for (i, info) in positions.iter().enumerate() {
+        require!(
+            positions[..i].iter().all(|other| other.key() != info.key()),
+            MarketError::ReduceOnlyWindow
+        );
+        let position = Account::<Prediction>::try_from(info)?;
+        require!(
+            position.predictor == *predictor && position.market_id == market.market_id,
+            MarketError::MarketMismatch
+        );
+        let tokens = position.tokens_received.saturating_sub(position.tokens_claimed) as i128;
+        net += if position.prediction_type { tokens } else { -tokens };
+    }
+    require!(
+        net != 0 && prediction_type != (net > 0) && (tokens_out as i128) <= net.abs(),
+        MarketError::ReduceOnlyWindow
+    );
+    Ok(())
+}
+
+/// Checks the optional membership NFT passed to place_prediction
+/// (or quote_prediction)
+/// Returns false when no NFT accounts were supplied (full fee). Supplying an
+/// NFT that isn't held by the bettor, isn't backed by genuine Token Metadata,
+/// or isn't a verified member of the discount collection is an error
+fn discount_nft_verified(
+    config: &Config,
+    predictor: &Pubkey,
+    nft_token_account: &Option<Account<TokenAccount>>,
+    nft_metadata: &Option<UncheckedAccount>,
+) -> Result<bool> {
+    let (token_account, metadata_info) = match (nft_token_account, nft_metadata) {
+        (None, None) => return Ok(false),
+        (Some(token_account), Some(metadata_info)) => (token_account, metadata_info),
+        _ => return err!(MarketError::InvalidDiscountNft),
+    };
+    let collection = config
+        .discount_collection
+        .ok_or(MarketError::InvalidDiscountNft)?;
+
+    require_keys_eq!(token_account.owner, *predictor, MarketError::NftNotOwned);
+    require!(token_account.amount == 1, MarketError::NftNotOwned);
+
+    // Metadata must be the canonical Token Metadata PDA for this mint
+    require_keys_eq!(
+        *metadata_info.owner,
+        mpl_token_metadata::ID,
+        MarketError::ForgedNftMetadata
+    );
+    let (expected_metadata, _) = Metadata::find_pda(&token_account.mint);
+    require_keys_eq!(
+        metadata_info.key(),
+        expected_metadata,
+        MarketError::ForgedNftMetadata
+    );
+    let metadata = Metadata::safe_deserialize(&metadata_info.try_borrow_data()?)
+        .map_err(|_| error!(MarketError::ForgedNftMetadata))?;
+
+    let in_collection = metadata
+        .collection
+        .map(|c| c.verified && c.key == collection)
+        .unwrap_or(false);
+    require!(in_collection, MarketError::NftNotInCollection);
+
+    Ok(true)
+}
+
+/// Passes an event through, also writing it to the program log when the
+/// `log-events` feature is enabled. Events are always emitted via
+/// emit_cpi!, which survives RPC log truncation
+pub fn log_event<E: anchor_lang::Event>(event: E) -> E {
+    #[cfg(feature = "log-events")]
+    anchor_lang::solana_program::log::sol_log_data(&[&event.data()]);
+    event
+}
+
+/// Compares a market vault's balance above rent to the market's liabilities
+/// Returns the violation when the vault can't cover them, numbered with the
+/// event it follows since the check runs on a read-only borrow
+fn solvency_violation(market: &Account<Market>, vault_balance: u64) -> Option<InvariantViolation> {
+    // Funds with the yield adapter still back the market
+    let vault_balance = vault_balance.saturating_add(market.idle_deployed);
+    let liabilities = market.liabilities();
+    (vault_balance < liabilities).then_some(InvariantViolation {
+        market_id: market.market_id,
+        market: market.key(),
+        tenant: market.tenant,
+        event_seq: market.event_seq,
+        vault_balance,
+        liabilities,
+    })
+}
+
+/// Lamports the vault PDA holds above its rent
+fn vault_lamports(vault: &AccountInfo) -> Result<u64> {
+    let rent = Rent::get()?.minimum_balance(vault.data_len());
+    Ok(vault.lamports().saturating_sub(rent))
+}
+
+/// Refuses collateral mints whose extensions let tokens leave the vault
+/// without the market's signature, or never leave it at all. Legacy token
+/// program mints carry no extensions
+fn validate_collateral_mint(mint: &AccountInfo) -> Result<()> {
+    if *mint.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+    let data = mint.try_borrow_data()?;
+    let mint = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&data)?;
+    let extensions = mint.get_extension_types()?;
+    require!(
+        !extensions.contains(&ExtensionType::PermanentDelegate)
+            && !extensions.contains(&ExtensionType::NonTransferable),
+        MarketError::UnsupportedCollateralMint
+    );
+    Ok(())
+}
+
+/// Collateral a market holds: the wSOL vault's token balance on a
+/// `wsol_vault` market, otherwise the vault PDA's lamports above rent plus
+/// any deposited with the yield adapter
+fn collateral_balance(
+    market: &Market,
+    vault: &AccountInfo,
+    wsol_vault: &Option<InterfaceAccount<token_interface::TokenAccount>>,
+) -> Result<u64> {
+    if market.wsol_vault {
+        let wsol_vault = wsol_vault.as_ref().ok_or(MarketError::WsolVaultRequired)?;
+        return Ok(wsol_vault.amount);
+    }
+    Ok(vault_lamports(vault)?
+        .saturating_add(market.idle_deployed)
+        .saturating_add(market.escrow_clawback()))
+}
+
+/// Fails with IdleFundsNotRecalled when the vault can't pay `amount` from
+/// what it holds, because part of the market's collateral is with the
+/// yield adapter or still in escrowed claims being clawed back. A claim
+/// waits for withdraw_idle or release_escrowed_claim rather than taking a haircut
+fn require_liquid(market: &Market, vault: &AccountInfo, amount: u64) -> Result<()> {
+    if market.idle_deployed > 0 {
+        require!(
+            vault_lamports(vault)? >= amount,
+            MarketError::IdleFundsNotRecalled
+        );
+    }
+    if market.escrow_clawback() > 0 {
+        require!(
+            vault_lamports(vault)? >= amount,
+            MarketError::EscrowClawbackPending
+        );
+    }
+    Ok(())
+}
+
+/// Deposits `amount` of the payer's SOL as market collateral: into the
+/// vault PDA, or wrapped into the wSOL vault on a `wsol_vault` market.
+/// The vault's balance change is measured rather than assumed, so a mint
+/// that takes a cut in transit can't leave the market short
+fn deposit_collateral<'info>(
+    market: &Market,
+    from: &AccountInfo<'info>,
+    vault: &AccountInfo<'info>,
+    wsol_vault: &Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    token_program: &Option<Interface<'info, TokenInterface>>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    if !market.wsol_vault {
+        return transfer_lamports(from, vault, system_program, amount);
+    }
+    let (Some(wsol_vault), Some(token_program)) = (wsol_vault, token_program) else {
+        return err!(MarketError::WsolVaultRequired);
+    };
+    let wsol_vault = wsol_vault.to_account_info();
+    let before = token::accessor::amount(&wsol_vault)?;
+    transfer_lamports(from, &wsol_vault, system_program, amount)?;
+    token_interface::sync_native(CpiContext::new(
+        token_program.to_account_info(),
+        SyncNative {
+            account: wsol_vault.clone(),
+        },
+    ))?;
+    let received = token::accessor::amount(&wsol_vault)?.saturating_sub(before);
+    require!(received >= amount, MarketError::CollateralShortfall);
+    Ok(())
+}
+
+/// Pays `amount` of collateral out of a market: lamports from the vault PDA
+/// to `recipient`, or wSOL to `payout_token_account` on a `wsol_vault`
+/// market. Returns the account credited
+#[allow(clippy::too_many_arguments)]
+fn pay_out_collateral<'info>(
+    market: &Account<'info, Market>,
+    vault: &AccountInfo<'info>,
+    wsol_vault: &Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    payout_token_account: &Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+    collateral_mint: &Option<InterfaceAccount<'info, token_interface::Mint>>,
+    token_program: &Option<Interface<'info, TokenInterface>>,
+    recipient: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<Pubkey> {
+    if !market.wsol_vault {
+        require_liquid(market, vault, amount)?;
+        **vault.try_borrow_mut_lamports()? -= amount;
+        **recipient.try_borrow_mut_lamports()? += amount;
+        return Ok(recipient.key());
+    }
+    let (Some(wsol_vault), Some(payout_token_account), Some(mint), Some(token_program)) =
+        (wsol_vault, payout_token_account, collateral_mint, token_program)
+    else {
+        return err!(MarketError::WsolVaultRequired);
+    };
+    let id_bytes = market.market_id.to_le_bytes();
+    let signer_seeds: &[&[&[u8]]] = &[&[b"market", id_bytes.as_ref(), &[market.bump]]];
+    token_interface::transfer_checked(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            TransferChecked {
+                from: wsol_vault.to_account_info(),
+                mint: mint.to_account_info(),
+                to: payout_token_account.to_account_info(),
+                authority: market.to_account_info(),
+            },
+            signer_seeds,
+        ),
+        amount,
+        mint.decimals,
+    )?;
+    Ok(payout_token_account.key())
+}
+
+/// Parlay payout: the stake times each leg's pari-mutuel odds
+/// (pot / winning pool), capped at `max_payout`. Legs are (pot, winning pool)
+fn parlay_payout(stake: u64, max_payout: u64, legs: &[(u64, u64)]) -> u64 {
+    let cap = max_payout as u128;
+    let mut payout = stake as u128;
+    for &(pot, winning_pool) in legs {
+        if winning_pool == 0 {
+            continue;
+        }
+        // Odds are >= 1, so capping at each step doesn't change the result
+        payout = (payout.saturating_mul(pot as u128) / winning_pool as u128).min(cap);
+    }
+    payout.min(cap) as u64
+}
+
+/// Calls `name` on the yield adapter, Anchor-style: the 8-byte sighash of
+/// "global:<name>" then `amount`. The market signs as the position's
+/// owner; the vault, the market's position and any remaining accounts
+/// follow. On deposit the lamports have already moved to the position; on
+/// withdraw the adapter pays them back into the vault
+fn invoke_yield_adapter<'info>(
+    name: &str,
+    market: &Account<'info, Market>,
+    vault: &AccountInfo<'info>,
+    position: &AccountInfo<'info>,
+    adapter_program: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    amount: u64,
+) -> Result<()> {
+    let sighash = anchor_lang::solana_program::hash::hash(format!("global:{name}").as_bytes());
+    let mut data = sighash.to_bytes()[..8].to_vec();
+    data.extend_from_slice(&amount.to_le_bytes());
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(market.key(), true),
+        AccountMeta::new(vault.key(), false),
+        AccountMeta::new(position.key(), false),
+    ];
+    let mut infos = vec![market.to_account_info(), vault.clone(), position.clone()];
+    for account in remaining_accounts {
+        accounts.push(if account.is_writable {
+            AccountMeta::new(account.key(), account.is_signer)
+        } else {
+            AccountMeta::new_readonly(account.key(), account.is_signer)
+        });
+        infos.push(account.clone());
+    }
+    infos.push(adapter_program.clone());
+
+    let id_bytes = market.market_id.to_le_bytes();
+    anchor_lang::solana_program::program::invoke_signed(
+        &Instruction {
+            program_id: adapter_program.key(),
+            accounts,
+            data,
+        },
+        &infos,
+        &[&[b"market", id_bytes.as_ref(), &[market.bump]]],
+    )?;
+    Ok(())
+}
+
+/// Moves lamports out of a system-owned signer via the system program
+fn transfer_lamports<'info>(
+    from: &AccountInfo<'info>,
+    to: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    if amount == 0 {
+        return Ok(());
+    }
+    let transfer_instruction =
+        anchor_lang::solana_program::system_instruction::transfer(from.key, to.key, amount);
+    anchor_lang::solana_program::program::invoke(
+        &transfer_instruction,
+        &[from.clone(), to.clone(), system_program.clone()],
+    )?;
+    Ok(())
+}
+
+// ==================== CPI ====================
+
+/// Typed helpers for programs integrating via CPI (enable the `cpi`
+/// feature). Account structs live in `cpi::accounts`; instructions that emit
+/// events also take `event_authority` (see `event_authority()`) and
+/// `program`, this program's own account
+///
+/// Authorities are plain `Signer`s, so a calling program can hold a role
+/// through a PDA it signs for with invoke_signed. A PDA authority is
+/// supported by resolve_market (any resolver tier), withdraw_fees (the
+/// creator) and the config admin instructions that create no account
+/// (update_config, pause_protocol, emergency_cancel_market). Where an
+/// authority also pays for an account, as the predictor of
+/// place_prediction does, the PDA must be system-owned and funded
+#[cfg(feature = "cpi")]
+pub mod instructions {
+    use super::*;
+    use crate::cpi::accounts;
+
+    /// PDA this program signs its event self-CPIs with
+    pub fn event_authority() -> Pubkey {
+        Pubkey::find_program_address(&[b"__event_authority"], &crate::ID).0
+    }
+
+    /// Place a prediction as `accounts.predictor`
+    /// The predictor must sign and pays the deposit; `accounts.rent_payer`
+    /// (often the predictor too) signs and pays for the prediction account
+    /// (plus its profile and epoch stats on a first bet). Both must be
+    /// system-owned.
+    /// If it is a PDA of the calling program, pass its seeds in
+    /// `signer_seeds`. No PDA of this program needs to sign
+    /// `max_price_impact_bps` of 0 places the bet without an impact limit;
+    /// an empty `note` stores none
+    /// The bet always runs for real: a dry run's failure would fail the
+    /// calling instruction too
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_prediction<'info>(
+        program: AccountInfo<'info>,
+        accounts: accounts::PlacePrediction<'info>,
+        signer_seeds: &[&[&[u8]]],
+        market_id: u64,
+        prediction_type: bool,
+        amount: u64,
+        max_price_impact_bps: u16,
+        note: String,
+    ) -> Result<()> {
+        crate::cpi::place_prediction(
+            CpiContext::new_with_signer(program, accounts, signer_seeds),
+            market_id,
+            prediction_type,
+            amount,
+            max_price_impact_bps,
+            note,
+            false,
+        )
+    }
+
+    /// Claim a winning prediction's reward to `accounts.claimer`, or to
+    /// `accounts.recipient` when set
+    /// The claimer must be the prediction's predictor and must sign (pass
+    /// PDA seeds in `signer_seeds`); it pays for a missing profile or epoch
+    /// stats account. The vault is debited by this program directly, so it
+    /// needs no signature
+    pub fn claim_reward<'info>(
+        program: AccountInfo<'info>,
+        accounts: accounts::ClaimReward<'info>,
+        signer_seeds: &[&[&[u8]]],
+        market_id: u64,
+        tokens_to_claim: Option<u64>,
+        with_receipt: bool,
+    ) -> Result<()> {
+        crate::cpi::claim_reward(
+            CpiContext::new_with_signer(program, accounts, signer_seeds),
+            market_id,
+            tokens_to_claim,
+            with_receipt,
+            false,
+        )
+    }
+
+    /// Resolve a market as `accounts.admin`, one of its resolvers
+    /// The resolver may be a PDA of the calling program, such as a DAO or
+    /// multisig vault: pass its seeds in `signer_seeds`. It only signs;
+    /// `accounts.payer` funds the resolution account
+    pub fn resolve_market<'info>(
+        program: AccountInfo<'info>,
+        accounts: accounts::ResolveMarket<'info>,
+        signer_seeds: &[&[&[u8]]],
+        market_id: u64,
+        outcome: bool,
+        evidence: String,
+    ) -> Result<()> {
+        crate::cpi::resolve_market(
+            CpiContext::new_with_signer(program, accounts, signer_seeds),
+            market_id,
+            outcome,
+            evidence,
+        )
+    }
+
+    /// Resolve a market whose resolution_program is the calling program
+    /// Must be called from that program's top-level instruction; nothing
+    /// needs to sign but `accounts.payer`, who funds the resolution account
+    pub fn resolve_via_program<'info>(
+        program: AccountInfo<'info>,
+        accounts: accounts::ResolveViaProgram<'info>,
+        market_id: u64,
+        outcome: bool,
+        evidence: String,
+    ) -> Result<()> {
+        crate::cpi::resolve_via_program(
+            CpiContext::new(program, accounts),
+            market_id,
+            outcome,
+            evidence,
+        )
+    }
+
+    /// Withdraw collected fees to `accounts.admin`, the market's creator
+    /// The creator may be a PDA of the calling program (pass its seeds in
+    /// `signer_seeds`); it is credited directly and pays for nothing
+    pub fn withdraw_fees<'info>(
+        program: AccountInfo<'info>,
+        accounts: accounts::WithdrawFees<'info>,
+        signer_seeds: &[&[&[u8]]],
+        market_id: u64,
+        amount: u64,
+    ) -> Result<()> {
+        crate::cpi::withdraw_fees(
+            CpiContext::new_with_signer(program, accounts, signer_seeds),
+            market_id,
+            amount,
+        )
+    }
+
+    /// Price a prediction without placing it; nothing needs to sign
+    pub fn quote_prediction<'info>(
+        program: AccountInfo<'info>,
+        accounts: accounts::QuotePrediction<'info>,
+        market_id: u64,
+        prediction_type: bool,
+        amount: u64,
+    ) -> Result<PredictionQuote> {
+        let quote = crate::cpi::quote_prediction(
+            CpiContext::new(program, accounts),
+            market_id,
+            prediction_type,
+            amount,
+        )?;
+        Ok(quote.get())
+    }
+
+    /// Read a market's solvency; nothing needs to sign
+    pub fn market_health<'info>(
+        program: AccountInfo<'info>,
+        accounts: accounts::GetMarketHealth<'info>,
+        market_id: u64,
+    ) -> Result<MarketHealth> {
+        let health = crate::cpi::market_health(CpiContext::new(program, accounts), market_id)?;
+        Ok(health.get())
+    }
+
+    /// Read what a prediction is owed; nothing needs to sign
+    pub fn get_claimable<'info>(
+        program: AccountInfo<'info>,
+        accounts: accounts::GetClaimable<'info>,
+        market_id: u64,
+    ) -> Result<Claimable> {
+        let claimable =
+            crate::cpi::get_claimable(CpiContext::new(program, accounts), market_id)?;
+        Ok(claimable.get())
+    }
+
+    /// Read a wallet's exposure over `positions`, (prediction, market)
+    /// pairs; nothing needs to sign
+    pub fn compute_exposure<'info>(
+        program: AccountInfo<'info>,
+        accounts: accounts::ComputeExposure<'info>,
+        positions: Vec<AccountInfo<'info>>,
+    ) -> Result<Exposure> {
+        let context = CpiContext::new(program, accounts).with_remaining_accounts(positions);
+        Ok(crate::cpi::compute_exposure(context)?.get())
+    }
+}
+
+// ==================== TEST FIXTURES ====================
+
+/// Localnet scaffolding for integration tests (enable the `test-utils`
+/// feature): instruction builders with their PDAs filled in, a
+/// `MarketScenario` that sets up a funded market under
+/// `solana-program-test`, and clock warping. The program is loaded from
+/// `prediction_market.so`, so run `anchor build` first; SBF_OUT_DIR
+/// defaults to this workspace's target/deploy
+#[cfg(feature = "test-utils")]
+pub mod fixtures {
+    use super::*;
+    use anchor_lang::solana_program::bpf_loader_upgradeable::UpgradeableLoaderState;
+    use anchor_lang::{system_program, InstructionData, ToAccountMetas};
+    use solana_program_test::{processor, BanksClientError, ProgramTest, ProgramTestContext};
+    use solana_sdk::signature::{Keypair, Signer as _};
+    use solana_sdk::transaction::Transaction;
+    use solana_system_interface::instruction as system_instruction;
+
+    /// Runs the program natively when no `prediction_market.so` is built.
+    /// The entrypoint wants accounts that live for 'info, so they're leaked
+    fn native_entry(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        data: &[u8],
+    ) -> anchor_lang::solana_program::entrypoint::ProgramResult {
+        let accounts = Box::leak(accounts.to_vec().into_boxed_slice());
+        crate::entry(program_id, accounts, data)
+    }
+
+    pub fn config_pda() -> Pubkey {
+        Pubkey::find_program_address(&[b"config"], &crate::ID).0
+    }
+
+    pub fn program_data_pda() -> Pubkey {
+        Pubkey::find_program_address(&[crate::ID.as_ref()], &bpf_loader_upgradeable::ID).0
+    }
+
+    pub fn counter_pda() -> Pubkey {
+        Pubkey::find_program_address(&[b"counter"], &crate::ID).0
+    }
+
+    pub fn insurance_fund_pda() -> Pubkey {
+        Pubkey::find_program_address(&[b"insurance"], &crate::ID).0
+    }
+
+    pub fn market_pda(market_id: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"market", &market_id.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn vault_pda(market_id: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"vault", &market_id.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn resolution_pda(market_id: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"resolution", &market_id.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn registry_page_pda(page: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"registry", &page.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn question_registry_pda(question: &str, resolution_time: i64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"question", &question_hash(question, resolution_time)],
+            &crate::ID,
+        )
+        .0
+    }
+
+    /// A user's primary position in a market
+    pub fn prediction_pda(market_id: u64, predictor: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"prediction", &market_id.to_le_bytes(), predictor.as_ref()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    /// A user's primary position in a market of the given generation
+    pub fn prediction_pda_at(market_id: u64, predictor: &Pubkey, generation: u64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                b"prediction",
+                &market_id.to_le_bytes(),
+                predictor.as_ref(),
+                &generation_seed(generation),
+            ],
+            &crate::ID,
+        )
+        .0
+    }
+
+    /// `instruction`, built for `predictor`'s primary position, pointed at
+    /// that position in the market's `generation` instead
+    pub fn at_generation(
+        mut instruction: Instruction,
+        market_id: u64,
+        predictor: &Pubkey,
+        generation: u64,
+    ) -> Instruction {
+        let primary = prediction_pda(market_id, predictor);
+        for meta in &mut instruction.accounts {
+            if meta.pubkey == primary {
+                meta.pubkey = prediction_pda_at(market_id, predictor, generation);
+            }
+        }
+        instruction
+    }
+
+    pub fn profile_pda(user: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"profile", user.as_ref()], &crate::ID).0
+    }
+
+    pub fn market_index_pda(market_id: u64, user: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"index", market_pda(market_id).as_ref(), user.as_ref()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    pub fn index_page_pda(user: &Pubkey, page: u32) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"user_index", user.as_ref(), &page.to_le_bytes()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    pub fn self_exclusion_pda(user: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"self_exclusion", user.as_ref()], &crate::ID).0
+    }
+
+    pub fn market_maker_pda(maker: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"market_maker", maker.as_ref()], &crate::ID).0
+    }
+
+    pub fn fill_pda(market_id: u64, taker: &Pubkey, fill_id: u64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"fill", &market_id.to_le_bytes(), taker.as_ref(), &fill_id.to_le_bytes()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    pub fn receipt_tree_pda(market_id: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"receipt_tree", &market_id.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn receipt_claims_pda(market_id: u64, page: u32) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"receipt_claims", &market_id.to_le_bytes(), &page.to_le_bytes()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    pub fn archive_pda(market_id: u64, generation: u64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"archive", &market_id.to_le_bytes(), &generation_seed(generation)],
+            &crate::ID,
+        )
+        .0
+    }
+
+    pub fn order_book_pda(market_id: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"order_book", &market_id.to_le_bytes()], &crate::ID).0
+    }
+
+    pub fn admin_multisig_pda(nonce: u64) -> Pubkey {
+        Pubkey::find_program_address(&[b"admin_multisig", &nonce.to_le_bytes()], &crate::ID).0
+    }
+
+    /// The key a multisig holds the config admin role as
+    pub fn multisig_signer_pda(nonce: u64) -> Pubkey {
+        let multisig = admin_multisig_pda(nonce);
+        Pubkey::find_program_address(&[b"multisig_signer", multisig.as_ref()], &crate::ID).0
+    }
+
+    pub fn admin_tx_pda(nonce: u64, index: u64) -> Pubkey {
+        let multisig = admin_multisig_pda(nonce);
+        Pubkey::find_program_address(
+            &[b"admin_tx", multisig.as_ref(), &index.to_le_bytes()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    pub fn creator_allow_pda(creator: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"creator_allow", creator.as_ref()], &crate::ID).0
+    }
+
+    pub fn epoch_stats_pda(user: &Pubkey, epoch: u64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"epoch_stats", user.as_ref(), &epoch.to_le_bytes()],
+            &crate::ID,
+        )
+        .0
+    }
+
+    fn event_authority() -> Pubkey {
+        Pubkey::find_program_address(&[b"__event_authority"], &crate::ID).0
+    }
+
+    pub fn initialize_config(admin: &Pubkey, params: ConfigParams) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitializeConfig {
+                config: config_pda(),
+                market_counter: counter_pda(),
+                insurance_fund: insurance_fund_pda(),
+                admin: *admin,
+                program_data: program_data_pda(),
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitializeConfig { params }.data(),
+        }
+    }
+
+    /// Create market `market_id`, which must be the counter's next id;
+    /// `registry_page` is the config's next_registry_page()
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize_market(
+        creator: &Pubkey,
+        market_id: u64,
+        registry_page: u64,
+        question: &str,
+        resolution_time: i64,
+        initial_liquidity: u64,
+        initial_yes_probability_bps: u16,
+        options: MarketOptions,
+    ) -> Instruction {
+        let vault = vault_pda(market_id);
+        let parent_market = options.parent.map(|parent| parent.market);
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitializeMarket {
+                market_counter: counter_pda(),
+                market: market_pda(market_id),
+                config: config_pda(),
+                registry_page: registry_page_pda(registry_page),
+                creator: *creator,
+                market_vault: vault,
+                yes_mint: None,
+                no_mint: None,
+                question_registry: question_registry_pda(question, resolution_time),
+                duplicate_approver: None,
+                series: None,
+                parent_market,
+                creator_allow: None,
+                creator_stats: creator_stats_pda(creator),
+                treasury: None,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitializeMarket {
+                question: question.to_string(),
+                resolution_time,
+                initial_liquidity,
+                initial_yes_probability_bps,
+                fee_bps: 0,
+                options,
+            }
+            .data(),
+        }
+    }
+
+    /// Create market `market_id` under an explicit ID, as
+    /// initialize_market_with_id
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize_market_with_id(
+        creator: &Pubkey,
+        market_id: u64,
+        registry_page: u64,
+        question: &str,
+        resolution_time: i64,
+        initial_liquidity: u64,
+        initial_yes_probability_bps: u16,
+        options: MarketOptions,
+    ) -> Instruction {
+        let parent_market = options.parent.map(|parent| parent.market);
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitializeMarketWithId {
+                yes_mint: None,
+                no_mint: None,
+                market: market_pda(market_id),
+                config: config_pda(),
+                registry_page: registry_page_pda(registry_page),
+                creator: *creator,
+                market_vault: vault_pda(market_id),
+                question_registry: question_registry_pda(question, resolution_time),
+                duplicate_approver: None,
+                series: None,
+                parent_market,
+                creator_allow: None,
+                creator_stats: creator_stats_pda(creator),
+                treasury: None,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitializeMarketWithId {
+                market_id,
+                question: question.to_string(),
+                resolution_time,
+                initial_liquidity,
+                initial_yes_probability_bps,
+                fee_bps: 0,
+                options,
+            }
+            .data(),
+        }
+    }
+
+    /// Create a market with even odds and place `creator`'s first bet in
+    /// it; `index_page` and `epoch` as for place_prediction
+    #[allow(clippy::too_many_arguments)]
+    pub fn initialize_market_and_predict(
+        creator: &Pubkey,
+        market_id: u64,
+        registry_page: u64,
+        question: &str,
+        resolution_time: i64,
+        initial_liquidity: u64,
+        prediction_type: bool,
+        amount: u64,
+        index_page: u32,
+        epoch: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitializeMarketAndPredict {
+                market_counter: counter_pda(),
+                market: market_pda(market_id),
+                config: config_pda(),
+                registry_page: registry_page_pda(registry_page),
+                creator: *creator,
+                market_vault: vault_pda(market_id),
+                yes_mint: None,
+                no_mint: None,
+                question_registry: question_registry_pda(question, resolution_time),
+                duplicate_approver: None,
+                series: None,
+                parent_market: None,
+                creator_allow: None,
+                creator_stats: creator_stats_pda(creator),
+                treasury: None,
+                prediction_account: prediction_pda(market_id, creator),
+                user_profile: profile_pda(creator),
+                user_market_index: market_index_pda(market_id, creator),
+                user_index_page: index_page_pda(creator, index_page),
+                epoch_stats: epoch_stats_pda(creator, epoch),
+                referrer: None,
+                nft_token_account: None,
+                nft_metadata: None,
+                insurance_fund: insurance_fund_pda(),
+                self_exclusion: self_exclusion_pda(creator),
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitializeMarketAndPredict {
+                question: question.to_string(),
+                resolution_time,
+                initial_liquidity,
+                initial_yes_probability_bps: EVEN_ODDS_BPS,
+                fee_bps: 0,
+                options: MarketOptions::default(),
+                prediction_type,
+                amount,
+            }
+            .data(),
+        }
+    }
+
+    pub fn creator_stats_pda(creator: &Pubkey) -> Pubkey {
+        Pubkey::find_program_address(&[b"creator_stats", creator.as_ref()], &crate::ID).0
+    }
+
+    /// Bet `amount` on `prediction_type` in `creator`'s market;
+    /// `index_page` is the predictor's next_index_page() and `epoch` the
+    /// config's current_epoch
+    #[allow(clippy::too_many_arguments)]
+    pub fn place_prediction(
+        predictor: &Pubkey,
+        creator: &Pubkey,
+        market_id: u64,
+        prediction_type: bool,
+        amount: u64,
+        index_page: u32,
+        epoch: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::PlacePrediction {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                config: config_pda(),
+                prediction_account: prediction_pda(market_id, predictor),
+                user_profile: profile_pda(predictor),
+                user_market_index: market_index_pda(market_id, predictor),
+                user_index_page: index_page_pda(predictor, index_page),
+                epoch_stats: epoch_stats_pda(predictor, epoch),
+                referrer: None,
+                nft_token_account: None,
+                nft_metadata: None,
+                series: None,
+                insurance_fund: insurance_fund_pda(),
+                wsol_vault: None,
+                creator_stats: creator_stats_pda(creator),
+                self_exclusion: self_exclusion_pda(predictor),
+                predictor: *predictor,
+                rent_payer: *predictor,
+                token_program: None,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::PlacePrediction {
+                market_id,
+                prediction_type,
+                amount,
+                max_price_impact_bps: 0,
+                note: String::new(),
+                dry_run: false,
+            }
+            .data(),
+        }
+    }
+
+    pub fn init_receipt_tree(creator: &Pubkey, market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitReceiptTree {
+                market: market_pda(market_id),
+                receipt_tree: receipt_tree_pda(market_id),
+                creator: *creator,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitReceiptTree { _market_id: market_id }.data(),
+        }
+    }
+
+    pub fn place_compressed_prediction(
+        predictor: &Pubkey,
+        creator: &Pubkey,
+        market_id: u64,
+        prediction_type: bool,
+        amount: u64,
+        expected_root: [u8; 32],
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::PlaceCompressedPrediction {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                config: config_pda(),
+                receipt_tree: receipt_tree_pda(market_id),
+                insurance_fund: insurance_fund_pda(),
+                creator_stats: creator_stats_pda(creator),
+                self_exclusion: self_exclusion_pda(predictor),
+                predictor: *predictor,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::PlaceCompressedPrediction {
+                market_id,
+                prediction_type,
+                amount,
+                max_price_impact_bps: 0,
+                expected_root,
+            }
+            .data(),
+        }
+    }
+
+    pub fn claim_compressed(
+        claimer: &Pubkey,
+        market_id: u64,
+        receipt: CompressedReceipt,
+        proof: Vec<[u8; 32]>,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ClaimCompressed {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                receipt_claims: receipt_claims_pda(market_id, receipt.claims_page()),
+                claimer: *claimer,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ClaimCompressed { market_id, receipt, proof }.data(),
+        }
+    }
+
+    /// Sibling path of leaf `index` in a receipt tree holding `leaves`, as
+    /// an indexer would build it from CompressedPredictionAppended events
+    pub fn receipt_proof(leaves: &[[u8; 32]], index: u64) -> Vec<[u8; 32]> {
+        let (mut level, mut zero) = (leaves.to_vec(), [0; 32]);
+        let mut index = index as usize;
+        let mut proof = Vec::with_capacity(RECEIPT_TREE_DEPTH);
+        for _ in 0..RECEIPT_TREE_DEPTH {
+            proof.push(level.get(index ^ 1).copied().unwrap_or(zero));
+            level = level
+                .chunks(2)
+                .map(|pair| receipt_node(&pair[0], pair.get(1).unwrap_or(&zero)))
+                .collect();
+            zero = receipt_node(&zero, &zero);
+            index /= 2;
+        }
+        proof
+    }
+
+    /// Resolve as the market's creator, without evidence
+    pub fn resolve_market(creator: &Pubkey, market_id: u64, outcome: bool) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ResolveMarket {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                wsol_vault: None,
+                resolution: resolution_pda(market_id),
+                admin: *creator,
+                payer: *creator,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ResolveMarket {
+                market_id,
+                outcome,
+                evidence: String::new(),
+            }
+            .data(),
+        }
+    }
+
+    /// Set the market's resolver tiers as its creator
+    pub fn set_resolvers(
+        creator: &Pubkey,
+        market_id: u64,
+        resolvers: [Pubkey; 3],
+        delays: [i64; 3],
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::UpdateResolvers {
+                market: market_pda(market_id),
+                creator: *creator,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::SetResolvers {
+                market_id,
+                resolvers,
+                delays,
+            }
+            .data(),
+        }
+    }
+
+    /// Revoke `resolver` as the market's creator or the config admin
+    pub fn revoke_resolver(authority: &Pubkey, market_id: u64, resolver: Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::RevokeResolver {
+                config: config_pda(),
+                market: market_pda(market_id),
+                authority: *authority,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::RevokeResolver {
+                market_id,
+                resolver,
+            }
+            .data(),
+        }
+    }
+
+    /// Claim the whole of a winning primary position to the claimer;
+    /// `epoch` is the config's current_epoch
+    pub fn claim_reward(claimer: &Pubkey, market_id: u64, epoch: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ClaimReward {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                config: config_pda(),
+                prediction_account: prediction_pda(market_id, claimer),
+                user_profile: profile_pda(claimer),
+                epoch_stats: epoch_stats_pda(claimer, epoch),
+                claimer: *claimer,
+                recipient: None,
+                wsol_vault: None,
+                payout_token_account: None,
+                collateral_mint: None,
+                token_program: None,
+                claim_receipt: None,
+                escrowed_claim: None,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ClaimReward {
+                market_id,
+                tokens_to_claim: None,
+                with_receipt: false,
+                dry_run: false,
+            }
+            .data(),
+        }
+    }
+
+    /// Settle a conditional market against its parent; anyone may sign
+    pub fn settle_parent_condition(market_id: u64, parent_market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::SettleParentCondition {
+                market: market_pda(market_id),
+                parent_market: market_pda(parent_market_id),
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::SettleParentCondition { market_id }.data(),
+        }
+    }
+
+    /// Void the market as the config admin
+    pub fn emergency_cancel_market(admin: &Pubkey, market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::EmergencyCancelMarket {
+                config: config_pda(),
+                market: market_pda(market_id),
+                admin: *admin,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::EmergencyCancelMarket {
+                _market_id: market_id,
+                reason: String::new(),
+            }
+            .data(),
+        }
+    }
+
+    pub fn top_up_vault(admin: &Pubkey, market_id: u64, amount: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::TopUpVault {
+                config: config_pda(),
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                admin: *admin,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::TopUpVault { market_id, amount }.data(),
+        }
+    }
+
+    /// Tip the market's creator `amount` lamports from `tipper`
+    pub fn tip_creator(tipper: &Pubkey, market_id: u64, amount: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::TipCreator {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                tipper: *tipper,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::TipCreator { market_id, amount }.data(),
+        }
+    }
+
+    /// Pay the market's tips out to `creator`
+    pub fn withdraw_tips(creator: &Pubkey, market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::WithdrawTips {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                creator: *creator,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::WithdrawTips { market_id }.data(),
+        }
+    }
+
+    pub fn add_house_account(creator: &Pubkey, market_id: u64, account: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::UpdateHouseAccounts {
+                market: market_pda(market_id),
+                creator: *creator,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::AddHouseAccount {
+                market_id,
+                account: *account,
+            }
+            .data(),
+        }
+    }
+
+    pub fn remove_house_account(creator: &Pubkey, market_id: u64, account: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::UpdateHouseAccounts {
+                market: market_pda(market_id),
+                creator: *creator,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::RemoveHouseAccount {
+                market_id,
+                account: *account,
+            }
+            .data(),
+        }
+    }
+
+    /// The creator closes a settled, emptied market of `generation`,
+    /// archiving it
+    pub fn close_market(creator: &Pubkey, market_id: u64, generation: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::CloseMarket {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                archive: archive_pda(market_id, generation),
+                creator: *creator,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::CloseMarket { market_id }.data(),
+        }
+    }
+
+    /// Create a market's order book, with `payer` funding its rent
+    pub fn initialize_order_book(payer: &Pubkey, market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::InitializeOrderBook {
+                market: market_pda(market_id),
+                order_book: order_book_pda(market_id),
+                payer: *payer,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::InitializeOrderBook { market_id }.data(),
+        }
+    }
+
+    pub fn post_order(
+        owner: &Pubkey,
+        market_id: u64,
+        side: bool,
+        price_bps: u16,
+        amount: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::PostOrder {
+                config: config_pda(),
+                market: market_pda(market_id),
+                order_book: order_book_pda(market_id),
+                self_exclusion: self_exclusion_pda(owner),
+                owner: *owner,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::PostOrder {
+                market_id,
+                side,
+                price_bps,
+                amount,
+            }
+            .data(),
+        }
+    }
+
+    /// `taker` takes `amount` of the order in `slot`, owned by `maker`
+    pub fn fill_order(
+        taker: &Pubkey,
+        maker: &Pubkey,
+        market_id: u64,
+        slot: u16,
+        amount: u64,
+        fill_id: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::FillOrder {
+                config: config_pda(),
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                order_book: order_book_pda(market_id),
+                fill: fill_pda(market_id, taker, fill_id),
+                maker_registration: market_maker_pda(maker),
+                self_exclusion: self_exclusion_pda(taker),
+                taker: *taker,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::FillOrder {
+                market_id,
+                slot,
+                amount,
+                _fill_id: fill_id,
+            }
+            .data(),
+        }
+    }
+
+    pub fn settle_fill(
+        maker: &Pubkey,
+        taker: &Pubkey,
+        market_id: u64,
+        fill_id: u64,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::SettleFill {
+                market: market_pda(market_id),
+                fill: fill_pda(market_id, taker, fill_id),
+                maker: *maker,
+                taker: *taker,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::SettleFill { market_id, _fill_id: fill_id }.data(),
+        }
+    }
+
+    pub fn approve_market_maker(admin: &Pubkey, maker: &Pubkey, rebate_bps: u16) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ApproveMarketMaker {
+                config: config_pda(),
+                registration: market_maker_pda(maker),
+                admin: *admin,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ApproveMarketMaker {
+                maker: *maker,
+                rebate_bps,
+            }
+            .data(),
+        }
+    }
+
+    pub fn claim_mm_rebates(maker: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ClaimMmRebates {
+                registration: market_maker_pda(maker),
+                maker: *maker,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ClaimMmRebates {}.data(),
+        }
+    }
+
+    /// Halt market creation and betting as the config admin
+    pub fn pause_protocol(admin: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::UpdateConfig {
+                config: config_pda(),
+                admin: *admin,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::PauseProtocol {}.data(),
+        }
+    }
+
+    pub fn approve_creator(admin: &Pubkey, creator: Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ApproveCreator {
+                config: config_pda(),
+                creator_allow: creator_allow_pda(&creator),
+                admin: *admin,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ApproveCreator {
+                creator,
+                fee_waived: false,
+                max_markets_per_day: 0,
+            }
+            .data(),
+        }
+    }
+
+    pub fn create_admin_multisig(
+        admin: &Pubkey,
+        nonce: u64,
+        owners: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::CreateAdminMultisig {
+                config: config_pda(),
+                multisig: admin_multisig_pda(nonce),
+                multisig_signer: multisig_signer_pda(nonce),
+                admin: *admin,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::CreateAdminMultisig {
+                nonce,
+                owners,
+                threshold,
+            }
+            .data(),
+        }
+    }
+
+    /// Propose `action` as the multisig's admin action `index`, its next
+    pub fn create_admin_tx(
+        owner: &Pubkey,
+        nonce: u64,
+        index: u64,
+        action: AdminAction,
+    ) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::CreateAdminTx {
+                multisig: admin_multisig_pda(nonce),
+                admin_tx: admin_tx_pda(nonce, index),
+                owner: *owner,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::CreateAdminTx { action }.data(),
+        }
+    }
+
+    pub fn approve_admin_tx(owner: &Pubkey, nonce: u64, index: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ApproveAdminTx {
+                multisig: admin_multisig_pda(nonce),
+                admin_tx: admin_tx_pda(nonce, index),
+                owner: *owner,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ApproveAdminTx { index }.data(),
+        }
+    }
+
+    /// An Invoke action running `instruction`, built with the multisig
+    /// signer as its admin
+    pub fn invoke_action(instruction: &Instruction) -> AdminAction {
+        AdminAction::Invoke {
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|meta| AdminAccountMeta {
+                    pubkey: meta.pubkey,
+                    is_signer: meta.is_signer,
+                    is_writable: meta.is_writable,
+                })
+                .collect(),
+            data: instruction.data.clone(),
+        }
+    }
+
+    /// Execute admin action `index`; an Invoke action's `instruction`
+    /// goes in as remaining accounts, none of them signing
+    pub fn execute_admin_tx(
+        owner: &Pubkey,
+        nonce: u64,
+        index: u64,
+        invoked: Option<&Instruction>,
+    ) -> Instruction {
+        let mut accounts = crate::accounts::ExecuteAdminTx {
+            config: config_pda(),
+            multisig: admin_multisig_pda(nonce),
+            admin_tx: admin_tx_pda(nonce, index),
+            multisig_signer: multisig_signer_pda(nonce),
+            market: None,
+            owner: *owner,
+            event_authority: event_authority(),
+            program: crate::ID,
+        }
+        .to_account_metas(None);
+        for meta in invoked.map_or(&[][..], |ix| &ix.accounts) {
+            accounts.push(AccountMeta {
+                is_signer: false,
+                ..meta.clone()
+            });
+        }
+        Instruction {
+            program_id: crate::ID,
+            accounts,
+            data: crate::instruction::ExecuteAdminTx { index }.data(),
+        }
+    }
+
+    pub fn cancel_order(owner: &Pubkey, market_id: u64, slot: u16) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::CancelOrder {
+                market: market_pda(market_id),
+                order_book: order_book_pda(market_id),
+                owner: *owner,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::CancelOrder { market_id, slot }.data(),
+        }
+    }
+
+    /// Refund a primary position in a voided market to the claimer
+    pub fn claim_refund(claimer: &Pubkey, market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ClaimRefund {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                prediction_account: prediction_pda(market_id, claimer),
+                claimer: *claimer,
+                wsol_vault: None,
+                payout_token_account: None,
+                collateral_mint: None,
+                token_program: None,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ClaimRefund { market_id }.data(),
+        }
+    }
+
+    /// The creator takes back a voided market's seed liquidity
+    pub fn refund_liquidity(creator: &Pubkey, market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::RefundLiquidity {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                creator: *creator,
+                wsol_vault: None,
+                payout_token_account: None,
+                collateral_mint: None,
+                token_program: None,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::RefundLiquidity { market_id }.data(),
+        }
+    }
+
+    /// Close `predictor`'s settled primary position, its rent going back
+    /// to `predictor`
+    pub fn close_prediction(predictor: &Pubkey, market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::ClosePrediction {
+                market: market_pda(market_id),
+                prediction_account: prediction_pda(market_id, predictor),
+                user_market_index: market_index_pda(market_id, predictor),
+                predictor: *predictor,
+                rent_payer: *predictor,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::ClosePrediction { market_id }.data(),
+        }
+    }
+
+    /// The market's creator withdraws `amount` of its collected fees
+    pub fn withdraw_fees(creator: &Pubkey, market_id: u64, amount: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::WithdrawFees {
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                admin: *creator,
+                wsol_vault: None,
+                payout_token_account: None,
+                collateral_mint: None,
+                token_program: None,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::WithdrawFees { market_id, amount }.data(),
+        }
+    }
+
+    /// Undo the predictor's primary position within the cooling-off window
+    pub fn cancel_prediction(predictor: &Pubkey, market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::CancelPrediction {
+                config: config_pda(),
+                market: market_pda(market_id),
+                market_vault: vault_pda(market_id),
+                prediction_account: prediction_pda(market_id, predictor),
+                user_market_index: market_index_pda(market_id, predictor),
+                predictor: *predictor,
+                rent_payer: *predictor,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::CancelPrediction { market_id }.data(),
+        }
+    }
+
+    /// Exclude `user` from betting until `until`, or extend their exclusion
+    pub fn self_exclude(user: &Pubkey, until: i64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::SelfExclude {
+                self_exclusion: self_exclusion_pda(user),
+                user: *user,
+                system_program: system_program::ID,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::SelfExclude { until }.data(),
+        }
+    }
+
+    /// Close `user`'s expired self-exclusion
+    pub fn close_self_exclusion(user: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::CloseSelfExclusion {
+                self_exclusion: self_exclusion_pda(user),
+                user: *user,
+                event_authority: event_authority(),
+                program: crate::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::CloseSelfExclusion {}.data(),
+        }
+    }
+
+    /// Upgrade the market to the current layout, paid by `payer`
+    pub fn migrate_market(payer: &Pubkey, market_id: u64) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::MigrateMarket {
+                market: market_pda(market_id),
+                payer: *payer,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::MigrateMarket { market_id }.data(),
+        }
+    }
+
+    /// Grow `predictor`'s primary position to the current Prediction size
+    pub fn migrate_prediction(payer: &Pubkey, market_id: u64, predictor: &Pubkey) -> Instruction {
+        Instruction {
+            program_id: crate::ID,
+            accounts: crate::accounts::MigratePrediction {
+                prediction_account: prediction_pda(market_id, predictor),
+                payer: *payer,
+                system_program: system_program::ID,
+            }
+            .to_account_metas(None),
+            data: crate::instruction::MigratePrediction { market_id }.data(),
+        }
+    }
+
+    /// Read `wallet`'s exposure over `positions`, (prediction, market) pairs
+    pub fn compute_exposure(wallet: &Pubkey, positions: &[(Pubkey, Pubkey)]) -> Instruction {
+        let mut accounts = crate::accounts::ComputeExposure {
+            wallet: *wallet,
+            user_profile: profile_pda(wallet),
+        }
+        .to_account_metas(None);
+        for (prediction, market) in positions {
+            accounts.push(AccountMeta::new_readonly(*prediction, false));
+            accounts.push(AccountMeta::new_readonly(*market, false));
+        }
+        Instruction {
+            program_id: crate::ID,
+            accounts,
+            data: crate::instruction::ComputeExposure {}.data(),
+        }
+    }
+
+    /// Fetch and deserialize an account of this program
+    pub async fn fetch<T: AccountDeserialize>(
+        context: &mut ProgramTestContext,
+        address: Pubkey,
+    ) -> Result<T> {
+        let account = context
+            .banks_client
+            .get_account(address)
+            .await
+            .map_err(|_| error!(ErrorCode::AccountNotInitialized))?
+            .ok_or(error!(ErrorCode::AccountNotInitialized))?;
+        T::try_deserialize(&mut account.data.as_slice())
+    }
+
+    pub async fn now(context: &mut ProgramTestContext) -> i64 {
+        let clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp
+    }
+
+    /// Simulate a view instruction and decode what it returned
+    pub async fn view<T: AnchorDeserialize>(
+        context: &mut ProgramTestContext,
+        instruction: Instruction,
+    ) -> std::result::Result<T, BanksClientError> {
+        let blockhash = context.get_new_latest_blockhash().await?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&context.payer.pubkey()),
+            &[&context.payer],
+            blockhash,
+        );
+        let simulation = context.banks_client.simulate_transaction(transaction).await?;
+        if let Some(Err(error)) = simulation.result {
+            return Err(BanksClientError::TransactionError(error));
+        }
+        let data = simulation
+            .simulation_details
+            .and_then(|details| details.return_data)
+            .map(|return_data| return_data.data)
+            .unwrap_or_default();
+        T::try_from_slice(&data)
+            .map_err(|_| BanksClientError::ClientError("view returned undecodable data"))
+    }
+
+    /// Compute units `instructions` take, from a simulation that leaves
+    /// the bank untouched
+    pub async fn compute_units(
+        context: &mut ProgramTestContext,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> std::result::Result<u64, BanksClientError> {
+        let blockhash = context.get_new_latest_blockhash().await?;
+        let mut all_signers = vec![&context.payer];
+        all_signers.extend_from_slice(signers);
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        let simulation = context.banks_client.simulate_transaction(transaction).await?;
+        if let Some(Err(error)) = simulation.result {
+            return Err(BanksClientError::TransactionError(error));
+        }
+        Ok(simulation
+            .simulation_details
+            .map_or(0, |details| details.units_consumed))
+    }
+
+    /// Set the cluster clock to `unix_timestamp`, leaving the slot alone
+    pub async fn warp_to_timestamp(context: &mut ProgramTestContext, unix_timestamp: i64) {
+        let mut clock: Clock = context.banks_client.get_sysvar().await.unwrap();
+        clock.unix_timestamp = unix_timestamp;
+        context.set_sysvar(&clock);
+    }
+
+    pub async fn warp_by(context: &mut ProgramTestContext, secs: i64) {
+        let now = now(context).await;
+        warp_to_timestamp(context, now + secs).await;
+    }
+
+    /// Boot the program, natively unless `prediction_market.so` is built,
+    /// with the payer as its upgrade authority
+    pub async fn boot() -> ProgramTestContext {
+        if std::env::var_os("SBF_OUT_DIR").is_none() {
+            std::env::set_var(
+                "SBF_OUT_DIR",
+                concat!(env!("CARGO_MANIFEST_DIR"), "/../../target/deploy"),
+            );
+        }
+        let built = std::path::Path::new(&std::env::var_os("SBF_OUT_DIR").unwrap())
+            .join("prediction_market.so")
+            .exists();
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(built);
+        program_test.add_program("prediction_market", crate::ID, processor!(native_entry));
+        let mut context = program_test.start_with_context().await;
+        let payer = context.payer.pubkey();
+        set_upgrade_authority(&mut context, Some(&payer));
+        context
+    }
+
+    /// Write the program's ProgramData account with `authority` as its
+    /// upgrade authority; None makes the program immutable. ProgramTest
+    /// loads programs without one, so this is all initialize_config sees
+    pub fn set_upgrade_authority(context: &mut ProgramTestContext, authority: Option<&Pubkey>) {
+        // Bincode of UpgradeableLoaderState::ProgramData { slot: 0, authority }
+        let mut data = vec![0; UpgradeableLoaderState::size_of_programdata_metadata()];
+        data[0] = 3;
+        if let Some(authority) = authority {
+            data[12] = 1;
+            data[13..45].copy_from_slice(authority.as_ref());
+        }
+        let account = solana_sdk::account::Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: bpf_loader_upgradeable::ID,
+            executable: false,
+            rent_epoch: 0,
+        };
+        context.set_account(&program_data_pda(), &account.into());
+    }
+
+    /// Sign with the payer plus `signers` and process `instructions` as one
+    /// transaction
+    pub async fn process(
+        context: &mut ProgramTestContext,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> std::result::Result<(), BanksClientError> {
+        let blockhash = context.get_new_latest_blockhash().await?;
+        let mut all_signers = vec![&context.payer];
+        all_signers.extend_from_slice(signers);
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&context.payer.pubkey()),
+            &all_signers,
+            blockhash,
+        );
+        context.banks_client.process_transaction(transaction).await
+    }
+
+    /// A market with funded bettors, started under solana-program-test.
+    /// Defaults: default config with no minimum duration, a one-hour market
+    /// seeded with 1 SOL at 50/50, and no bets
+    pub struct MarketScenario {
+        pub config: ConfigParams,
+        pub options: MarketOptions,
+        pub question: String,
+        pub duration_secs: i64,
+        pub initial_liquidity: u64,
+        pub initial_yes_probability_bps: u16,
+        /// Side and stake of each bettor, one bettor per entry
+        pub bets: Vec<(bool, u64)>,
+        /// Lamports given to each further bettor, who doesn't bet at start
+        pub idle_bettors: Vec<u64>,
+    }
+
+    impl Default for MarketScenario {
+        fn default() -> Self {
+            Self {
+                config: ConfigParams {
+                    min_market_duration: 0,
+                    ..ConfigParams::default()
+                },
+                options: MarketOptions::default(),
+                question: "Will the fixture market resolve YES?".to_string(),
+                duration_secs: 60 * 60,
+                initial_liquidity: 1_000_000_000,
+                initial_yes_probability_bps: 5_000,
+                bets: Vec::new(),
+                idle_bettors: Vec::new(),
+            }
+        }
+    }
+
+    impl MarketScenario {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn config(mut self, config: ConfigParams) -> Self {
+            self.config = config;
+            self
+        }
+
+        pub fn options(mut self, options: MarketOptions) -> Self {
+            self.options = options;
+            self
+        }
+
+        pub fn duration_secs(mut self, duration_secs: i64) -> Self {
+            self.duration_secs = duration_secs;
+            self
+        }
+
+        pub fn initial_liquidity(mut self, initial_liquidity: u64) -> Self {
+            self.initial_liquidity = initial_liquidity;
+            self
+        }
+
+        /// Add a bettor who stakes `amount` on `prediction_type`
+        pub fn bet(mut self, prediction_type: bool, amount: u64) -> Self {
+            self.bets.push((prediction_type, amount));
+            self
+        }
+
+        /// Add `count` bettors funded with `lamports` each who place no bet
+        /// at start
+        pub fn idle_bettors(mut self, count: usize, lamports: u64) -> Self {
+            self.idle_bettors.extend(std::iter::repeat_n(lamports, count));
+            self
+        }
+
+        /// Boot the program, initialize the config, create the market and
+        /// place every bet
+        pub async fn start(self) -> std::result::Result<Scenario, BanksClientError> {
+            let mut context = boot().await;
+
+            let admin = Keypair::new();
+            set_upgrade_authority(&mut context, Some(&admin.pubkey()));
+            let creator = Keypair::new();
+            let funding_per_bettor: Vec<u64> = self
+                .bets
+                .iter()
+                .map(|(_, amount)| amount + 1_000_000_000)
+                .chain(self.idle_bettors.iter().copied())
+                .collect();
+            let bettors: Vec<Keypair> =
+                funding_per_bettor.iter().map(|_| Keypair::new()).collect();
+            let payer = context.payer.pubkey();
+            let mut funding = vec![
+                system_instruction::transfer(&payer, &admin.pubkey(), 1_000_000_000),
+                system_instruction::transfer(
+                    &payer,
+                    &creator.pubkey(),
+                    self.initial_liquidity + 1_000_000_000,
+                ),
+            ];
+            for (bettor, lamports) in bettors.iter().zip(funding_per_bettor) {
+                funding.push(system_instruction::transfer(&payer, &bettor.pubkey(), lamports));
+            }
+            // Kept well under the per-transaction account limit
+            for transfers in funding.chunks(16) {
+                process(&mut context, transfers, &[]).await?;
+            }
+            process(
+                &mut context,
+                &[initialize_config(&admin.pubkey(), self.config)],
+                &[&admin],
+            )
+            .await?;
+
+            let market_id = fetch::<MarketCounter>(&mut context, counter_pda())
+                .await
+                .unwrap()
+                .next_id;
+            let registry_page = fetch::<Config>(&mut context, config_pda())
+                .await
+                .unwrap()
+                .next_registry_page();
+            let resolution_time = now(&mut context).await + self.duration_secs;
+            process(
+                &mut context,
+                &[initialize_market(
+                    &creator.pubkey(),
+                    market_id,
+                    registry_page,
+                    &self.question,
+                    resolution_time,
+                    self.initial_liquidity,
+                    self.initial_yes_probability_bps,
+                    self.options,
+                )],
+                &[&creator],
+            )
+            .await?;
+
+            let mut scenario = Scenario {
+                context,
+                admin,
+                creator,
+                bettors,
+                market_id,
+                resolution_time,
+            };
+            for (bettor, (prediction_type, amount)) in self.bets.into_iter().enumerate() {
+                scenario.place_prediction(bettor, prediction_type, amount).await?;
+            }
+            Ok(scenario)
+        }
+    }
+
+    /// A started MarketScenario
+    pub struct Scenario {
+        pub context: ProgramTestContext,
+        pub admin: Keypair,
+        pub creator: Keypair,
+        pub bettors: Vec<Keypair>,
+        pub market_id: u64,
+        pub resolution_time: i64,
+    }
+
+    impl Scenario {
+        pub async fn market(&mut self) -> Market {
+            fetch(&mut self.context, market_pda(self.market_id)).await.unwrap()
+        }
+
+        pub async fn prediction(&mut self, bettor: usize) -> Prediction {
+            let address = prediction_pda(self.market_id, &self.bettors[bettor].pubkey());
+            fetch(&mut self.context, address).await.unwrap()
+        }
+
+        async fn epoch(&mut self) -> u64 {
+            fetch::<Config>(&mut self.context, config_pda())
+                .await
+                .unwrap()
+                .current_epoch
+        }
+
+        /// The bettor's place_prediction, at its next index page and the
+        /// current epoch
+        pub async fn place_prediction_instruction(
+            &mut self,
+            bettor: usize,
+            prediction_type: bool,
+            amount: u64,
+        ) -> Instruction {
+            let predictor = self.bettors[bettor].pubkey();
+            let index_page = fetch::<UserProfile>(&mut self.context, profile_pda(&predictor))
+                .await
+                .map(|profile| profile.next_index_page())
+                .unwrap_or(0);
+            let epoch = self.epoch().await;
+            place_prediction(
+                &predictor,
+                &self.creator.pubkey(),
+                self.market_id,
+                prediction_type,
+                amount,
+                index_page,
+                epoch,
+            )
+        }
+
+        pub async fn place_prediction(
+            &mut self,
+            bettor: usize,
+            prediction_type: bool,
+            amount: u64,
+        ) -> std::result::Result<(), BanksClientError> {
+            let predictor = self.bettors[bettor].insecure_clone();
+            let instruction =
+                self.place_prediction_instruction(bettor, prediction_type, amount).await;
+            process(&mut self.context, &[instruction], &[&predictor]).await
+        }
+
+        /// Create another market as the scenario's creator, whose seed the
+        /// payer funds. The scenario keeps pointing at its own market
+        pub async fn create_market(
+            &mut self,
+            question: &str,
+            resolution_time: i64,
+            options: MarketOptions,
+        ) -> std::result::Result<u64, BanksClientError> {
+            let creator = self.creator.insecure_clone();
+            let market_id = fetch::<MarketCounter>(&mut self.context, counter_pda())
+                .await
+                .unwrap()
+                .next_id;
+            let registry_page = fetch::<Config>(&mut self.context, config_pda())
+                .await
+                .unwrap()
+                .next_registry_page();
+            let seed = 1_000_000_000;
+            let instructions = [
+                system_instruction::transfer(
+                    &self.context.payer.pubkey(),
+                    &creator.pubkey(),
+                    seed + 1_000_000_000,
+                ),
+                initialize_market(
+                    &creator.pubkey(),
+                    market_id,
+                    registry_page,
+                    question,
+                    resolution_time,
+                    seed,
+                    EVEN_ODDS_BPS,
+                    options,
+                ),
+            ];
+            process(&mut self.context, &instructions, &[&creator]).await?;
+            Ok(market_id)
+        }
+
+        /// Move the clock to the market's resolution time
+        pub async fn warp_to_resolution(&mut self) {
+            warp_to_timestamp(&mut self.context, self.resolution_time).await;
+        }
+
+        pub async fn resolve(&mut self, outcome: bool) -> std::result::Result<(), BanksClientError> {
+            let creator = self.creator.insecure_clone();
+            let instruction = resolve_market(&creator.pubkey(), self.market_id, outcome);
+            process(&mut self.context, &[instruction], &[&creator]).await
+        }
+
+        pub async fn claim(&mut self, bettor: usize) -> std::result::Result<(), BanksClientError> {
+            let claimer = self.bettors[bettor].insecure_clone();
+            let epoch = self.epoch().await;
+            let instruction = claim_reward(&claimer.pubkey(), self.market_id, epoch);
+            process(&mut self.context, &[instruction], &[&claimer]).await
+        }
+
+        pub async fn cancel(&mut self) -> std::result::Result<(), BanksClientError> {
+            let admin = self.admin.insecure_clone();
+            let instruction = emergency_cancel_market(&admin.pubkey(), self.market_id);
+            process(&mut self.context, &[instruction], &[&admin]).await
+        }
+
+        /// The admin pays `amount` into the vault; the admin must hold it
+        pub async fn top_up(&mut self, amount: u64) -> std::result::Result<(), BanksClientError> {
+            let admin = self.admin.insecure_clone();
+            let instruction = top_up_vault(&admin.pubkey(), self.market_id, amount);
+            process(&mut self.context, &[instruction], &[&admin]).await
+        }
+
+        /// The payer tips the creator `amount`
+        pub async fn tip(&mut self, amount: u64) -> std::result::Result<(), BanksClientError> {
+            let instruction = tip_creator(&self.context.payer.pubkey(), self.market_id, amount);
+            process(&mut self.context, &[instruction], &[]).await
+        }
+
+        /// The creator makes bettor `bettor` a house account
+        pub async fn add_house_account(
+            &mut self,
+            bettor: usize,
+        ) -> std::result::Result<(), BanksClientError> {
+            let creator = self.creator.insecure_clone();
+            let account = self.bettors[bettor].pubkey();
+            let instruction = add_house_account(&creator.pubkey(), self.market_id, &account);
+            process(&mut self.context, &[instruction], &[&creator]).await
+        }
+
+        pub async fn remove_house_account(
+            &mut self,
+            bettor: usize,
+        ) -> std::result::Result<(), BanksClientError> {
+            let creator = self.creator.insecure_clone();
+            let account = self.bettors[bettor].pubkey();
+            let instruction = remove_house_account(&creator.pubkey(), self.market_id, &account);
+            process(&mut self.context, &[instruction], &[&creator]).await
+        }
+
+        pub async fn withdraw_tips(&mut self) -> std::result::Result<(), BanksClientError> {
+            let creator = self.creator.insecure_clone();
+            let instruction = withdraw_tips(&creator.pubkey(), self.market_id);
+            process(&mut self.context, &[instruction], &[&creator]).await
+        }
+
+        pub async fn refund(&mut self, bettor: usize) -> std::result::Result<(), BanksClientError> {
+            let claimer = self.bettors[bettor].insecure_clone();
+            let instruction = claim_refund(&claimer.pubkey(), self.market_id);
+            process(&mut self.context, &[instruction], &[&claimer]).await
+        }
+
+        pub async fn withdraw_fees(
+            &mut self,
+            amount: u64,
+        ) -> std::result::Result<(), BanksClientError> {
+            let creator = self.creator.insecure_clone();
+            let instruction = withdraw_fees(&creator.pubkey(), self.market_id, amount);
+            process(&mut self.context, &[instruction], &[&creator]).await
+        }
+
+        pub async fn cancel_prediction(
+            &mut self,
+            bettor: usize,
+        ) -> std::result::Result<(), BanksClientError> {
+            let predictor = self.bettors[bettor].insecure_clone();
+            let instruction = cancel_prediction(&predictor.pubkey(), self.market_id);
+            process(&mut self.context, &[instruction], &[&predictor]).await
+        }
+
+        pub async fn self_exclude(
+            &mut self,
+            bettor: usize,
+            until: i64,
+        ) -> std::result::Result<(), BanksClientError> {
+            let user = self.bettors[bettor].insecure_clone();
+            let instruction = self_exclude(&user.pubkey(), until);
+            process(&mut self.context, &[instruction], &[&user]).await
+        }
+
+        pub async fn close_self_exclusion(
+            &mut self,
+            bettor: usize,
+        ) -> std::result::Result<(), BanksClientError> {
+            let user = self.bettors[bettor].insecure_clone();
+            let instruction = close_self_exclusion(&user.pubkey());
+            process(&mut self.context, &[instruction], &[&user]).await
+        }
+
+        pub async fn migrate(&mut self) -> std::result::Result<(), BanksClientError> {
+            let instruction = migrate_market(&self.context.payer.pubkey(), self.market_id);
+            process(&mut self.context, &[instruction], &[]).await
+        }
+
+        pub async fn balance(&mut self, address: Pubkey) -> u64 {
+            self.context.banks_client.get_balance(address).await.unwrap()
+        }
+
+        /// Vault lamports above rent, as the program counts them
+        pub async fn vault_balance(&mut self) -> u64 {
+            let balance = self.balance(vault_pda(self.market_id)).await;
+            let rent = self.context.banks_client.get_rent().await.unwrap();
+            balance.saturating_sub(rent.minimum_balance(0))
+        }
+    }
+}
+
+// ==================== ACCOUNTS ====================
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Config::INIT_SPACE,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + MarketCounter::INIT_SPACE,
+        seeds = [b"counter"],
+        bump
+    )]
+    pub market_counter: Account<'info, MarketCounter>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + InsuranceFund::INIT_SPACE,
+        seeds = [b"insurance"],
+        bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// This program's ProgramData; only its upgrade authority may claim the
+    /// config, so nobody can front-run the deployer to it
+    #[account(
+        seeds = [crate::ID.as_ref()],
+        bump,
+        seeds::program = bpf_loader_upgradeable::ID,
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+            @ MarketError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(nonce: u64)]
+pub struct CreateAdminMultisig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + AdminMultisig::INIT_SPACE,
+        seeds = [b"admin_multisig", nonce.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub multisig: Account<'info, AdminMultisig>,
+
+    /// Signs as the config admin for the multisig
+    #[account(seeds = [b"multisig_signer", multisig.key().as_ref()], bump)]
+    pub multisig_signer: SystemAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CreateAdminTx<'info> {
+    #[account(
+        mut,
+        seeds = [b"admin_multisig", multisig.nonce.to_le_bytes().as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, AdminMultisig>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + AdminTx::INIT_SPACE,
+        seeds = [
+            b"admin_tx",
+            multisig.key().as_ref(),
+            multisig.tx_count.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub admin_tx: Account<'info, AdminTx>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(index: u64)]
+pub struct ApproveAdminTx<'info> {
+    #[account(
+        seeds = [b"admin_multisig", multisig.nonce.to_le_bytes().as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, AdminMultisig>,
+
+    #[account(
+        mut,
+        seeds = [b"admin_tx", multisig.key().as_ref(), index.to_le_bytes().as_ref()],
+        bump = admin_tx.bump
+    )]
+    pub admin_tx: Account<'info, AdminTx>,
+
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(index: u64)]
+pub struct ExecuteAdminTx<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.admin == multisig_signer.key() @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [b"admin_multisig", multisig.nonce.to_le_bytes().as_ref()],
+        bump = multisig.bump
+    )]
+    pub multisig: Account<'info, AdminMultisig>,
+
+    #[account(
+        mut,
+        seeds = [b"admin_tx", multisig.key().as_ref(), index.to_le_bytes().as_ref()],
+        bump = admin_tx.bump
+    )]
+    pub admin_tx: Account<'info, AdminTx>,
+
+    /// Signs an Invoke action's instruction as the config admin
+    #[account(
+        mut,
+        seeds = [b"multisig_signer", multisig.key().as_ref()],
+        bump = multisig.signer_bump
+    )]
+    pub multisig_signer: SystemAccount<'info>,
+
+    /// The market an EmergencyCancelMarket action voids
+    #[account(
+        mut,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion
+    )]
+    pub market: Option<Account<'info, Market>>,
+
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(creator: Pubkey)]
+pub struct ApproveCreator<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ MarketError::Unauthorized)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + CreatorAllow::INIT_SPACE,
+        seeds = [b"creator_allow", creator.as_ref()],
+        bump
+    )]
+    pub creator_allow: Account<'info, CreatorAllow>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(maker: Pubkey)]
+pub struct ApproveMarketMaker<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ MarketError::Unauthorized)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + MarketMakerRegistration::INIT_SPACE,
+        seeds = [b"market_maker", maker.as_ref()],
+        bump
+    )]
+    pub registration: Account<'info, MarketMakerRegistration>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RevokeMarketMaker<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ MarketError::Unauthorized)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market_maker", maker.key().as_ref()],
+        bump = registration.bump,
+        close = admin
+    )]
+    pub registration: Account<'info, MarketMakerRegistration>,
+
+    /// CHECK: The revoked maker, paid its unclaimed rebates
+    #[account(mut)]
+    pub maker: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimMmRebates<'info> {
+    #[account(
+        mut,
+        seeds = [b"market_maker", maker.key().as_ref()],
+        bump = registration.bump,
+        has_one = maker @ MarketError::Unauthorized
+    )]
+    pub registration: Account<'info, MarketMakerRegistration>,
+
+    #[account(mut)]
+    pub maker: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(creator: Pubkey)]
+pub struct RevokeCreator<'info> {
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ MarketError::Unauthorized)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"creator_allow", creator.as_ref()],
+        bump = creator_allow.bump,
+        close = admin
+    )]
+    pub creator_allow: Account<'info, CreatorAllow>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ProposeConfigUpdate<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + PendingConfig::INIT_SPACE,
+        seeds = [b"pending_config"],
+        bump
+    )]
+    pub pending_config: Account<'info, PendingConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ExecuteConfigUpdate<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"pending_config"],
+        bump = pending_config.bump
+    )]
+    pub pending_config: Account<'info, PendingConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CancelConfigUpdate<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"pending_config"],
+        bump = pending_config.bump
+    )]
+    pub pending_config: Account<'info, PendingConfig>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct AdvanceEpoch<'info> {
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + LeaderboardEpoch::INIT_SPACE,
+        seeds = [b"leaderboard", config.current_epoch.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub leaderboard: Account<'info, LeaderboardEpoch>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(epoch: u64)]
+pub struct SubmitEpochScore<'info> {
+    #[account(
+        mut,
+        seeds = [b"leaderboard", epoch.to_le_bytes().as_ref()],
+        bump = leaderboard.bump
+    )]
+    pub leaderboard: Account<'info, LeaderboardEpoch>,
+
+    #[account(
+        seeds = [b"epoch_stats", epoch_stats.user.as_ref(), epoch.to_le_bytes().as_ref()],
+        bump = epoch_stats.bump
+    )]
+    pub epoch_stats: Account<'info, UserEpochStats>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(
+    question: String,
+    resolution_time: i64,
+    initial_liquidity: u64,
+    initial_yes_probability_bps: u16,
+    fee_bps: u16,
+    options: MarketOptions
+)]
+pub struct InitializeMarket<'info> {
+    #[account(
+        mut,
+        seeds = [b"counter"],
+        bump = market_counter.bump
+    )]
+    pub market_counter: Account<'info, MarketCounter>,
+
+    /// YES outcome mint, with the market as mint authority; omit, with
+    /// no_mint, for a market without outcome tokens
+    #[account(mint::authority = market)]
+    pub yes_mint: Option<Account<'info, Mint>>,
+
+    /// NO outcome mint, likewise
+    #[account(mint::authority = market)]
+    pub no_mint: Option<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = Market::space(yes_mint.is_some(), options.pool_history_len),
+        seeds = [b"market", market_counter.next_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + MarketRegistryPage::INIT_SPACE,
+        seeds = [b"registry", &config.next_registry_page().to_le_bytes()[..]],
+        bump
+    )]
+    pub registry_page: Account<'info, MarketRegistryPage>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    /// CHECK: Market vault for holding SOL, initialized with zero space
+    #[account(
+        init,
+        payer = creator,
+        space = 0,
+        seeds = [b"vault", market_counter.next_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// Registry entry for the question and resolution day; an existing one
+    /// marks the market a duplicate
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + QuestionRegistry::INIT_SPACE,
+        seeds = [b"question", &question_hash(&question, resolution_time)[..]],
+        bump
+    )]
+    pub question_registry: Account<'info, QuestionRegistry>,
+
+    /// Config admin approving a duplicate question, e.g. to re-run a market
+    #[account(constraint = duplicate_approver.key() == config.admin @ MarketError::Unauthorized)]
+    pub duplicate_approver: Option<Signer<'info>>,
+
+    /// Series the market joins at creation; must belong to the creator
+    #[account(mut)]
+    pub series: Option<Account<'info, MarketSeries>>,
+
+    /// Parent market of a conditional market, named in options.parent
+    #[account(
+        constraint = parent_market.version == MARKET_VERSION
+            @ MarketError::UnsupportedAccountVersion
+    )]
+    pub parent_market: Option<Account<'info, Market>>,
+
+    /// The creator's approval, required while creation is whitelisted
+    #[account(seeds = [b"creator_allow", creator.key().as_ref()], bump = creator_allow.bump)]
+    pub creator_allow: Option<Account<'info, CreatorAllow>>,
+
+    /// Counts the creator's markets against the daily creation limit
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + CreatorStats::INIT_SPACE,
+        seeds = [b"creator_stats", creator.key().as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    /// Receives the config's creation fee; required when one is charged
+    #[account(mut, seeds = [b"treasury"], bump = treasury.bump)]
+    pub treasury: Option<Account<'info, Treasury>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(
+    question: String,
+    resolution_time: i64,
+    initial_liquidity: u64,
+    initial_yes_probability_bps: u16,
+    fee_bps: u16,
+    options: MarketOptions
+)]
+pub struct InitializeMarketAndPredict<'info> {
+    #[account(
+        mut,
+        seeds = [b"counter"],
+        bump = market_counter.bump
+    )]
+    pub market_counter: Account<'info, MarketCounter>,
+
+    /// YES outcome mint, with the market as mint authority; omit, with
+    /// no_mint, for a market without outcome tokens
+    #[account(mint::authority = market)]
+    pub yes_mint: Option<Account<'info, Mint>>,
+
+    /// NO outcome mint, likewise
+    #[account(mint::authority = market)]
+    pub no_mint: Option<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = Market::space(yes_mint.is_some(), options.pool_history_len),
+        seeds = [b"market", market_counter.next_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market: Box<Account<'info, Market>>,
+
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Box<Account<'info, Config>>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + MarketRegistryPage::INIT_SPACE,
+        seeds = [b"registry", &config.next_registry_page().to_le_bytes()[..]],
+        bump
+    )]
+    pub registry_page: Box<Account<'info, MarketRegistryPage>>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    /// CHECK: Market vault for holding SOL, initialized with zero space
+    #[account(
+        init,
+        payer = creator,
+        space = 0,
+        seeds = [b"vault", market_counter.next_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// Registry entry for the question and resolution day; an existing one
+    /// marks the market a duplicate
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + QuestionRegistry::INIT_SPACE,
+        seeds = [b"question", &question_hash(&question, resolution_time)[..]],
+        bump
+    )]
+    pub question_registry: Box<Account<'info, QuestionRegistry>>,
+
+    /// Config admin approving a duplicate question, e.g. to re-run a market
+    #[account(constraint = duplicate_approver.key() == config.admin @ MarketError::Unauthorized)]
+    pub duplicate_approver: Option<Signer<'info>>,
+
+    /// Series the market joins at creation; must belong to the creator
+    #[account(mut)]
+    pub series: Option<Account<'info, MarketSeries>>,
+
+    /// Parent market of a conditional market, named in options.parent
+    #[account(
+        constraint = parent_market.version == MARKET_VERSION
+            @ MarketError::UnsupportedAccountVersion
+    )]
+    pub parent_market: Option<Account<'info, Market>>,
+
+    /// The creator's approval, required while creation is whitelisted
+    #[account(seeds = [b"creator_allow", creator.key().as_ref()], bump = creator_allow.bump)]
+    pub creator_allow: Option<Account<'info, CreatorAllow>>,
+
+    /// Counts the creator's markets against the daily creation limit, and
+    /// the first bet against its open liability
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + CreatorStats::INIT_SPACE,
+        seeds = [b"creator_stats", creator.key().as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    /// Receives the config's creation fee; required when one is charged
+    #[account(mut, seeds = [b"treasury"], bump = treasury.bump)]
+    pub treasury: Option<Account<'info, Treasury>>,
+
+    /// The creator's first prediction
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + Prediction::INIT_SPACE,
+        seeds = [
+            b"prediction",
+            market_counter.next_id.to_le_bytes().as_ref(),
+            creator.key().as_ref()
+        ],
+        bump
+    )]
+    pub prediction_account: Box<Account<'info, Prediction>>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", creator.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Box<Account<'info, UserProfile>>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + UserMarketIndex::INIT_SPACE,
+        seeds = [b"index", market.key().as_ref(), creator.key().as_ref()],
+        bump
+    )]
+    pub user_market_index: Box<Account<'info, UserMarketIndex>>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + UserIndexPage::INIT_SPACE,
+        seeds = [
+            b"user_index",
+            creator.key().as_ref(),
+            &user_profile.next_index_page().to_le_bytes()[..]
+        ],
+        bump
+    )]
+    pub user_index_page: Box<Account<'info, UserIndexPage>>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + UserEpochStats::INIT_SPACE,
+        seeds = [
+            b"epoch_stats",
+            creator.key().as_ref(),
+            config.current_epoch.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub epoch_stats: Box<Account<'info, UserEpochStats>>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer", referrer.owner.as_ref()],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+
+    /// Membership NFT held by the creator, for the fee discount
+    pub nft_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Token Metadata account for the NFT; owner, address and
+    /// collection are verified in discount_nft_verified
+    pub nft_metadata: Option<UncheckedAccount<'info>>,
+
+    #[account(mut, seeds = [b"insurance"], bump = insurance_fund.bump)]
+    pub insurance_fund: Box<Account<'info, InsuranceFund>>,
+
+    /// CHECK: The creator's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", creator.key().as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(
+    market_id: u64,
+    question: String,
+    resolution_time: i64,
+    initial_liquidity: u64,
+    initial_yes_probability_bps: u16,
+    fee_bps: u16,
+    options: MarketOptions
+)]
+pub struct InitializeMarketWithId<'info> {
+    /// YES outcome mint, with the market as mint authority; omit, with
+    /// no_mint, for a market without outcome tokens
+    #[account(mint::authority = market)]
+    pub yes_mint: Option<Account<'info, Mint>>,
+
+    /// NO outcome mint, likewise
+    #[account(mint::authority = market)]
+    pub no_mint: Option<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = Market::space(yes_mint.is_some(), options.pool_history_len),
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + MarketRegistryPage::INIT_SPACE,
+        seeds = [b"registry", &config.next_registry_page().to_le_bytes()[..]],
+        bump
+    )]
+    pub registry_page: Account<'info, MarketRegistryPage>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    /// CHECK: Market vault for holding SOL, initialized with zero space
+    #[account(
+        init,
+        payer = creator,
+        space = 0,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// Registry entry for the question and resolution day; an existing one
+    /// marks the market a duplicate
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + QuestionRegistry::INIT_SPACE,
+        seeds = [b"question", &question_hash(&question, resolution_time)[..]],
+        bump
+    )]
+    pub question_registry: Account<'info, QuestionRegistry>,
+
+    /// Config admin approving a duplicate question, e.g. to re-run a market
+    #[account(constraint = duplicate_approver.key() == config.admin @ MarketError::Unauthorized)]
+    pub duplicate_approver: Option<Signer<'info>>,
+
+    /// Series the market joins at creation; must belong to the creator
+    #[account(mut)]
+    pub series: Option<Account<'info, MarketSeries>>,
+
+    /// Parent market of a conditional market, named in options.parent
+    #[account(
+        constraint = parent_market.version == MARKET_VERSION
+            @ MarketError::UnsupportedAccountVersion
+    )]
+    pub parent_market: Option<Account<'info, Market>>,
+
+    /// The creator's approval, required while creation is whitelisted
+    #[account(seeds = [b"creator_allow", creator.key().as_ref()], bump = creator_allow.bump)]
+    pub creator_allow: Option<Account<'info, CreatorAllow>>,
+
+    /// Counts the creator's markets against the daily creation limit
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + CreatorStats::INIT_SPACE,
+        seeds = [b"creator_stats", creator.key().as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    /// Receives the config's creation fee; required when one is charged
+    #[account(mut, seeds = [b"treasury"], bump = treasury.bump)]
+    pub treasury: Option<Account<'info, Treasury>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(name: String)]
+pub struct CreateTemplate<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + MarketTemplate::INIT_SPACE,
+        seeds = [b"template", creator.key().as_ref(), name.as_bytes()],
+        bump
+    )]
+    pub template: Account<'info, MarketTemplate>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateTemplate<'info> {
+    #[account(
+        mut,
+        seeds = [b"template", creator.key().as_ref(), template.name.as_bytes()],
+        bump = template.bump,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub template: Account<'info, MarketTemplate>,
+
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CreateMarketFromTemplate<'info> {
+    #[account(
+        mut,
+        seeds = [b"counter"],
+        bump = market_counter.bump
+    )]
+    pub market_counter: Account<'info, MarketCounter>,
+
+    #[account(
+        mut,
+        seeds = [b"template", creator.key().as_ref(), template.name.as_bytes()],
+        bump = template.bump,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub template: Account<'info, MarketTemplate>,
+
+    /// YES outcome mint, with the market as mint authority; omit, with
+    /// no_mint, for a market without outcome tokens
+    #[account(mint::authority = market)]
+    pub yes_mint: Option<Account<'info, Mint>>,
+
+    /// NO outcome mint, likewise
+    #[account(mint::authority = market)]
+    pub no_mint: Option<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = Market::space(yes_mint.is_some(), template.options.pool_history_len),
+        seeds = [b"market", market_counter.next_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + MarketRegistryPage::INIT_SPACE,
+        seeds = [b"registry", &config.next_registry_page().to_le_bytes()[..]],
+        bump
+    )]
+    pub registry_page: Account<'info, MarketRegistryPage>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    /// CHECK: Market vault for holding SOL, initialized with zero space
+    #[account(
+        init,
+        payer = creator,
+        space = 0,
+        seeds = [b"vault", market_counter.next_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// The creator's approval, required while creation is whitelisted
+    #[account(seeds = [b"creator_allow", creator.key().as_ref()], bump = creator_allow.bump)]
+    pub creator_allow: Option<Account<'info, CreatorAllow>>,
+
+    /// Counts the creator's markets against the daily creation limit
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + CreatorStats::INIT_SPACE,
+        seeds = [b"creator_stats", creator.key().as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    /// Receives the config's creation fee; required when one is charged
+    #[account(mut, seeds = [b"treasury"], bump = treasury.bump)]
+    pub treasury: Option<Account<'info, Treasury>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(source_market_id: u64, new_market_id: u64, new_resolution_time: i64)]
+pub struct CloneMarket<'info> {
+    #[account(
+        seeds = [b"market", source_market_id.to_le_bytes().as_ref()],
+        bump = source_market.bump,
+        constraint = source_market.market_id == source_market_id @ MarketError::MarketMismatch
+    )]
+    pub source_market: Account<'info, Market>,
+
+    /// The source's parent market, when the source is conditional
+    #[account(
+        constraint = parent_market.version == MARKET_VERSION
+            @ MarketError::UnsupportedAccountVersion
+    )]
+    pub parent_market: Option<Account<'info, Market>>,
+
+    /// YES outcome mint, with the market as mint authority; omit, with
+    /// no_mint, for a market without outcome tokens
+    #[account(mint::authority = market)]
+    pub yes_mint: Option<Account<'info, Mint>>,
+
+    /// NO outcome mint, likewise
+    #[account(mint::authority = market)]
+    pub no_mint: Option<Account<'info, Mint>>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = Market::space(yes_mint.is_some(), source_market.pool_history_len),
+        seeds = [b"market", new_market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(mut, seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + MarketRegistryPage::INIT_SPACE,
+        seeds = [b"registry", &config.next_registry_page().to_le_bytes()[..]],
+        bump
+    )]
+    pub registry_page: Account<'info, MarketRegistryPage>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    /// CHECK: Market vault for holding SOL, initialized with zero space
+    #[account(
+        init,
+        payer = creator,
+        space = 0,
+        seeds = [b"vault", new_market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// Registry entry for the copied question and the new resolution day
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + QuestionRegistry::INIT_SPACE,
+        seeds = [
+            b"question",
+            &question_hash(&source_market.question, new_resolution_time)[..]
+        ],
+        bump
+    )]
+    pub question_registry: Account<'info, QuestionRegistry>,
+
+    /// Config admin approving a duplicate question
+    #[account(constraint = duplicate_approver.key() == config.admin @ MarketError::Unauthorized)]
+    pub duplicate_approver: Option<Signer<'info>>,
+
+    /// The creator's approval, required while creation is whitelisted
+    #[account(seeds = [b"creator_allow", creator.key().as_ref()], bump = creator_allow.bump)]
+    pub creator_allow: Option<Account<'info, CreatorAllow>>,
+
+    /// Counts the creator's markets against the daily creation limit
+    #[account(
+        init_if_needed,
+        payer = creator,
+        space = 8 + CreatorStats::INIT_SPACE,
+        seeds = [b"creator_stats", creator.key().as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    /// Receives the config's creation fee; required when one is charged
+    #[account(mut, seeds = [b"treasury"], bump = treasury.bump)]
+    pub treasury: Option<Account<'info, Treasury>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(name: String)]
+pub struct CreateSeries<'info> {
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + MarketSeries::INIT_SPACE,
+        seeds = [b"series", creator.key().as_ref(), name.as_bytes()],
+        bump
+    )]
+    pub series: Account<'info, MarketSeries>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SeriesMembership<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"series", creator.key().as_ref(), series.name.as_bytes()],
+        bump = series.bump,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub series: Account<'info, MarketSeries>,
+
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64, description: String)]
+pub struct SetDescription<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized,
+        realloc = Market::space(market.outcome_mints.is_some(), market.pool_history_len)
+            + description.len().min(MAX_DESCRIPTION_LEN),
+        realloc::payer = creator,
+        realloc::zero = true
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SetMarketFee<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SetPushPolicy<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct UpdateOdds<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ResetCircuitBreaker<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct UpdateResolvers<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct UpdateHouseAccounts<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SettleParentCondition<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        constraint = market.parent.map(|p| p.market) == Some(parent_market.key())
+            @ MarketError::InvalidParentMarket,
+        constraint = parent_market.version == MARKET_VERSION
+            @ MarketError::UnsupportedAccountVersion
+    )]
+    pub parent_market: Account<'info, Market>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct RevokeResolver<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// The market's creator or the config admin
+    pub authority: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct MarketTransfer<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct AcceptMarketTransfer<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        constraint = market.pending_owner == Some(new_owner.key()) @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    pub new_owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct PlacePrediction<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = rent_payer,
+        space = 8 + Prediction::INIT_SPACE,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            predictor.key().as_ref(),
+            &market.generation_seed()[..]
+        ],
+        bump
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        init_if_needed,
+        payer = rent_payer,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", predictor.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init_if_needed,
+        payer = rent_payer,
+        space = 8 + UserMarketIndex::INIT_SPACE,
+        seeds = [b"index", market.key().as_ref(), predictor.key().as_ref()],
+        bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    #[account(
+        init_if_needed,
+        payer = rent_payer,
+        space = 8 + UserIndexPage::INIT_SPACE,
+        seeds = [
+            b"user_index",
+            predictor.key().as_ref(),
+            &user_profile.next_index_page().to_le_bytes()[..]
+        ],
+        bump
+    )]
+    pub user_index_page: Account<'info, UserIndexPage>,
+
+    #[account(
+        init_if_needed,
+        payer = rent_payer,
+        space = 8 + UserEpochStats::INIT_SPACE,
+        seeds = [
+            b"epoch_stats",
+            predictor.key().as_ref(),
+            config.current_epoch.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub epoch_stats: Account<'info, UserEpochStats>,
+
+    #[account(
+        mut,
+        seeds = [b"referrer", referrer.owner.as_ref()],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+
+    /// Membership NFT held by the predictor, for the fee discount
+    pub nft_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Token Metadata account for the NFT; owner, address and
+    /// collection are verified in discount_nft_verified
+    pub nft_metadata: Option<UncheckedAccount<'info>>,
+
+    /// The market's series, required when it belongs to one
+    #[account(mut)]
+    pub series: Option<Account<'info, MarketSeries>>,
+
+    #[account(mut, seeds = [b"insurance"], bump = insurance_fund.bump)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    /// wSOL collateral of a `wsol_vault` market, created by init_wsol_vault
+    #[account(mut, address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Open deposits across the market creator's markets
+    #[account(
+        init_if_needed,
+        payer = rent_payer,
+        space = 8 + CreatorStats::INIT_SPACE,
+        seeds = [b"creator_stats", market.liability_creator.as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    /// CHECK: The predictor's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", predictor.key().as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    /// Pays the deposit
+    #[account(mut)]
+    pub predictor: Signer<'info>,
+
+    /// Pays the rent of the accounts created for the bet, and gets the
+    /// prediction's back when it is closed. Usually the predictor itself
+    #[account(mut)]
+    pub rent_payer: Signer<'info>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct InitReceiptTree<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = creator @ MarketError::Unauthorized,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + ReceiptTree::INIT_SPACE,
+        seeds = [b"receipt_tree", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub receipt_tree: Account<'info, ReceiptTree>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct PlaceCompressedPrediction<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"receipt_tree", market_id.to_le_bytes().as_ref()],
+        bump = receipt_tree.bump
+    )]
+    pub receipt_tree: Account<'info, ReceiptTree>,
+
+    #[account(mut, seeds = [b"insurance"], bump = insurance_fund.bump)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    /// Open deposits across the market creator's markets
+    #[account(
+        init_if_needed,
+        payer = predictor,
+        space = 8 + CreatorStats::INIT_SPACE,
+        seeds = [b"creator_stats", market.liability_creator.as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    /// CHECK: The predictor's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", predictor.key().as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub predictor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64, receipt: CompressedReceipt)]
+pub struct ClaimCompressed<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault paying out claims
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// Bitmap page holding the receipt's claimed bit; the first claimer
+    /// on a page pays its rent
+    #[account(
+        init_if_needed,
+        payer = claimer,
+        space = 8 + ReceiptClaims::INIT_SPACE,
+        seeds = [
+            b"receipt_claims",
+            market_id.to_le_bytes().as_ref(),
+            &receipt.claims_page().to_le_bytes()[..]
+        ],
+        bump
+    )]
+    pub receipt_claims: Box<Account<'info, ReceiptClaims>>,
+
+    #[account(mut)]
+    pub claimer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CommitBet<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// Holds the escrowed lamports until reveal or refund
+    #[account(
+        init,
+        payer = bettor,
+        space = 8 + BetCommit::INIT_SPACE,
+        seeds = [b"bet_commit", market_id.to_le_bytes().as_ref(), bettor.key().as_ref()],
+        bump
+    )]
+    pub bet_commit: Account<'info, BetCommit>,
+
+    /// CHECK: The bettor's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", bettor.key().as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub bettor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct RevealBet<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"bet_commit", market_id.to_le_bytes().as_ref(), bettor.key().as_ref()],
+        bump = bet_commit.bump,
+        close = bettor
+    )]
+    pub bet_commit: Account<'info, BetCommit>,
+
+    #[account(
+        init,
+        payer = bettor,
+        space = 8 + Prediction::INIT_SPACE,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            bettor.key().as_ref(),
+            &market.generation_seed()[..]
+        ],
+        bump
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        init_if_needed,
+        payer = bettor,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", bettor.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init_if_needed,
+        payer = bettor,
+        space = 8 + UserMarketIndex::INIT_SPACE,
+        seeds = [b"index", market.key().as_ref(), bettor.key().as_ref()],
+        bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    #[account(
+        init_if_needed,
+        payer = bettor,
+        space = 8 + UserIndexPage::INIT_SPACE,
+        seeds = [
+            b"user_index",
+            bettor.key().as_ref(),
+            &user_profile.next_index_page().to_le_bytes()[..]
+        ],
+        bump
+    )]
+    pub user_index_page: Account<'info, UserIndexPage>,
+
+    #[account(
+        init_if_needed,
+        payer = bettor,
+        space = 8 + UserEpochStats::INIT_SPACE,
+        seeds = [
+            b"epoch_stats",
+            bettor.key().as_ref(),
+            config.current_epoch.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub epoch_stats: Account<'info, UserEpochStats>,
+
+    /// The market's series, required when it belongs to one
+    #[account(mut)]
+    pub series: Option<Account<'info, MarketSeries>>,
+
+    #[account(mut, seeds = [b"insurance"], bump = insurance_fund.bump)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(mut)]
+    pub bettor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct RefundBetCommit<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"bet_commit", market_id.to_le_bytes().as_ref(), bettor.key().as_ref()],
+        bump = bet_commit.bump,
+        close = bettor
+    )]
+    pub bet_commit: Account<'info, BetCommit>,
+
+    #[account(mut)]
+    pub bettor: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct PlaceLimitEntry<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + LimitEntry::INIT_SPACE,
+        seeds = [b"limit_entry", market_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub limit_entry: Account<'info, LimitEntry>,
+
+    /// CHECK: The owner's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", owner.key().as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct TriggerLimitEntry<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"limit_entry", market_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump = limit_entry.bump,
+        close = owner
+    )]
+    pub limit_entry: Account<'info, LimitEntry>,
+
+    /// Receives the rest of the escrow when the entry closes
+    #[account(mut)]
+    pub owner: SystemAccount<'info>,
+
+    #[account(
+        init,
+        payer = keeper,
+        space = 8 + Prediction::INIT_SPACE,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            owner.key().as_ref(),
+            &market.generation_seed()[..]
+        ],
+        bump
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", owner.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = 8 + UserMarketIndex::INIT_SPACE,
+        seeds = [b"index", market.key().as_ref(), owner.key().as_ref()],
+        bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = 8 + UserIndexPage::INIT_SPACE,
+        seeds = [
+            b"user_index",
+            owner.key().as_ref(),
+            &user_profile.next_index_page().to_le_bytes()[..]
+        ],
+        bump
+    )]
+    pub user_index_page: Account<'info, UserIndexPage>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = 8 + UserEpochStats::INIT_SPACE,
+        seeds = [
+            b"epoch_stats",
+            owner.key().as_ref(),
+            config.current_epoch.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub epoch_stats: Account<'info, UserEpochStats>,
+
+    /// The market's series, required when it belongs to one
+    #[account(mut)]
+    pub series: Option<Account<'info, MarketSeries>>,
+
+    #[account(mut, seeds = [b"insurance"], bump = insurance_fund.bump)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CancelLimitEntry<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"limit_entry", market_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump = limit_entry.bump,
+        close = owner
+    )]
+    pub limit_entry: Account<'info, LimitEntry>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ExpireLimitEntry<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"limit_entry", market_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump = limit_entry.bump,
+        close = owner
+    )]
+    pub limit_entry: Account<'info, LimitEntry>,
+
+    /// Receives the escrow; bound to the entry by its seeds
+    #[account(mut)]
+    pub owner: SystemAccount<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: Market vault paying the keeper tip
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(delegate: Pubkey)]
+pub struct CreateTradingDelegate<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + TradingDelegate::INIT_SPACE,
+        seeds = [b"trading_delegate", owner.key().as_ref(), delegate.as_ref()],
+        bump
+    )]
+    pub trading_delegate: Account<'info, TradingDelegate>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct PlacePredictionDelegated<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"trading_delegate",
+            trading_delegate.owner.as_ref(),
+            delegate.key().as_ref()
+        ],
+        bump = trading_delegate.bump,
+        has_one = delegate @ MarketError::Unauthorized
+    )]
+    pub trading_delegate: Account<'info, TradingDelegate>,
+
+    #[account(
+        init,
+        payer = delegate,
+        space = 8 + Prediction::INIT_SPACE,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            trading_delegate.owner.as_ref(),
+            &market.generation_seed()[..]
+        ],
+        bump
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        init_if_needed,
+        payer = delegate,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", trading_delegate.owner.as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init_if_needed,
+        payer = delegate,
+        space = 8 + UserMarketIndex::INIT_SPACE,
+        seeds = [b"index", market.key().as_ref(), trading_delegate.owner.as_ref()],
+        bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    #[account(
+        init_if_needed,
+        payer = delegate,
+        space = 8 + UserIndexPage::INIT_SPACE,
+        seeds = [
+            b"user_index",
+            trading_delegate.owner.as_ref(),
+            &user_profile.next_index_page().to_le_bytes()[..]
+        ],
+        bump
+    )]
+    pub user_index_page: Account<'info, UserIndexPage>,
+
+    #[account(
+        init_if_needed,
+        payer = delegate,
+        space = 8 + UserEpochStats::INIT_SPACE,
+        seeds = [
+            b"epoch_stats",
+            trading_delegate.owner.as_ref(),
+            config.current_epoch.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub epoch_stats: Account<'info, UserEpochStats>,
+
+    #[account(
+        init_if_needed,
+        payer = delegate,
+        space = 8 + CreatorStats::INIT_SPACE,
+        seeds = [b"creator_stats", market.liability_creator.as_ref()],
+        bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+
+    /// The market's series, required when it belongs to one
+    #[account(mut)]
+    pub series: Option<Account<'info, MarketSeries>>,
+
+    #[account(mut, seeds = [b"insurance"], bump = insurance_fund.bump)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    /// CHECK: The owner's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", trading_delegate.owner.as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub delegate: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RevokeTradingDelegate<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"trading_delegate",
+            owner.key().as_ref(),
+            trading_delegate.delegate.as_ref()
+        ],
+        bump = trading_delegate.bump,
+        close = owner
+    )]
+    pub trading_delegate: Account<'info, TradingDelegate>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SelfExclude<'info> {
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + SelfExclusion::INIT_SPACE,
+        seeds = [b"self_exclusion", user.key().as_ref()],
+        bump
+    )]
+    pub self_exclusion: Account<'info, SelfExclusion>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CloseSelfExclusion<'info> {
+    #[account(
+        mut,
+        seeds = [b"self_exclusion", user.key().as_ref()],
+        bump = self_exclusion.bump,
+        close = user
+    )]
+    pub self_exclusion: Account<'info, SelfExclusion>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct QuotePrediction<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        seeds = [b"referrer", referrer.owner.as_ref()],
+        bump = referrer.bump
+    )]
+    pub referrer: Option<Account<'info, Referrer>>,
+
+    /// Membership NFT held by the predictor, for the fee discount
+    pub nft_token_account: Option<Account<'info, TokenAccount>>,
+
+    /// CHECK: Token Metadata account for the NFT; owner, address and
+    /// collection are verified in discount_nft_verified
+    pub nft_metadata: Option<UncheckedAccount<'info>>,
+
+    /// CHECK: Wallet the quote is for; only its key is read
+    pub predictor: UncheckedAccount<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ResolveMarket<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault; its balance goes into the resolution snapshot
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, checked and snapshotted
+    /// in place of the vault's lamports
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Evidence behind the resolution; created on first use
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + Resolution::INIT_SPACE,
+        seeds = [b"resolution", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub resolution: Account<'info, Resolution>,
+
+    /// One of the market's resolvers; the handler checks its tier's window.
+    /// May be a PDA signing through invoke_signed: it needs no lamports
+    pub admin: Signer<'info>,
+
+    /// Pays for the resolution account
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ResolveViaProgram<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault; its balance goes into the resolution snapshot
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, checked and snapshotted
+    /// in place of the vault's lamports
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Evidence behind the resolution; created on first use
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + Resolution::INIT_SPACE,
+        seeds = [b"resolution", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub resolution: Account<'info, Resolution>,
+
+    /// Pays for the resolution account
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Instructions sysvar, used to check who made the call
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CreatorResolution<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault; its balance goes into the resolution snapshot
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, checked and snapshotted
+    /// in place of the vault's lamports
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Evidence behind the creator's resolution; created on first use
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + Resolution::INIT_SPACE,
+        seeds = [b"resolution", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub resolution: Account<'info, Resolution>,
+
+    /// The market's creator
+    #[account(mut, constraint = market.creator == admin.key() @ MarketError::Unauthorized)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CommitResolution<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// The market's creator
+    #[account(constraint = market.creator == admin.key() @ MarketError::Unauthorized)]
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(feed_id: u64)]
+pub struct CreatePriceFeed<'info> {
+    #[account(
+        init,
+        payer = publisher,
+        space = 8 + PriceFeed::INIT_SPACE,
+        seeds = [b"price_feed", publisher.key().as_ref(), feed_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_feed: Account<'info, PriceFeed>,
+
+    #[account(mut)]
+    pub publisher: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(feed_id: u64)]
+pub struct PublishPrice<'info> {
+    #[account(
+        mut,
+        seeds = [b"price_feed", publisher.key().as_ref(), feed_id.to_le_bytes().as_ref()],
+        bump = price_feed.bump,
+        has_one = publisher @ MarketError::Unauthorized
+    )]
+    pub price_feed: Account<'info, PriceFeed>,
+
+    pub publisher: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ResolveWithOracle<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault; its balance goes into the resolution snapshot
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, checked and snapshotted
+    /// in place of the vault's lamports
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    pub price_feed: Account<'info, PriceFeed>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct RecordPriceSample<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = cranker,
+        space = 8 + PriceSamples::INIT_SPACE,
+        seeds = [b"price_samples", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub price_samples: Account<'info, PriceSamples>,
+
+    pub price_feed: Account<'info, PriceFeed>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ResolveWithTwap<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault; its balance goes into the resolution snapshot
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, checked and snapshotted
+    /// in place of the vault's lamports
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    #[account(
+        seeds = [b"price_samples", market_id.to_le_bytes().as_ref()],
+        bump = price_samples.bump
+    )]
+    pub price_samples: Account<'info, PriceSamples>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64, outcome: bool, timestamp: i64, expires_at: i64)]
+pub struct ResolveWithAttestation<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault; its balance goes into the resolution snapshot
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, checked and snapshotted
+    /// in place of the vault's lamports
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// Created for the attestation; an existing one means it was replayed
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + AttestationRecord::INIT_SPACE,
+        seeds = [
+            b"attestation",
+            &attestation_hash(
+                &market.oracle_key.unwrap_or_default(),
+                &attestation_message(market_id, outcome, timestamp, expires_at)
+            )[..]
+        ],
+        bump
+    )]
+    pub attestation_record: Account<'info, AttestationRecord>,
+
+    /// Relayer paying for the attestation record
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    /// CHECK: Instructions sysvar, used to inspect the ed25519 instruction
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ResolveWithEthAttestation<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault; its balance goes into the resolution snapshot
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, checked and snapshotted
+    /// in place of the vault's lamports
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// CHECK: Instructions sysvar, used to inspect the secp256k1 instruction
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct PurgeAttestationRecords<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct PublishPayoutParams<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault; its balance sets any haircut
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ClaimReward<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL and rewards
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            claimer.key().as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch,
+        constraint = prediction_account.market_id == market.market_id
+            @ MarketError::MarketMismatch,
+        constraint = prediction_account.tokens_received > 0 @ MarketError::NoReward
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        init_if_needed,
+        payer = claimer,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", claimer.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init_if_needed,
+        payer = claimer,
+        space = 8 + UserEpochStats::INIT_SPACE,
+        seeds = [
+            b"epoch_stats",
+            claimer.key().as_ref(),
+            config.current_epoch.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub epoch_stats: Account<'info, UserEpochStats>,
+
+    #[account(mut)]
+    pub claimer: Signer<'info>,
+
+    /// CHECK: Receives the reward in place of the claimer when provided
+    #[account(
+        mut,
+        constraint = recipient.key() != market_vault.key() @ MarketError::InvalidRecipient,
+        constraint = recipient.key() != prediction_account.key() @ MarketError::InvalidRecipient
+    )]
+    pub recipient: Option<UncheckedAccount<'info>>,
+
+    /// wSOL collateral of a `wsol_vault` market, created by init_wsol_vault
+    #[account(mut, address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// wSOL account credited instead of the signer on a `wsol_vault` market
+    #[account(mut, token::mint = market.collateral_mint)]
+    pub payout_token_account: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    #[account(address = market.collateral_mint @ MarketError::WsolVaultRequired)]
+    pub collateral_mint: Option<InterfaceAccount<'info, token_interface::Mint>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+
+    /// Record of this claim, created when claiming `with_receipt`
+    #[account(
+        init,
+        payer = claimer,
+        space = 8 + ClaimReceipt::INIT_SPACE,
+        seeds = [b"receipt", prediction_account.key().as_ref()],
+        bump
+    )]
+    pub claim_receipt: Option<Account<'info, ClaimReceipt>>,
+
+    /// Holds the reward of a claim made while a dispute is open, on a
+    /// market that escrows them
+    #[account(
+        init,
+        payer = claimer,
+        space = 8 + EscrowedClaim::INIT_SPACE,
+        seeds = [b"escrowed_claim", prediction_account.key().as_ref()],
+        bump
+    )]
+    pub escrowed_claim: Option<Account<'info, EscrowedClaim>>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ReleaseEscrowedClaim<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault, credited when the claim is clawed back
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"escrowed_claim", escrowed_claim.prediction.as_ref()],
+        bump = escrowed_claim.bump,
+        constraint = escrowed_claim.market_id == market_id @ MarketError::MarketMismatch,
+        close = claimer
+    )]
+    pub escrowed_claim: Account<'info, EscrowedClaim>,
+
+    #[account(
+        mut,
+        address = escrowed_claim.prediction @ MarketError::EscrowedClaimMismatch,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        mut,
+        seeds = [b"profile", escrowed_claim.claimer.as_ref()],
+        bump = user_profile.bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"epoch_stats",
+            escrowed_claim.claimer.as_ref(),
+            escrowed_claim.epoch.to_le_bytes().as_ref()
+        ],
+        bump = epoch_stats.bump
+    )]
+    pub epoch_stats: Account<'info, UserEpochStats>,
+
+    /// CHECK: Paid the escrow's rent back
+    #[account(mut, address = escrowed_claim.claimer @ MarketError::EscrowedClaimMismatch)]
+    pub claimer: UncheckedAccount<'info>,
+
+    /// CHECK: Paid the reward if the outcome stood
+    #[account(mut, address = escrowed_claim.recipient @ MarketError::EscrowedClaimMismatch)]
+    pub recipient: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CloseClaimReceipt<'info> {
+    #[account(
+        mut,
+        has_one = claimer @ MarketError::Unauthorized,
+        seeds = [b"receipt", claim_receipt.prediction.as_ref()],
+        bump = claim_receipt.bump,
+        close = claimer
+    )]
+    pub claim_receipt: Account<'info, ClaimReceipt>,
+
+    #[account(mut)]
+    pub claimer: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(source_market_id: u64, target_market_id: u64)]
+pub struct ClaimAndRoll<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", source_market_id.to_le_bytes().as_ref()],
+        bump = source_market.bump,
+        constraint = source_market.version == MARKET_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = source_market.market_id == source_market_id @ MarketError::MarketMismatch
+    )]
+    pub source_market: Account<'info, Market>,
+
+    /// CHECK: Vault of the market being claimed from
+    #[account(
+        mut,
+        seeds = [b"vault", source_market_id.to_le_bytes().as_ref()],
+        bump = source_market.vault_bump
+    )]
+    pub source_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"prediction",
+            source_market_id.to_le_bytes().as_ref(),
+            claimer.key().as_ref(),
+            &source_prediction.position_seed()[..],
+            &source_prediction.generation_seed()[..]
+        ],
+        bump = source_prediction.bump,
+        constraint = source_prediction.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = source_prediction.generation.0 == source_market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub source_prediction: Account<'info, Prediction>,
+
+    #[account(
+        mut,
+        seeds = [b"market", target_market_id.to_le_bytes().as_ref()],
+        bump = target_market.bump,
+        constraint = target_market.version == MARKET_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = target_market.market_id == target_market_id @ MarketError::MarketMismatch
+    )]
+    pub target_market: Account<'info, Market>,
+
+    /// CHECK: Vault of the market being bet on
+    #[account(
+        mut,
+        seeds = [b"vault", target_market_id.to_le_bytes().as_ref()],
+        bump = target_market.vault_bump
+    )]
+    pub target_vault: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = claimer,
+        space = 8 + Prediction::INIT_SPACE,
+        seeds = [
+            b"prediction",
+            target_market_id.to_le_bytes().as_ref(),
+            claimer.key().as_ref(),
+            &target_market.generation_seed()[..]
+        ],
+        bump
+    )]
+    pub target_prediction: Account<'info, Prediction>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = claimer,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", claimer.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init_if_needed,
+        payer = claimer,
+        space = 8 + UserMarketIndex::INIT_SPACE,
+        seeds = [b"index", target_market.key().as_ref(), claimer.key().as_ref()],
+        bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    #[account(
+        init_if_needed,
+        payer = claimer,
+        space = 8 + UserIndexPage::INIT_SPACE,
+        seeds = [
+            b"user_index",
+            claimer.key().as_ref(),
+            &user_profile.next_index_page().to_le_bytes()[..]
+        ],
+        bump
+    )]
+    pub user_index_page: Account<'info, UserIndexPage>,
+
+    #[account(
+        init_if_needed,
+        payer = claimer,
+        space = 8 + UserEpochStats::INIT_SPACE,
+        seeds = [
+            b"epoch_stats",
+            claimer.key().as_ref(),
+            config.current_epoch.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub epoch_stats: Account<'info, UserEpochStats>,
+
+    /// The series both markets belong to
+    #[account(mut)]
+    pub series: Account<'info, MarketSeries>,
+
+    #[account(mut, seeds = [b"insurance"], bump = insurance_fund.bump)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    /// CHECK: The claimer's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", claimer.key().as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub claimer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GetClaimable<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            prediction_account.predictor.as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GetMarketHealth<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault; only its balance is read
+    #[account(seeds = [b"vault", market_id.to_le_bytes().as_ref()], bump = market.vault_bump)]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, required for one
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+}
+
+#[derive(Accounts)]
+pub struct ComputeExposure<'info> {
+    /// CHECK: The wallet whose positions are summed; only its key is read
+    pub wallet: UncheckedAccount<'info>,
+
+    #[account(seeds = [b"profile", wallet.key().as_ref()], bump = user_profile.bump)]
+    pub user_profile: Account<'info, UserProfile>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ReplayMarketEvents<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct EmergencyCancelMarket<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ClaimRefund<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault paying out refunds
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            claimer.key().as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(mut)]
+    pub claimer: Signer<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, created by init_wsol_vault
+    #[account(mut, address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// wSOL account credited instead of the signer on a `wsol_vault` market
+    #[account(mut, token::mint = market.collateral_mint)]
+    pub payout_token_account: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    #[account(address = market.collateral_mint @ MarketError::WsolVaultRequired)]
+    pub collateral_mint: Option<InterfaceAccount<'info, token_interface::Mint>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct RefundLiquidity<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault paying out refunds
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, created by init_wsol_vault
+    #[account(mut, address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// wSOL account credited instead of the signer on a `wsol_vault` market
+    #[account(mut, token::mint = market.collateral_mint)]
+    pub payout_token_account: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    #[account(address = market.collateral_mint @ MarketError::WsolVaultRequired)]
+    pub collateral_mint: Option<InterfaceAccount<'info, token_interface::Mint>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SponsorMarket<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = sponsor,
+        space = 8 + Sponsorship::INIT_SPACE,
+        seeds = [b"sponsorship", market_id.to_le_bytes().as_ref(), sponsor.key().as_ref()],
+        bump
+    )]
+    pub sponsorship: Account<'info, Sponsorship>,
+
+    #[account(mut)]
+    pub sponsor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct FundKeeperBudget<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault holding the budget
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ReclaimKeeperBudget<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault holding the budget
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// CHECK: Whoever funded the budget
+    #[account(mut, address = market.keeper_funder @ MarketError::Unauthorized)]
+    pub funder: AccountInfo<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CloseMarket<'info> {
+    #[account(
+        mut,
+        close = creator,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        has_one = creator @ MarketError::Unauthorized,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault, emptied to the creator
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = creator,
+        space = 8 + MarketArchive::INIT_SPACE,
+        seeds = [
+            b"archive",
+            market_id.to_le_bytes().as_ref(),
+            &market.generation_seed()[..]
+        ],
+        bump
+    )]
+    pub archive: Account<'info, MarketArchive>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct TipCreator<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault holding the tips
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub tipper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct WithdrawTips<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault holding the tips
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct RefundSponsorship<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault paying out refunds
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        close = sponsor,
+        seeds = [b"sponsorship", market_id.to_le_bytes().as_ref(), sponsor.key().as_ref()],
+        bump = sponsorship.bump
+    )]
+    pub sponsorship: Account<'info, Sponsorship>,
+
+    #[account(mut)]
+    pub sponsor: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CancelPrediction<'info> {
+    /// Feature flags, checked by early_exit
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault returning the deposit
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        close = rent_payer,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            predictor.key().as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        mut,
+        seeds = [b"index", market.key().as_ref(), predictor.key().as_ref()],
+        bump = user_market_index.bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    #[account(mut)]
+    pub predictor: Signer<'info>,
+
+    /// Gets the rent back
+    #[account(
+        mut,
+        address = prediction_account.rent_recipient() @ MarketError::RentPayerMismatch
+    )]
+    pub rent_payer: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SetStopLoss<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            predictor.key().as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    pub predictor: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ExecuteStopLoss<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault paying out the exit
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        close = rent_payer,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            predictor.key().as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        mut,
+        seeds = [b"index", market.key().as_ref(), predictor.key().as_ref()],
+        bump = user_market_index.bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    /// Receives the proceeds
+    #[account(mut)]
+    pub predictor: SystemAccount<'info>,
+
+    /// Gets the position's rent back
+    #[account(
+        mut,
+        address = prediction_account.rent_recipient() @ MarketError::RentPayerMismatch
+    )]
+    pub rent_payer: SystemAccount<'info>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ClosePrediction<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        close = rent_payer,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            predictor.key().as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        mut,
+        seeds = [b"index", market.key().as_ref(), predictor.key().as_ref()],
+        bump = user_market_index.bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    pub predictor: Signer<'info>,
+
+    /// Gets the rent back
+    #[account(
+        mut,
+        address = prediction_account.rent_recipient() @ MarketError::RentPayerMismatch
+    )]
+    pub rent_payer: SystemAccount<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CreateOutcomeMetadata<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        has_one = creator @ MarketError::Unauthorized
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        constraint = market.outcome_mints.map(|m| m.yes) == Some(yes_mint.key())
+            @ MarketError::InvalidOutcomeMint,
+        mint::authority = market
+    )]
+    pub yes_mint: Account<'info, Mint>,
+
+    #[account(
+        constraint = market.outcome_mints.map(|m| m.no) == Some(no_mint.key())
+            @ MarketError::InvalidOutcomeMint,
+        mint::authority = market
+    )]
+    pub no_mint: Account<'info, Mint>,
+
+    /// CHECK: Created by the Token Metadata program, which checks its address
+    #[account(mut)]
+    pub yes_metadata: UncheckedAccount<'info>,
+
+    /// CHECK: Created by the Token Metadata program, which checks its address
+    #[account(mut)]
+    pub no_metadata: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub creator: Signer<'info>,
+
+    pub token_metadata_program: Program<'info, TokenMetadata>,
+    pub system_program: Program<'info, System>,
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct FinalizeMints<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        constraint = market.outcome_mints.map(|m| m.yes) == Some(yes_mint.key())
+            @ MarketError::InvalidOutcomeMint,
+        mint::authority = market
+    )]
+    pub yes_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = market.outcome_mints.map(|m| m.no) == Some(no_mint.key())
+            @ MarketError::InvalidOutcomeMint,
+        mint::authority = market
+    )]
+    pub no_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct InitWsolVault<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault holding the seed liquidity
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        associated_token::mint = collateral_mint,
+        associated_token::authority = market,
+        associated_token::token_program = token_program
+    )]
+    pub wsol_vault: InterfaceAccount<'info, token_interface::TokenAccount>,
+
+    /// The wrapped SOL mint of the legacy token program or of Token-2022
+    #[account(
+        constraint = collateral_mint.key() == native_mint::ID
+            || collateral_mint.key() == spl_token_2022::native_mint::ID
+            @ MarketError::UnsupportedCollateralMint
+    )]
+    pub collateral_mint: InterfaceAccount<'info, token_interface::Mint>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SplitPosition<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            predictor.key().as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        init_if_needed,
+        payer = predictor,
+        space = 8 + UserMarketIndex::INIT_SPACE,
+        seeds = [b"index", market.key().as_ref(), predictor.key().as_ref()],
+        bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    /// Position index 0 is the wallet's first position, which an index
+    /// created here for a pre-index position accounts for
+    #[account(
+        init,
+        payer = predictor,
+        space = 8 + Prediction::INIT_SPACE,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            predictor.key().as_ref(),
+            &user_market_index.next_position_index.max(1).to_le_bytes()[..],
+            &market.generation_seed()[..]
+        ],
+        bump
+    )]
+    pub new_prediction: Account<'info, Prediction>,
+
+    #[account(mut)]
+    pub predictor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct RecordLoss<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            prediction_account.predictor.as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", prediction_account.predictor.as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SettleLosingPrediction<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            owner.key().as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        init_if_needed,
+        payer = keeper,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", owner.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        mut,
+        seeds = [b"index", market.key().as_ref(), owner.key().as_ref()],
+        bump = user_market_index.bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    /// Owner of the position; bound to it by its seeds
+    pub owner: SystemAccount<'info>,
+
+    /// Receives the rent of a closed position
+    #[account(
+        mut,
+        address = prediction_account.rent_recipient() @ MarketError::RentPayerMismatch
+    )]
+    pub rent_payer: SystemAccount<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    /// CHECK: Market vault paying the keeper tip
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ClaimRebate<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch,
+        constraint = !market.wsol_vault @ MarketError::LamportVaultRequired
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault holding the rebate pot
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            predictor.key().as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(mut)]
+    pub predictor: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct RegisterReferrer<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Referrer::INIT_SPACE,
+        seeds = [b"referrer", owner.key().as_ref()],
+        bump
+    )]
+    pub referrer: Account<'info, Referrer>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimReferralFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"referrer", owner.key().as_ref()],
+        bump = referrer.bump,
+        has_one = owner @ MarketError::Unauthorized
+    )]
+    pub referrer: Account<'info, Referrer>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct WithdrawFees<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for withdrawing collected fees
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// The market's creator, credited with the fees
+    #[account(mut, constraint = market.creator == admin.key() @ MarketError::Unauthorized)]
+    pub admin: Signer<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market, created by init_wsol_vault
+    #[account(mut, address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    /// wSOL account credited instead of the signer on a `wsol_vault` market
+    #[account(mut, token::mint = market.collateral_mint)]
+    pub payout_token_account: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+
+    #[account(address = market.collateral_mint @ MarketError::WsolVaultRequired)]
+    pub collateral_mint: Option<InterfaceAccount<'info, token_interface::Mint>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct InitTreasury<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Treasury::INIT_SPACE,
+        seeds = [b"treasury"],
+        bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CollectFeesMany<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury"],
+        bump = treasury.bump,
+        constraint = config.treasury == treasury.key() @ MarketError::TreasuryNotSet
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    /// Required once config.staking_vault is set
+    #[account(mut, seeds = [b"staking_vault"], bump = staking_vault.bump)]
+    pub staking_vault: Option<Account<'info, StakingVault>>,
+
+    /// Required once config.buyback_escrow is set
+    #[account(mut, seeds = [b"buyback_escrow"], bump = buyback_escrow.bump)]
+    pub buyback_escrow: Option<Account<'info, BuybackEscrow>>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ProposeTreasuryWithdrawal<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + PendingTreasuryWithdrawal::INIT_SPACE,
+        seeds = [b"pending_withdrawal"],
+        bump
+    )]
+    pub pending_withdrawal: Account<'info, PendingTreasuryWithdrawal>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ExecuteTreasuryWithdrawal<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [b"treasury"], bump = treasury.bump)]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"pending_withdrawal"],
+        bump = pending_withdrawal.bump
+    )]
+    pub pending_withdrawal: Account<'info, PendingTreasuryWithdrawal>,
+
+    /// CHECK: Receives lamports only; must be the proposed recipient
+    #[account(mut, address = pending_withdrawal.recipient @ MarketError::InvalidRecipient)]
+    pub recipient: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CancelTreasuryWithdrawal<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"pending_withdrawal"],
+        bump = pending_withdrawal.bump
+    )]
+    pub pending_withdrawal: Account<'info, PendingTreasuryWithdrawal>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ConfigureStaking<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + StakingVault::INIT_SPACE,
+        seeds = [b"staking_vault"],
+        bump
+    )]
+    pub staking_vault: Account<'info, StakingVault>,
+
+    /// Holds staked tokens, owned by the staking vault
+    #[account(
+        init_if_needed,
+        payer = admin,
+        seeds = [b"staking_tokens"],
+        bump,
+        token::mint = staking_mint,
+        token::authority = staking_vault
+    )]
+    pub staking_tokens: Account<'info, TokenAccount>,
+
+    pub staking_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Stake<'info> {
+    #[account(mut, seeds = [b"staking_vault"], bump = staking_vault.bump)]
+    pub staking_vault: Account<'info, StakingVault>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_tokens"],
+        bump = staking_vault.tokens_bump
+    )]
+    pub staking_tokens: Account<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + StakePosition::INIT_SPACE,
+        seeds = [b"stake_position", owner.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, StakePosition>,
+
+    #[account(mut, token::authority = owner)]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Unstake<'info> {
+    #[account(mut, seeds = [b"staking_vault"], bump = staking_vault.bump)]
+    pub staking_vault: Account<'info, StakingVault>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_position", owner.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, StakePosition>,
+
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct WithdrawUnstaked<'info> {
+    #[account(seeds = [b"staking_vault"], bump = staking_vault.bump)]
+    pub staking_vault: Account<'info, StakingVault>,
+
+    #[account(
+        mut,
+        seeds = [b"staking_tokens"],
+        bump = staking_vault.tokens_bump
+    )]
+    pub staking_tokens: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_position", owner.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, StakePosition>,
+
+    #[account(mut)]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimStakingRewards<'info> {
+    #[account(mut, seeds = [b"staking_vault"], bump = staking_vault.bump)]
+    pub staking_vault: Account<'info, StakingVault>,
+
+    #[account(
+        mut,
+        seeds = [b"stake_position", owner.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, StakePosition>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ConfigureBuyback<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + BuybackEscrow::INIT_SPACE,
+        seeds = [b"buyback_escrow"],
+        bump
+    )]
+    pub buyback_escrow: Account<'info, BuybackEscrow>,
+
+    /// Receives bought-back tokens for burning, owned by the escrow
+    #[account(
+        init_if_needed,
+        payer = admin,
+        seeds = [b"buyback_tokens"],
+        bump,
+        token::mint = protocol_mint,
+        token::authority = buyback_escrow
+    )]
+    pub buyback_tokens: Account<'info, TokenAccount>,
+
+    pub protocol_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteBuyback<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [b"buyback_escrow"], bump = buyback_escrow.bump)]
+    pub buyback_escrow: Account<'info, BuybackEscrow>,
+
+    #[account(seeds = [b"buyback_tokens"], bump = buyback_escrow.tokens_bump)]
+    pub buyback_tokens: Account<'info, TokenAccount>,
+
+    /// CHECK: Receives lamports only; must be the whitelisted executor
+    #[account(mut, address = buyback_escrow.executor @ MarketError::InvalidRecipient)]
+    pub executor: AccountInfo<'info>,
+
+    pub admin: Signer<'info>,
+
+    /// CHECK: Instructions sysvar, used to find the settle_buyback instruction
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct SettleBuyback<'info> {
+    #[account(mut, seeds = [b"buyback_escrow"], bump = buyback_escrow.bump)]
+    pub buyback_escrow: Account<'info, BuybackEscrow>,
+
+    #[account(
+        mut,
+        seeds = [b"buyback_tokens"],
+        bump = buyback_escrow.tokens_bump
+    )]
+    pub buyback_tokens: Account<'info, TokenAccount>,
+
+    #[account(mut, address = buyback_escrow.protocol_mint)]
+    pub protocol_mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct InitializeOrderBook<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + std::mem::size_of::<OrderBook>(),
+        seeds = [b"order_book", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBook>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct PostOrder<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"order_book", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBook>,
+
+    /// CHECK: The owner's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", owner.key().as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CancelOrder<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"order_book", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBook>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64, slot: u16, amount: u64, fill_id: u64)]
+pub struct FillOrder<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault, credited with the fee the market keeps
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"order_book", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub order_book: AccountLoader<'info, OrderBook>,
+
+    #[account(
+        init,
+        payer = taker,
+        space = 8 + Fill::INIT_SPACE,
+        seeds = [
+            b"fill",
+            market_id.to_le_bytes().as_ref(),
+            taker.key().as_ref(),
+            fill_id.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub fill: Account<'info, Fill>,
+
+    /// CHECK: The order owner's market-maker registration PDA, usually
+    /// uncreated; checked and credited by credit_mm_rebate
+    #[account(mut)]
+    pub maker_registration: UncheckedAccount<'info>,
+
+    /// CHECK: The taker's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", taker.key().as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub taker: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64, fill_id: u64)]
+pub struct SettleFill<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"fill",
+            market_id.to_le_bytes().as_ref(),
+            taker.key().as_ref(),
+            fill_id.to_le_bytes().as_ref()
+        ],
+        bump = fill.bump,
+        close = taker
+    )]
+    pub fill: Account<'info, Fill>,
+
+    /// CHECK: The fill's maker, paid its side's payout
+    #[account(mut, address = fill.maker)]
+    pub maker: UncheckedAccount<'info>,
+
+    /// CHECK: The fill's taker, paid its side's payout and the rent
+    #[account(mut, address = fill.taker)]
+    pub taker: UncheckedAccount<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct FundInsurance<'info> {
+    #[account(mut, seeds = [b"insurance"], bump = insurance_fund.bump)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(mut)]
+    pub donor: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct TopUpVault<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CoverShortfall<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [b"insurance"], bump = insurance_fund.bump)]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureDisputes<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + DisputeConfig::INIT_SPACE,
+        seeds = [b"dispute_config"],
+        bump
+    )]
+    pub dispute_config: Account<'info, DisputeConfig>,
+
+    pub governance_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct RaiseDispute<'info> {
+    #[account(seeds = [b"dispute_config"], bump = dispute_config.bump)]
+    pub dispute_config: Account<'info, DisputeConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init,
+        payer = raiser,
+        space = 8 + Dispute::INIT_SPACE,
+        seeds = [b"dispute", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+
+    /// Escrows voters' governance tokens, owned by the dispute
+    #[account(
+        init,
+        payer = raiser,
+        seeds = [b"dispute_vault", market_id.to_le_bytes().as_ref()],
+        bump,
+        token::mint = governance_mint,
+        token::authority = dispute
+    )]
+    pub dispute_vault: Account<'info, TokenAccount>,
+
+    #[account(address = dispute_config.governance_mint)]
+    pub governance_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub raiser: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct CastDisputeVote<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"dispute", market_id.to_le_bytes().as_ref()],
+        bump = dispute.bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+
+    #[account(
+        init,
+        payer = voter,
+        space = 8 + DisputeVote::INIT_SPACE,
+        seeds = [b"dispute_vote", market_id.to_le_bytes().as_ref(), voter.key().as_ref()],
+        bump
+    )]
+    pub dispute_vote: Account<'info, DisputeVote>,
+
+    #[account(
+        mut,
+        seeds = [b"dispute_vault", market_id.to_le_bytes().as_ref()],
+        bump = dispute.vault_bump
+    )]
+    pub dispute_vault: Account<'info, TokenAccount>,
+
+    #[account(mut, token::authority = voter)]
+    pub voter_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub voter: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct TallyDispute<'info> {
+    #[account(seeds = [b"dispute_config"], bump = dispute_config.bump)]
+    pub dispute_config: Account<'info, DisputeConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault, receives a forfeited bond
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"dispute", market_id.to_le_bytes().as_ref()],
+        bump = dispute.bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+
+    /// CHECK: Receives the bond back if the outcome is overturned
+    #[account(mut, address = dispute.raiser)]
+    pub raiser: AccountInfo<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ArbitrateDispute<'info> {
+    #[account(
+        seeds = [b"dispute_config"],
+        bump = dispute_config.bump,
+        has_one = arbiter @ MarketError::Unauthorized
+    )]
+    pub dispute_config: Account<'info, DisputeConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault, receives a forfeited bond
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"dispute", market_id.to_le_bytes().as_ref()],
+        bump = dispute.bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+
+    /// CHECK: Receives the bond back if the outcome is overturned
+    #[account(mut, address = dispute.raiser)]
+    pub raiser: AccountInfo<'info>,
+
+    pub arbiter: Signer<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct WithdrawDisputeVote<'info> {
+    #[account(
+        seeds = [b"dispute", market_id.to_le_bytes().as_ref()],
+        bump = dispute.bump
+    )]
+    pub dispute: Account<'info, Dispute>,
+
+    #[account(
+        mut,
+        close = voter,
+        seeds = [b"dispute_vote", market_id.to_le_bytes().as_ref(), voter.key().as_ref()],
+        bump = dispute_vote.bump
+    )]
+    pub dispute_vote: Account<'info, DisputeVote>,
+
+    #[account(
+        mut,
+        seeds = [b"dispute_vault", market_id.to_le_bytes().as_ref()],
+        bump = dispute.vault_bump
+    )]
+    pub dispute_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub voter_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub voter: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FundParlayReserve<'info> {
+    #[account(
+        init_if_needed,
+        payer = funder,
+        space = 8 + ParlayReserve::INIT_SPACE,
+        seeds = [b"parlay_reserve"],
+        bump
+    )]
+    pub parlay_reserve: Account<'info, ParlayReserve>,
+
+    #[account(mut)]
+    pub funder: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(parlay_id: u64)]
+pub struct CreateParlay<'info> {
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + Parlay::INIT_SPACE,
+        seeds = [b"parlay", owner.key().as_ref(), parlay_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub parlay: Account<'info, Parlay>,
+
+    #[account(mut, seeds = [b"parlay_reserve"], bump = parlay_reserve.bump)]
+    pub parlay_reserve: Account<'info, ParlayReserve>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(parlay_id: u64)]
+pub struct SettleParlay<'info> {
+    #[account(
+        mut,
+        seeds = [b"parlay", owner.key().as_ref(), parlay_id.to_le_bytes().as_ref()],
+        bump = parlay.bump,
+        has_one = owner @ MarketError::Unauthorized,
+        close = owner
+    )]
+    pub parlay: Account<'info, Parlay>,
+
+    #[account(mut, seeds = [b"parlay_reserve"], bump = parlay_reserve.bump)]
+    pub parlay_reserve: Account<'info, ParlayReserve>,
+
+    /// Parlay owner; receives the payout and the parlay account's rent
+    #[account(mut)]
+    pub owner: SystemAccount<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct AssertSolvent<'info> {
+    #[account(
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// wSOL collateral of a `wsol_vault` market
+    #[account(address = market.collateral_vault @ MarketError::WsolVaultRequired)]
+    pub wsol_vault: Option<InterfaceAccount<'info, token_interface::TokenAccount>>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ReconcileVault<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct FlagStale<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault paying the keeper tip
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ReleaseCreatorLiability<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        mut,
+        seeds = [b"creator_stats", market.liability_creator.as_ref()],
+        bump = creator_stats.bump
+    )]
+    pub creator_stats: Account<'info, CreatorStats>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct BidOpenAuction<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault escrowing the bid
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init,
+        payer = bidder,
+        space = 8 + AuctionBid::INIT_SPACE,
+        seeds = [b"auction_bid", market_id.to_le_bytes().as_ref(), bidder.key().as_ref()],
+        bump
+    )]
+    pub auction_bid: Account<'info, AuctionBid>,
+
+    /// CHECK: The bidder's self-exclusion PDA, usually uncreated; read
+    /// by require_not_self_excluded
+    #[account(seeds = [b"self_exclusion", bidder.key().as_ref()], bump)]
+    pub self_exclusion: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub bidder: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SettleOpenAuction<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ClaimAuctionAllocation<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"auction_bid", market_id.to_le_bytes().as_ref(), bidder.key().as_ref()],
+        bump = auction_bid.bump,
+        close = bidder
+    )]
+    pub auction_bid: Account<'info, AuctionBid>,
+
+    #[account(
+        init,
+        payer = bidder,
+        space = 8 + Prediction::INIT_SPACE,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            bidder.key().as_ref(),
+            &market.generation_seed()[..]
+        ],
+        bump
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(
+        init_if_needed,
+        payer = bidder,
+        space = 8 + UserProfile::INIT_SPACE,
+        seeds = [b"profile", bidder.key().as_ref()],
+        bump
+    )]
+    pub user_profile: Account<'info, UserProfile>,
+
+    #[account(
+        init_if_needed,
+        payer = bidder,
+        space = 8 + UserMarketIndex::INIT_SPACE,
+        seeds = [b"index", market.key().as_ref(), bidder.key().as_ref()],
+        bump
+    )]
+    pub user_market_index: Account<'info, UserMarketIndex>,
+
+    #[account(
+        init_if_needed,
+        payer = bidder,
+        space = 8 + UserIndexPage::INIT_SPACE,
+        seeds = [
+            b"user_index",
+            bidder.key().as_ref(),
+            &user_profile.next_index_page().to_le_bytes()[..]
+        ],
+        bump
+    )]
+    pub user_index_page: Account<'info, UserIndexPage>,
+
+    #[account(
+        init_if_needed,
+        payer = bidder,
+        space = 8 + UserEpochStats::INIT_SPACE,
+        seeds = [
+            b"epoch_stats",
+            bidder.key().as_ref(),
+            config.current_epoch.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub epoch_stats: Account<'info, UserEpochStats>,
+
+    #[account(mut)]
+    pub bidder: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct RefundAuctionBid<'info> {
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault escrowing the bid
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"auction_bid", market_id.to_le_bytes().as_ref(), bidder.key().as_ref()],
+        bump = auction_bid.bump,
+        close = bidder
+    )]
+    pub auction_bid: Account<'info, AuctionBid>,
+
+    #[account(mut)]
+    pub bidder: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ClearAttention<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct SweepDust<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// CHECK: Protocol treasury named in the config
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct EscheatUnclaimed<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault for holding SOL
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(
+        init_if_needed,
+        payer = payer,
+        space = 8 + UnclaimedRewardsPool::INIT_SPACE,
+        seeds = [b"unclaimed_rewards"],
+        bump
+    )]
+    pub unclaimed_rewards_pool: Account<'info, UnclaimedRewardsPool>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct GrantUnclaimedRewards<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault receiving the grant
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    #[account(mut, seeds = [b"unclaimed_rewards"], bump = unclaimed_rewards_pool.bump)]
+    pub unclaimed_rewards_pool: Account<'info, UnclaimedRewardsPool>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct DepositIdle<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized,
+        constraint = config.yield_adapter != Pubkey::default() @ MarketError::YieldAdapterNotSet
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault the deposit is taken from
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// CHECK: The adapter's account holding this market's funds; the adapter checks it
+    #[account(mut, owner = config.yield_adapter @ MarketError::InvalidYieldAdapter)]
+    pub adapter_position: AccountInfo<'info>,
+
+    /// CHECK: The whitelisted yield adapter
+    #[account(executable, address = config.yield_adapter @ MarketError::InvalidYieldAdapter)]
+    pub adapter_program: AccountInfo<'info>,
+
+    pub admin: Signer<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct WithdrawIdle<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    /// CHECK: Market vault the adapter pays back into
+    #[account(
+        mut,
+        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
+        bump = market.vault_bump
+    )]
+    pub market_vault: AccountInfo<'info>,
+
+    /// CHECK: The adapter's account holding this market's funds; the adapter checks it
+    #[account(mut, owner = market.idle_adapter @ MarketError::InvalidYieldAdapter)]
+    pub adapter_position: AccountInfo<'info>,
+
+    /// CHECK: The adapter the market deposited with
+    #[account(executable, address = market.idle_adapter @ MarketError::InvalidYieldAdapter)]
+    pub adapter_program: AccountInfo<'info>,
+
+    /// CHECK: Protocol treasury named in the config; receives the yield
+    #[account(mut, address = config.treasury)]
+    pub treasury: AccountInfo<'info>,
+
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureRewards<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + RewardsConfig::INIT_SPACE,
+        seeds = [b"rewards_config"],
+        bump
+    )]
+    pub rewards_config: Account<'info, RewardsConfig>,
+
+    /// Holds reward tokens, owned by the rewards config
+    #[account(
+        init_if_needed,
+        payer = admin,
+        seeds = [b"reward_vault"],
+        bump,
+        token::mint = reward_mint,
+        token::authority = rewards_config
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+
+    pub reward_mint: Account<'info, Mint>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct FundRewards<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ MarketError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(seeds = [b"rewards_config"], bump = rewards_config.bump)]
+    pub rewards_config: Account<'info, RewardsConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault"],
+        bump = rewards_config.vault_bump
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub admin_token_account: Account<'info, TokenAccount>,
+
+    pub admin: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct StakeIncentives<'info> {
+    #[account(mut, seeds = [b"rewards_config"], bump = rewards_config.bump)]
+    pub rewards_config: Account<'info, RewardsConfig>,
+
+    #[account(seeds = [b"reward_vault"], bump = rewards_config.vault_bump)]
+    pub reward_vault: Account<'info, TokenAccount>,
 
-#[derive(Accounts)]
-#[instruction(market_id: u64)]
-pub struct WithdrawFees<'info> {
     #[account(
         mut,
         seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
+    pub market: Account<'info, Market>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + MarketIncentives::INIT_SPACE,
+        seeds = [b"market_incentives", market_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub market_incentives: Account<'info, MarketIncentives>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + IncentivePosition::INIT_SPACE,
+        seeds = [b"incentive_position", market_id.to_le_bytes().as_ref(), owner.key().as_ref()],
         bump
     )]
+    pub position: Account<'info, IncentivePosition>,
+
+    /// The owner's bet, if any; creators may stake liquidity alone
+    #[account(
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            owner.key().as_ref(),
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.generation.0 == market.generation
+            @ MarketError::GenerationMismatch
+    )]
+    pub prediction_account: Option<Account<'info, Prediction>>,
+
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct ClaimIncentives<'info> {
+    #[account(mut, seeds = [b"rewards_config"], bump = rewards_config.bump)]
+    pub rewards_config: Account<'info, RewardsConfig>,
+
+    #[account(
+        mut,
+        seeds = [b"reward_vault"],
+        bump = rewards_config.vault_bump
+    )]
+    pub reward_vault: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump = market.bump,
+        constraint = market.version == MARKET_VERSION @ MarketError::UnsupportedAccountVersion,
+        constraint = market.market_id == market_id @ MarketError::MarketMismatch
+    )]
     pub market: Account<'info, Market>,
 
-    /// CHECK: Market vault for withdrawing collected fees
-    #[account(
-        mut,
-        seeds = [b"vault", market_id.to_le_bytes().as_ref()],
-        bump
-    )]
-    pub market_vault: AccountInfo<'info>,
+    #[account(
+        mut,
+        seeds = [b"market_incentives", market_id.to_le_bytes().as_ref()],
+        bump = market_incentives.bump
+    )]
+    pub market_incentives: Account<'info, MarketIncentives>,
+
+    #[account(
+        mut,
+        seeds = [b"incentive_position", market_id.to_le_bytes().as_ref(), owner.key().as_ref()],
+        bump = position.bump
+    )]
+    pub position: Account<'info, IncentivePosition>,
+
+    #[account(mut, token::mint = rewards_config.reward_mint)]
+    pub owner_token_account: Account<'info, TokenAccount>,
+
+    pub owner: Signer<'info>,
+
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct MigrateMarket<'info> {
+    /// CHECK: May hold a legacy layout that doesn't deserialize as Market;
+    /// its version is inspected in migrate_market
+    #[account(
+        mut,
+        seeds = [b"market", market_id.to_le_bytes().as_ref()],
+        bump,
+        owner = crate::ID
+    )]
+    pub market: UncheckedAccount<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(market_id: u64)]
+pub struct MigratePrediction<'info> {
+    #[account(
+        mut,
+        seeds = [
+            b"prediction",
+            market_id.to_le_bytes().as_ref(),
+            prediction_account.predictor.as_ref(),
+            &prediction_account.position_seed()[..],
+            &prediction_account.generation_seed()[..]
+        ],
+        bump = prediction_account.bump,
+        constraint = prediction_account.version <= PREDICTION_VERSION
+            @ MarketError::UnsupportedAccountVersion
+    )]
+    pub prediction_account: Account<'info, Prediction>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    pub system_program: Program<'info, System>,
+}
+
+// ==================== STATE ====================
+
+#[account]
+pub struct Config {
+    pub admin: Pubkey,
+    pub min_market_duration: i64, // seconds between creation and resolution
+    pub max_market_duration: i64,
+    pub registry_len: u64,  // total markets appended to the registry
+    pub registry_head: u64, // index of the latest registry page
+    pub current_epoch: u64,
+    pub epoch_started_at: i64,
+    pub epoch_length: i64,
+    pub trading_fee_bps: u16,
+    pub referral_fee_bps: u16, // share of the trading fee paid to referrers
+    pub discount_collection: Option<Pubkey>, // Metaplex collection mint
+    pub discount_bps: u16, // fee reduction for holders of the collection
+    pub insurance_bps: u16, // share of kept trading fees paid into the insurance fund
+    pub treasury: Pubkey, // receives swept vault dust; the Treasury PDA once created
+    pub config_delay_secs: i64, // timelock on config changes; 0 allows direct updates
+    pub paused: bool, // blocks market creation and betting
+    pub paused_at: i64,
+    pub max_fee_bps: u16, // ceiling for market fees and fee schedules
+    pub freeze_before_close_secs: i64, // default for new markets
+    pub exits_during_freeze: bool,     // default for new markets
+    pub allow_duplicate_markets: bool, // skips the question registry's duplicate check
+    pub min_fee_bps: u16, // floor for creator-chosen market fees
+    pub min_settle_delay: i64, // default for new markets
+    pub creation_mode: CreationMode,
+    pub creation_fee_lamports: u64, // paid to the treasury by each new market's creator
+    pub bump: u8,
+    pub staking_vault: Pubkey, // takes a share of collected fees once set by configure_staking
+    pub buyback_escrow: Pubkey, // likewise, once set by configure_buyback
+    pub stale_after_secs: i64, // past betting close before flag_stale applies
+    pub keeper_tips: [u64; KEEPER_ACTION_COUNT], // per KeeperAction, from market keeper budgets
+    pub category_defaults: [CategoryDefaults; MAX_CATEGORY_DEFAULTS], // category 0 = empty slot
+    pub max_open_liability: u64, // cap on a creator's open deposits; 0 = none
+    pub tenants: [TenantQuota; MAX_TENANTS], // zero tenant = empty slot
+    pub escheat_rebate_bps: u16, // share of escheated winnings kept for loser rebates; the rest tips keepers
+    pub feature_flags: u64, // FEATURE_* bits of the gated instructions that are on
+    pub yield_adapter: Pubkey, // program idle market funds may be deposited with; default = none
+    pub max_idle_bps: u16, // most of a market's collateral the adapter may hold; <= MAX_IDLE_BPS
+    pub max_markets_per_day: u32, // per creator per UTC day; 0 = no limit
+}
+
+impl Config {
+    pub const INIT_SPACE: usize =
+        32 +     // admin
+        8 +      // min_market_duration
+        8 +      // max_market_duration
+        8 +      // registry_len
+        8 +      // registry_head
+        8 +      // current_epoch
+        8 +      // epoch_started_at
+        8 +      // epoch_length
+        2 +      // trading_fee_bps
+        2 +      // referral_fee_bps
+        (1 + 32) + // discount_collection
+        2 +      // discount_bps
+        2 +      // insurance_bps
+        32 +     // treasury
+        8 +      // config_delay_secs
+        1 +      // paused
+        8 +      // paused_at
+        2 +      // max_fee_bps
+        8 +      // freeze_before_close_secs
+        1 +      // exits_during_freeze
+        1 +      // allow_duplicate_markets
+        2 +      // min_fee_bps
+        8 +      // min_settle_delay
+        1 +      // creation_mode
+        8 +      // creation_fee_lamports
+        1 +      // bump
+        32 +     // staking_vault
+        32 +     // buyback_escrow
+        8 +      // stale_after_secs
+        8 * KEEPER_ACTION_COUNT + // keeper_tips
+        CategoryDefaults::INIT_SPACE * MAX_CATEGORY_DEFAULTS + // category_defaults
+        8 +      // max_open_liability
+        TenantQuota::INIT_SPACE * MAX_TENANTS + // tenants
+        2 +      // escheat_rebate_bps
+        8 +      // feature_flags
+        32 +     // yield_adapter
+        2 +      // max_idle_bps
+        4;       // max_markets_per_day
+
+    /// Registry page that the next created market will be appended to
+    pub fn next_registry_page(&self) -> u64 {
+        self.registry_len / REGISTRY_PAGE_SIZE as u64
+    }
+
+    /// Quota slot of a registered tenant
+    /// Fails with FeatureDisabled unless the FEATURE_* bit `feature` is on
+    pub fn require_feature(&self, feature: u64) -> Result<()> {
+        require!(self.feature_flags & feature == feature, MarketError::FeatureDisabled);
+        Ok(())
+    }
+
+    pub fn tenant_quota_mut(&mut self, tenant: &[u8; 16]) -> Option<&mut TenantQuota> {
+        self.tenants.iter_mut().find(|t| t.tenant == *tenant)
+    }
+
+    /// Defaults set for `category`; None for category 0 or an unknown one
+    pub fn category_defaults(&self, category: u8) -> Option<&CategoryDefaults> {
+        if category == 0 {
+            return None;
+        }
+        self.category_defaults.iter().find(|d| d.category == category)
+    }
+}
+
+/// A tenant's market creation counters, kept in the config so its creation
+/// rate can be capped
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct TenantQuota {
+    pub tenant: [u8; 16],
+    pub max_markets_per_window: u32, // per TENANT_QUOTA_WINDOW_SECS; 0 = no cap
+    pub markets_created: u64,        // all time
+    pub window_start: i64,
+    pub window_created: u32,
+}
+
+impl TenantQuota {
+    pub const INIT_SPACE: usize =
+        16 +     // tenant
+        4 +      // max_markets_per_window
+        8 +      // markets_created
+        8 +      // window_start
+        4;       // window_created
+
+    /// Counts a new market, starting a fresh window once the last one has
+    /// run out. A clock reading behind the window's start counts as inside it
+    pub fn count_market(&mut self, now: i64) -> Result<()> {
+        if now.saturating_sub(self.window_start) >= TENANT_QUOTA_WINDOW_SECS {
+            self.window_start = now;
+            self.window_created = 0;
+        }
+        require!(
+            self.max_markets_per_window == 0
+                || self.window_created < self.max_markets_per_window,
+            MarketError::TenantQuotaExceeded
+        );
+        self.window_created += 1;
+        self.markets_created = self.markets_created.saturating_add(1);
+        Ok(())
+    }
+}
+
+/// Settings a market of one category falls back to when its creator
+/// leaves them at zero
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct CategoryDefaults {
+    pub category: u8,
+    pub fee_bps: u16, // 0 leaves the config's trading fee
+    pub dispute_window_secs: i64, // 0 leaves the dispute config's window
+    pub requires_oracle: bool, // markets must name an oracle key, eth address or price feed
+}
+
+impl CategoryDefaults {
+    pub const INIT_SPACE: usize =
+        1 +      // category
+        2 +      // fee_bps
+        8 +      // dispute_window_secs
+        1;       // requires_oracle
+}
+
+/// A config change waiting out the timelock
+#[account]
+pub struct PendingConfig {
+    pub params: ConfigParams,
+    pub proposed_at: i64,
+    pub executable_at: i64,
+    pub bump: u8,
+}
+
+impl PendingConfig {
+    pub const INIT_SPACE: usize =
+        ConfigParams::INIT_SPACE + // params
+        8 +      // proposed_at
+        8 +      // executable_at
+        1;       // bump
+}
+
+/// N-of-M multisig holding the config admin role through its signer PDA,
+/// [b"multisig_signer", multisig]
+#[account]
+pub struct AdminMultisig {
+    pub owners: [Pubkey; MAX_MULTISIG_OWNERS], // first owner_count are set
+    pub owner_count: u8,
+    pub threshold: u8, // approvals an action needs
+    pub tx_count: u64, // admin actions proposed so far; the next one's index
+    pub nonce: u64, // address seed, distinct per multisig
+    pub bump: u8,
+    pub signer_bump: u8,
+}
+
+impl AdminMultisig {
+    pub const INIT_SPACE: usize =
+        32 * MAX_MULTISIG_OWNERS + // owners
+        1 +      // owner_count
+        1 +      // threshold
+        8 +      // tx_count
+        8 +      // nonce
+        1 +      // bump
+        1;       // signer_bump
+
+    /// Position of `key` among the owners, which is its approval bit
+    pub fn owner_index(&self, key: &Pubkey) -> Option<usize> {
+        self.owners[..self.owner_count as usize]
+            .iter()
+            .position(|owner| owner == key)
+    }
+}
+
+/// An admin action awaiting or past its multisig approvals
+#[account]
+pub struct AdminTx {
+    pub index: u64,
+    pub proposer: Pubkey,
+    pub action: AdminAction,
+    pub approvals: u8, // bit i set once owners[i] approved
+    pub executed: bool,
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+impl AdminTx {
+    pub const INIT_SPACE: usize =
+        8 +      // index
+        32 +     // proposer
+        AdminAction::INIT_SPACE + // action
+        1 +      // approvals
+        1 +      // executed
+        8 +      // created_at
+        1;       // bump
+}
+
+/// Operations the admin multisig can carry out, with their parameters
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub enum AdminAction {
+    /// As update_config; needs the config timelock to be off
+    UpdateConfig { params: ConfigParams },
+    /// As pause_protocol
+    PauseProtocol,
+    /// As emergency_cancel_market
+    EmergencyCancelMarket { market_id: u64, reason: String },
+    /// Hand the admin role to another key
+    SetAdmin { admin: Pubkey },
+    /// Run one of this program's instructions with the multisig signer
+    /// signing as the config admin, so any admin instruction can go
+    /// through the multisig, create_admin_multisig for a replacement too
+    Invoke {
+        accounts: Vec<AdminAccountMeta>,
+        data: Vec<u8>,
+    },
+}
+
+impl AdminAction {
+    /// Enough for the largest variant
+    pub const INIT_SPACE: usize = 1
+        + ConfigParams::INIT_SPACE
+        + 8
+        + (4 + MAX_CANCEL_REASON_LEN)
+        + (4 + MAX_ADMIN_TX_ACCOUNTS * AdminAccountMeta::INIT_SPACE)
+        + (4 + MAX_ADMIN_TX_DATA);
+
+    /// Rejects an action that could never execute
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            AdminAction::UpdateConfig { params } => params.validate(),
+            AdminAction::EmergencyCancelMarket { reason, .. } => {
+                require!(
+                    reason.len() <= MAX_CANCEL_REASON_LEN,
+                    MarketError::InvalidCancelReason
+                );
+                Ok(())
+            }
+            AdminAction::Invoke { accounts, data } => {
+                require!(
+                    accounts.len() <= MAX_ADMIN_TX_ACCOUNTS && data.len() <= MAX_ADMIN_TX_DATA,
+                    MarketError::AdminActionTooLarge
+                );
+                Ok(())
+            }
+            AdminAction::PauseProtocol | AdminAction::SetAdmin { .. } => Ok(()),
+        }
+    }
+}
+
+/// An account of an Invoke action's instruction. The multisig signer signs
+/// whatever its flag says; any other signer must sign execute_admin_tx
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct AdminAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: bool,
+    pub is_writable: bool,
+}
+
+impl AdminAccountMeta {
+    pub const INIT_SPACE: usize = 32 + 1 + 1;
+}
+
+/// Who may create markets
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CreationMode {
+    Open,
+    /// Only creators with a CreatorAllow
+    Whitelisted,
+}
+
+/// A creator approved by the config admin to create markets while
+/// creation is whitelisted
+#[account]
+pub struct CreatorAllow {
+    pub creator: Pubkey,
+    pub approved_at: i64,
+    pub fee_waived: bool, // creates markets without paying the creation fee
+    pub bump: u8,
+    pub max_markets_per_day: u32, // personal limit; applies when above the config's
+}
+
+impl CreatorAllow {
+    pub const INIT_SPACE: usize =
+        32 +     // creator
+        8 +      // approved_at
+        1 +      // fee_waived
+        1 +      // bump
+        4;       // max_markets_per_day
+}
+
+/// Global counter handing out sequential market IDs
+#[account]
+pub struct MarketCounter {
+    pub next_id: u64,
+    pub bump: u8,
+}
+
+impl MarketCounter {
+    pub const INIT_SPACE: usize =
+        8 +      // next_id
+        1;       // bump
+}
+
+/// Oracle attestation that resolved a market, keyed by attestation_hash.
+/// Its existence blocks the attestation from being used again
+#[account]
+pub struct AttestationRecord {
+    pub market: Pubkey,
+    pub market_id: u64,
+    pub oracle: Pubkey,
+    pub outcome: bool,
+    pub expires_at: i64, // from the signed payload
+    pub recorded_at: i64,
+    pub payer: Pubkey, // paid the rent; refunded on purge
+    pub bump: u8,
+}
+
+impl AttestationRecord {
+    pub const INIT_SPACE: usize =
+        32 +     // market
+        8 +      // market_id
+        32 +     // oracle
+        1 +      // outcome
+        8 +      // expires_at
+        8 +      // recorded_at
+        32 +     // payer
+        1;       // bump
+}
+
+/// First market created for a normalized question and resolution day; see
+/// question_hash. Later markets with the same key are duplicates
+#[account]
+pub struct QuestionRegistry {
+    pub market: Pubkey,
+    pub market_id: u64,
+    pub bump: u8,
+}
+
+impl QuestionRegistry {
+    pub const INIT_SPACE: usize =
+        32 +     // market
+        8 +      // market_id
+        1;       // bump
+}
+
+/// One page of the on-chain market registry, enumerable without
+/// getProgramAccounts by walking pages 0..=config.registry_head
+#[account]
+pub struct MarketRegistryPage {
+    pub page_index: u64,
+    pub count: u32,
+    pub markets: [Pubkey; REGISTRY_PAGE_SIZE],
+    pub bump: u8,
+}
+
+impl MarketRegistryPage {
+    pub const INIT_SPACE: usize =
+        8 +                             // page_index
+        4 +                             // count
+        32 * REGISTRY_PAGE_SIZE +       // markets
+        1;                              // bump
+}
+
+/// Per-market settings chosen by the creator at initialization
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Default)]
+pub struct MarketOptions {
+    /// Weight early bets more heavily in payouts
+    pub time_decay: bool,
+    /// Minimum weight (bps) applied to a bet placed right at close
+    pub time_decay_floor_bps: u16,
+    /// Key allowed to resolve the market via signed attestations
+    pub oracle_key: Option<Pubkey>,
+    /// Ethereum-style address allowed to resolve via secp256k1 attestations
+    pub oracle_eth_address: Option<[u8; 20]>,
+    /// Nonzero switches the market to commit-reveal betting, with this many
+    /// seconds to reveal each commitment
+    pub bet_reveal_window: i64,
+    /// Price feed and thresholds for resolve_with_oracle
+    pub price_oracle: Option<PriceOracle>,
+    /// Seconds after resolution during which the outcome may be amended
+    /// and claims are held
+    pub correction_window_secs: i64,
+    /// Seconds after claims open until leftover vault dust may be swept to
+    /// the treasury; 0 never allows a sweep
+    pub claim_period_secs: i64,
+    /// Share of the kept fee (bps) refunded to a bet that fully closes the gap
+    /// between the pools; 0 disables rebates
+    pub imbalance_rebate_bps: u16,
+    /// Raise the fee with the imbalance a bet leaves behind
+    pub fee_curve: Option<FeeCurve>,
+    /// Move the base fee from a start to an end rate over the betting period
+    pub fee_schedule: Option<FeeSchedule>,
+    /// Seconds before betting close during which new bets are refused;
+    /// None takes the config default
+    pub freeze_before_close_secs: Option<i64>,
+    /// Whether resting orders may still be cancelled during the freeze;
+    /// None takes the config default
+    pub exits_during_freeze: Option<bool>,
+    /// Hold collateral as wSOL (of either token program) in a token account
+    /// owned by the market instead of lamports in the vault PDA; see
+    /// init_wsol_vault
+    pub wsol_vault: bool,
+    /// Outcome is a judgment call; manual resolutions must give evidence
+    pub subjective: bool,
+    /// Seconds after a bet during which it can be cancelled for a refund;
+    /// 0 disables cancellation. Lamport vault markets only
+    pub cooloff_secs: i64,
+    /// Share of the deposit kept in the pot when a bettor exits early;
+    /// 0 disables early_exit. Should exceed the trading fee, or exits are
+    /// partly paid by the other bettors. Lamport vault markets only
+    pub early_exit_penalty_bps: u16,
+    /// How bets are priced
+    pub mode: MarketMode,
+    /// Bookmaker payout per lamport staked on YES, in bps (18_000 pays 1.8x)
+    pub yes_odds_bps: u32,
+    /// Bookmaker payout per lamport staked on NO, in bps
+    pub no_odds_bps: u32,
+    /// Share of a winning claim's profit (bps) kept as a market fee;
+    /// 0 charges none
+    pub winnings_fee_bps: u16,
+    /// Share of the fees collected by resolution (bps) set aside for the
+    /// losing bettors, pro rata to their stakes; 0 disables rebates.
+    /// Lamport vault markets only
+    pub loser_rebate_bps: u16,
+    /// Lamports the creator puts into the market's keeper budget at
+    /// creation; see fund_keeper_budget. Lamport vault markets only
+    pub keeper_budget: u64,
+    /// Category the market is filed under; 0 for none. A zero fee or
+    /// dispute window falls back to the category's defaults in the config
+    pub category: u8,
+    /// Seconds after resolution during which the outcome may be disputed;
+    /// 0 takes the category default, then the dispute config's window
+    pub dispute_window_secs: i64,
+    /// Length of the fair-launch auction that opens the market: bids are
+    /// escrowed and filled together at one clearing price per side by
+    /// settle_open_auction, after which normal trading begins. 0 for none.
+    /// AMM, lamport vault markets only
+    pub open_auction_secs: i64,
+    /// Circuit breaker: the most one bet may move the implied probability,
+    /// in bps. 0 for no limit
+    pub max_move_per_trade_bps: u16,
+    /// Likewise for all bets within a CIRCUIT_BREAKER_WINDOW_SECS window;
+    /// the creator may restart the window with reset_circuit_breaker
+    pub max_move_per_window_bps: u16,
+    /// Brand the market is created for, stamped on every event about it;
+    /// all zeroes for none. Must be registered with set_tenant_quota
+    pub tenant: [u8; 16],
+    /// Seconds after claims open that winners have to claim; what is left
+    /// unclaimed then goes to the unclaimed rewards pool through
+    /// escheat_unclaimed. 0 for no limit. Lamport vault markets only
+    pub claim_window_secs: i64,
+    /// Largest single bet, in collateral units; 0 for no limit
+    pub max_bet: u64,
+    /// Seconds after creation over which the max_bet cap ramps up linearly
+    /// from max_bet_ramp_start_bps of max_bet to all of it. 0 for no ramp
+    pub max_bet_ramp_secs: i64,
+    /// Share of max_bet (bps) allowed at creation when ramping
+    pub max_bet_ramp_start_bps: u16,
+    /// Makes this a conditional market: it resolves only if the parent,
+    /// which must resolve first, goes the given way, and is voided
+    /// otherwise; see settle_parent_condition. The parent is passed as
+    /// the creation's parent_market account
+    pub parent: Option<ParentCondition>,
+    /// Take bets as leaves of a Merkle receipt tree rather than Prediction
+    /// accounts, sparing bettors the rent; see place_compressed_prediction.
+    /// Lamport vault markets without loser rebates or commit-reveal only
+    pub compressed: bool,
+    /// Seconds before betting close in which bets may only take a wallet's
+    /// net position toward flat; exits stay open. 0 for none
+    pub reduce_only_window_secs: i64,
+    /// Let claims through an open dispute, paying each into an EscrowedClaim
+    /// until the dispute settles; see release_escrowed_claim. Lamport vault
+    /// markets only
+    pub escrow_disputed_claims: bool,
+    /// Pool checkpoints kept for replay_market_events, the most recent
+    /// trades' pools, up to MAX_POOL_HISTORY. Each adds
+    /// PoolCheckpoint::INIT_SPACE bytes of market rent; 0 keeps none
+    pub pool_history_len: u8,
+    /// External adjudication program that alone resolves the market, by
+    /// CPI into resolve_via_program; every other resolution path is closed
+    pub resolution_program: Option<Pubkey>,
+    /// What a push or draw reported through resolve_push resolves to;
+    /// changeable with set_push_policy until the first bet
+    pub push_policy: PushPolicy,
+    /// Most distinct wallets that may open a position, first come first
+    /// served; wallets already in may keep betting. 0 = no limit
+    pub max_participants: u32,
+    /// Which way fees, token outputs and claims round; fixed at creation
+    pub rounding_policy: RoundingPolicy,
+}
+
+/// Pool state copied onto a market as it resolves. Claims, winner
+/// entitlements and loser rebates are computed from it rather than the
+/// live fields, so nothing that moves after resolution changes a payout
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace)]
+pub struct ResolutionSnapshot {
+    pub yes_pool: u64,
+    pub no_pool: u64,
+    pub sponsored_amount: u64,
+    pub yes_deposits: u64,
+    pub no_deposits: u64,
+    pub fee_collected: u64, // after the rebate pot was carved out
+    pub vault_lamports: u64, // vault PDA balance, rent included; a wSOL vault's token amount
+    pub implied_yes_probability_bps: u16,
+    pub taken_at: i64, // 0 = not resolved yet
+}
+
+impl ResolutionSnapshot {
+    pub fn capture(market: &Market, vault_lamports: u64, now: i64) -> Self {
+        Self {
+            yes_pool: market.yes_pool,
+            no_pool: market.no_pool,
+            sponsored_amount: market.sponsored_amount,
+            yes_deposits: market.yes_deposits,
+            no_deposits: market.no_deposits,
+            fee_collected: market.fee_collected,
+            vault_lamports,
+            implied_yes_probability_bps: market.implied_yes_probability_bps(),
+            taken_at: now,
+        }
+    }
+
+    pub fn pool(&self, outcome: bool) -> u64 {
+        if outcome {
+            self.yes_pool
+        } else {
+            self.no_pool
+        }
+    }
+
+    pub fn deposits(&self, outcome: bool) -> u64 {
+        if outcome {
+            self.yes_deposits
+        } else {
+            self.no_deposits
+        }
+    }
+
+    /// What winners share: both pools plus sponsorships
+    pub fn pot(&self) -> u64 {
+        self.yes_pool
+            .saturating_add(self.no_pool)
+            .saturating_add(self.sponsored_amount)
+    }
+
+    /// keccak of every field in declaration order, little-endian
+    pub fn hash(&self) -> [u8; 32] {
+        keccak::hashv(&[
+            &self.yes_pool.to_le_bytes(),
+            &self.no_pool.to_le_bytes(),
+            &self.sponsored_amount.to_le_bytes(),
+            &self.yes_deposits.to_le_bytes(),
+            &self.no_deposits.to_le_bytes(),
+            &self.fee_collected.to_le_bytes(),
+            &self.vault_lamports.to_le_bytes(),
+            &self.implied_yes_probability_bps.to_le_bytes(),
+            &self.taken_at.to_le_bytes(),
+        ])
+        .to_bytes()
+    }
+}
+
+/// Permanent record of a claim_reward payout, seeded by prediction.
+/// Outlives the prediction account; only its claimer may close it, and not
+/// before CLAIM_RECEIPT_LOCK_SECS
+#[account]
+pub struct ClaimReceipt {
+    pub prediction: Pubkey,
+    pub claimer: Pubkey,
+    pub market_id: u64,
+    pub amount: u64, // paid out by the claim, net of the winnings fee
+    pub fee: u64,    // winnings fee deducted
+    pub claimed_at: i64,
+    pub snapshot_hash: [u8; 32], // ResolutionSnapshot::hash of the outcome paid against
+    pub bump: u8,
+}
+
+impl ClaimReceipt {
+    pub const INIT_SPACE: usize =
+        32 +     // prediction
+        32 +     // claimer
+        8 +      // market_id
+        8 +      // amount
+        8 +      // fee
+        8 +      // claimed_at
+        32 +     // snapshot_hash
+        1;       // bump
+}
+
+/// Permanent fingerprint of a closed market's final state, seeded by
+/// market_id and generation_seed. Nothing closes it
+#[account]
+pub struct MarketArchive {
+    pub market_id: u64,
+    pub generation: u64,
+    pub outcome: MarketOutcome,
+    pub hash: [u8; 32], // compute_archive_hash of the market as it closed
+    pub archived_at: i64,
+    pub bump: u8,
+}
+
+impl MarketArchive {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        8 +      // generation
+        1 +      // outcome
+        32 +     // hash
+        8 +      // archived_at
+        1;       // bump
+}
+
+/// A claim_reward payout held while a dispute is open, seeded by prediction.
+/// Holds the net reward above its rent until release_escrowed_claim
+#[account]
+pub struct EscrowedClaim {
+    pub prediction: Pubkey,
+    pub claimer: Pubkey,
+    pub recipient: Pubkey, // paid if the outcome stands
+    pub market_id: u64,
+    pub outcome: bool, // side the claim was paid for
+    pub tokens: u64,   // winning tokens redeemed
+    pub amount: u64,   // held, net of the winnings fee
+    pub gross_reward: u64,
+    pub winnings_fee: u64,
+    pub first_claim: bool,
+    pub epoch: u64, // of the epoch stats the claim was counted in
+    pub bump: u8,
+}
+
+impl EscrowedClaim {
+    pub const INIT_SPACE: usize =
+        32 +     // prediction
+        32 +     // claimer
+        32 +     // recipient
+        8 +      // market_id
+        1 +      // outcome
+        8 +      // tokens
+        8 +      // amount
+        8 +      // gross_reward
+        8 +      // winnings_fee
+        1 +      // first_claim
+        8 +      // epoch
+        1;       // bump
+}
+
+/// SPL mints for a market's YES and NO outcome tokens, with the market as
+/// mint authority
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct OutcomeMints {
+    pub yes: Pubkey,
+    pub no: Pubkey,
+}
+
+/// One compressed bet, as hashed into its market's receipt tree
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompressedReceipt {
+    pub index: u64, // leaf position in the tree
+    pub owner: Pubkey,
+    pub prediction_type: bool,
+    pub amount_deposited: u64,
+    pub tokens_received: u64,
+}
+
+impl CompressedReceipt {
+    /// Leaf hash: keccak(0x00 || market_id || index || owner || side ||
+    /// amount_deposited || tokens_received). The prefix keeps an inner
+    /// node from passing for a leaf
+    pub fn leaf(&self, market_id: u64) -> [u8; 32] {
+        keccak::hashv(&[
+            &[0],
+            &market_id.to_le_bytes(),
+            &self.index.to_le_bytes(),
+            self.owner.as_ref(),
+            &[self.prediction_type as u8],
+            &self.amount_deposited.to_le_bytes(),
+            &self.tokens_received.to_le_bytes(),
+        ])
+        .to_bytes()
+    }
+
+    /// Page of the market's ReceiptClaims bitmap holding this receipt's bit
+    pub fn claims_page(&self) -> u32 {
+        (self.index / (RECEIPT_CLAIMS_PAGE_BYTES as u64 * 8)) as u32
+    }
+
+    /// The receipt as a never-claimed Prediction, so claim_compressed pays
+    /// winners through redeem_winnings like every other claim
+    fn to_prediction(self, market_id: u64) -> Prediction {
+        Prediction {
+            version: PREDICTION_VERSION,
+            market_id,
+            predictor: self.owner,
+            prediction_type: self.prediction_type,
+            amount_deposited: self.amount_deposited,
+            tokens_received: self.tokens_received,
+            created_at: 0,
+            claimed: false,
+            referrer: Pubkey::default(),
+            bump: 0,
+            slot: 0,
+            yes_pool_at_entry: 0,
+            no_pool_at_entry: 0,
+            entry_price_bps: 0,
+            tokens_claimed: 0,
+            position_index: 0,
+            entry_probability_bps: 0,
+            trade_sequence: 0,
+            cancel_refund: 0,
+            stop_loss_bps: 0,
+            note: String::new(),
+            rebate_claimed: false,
+            rent_payer: Pubkey::default(),
+            is_house: ReserveFlag(false),
+            generation: ReserveU64(0),
+            _reserved: Reserved,
+        }
+    }
+}
+
+/// The parent outcome a conditional market depends on
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub struct ParentCondition {
+    pub market: Pubkey,
+    pub outcome: bool, // the parent must resolve this way for the market to resolve
+}
+
+/// Everything a winning claim is computed from, fixed once per resolved
+/// market and never changed after. compute_payout reproduces any claim
+/// from these and the position's tokens
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace)]
+pub struct PayoutParams {
+    pub published_at: i64, // 0 = not published yet
+    pub winning_side: bool,
+    pub fixed_odds: bool, // bookmaker: each winning token pays one lamport
+    pub winning_pool: u64, // from the resolution snapshot
+    pub total_pot: u64, // both pools plus sponsorships, from the resolution snapshot
+    pub winnings_fee_bps: u16, // charged on the profit over each claim's deposit
+    pub covered: u64, // what the vault could pay winners when published
+    pub owed: u64, // winner entitlements outstanding when published; a haircut applies while covered < owed
+}
+
+impl PayoutParams {
+    pub fn capture(market: &Market, outcome: bool, available: u64, now: i64) -> Self {
+        Self {
+            published_at: now,
+            winning_side: outcome,
+            fixed_odds: market.mode == MarketMode::Bookmaker,
+            winning_pool: market.resolution_snapshot.pool(outcome),
+            total_pot: market.resolution_snapshot.pot(),
+            winnings_fee_bps: market.winnings_fee_bps,
+            covered: available,
+            owed: market.outstanding_entitlement,
+        }
+    }
+
+    /// What `tokens` winning tokens are owed before any haircut, rounded
+    /// by `rounding`
+    pub fn entitlement(&self, tokens: u64, rounding: RoundingPolicy) -> u64 {
+        if self.fixed_odds {
+            return tokens;
+        }
+        if self.winning_pool == 0 {
+            return 0;
+        }
+        rounding.payout(
+            (tokens as u128) * (self.total_pot as u128),
+            self.winning_pool as u128,
+        ) as u64
+    }
+
+    /// Pays `entitled` scaled by covered / owed when the vault fell short.
+    /// Returns the payout and the ratio in bps
+    pub fn haircut(&self, entitled: u64) -> (u64, u16) {
+        pro_rata_payout(entitled, self.covered, self.owed.max(entitled))
+    }
+}
+
+/// Permissionless cranks that pay a tip from the market's keeper budget;
+/// the discriminant indexes Config::keeper_tips
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeeperAction {
+    FlagStale,
+    TriggerLimitEntry,
+    ExpireLimitEntry,
+    ExecuteStopLoss,
+    RecordPriceSample,
+    SettleLoss,
+}
+
+/// Which way a market rounds every basis-point share it takes: trading,
+/// winnings and early exit fees, fee discounts, tips and penalties, token
+/// outputs and claim entitlements, and the referral, insurance and rebate
+/// cuts carved out of its fees. Haircuts and pro-rata shares that must
+/// never exceed what they divide round down under every policy
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace)]
+pub enum RoundingPolicy {
+    /// Fees round up, tokens and payouts down; the vault keeps every
+    /// remainder
+    #[default]
+    FavorPool,
+    /// Fees round down, tokens and payouts up; each division gives away
+    /// less than one unit
+    FavorUser,
+    /// Everything rounds to the nearest unit, ties to even
+    Bankers,
+}
+
+impl RoundingPolicy {
+    /// `numerator / denominator` for an amount the bettor receives
+    pub fn payout(self, numerator: u128, denominator: u128) -> u128 {
+        match self {
+            RoundingPolicy::FavorPool => numerator / denominator,
+            RoundingPolicy::FavorUser => numerator.div_ceil(denominator),
+            RoundingPolicy::Bankers => div_half_even(numerator, denominator),
+        }
+    }
+
+    /// `numerator / denominator` for an amount the bettor pays
+    pub fn charge(self, numerator: u128, denominator: u128) -> u128 {
+        match self {
+            RoundingPolicy::FavorPool => numerator.div_ceil(denominator),
+            RoundingPolicy::FavorUser => numerator / denominator,
+            RoundingPolicy::Bankers => div_half_even(numerator, denominator),
+        }
+    }
+
+    /// `bps` basis points of `amount`, as a fee the bettor pays
+    pub fn charge_bps(self, amount: u64, bps: u16) -> u64 {
+        self.charge((amount as u128) * (bps as u128), BPS_DENOMINATOR as u128) as u64
+    }
+
+    /// `bps` basis points of `amount`, as a discount the bettor receives
+    pub fn payout_bps(self, amount: u64, bps: u16) -> u64 {
+        self.payout((amount as u128) * (bps as u128), BPS_DENOMINATOR as u128) as u64
+    }
+}
+
+/// How a market settles a push or draw
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace)]
+pub enum PushPolicy {
+    /// Void the market, refunding every bet
+    #[default]
+    VoidOnPush,
+    /// NO wins
+    NoWinsOnPush,
+    /// YES wins
+    YesWinsOnPush,
+}
+
+impl PushPolicy {
+    /// The outcome a push resolves to; None voids the market
+    pub fn outcome(self) -> Option<bool> {
+        match self {
+            PushPolicy::VoidOnPush => None,
+            PushPolicy::NoWinsOnPush => Some(false),
+            PushPolicy::YesWinsOnPush => Some(true),
+        }
+    }
+}
+
+/// How a market prices bets
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace)]
+pub enum MarketMode {
+    /// Constant-product pools; winners split the pot
+    #[default]
+    Amm,
+    /// The creator takes the other side of every bet at fixed odds, backed
+    /// by the seed liquidity as a bankroll
+    Bookmaker,
+    /// Bets join their side's pool 1:1 and winners split the pot by stake;
+    /// the seed is returned to the creator
+    Parimutuel,
+}
+
+/// Where a market stands on its result
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace)]
+pub enum MarketOutcome {
+    #[default]
+    Unresolved,
+    Yes,
+    No,
+    /// Resolved without a winner; every deposit is refundable
+    Void,
+}
+
+impl MarketOutcome {
+    /// The winning side, if the market resolved to one
+    pub fn winner(self) -> Option<bool> {
+        match self {
+            MarketOutcome::Yes => Some(true),
+            MarketOutcome::No => Some(false),
+            MarketOutcome::Unresolved | MarketOutcome::Void => None,
+        }
+    }
+
+    /// Reads the Option<bool> outcome of version 1 and legacy accounts, where
+    /// a resolved market with no outcome was void
+    pub fn from_legacy(resolved: bool, outcome: Option<bool>) -> Self {
+        match (resolved, outcome) {
+            (_, Some(side)) => side.into(),
+            (true, None) => MarketOutcome::Void,
+            (false, None) => MarketOutcome::Unresolved,
+        }
+    }
+}
+
+impl From<bool> for MarketOutcome {
+    /// The outcome won by YES when `side` is true, NO otherwise
+    fn from(side: bool) -> Self {
+        if side {
+            MarketOutcome::Yes
+        } else {
+            MarketOutcome::No
+        }
+    }
+}
+
+/// Fee that grows with pool imbalance:
+/// base_fee_bps + k_bps * post_trade_imbalance_bps / 10_000, up to cap_bps
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct FeeCurve {
+    /// Fee bps added when the pools are fully one-sided
+    pub k_bps: u16,
+    /// Highest fee the curve charges; never below the base fee
+    pub cap_bps: u16,
+}
+
+/// Base fee interpolated linearly from creation to betting close
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, InitSpace)]
+pub struct FeeSchedule {
+    pub start_fee_bps: u16,
+    pub end_fee_bps: u16,
+}
+
+impl FeeSchedule {
+    /// Fee rate at `now` for a betting period running from `start` to `end`
+    pub fn fee_bps_at(&self, start: i64, end: i64, now: i64) -> u16 {
+        if now <= start || end <= start {
+            return self.start_fee_bps;
+        }
+        if now >= end {
+            return self.end_fee_bps;
+        }
+        let rise = self.end_fee_bps.saturating_sub(self.start_fee_bps) as i128;
+        let elapsed = (now - start) as i128;
+        let span = (end - start) as i128;
+        (self.start_fee_bps as i128 + rise * elapsed / span) as u16
+    }
+}
+
+/// How a price-threshold market resolves from a PriceFeed
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, InitSpace)]
+pub struct PriceOracle {
+    pub feed: Pubkey,
+    /// YES wins when the price is at or above this, in the feed's units
+    pub strike_price: i64,
+    /// Oldest print (seconds before now) accepted at resolution
+    pub max_price_age_secs: u32,
+    /// Widest confidence interval accepted, in bps of the price
+    pub max_confidence_bps: u16,
+    /// Seconds after resolution time before the creator may fall back
+    /// to manual resolution
+    pub fallback_grace_secs: u32,
+    /// Nonzero resolves from a TWAP of samples recorded over this many
+    /// seconds before resolution time, instead of a single print
+    pub twap_window_secs: u32,
+    /// Samples required before resolve_with_twap
+    pub min_twap_samples: u8,
+}
+
+/// Template settings chosen at creation, editable until first use
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct TemplateSettings {
+    pub question_format: String,
+    pub category: u8,
+    pub trading_fee_bps: Option<u16>,
+    pub options: MarketOptions,
+    pub initial_liquidity: u64,
+    pub resolution_delay: i64,
+}
+
+impl TemplateSettings {
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            !self.question_format.is_empty() && self.question_format.len() <= MAX_QUESTION_LEN,
+            MarketError::InvalidTemplate
+        );
+        require!(
+            self.trading_fee_bps.unwrap_or(0) as u64 <= BPS_DENOMINATOR
+                && self.options.time_decay_floor_bps as u64 <= BPS_DENOMINATOR,
+            MarketError::InvalidTemplate
+        );
+        require!(self.resolution_delay >= 0, MarketError::InvalidTemplate);
+        // Creation from a template takes no parent account to check against
+        require!(self.options.parent.is_none(), MarketError::InvalidTemplate);
+        Ok(())
+    }
+
+    pub fn apply(&self, template: &mut MarketTemplate) {
+        template.question_format = self.question_format.clone();
+        template.category = self.category;
+        template.trading_fee_bps = self.trading_fee_bps;
+        template.options = self.options.clone();
+        template.initial_liquidity = self.initial_liquidity;
+        template.resolution_delay = self.resolution_delay;
+    }
+}
+
+/// Per-market values substituted into a template
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct TemplateParams {
+    /// Replace `{0}`, `{1}`, ... in the question format
+    pub args: Vec<String>,
+    /// Event start; the market resolves `resolution_delay` after it
+    pub start_time: i64,
+}
+
+/// Pricing of a prediction, returned by quote_prediction
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct PredictionQuote {
+    /// Outcome tokens the bet would receive
+    pub tokens_out: u64,
+    /// Lamports paid per outcome token, in bps (10_000 = 1:1)
+    pub effective_price_bps: u64,
+    /// Total trading fee, including the referral cut
+    pub fee: u64,
+    /// Part of the fee paid to the referrer
+    pub referral_fee: u64,
+    /// Part of the fee paid into the insurance fund
+    pub insurance_fee: u64,
+    /// Amount added to the pool after the fee
+    pub net_amount: u64,
+    /// Time-decay weight applied to the output
+    pub weight_bps: u64,
+    /// Fee rate charged (bps), before any NFT discount
+    pub fee_bps: u16,
+    /// Move in the implied YES probability the bet causes (bps)
+    pub price_impact_bps: u16,
+    /// Part of the fee refunded for narrowing the gap between the pools
+    pub rebate: u64,
+}
+
+/// Settlement state of a prediction, as reported by get_claimable
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClaimStatus {
+    Unresolved,
+    Won,
+    Lost,
+    Claimed,
+    /// Deposit can be taken back; reserved for voided markets
+    Refundable,
+}
+
+/// What a prediction is owed, returned by get_claimable
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct Claimable {
+    pub status: ClaimStatus,
+    /// Lamports claimable now (zero unless Won or Refundable)
+    pub amount: u64,
+    /// Last time the claim can be made, if the market has one
+    pub deadline: Option<i64>,
+}
+
+/// A market's solvency, returned by market_health
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct MarketHealth {
+    /// Lamports in the vault PDA, rent included
+    pub vault_lamports: u64,
+    /// Rent-exempt minimum the vault PDA keeps back
+    pub rent_floor: u64,
+    /// What backs the market: the vault above rent, or the wSOL vault's
+    /// balance, plus anything with the yield adapter
+    pub collateral: u64,
+    /// Market::outcome_liabilities: before resolution the whole pot,
+    /// after it the outstanding winner entitlements
+    pub outcome_liabilities: u64,
+    pub fee_collected: u64,
+    pub rebate_outstanding: u64,
+    pub keeper_budget: u64,
+    pub auction_escrow: u64,
+    pub creator_tips: u64,
+    /// Market::liabilities, the sum of the six above
+    pub liabilities: u64,
+    /// collateral / liabilities in bps; u64::MAX when nothing is owed
+    pub health_bps: u64,
+}
+
+/// A wallet's exposure across markets, returned by compute_exposure
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug, Default)]
+pub struct Exposure {
+    pub wallet: Pubkey,
+    /// Valid positions on unresolved markets; the only ones summed below
+    pub open_positions: u16,
+    /// Valid positions on resolved markets, left out
+    pub skipped: u16,
+    /// What the open positions deposited
+    pub total_deposited: u64,
+    /// Market::mark_value of the open positions' unclaimed tokens
+    pub mark_value: u64,
+    /// mark_value less total_deposited
+    pub unrealized_pnl: i64,
+    /// The profile's winnings less its wagers outside the open positions;
+    /// exact when every open position is passed
+    pub realized_pnl: i64,
+    /// Indexes of the pairs that failed validation
+    pub flagged: Vec<u16>,
+}
+
+/// Admin-tunable protocol parameters
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct ConfigParams {
+    pub min_market_duration: i64,
+    pub max_market_duration: i64,
+    pub epoch_length: i64,
+    pub trading_fee_bps: u16,
+    pub referral_fee_bps: u16,
+    pub discount_collection: Option<Pubkey>,
+    pub discount_bps: u16,
+    pub insurance_bps: u16,
+    pub treasury: Pubkey,
+    pub config_delay_secs: i64,
+    pub paused: bool,
+    pub max_fee_bps: u16,
+    pub freeze_before_close_secs: i64,
+    pub exits_during_freeze: bool,
+    pub allow_duplicate_markets: bool,
+    pub min_fee_bps: u16,
+    pub min_settle_delay: i64,
+    pub creation_mode: CreationMode,
+    pub creation_fee_lamports: u64,
+    pub stale_after_secs: i64,
+    pub max_open_liability: u64,
+    pub max_markets_per_day: u32,
+    pub yield_adapter: Pubkey,
+    pub max_idle_bps: u16,
+}
+
+impl Default for ConfigParams {
+    fn default() -> Self {
+        Self {
+            min_market_duration: DEFAULT_MIN_MARKET_DURATION,
+            max_market_duration: DEFAULT_MAX_MARKET_DURATION,
+            epoch_length: DEFAULT_EPOCH_LENGTH,
+            trading_fee_bps: DEFAULT_TRADING_FEE_BPS,
+            referral_fee_bps: DEFAULT_REFERRAL_FEE_BPS,
+            discount_collection: None,
+            discount_bps: 0,
+            insurance_bps: DEFAULT_INSURANCE_BPS,
+            treasury: Pubkey::default(),
+            config_delay_secs: DEFAULT_CONFIG_DELAY,
+            paused: false,
+            max_fee_bps: DEFAULT_MAX_FEE_BPS,
+            freeze_before_close_secs: 0,
+            exits_during_freeze: true,
+            allow_duplicate_markets: false,
+            min_fee_bps: DEFAULT_MIN_FEE_BPS,
+            min_settle_delay: DEFAULT_MIN_SETTLE_DELAY,
+            creation_mode: CreationMode::Open,
+            creation_fee_lamports: 0,
+            stale_after_secs: DEFAULT_STALE_AFTER_SECS,
+            max_open_liability: 0,
+            max_markets_per_day: 0,
+            yield_adapter: Pubkey::default(),
+            max_idle_bps: 0,
+        }
+    }
+}
+
+impl ConfigParams {
+    pub const INIT_SPACE: usize =
+        8 +      // min_market_duration
+        8 +      // max_market_duration
+        8 +      // epoch_length
+        2 +      // trading_fee_bps
+        2 +      // referral_fee_bps
+        (1 + 32) + // discount_collection
+        2 +      // discount_bps
+        2 +      // insurance_bps
+        32 +     // treasury
+        8 +      // config_delay_secs
+        1 +      // paused
+        2 +      // max_fee_bps
+        8 +      // freeze_before_close_secs
+        1 +      // exits_during_freeze
+        1 +      // allow_duplicate_markets
+        2 +      // min_fee_bps
+        8 +      // min_settle_delay
+        1 +      // creation_mode
+        8 +      // creation_fee_lamports
+        8 +      // stale_after_secs
+        8 +      // max_open_liability
+        4 +      // max_markets_per_day
+        32 +     // yield_adapter
+        2;       // max_idle_bps
+
+    pub fn validate(&self) -> Result<()> {
+        require!(
+            self.min_market_duration >= 0
+                && self.max_market_duration >= self.min_market_duration,
+            MarketError::InvalidConfig
+        );
+        require!(
+            self.epoch_length > 0
+                && self.config_delay_secs >= 0
+                && self.freeze_before_close_secs >= 0
+                && self.min_settle_delay >= 0
+                && self.stale_after_secs >= 0,
+            MarketError::InvalidConfig
+        );
+        require!(
+            self.trading_fee_bps as u64 <= BPS_DENOMINATOR
+                && self.referral_fee_bps as u64 <= BPS_DENOMINATOR
+                && self.discount_bps as u64 <= BPS_DENOMINATOR
+                && self.insurance_bps as u64 <= BPS_DENOMINATOR
+                && self.max_fee_bps as u64 <= BPS_DENOMINATOR
+                && self.min_fee_bps <= self.max_fee_bps
+                && self.max_idle_bps <= MAX_IDLE_BPS,
+            MarketError::InvalidConfig
+        );
+        Ok(())
+    }
+
+    pub fn apply(&self, config: &mut Config) {
+        config.min_market_duration = self.min_market_duration;
+        config.max_market_duration = self.max_market_duration;
+        config.epoch_length = self.epoch_length;
+        config.trading_fee_bps = self.trading_fee_bps;
+        config.referral_fee_bps = self.referral_fee_bps;
+        config.discount_collection = self.discount_collection;
+        config.discount_bps = self.discount_bps;
+        config.insurance_bps = self.insurance_bps;
+        config.treasury = self.treasury;
+        config.config_delay_secs = self.config_delay_secs;
+        config.paused = self.paused;
+        config.max_fee_bps = self.max_fee_bps;
+        config.freeze_before_close_secs = self.freeze_before_close_secs;
+        config.exits_during_freeze = self.exits_during_freeze;
+        config.allow_duplicate_markets = self.allow_duplicate_markets;
+        config.min_fee_bps = self.min_fee_bps;
+        config.min_settle_delay = self.min_settle_delay;
+        config.creation_mode = self.creation_mode;
+        config.creation_fee_lamports = self.creation_fee_lamports;
+        config.stale_after_secs = self.stale_after_secs;
+        config.max_open_liability = self.max_open_liability;
+        config.max_markets_per_day = self.max_markets_per_day;
+        config.yield_adapter = self.yield_adapter;
+        config.max_idle_bps = self.max_idle_bps;
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Market {
+    pub version: u8,
+    pub market_id: u64,
+    pub tenant: [u8; 16], // brand the market belongs to; zero = none. Kept at MARKET_TENANT_OFFSET
+    // Fixed-size filter fields, at the MARKET_*_OFFSET constants
+    pub creator: Pubkey,
+    pub resolution_time: i64,
+    pub resolved: bool,
+    pub outcome: MarketOutcome,
+    pub category: u8,
+    #[max_len(MAX_QUESTION_LEN)]
+    pub question: String,
+    pub created_at: i64,
+    pub yes_pool: u64,
+    pub no_pool: u64,
+    pub total_liquidity: u64,
+    pub outcome_mints: Option<OutcomeMints>, // None for markets without outcome tokens
+    pub fee_collected: u64,
+    pub total_volume: u64,
+    pub trade_count: u32,
+    pub unique_bettors: u32,
+    pub time_decay: bool,
+    pub time_decay_floor_bps: u16,
+    pub sequence: u64, // bumped on every trade
+    pub yes_tokens: u64, // outstanding (unclaimed) YES tokens
+    pub no_tokens: u64,  // outstanding (unclaimed) NO tokens
+    pub total_claimed: u64,
+    pub bump: u8,
+    pub vault_bump: u8,
+    #[max_len(0)]
+    pub description: String, // resolution criteria, sized on demand
+    pub series: Option<Pubkey>,
+    pub fee_bps: Option<u16>, // overrides config.trading_fee_bps when set
+    pub oracle_key: Option<Pubkey>, // signs attestations for resolve_with_attestation
+    pub oracle_eth_address: Option<[u8; 20]>, // for resolve_with_eth_attestation
+    pub resolution_commitment: Option<[u8; 32]>, // keccak(outcome || salt)
+    pub resolution_committed_at: i64,
+    pub bet_reveal_window: i64, // seconds to reveal a bet commitment; 0 = direct betting
+    pub price_oracle: Option<PriceOracle>, // feed that resolves a price-threshold market
+    pub resolved_at: i64,
+    pub disputed: bool, // claims are held while a dispute is open
+    pub claims_count: u32, // predictions settled by claim_reward or record_loss
+    pub pending_owner: Option<Pubkey>, // proposed new creator, until accepted
+    pub correction_window_secs: i64,
+    pub outstanding_entitlement: u64, // winner payouts still owed; set at resolution
+    pub last_reconciled_at: i64,
+    pub drift_lamports: i64, // vault balance above rent minus liabilities, at last reconcile
+    pub needs_attention: bool, // set by reconcile_vault on a shortfall; blocks betting
+    pub claim_period_secs: i64,
+    pub dust_swept: u64,
+    pub sponsored_amount: u64, // paid to winners on top of the pools
+    pub imbalance_rebate_bps: u16,
+    pub fee_curve: Option<FeeCurve>,
+    pub fee_schedule: Option<FeeSchedule>,
+    pub freeze_before_close_secs: i64,
+    pub exits_during_freeze: bool, // resting orders may be cancelled while frozen
+    pub wsol_vault: bool, // collateral is wSOL in the market's associated token account
+    pub collateral_mint: Pubkey,  // wSOL mint of either token program; set by init_wsol_vault
+    pub collateral_vault: Pubkey, // the market's token account for collateral_mint
+    pub subjective: bool, // manual resolutions must record evidence
+    pub cooloff_secs: i64, // window for cancel_prediction; 0 disables it
+    pub early_exit_penalty_bps: u16, // 0 disables early_exit
+    pub last_trade_at: i64,
+    pub min_settle_delay: i64, // seconds after the last trade before resolution
+    pub mode: MarketMode,
+    pub bankroll: u64, // bookmaker: creator's seed backing payouts; 0 for the AMM
+    pub locked_liability: u64, // bookmaker: payouts owed beyond their own stakes
+    pub yes_odds_bps: u32, // bookmaker: payout per lamport on YES for new bets
+    pub no_odds_bps: u32,
+    pub event_seq: u64, // numbers every event about this market, from 1 with no gaps
+    pub winnings_fee_bps: u16, // cut of each winning claim's profit; 0 for none
+    pub resolvers: [Pubkey; 3], // resolve_market tiers, in order; default = unused
+    pub resolver_delays: [i64; 3], // seconds after resolution_time each tier opens
+    pub loser_rebate_bps: u16, // share of fees set aside for losers at resolution
+    pub yes_deposits: u64, // stakes open on YES, rebates are pro rata to
+    pub no_deposits: u64,
+    pub rebate_pot: u64, // carved out of fee_collected at resolution
+    pub rebate_paid: u64,
+    pub last_activity_at: i64, // latest trade, exit or sponsorship
+    pub stale: bool, // set by flag_stale, cleared at resolution
+    pub keeper_budget: u64, // lamports in the vault for keeper tips
+    pub keeper_funder: Pubkey, // gets the unused budget back; default = none yet
+    pub dispute_window_secs: i64, // 0 = the dispute config's window
+    pub liability_creator: Pubkey, // creator at creation, whose CreatorStats the deposits count against
+    pub creator_liability: u64, // deposits counted there, until released after resolution
+    pub auction_end: i64, // end of the open auction; 0 = none
+    pub auction_yes: u64, // lamports bid on YES in the open auction
+    pub auction_no: u64,
+    pub auction_yes_tokens: u64, // YES tokens the auction cleared for, split pro rata
+    pub auction_no_tokens: u64,
+    pub auction_settled: bool,
+    pub resolution_snapshot: ResolutionSnapshot, // pool state claims are paid from
+    pub yes_positions: u32, // open positions on each side
+    pub no_positions: u32,
+    pub eligible_claims: u32, // positions owed a claim: winners, or every position if voided
+    pub eligible_claimed: u32, // of those, fully claimed or refunded
+    pub fully_settled: bool, // all eligible claims landed, or the claim deadline passed
+    pub max_move_per_trade_bps: u16, // circuit breaker limits; 0 = none
+    pub max_move_per_window_bps: u16,
+    pub move_window_start: i64, // start of the circuit breaker's current window; 0 = none yet
+    pub move_window_open_bps: u16, // implied YES probability as that window opened
+    pub claim_window_secs: i64, // after claims open, how long winners have to claim; 0 = forever
+    pub escheated: u64, // unclaimed winnings moved to the unclaimed rewards pool
+    pub payout_params: PayoutParams, // fixed by the first claim or publish_payout_params
+    pub idle_adapter: Pubkey, // yield adapter holding idle_deployed
+    pub idle_deployed: u64, // collateral deposited with the yield adapter, not yet recalled
+    pub max_bet: u64, // largest single bet once any ramp is over; 0 = no limit
+    pub max_bet_ramp_secs: i64, // from created_at; 0 = no ramp
+    pub max_bet_ramp_start_bps: u16, // share of max_bet allowed at created_at
+    pub resolvers_active_at: i64, // resolution is blocked before this; i64::MAX after a revocation
+    pub parent: Option<ParentCondition>, // set on a conditional market
+    pub parent_met: bool, // the parent resolved the required way, so this market may resolve
+    pub compressed: bool, // bets are receipt tree leaves
+    pub receipt_root: [u8; 32], // root of the receipt tree; zero until init_receipt_tree
+    pub receipt_count: u64, // leaves appended so far
+    pub reduce_only_window_secs: i64, // before betting close; 0 = none
+    pub escrow_disputed_claims: bool, // claims during a dispute are paid into escrow
+    pub escrowed_claims: u64, // net rewards held in EscrowedClaims
+    pub pool_history_len: u8, // capacity of pool_history
+    pub pool_history_head: u8, // oldest checkpoint once pool_history is full
+    #[max_len(0)]
+    pub pool_history: Vec<PoolCheckpoint>, // ring buffer of the latest trades' pools; Market::space sizes it
+    pub collateral_decimals: u8, // SOL_DECIMALS, or the collateral mint's
+    pub resolution_program: Option<Pubkey>, // sole resolver, via resolve_via_program
+    pub push_policy: PushPolicy, // what resolve_push resolves to
+    pub max_participants: u32, // distinct wallets that may enter; 0 = no limit
+    pub participants: u32, // wallets that have opened a position, never decremented
+    pub rounding_dust_accrued: u64, // entitlement left behind by claims rounding down
+    pub creator_tips: u64, // tips held in the vault for the creator, outside all payouts
+    pub rounding_policy: RoundingPolicy, // how fees, token outputs and claims round
+    #[max_len(MAX_HOUSE_ACCOUNTS)]
+    pub house_accounts: Vec<Pubkey>, // bet past max_bet, outside profile stats; creator-managed
+    pub net_volume: u64, // the part of total_volume that reached the pools, after every fee
+    pub generation: u64, // in the prediction seeds; 0 unless the ID is explicit and so reusable
+    pub live_predictions: u32, // prediction accounts opened under this generation, not yet closed
+    pub _reserved: Reserved<64>, // for fields added by later versions
+}
+
+impl Market {
+    /// Account size for a new market, with or without outcome mints and
+    /// with room for `pool_history_len` checkpoints, before any description
+    pub const fn space(with_outcome_mints: bool, pool_history_len: u8) -> usize {
+        let no_mints = if with_outcome_mints { 0 } else { OutcomeMints::INIT_SPACE };
+        8 + Self::INIT_SPACE - no_mints + PoolCheckpoint::INIT_SPACE * pool_history_len as usize
+    }
+
+    /// Records the pools after a trade, overwriting the oldest checkpoint
+    /// once the ring buffer is full
+    pub fn record_pool_checkpoint(&mut self, now: i64) {
+        if self.pool_history_len == 0 {
+            return;
+        }
+        let checkpoint = PoolCheckpoint {
+            sequence: self.sequence,
+            yes_pool: self.yes_pool,
+            no_pool: self.no_pool,
+            timestamp: now,
+        };
+        if self.pool_history.len() < self.pool_history_len as usize {
+            self.pool_history.push(checkpoint);
+        } else {
+            self.pool_history[self.pool_history_head as usize] = checkpoint;
+            self.pool_history_head = (self.pool_history_head + 1) % self.pool_history_len;
+        }
+    }
+
+    /// The pool checkpoints held, oldest first
+    pub fn pool_checkpoints(&self) -> impl Iterator<Item = &PoolCheckpoint> {
+        let (newer, older) = self.pool_history.split_at(self.pool_history_head as usize);
+        older.iter().chain(newer)
+    }
+
+    /// Implied probability of YES (bps): the YES share of the total pool
+    pub fn implied_yes_probability_bps(&self) -> u16 {
+        implied_yes_probability_bps(self.yes_pool, self.no_pool)
+    }
+
+    /// Sequence number for the next event about this market. Each event
+    /// takes one, so an indexer can detect a missed event as a gap
+    pub fn next_event_seq(&mut self) -> u64 {
+        self.event_seq = self.event_seq.saturating_add(1);
+        self.event_seq
+    }
+
+    /// The first resolver tier `resolver` holds. An unset primary stands
+    /// for the creator, so a transferred market follows its new owner
+    pub fn resolver_tier(&self, resolver: &Pubkey) -> Option<usize> {
+        if self.resolvers[0] == Pubkey::default() && *resolver == self.creator {
+            return Some(0);
+        }
+        self.resolvers
+            .iter()
+            .position(|key| key == resolver && *key != Pubkey::default())
+    }
+
+    /// The options this market was created with, as clone_market reuses
+    /// them; config defaults are pinned to the values the market took
+    pub fn options(&self) -> MarketOptions {
+        MarketOptions {
+            time_decay: self.time_decay,
+            time_decay_floor_bps: self.time_decay_floor_bps,
+            oracle_key: self.oracle_key,
+            oracle_eth_address: self.oracle_eth_address,
+            bet_reveal_window: self.bet_reveal_window,
+            price_oracle: self.price_oracle.clone(),
+            correction_window_secs: self.correction_window_secs,
+            claim_period_secs: self.claim_period_secs,
+            imbalance_rebate_bps: self.imbalance_rebate_bps,
+            fee_curve: self.fee_curve,
+            fee_schedule: self.fee_schedule,
+            freeze_before_close_secs: Some(self.freeze_before_close_secs),
+            exits_during_freeze: Some(self.exits_during_freeze),
+            wsol_vault: self.wsol_vault,
+            subjective: self.subjective,
+            cooloff_secs: self.cooloff_secs,
+            early_exit_penalty_bps: self.early_exit_penalty_bps,
+            mode: self.mode,
+            yes_odds_bps: self.yes_odds_bps,
+            no_odds_bps: self.no_odds_bps,
+            winnings_fee_bps: self.winnings_fee_bps,
+            loser_rebate_bps: self.loser_rebate_bps,
+            // A clone's creator funds their own budget
+            keeper_budget: 0,
+            category: self.category,
+            dispute_window_secs: self.dispute_window_secs,
+            open_auction_secs: if self.auction_end > 0 {
+                self.auction_end - self.created_at
+            } else {
+                0
+            },
+            max_move_per_trade_bps: self.max_move_per_trade_bps,
+            max_move_per_window_bps: self.max_move_per_window_bps,
+            tenant: self.tenant,
+            claim_window_secs: self.claim_window_secs,
+            max_bet: self.max_bet,
+            max_bet_ramp_secs: self.max_bet_ramp_secs,
+            max_bet_ramp_start_bps: self.max_bet_ramp_start_bps,
+            parent: self.parent,
+            compressed: self.compressed,
+            reduce_only_window_secs: self.reduce_only_window_secs,
+            escrow_disputed_claims: self.escrow_disputed_claims,
+            pool_history_len: self.pool_history_len,
+            resolution_program: self.resolution_program,
+            push_policy: self.push_policy,
+            max_participants: self.max_participants,
+            rounding_policy: self.rounding_policy,
+        }
+    }
+
+    /// The largest bet accepted at `now`, or None when bets are uncapped.
+    /// During the ramp the cap rises linearly from max_bet_ramp_start_bps of
+    /// max_bet at creation to all of it at the ramp's end
+    pub fn max_bet_at(&self, now: i64) -> Option<u64> {
+        if self.max_bet == 0 {
+            return None;
+        }
+        let elapsed = now.saturating_sub(self.created_at).max(0);
+        if elapsed >= self.max_bet_ramp_secs {
+            return Some(self.max_bet);
+        }
+        let start_bps = self.max_bet_ramp_start_bps as u128;
+        let bps = start_bps
+            + (BPS_DENOMINATOR as u128 - start_bps) * (elapsed as u128)
+                / (self.max_bet_ramp_secs as u128);
+        Some(((self.max_bet as u128) * bps / (BPS_DENOMINATOR as u128)) as u64)
+    }
+
+    /// Lamports the vault must hold, above rent, for the market to be
+    /// solvent: its outcome liabilities plus its liquid liabilities
+    pub fn liabilities(&self) -> u64 {
+        self.outcome_liabilities().saturating_add(self.liquid_liabilities())
+    }
+
+    /// What the pot owes, depending on the outcome. Unresolved, the whole
+    /// pot is at stake. Resolved, the outstanding winner entitlements under
+    /// the resolution snapshot are owed, plus the seed's residual claim on
+    /// whatever of the pot winners haven't been paid. Voided, every
+    /// unrefunded deposit. Bookmaker and pari-mutuel markets also owe the
+    /// creator's seed until withdrawn
+    pub fn outcome_liabilities(&self) -> u64 {
+        match self.outcome.winner() {
+            Some(outcome) if self.resolved && self.mode == MarketMode::Bookmaker => {
+                let unclaimed = if outcome { self.yes_tokens } else { self.no_tokens };
+                unclaimed.saturating_add(self.bankroll_due())
+            }
+            Some(outcome) if self.resolved => {
+                let tokens = if outcome { self.yes_tokens } else { self.no_tokens };
+                let pool = self.resolution_snapshot.pool(outcome);
+                let pot = self.resolution_snapshot.pot();
+                // Rounded up, so per-claim rounding can never undercount
+                let entitlements = if pool == 0 {
+                    0
+                } else {
+                    ((tokens as u128) * (pot as u128)).div_ceil(pool as u128) as u64
+                };
+                entitlements
+                    .max(
+                        pot.saturating_sub(self.total_claimed)
+                            .saturating_sub(self.dust_swept)
+                            .saturating_sub(self.escheated),
+                    )
+                    .saturating_add(self.bankroll_due())
+            }
+            None if self.resolved => self
+                .total_volume
+                .saturating_add(self.total_liquidity)
+                .saturating_add(self.sponsored_amount)
+                .saturating_sub(self.total_claimed),
+            _ => self.total_winnings().saturating_add(self.bankroll),
+        }
+    }
+
+    /// Rejects a bet that moved the implied probability from `before_bps`
+    /// by more than max_move_per_trade_bps, or from where the current
+    /// window opened by more than max_move_per_window_bps. A clock reading
+    /// behind the window's start, as can happen across slots, counts as
+    /// inside it
+    pub fn check_circuit_breaker(&mut self, before_bps: u16, now: i64) -> Result<()> {
+        let after_bps = self.implied_yes_probability_bps();
+        if self.max_move_per_trade_bps > 0 {
+            require!(
+                after_bps.abs_diff(before_bps) <= self.max_move_per_trade_bps,
+                MarketError::CircuitBreakerTripped
+            );
+        }
+        if self.max_move_per_window_bps > 0 {
+            if self.move_window_start == 0
+                || now.saturating_sub(self.move_window_start) >= CIRCUIT_BREAKER_WINDOW_SECS
+            {
+                self.move_window_start = now;
+                self.move_window_open_bps = before_bps;
+            }
+            require!(
+                after_bps.abs_diff(self.move_window_open_bps) <= self.max_move_per_window_bps,
+                MarketError::CircuitBreakerTripped
+            );
+        }
+        Ok(())
+    }
+
+    /// Escrowed claim lamports owed back to the vault: those paid under an
+    /// outcome a settled dispute overturned, not yet clawed back
+    pub fn escrow_clawback(&self) -> u64 {
+        if self.disputed {
+            0
+        } else {
+            self.escrowed_claims
+        }
+    }
+
+    /// Whether `wallet` is one of the market's house accounts
+    pub fn is_house(&self, wallet: &Pubkey) -> bool {
+        self.house_accounts.contains(wallet)
+    }
+
+    /// Counts a position opened on `side`
+    pub fn add_position(&mut self, side: bool) {
+        if side {
+            self.yes_positions = self.yes_positions.saturating_add(1);
+        } else {
+            self.no_positions = self.no_positions.saturating_add(1);
+        }
+    }
+
+    /// Counts a position on `side` taken out before resolution
+    pub fn remove_position(&mut self, side: bool) {
+        if side {
+            self.yes_positions = self.yes_positions.saturating_sub(1);
+        } else {
+            self.no_positions = self.no_positions.saturating_sub(1);
+        }
+    }
+
+    /// Prediction PDA seed for the market's generation: empty for 0, so
+    /// markets under counter-assigned IDs keep their original addresses
+    pub fn generation_seed(&self) -> Vec<u8> {
+        generation_seed(self.generation)
+    }
+
+    /// Stamps a prediction account just opened on the market with its
+    /// generation, and counts it live until closed
+    pub fn open_prediction(&mut self, prediction: &mut Prediction) {
+        prediction.generation = ReserveU64(self.generation);
+        self.live_predictions = self.live_predictions.saturating_add(1);
+    }
+
+    /// Stops counting a prediction account being closed. One left from an
+    /// earlier market under the same ID was never counted
+    pub fn close_prediction(&mut self, prediction: &Prediction) {
+        if prediction.generation.0 == self.generation {
+            self.live_predictions = self.live_predictions.saturating_sub(1);
+        }
+    }
+
+    /// Sets the claims owed under the current outcome: one per winning
+    /// position, or one per position when voided. Runs whenever the
+    /// outcome is set, which is always before the first claim
+    pub fn reset_eligible_claims(&mut self) {
+        self.eligible_claims = match self.outcome.winner() {
+            Some(true) => self.yes_positions,
+            Some(false) => self.no_positions,
+            None => self.yes_positions.saturating_add(self.no_positions),
+        };
+        self.eligible_claimed = 0;
+    }
+
+    /// Whether the market opened with an auction that hasn't been settled;
+    /// no bet is priced until it is
+    pub fn auction_pending(&self) -> bool {
+        self.auction_end > 0 && !self.auction_settled
+    }
+
+    /// Bids held in the vault for an unsettled open auction
+    pub fn auction_escrow(&self) -> u64 {
+        if self.auction_pending() {
+            self.auction_yes.saturating_add(self.auction_no)
+        } else {
+            0
+        }
+    }
+
+    /// What can be paid out of the vault at any time, regardless of how
+    /// the market resolves: fees, loser rebates, keeper budget, auction
+    /// escrow and creator tips. deposit_idle always leaves this much liquid
+    pub fn liquid_liabilities(&self) -> u64 {
+        self.fee_collected
+            .saturating_add(self.rebate_outstanding())
+            .saturating_add(self.keeper_budget)
+            .saturating_add(self.auction_escrow())
+            .saturating_add(self.creator_tips)
+    }
+
+    /// Part of the rebate pot losers have yet to claim
+    pub fn rebate_outstanding(&self) -> u64 {
+        self.rebate_pot.saturating_sub(self.rebate_paid)
+    }
+
+    /// What a resolved market still owes its creator: for a bookmaker, the
+    /// bankroll plus every stake, less the winners' payouts, claimed or
+    /// not, and anything already withdrawn; for a pari-mutuel market, the
+    /// seed until withdrawn. Zero for AMM markets
+    pub fn bankroll_due(&self) -> u64 {
+        match self.outcome.winner() {
+            Some(_) if self.resolved && self.mode == MarketMode::Parimutuel => self.bankroll,
+            Some(outcome) if self.resolved && self.mode == MarketMode::Bookmaker => {
+                let unclaimed = if outcome { self.yes_tokens } else { self.no_tokens };
+                self.bankroll
+                    .saturating_add(self.resolution_snapshot.yes_pool)
+                    .saturating_add(self.resolution_snapshot.no_pool)
+                    .saturating_sub(unclaimed)
+                    .saturating_sub(self.total_claimed)
+                    .saturating_sub(self.escheated)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Total owed to the unclaimed tokens on the `outcome` side of a
+    /// resolved market, rounded down; never less than the sum of the
+    /// individual rewards
+    pub fn winner_entitlement(&self, outcome: bool) -> u64 {
+        let tokens = self.unredeemed_tokens(outcome);
+        if self.mode == MarketMode::Bookmaker {
+            return tokens;
+        }
+        let pool = self.resolution_snapshot.pool(outcome);
+        if pool == 0 {
+            return 0;
+        }
+        let pot = self.resolution_snapshot.pot();
+        self.rounding_policy.payout((tokens as u128) * (pot as u128), pool as u128) as u64
+    }
+
+    /// What `tokens` on `side` are worth at the live pools: their payout
+    /// were the side to win now, weighted by its implied probability
+    pub fn mark_value(&self, side: bool, tokens: u64) -> u64 {
+        let payout = if self.mode == MarketMode::Bookmaker {
+            tokens as u128
+        } else {
+            let pool = if side { self.yes_pool } else { self.no_pool };
+            if pool == 0 {
+                return 0;
+            }
+            (tokens as u128) * (self.total_winnings() as u128) / (pool as u128)
+        };
+        let probability = side_probability_bps(self.yes_pool, self.no_pool, side);
+        (payout * probability as u128 / BPS_DENOMINATOR as u128).min(u64::MAX as u128) as u64
+    }
+
+    /// Outcome tokens on `side` not yet redeemed
+    pub fn unredeemed_tokens(&self, side: bool) -> u64 {
+        if side {
+            self.yes_tokens
+        } else {
+            self.no_tokens
+        }
+    }
+
+    /// What winners share: both pools plus sponsorships
+    pub fn total_winnings(&self) -> u64 {
+        self.yes_pool
+            .saturating_add(self.no_pool)
+            .saturating_add(self.sponsored_amount)
+    }
+
+    /// When the market stops earning liquidity-mining rewards
+    pub fn incentives_end(&self) -> i64 {
+        if self.resolved {
+            self.resolved_at.min(self.betting_close_time())
+        } else {
+            self.betting_close_time()
+        }
+    }
+
+    /// Time from which claims are accepted and the outcome is final
+    pub fn claims_open_at(&self) -> i64 {
+        self.resolved_at.saturating_add(self.correction_window_secs)
+    }
+
+    /// Time after which leftover vault dust may be swept, if ever
+    pub fn claim_deadline(&self) -> Option<i64> {
+        (self.claim_period_secs > 0)
+            .then(|| self.claims_open_at().saturating_add(self.claim_period_secs))
+    }
+
+    /// Last moment a winner may claim, if the market has a claim window
+    pub fn claim_window_end(&self) -> Option<i64> {
+        (self.claim_window_secs > 0)
+            .then(|| self.claims_open_at().saturating_add(self.claim_window_secs))
+    }
+
+    /// Time after which no new bets are accepted
+    pub fn betting_close_time(&self) -> i64 {
+        self.resolution_time
+    }
+
+    /// Whether `now` falls in the reduce-only window before betting close
+    pub fn reduce_only(&self, now: i64) -> bool {
+        self.reduce_only_window_secs > 0
+            && now
+                >= self
+                    .betting_close_time()
+                    .saturating_sub(self.reduce_only_window_secs)
+    }
+
+    /// Whether `now` falls in the anti-sniping freeze before betting close
+    pub fn betting_frozen(&self, now: i64) -> bool {
+        now >= self
+            .betting_close_time()
+            .saturating_sub(self.freeze_before_close_secs)
+    }
+
+    /// Payout weight (in bps) for a bet placed at `now`
+    /// Full weight at creation, decaying linearly to the floor at betting
+    /// close. Always BPS_DENOMINATOR when time decay is off
+    pub fn time_weight_bps(&self, now: i64) -> u64 {
+        if !self.time_decay {
+            return BPS_DENOMINATOR;
+        }
+        let close = self.betting_close_time();
+        let total = close.saturating_sub(self.created_at).max(1) as u128;
+        let remaining = close.saturating_sub(now).clamp(0, total as i64) as u128;
+        let floor = self.time_decay_floor_bps as u128;
+        let span = (BPS_DENOMINATOR as u128).saturating_sub(floor);
+        (floor + span * remaining / total) as u64
+    }
+}
+
+// init_market creates the account through a system program CPI, which caps
+// its size; the largest market must still fit
+const _: () = assert!(Market::space(true, MAX_POOL_HISTORY) <= MAX_PERMITTED_DATA_INCREASE);
+
+// The derived size of a market without mints, checkpoints or description,
+// as new markets are created: 1700 bytes through version 6's fields, then
+// the 64-byte reserve. Markets created while the reserve was 2 bytes end
+// early; Reserved reads and writes them only as far as they go. Fields
+// added later take their bytes from _reserved; growing past it means a
+// migrate_market upgrade
+const _: () = assert!(Market::space(false, 0) == 8 + 1700 + 62);
+
+/// Shared settings for markets instantiated from a question pattern,
+/// e.g. "Will {0} beat {1}?" for every fixture of a league
+#[account]
+pub struct MarketTemplate {
+    pub creator: Pubkey,
+    pub name: String,
+    pub question_format: String, // `{0}`..`{3}` are filled per market
+    pub category: u8,
+    pub trading_fee_bps: Option<u16>, // None uses config.trading_fee_bps
+    pub options: MarketOptions,
+    pub initial_liquidity: u64,
+    pub resolution_delay: i64, // seconds from event start to resolution
+    pub instance_count: u64, // settings freeze once this is nonzero
+    pub bump: u8,
+}
+
+impl MarketTemplate {
+    pub const INIT_SPACE: usize =
+        32 +          // creator
+        (4 + 32) +    // name
+        (4 + MAX_QUESTION_LEN) + // question_format
+        1 +           // category
+        (1 + 2) +     // trading_fee_bps
+        (1 + 2 + 33 + 21 + 8 + 1 + PriceOracle::INIT_SPACE + 8 + 8 + 2
+            + 1 + FeeCurve::INIT_SPACE + 1 + FeeSchedule::INIT_SPACE + 9 + 2 + 1 + 1 + 8 + 2
+            + 1 + 4 + 4 + 2 + 2 + 8 + 1 + 8 + 8 + 2 + 2 + 16 + 8 + 8 + 8 + 2
+            + 1 + ParentCondition::INIT_SPACE + 1 + 8 + 1 + 1 + 33 + 1 + 4 + 1) + // options
+        8 +           // initial_liquidity
+        8 +           // resolution_delay
+        8 +           // instance_count
+        1;            // bump
+}
+
+/// A pushed price, mirroring the price/confidence/publish_time shape of
+/// common pull oracles so markets can resolve from it
+#[account]
+pub struct PriceFeed {
+    pub publisher: Pubkey,
+    pub feed_id: u64,
+    pub price: i64,
+    pub confidence: u64, // +/- around price, same units
+    pub publish_time: i64,
+    pub bump: u8,
+}
+
+impl PriceFeed {
+    pub const INIT_SPACE: usize =
+        32 +     // publisher
+        8 +      // feed_id
+        8 +      // price
+        8 +      // confidence
+        8 +      // publish_time
+        1;       // bump
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PriceSample {
+    pub price: i64,
+    pub timestamp: i64,
+}
+
+/// A market's pools just after one of its trades
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, Default, PartialEq, Eq, InitSpace)]
+pub struct PoolCheckpoint {
+    pub sequence: u64, // the market's trade sequence after the trade
+    pub yes_pool: u64,
+    pub no_pool: u64,
+    pub timestamp: i64,
+}
+
+/// Ring buffer of feed prices recorded during a market's TWAP window
+#[account]
+pub struct PriceSamples {
+    pub market_id: u64,
+    pub head: u32, // next slot to write
+    pub count: u32,
+    pub last_slot: u64,
+    pub samples: [PriceSample; TWAP_SAMPLE_CAPACITY],
+    pub bump: u8,
+}
+
+impl PriceSamples {
+    pub const INIT_SPACE: usize =
+        8 +                             // market_id
+        4 +                             // head
+        4 +                             // count
+        8 +                             // last_slot
+        16 * TWAP_SAMPLE_CAPACITY +     // samples
+        1;                              // bump
+
+    /// Appends a sample, overwriting the oldest once full
+    pub fn push(&mut self, sample: PriceSample) {
+        self.samples[self.head as usize] = sample;
+        self.head = (self.head + 1) % TWAP_SAMPLE_CAPACITY as u32;
+        self.count = (self.count + 1).min(TWAP_SAMPLE_CAPACITY as u32);
+    }
+
+    /// Time-weighted average price up to `end`: each sample counts for the
+    /// time until the next one (the last until `end`). Falls back to the
+    /// plain mean when the samples span no time
+    pub fn twap(&self, end: i64) -> i64 {
+        let count = self.count as usize;
+        let oldest = (self.head as usize + TWAP_SAMPLE_CAPACITY - count) % TWAP_SAMPLE_CAPACITY;
+        let ordered = (0..count).map(|i| self.samples[(oldest + i) % TWAP_SAMPLE_CAPACITY]);
+
+        let mut weighted: i128 = 0;
+        let mut total_time: i128 = 0;
+        let mut sum: i128 = 0;
+        let mut iter = ordered.peekable();
+        while let Some(sample) = iter.next() {
+            let until = iter.peek().map_or(end, |next| next.timestamp);
+            let span = until.saturating_sub(sample.timestamp).max(0) as i128;
+            weighted += sample.price as i128 * span;
+            total_time += span;
+            sum += sample.price as i128;
+        }
+        if total_time == 0 {
+            (sum / count.max(1) as i128) as i64
+        } else {
+            (weighted / total_time) as i64
+        }
+    }
+}
+
+/// Protocol backstop for vault shortfalls, fed by a slice of trading
+/// fees and by donations
+#[account]
+pub struct InsuranceFund {
+    pub total_deposited: u64,
+    pub total_covered: u64,
+    pub bump: u8,
+}
+
+impl InsuranceFund {
+    pub const INIT_SPACE: usize =
+        8 +      // total_deposited
+        8 +      // total_covered
+        1;       // bump
+}
+
+/// Winnings left unclaimed past their market's claim window, escheated by
+/// escheat_unclaimed and granted back out to markets as loser rebates or
+/// keeper tips. Whatever isn't held for rebates is for keeper tips
+#[account]
+pub struct UnclaimedRewardsPool {
+    pub total_escheated: u64,
+    pub total_granted: u64,
+    pub rebate_funds: u64, // config.escheat_rebate_bps of each escheat, until granted
+    pub bump: u8,
+}
+
+impl UnclaimedRewardsPool {
+    pub const INIT_SPACE: usize =
+        8 +      // total_escheated
+        8 +      // total_granted
+        8 +      // rebate_funds
+        1;       // bump
+
+    /// Lamports available for keeper tips, given the pool's balance and rent
+    pub fn keeper_funds(&self, lamports: u64, rent: u64) -> u64 {
+        lamports.saturating_sub(rent).saturating_sub(self.rebate_funds)
+    }
+}
+
+/// Why the creator resolved a market the way they did, kept for disputes
+/// Written by every manual resolution path; amend_resolution overwrites it
+#[account]
+pub struct Resolution {
+    pub market_id: u64,
+    pub resolver: Pubkey,
+    pub evidence: String, // URL or short justification, up to MAX_EVIDENCE_LEN
+    pub recorded_at: i64,
+    pub bump: u8,
+}
+
+impl Resolution {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        32 +     // resolver
+        (4 + MAX_EVIDENCE_LEN) + // evidence
+        8 +      // recorded_at
+        1;       // bump
+}
+
+/// Program-wide treasury named by config.treasury once created; collects
+/// market fees via collect_fees_many and pays out only through the timelock
+#[account]
+pub struct Treasury {
+    pub total_collected: u64,
+    pub total_withdrawn: u64,
+    pub bump: u8,
+}
+
+impl Treasury {
+    pub const INIT_SPACE: usize =
+        8 +      // total_collected
+        8 +      // total_withdrawn
+        1;       // bump
+}
+
+/// A treasury withdrawal waiting out the config timelock
+#[account]
+pub struct PendingTreasuryWithdrawal {
+    pub amount: u64,
+    pub recipient: Pubkey,
+    pub proposed_at: i64,
+    pub executable_at: i64,
+    pub bump: u8,
+}
+
+impl PendingTreasuryWithdrawal {
+    pub const INIT_SPACE: usize =
+        8 +      // amount
+        32 +     // recipient
+        8 +      // proposed_at
+        8 +      // executable_at
+        1;       // bump
+}
+
+/// Protocol-token staking: escrows staked tokens and holds the fee
+/// lamports routed to stakers until they claim them
+#[account]
+pub struct StakingVault {
+    pub mint: Pubkey,
+    pub fee_share_bps: u16, // share of each collect_fees_many total paid in
+    pub unbonding_secs: i64,
+    pub total_staked: u64, // excludes tokens still unbonding
+    pub acc_reward_per_token: u128, // lamports, scaled by STAKING_ACC_SCALE
+    pub undistributed: u64, // fees paid in while nothing was staked
+    pub total_distributed: u64,
+    pub total_claimed: u64,
+    pub bump: u8,
+    pub tokens_bump: u8,
+}
+
+impl StakingVault {
+    pub const INIT_SPACE: usize =
+        32 +     // mint
+        2 +      // fee_share_bps
+        8 +      // unbonding_secs
+        8 +      // total_staked
+        16 +     // acc_reward_per_token
+        8 +      // undistributed
+        8 +      // total_distributed
+        8 +      // total_claimed
+        1 +      // bump
+        1;       // tokens_bump
+
+    /// Credit fee lamports to stakers pro rata. Fees that arrive while
+    /// nothing is staked are held over to the next distribution
+    pub fn distribute(&mut self, amount: u64) {
+        let pending = self.undistributed.saturating_add(amount);
+        if self.total_staked == 0 {
+            self.undistributed = pending;
+            return;
+        }
+        self.acc_reward_per_token = self.acc_reward_per_token.saturating_add(
+            pending as u128 * STAKING_ACC_SCALE / self.total_staked as u128,
+        );
+        self.undistributed = 0;
+        self.total_distributed = self.total_distributed.saturating_add(pending);
+    }
+}
+
+/// One wallet's stake in the staking vault
+#[account]
+pub struct StakePosition {
+    pub owner: Pubkey,
+    pub staked: u64,
+    pub reward_debt: u128, // accumulator value already credited
+    pub unclaimed: u64, // lamports
+    pub unbonding: u64, // unstaked tokens waiting out the delay
+    pub unbonding_ends_at: i64,
+    pub bump: u8,
+}
+
+impl StakePosition {
+    pub const INIT_SPACE: usize =
+        32 +     // owner
+        8 +      // staked
+        16 +     // reward_debt
+        8 +      // unclaimed
+        8 +      // unbonding
+        8 +      // unbonding_ends_at
+        1;       // bump
+
+    /// Credit rewards earned since the last settlement
+    pub fn settle(&mut self, acc_reward_per_token: u128) {
+        let earned = (self.staked as u128 * acc_reward_per_token / STAKING_ACC_SCALE)
+            .saturating_sub(self.reward_debt);
+        self.unclaimed = self.unclaimed.saturating_add(earned as u64);
+        self.reset_debt(acc_reward_per_token);
+    }
+
+    pub fn reset_debt(&mut self, acc_reward_per_token: u128) {
+        self.reward_debt = self.staked as u128 * acc_reward_per_token / STAKING_ACC_SCALE;
+    }
+}
+
+/// Fee lamports set aside to buy back and burn the protocol token, and
+/// the buyback in flight between execute_buyback and settle_buyback
+#[account]
+pub struct BuybackEscrow {
+    pub protocol_mint: Pubkey,
+    pub executor: Pubkey, // the only wallet escrowed SOL is released to
+    pub buyback_bps: u16, // share of the treasury's part of each collection
+    pub escrowed: u64, // lamports awaiting a buyback
+    pub pending_sol: u64, // released, awaiting the burn; 0 between transactions
+    pub pending_min_tokens: u64,
+    pub pending_start_balance: u64, // token account balance when the SOL left
+    pub total_sol_spent: u64,
+    pub total_burned: u64,
+    pub bump: u8,
+    pub tokens_bump: u8,
+}
+
+impl BuybackEscrow {
+    pub const INIT_SPACE: usize =
+        32 +     // protocol_mint
+        32 +     // executor
+        2 +      // buyback_bps
+        8 +      // escrowed
+        8 +      // pending_sol
+        8 +      // pending_min_tokens
+        8 +      // pending_start_balance
+        8 +      // total_sol_spent
+        8 +      // total_burned
+        1 +      // bump
+        1;       // tokens_bump
+}
+
+/// One wallet's donation to a market's winners
+#[account]
+pub struct Sponsorship {
+    pub market_id: u64,
+    pub sponsor: Pubkey,
+    pub amount: u64,
+    pub name: String, // display name, empty if none was given
+    pub bump: u8,
+}
+
+impl Sponsorship {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        32 +     // sponsor
+        8 +      // amount
+        (4 + MAX_SPONSOR_NAME_LEN) + // name
+        1;       // bump
+}
+
+/// Liquidity-mining settings and the reward vault's outstanding promises
+#[account]
+pub struct RewardsConfig {
+    pub reward_mint: Pubkey,
+    pub emission_rate: u64, // reward tokens per second for each active market
+    pub total_owed: u64,    // accrued to positions but not yet claimed
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+impl RewardsConfig {
+    pub const INIT_SPACE: usize =
+        32 +     // reward_mint
+        8 +      // emission_rate
+        8 +      // total_owed
+        1 +      // bump
+        1;       // vault_bump
+}
+
+/// A market's reward-per-share accumulator
+#[account]
+pub struct MarketIncentives {
+    pub market_id: u64,
+    pub acc_reward_per_share: u128, // scaled by INCENTIVE_ACC_SCALE
+    pub total_shares: u64,
+    pub last_accrued_at: i64,
+    pub bump: u8,
+}
+
+impl MarketIncentives {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        16 +     // acc_reward_per_share
+        8 +      // total_shares
+        8 +      // last_accrued_at
+        1;       // bump
+
+    /// Emit rewards for the time since the last accrual, up to `until`
+    /// Emission is capped by the vault balance not already owed, so an
+    /// empty vault simply stops rewards
+    pub fn accrue(&mut self, rewards: &mut RewardsConfig, vault_balance: u64, until: i64) {
+        if until <= self.last_accrued_at {
+            return;
+        }
+        if self.total_shares > 0 {
+            let elapsed = (until - self.last_accrued_at) as u64;
+            let available = vault_balance.saturating_sub(rewards.total_owed);
+            let emitted = rewards.emission_rate.saturating_mul(elapsed).min(available);
+            self.acc_reward_per_share = self.acc_reward_per_share.saturating_add(
+                emitted as u128 * INCENTIVE_ACC_SCALE / self.total_shares as u128,
+            );
+            rewards.total_owed = rewards.total_owed.saturating_add(emitted);
+        }
+        self.last_accrued_at = until;
+    }
+}
+
+/// One wallet's stake in a market's liquidity mining
+#[account]
+pub struct IncentivePosition {
+    pub owner: Pubkey,
+    pub market_id: u64,
+    pub shares: u64,
+    pub reward_debt: u128, // accumulator value already credited
+    pub unclaimed: u64,
+    pub bump: u8,
+}
+
+impl IncentivePosition {
+    pub const INIT_SPACE: usize =
+        32 +     // owner
+        8 +      // market_id
+        8 +      // shares
+        16 +     // reward_debt
+        8 +      // unclaimed
+        1;       // bump
+
+    /// Credit rewards earned since the last settlement
+    pub fn settle(&mut self, acc_reward_per_share: u128) {
+        let earned = (self.shares as u128 * acc_reward_per_share / INCENTIVE_ACC_SCALE)
+            .saturating_sub(self.reward_debt);
+        self.unclaimed = self.unclaimed.saturating_add(earned as u64);
+        self.reset_debt(acc_reward_per_share);
+    }
+
+    pub fn reset_debt(&mut self, acc_reward_per_share: u128) {
+        self.reward_debt = self.shares as u128 * acc_reward_per_share / INCENTIVE_ACC_SCALE;
+    }
+}
+
+/// Settings for community settlement of disputed outcomes
+#[account]
+pub struct DisputeConfig {
+    pub governance_mint: Pubkey, // tokens that carry voting weight
+    pub arbiter: Pubkey,         // settles disputes that miss quorum
+    pub dispute_window_secs: i64, // after resolution
+    pub voting_period_secs: i64,
+    pub quorum: u64, // minimum tokens voted for the vote to count
+    pub dispute_bond: u64, // lamports, returned if the outcome is overturned
+    pub bump: u8,
+}
+
+impl DisputeConfig {
+    pub const INIT_SPACE: usize =
+        32 +     // governance_mint
+        32 +     // arbiter
+        8 +      // dispute_window_secs
+        8 +      // voting_period_secs
+        8 +      // quorum
+        8 +      // dispute_bond
+        1;       // bump
+}
+
+/// Admin-set dispute parameters; the governance mint is passed as an account
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct DisputeParams {
+    pub arbiter: Pubkey,
+    pub dispute_window_secs: i64,
+    pub voting_period_secs: i64,
+    pub quorum: u64,
+    pub dispute_bond: u64,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeStatus {
+    Voting,
+    AwaitingArbiter,
+    Settled,
+}
+
+/// A challenge to a market's outcome and its running stake-weighted tally
+#[account]
+pub struct Dispute {
+    pub market_id: u64,
+    pub raiser: Pubkey,
+    pub original_outcome: bool,
+    pub bond: u64,
+    pub voting_ends_at: i64,
+    pub yes_votes: u64,
+    pub no_votes: u64,
+    pub status: DisputeStatus,
+    pub bump: u8,
+    pub vault_bump: u8,
+}
+
+impl Dispute {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        32 +     // raiser
+        1 +      // original_outcome
+        8 +      // bond
+        8 +      // voting_ends_at
+        8 +      // yes_votes
+        8 +      // no_votes
+        1 +      // status
+        1 +      // bump
+        1;       // vault_bump
+}
+
+/// One holder's vote; its tokens sit in the dispute vault until withdrawn
+#[account]
+pub struct DisputeVote {
+    pub voter: Pubkey,
+    pub outcome: bool,
+    pub amount: u64,
+    pub bump: u8,
+}
+
+impl DisputeVote {
+    pub const INIT_SPACE: usize =
+        32 +     // voter
+        1 +      // outcome
+        8 +      // amount
+        1;       // bump
+}
+
+/// A named group of related markets, e.g. one per game of a season
+#[account]
+pub struct MarketSeries {
+    pub creator: Pubkey,
+    pub name: String,
+    pub member_count: u32,
+    pub total_volume: u64, // across all member markets
+    pub bump: u8,
+}
+
+impl MarketSeries {
+    pub const INIT_SPACE: usize =
+        32 +          // creator
+        (4 + 32) +    // name
+        4 +           // member_count
+        8 +           // total_volume
+        1;            // bump
+}
+
+/// Market layout as originally deployed, before accounts were versioned
+/// Only read by migrate_market
+#[derive(AnchorDeserialize, InitSpace)]
+pub struct LegacyMarket {
+    pub market_id: u64,
+    #[max_len(256)]
+    pub question: String,
+    pub creator: Pubkey,
+    pub created_at: i64,
+    pub resolution_time: i64,
+    pub yes_pool: u64,
+    pub no_pool: u64,
+    pub total_liquidity: u64,
+    pub resolved: bool,
+    pub outcome: Option<bool>,
+    pub yes_token_vault: Pubkey,
+    pub no_token_vault: Pubkey,
+    pub fee_collected: u64,
+}
+
+// The version 1 layout is frozen; migrate_market reads accounts of this size
+const _: () = assert!(LegacyMarket::INIT_SPACE == 415);
+
+// Clients hard-code the filter offsets; moving them needs a new version
+const _: () = assert!(MARKET_QUESTION_OFFSET == 76);
+
+/// A hidden bet awaiting reveal; holds the escrowed lamports
+#[account]
+pub struct BetCommit {
+    pub market_id: u64,
+    pub bettor: Pubkey,
+    pub commitment: [u8; 32], // keccak(side || amount || salt)
+    pub escrow: u64,
+    pub committed_at: i64,
+    pub bump: u8,
+}
+
+impl BetCommit {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        32 +     // bettor
+        32 +     // commitment
+        8 +      // escrow
+        8 +      // committed_at
+        1;       // bump
+}
+
+/// A resting order to bet once a side's probability reaches a limit
+/// Holds the escrowed amount plus the rent its fill will need
+#[account]
+pub struct LimitEntry {
+    pub market_id: u64,
+    pub owner: Pubkey,
+    pub side: bool, // true = YES, false = NO
+    pub limit_probability_bps: u16,
+    pub amount: u64, // bet plus keeper tip, excluding the rent deposit
+    pub placed_at: i64,
+    pub bump: u8,
+}
+
+impl LimitEntry {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        32 +     // owner
+        1 +      // side
+        2 +      // limit_probability_bps
+        8 +      // amount
+        8 +      // placed_at
+        1;       // bump
+}
+
+/// A bid in a market's open auction, escrowed in the market vault until
+/// the auction settles
+#[account]
+pub struct AuctionBid {
+    pub market_id: u64,
+    pub bidder: Pubkey,
+    pub side: bool, // true = YES, false = NO
+    pub amount: u64,
+    pub placed_at: i64,
+    pub bump: u8,
+}
+
+impl AuctionBid {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        32 +     // bidder
+        1 +      // side
+        8 +      // amount
+        8 +      // placed_at
+        1;       // bump
+}
+
+/// A bot key's right to bet from an escrow its owner funded, seeded by
+/// (owner, delegate). The escrow is the lamports above rent
+#[account]
+pub struct TradingDelegate {
+    pub owner: Pubkey, // holds every position the delegate opens
+    pub delegate: Pubkey,
+    pub max_bet: u64,
+    pub budget: u64, // total the delegate may stake
+    pub spent: u64,
+    pub expires_at: i64,
+    pub bump: u8,
+}
+
+impl TradingDelegate {
+    pub const INIT_SPACE: usize =
+        32 +     // owner
+        32 +     // delegate
+        8 +      // max_bet
+        8 +      // budget
+        8 +      // spent
+        8 +      // expires_at
+        1;       // bump
+}
+
+/// A user's exclusion of themselves from betting, seeded by the user.
+/// Every instruction that stakes new funds reads it; it can be extended
+/// while active but only closed once expired
+#[account]
+pub struct SelfExclusion {
+    pub user: Pubkey,
+    pub until: i64, // betting reopens at this time
+    pub created_at: i64, // start of the current exclusion, kept across extensions
+    pub bump: u8,
+}
+
+impl SelfExclusion {
+    pub const INIT_SPACE: usize =
+        32 +     // user
+        8 +      // until
+        8 +      // created_at
+        1;       // bump
+
+    pub fn active(&self, now: i64) -> bool {
+        now < self.until
+    }
+}
+
+/// Frontier of a compressed market's receipt tree: the last left-hand node
+/// filled at each level, which is all an append needs to compute the root
+#[account]
+pub struct ReceiptTree {
+    pub market: Pubkey,
+    pub frontier: [[u8; 32]; RECEIPT_TREE_DEPTH],
+    pub bump: u8,
+}
+
+impl ReceiptTree {
+    pub const INIT_SPACE: usize =
+        32 +     // market
+        32 * RECEIPT_TREE_DEPTH + // frontier
+        1;       // bump
+
+    /// Adds `leaf` at `index`, the tree's leaf count, and returns the new root
+    pub fn append(&mut self, leaf: [u8; 32], index: u64) -> [u8; 32] {
+        let (mut node, mut zero) = (leaf, [0; 32]);
+        for (level, filled) in self.frontier.iter_mut().enumerate() {
+            node = if (index >> level) & 1 == 0 {
+                *filled = node;
+                receipt_node(&node, &zero)
+            } else {
+                receipt_node(filled, &node)
+            };
+            zero = receipt_node(&zero, &zero);
+        }
+        node
+    }
+}
+
+/// One page of a compressed market's claimed-receipt bitmap. Bit i stands
+/// for receipt page * RECEIPT_CLAIMS_PAGE_BYTES * 8 + i
+#[account]
+pub struct ReceiptClaims {
+    pub market_id: u64,
+    pub page: u32,
+    pub bits: [u8; RECEIPT_CLAIMS_PAGE_BYTES],
+    pub bump: u8,
+}
+
+impl ReceiptClaims {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        4 +      // page
+        RECEIPT_CLAIMS_PAGE_BYTES + // bits
+        1;       // bump
+
+    /// Sets receipt `index`'s bit; false if it was already set
+    pub fn mark(&mut self, index: u64) -> bool {
+        let bit = (index % (RECEIPT_CLAIMS_PAGE_BYTES as u64 * 8)) as usize;
+        let mask = 1u8 << (bit % 8);
+        let fresh = self.bits[bit / 8] & mask == 0;
+        self.bits[bit / 8] |= mask;
+        fresh
+    }
+}
+
+/// A flag kept in Prediction's reserve. Predictions created before the
+/// reserve end where it would start, so on those it reads as unset
+#[derive(AnchorSerialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReserveFlag(pub bool);
+
+impl Space for ReserveFlag {
+    const INIT_SPACE: usize = 1;
+}
+
+impl AnchorDeserialize for ReserveFlag {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte)? == 0 {
+            return Ok(Self(false));
+        }
+        match byte[0] {
+            0 => Ok(Self(false)),
+            1 => Ok(Self(true)),
+            _ => Err(std::io::ErrorKind::InvalidData.into()),
+        }
+    }
+}
+
+/// A u64 kept in Prediction's reserve, reading as 0 on predictions
+/// created before the reserve
+#[derive(AnchorSerialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ReserveU64(pub u64);
+
+impl Space for ReserveU64 {
+    const INIT_SPACE: usize = 8;
+}
+
+impl AnchorDeserialize for ReserveU64 {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut bytes = [0u8; 8];
+        let mut filled = 0;
+        while filled < 8 {
+            match reader.read(&mut bytes[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        Ok(Self(u64::from_le_bytes(bytes)))
+    }
+}
+
+/// `N` bytes an account holds for fields added by later versions, written
+/// as zeros. Accounts created before the reserve, or before it grew, lack
+/// some or all of them, so reading and writing stop quietly at the end of
+/// the account
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Reserved<const N: usize>;
+
+impl<const N: usize> AnchorSerialize for Reserved<N> {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut zeros = &[0u8; N][..];
+        while !zeros.is_empty() {
+            match writer.write(zeros)? {
+                0 => break,
+                written => zeros = &zeros[written..],
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> AnchorDeserialize for Reserved<N> {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut bytes = [0u8; N];
+        let mut filled = 0;
+        while filled < N {
+            match reader.read(&mut bytes[filled..])? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+        Ok(Self)
+    }
+}
+
+impl<const N: usize> Space for Reserved<N> {
+    const INIT_SPACE: usize = N;
+}
+
+/// Describes the reserve to clients as the `[u8; N]` it is written as
+#[cfg(feature = "idl-build")]
+impl<const N: usize> IdlBuild for Reserved<N> {
+    fn create_type() -> Option<anchor_lang::idl::types::IdlTypeDef> {
+        use anchor_lang::idl::types::*;
+        Some(IdlTypeDef {
+            name: Self::get_full_path(),
+            docs: vec![],
+            serialization: IdlSerialization::Borsh,
+            repr: None,
+            generics: vec![IdlTypeDefGeneric::Const { name: "N".into(), ty: "usize".into() }],
+            ty: IdlTypeDefTy::Struct {
+                fields: Some(IdlDefinedFields::Tuple(vec![IdlType::Array(
+                    Box::new(IdlType::U8),
+                    IdlArrayLen::Generic("N".into()),
+                )])),
+            },
+        })
+    }
+
+    fn get_full_path() -> String {
+        format!("{}::Reserved", module_path!())
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Prediction {
+    pub version: u8,
+    pub market_id: u64,
+    pub predictor: Pubkey,
+    pub prediction_type: bool, // true = YES, false = NO
+    pub amount_deposited: u64,
+    pub tokens_received: u64,
+    pub created_at: i64,
+    pub claimed: bool,
+    pub referrer: Pubkey, // default pubkey when the bet had no referrer
+    pub bump: u8,
+    // Entry snapshot, from version 2; zero on older accounts
+    pub slot: u64,
+    pub yes_pool_at_entry: u64, // pools the bet was priced against
+    pub no_pool_at_entry: u64,
+    pub entry_price_bps: u64,   // lamports per outcome token, in bps
+    pub tokens_claimed: u64,    // paid out by partial claims so far
+    pub position_index: u32,    // 0 for the wallet's first position in the market
+    pub entry_probability_bps: u16, // implied probability of the chosen side before the bet
+    pub trade_sequence: u64, // market.sequence right after this bet
+    pub cancel_refund: u64,  // lamports a cooling-off cancel returns; 0 if it can't be cancelled
+    pub stop_loss_bps: u16,  // exit when the side's implied probability falls below; 0 = none
+    #[max_len(MAX_NOTE_LEN)]
+    pub note: String,        // bettor's own reference, at most MAX_NOTE_LEN bytes; empty = none
+    pub rebate_claimed: bool, // loser rebate paid out by claim_rebate
+    pub rent_payer: Pubkey, // paid the account's rent and gets it back; default = the predictor
+    pub is_house: ReserveFlag, // placed by a house account; kept out of profile stats
+    pub generation: ReserveU64, // market.generation when opened; in the PDA seeds
+    pub _reserved: Reserved<64>, // for fields added by later versions
+}
+
+impl Prediction {
+    /// Last PDA seed of a position: empty for position 0, so a wallet's
+    /// first position keeps the original [prediction, market, predictor]
+    /// address, and the index bytes for positions split off it
+    pub fn position_seed(&self) -> Vec<u8> {
+        match self.position_index {
+            0 => Vec::new(),
+            index => index.to_le_bytes().to_vec(),
+        }
+    }
+
+    /// PDA seed after position_seed: the generation of the market the
+    /// position was opened on, empty for 0
+    pub fn generation_seed(&self) -> Vec<u8> {
+        generation_seed(self.generation.0)
+    }
+
+    /// Where the account's rent goes when it is closed
+    pub fn rent_recipient(&self) -> Pubkey {
+        if self.rent_payer == Pubkey::default() {
+            self.predictor
+        } else {
+            self.rent_payer
+        }
+    }
+}
+
+// Predictions created before the reserve hold 265 bytes, and those created
+// while it was 16 bytes hold 281: is_house and generation took 9 of them.
+// Fields after the note move with its length, so one with a long note can't
+// write the reserve's fields back until migrate_prediction has grown it.
+// New fields take their bytes from _reserved, keeping the derived size
+// deployed
+const _: () = assert!(Prediction::INIT_SPACE == 265 + 9 + 64);
+
+/// A wallet's positions in one market
+#[account]
+pub struct UserMarketIndex {
+    pub user: Pubkey,
+    pub market_id: u64,
+    pub next_position_index: u32, // positions ever opened
+    pub open_positions: u32,      // opened and not yet closed
+    pub bump: u8,
+}
+
+impl UserMarketIndex {
+    pub const INIT_SPACE: usize =
+        32 +     // user
+        8 +      // market_id
+        4 +      // next_position_index
+        4 +      // open_positions
+        1;       // bump
+}
+
+/// One page of the markets a wallet has bet on, in first-bet order
+#[account]
+pub struct UserIndexPage {
+    pub user: Pubkey,
+    pub page_index: u32,
+    pub count: u32,
+    pub market_ids: [u64; USER_INDEX_PAGE_SIZE],
+    pub bump: u8,
+}
+
+impl UserIndexPage {
+    pub const INIT_SPACE: usize =
+        32 +                            // user
+        4 +                             // page_index
+        4 +                             // count
+        8 * USER_INDEX_PAGE_SIZE +      // market_ids
+        1;                              // bump
+}
+
+/// Lifetime betting record for a wallet, created on its first bet
+#[account]
+pub struct UserProfile {
+    pub user: Pubkey,
+    pub bets_placed: u32,
+    pub markets_won: u32,
+    pub markets_lost: u32,
+    pub total_wagered: u64,
+    pub total_won: u64,
+    pub bump: u8,
+}
+
+impl UserProfile {
+    pub const INIT_SPACE: usize =
+        32 +     // user
+        4 +      // bets_placed
+        4 +      // markets_won
+        4 +      // markets_lost
+        8 +      // total_wagered
+        8 +      // total_won
+        1;       // bump
+
+    /// Page of the user's market index that their next market lands in
+    pub fn next_index_page(&self) -> u32 {
+        self.bets_placed / USER_INDEX_PAGE_SIZE as u32
+    }
+
+    /// Fills in identity fields on a freshly created (zeroed) profile
+    pub fn init_if_new(&mut self, user: Pubkey, bump: u8) {
+        if self.user == Pubkey::default() {
+            self.user = user;
+            self.bump = bump;
+        }
+    }
+
+    pub fn update_event(&self) -> ProfileUpdated {
+        ProfileUpdated {
+            user: self.user,
+            bets_placed: self.bets_placed,
+            markets_won: self.markets_won,
+            markets_lost: self.markets_lost,
+            total_wagered: self.total_wagered,
+            total_won: self.total_won,
+        }
+    }
+}
+
+/// Deposits open on a creator's unresolved markets and the markets it
+/// created today, seeded by creator
+#[account]
+pub struct CreatorStats {
+    pub creator: Pubkey,
+    pub open_liability: u64,
+    pub bump: u8,
+    pub day_bucket: i64, // unix day (UTC) markets_created_today counts
+    pub markets_created_today: u32,
+}
+
+impl CreatorStats {
+    pub const INIT_SPACE: usize =
+        32 +     // creator
+        8 +      // open_liability
+        1 +      // bump
+        8 +      // day_bucket
+        4;       // markets_created_today
+
+    /// Counts a new market against `limit` per UTC day (0 = none), starting
+    /// the count over once the day has rolled over
+    pub fn count_market(&mut self, now: i64, limit: u32) -> Result<()> {
+        let day = now.div_euclid(CREATION_DAY_SECS);
+        if day != self.day_bucket {
+            self.day_bucket = day;
+            self.markets_created_today = 0;
+        }
+        require!(
+            limit == 0 || self.markets_created_today < limit,
+            MarketError::CreationRateLimited
+        );
+        self.markets_created_today += 1;
+        Ok(())
+    }
+
+    pub fn init_if_new(&mut self, creator: Pubkey, bump: u8) {
+        if self.creator == Pubkey::default() {
+            self.creator = creator;
+            self.bump = bump;
+        }
+    }
+}
+
+/// Per-epoch betting stats for a wallet, seeded by (user, epoch)
+#[account]
+pub struct UserEpochStats {
+    pub user: Pubkey,
+    pub epoch: u64,
+    pub bets_placed: u32,
+    pub markets_won: u32,
+    pub total_wagered: u64,
+    pub total_won: u64,
+    pub bump: u8,
+}
+
+impl UserEpochStats {
+    pub const INIT_SPACE: usize =
+        32 +     // user
+        8 +      // epoch
+        4 +      // bets_placed
+        4 +      // markets_won
+        8 +      // total_wagered
+        8 +      // total_won
+        1;       // bump
+
+    pub fn init_if_new(&mut self, user: Pubkey, epoch: u64, bump: u8) {
+        if self.user == Pubkey::default() {
+            self.user = user;
+            self.epoch = epoch;
+            self.bump = bump;
+        }
+    }
+}
+
+/// A closed leaderboard epoch and its best-scoring wallet (by winnings)
+#[account]
+pub struct LeaderboardEpoch {
+    pub epoch: u64,
+    pub started_at: i64,
+    pub ended_at: i64,
+    pub leader: Pubkey,
+    pub leader_score: u64,
+    pub bump: u8,
+}
+
+impl LeaderboardEpoch {
+    pub const INIT_SPACE: usize =
+        8 +      // epoch
+        8 +      // started_at
+        8 +      // ended_at
+        32 +     // leader
+        8 +      // leader_score
+        1;       // bump
+}
+
+/// A wallet that earns a share of the trading fee on bets it refers
+#[account]
+pub struct Referrer {
+    pub owner: Pubkey,
+    pub accrued_fees: u64, // lamports held in this account awaiting claim
+    pub total_referred_volume: u64,
+    pub bump: u8,
+}
+
+impl Referrer {
+    pub const INIT_SPACE: usize =
+        32 +     // owner
+        8 +      // accrued_fees
+        8 +      // total_referred_volume
+        1;       // bump
+}
+
+/// One leg of a parlay: a market and the outcome the parlay needs from it
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct ParlayLeg {
+    pub market_id: u64,
+    pub outcome: bool,
+}
+
+#[account]
+pub struct Parlay {
+    pub owner: Pubkey,
+    pub parlay_id: u64,
+    pub stake: u64, // lamports escrowed in this account
+    pub max_payout: u64,
+    pub leg_count: u8,
+    pub legs: [ParlayLeg; MAX_PARLAY_LEGS],
+    pub created_at: i64,
+    pub bump: u8,
+}
+
+impl Parlay {
+    pub const INIT_SPACE: usize =
+        32 +                        // owner
+        8 +                         // parlay_id
+        8 +                         // stake
+        8 +                         // max_payout
+        1 +                         // leg_count
+        (8 + 1) * MAX_PARLAY_LEGS + // legs
+        8 +                         // created_at
+        1;                          // bump
+}
+
+/// Lamports backing parlay winnings beyond the stakes
+#[account]
+pub struct ParlayReserve {
+    pub committed: u64, // worst-case winnings owed to open parlays
+    pub bump: u8,
+}
+
+impl ParlayReserve {
+    pub const INIT_SPACE: usize =
+        8 +      // committed
+        1;       // bump
+
+    /// Lamports available to back new parlays
+    pub fn free_liquidity(&self, lamports: u64, rent: u64) -> u64 {
+        lamports.saturating_sub(rent).saturating_sub(self.committed)
+    }
+}
+
+/// A resting order; `in_use` and `side` are u8 flags so the layout stays Pod
+#[zero_copy]
+pub struct Order {
+    pub owner: Pubkey,
+    pub amount: u64, // lamports escrowed in the order book account
+    pub posted_at: i64,
+    pub price_bps: u16,
+    pub next_free: u16, // next slot in the free list, while unused
+    pub side: u8,       // 1 = YES, 0 = NO
+    pub in_use: u8,
+    pub _padding: [u8; 2],
+}
+
+/// Fixed-capacity order book for a market, loaded zero-copy so cost
+/// doesn't grow with the number of resting orders. Free slots form a
+/// singly linked list threaded through `next_free`
+#[account(zero_copy)]
+pub struct OrderBook {
+    pub market_id: u64,
+    pub free_head: u16,
+    pub count: u16,
+    pub bump: u8,
+    pub _padding: [u8; 3],
+    pub orders: [Order; ORDER_BOOK_CAPACITY],
+}
+
+impl OrderBook {
+    /// Links every slot into the free list
+    pub fn init(&mut self, market_id: u64, bump: u8) {
+        self.market_id = market_id;
+        self.bump = bump;
+        self.count = 0;
+        self.free_head = 0;
+        for (i, order) in self.orders.iter_mut().enumerate() {
+            order.next_free = if i + 1 < ORDER_BOOK_CAPACITY {
+                (i + 1) as u16
+            } else {
+                ORDER_SLOT_NONE
+            };
+        }
+    }
+
+    /// Stores an order in the first free slot and returns its index
+    pub fn insert(&mut self, order: Order) -> Result<u16> {
+        let slot = self.free_head;
+        require!(slot != ORDER_SLOT_NONE, MarketError::OrderBookFull);
+        let entry = &mut self.orders[slot as usize];
+        self.free_head = entry.next_free;
+        *entry = order;
+        entry.next_free = ORDER_SLOT_NONE;
+        self.count += 1;
+        Ok(slot)
+    }
+
+    /// Takes `amount` off the order in `slot`, freeing the slot once the
+    /// order is used up, and returns the order as it was
+    pub fn take(&mut self, slot: u16, amount: u64) -> Result<Order> {
+        let entry = self
+            .orders
+            .get_mut(slot as usize)
+            .filter(|order| order.in_use == 1)
+            .ok_or(MarketError::InvalidOrderSlot)?;
+        require!(amount <= entry.amount, MarketError::InvalidAmount);
+
+        let order = *entry;
+        entry.amount -= amount;
+        if entry.amount == 0 {
+            let owner = entry.owner;
+            self.remove(slot, &owner)?;
+        }
+        Ok(order)
+    }
+
+    /// Clears an owner's order and pushes its slot onto the free list
+    pub fn remove(&mut self, slot: u16, owner: &Pubkey) -> Result<Order> {
+        let entry = self
+            .orders
+            .get_mut(slot as usize)
+            .filter(|order| order.in_use == 1)
+            .ok_or(MarketError::InvalidOrderSlot)?;
+        require_keys_eq!(entry.owner, *owner, MarketError::Unauthorized);
+
+        let order = *entry;
+        entry.in_use = 0;
+        entry.amount = 0;
+        entry.next_free = self.free_head;
+        self.free_head = slot;
+        self.count -= 1;
+        Ok(order)
+    }
+}
+
+/// A market maker approved by the config admin. Fills of its resting
+/// orders pay rebate_bps of their taker fee into this account until it
+/// claims them with claim_mm_rebates
+#[account]
+pub struct MarketMakerRegistration {
+    pub maker: Pubkey,
+    pub approved_at: i64,
+    pub rebate_bps: u16, // share of each fill's taker fee; 0 while suspended
+    pub rebates_accrued: u64, // lifetime, held here until claimed
+    pub rebates_claimed: u64,
+    pub bump: u8,
+}
+
+impl MarketMakerRegistration {
+    pub const INIT_SPACE: usize =
+        32 +     // maker
+        8 +      // approved_at
+        2 +      // rebate_bps
+        8 +      // rebates_accrued
+        8 +      // rebates_claimed
+        1;       // bump
+
+    /// Rebates accrued but not yet claimed, held above the account's rent
+    pub fn unclaimed(&self) -> u64 {
+        self.rebates_accrued - self.rebates_claimed
+    }
+}
+
+/// A resting order matched by fill_order: both sides' stakes, held in
+/// this account until settle_fill pays them out
+#[account]
+pub struct Fill {
+    pub market_id: u64,
+    pub maker: Pubkey,
+    pub taker: Pubkey,
+    pub maker_side: bool, // the side of the maker's order; the taker holds the other
+    pub maker_stake: u64,
+    pub taker_stake: u64,
+    pub price_bps: u16,
+    pub filled_at: i64,
+    pub bump: u8,
+}
+
+impl Fill {
+    pub const INIT_SPACE: usize =
+        8 +      // market_id
+        32 +     // maker
+        32 +     // taker
+        1 +      // maker_side
+        8 +      // maker_stake
+        8 +      // taker_stake
+        2 +      // price_bps
+        8 +      // filled_at
+        1;       // bump
+}
+
+// ==================== EVENTS ====================
+
+#[event]
+pub struct ConfigUpdateProposed {
+    pub admin: Pubkey,
+    pub proposed_at: i64,
+    pub executable_at: i64,
+}
+
+#[event]
+pub struct ConfigUpdateExecuted {
+    pub admin: Pubkey,
+    pub proposed_at: i64,
+}
+
+#[event]
+pub struct ConfigUpdateCancelled {
+    pub admin: Pubkey,
+    pub proposed_at: i64,
+}
+
+#[event]
+pub struct ProtocolPaused {
+    pub admin: Pubkey,
+}
+
+#[event]
+pub struct AdminMultisigCreated {
+    pub multisig: Pubkey,
+    pub signer: Pubkey, // the new config admin
+    pub owners: Vec<Pubkey>,
+    pub threshold: u8,
+}
+
+#[event]
+pub struct AdminTxCreated {
+    pub index: u64,
+    pub proposer: Pubkey,
+}
+
+#[event]
+pub struct AdminTxApproved {
+    pub index: u64,
+    pub owner: Pubkey,
+    pub approvals: u8, // count so far
+}
+
+#[event]
+pub struct AdminTxExecuted {
+    pub index: u64,
+    pub admin: Pubkey, // config admin afterwards
+}
+
+#[event]
+pub struct CreatorApprovalChanged {
+    pub creator: Pubkey,
+    pub approved: bool, // false when revoked
+}
+
+#[event]
+pub struct MarketMakerApprovalChanged {
+    pub maker: Pubkey,
+    pub rebate_bps: u16,
+    pub approved: bool, // false when revoked
+}
+
+#[event]
+pub struct MmRebatesClaimed {
+    pub maker: Pubkey,
+    pub amount: u64,
+    pub total_claimed: u64,
+}
+
+#[event]
+pub struct ConfigUpdated {
+    pub admin: Pubkey,
+    pub min_market_duration: i64,
+    pub max_market_duration: i64,
+}
+
+#[event]
+pub struct MarketCreated {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub creator: Pubkey,
+    pub question: String,
+    pub resolution_time: i64,
+    pub time_decay: bool,
+    pub time_decay_floor_bps: u16,
+    pub series: Option<Pubkey>,
+    pub initial_yes_probability_bps: u16, // as seeded; may round off the requested bps
+    pub creation_fee: u64, // paid to the treasury; 0 when none is set or it was waived
+    pub mode: MarketMode,
+    pub max_bet: u64, // 0 = no limit
+    pub max_bet_ramp_secs: i64, // the cap at a time is Market::max_bet_at
+    pub max_bet_ramp_start_bps: u16,
+    pub parent: Option<ParentCondition>,
+    pub collateral_decimals: u8, // of every amount in this market's events
+    pub push_policy: PushPolicy,
+    pub max_participants: u32, // 0 = no limit
+    pub rounding_policy: RoundingPolicy,
+}
+
+#[event]
+pub struct MarketCloned {
+    pub source_market_id: u64,
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub creator: Pubkey, // the caller, not the source's creator
+}
+
+#[event]
+pub struct TemplateCreated {
+    pub template: Pubkey,
+    pub creator: Pubkey,
+    pub name: String,
+}
+
+#[event]
+pub struct SeriesCreated {
+    pub series: Pubkey,
+    pub creator: Pubkey,
+    pub name: String,
+}
+
+#[event]
+pub struct SeriesMembershipChanged {
+    pub series: Pubkey,
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub added: bool,
+    pub member_count: u32,
+}
+
+#[event]
+pub struct MarketTransferProposed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub owner: Pubkey,
+    pub pending_owner: Pubkey,
+}
+
+#[event]
+pub struct MarketTransferAccepted {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub previous_owner: Pubkey,
+    pub new_owner: Pubkey,
+}
+
+#[event]
+pub struct MarketTransferCancelled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub owner: Pubkey,
+    pub pending_owner: Pubkey,
+}
+
+#[event]
+pub struct DescriptionSet {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub length: u32,
+    pub account_size: u32,
+}
+
+#[event]
+pub struct MarketFeeSet {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub fee_bps: u16, // effective fee for new bets
+}
+
+#[event]
+pub struct PushPolicySet {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub push_policy: PushPolicy,
+}
+
+#[event]
+pub struct RebateClaimed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub predictor: Pubkey,
+    pub position_index: u32,
+    pub amount: u64,
+    pub rebate_outstanding: u64, // left in the pot for other losers
+}
+
+#[event]
+pub struct ResolversSet {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub resolvers: [Pubkey; 3],
+    pub delays: [i64; 3], // seconds after resolution_time each tier opens
+    pub active_at: i64, // later than now when reassigning after a revocation
+}
+
+#[event]
+pub struct CompressedPredictionAppended {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub receipt: CompressedReceipt,
+    pub leaf: [u8; 32],
+    pub root: [u8; 32], // the market's receipt root after this append
+    pub yes_pool: u64,
+    pub no_pool: u64,
+}
+
+#[event]
+pub struct CompressedReceiptClaimed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub index: u64,
+    pub owner: Pubkey,
+    pub amount: u64, // net reward, or the deposit if refunded
+    pub refunded: bool,
+}
+
+#[event]
+pub struct ParentConditionMet {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub parent: Pubkey,
+    pub outcome: bool,
+}
+
+#[event]
+pub struct ResolverRevoked {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub resolver: Pubkey,
+    pub revoked_by: Pubkey, // the creator or the config admin
+    pub commitment_voided: bool,
+}
+
+#[event]
+pub struct OddsUpdated {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub yes_odds_bps: u32, // for bets placed from now on
+    pub no_odds_bps: u32,
+}
+
+#[event]
+pub struct BankrollWithdrawn {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub recipient: Pubkey,
+    pub amount: u64, // bankroll left plus losing stakes
+}
+
+/// Why a bet failed, for clients to show the limit it hit. Written to the
+/// program log with emit! rather than emit_cpi!: the transaction fails, and
+/// its logs are all that is kept
+#[event]
+pub struct BetRejected {
+    pub market_id: u64,
+    pub error_code: u32, // MarketError number the transaction fails with
+    pub limit: u64, // lamports, bps or unix time, per error_code
+    pub provided: u64, // the bet's value against limit
+}
+
+/// What a dry run of place_prediction or claim_reward would have done.
+/// Logged with emit!, like BetRejected, since the dry run fails
+#[event]
+pub struct DryRunResult {
+    pub market_id: u64,
+    pub user: Pubkey, // predictor or claimer
+    pub side: bool,
+    pub paid: u64, // lamports the user would pay in; 0 for claims
+    pub received: u64, // lamports the user would receive; 0 for bets
+    pub tokens: u64, // outcome tokens minted, or redeemed by a claim
+    pub fee: u64, // trading fee, or winnings fee for claims
+    pub yes_pool: u64, // pools after the instruction
+    pub no_pool: u64,
+}
+
+#[event]
+pub struct PredictionPlaced {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub predictor: Pubkey,
+    pub prediction_type: bool,
+    pub amount: u64,
+    pub normalized_amount: u64, // amount scaled to NORMALIZED_DECIMALS
+    pub tokens_received: u64,
+    pub weight_bps: u64, // payout weight applied to tokens_received
+    pub fee_bps: u16,    // effective fee rate charged, before any NFT discount
+    pub rebate: u64,     // fee refunded for narrowing the pool gap
+    pub yes_pool: u64,  // pool state after this trade
+    pub no_pool: u64,
+    pub implied_yes_probability_bps: u16,
+    pub sequence: u64, // per-market trade sequence, gap-free
+    pub note: String,  // bettor's reference for the bet; empty for none
+}
+
+#[event]
+pub struct BetCommitted {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub bettor: Pubkey,
+    pub escrow: u64,
+    pub reveal_deadline: i64,
+}
+
+#[event]
+pub struct BetCommitRefunded {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub bettor: Pubkey,
+    pub refunded: u64,
+    pub penalty: u64,
+}
+
+#[event]
+pub struct MarketStats {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub total_volume: u64,
+    pub trade_count: u32,
+    pub unique_bettors: u32,
+}
+
+#[event]
+pub struct PriceSampled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub price: i64,
+    pub timestamp: i64,
+    pub tip: u64,
+}
+
+#[event]
+pub struct MarketResolved {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub outcome: MarketOutcome,
+    pub yes_pool: u64,
+    pub no_pool: u64,
+    pub implied_yes_probability_bps: u16,
+    pub sequence: u64, // sequence of the last trade before resolution
+    pub evidence: String, // resolver's justification; empty for oracle resolutions
+    pub resolver_tier: Option<u8>, // tier that resolved through resolve_market
+    pub rebate_pot: u64, // fees set aside for losing bettors
+    pub push: bool, // a push, mapped to this outcome by the market's push_policy
+}
+
+#[event]
+pub struct AttestationRecordsPurged {
+    pub admin: Pubkey,
+    pub records: u32,
+    pub max_age_days: u16,
+    pub lamports: u64, // rent refunded to the records' payers
+}
+
+#[event]
+pub struct PayoutParamsPublished {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub params: PayoutParams,
+}
+
+#[event]
+pub struct ResolutionAmended {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub previous_outcome: MarketOutcome,
+    pub new_outcome: MarketOutcome,
+    pub claims_open_at: i64,
+    pub evidence: String,
+}
+
+#[event]
+pub struct ResolutionCommitted {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub commitment: [u8; 32],
+    pub committed_at: i64,
+}
+
+#[event]
+pub struct OutcomeMetadataCreated {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub yes_metadata: Pubkey,
+    pub no_metadata: Pubkey,
+    pub uri: String,
+}
+
+#[event]
+pub struct MintsFinalized {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub yes_mint: Pubkey,
+    pub no_mint: Pubkey,
+}
+
+#[event]
+pub struct WsolVaultInitialized {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub wsol_vault: Pubkey,
+    pub amount: u64, // seed liquidity wrapped from the vault PDA
+}
+
+#[event]
+pub struct RewardClaimed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub claimer: Pubkey,
+    pub recipient: Pubkey, // the claimer unless another account was passed
+    pub reward: u64,       // paid out, net of the winnings fee
+    pub normalized_amount: u64, // reward scaled to NORMALIZED_DECIMALS
+    pub gross_reward: u64, // before the winnings fee
+    pub winnings_fee: u64, // kept from the profit as a market fee
+    pub entitled: u64,
+    pub payout_bps: u16, // share of the entitlement paid; below 10_000 on a haircut
+    pub pnl_lamports: i64, // net reward minus the deposit behind the claimed tokens
+    pub return_bps: i64,   // pnl_lamports as a share of that deposit
+}
+
+#[event]
+pub struct MarketStateSnapshot {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub from_seq: u64,
+    pub sequence: u64, // trades so far
+    pub yes_pool: u64,
+    pub no_pool: u64,
+    pub total_liquidity: u64,
+    pub yes_tokens: u64,
+    pub no_tokens: u64,
+    pub total_volume: u64,
+    pub fee_collected: u64,
+    pub total_claimed: u64,
+    pub trade_count: u32,
+    pub unique_bettors: u32,
+    pub claims_count: u32,
+    pub resolved: bool,
+    pub outcome: MarketOutcome,
+    pub disputed: bool,
+    pub history_complete: bool, // checkpoints reach back to from_seq
+    pub checkpoints: Vec<PoolCheckpoint>, // oldest first
+}
+
+#[event]
+pub struct EscrowedClaimReleased {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub prediction: Pubkey,
+    pub claimer: Pubkey,
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub clawed_back: bool, // the outcome was overturned and the claim undone
+}
+
+#[event]
+pub struct PredictionCancelled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub predictor: Pubkey,
+    pub refund: u64,
+    pub yes_pool: u64, // pools after the bet was taken back out
+    pub no_pool: u64,
+    pub sequence: u64,
+}
+
+#[event]
+pub struct EarlyExited {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub predictor: Pubkey,
+    pub refund: u64,
+    pub penalty: u64, // left in the pot
+    pub yes_pool: u64,
+    pub no_pool: u64,
+    pub sequence: u64,
+}
+
+#[event]
+pub struct LimitEntryPlaced {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub owner: Pubkey,
+    pub side: bool,
+    pub limit_probability_bps: u16,
+    pub amount: u64,
+}
+
+#[event]
+pub struct WinningsRolled {
+    pub source_market_id: u64,
+    pub target_market_id: u64,
+    pub market: Pubkey, // the target market
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub owner: Pubkey,
+    pub reward: u64,    // claimed from the source market
+    pub kept_back: u64, // sent to the wallet
+    pub amount: u64,    // bet on the target market
+    pub tokens_received: u64,
+}
+
+#[event]
+pub struct LimitEntryFilled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub owner: Pubkey,
+    pub keeper: Pubkey,
+    pub probability_bps: u16, // the side's implied probability when filled
+    pub amount: u64,          // bet placed, after the tip
+    pub tokens_received: u64,
+    pub tip: u64,
+}
+
+#[event]
+pub struct LimitEntryClosed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub owner: Pubkey,
+    pub refunded: u64, // escrow and rent returned to the owner
+    pub expired: bool, // false when cancelled by the owner
+}
+
+#[event]
+pub struct StopLossExecuted {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub predictor: Pubkey,
+    pub keeper: Pubkey,
+    pub probability_bps: u16, // the side's implied probability that triggered it
+    pub proceeds: u64,        // paid to the predictor, after the tip
+    pub tip: u64,
+}
+
+#[event]
+pub struct PositionClosed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub predictor: Pubkey,
+    pub position_index: u32,
+    pub amount_deposited: u64,
+    pub pnl_lamports: i64,
+    pub return_bps: i64,
+}
+
+#[event]
+pub struct ProfileUpdated {
+    pub user: Pubkey,
+    pub bets_placed: u32,
+    pub markets_won: u32,
+    pub markets_lost: u32,
+    pub total_wagered: u64,
+    pub total_won: u64,
+}
+
+#[event]
+pub struct EpochAdvanced {
+    pub closed_epoch: u64,
+    pub new_epoch: u64,
+    pub started_at: i64,
+}
+
+#[event]
+pub struct EpochLeaderUpdated {
+    pub epoch: u64,
+    pub leader: Pubkey,
+    pub score: u64,
+}
+
+#[event]
+pub struct ReferrerRegistered {
+    pub referrer: Pubkey,
+}
+
+#[event]
+pub struct ReferralAttributed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub predictor: Pubkey,
+    pub referrer: Pubkey,
+    pub amount: u64,
+    pub referral_fee: u64,
+}
+
+#[event]
+pub struct ReferralFeesClaimed {
+    pub referrer: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct InsuranceFunded {
+    pub donor: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct MarketCancelled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub admin: Pubkey,
+    pub reason: String,
+    pub refundable: u64, // deposits and seed owed back
+    pub trade_count: u32,
+}
+
+/// Why a market was voided at resolution
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VoidReason {
+    /// One side had no bets
+    NoCounterparty,
+    /// A conditional market's parent resolved against its condition
+    ParentOutcome,
+    /// The event ended in a push under a VoidOnPush policy
+    Push,
+}
+
+#[event]
+pub struct MarketVoided {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub reason: VoidReason,
+    pub refundable: u64, // deposits, seed and sponsorships owed back
+}
 
-    pub admin: Signer<'info>,
+#[event]
+pub struct MarketSponsored {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub sponsor: Pubkey,
+    pub sponsor_name: String,
+    pub amount: u64,
+    pub sponsored_amount: u64, // market total
 }
 
-// ==================== STATE ====================
+#[event]
+pub struct RefundClaimed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
 
-#[account]
-pub struct Market {
+#[event]
+pub struct ShortfallCovered {
     pub market_id: u64,
-    pub question: String,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub amount: u64,
+    pub reason: String,
+}
+
+#[event]
+pub struct VaultToppedUp {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub admin: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct DisputeRaised {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub raiser: Pubkey,
+    pub original_outcome: MarketOutcome,
+    pub voting_ends_at: i64,
+}
+
+#[event]
+pub struct DisputeVoteCast {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub voter: Pubkey,
+    pub outcome: bool,
+    pub amount: u64,
+}
+
+#[event]
+pub struct DisputeEscalated {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub yes_votes: u64,
+    pub no_votes: u64,
+}
+
+#[event]
+pub struct DisputeSettled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub outcome: MarketOutcome,
+    pub overturned: bool,
+    pub yes_votes: u64,
+    pub no_votes: u64,
+}
+
+#[event]
+pub struct FeesWithdrawn {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub admin: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct TreasuryInitialized {
+    pub treasury: Pubkey,
+}
+
+#[event]
+pub struct FeesCollected {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub amount: u64,
+}
+
+#[event]
+pub struct TreasuryFeesCollected {
+    pub markets: u32,
+    pub amount: u64,
+    pub total_collected: u64,
+    pub to_stakers: u64, // part of amount routed to the staking vault
+    pub to_buyback: u64, // part of amount escrowed for buyback-and-burn
+}
+
+#[event]
+pub struct TreasuryWithdrawalProposed {
+    pub amount: u64,
+    pub recipient: Pubkey,
+    pub executable_at: i64,
+}
+
+#[event]
+pub struct TreasuryWithdrawn {
+    pub recipient: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct TreasuryWithdrawalCancelled {
+    pub amount: u64,
+    pub recipient: Pubkey,
+}
+
+#[event]
+pub struct StakingConfigured {
+    pub mint: Pubkey,
+    pub fee_share_bps: u16,
+    pub unbonding_secs: i64,
+}
+
+#[event]
+pub struct Staked {
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub staked: u64, // the owner's stake after this one
+    pub total_staked: u64,
+}
+
+#[event]
+pub struct Unstaked {
+    pub owner: Pubkey,
+    pub amount: u64,
+    pub staked: u64,
+    pub unbonding: u64, // all of the owner's unbonding tokens
+    pub unbonding_ends_at: i64,
+}
+
+#[event]
+pub struct UnstakedWithdrawn {
+    pub owner: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct StakingRewardsClaimed {
+    pub owner: Pubkey,
+    pub amount: u64, // lamports
+}
+
+#[event]
+pub struct BuybackConfigured {
+    pub protocol_mint: Pubkey,
+    pub executor: Pubkey,
+    pub buyback_bps: u16,
+}
+
+#[event]
+pub struct BuybackExecuted {
+    pub executor: Pubkey,
+    pub sol_spent: u64,
+    pub tokens_received: u64, // delivered by the executor for this buyback
+    pub tokens_burned: u64, // everything in the escrow's token account
+}
+
+#[event]
+pub struct OrderPosted {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub slot: u16,
+    pub owner: Pubkey,
+    pub side: bool,
+    pub price_bps: u16,
+    pub amount: u64,
+}
+
+#[event]
+pub struct OrderCancelled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub slot: u16,
+    pub owner: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct OrderFilled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub slot: u16,
+    pub fill: Pubkey,
+    pub maker: Pubkey,
+    pub taker: Pubkey,
+    pub maker_side: bool,
+    pub price_bps: u16,
+    pub maker_stake: u64,
+    pub taker_stake: u64,
+    pub fee: u64, // paid by the taker, mm_rebate included
+    pub mm_rebate: u64, // credited to the maker's registration
+}
+
+#[event]
+pub struct FillSettled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub fill: Pubkey,
+    pub maker: Pubkey,
+    pub taker: Pubkey,
+    pub maker_payout: u64,
+    pub taker_payout: u64,
+}
+
+#[event]
+pub struct ParlayCreated {
+    pub owner: Pubkey,
+    pub parlay_id: u64,
+    pub legs: Vec<ParlayLeg>,
+    pub stake: u64,
+    pub max_payout: u64,
+}
+
+#[event]
+pub struct ParlaySettled {
+    pub owner: Pubkey,
+    pub parlay_id: u64,
+    pub won: bool,
+    pub payout: u64,
+}
+
+#[event]
+pub struct InvariantViolation {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub vault_balance: u64,
+    pub liabilities: u64,
+}
+
+#[event]
+pub struct VaultReconciled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub vault_balance: u64, // above rent
+    pub liabilities: u64,
+    pub drift_lamports: i64,
+    pub needs_attention: bool,
+}
+
+#[event]
+pub struct DustSwept {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub treasury: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct WinningsEscheated {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub amount: u64,
+    pub to_rebates: u64,
+    pub to_keeper_tips: u64,
+}
+
+#[event]
+pub struct UnclaimedRewardsGranted {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub rebates: u64,
+    pub keeper_tips: u64,
+}
+
+#[event]
+pub struct IdleFundsDeposited {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub adapter: Pubkey,
+    pub amount: u64,
+    pub idle_deployed: u64,
+}
+
+#[event]
+pub struct IdleFundsWithdrawn {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub adapter: Pubkey,
+    pub principal: u64,
+    pub yield_amount: u64, // paid to the treasury
+    pub idle_deployed: u64,
+}
+
+#[event]
+pub struct IncentivesStaked {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub owner: Pubkey,
+    pub shares: u64,
+    pub total_shares: u64,
+}
+
+#[event]
+pub struct IncentivesClaimed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub owner: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct AttentionCleared {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub admin: Pubkey,
+    pub drift_lamports: i64,
+}
+
+#[event]
+pub struct MarketFlaggedStale {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub last_activity_at: i64,
+}
+
+#[event]
+pub struct CreatorLiabilityReleased {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
     pub creator: Pubkey,
+    pub amount: u64,
+    pub open_liability: u64, // creator's total afterwards
+}
+
+#[event]
+pub struct CircuitBreakerReset {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub window_start: i64,
+    pub implied_yes_probability_bps: u16, // the new window's starting point
+}
+
+#[event]
+pub struct MarketFull {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub participants: u32, // the market's max_participants
+}
+
+#[event]
+pub struct MarketFullySettled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub eligible_claims: u32,
+    pub eligible_claimed: u32, // short of eligible_claims when the deadline ran out
+    pub claims_count: u32,
+    pub total_claimed: u64,
+    pub rounding_dust: u64, // left in the vault for sweep_dust
+    pub deadline_passed: bool,
+}
+
+#[event]
+pub struct TradingDelegateCreated {
+    pub owner: Pubkey,
+    pub delegate: Pubkey,
+    pub max_bet: u64,
+    pub budget: u64,
+    pub expires_at: i64,
+    pub deposit: u64,
+}
+
+#[event]
+pub struct DelegatedPredictionPlaced {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub owner: Pubkey,
+    pub delegate: Pubkey,
+    pub amount: u64,
+    pub spent: u64, // delegate's total so far
+}
+
+#[event]
+pub struct SelfExclusionSet {
+    pub user: Pubkey,
+    pub until: i64,
     pub created_at: i64,
-    pub resolution_time: i64,
+    pub extended: bool, // pushed out an exclusion that was still active
+}
+
+#[event]
+pub struct SelfExclusionClosed {
+    pub user: Pubkey,
+    pub until: i64,
+}
+
+#[event]
+pub struct TradingDelegateRevoked {
+    pub owner: Pubkey,
+    pub delegate: Pubkey,
+    pub spent: u64,
+    pub refunded: u64, // escrow plus rent
+}
+
+#[event]
+pub struct OpenAuctionBid {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub bidder: Pubkey,
+    pub side: bool,
+    pub amount: u64,
+    pub yes_total: u64, // bid on each side so far
+    pub no_total: u64,
+}
+
+#[event]
+pub struct OpenAuctionSettled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub yes_total: u64,
+    pub no_total: u64,
+    pub yes_tokens: u64, // cleared for all YES bids together
+    pub no_tokens: u64,
     pub yes_pool: u64,
     pub no_pool: u64,
-    pub total_liquidity: u64,
-    pub resolved: bool,
-    pub outcome: Option<bool>, // true = YES won, false = NO won
-    pub yes_token_vault: Pubkey,
-    pub no_token_vault: Pubkey,
-    pub fee_collected: u64,
+    pub implied_yes_probability_bps: u16,
+}
+
+#[event]
+pub struct AuctionAllocationClaimed {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub bidder: Pubkey,
+    pub side: bool,
+    pub amount: u64,
+    pub tokens_received: u64,
+    pub entry_price_bps: u64, // the side's clearing price
+}
+
+#[event]
+pub struct AuctionBidRefunded {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub bidder: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct FeatureFlagsSet {
+    pub admin: Pubkey,
+    pub previous: u64,
+    pub flags: u64,
+}
+
+#[event]
+pub struct EscheatSplitSet {
+    pub admin: Pubkey,
+    pub rebate_bps: u16,
+}
+
+#[event]
+pub struct TenantQuotaSet {
+    pub admin: Pubkey,
+    pub tenant: [u8; 16],
+    pub max_markets_per_window: u32,
+}
+
+#[event]
+pub struct CategoryDefaultsSet {
+    pub admin: Pubkey,
+    pub defaults: Vec<CategoryDefaults>,
+}
+
+#[event]
+pub struct KeeperTipsSet {
+    pub admin: Pubkey,
+    pub tips: [u64; KEEPER_ACTION_COUNT],
 }
 
-impl Market {
-    pub const INIT_SPACE: usize = 
-        8 +           // market_id
-        (4 + 256) +   // question (string)
-        32 +          // creator
-        8 +           // created_at
-        8 +           // resolution_time
-        8 +           // yes_pool
-        8 +           // no_pool
-        8 +           // total_liquidity
-        1 +           // resolved
-        (1 + 1) +     // outcome (Option<bool>)
-        32 +          // yes_token_vault
-        32 +          // no_token_vault
-        8;            // fee_collected
+#[event]
+pub struct KeeperBudgetFunded {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub funder: Pubkey,
+    pub amount: u64,
+    pub budget: u64, // after funding
 }
 
-#[account]
-pub struct Prediction {
+#[event]
+pub struct KeeperTipPaid {
     pub market_id: u64,
-    pub predictor: Pubkey,
-    pub prediction_type: bool, // true = YES, false = NO
-    pub amount_deposited: u64,
-    pub tokens_received: u64,
-    pub created_at: i64,
-    pub claimed: bool,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub keeper: Pubkey,
+    pub action: KeeperAction,
+    pub tip: u64,
+    pub budget_left: u64,
 }
 
-impl Prediction {
-    pub const INIT_SPACE: usize =
-        8 +      // market_id
-        32 +     // predictor
-        1 +      // prediction_type
-        8 +      // amount_deposited
-        8 +      // tokens_received
-        8 +      // created_at
-        1;       // claimed
+#[event]
+pub struct LossSettled {
+    pub market_id: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub predictor: Pubkey,
+    pub position_index: u32,
+    pub keeper: Pubkey,
+    pub closed: bool, // position closed, rent returned to the predictor
 }
 
-// ==================== EVENTS ====================
-
 #[event]
-pub struct MarketCreated {
+pub struct KeeperBudgetReclaimed {
     pub market_id: u64,
-    pub creator: Pubkey,
-    pub question: String,
-    pub resolution_time: i64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub funder: Pubkey,
+    pub amount: u64,
 }
 
 #[event]
-pub struct PredictionPlaced {
+pub struct HouseAccountsChanged {
     pub market_id: u64,
-    pub predictor: Pubkey,
-    pub prediction_type: bool,
-    pub amount: u64,
-    pub tokens_received: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub account: Pubkey,
+    pub added: bool, // false when removed
+    pub house_accounts: Vec<Pubkey>, // the market's house accounts after the change
 }
 
 #[event]
-pub struct MarketResolved {
+pub struct CreatorTipped {
     pub market_id: u64,
-    pub outcome: bool,
-    pub yes_pool: u64,
-    pub no_pool: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub tipper: Pubkey,
+    pub amount: u64,
+    pub creator_tips: u64, // held for the creator after this tip
 }
 
 #[event]
-pub struct RewardClaimed {
+pub struct MarketClosed {
     pub market_id: u64,
-    pub claimer: Pubkey,
-    pub reward: u64,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub creator: Pubkey,
+    pub generation: u64,
+    pub vault_lamports: u64,
+    pub archive: Pubkey,
+    pub outcome: MarketOutcome,
+    pub hash: [u8; 32], // compute_archive_hash, as kept in the archive
 }
 
 #[event]
-pub struct FeesWithdrawn {
+pub struct TipsWithdrawn {
     pub market_id: u64,
-    pub admin: Pubkey,
+    pub market: Pubkey,
+    pub tenant: [u8; 16],
+    pub event_seq: u64,
+    pub creator: Pubkey,
     pub amount: u64,
 }
 
@@ -510,4 +22499,640 @@ pub enum MarketError {
     
     #[msg("Insufficient fees collected")]
     InsufficientFees,
+
+    #[msg("Resolution time is earlier than the minimum market duration")]
+    ResolutionTooSoon,
+
+    #[msg("Resolution time is later than the maximum market duration")]
+    ResolutionTooFar,
+
+    #[msg("Invalid config parameters")]
+    InvalidConfig,
+
+    #[msg("Market ID counter overflow")]
+    MarketIdOverflow,
+
+    #[msg("Explicit market IDs must be in the reserved range")]
+    InvalidMarketId,
+
+    #[msg("Prediction won; claim the reward instead")]
+    PredictionWon,
+
+    #[msg("Leaderboard epoch has not ended yet")]
+    EpochNotOver,
+
+    #[msg("Cannot refer your own bets")]
+    SelfReferral,
+
+    #[msg("Fee discount NFT accounts are incomplete or no discount is configured")]
+    InvalidDiscountNft,
+
+    #[msg("Discount NFT is not held by the predictor")]
+    NftNotOwned,
+
+    #[msg("Discount NFT metadata is not genuine Token Metadata")]
+    ForgedNftMetadata,
+
+    #[msg("Discount NFT is not a verified member of the discount collection")]
+    NftNotInCollection,
+
+    #[msg("Invalid market options")]
+    InvalidMarketOptions,
+
+    #[msg("Arithmetic overflow in pricing")]
+    MathOverflow,
+
+    #[msg("Market vault cannot cover its liabilities")]
+    InsolventMarket,
+
+    #[msg("Account layout version is not supported")]
+    UnsupportedAccountVersion,
+
+    #[msg("Description exceeds 1024 bytes")]
+    DescriptionTooLong,
+
+    #[msg("Description can only change before the first bet")]
+    DescriptionLocked,
+
+    #[msg("Order book has no free slots")]
+    OrderBookFull,
+
+    #[msg("No resting order in this slot")]
+    InvalidOrderSlot,
+
+    #[msg("Order price must be between 1 and 9999 bps")]
+    InvalidOrderPrice,
+
+    #[msg("Invalid parlay legs or accounts")]
+    InvalidParlay,
+
+    #[msg("Parlay reserve cannot cover the potential payout")]
+    ParlayReserveInsufficient,
+
+    #[msg("Series name must be 1-32 bytes")]
+    InvalidSeriesName,
+
+    #[msg("Market is not in this series")]
+    SeriesMismatch,
+
+    #[msg("Market already has bets")]
+    MarketHasBets,
+
+    #[msg("Invalid template settings or parameters")]
+    InvalidTemplate,
+
+    #[msg("Template is locked after its first market")]
+    TemplateLocked,
+
+    #[msg("Market has no oracle key")]
+    OracleNotConfigured,
+
+    #[msg("Oracle attestation is missing or does not match")]
+    InvalidAttestation,
+
+    #[msg("Oracle attestation deadline has passed")]
+    AttestationExpired,
+
+    #[msg("A resolution commitment is awaiting its reveal")]
+    ResolutionPending,
+
+    #[msg("No resolution commitment to reveal")]
+    NoResolutionCommitment,
+
+    #[msg("Reveal delay has not elapsed")]
+    RevealTooEarly,
+
+    #[msg("Revealed values do not match the commitment")]
+    CommitmentMismatch,
+
+    #[msg("Market only accepts committed bets")]
+    CommitRevealRequired,
+
+    #[msg("Market does not use commit-reveal betting")]
+    CommitRevealDisabled,
+
+    #[msg("Reveal window has closed")]
+    RevealWindowClosed,
+
+    #[msg("Reveal window is still open")]
+    RevealWindowOpen,
+
+    #[msg("Price feed does not match the market or is invalid")]
+    InvalidPriceFeed,
+
+    #[msg("Oracle price is too old")]
+    StaleOracle,
+
+    #[msg("Oracle confidence interval is too wide")]
+    LowConfidence,
+
+    #[msg("Manual fallback is not yet available")]
+    FallbackNotAvailable,
+
+    #[msg("Market resolves from its TWAP")]
+    TwapResolutionRequired,
+
+    #[msg("Outside the price sampling window")]
+    OutsideSampleWindow,
+
+    #[msg("A sample was already recorded this slot")]
+    DuplicateSample,
+
+    #[msg("Not enough price samples recorded")]
+    InsufficientSamples,
+
+    #[msg("Shortfall reason is too long")]
+    InvalidShortfallReason,
+
+    #[msg("Insurance fund cannot cover this amount")]
+    InsuranceFundInsufficient,
+
+    #[msg("Market outcome is under dispute")]
+    DisputeOpen,
+
+    #[msg("Dispute window has closed")]
+    DisputeWindowClosed,
+
+    #[msg("Predictions have already been settled")]
+    DisputeAfterClaims,
+
+    #[msg("Dispute voting is closed")]
+    VotingClosed,
+
+    #[msg("Dispute voting is still open")]
+    VotingStillOpen,
+
+    #[msg("Dispute is not in its voting phase")]
+    DisputeNotVoting,
+
+    #[msg("Dispute is not awaiting the arbiter")]
+    DisputeNotAwaitingArbiter,
+
+    #[msg("Config changes must go through the timelock")]
+    TimelockRequired,
+
+    #[msg("Config timelock has not elapsed")]
+    TimelockNotElapsed,
+
+    #[msg("Proposal was made before the protocol was paused")]
+    ProposalPredatesPause,
+
+    #[msg("Protocol is paused")]
+    ProtocolPaused,
+
+    #[msg("New owner must differ from the current owner")]
+    InvalidNewOwner,
+
+    #[msg("No ownership transfer is pending")]
+    NoPendingTransfer,
+
+    #[msg("Cancel reason is too long")]
+    InvalidCancelReason,
+
+    #[msg("Market has not been voided")]
+    MarketNotVoided,
+
+    #[msg("Claims open once the correction window has passed")]
+    ClaimWindowNotOpen,
+
+    #[msg("Correction window has closed")]
+    CorrectionWindowClosed,
+
+    #[msg("Predictions have already been claimed")]
+    ClaimsAlreadyMade,
+
+    #[msg("Market is flagged for attention")]
+    NeedsAttention,
+
+    #[msg("Market is not flagged for attention")]
+    NoAttentionNeeded,
+
+    #[msg("Protocol treasury is not set")]
+    TreasuryNotSet,
+
+    #[msg("Market has no claim deadline")]
+    SweepNotAvailable,
+
+    #[msg("Claim period has not ended")]
+    ClaimPeriodActive,
+
+    #[msg("Vault dust was already swept")]
+    DustAlreadySwept,
+
+    #[msg("Nothing to sweep")]
+    NothingToSweep,
+
+    #[msg("Sponsor name is too long")]
+    InvalidSponsorName,
+
+    #[msg("Nothing to stake in this market's incentives")]
+    NoIncentiveShares,
+
+    #[msg("Betting is frozen ahead of close")]
+    BettingFrozen,
+
+    #[msg("Bet would move the price more than allowed")]
+    PriceImpactTooHigh,
+
+    #[msg("Prediction has not been claimed, refunded or recorded")]
+    PredictionNotSettled,
+
+    #[msg("Recipient cannot be the market vault or the prediction account")]
+    InvalidRecipient,
+
+    #[msg("wSOL market requires its wSOL vault, a payout token account and the token program")]
+    WsolVaultRequired,
+
+    #[msg("Not available on markets holding collateral as wSOL")]
+    LamportVaultRequired,
+
+    #[msg("Collateral mint must be wrapped SOL without permanent-delegate or non-transferable extensions")]
+    UnsupportedCollateralMint,
+
+    #[msg("Collateral vault received less than the deposited amount")]
+    CollateralShortfall,
+
+    #[msg("Market's wSOL vault already exists")]
+    WsolVaultExists,
+
+    #[msg("Metadata URI too long")]
+    InvalidMetadataUri,
+
+    #[msg("Account is not the market's outcome mint")]
+    InvalidOutcomeMint,
+
+    #[msg("A market with this question already resolves on the same day")]
+    DuplicateMarket,
+
+    #[msg("Initial YES probability must be between 2% and 98%")]
+    InvalidInitialProbability,
+
+    #[msg("Remaining accounts must be market and vault pairs")]
+    InvalidMarketAccounts,
+
+    #[msg("Treasury cannot cover the withdrawal")]
+    TreasuryInsufficient,
+
+    #[msg("Resolution evidence is too long")]
+    EvidenceTooLong,
+
+    #[msg("Subjective markets must be resolved with evidence")]
+    EvidenceRequired,
+
+    #[msg("Market fee is outside the configured bounds")]
+    FeeOutOfBounds,
+
+    #[msg("Market fee can no longer change after the first bet")]
+    FeeLocked,
+
+    #[msg("This market does not allow cancelling bets")]
+    CooloffDisabled,
+
+    #[msg("Cooling-off window has passed; the position can only be exited through the regular paths")]
+    CooloffExpired,
+
+    #[msg("The market has traded since this bet, so it can no longer be cancelled")]
+    TradedSinceBet,
+
+    #[msg("This position cannot be cancelled")]
+    NotCancellable,
+
+    #[msg("This market does not allow early exits")]
+    EarlyExitDisabled,
+
+    #[msg("The bettor's side of the pool cannot cover this early exit")]
+    EarlyExitUnderfunded,
+
+    #[msg("The market has not reached the limit probability")]
+    LimitNotReached,
+
+    #[msg("Limit entries can only be expired once betting closes")]
+    LimitEntryActive,
+
+    #[msg("Stop-loss threshold must be below 100%")]
+    InvalidStopLoss,
+
+    #[msg("This position has no stop-loss")]
+    StopLossNotSet,
+
+    #[msg("The position's side is not below its stop-loss")]
+    StopLossNotBreached,
+
+    #[msg("Tokens received would fall below the minimum")]
+    SlippageExceeded,
+
+    #[msg("Account belongs to a different market")]
+    MarketMismatch,
+
+    #[msg("Market traded too recently to be resolved")]
+    SettleDelayActive,
+
+    #[msg("Creator is not approved to create markets")]
+    CreatorNotApproved,
+
+    #[msg("Creator can't cover the seed liquidity and creation fee")]
+    InsufficientCreatorFunds,
+
+    #[msg("Fixed odds must pay more than the stake")]
+    InvalidOdds,
+
+    #[msg("Bankroll can't cover this bet's payout")]
+    BankrollExceeded,
+
+    #[msg("Not available in this market's mode")]
+    UnsupportedMarketMode,
+
+    #[msg("Prediction note exceeds 64 bytes")]
+    NoteTooLong,
+
+    #[msg("Resolver delays must be non-negative and non-decreasing by tier")]
+    InvalidResolverTiers,
+
+    #[msg("This resolver's tier may not resolve the market yet")]
+    ResolverTierNotOpen,
+
+    #[msg("Rebate already claimed for this prediction")]
+    RebateAlreadyClaimed,
+
+    #[msg("No rebate to claim")]
+    NoRebate,
+
+    #[msg("The staking vault must receive its share of collected fees")]
+    StakingVaultRequired,
+
+    #[msg("Unstaked tokens are still unbonding")]
+    UnbondingActive,
+
+    #[msg("The buyback escrow must receive its share of collected fees")]
+    BuybackEscrowRequired,
+
+    #[msg("A buyback is already in progress")]
+    BuybackPending,
+
+    #[msg("execute_buyback must be followed by settle_buyback in the same transaction")]
+    BuybackNotSettled,
+
+    #[msg("No buyback is in progress")]
+    NoBuybackPending,
+
+    #[msg("Fewer protocol tokens were delivered than the buyback's minimum")]
+    BuybackUnderDelivered,
+
+    #[msg("Multisig needs 1 to 5 distinct owners and a threshold no larger than the owner count")]
+    InvalidMultisig,
+
+    #[msg("Signer is not an owner of the admin multisig")]
+    NotMultisigOwner,
+
+    #[msg("Owner already approved this admin action")]
+    AlreadyApproved,
+
+    #[msg("Admin action already executed")]
+    AdminTxExecuted,
+
+    #[msg("Admin action lacks the approvals the multisig requires")]
+    ThresholdNotMet,
+
+    #[msg("Market is not yet past the staleness threshold")]
+    MarketNotStale,
+
+    #[msg("Market is already flagged stale")]
+    AlreadyStale,
+
+    #[msg("Market has no keeper budget left")]
+    NoKeeperBudget,
+
+    #[msg("Markets of this category must be created with an oracle")]
+    OracleRequired,
+
+    #[msg("Category defaults need distinct nonzero categories, in-bounds fees and non-negative windows")]
+    InvalidCategoryDefaults,
+
+    #[msg("Bet would push the market creator's open liability past the cap")]
+    CreatorLiabilityCapReached,
+
+    #[msg("Market has no open liability to release")]
+    NoOpenLiability,
+
+    #[msg("Betting opens once the open auction is settled")]
+    OpenAuctionPending,
+
+    #[msg("The open auction has ended or the market has none")]
+    AuctionClosed,
+
+    #[msg("The open auction has not ended")]
+    AuctionActive,
+
+    #[msg("The market has no open auction to settle")]
+    NoPendingAuction,
+
+    #[msg("The open auction has not been settled")]
+    AuctionNotSettled,
+
+    #[msg("Auction bids are refundable only if the auction was never settled by betting close or resolution")]
+    AuctionNotRefundable,
+
+    #[msg("A delegate needs a positive per-bet max, a budget at least as large, a future expiry and a deposit")]
+    InvalidTradingDelegate,
+
+    #[msg("The trading delegate has expired")]
+    DelegateExpired,
+
+    #[msg("Bet exceeds the delegate's per-bet maximum")]
+    DelegateBetTooLarge,
+
+    #[msg("Bet would exceed the delegate's total budget")]
+    DelegateBudgetExceeded,
+
+    #[msg("The delegate's escrow cannot cover the bet and its account rent")]
+    DelegateEscrowInsufficient,
+
+    #[msg("Claim the loser rebate before closing the position")]
+    RebateUnclaimed,
+
+    #[msg("Bet would move the implied probability past the market's circuit breaker")]
+    CircuitBreakerTripped,
+
+    #[msg("Account does not match the rent payer recorded on the prediction")]
+    RentPayerMismatch,
+
+    #[msg("Tenant is not registered in the config")]
+    UnknownTenant,
+
+    #[msg("Tenant has created its quota of markets for this window")]
+    TenantQuotaExceeded,
+
+    #[msg("Config has no free tenant slot")]
+    TenantTableFull,
+
+    #[msg("Tenant must not be all zeroes")]
+    InvalidTenant,
+
+    #[msg("Attestation records must be passed as (record, payer) pairs")]
+    InvalidAttestationRecords,
+
+    #[msg("Attestation record has not expired or is too recent to purge")]
+    AttestationRecordActive,
+
+    #[msg("Claim window for this market has closed")]
+    ClaimWindowExpired,
+
+    #[msg("Claim window has not closed yet")]
+    ClaimWindowOpen,
+
+    #[msg("Market has no claim window")]
+    EscheatNotAvailable,
+
+    #[msg("Unclaimed winnings have already been escheated")]
+    AlreadyEscheated,
+
+    #[msg("Unclaimed rewards pool cannot cover the grant")]
+    InsufficientUnclaimedRewards,
+
+    #[msg("This feature is turned off in the config")]
+    FeatureDisabled,
+
+    #[msg("Payout params for this market are already published")]
+    PayoutParamsAlreadyPublished,
+
+    #[msg("No yield adapter is whitelisted")]
+    YieldAdapterNotSet,
+
+    #[msg("Not the market's yield adapter")]
+    InvalidYieldAdapter,
+
+    #[msg("Deposit exceeds the idle funds limit")]
+    IdleLimitExceeded,
+
+    #[msg("The vault can't cover this until idle funds are recalled")]
+    IdleFundsNotRecalled,
+
+    #[msg("The yield adapter returned less than the principal")]
+    YieldAdapterShortfall,
+
+    #[msg("Betting is blocked by an active self-exclusion")]
+    SelfExcluded,
+
+    #[msg("Self-exclusion must end between a day and a year from now")]
+    InvalidExclusionPeriod,
+
+    #[msg("An active self-exclusion cannot be shortened or closed")]
+    SelfExclusionActive,
+
+    #[msg("Bet exceeds the market's current maximum bet")]
+    BetAboveCap,
+
+    #[msg("Resolution is blocked until the market's resolvers are reassigned and active")]
+    ResolversNotActive,
+
+    #[msg("Key is not one of the market's resolvers")]
+    UnknownResolver,
+
+    #[msg("Parent market is missing, mismatched or resolves after this market")]
+    InvalidParentMarket,
+
+    #[msg("Parent market already resolved against the condition")]
+    ParentConditionFailed,
+
+    #[msg("Parent market's outcome is not final yet")]
+    ParentUnresolved,
+
+    #[msg("Parent condition was already settled")]
+    ParentConditionSettled,
+
+    #[msg("Market does not take compressed bets, or its receipt tree is not open")]
+    NotCompressed,
+
+    #[msg("Receipt root changed since it was read")]
+    StaleReceiptRoot,
+
+    #[msg("Receipt tree is full")]
+    ReceiptTreeFull,
+
+    #[msg("Receipt is not in the market's receipt tree")]
+    InvalidReceiptProof,
+
+    #[msg("Creator has reached its daily market creation limit")]
+    CreationRateLimited,
+
+    #[msg("Pass claim_receipt exactly when claiming with a receipt")]
+    ClaimReceiptMismatch,
+
+    #[msg("Claim receipt can't be closed yet")]
+    ClaimReceiptLocked,
+
+    #[msg("Only bets that reduce an open position are allowed this close to betting close")]
+    ReduceOnlyWindow,
+
+    #[msg("Escrowed claim account does not match the claim")]
+    EscrowedClaimMismatch,
+
+    #[msg("Escrowed claims must be clawed back before this payout")]
+    EscrowClawbackPending,
+
+    #[msg("Vault cannot pay the winners of this outcome; top it up first")]
+    ResolutionWouldBeInsolvent,
+
+    #[msg("Bet amount must be greater than zero")]
+    BetAmountZero,
+
+    #[msg("Revealed bet exceeds the committed escrow")]
+    RevealExceedsEscrow,
+
+    #[msg("Limit entry doesn't cover its keeper tip")]
+    LimitEntryBelowTip,
+
+    #[msg("Dry run complete; no changes were made")]
+    DryRunComplete,
+
+    #[msg("Market resolves only through its resolution program")]
+    ResolutionProgramOnly,
+
+    #[msg("Caller is not the market's resolution program calling by CPI")]
+    NotResolutionProgram,
+
+    #[msg("Push policy can't change after the first bet")]
+    PushPolicyLocked,
+
+    #[msg("Market has reached its maximum number of participants")]
+    MarketFull,
+
+    #[msg("No tips to withdraw")]
+    NoTips,
+
+    #[msg("Remaining accounts must be prediction and market pairs")]
+    InvalidPositionAccounts,
+
+    #[msg("Market already has the most house accounts allowed")]
+    TooManyHouseAccounts,
+
+    #[msg("Wallet is already a house account")]
+    HouseAccountExists,
+
+    #[msg("Wallet is not a house account")]
+    NotHouseAccount,
+
+    #[msg("Market is not fully settled")]
+    MarketNotSettled,
+
+    #[msg("Account is not the market-maker registration of the order's owner")]
+    MarketMakerMismatch,
+
+    #[msg("Prediction was opened on an earlier market under this ID")]
+    GenerationMismatch,
+
+    #[msg("Market still has open prediction accounts")]
+    PredictionsOutstanding,
+
+    #[msg("Market vault still owes collateral")]
+    VaultNotDrained,
+
+    #[msg("Admin action has too many accounts or too much instruction data")]
+    AdminActionTooLarge,
+
+    #[msg("A voided market's fees fund its refunds")]
+    FeesRefundable,
+
+    #[msg("Legacy account data is too short for its layout")]
+    MalformedLegacyAccount,
 }
\ No newline at end of file
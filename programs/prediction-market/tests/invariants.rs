@@ -0,0 +1,211 @@
+// Random instruction sequences against one market under solana-program-test,
+// checking global invariants after every step under the pool- and
+// user-favoring rounding policies, and random claim orders, checking that
+// no winner is left short by those who claimed before them.
+// Run `anchor build` first, then
+// `cargo test -p prediction-market --features test-utils --test invariants`
+//
+// proptest shrinks a failing sequence and records its seed in
+// proptest-regressions/invariants.txt; commit that file so the case is
+// replayed on every run
+
+use prediction_market::fixtures::{fetch, prediction_pda, warp_by, MarketScenario};
+use prediction_market::{MarketOptions, MarketOutcome, Prediction, RoundingPolicy};
+use proptest::prelude::*;
+use solana_program_test::BanksClientError;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::signature::Signer;
+use solana_sdk::transaction::TransactionError;
+
+const BETTORS: usize = 4;
+const WINNERS: usize = 50;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Place { bettor: usize, side: bool, amount: u64 },
+    Warp { secs: i64 },
+    Resolve { outcome: bool },
+    Cancel,
+    Claim { bettor: usize },
+    Refund { bettor: usize },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (0..BETTORS, any::<bool>(), 1u64..2_000_000_000)
+            .prop_map(|(bettor, side, amount)| Op::Place { bettor, side, amount }),
+        2 => (0i64..2 * 60 * 60).prop_map(|secs| Op::Warp { secs }),
+        1 => any::<bool>().prop_map(|outcome| Op::Resolve { outcome }),
+        1 => Just(Op::Cancel),
+        2 => (0..BETTORS).prop_map(|bettor| Op::Claim { bettor }),
+        1 => (0..BETTORS).prop_map(|bettor| Op::Refund { bettor }),
+    ]
+}
+
+/// Rejections are expected; a panic or blown budget is not
+fn assert_no_panic(result: Result<(), BanksClientError>, op: &Op) {
+    if let Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+        _,
+        error @ (InstructionError::ProgramFailedToComplete
+        | InstructionError::ComputationalBudgetExceeded),
+    ))) = result
+    {
+        panic!("{op:?} failed with {error:?}");
+    }
+}
+
+/// Under FavorPool the vault covers the liabilities after every step; under
+/// any other policy it may fall short by at most a lamport per step so far
+async fn run(ops: Vec<Op>, rounding: RoundingPolicy) {
+    let mut scenario = MarketScenario::new()
+        .options(MarketOptions {
+            rounding_policy: rounding,
+            ..MarketOptions::default()
+        })
+        .idle_bettors(BETTORS, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    // Fees charged to bettors: what each bet didn't add to its pool
+    let mut fees_charged = 0u64;
+    let mut claimed = [false; BETTORS];
+
+    for (step, op) in ops.iter().enumerate() {
+        let before = scenario.market().await;
+        let result = match *op {
+            Op::Place { bettor, side, amount } => {
+                scenario.place_prediction(bettor, side, amount).await
+            }
+            Op::Warp { secs } => {
+                warp_by(&mut scenario.context, secs).await;
+                Ok(())
+            }
+            Op::Resolve { outcome } => scenario.resolve(outcome).await,
+            Op::Cancel => scenario.cancel().await,
+            Op::Claim { bettor } => scenario.claim(bettor).await,
+            Op::Refund { bettor } => scenario.refund(bettor).await,
+        };
+        let placed = matches!(op, Op::Place { .. }) && result.is_ok();
+        assert_no_panic(result, op);
+
+        // A void refunds whole deposits; the admin covers the cuts that
+        // never reached the vault
+        let mut market = scenario.market().await;
+        if market.outcome == MarketOutcome::Void && before.outcome != MarketOutcome::Void {
+            let shortfall = market.liabilities().saturating_sub(scenario.vault_balance().await);
+            if shortfall > 0 {
+                scenario.top_up(shortfall).await.unwrap();
+                market = scenario.market().await;
+            }
+        }
+        if let (true, Op::Place { amount, .. }) = (placed, op) {
+            let pooled = (market.yes_pool + market.no_pool) - (before.yes_pool + before.no_pool);
+            fees_charged += amount - pooled;
+        }
+
+        let vault = scenario.vault_balance().await;
+        let giveaway = match rounding {
+            RoundingPolicy::FavorPool => 0,
+            _ => step as u64 + 1,
+        };
+        assert!(
+            vault + giveaway >= market.liabilities(),
+            "after {op:?}: vault {vault} + {giveaway} < liabilities {}",
+            market.liabilities()
+        );
+        assert!(
+            market.fee_collected <= fees_charged,
+            "after {op:?}: fee_collected {} > fees charged {fees_charged}",
+            market.fee_collected
+        );
+        for (bettor, was_claimed) in claimed.iter_mut().enumerate() {
+            let address = prediction_pda(scenario.market_id, &scenario.bettors[bettor].pubkey());
+            let Ok(prediction) = fetch::<Prediction>(&mut scenario.context, address).await else {
+                continue;
+            };
+            assert!(
+                prediction.claimed || !*was_claimed,
+                "after {op:?}: bettor {bettor} lost its claimed flag"
+            );
+            *was_claimed = prediction.claimed;
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn invariants_hold(ops in prop::collection::vec(op(), 1..24)) {
+        let run = run(ops, RoundingPolicy::FavorPool);
+        tokio::runtime::Runtime::new().unwrap().block_on(run);
+    }
+
+    #[test]
+    fn favor_user_gives_away_a_lamport_per_step_at_most(
+        ops in prop::collection::vec(op(), 1..24)
+    ) {
+        let run = run(ops, RoundingPolicy::FavorUser);
+        tokio::runtime::Runtime::new().unwrap().block_on(run);
+    }
+}
+
+proptest! {
+    /// Each policy rounds every division to within one unit of the exact
+    /// quotient, in its own direction
+    #[test]
+    fn rounding_stays_within_one_unit(a in any::<u64>(), b in any::<u64>(), d in 1..=u64::MAX) {
+        let (n, d) = ((a as u128) * (b as u128), d as u128);
+        for policy in [RoundingPolicy::FavorPool, RoundingPolicy::FavorUser] {
+            let (payout, charge) = (policy.payout(n, d), policy.charge(n, d));
+            let (down, up) = if policy == RoundingPolicy::FavorPool {
+                (payout, charge)
+            } else {
+                (charge, payout)
+            };
+            prop_assert!(down * d <= n && n - down * d < d);
+            prop_assert!(up * d >= n && up * d - n < d);
+        }
+        let nearest = RoundingPolicy::Bankers.payout(n, d);
+        prop_assert_eq!(nearest, RoundingPolicy::Bankers.charge(n, d));
+        prop_assert!((nearest * d).abs_diff(n) * 2 <= d);
+    }
+}
+
+/// 50 winners of odd stakes claim in `order`. Each claim rounds down, so
+/// however they go, the vault covers every one and only dust is left
+async fn claim_in_order(order: Vec<usize>) {
+    let mut builder = MarketScenario::new();
+    for winner in 0..WINNERS as u64 {
+        builder = builder.bet(true, 10_000_003 + 7_919 * winner * winner);
+    }
+    let mut scenario = builder
+        .bet(false, 333_333_333)
+        .bet(false, 123_456_789)
+        .start()
+        .await
+        .unwrap();
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+
+    for &winner in &order {
+        let result = scenario.claim(winner).await;
+        assert!(result.is_ok(), "winner {winner} failed to claim: {result:?}");
+    }
+    let market = scenario.market().await;
+    assert!(market.fully_settled);
+    assert_eq!(market.outstanding_entitlement, 0);
+    assert!(market.rounding_dust_accrued < WINNERS as u64);
+    assert!(scenario.vault_balance().await >= market.liabilities());
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(8))]
+
+    #[test]
+    fn every_claim_order_pays_every_winner(
+        order in Just((0..WINNERS).collect::<Vec<_>>()).prop_shuffle()
+    ) {
+        tokio::runtime::Runtime::new().unwrap().block_on(claim_in_order(order));
+    }
+}
@@ -0,0 +1,1613 @@
+// End-to-end market lifecycle under solana-program-test, built on the
+// `test-utils` fixtures. Run `anchor build` first, then
+// `cargo test -p prediction-market --features test-utils`
+
+use prediction_market::fixtures::{
+    add_house_account, approve_admin_tx, approve_creator, approve_market_maker, archive_pda,
+    at_generation, boot, cancel_order, claim_compressed, claim_mm_rebates, claim_refund,
+    claim_reward, close_market, close_prediction, compute_exposure, compute_units, config_pda,
+    counter_pda, create_admin_multisig, create_admin_tx, creator_allow_pda, creator_stats_pda,
+    epoch_stats_pda, execute_admin_tx, fetch, fill_order, fill_pda, init_receipt_tree,
+    initialize_config, initialize_market_and_predict, initialize_market_with_id,
+    initialize_order_book, invoke_action, market_maker_pda, market_pda, multisig_signer_pda, now,
+    order_book_pda, pause_protocol, place_compressed_prediction, place_prediction, post_order,
+    prediction_pda, prediction_pda_at, process, profile_pda, receipt_proof, refund_liquidity,
+    resolve_market, revoke_resolver, self_exclusion_pda, set_resolvers, set_upgrade_authority,
+    settle_fill, settle_parent_condition, top_up_vault, vault_pda, view, warp_by,
+    warp_to_timestamp, withdraw_tips, MarketScenario, Scenario,
+};
+use prediction_market::{
+    compute_archive_hash, compute_payout, denormalized_amount, empty_receipt_root,
+    normalize_question, normalized_amount, question_hash, receipt_root_from_proof,
+    CompressedReceipt, Config, ConfigParams, CreatorAllow, CreatorStats, Exposure, Market,
+    MarketArchive, MarketCounter, MarketError, MarketMakerRegistration, MarketOptions,
+    MarketOutcome, OrderBook, ParentCondition, Prediction, RoundingPolicy, SelfExclusion,
+    UserEpochStats, UserProfile, CREATION_DAY_SECS, EXPLICIT_MARKET_ID_START, MAX_IDLE_BPS,
+    NORMALIZED_DECIMALS, ORDER_BOOK_CAPACITY, ORDER_SLOT_NONE, RECEIPT_TREE_DEPTH,
+    RESOLVER_REASSIGN_DELAY, SOL_DECIMALS,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::clock::Clock;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::keccak;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_sdk::transaction::TransactionError;
+use solana_system_interface::instruction as system_instruction;
+
+const STAKE: u64 = 500_000_000;
+
+#[tokio::test]
+async fn create_bet_resolve_claim() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+
+    let market = scenario.market().await;
+    assert_eq!(market.trade_count, 2);
+    assert_eq!(market.collateral_decimals, SOL_DECIMALS);
+    assert!(!market.resolved);
+
+    // Too early to resolve
+    assert!(scenario.resolve(true).await.is_err());
+
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+    assert_eq!(scenario.market().await.outcome, MarketOutcome::Yes);
+
+    // The NO bettor lost
+    assert!(scenario.claim(1).await.is_err());
+
+    let winner = scenario.bettors[0].pubkey();
+    let market_id = scenario.market_id;
+    let before = scenario.balance(winner).await;
+    let vault_before = scenario.balance(vault_pda(market_id)).await;
+    scenario.claim(0).await.unwrap();
+
+    let prediction = scenario.prediction(0).await;
+    assert!(prediction.claimed);
+    assert_eq!(prediction.tokens_claimed, prediction.tokens_received);
+    let paid = vault_before - scenario.balance(vault_pda(market_id)).await;
+    // Half the YES pool is the creator's seed, so the winner gets its
+    // tokens' share of both pools rather than the whole NO stake
+    let snapshot = scenario.market().await.resolution_snapshot;
+    let share = prediction.tokens_received as u128 * snapshot.pot() as u128;
+    assert_eq!(paid as u128, share / snapshot.pool(true) as u128);
+    assert_eq!(scenario.balance(winner).await, before + paid);
+
+    // A position can only be claimed once
+    assert!(scenario.claim(0).await.is_err());
+}
+
+#[tokio::test]
+async fn claims_match_compute_payout() {
+    use RoundingPolicy::*;
+    for rounding in [FavorPool, FavorUser, Bankers] {
+        claims_match_compute_payout_under(rounding).await;
+    }
+}
+
+async fn claims_match_compute_payout_under(rounding: RoundingPolicy) {
+    // A fixed xorshift stream, so a failure replays exactly
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    let options = MarketOptions {
+        rounding_policy: rounding,
+        ..MarketOptions::default()
+    };
+    let mut scenario = MarketScenario::new()
+        .options(options)
+        .bet(true, STAKE)
+        .bet(false, STAKE);
+    for _ in 0..10 {
+        let side = next() % 2 == 0;
+        let stake = 10_000_000 + next() % 1_990_000_000;
+        scenario = scenario.bet(side, stake);
+    }
+    let mut scenario = scenario.start().await.unwrap();
+
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+
+    for i in 0..scenario.bettors.len() {
+        let prediction = scenario.prediction(i).await;
+        if !prediction.prediction_type {
+            continue;
+        }
+        let winner = scenario.bettors[i].pubkey();
+        let before = scenario.balance(winner).await;
+        scenario.claim(i).await.unwrap();
+
+        // The first claim fixes the params every later claim is paid from
+        let params = scenario.market().await.payout_params;
+        assert!(params.published_at > 0);
+        let paid = scenario.balance(winner).await - before;
+        assert_eq!(paid, compute_payout(&params, rounding, prediction.tokens_received));
+    }
+}
+
+/// Overwrite the market_id stored in the scenario's market account, which
+/// follows the discriminator and version; its address stays the same
+async fn store_market_id(scenario: &mut Scenario, market_id: u64) {
+    let address = market_pda(scenario.market_id);
+    let mut account = scenario
+        .context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap();
+    account.data[9..17].copy_from_slice(&market_id.to_le_bytes());
+    scenario.context.set_account(&address, &account.into());
+}
+
+fn assert_market_error(result: Result<(), BanksClientError>, error: MarketError) {
+    let expected = u32::from(error);
+    match result {
+        Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        ))) => assert_eq!(code, expected),
+        other => panic!("expected {error:?}, got {other:?}"),
+    }
+}
+
+fn assert_market_mismatch(result: Result<(), BanksClientError>) {
+    assert_market_error(result, MarketError::MarketMismatch);
+}
+
+#[tokio::test]
+async fn rejects_a_stored_market_id_that_disagrees_with_its_seeds() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .idle_bettors(1, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let market_id = scenario.market_id;
+
+    store_market_id(&mut scenario, market_id + 1).await;
+    assert_market_mismatch(scenario.place_prediction(2, true, STAKE).await);
+    assert_market_mismatch(scenario.cancel().await);
+    scenario.warp_to_resolution().await;
+    assert_market_mismatch(scenario.resolve(true).await);
+
+    store_market_id(&mut scenario, market_id).await;
+    scenario.resolve(false).await.unwrap();
+    store_market_id(&mut scenario, market_id + 1).await;
+    assert_market_mismatch(scenario.claim(1).await);
+
+    // Refunds of a voided market likewise
+    let mut scenario = MarketScenario::new().bet(true, STAKE).start().await.unwrap();
+    scenario.cancel().await.unwrap();
+    let market_id = scenario.market_id;
+    store_market_id(&mut scenario, market_id.wrapping_sub(1)).await;
+    assert_market_mismatch(scenario.refund(0).await);
+}
+
+#[tokio::test]
+async fn self_exclusion_blocks_bets_until_it_expires() {
+    const DAY: i64 = 24 * 60 * 60;
+    let mut scenario = MarketScenario::new()
+        .duration_secs(7 * DAY)
+        .idle_bettors(1, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let user = scenario.bettors[0].pubkey();
+
+    let until = now(&mut scenario.context).await + DAY;
+    scenario.self_exclude(0, until).await.unwrap();
+    assert_market_error(
+        scenario.place_prediction(0, true, STAKE).await,
+        MarketError::SelfExcluded,
+    );
+
+    // Extending keeps the start; shortening or closing early is refused
+    scenario.self_exclude(0, until + DAY).await.unwrap();
+    let exclusion: SelfExclusion = fetch(&mut scenario.context, self_exclusion_pda(&user))
+        .await
+        .unwrap();
+    assert_eq!(exclusion.until, until + DAY);
+    assert_eq!(exclusion.created_at, until - DAY);
+    assert_market_error(
+        scenario.self_exclude(0, until).await,
+        MarketError::SelfExclusionActive,
+    );
+    assert_market_error(
+        scenario.close_self_exclusion(0).await,
+        MarketError::SelfExclusionActive,
+    );
+
+    // Still excluded past the original end
+    warp_by(&mut scenario.context, DAY + 1).await;
+    assert_market_error(
+        scenario.place_prediction(0, true, STAKE).await,
+        MarketError::SelfExcluded,
+    );
+
+    warp_by(&mut scenario.context, DAY).await;
+    scenario.place_prediction(0, true, STAKE).await.unwrap();
+    scenario.close_self_exclusion(0).await.unwrap();
+    assert_eq!(scenario.balance(self_exclusion_pda(&user)).await, 0);
+}
+
+#[tokio::test]
+async fn max_bet_ramps_up_from_creation() {
+    const MAX_BET: u64 = 1_000_000_000;
+    let mut scenario = MarketScenario::new()
+        .options(MarketOptions {
+            max_bet: MAX_BET,
+            max_bet_ramp_secs: 1_000,
+            max_bet_ramp_start_bps: 1_000,
+            ..MarketOptions::default()
+        })
+        .idle_bettors(3, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let created_at = scenario.market().await.created_at;
+
+    // 10% at creation, 55% halfway, all of it once the ramp is over
+    for (bettor, elapsed, cap) in [
+        (0, 0, MAX_BET / 10),
+        (1, 500, MAX_BET * 55 / 100),
+        (2, 1_000, MAX_BET),
+    ] {
+        warp_to_timestamp(&mut scenario.context, created_at + elapsed).await;
+        assert_eq!(scenario.market().await.max_bet_at(created_at + elapsed), Some(cap));
+        assert_market_error(
+            scenario.place_prediction(bettor, true, cap + 1).await,
+            MarketError::BetAboveCap,
+        );
+        scenario.place_prediction(bettor, true, cap).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn house_accounts_bet_past_the_cap_and_stay_off_the_leaderboard() {
+    const MAX_BET: u64 = STAKE;
+    let mut scenario = MarketScenario::new()
+        .options(MarketOptions {
+            max_bet: MAX_BET,
+            max_bet_ramp_secs: 1_000,
+            max_bet_ramp_start_bps: 1_000,
+            ..MarketOptions::default()
+        })
+        .idle_bettors(3, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+
+    // Only the creator names house accounts
+    let house = scenario.bettors[1].pubkey();
+    let payer = scenario.context.payer.pubkey();
+    let outsider = add_house_account(&payer, scenario.market_id, &house);
+    assert_market_error(
+        process(&mut scenario.context, &[outsider], &[]).await,
+        MarketError::Unauthorized,
+    );
+    scenario.add_house_account(1).await.unwrap();
+    scenario.add_house_account(2).await.unwrap();
+    assert_market_error(scenario.add_house_account(1).await, MarketError::HouseAccountExists);
+    scenario.remove_house_account(2).await.unwrap();
+
+    // Early in the ramp the house bets three times max_bet, while an
+    // ordinary bettor and a dropped house account are held to the cap
+    for bettor in [0, 2] {
+        assert_market_error(
+            scenario.place_prediction(bettor, true, 3 * MAX_BET).await,
+            MarketError::BetAboveCap,
+        );
+    }
+    let fees_before = scenario.market().await.fee_collected;
+    scenario.place_prediction(1, false, 3 * MAX_BET).await.unwrap();
+    assert!(scenario.market().await.fee_collected > fees_before);
+    assert!(scenario.prediction(1).await.is_house.0);
+    scenario.place_prediction(0, true, MAX_BET / 10).await.unwrap();
+    assert!(!scenario.prediction(0).await.is_house.0);
+
+    // The house wins and claims like anyone else
+    scenario.warp_to_resolution().await;
+    scenario.resolve(false).await.unwrap();
+    let before = scenario.balance(house).await;
+    scenario.claim(1).await.unwrap();
+    assert!(scenario.balance(house).await > before);
+
+    // ...but none of it reaches its profile or the epoch's leaderboard
+    // stats, beyond the bet count that pages its market index
+    let epoch = fetch::<Config>(&mut scenario.context, config_pda())
+        .await
+        .unwrap()
+        .current_epoch;
+    let profile: UserProfile = fetch(&mut scenario.context, profile_pda(&house)).await.unwrap();
+    assert_eq!(profile.bets_placed, 1);
+    assert_eq!(profile.total_wagered, 0);
+    assert_eq!((profile.markets_won, profile.total_won), (0, 0));
+    let stats: UserEpochStats =
+        fetch(&mut scenario.context, epoch_stats_pda(&house, epoch)).await.unwrap();
+    assert_eq!((stats.bets_placed, stats.total_wagered), (0, 0));
+    assert_eq!((stats.markets_won, stats.total_won), (0, 0));
+    let bettor = scenario.bettors[0].pubkey();
+    let stats: UserEpochStats =
+        fetch(&mut scenario.context, epoch_stats_pda(&bettor, epoch)).await.unwrap();
+    assert_eq!((stats.bets_placed, stats.total_wagered), (1, MAX_BET / 10));
+}
+
+#[tokio::test]
+async fn revoked_resolver_is_replaced_after_the_reassign_delay() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+    let market_id = scenario.market_id;
+    let creator = scenario.creator.insecure_clone();
+    let admin = scenario.admin.insecure_clone();
+    let (leaked, fresh) = (Keypair::new(), Keypair::new());
+    let unset = Pubkey::default();
+    let payer = scenario.context.payer.pubkey();
+    let funding = [&leaked, &fresh]
+        .map(|key| system_instruction::transfer(&payer, &key.pubkey(), 1_000_000_000));
+    process(&mut scenario.context, &funding, &[]).await.unwrap();
+
+    let tiers = |key: &Keypair| {
+        set_resolvers(&creator.pubkey(), market_id, [key.pubkey(), unset, unset], [0; 3])
+    };
+    process(&mut scenario.context, &[tiers(&leaked)], &[&creator]).await.unwrap();
+
+    // The leaked key is struck out, and nobody may resolve in its place
+    let revoke = revoke_resolver(&leaked.pubkey(), market_id, leaked.pubkey());
+    assert_market_error(
+        process(&mut scenario.context, &[revoke], &[&leaked]).await,
+        MarketError::Unauthorized,
+    );
+    let revoke = revoke_resolver(&admin.pubkey(), market_id, leaked.pubkey());
+    process(&mut scenario.context, &[revoke], &[&admin]).await.unwrap();
+    let market = scenario.market().await;
+    assert_eq!(market.resolvers, [unset; 3]);
+    assert_eq!(market.resolvers_active_at, i64::MAX);
+
+    scenario.warp_to_resolution().await;
+    for key in [&leaked, &creator] {
+        let resolve = resolve_market(&key.pubkey(), market_id, false);
+        assert_market_error(
+            process(&mut scenario.context, &[resolve], &[key]).await,
+            MarketError::ResolversNotActive,
+        );
+    }
+
+    // Reassigned past resolution time, the new key waits out the delay
+    process(&mut scenario.context, &[tiers(&fresh)], &[&creator]).await.unwrap();
+    let resolve = resolve_market(&fresh.pubkey(), market_id, true);
+    assert_market_error(
+        process(&mut scenario.context, std::slice::from_ref(&resolve), &[&fresh]).await,
+        MarketError::ResolversNotActive,
+    );
+
+    warp_by(&mut scenario.context, RESOLVER_REASSIGN_DELAY).await;
+    let stale = resolve_market(&leaked.pubkey(), market_id, false);
+    assert_market_error(
+        process(&mut scenario.context, &[stale], &[&leaked]).await,
+        MarketError::Unauthorized,
+    );
+    process(&mut scenario.context, &[resolve], &[&fresh]).await.unwrap();
+    assert_eq!(scenario.market().await.outcome, MarketOutcome::Yes);
+}
+
+#[tokio::test]
+async fn child_market_follows_its_parent_outcome() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .idle_bettors(1, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let parent_id = scenario.market_id;
+    let child_time = scenario.resolution_time + 3_600;
+    let child_of = |outcome| MarketOptions {
+        parent: Some(ParentCondition { market: market_pda(parent_id), outcome }),
+        ..MarketOptions::default()
+    };
+
+    // A parent resolving after the child can't gate it
+    let early = scenario.create_market("Too early", scenario.resolution_time - 1, child_of(true));
+    assert_market_error(early.await.map(|_| ()), MarketError::InvalidParentMarket);
+    let on_yes = scenario.create_market("If YES", child_time, child_of(true)).await.unwrap();
+    let on_no = scenario.create_market("If NO", child_time, child_of(false)).await.unwrap();
+    scenario.market_id = on_no;
+    scenario.place_prediction(2, true, STAKE).await.unwrap();
+
+    // Nothing settles while the parent is open, even past the child's close
+    warp_to_timestamp(&mut scenario.context, child_time).await;
+    let settle_yes = settle_parent_condition(on_yes, parent_id);
+    assert_market_error(
+        process(&mut scenario.context, std::slice::from_ref(&settle_yes), &[]).await,
+        MarketError::ParentUnresolved,
+    );
+    scenario.market_id = on_yes;
+    assert_market_error(scenario.resolve(true).await, MarketError::ParentUnresolved);
+
+    scenario.market_id = parent_id;
+    scenario.resolve(true).await.unwrap();
+
+    // Condition met: the child resolves as usual
+    process(&mut scenario.context, std::slice::from_ref(&settle_yes), &[]).await.unwrap();
+    scenario.market_id = on_yes;
+    assert!(scenario.market().await.parent_met);
+    assert_market_error(
+        process(&mut scenario.context, &[settle_yes], &[]).await,
+        MarketError::ParentConditionSettled,
+    );
+    scenario.resolve(false).await.unwrap();
+
+    // Condition failed: the child is voided and deposits come back
+    let settle_no = settle_parent_condition(on_no, parent_id);
+    process(&mut scenario.context, &[settle_no], &[]).await.unwrap();
+    scenario.market_id = on_no;
+    assert_eq!(scenario.market().await.outcome, MarketOutcome::Void);
+    scenario.refund(2).await.unwrap();
+}
+
+#[tokio::test]
+async fn compressed_receipts_pay_once_against_a_valid_proof() {
+    let mut scenario = MarketScenario::new()
+        .options(MarketOptions {
+            compressed: true,
+            ..MarketOptions::default()
+        })
+        .idle_bettors(3, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let market_id = scenario.market_id;
+    let creator = scenario.creator.insecure_clone();
+    let open = init_receipt_tree(&creator.pubkey(), market_id);
+    process(&mut scenario.context, &[open], &[&creator]).await.unwrap();
+    assert_eq!(scenario.market().await.receipt_root, empty_receipt_root());
+
+    // Each bet appends a leaf; one against a root that has moved on fails
+    let mut receipts = Vec::new();
+    for (bettor, side) in [(0, true), (1, false), (2, true)] {
+        let predictor = scenario.bettors[bettor].insecure_clone();
+        let before = scenario.market().await;
+        let (owner, market_creator) = (predictor.pubkey(), creator.pubkey());
+        let bet = |root| {
+            place_compressed_prediction(&owner, &market_creator, market_id, side, STAKE, root)
+        };
+        if bettor > 0 {
+            assert_market_error(
+                process(&mut scenario.context, &[bet(empty_receipt_root())], &[&predictor]).await,
+                MarketError::StaleReceiptRoot,
+            );
+        }
+        process(&mut scenario.context, &[bet(before.receipt_root)], &[&predictor]).await.unwrap();
+        let after = scenario.market().await;
+        let tokens_received = if side {
+            after.yes_tokens - before.yes_tokens
+        } else {
+            after.no_tokens - before.no_tokens
+        };
+        receipts.push(CompressedReceipt {
+            index: receipts.len() as u64,
+            owner: predictor.pubkey(),
+            prediction_type: side,
+            amount_deposited: STAKE,
+            tokens_received,
+        });
+    }
+    let leaves: Vec<_> = receipts.iter().map(|receipt| receipt.leaf(market_id)).collect();
+    let root = scenario.market().await.receipt_root;
+    for receipt in &receipts {
+        let proof = receipt_proof(&leaves, receipt.index);
+        let leaf = leaves[receipt.index as usize];
+        assert_eq!(receipt_root_from_proof(leaf, receipt.index, &proof), Some(root));
+    }
+
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+
+    let claim = |bettor: usize, receipt: CompressedReceipt, proof: Vec<[u8; 32]>| {
+        claim_compressed(&receipts[bettor].owner, market_id, receipt, proof)
+    };
+    let winner = scenario.bettors[0].insecure_clone();
+    let proof = receipt_proof(&leaves, 0);
+    let forgeries = [
+        // More tokens than were bought
+        CompressedReceipt { tokens_received: receipts[0].tokens_received + 1, ..receipts[0] },
+        // The same leaf claimed at another position, or one aliasing it
+        CompressedReceipt { index: 2, ..receipts[0] },
+        CompressedReceipt { index: 1 << RECEIPT_TREE_DEPTH, ..receipts[0] },
+    ];
+    for forged in forgeries {
+        assert_market_error(
+            process(&mut scenario.context, &[claim(0, forged, proof.clone())], &[&winner]).await,
+            MarketError::InvalidReceiptProof,
+        );
+    }
+    let truncated = proof[..RECEIPT_TREE_DEPTH - 1].to_vec();
+    assert_market_error(
+        process(&mut scenario.context, &[claim(0, receipts[0], truncated)], &[&winner]).await,
+        MarketError::InvalidReceiptProof,
+    );
+
+    // Someone else's receipt, whether signed for as is or rewritten to them
+    let loser = scenario.bettors[1].insecure_clone();
+    let stolen = claim_compressed(&loser.pubkey(), market_id, receipts[0], proof.clone());
+    assert_market_error(
+        process(&mut scenario.context, &[stolen], &[&loser]).await,
+        MarketError::Unauthorized,
+    );
+    let rewritten = CompressedReceipt { owner: loser.pubkey(), ..receipts[0] };
+    assert_market_error(
+        process(&mut scenario.context, &[claim(1, rewritten, proof.clone())], &[&loser]).await,
+        MarketError::InvalidReceiptProof,
+    );
+    let lost = claim(1, receipts[1], receipt_proof(&leaves, 1));
+    assert_market_error(
+        process(&mut scenario.context, &[lost], &[&loser]).await,
+        MarketError::PredictionLost,
+    );
+
+    let vault_before = scenario.vault_balance().await;
+    let paid = claim(0, receipts[0], proof.clone());
+    process(&mut scenario.context, &[paid], &[&winner]).await.unwrap();
+    let params = scenario.market().await.payout_params;
+    assert_eq!(
+        vault_before - scenario.vault_balance().await,
+        compute_payout(&params, RoundingPolicy::FavorPool, receipts[0].tokens_received)
+    );
+    assert_market_error(
+        process(&mut scenario.context, &[claim(0, receipts[0], proof)], &[&winner]).await,
+        MarketError::AlreadyClaimed,
+    );
+}
+
+#[tokio::test]
+async fn compressed_bets_are_held_to_the_same_limits() {
+    const MAX_BET: u64 = STAKE;
+    // A receipt can't be admitted against a participant cap
+    let capped = MarketScenario::new()
+        .options(MarketOptions {
+            compressed: true,
+            max_participants: 10,
+            ..MarketOptions::default()
+        })
+        .start()
+        .await;
+    assert_market_error(capped.map(|_| ()), MarketError::InvalidMarketOptions);
+
+    let mut scenario = MarketScenario::new()
+        .options(MarketOptions {
+            compressed: true,
+            max_bet: MAX_BET,
+            max_bet_ramp_secs: 1_000,
+            max_bet_ramp_start_bps: 1_000,
+            ..MarketOptions::default()
+        })
+        .idle_bettors(3, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let market_id = scenario.market_id;
+    let creator = scenario.creator.insecure_clone();
+    let open = init_receipt_tree(&creator.pubkey(), market_id);
+    process(&mut scenario.context, &[open], &[&creator]).await.unwrap();
+    scenario.add_house_account(1).await.unwrap();
+    let until = now(&mut scenario.context).await + 24 * 60 * 60;
+    scenario.self_exclude(2, until).await.unwrap();
+
+    let bettors: Vec<_> = scenario.bettors.iter().map(|b| b.insecure_clone()).collect();
+    let bet = |bettor: usize, amount: u64, root| {
+        let predictor = bettors[bettor].insecure_clone();
+        let ix = place_compressed_prediction(
+            &predictor.pubkey(),
+            &creator.pubkey(),
+            market_id,
+            true,
+            amount,
+            root,
+        );
+        (ix, predictor)
+    };
+    // Early in the ramp an ordinary bettor is held to a tenth of max_bet,
+    // the house isn't, and a self-excluded wallet can't bet at all
+    let root = scenario.market().await.receipt_root;
+    for (bettor, amount, error) in [
+        (0, MAX_BET / 10 + 1, MarketError::BetAboveCap),
+        (2, MAX_BET / 10, MarketError::SelfExcluded),
+    ] {
+        let (ix, predictor) = bet(bettor, amount, root);
+        assert_market_error(process(&mut scenario.context, &[ix], &[&predictor]).await, error);
+    }
+    let (ix, predictor) = bet(1, 3 * MAX_BET, root);
+    process(&mut scenario.context, &[ix], &[&predictor]).await.unwrap();
+    let root = scenario.market().await.receipt_root;
+    let (ix, predictor) = bet(0, MAX_BET / 10, root);
+    process(&mut scenario.context, &[ix], &[&predictor]).await.unwrap();
+    assert_eq!(scenario.market().await.receipt_count, 2);
+}
+
+#[tokio::test]
+async fn voided_compressed_receipts_refund_in_full() {
+    let mut scenario = MarketScenario::new()
+        .options(MarketOptions {
+            compressed: true,
+            ..MarketOptions::default()
+        })
+        .idle_bettors(2, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let market_id = scenario.market_id;
+    let creator = scenario.creator.insecure_clone();
+    let open = init_receipt_tree(&creator.pubkey(), market_id);
+    process(&mut scenario.context, &[open], &[&creator]).await.unwrap();
+
+    let mut receipts = Vec::new();
+    for (bettor, side) in [(0, true), (1, false)] {
+        let predictor = scenario.bettors[bettor].insecure_clone();
+        let before = scenario.market().await;
+        let bet = place_compressed_prediction(
+            &predictor.pubkey(),
+            &creator.pubkey(),
+            market_id,
+            side,
+            STAKE,
+            before.receipt_root,
+        );
+        process(&mut scenario.context, &[bet], &[&predictor]).await.unwrap();
+        let after = scenario.market().await;
+        receipts.push(CompressedReceipt {
+            index: bettor as u64,
+            owner: predictor.pubkey(),
+            prediction_type: side,
+            amount_deposited: STAKE,
+            tokens_received: (after.yes_tokens + after.no_tokens)
+                - (before.yes_tokens + before.no_tokens),
+        });
+    }
+    let leaves: Vec<_> = receipts.iter().map(|receipt| receipt.leaf(market_id)).collect();
+
+    // The fees go back into the refunds; the admin covers the cuts that
+    // never reached the vault
+    scenario.cancel().await.unwrap();
+    let market = scenario.market().await;
+    assert_eq!(market.fee_collected, 0);
+    let shortfall = market.liabilities().saturating_sub(scenario.vault_balance().await);
+    if shortfall > 0 {
+        scenario.top_up(shortfall).await.unwrap();
+    }
+
+    for receipt in receipts {
+        let owner = scenario.bettors[receipt.index as usize].insecure_clone();
+        let refund = receipt.amount_deposited;
+        let vault_before = scenario.vault_balance().await;
+        let proof = receipt_proof(&leaves, receipt.index);
+        let claim = claim_compressed(&owner.pubkey(), market_id, receipt, proof);
+        process(&mut scenario.context, &[claim], &[&owner]).await.unwrap();
+        assert_eq!(vault_before - scenario.vault_balance().await, refund);
+        let market = scenario.market().await;
+        assert!(scenario.vault_balance().await >= market.liabilities());
+    }
+}
+
+#[tokio::test]
+async fn only_the_upgrade_authority_initializes_the_config() {
+    let mut context = boot().await;
+    let deployer = context.payer.insecure_clone();
+    let squatter = Keypair::new();
+    let fund = system_instruction::transfer(&deployer.pubkey(), &squatter.pubkey(), 1_000_000_000);
+    process(&mut context, &[fund], &[]).await.unwrap();
+
+    let init = |admin: &Keypair| initialize_config(&admin.pubkey(), ConfigParams::default());
+    assert_market_error(
+        process(&mut context, &[init(&squatter)], &[&squatter]).await,
+        MarketError::Unauthorized,
+    );
+    // An immutable program has no upgrade authority to claim it either
+    set_upgrade_authority(&mut context, None);
+    assert_market_error(
+        process(&mut context, &[init(&deployer)], &[]).await,
+        MarketError::Unauthorized,
+    );
+
+    set_upgrade_authority(&mut context, Some(&deployer.pubkey()));
+    process(&mut context, &[init(&deployer)], &[]).await.unwrap();
+    let config = fetch::<Config>(&mut context, config_pda()).await.unwrap();
+    assert_eq!(config.admin, deployer.pubkey());
+}
+
+#[tokio::test]
+async fn admin_multisig_signs_admin_instructions_and_hands_over() {
+    let mut scenario = MarketScenario::new().start().await.unwrap();
+    let admin = scenario.admin.insecure_clone();
+    let owners: Vec<Keypair> = (0..3).map(|_| Keypair::new()).collect();
+    let keys: Vec<Pubkey> = owners.iter().map(|owner| owner.pubkey()).collect();
+    let signer = multisig_signer_pda(0);
+    let payer = scenario.context.payer.pubkey();
+    let funding: Vec<_> = keys
+        .iter()
+        .chain([&signer])
+        .map(|key| system_instruction::transfer(&payer, key, 1_000_000_000))
+        .collect();
+    process(&mut scenario.context, &funding, &[]).await.unwrap();
+    let create = create_admin_multisig(&admin.pubkey(), 0, keys.clone(), 2);
+    process(&mut scenario.context, &[create], &[&admin]).await.unwrap();
+    let config = fetch::<Config>(&mut scenario.context, config_pda()).await.unwrap();
+    assert_eq!(config.admin, signer);
+
+    // The multisig signer creates accounts as the admin, paying for them
+    let creator = Pubkey::new_unique();
+    let approve = approve_creator(&signer, creator);
+    let propose = create_admin_tx(&keys[0], 0, 0, invoke_action(&approve));
+    process(&mut scenario.context, &[propose], &[&owners[0]]).await.unwrap();
+    let execute = execute_admin_tx(&keys[0], 0, 0, Some(&approve));
+    assert_market_error(
+        process(&mut scenario.context, std::slice::from_ref(&execute), &[&owners[0]]).await,
+        MarketError::ThresholdNotMet,
+    );
+    let second = approve_admin_tx(&keys[1], 0, 0);
+    process(&mut scenario.context, &[second], &[&owners[1]]).await.unwrap();
+    process(&mut scenario.context, &[execute], &[&owners[0]]).await.unwrap();
+    let allow = fetch::<CreatorAllow>(&mut scenario.context, creator_allow_pda(&creator));
+    assert_eq!(allow.await.unwrap().creator, creator);
+
+    // A replacement multisig, under the next nonce, takes over
+    let replace = create_admin_multisig(&signer, 1, vec![keys[2]], 1);
+    let propose = create_admin_tx(&keys[1], 0, 1, invoke_action(&replace));
+    let second = approve_admin_tx(&keys[2], 0, 1);
+    let execute = execute_admin_tx(&keys[1], 0, 1, Some(&replace));
+    process(&mut scenario.context, &[propose, second, execute], &[&owners[1], &owners[2]])
+        .await
+        .unwrap();
+    let config = fetch::<Config>(&mut scenario.context, config_pda()).await.unwrap();
+    assert_eq!(config.admin, multisig_signer_pda(1));
+
+    // The first multisig no longer holds the role, whatever it approves
+    let approve = approve_creator(&signer, Pubkey::new_unique());
+    let propose = create_admin_tx(&keys[0], 0, 2, invoke_action(&approve));
+    let second = approve_admin_tx(&keys[1], 0, 2);
+    process(&mut scenario.context, &[propose, second], &[&owners[0], &owners[1]])
+        .await
+        .unwrap();
+    let execute = execute_admin_tx(&keys[0], 0, 2, Some(&approve));
+    assert_market_error(
+        process(&mut scenario.context, &[execute], &[&owners[0]]).await,
+        MarketError::Unauthorized,
+    );
+}
+
+#[tokio::test]
+async fn creation_limit_resets_at_utc_midnight() {
+    let mut scenario = MarketScenario::new()
+        .config(ConfigParams {
+            min_market_duration: 0,
+            max_markets_per_day: 2,
+            ..ConfigParams::default()
+        })
+        .start()
+        .await
+        .unwrap();
+    // A day after the scenario's own market, so the count starts empty
+    let start = now(&mut scenario.context).await;
+    let midnight = (start.div_euclid(CREATION_DAY_SECS) + 2) * CREATION_DAY_SECS;
+    let closes = midnight + 3_600;
+
+    warp_to_timestamp(&mut scenario.context, midnight - 60).await;
+    for question in ["Late one", "Late two"] {
+        scenario.create_market(question, closes, MarketOptions::default()).await.unwrap();
+    }
+    let late = scenario.create_market("Late three", closes, MarketOptions::default());
+    assert_market_error(late.await.map(|_| ()), MarketError::CreationRateLimited);
+
+    // The next UTC day counts afresh, without any crank
+    warp_to_timestamp(&mut scenario.context, midnight).await;
+    for question in ["Early one", "Early two"] {
+        scenario.create_market(question, closes, MarketOptions::default()).await.unwrap();
+    }
+    let early = scenario.create_market("Early three", closes, MarketOptions::default());
+    assert_market_error(early.await.map(|_| ()), MarketError::CreationRateLimited);
+
+    let stats_pda = creator_stats_pda(&scenario.creator.pubkey());
+    let stats = fetch::<CreatorStats>(&mut scenario.context, stats_pda).await.unwrap();
+    assert_eq!(stats.day_bucket, midnight / CREATION_DAY_SECS);
+    assert_eq!(stats.markets_created_today, 2);
+}
+
+#[tokio::test]
+async fn create_and_predict_matches_create_then_bet() {
+    let mut scenario = MarketScenario::new().start().await.unwrap();
+    let creator = scenario.creator.insecure_clone();
+    let at = now(&mut scenario.context).await + 60;
+    let closes = at + 3_600;
+
+    // The creator's next index page and the config's epoch, as a bet needs
+    async fn bet_pages(scenario: &mut Scenario, creator: &Pubkey) -> (u32, u64) {
+        let index_page = fetch::<UserProfile>(&mut scenario.context, profile_pda(creator))
+            .await
+            .map(|profile| profile.next_index_page())
+            .unwrap_or(0);
+        let config = fetch::<Config>(&mut scenario.context, config_pda()).await.unwrap();
+        (index_page, config.current_epoch)
+    }
+
+    // Two transactions, with nothing in between
+    warp_to_timestamp(&mut scenario.context, at).await;
+    let options = MarketOptions::default();
+    let two_step = scenario.create_market("Two step", closes, options).await.unwrap();
+    let (index_page, epoch) = bet_pages(&mut scenario, &creator.pubkey()).await;
+    let bet = place_prediction(
+        &creator.pubkey(),
+        &creator.pubkey(),
+        two_step,
+        true,
+        STAKE,
+        index_page,
+        epoch,
+    );
+    process(&mut scenario.context, &[bet], &[&creator]).await.unwrap();
+
+    // One instruction, at the same moment
+    warp_to_timestamp(&mut scenario.context, at).await;
+    let one_step = fetch::<MarketCounter>(&mut scenario.context, counter_pda())
+        .await
+        .unwrap()
+        .next_id;
+    let config = fetch::<Config>(&mut scenario.context, config_pda()).await.unwrap();
+    let (index_page, epoch) = bet_pages(&mut scenario, &creator.pubkey()).await;
+    let payer = scenario.context.payer.pubkey();
+    let instructions = [
+        system_instruction::transfer(&payer, &creator.pubkey(), 2_000_000_000 + STAKE),
+        initialize_market_and_predict(
+            &creator.pubkey(),
+            one_step,
+            config.next_registry_page(),
+            "One step",
+            closes,
+            1_000_000_000,
+            true,
+            STAKE,
+            index_page,
+            epoch,
+        ),
+    ];
+    process(&mut scenario.context, &instructions, &[&creator]).await.unwrap();
+
+    let mut markets = Vec::new();
+    let mut predictions = Vec::new();
+    let mut vaults = Vec::new();
+    for market_id in [two_step, one_step] {
+        scenario.market_id = market_id;
+        markets.push(scenario.market().await);
+        let prediction = prediction_pda(market_id, &creator.pubkey());
+        predictions.push(fetch::<Prediction>(&mut scenario.context, prediction).await.unwrap());
+        vaults.push(scenario.balance(vault_pda(market_id)).await);
+    }
+    let market_state = |m: &Market| {
+        (
+            (m.yes_pool, m.no_pool, m.fee_collected, m.total_volume),
+            (m.trade_count, m.unique_bettors, m.yes_positions, m.no_positions),
+            (m.sequence, m.event_seq, m.creator_liability),
+            (m.created_at, m.last_trade_at),
+        )
+    };
+    let prediction_state = |p: &Prediction| {
+        (
+            (p.prediction_type, p.amount_deposited, p.tokens_received, p.cancel_refund),
+            (p.entry_price_bps, p.entry_probability_bps, p.trade_sequence),
+            (p.yes_pool_at_entry, p.no_pool_at_entry, p.created_at),
+        )
+    };
+    assert_eq!(market_state(&markets[0]), market_state(&markets[1]));
+    assert_eq!(prediction_state(&predictions[0]), prediction_state(&predictions[1]));
+    assert_eq!(vaults[0], vaults[1]);
+    assert!(markets[1].fee_collected > 0);
+}
+
+#[tokio::test]
+async fn resolution_waits_for_a_top_up_when_the_vault_is_short() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+
+    // Lamports leave the vault behind the program's back, down to its rent
+    let vault = vault_pda(scenario.market_id);
+    let shortfall = scenario.vault_balance().await;
+    let mut account = scenario.context.banks_client.get_account(vault).await.unwrap().unwrap();
+    account.lamports -= shortfall;
+    scenario.context.set_account(&vault, &account.into());
+
+    scenario.warp_to_resolution().await;
+    assert_market_error(scenario.resolve(true).await, MarketError::ResolutionWouldBeInsolvent);
+    assert!(!scenario.market().await.resolved);
+
+    // Only the config admin may top up
+    let payer = scenario.context.payer.pubkey();
+    let admin = scenario.admin.pubkey();
+    let outsider = top_up_vault(&payer, scenario.market_id, shortfall);
+    assert_market_error(
+        process(&mut scenario.context, &[outsider], &[]).await,
+        MarketError::Unauthorized,
+    );
+    let funding = system_instruction::transfer(&payer, &admin, shortfall);
+    process(&mut scenario.context, &[funding], &[]).await.unwrap();
+    scenario.top_up(shortfall).await.unwrap();
+
+    scenario.resolve(true).await.unwrap();
+    let market = scenario.market().await;
+    assert_eq!(market.outcome, MarketOutcome::Yes);
+    assert!(scenario.vault_balance().await >= market.liabilities());
+    scenario.claim(0).await.unwrap();
+    assert!(scenario.prediction(0).await.claimed);
+}
+
+#[tokio::test]
+async fn voiding_refunds_deposits_in_full_and_keeps_the_fees() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(true, 3 * STAKE)
+        .bet(false, 2 * STAKE)
+        .start()
+        .await
+        .unwrap();
+
+    let fees = scenario.market().await.fee_collected;
+    assert!(fees > 0);
+    scenario.cancel().await.unwrap();
+    let market = scenario.market().await;
+    assert_eq!(market.outcome, MarketOutcome::Void);
+    assert_eq!(market.fee_collected, 0);
+
+    // The creator can't take back fees the refunds now need
+    assert_market_error(scenario.withdraw_fees(fees).await, MarketError::FeesRefundable);
+
+    // The admin covers the cuts that never reached the vault
+    let shortfall = market.liabilities().saturating_sub(scenario.vault_balance().await);
+    if shortfall > 0 {
+        scenario.top_up(shortfall).await.unwrap();
+    }
+    assert!(scenario.vault_balance().await >= market.liabilities());
+
+    // Each bettor gets back exactly what they deposited
+    for bettor in 0..scenario.bettors.len() {
+        let owner = scenario.bettors[bettor].pubkey();
+        let deposited = scenario.prediction(bettor).await.amount_deposited;
+        let before = scenario.balance(owner).await;
+        scenario.refund(bettor).await.unwrap();
+        assert_eq!(scenario.balance(owner).await - before, deposited);
+        let market = scenario.market().await;
+        assert!(scenario.vault_balance().await >= market.liabilities());
+    }
+}
+
+/// What place_prediction may take, leaving room beside ATA creation and a
+/// priority-fee instruction at peak
+const PLACE_PREDICTION_CU_BUDGET: u64 = 25_000;
+
+#[tokio::test]
+async fn place_prediction_fits_its_compute_budget() {
+    let options = MarketOptions {
+        cooloff_secs: 60 * 60,
+        ..MarketOptions::default()
+    };
+    let mut scenario = MarketScenario::new()
+        .options(options)
+        .bet(true, STAKE)
+        .idle_bettors(1, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let bettor = scenario.bettors[1].insecure_clone();
+
+    // A returning bettor, whose profile, index page and epoch stats exist.
+    // Only the SBF build meters the program itself; run natively, this
+    // sees the CPIs alone
+    scenario.place_prediction(1, false, STAKE).await.unwrap();
+    scenario.cancel_prediction(1).await.unwrap();
+    let bet = scenario.place_prediction_instruction(1, false, STAKE).await;
+    let bet = std::slice::from_ref(&bet);
+    let units = compute_units(&mut scenario.context, bet, &[&bettor]).await.unwrap();
+    assert!(units <= PLACE_PREDICTION_CU_BUDGET, "place_prediction took {units} CU");
+    process(&mut scenario.context, bet, &[&bettor]).await.unwrap();
+}
+
+#[tokio::test]
+async fn participant_cap_admits_the_first_100_wallets() {
+    const BET: u64 = 10_000_000;
+    let options = MarketOptions {
+        max_participants: 100,
+        cooloff_secs: 60 * 60,
+        ..MarketOptions::default()
+    };
+    let mut scenario = MarketScenario::new()
+        .options(options)
+        .idle_bettors(101, 1_000_000_000)
+        .start()
+        .await
+        .unwrap();
+
+    // The first wallet backs out of its bet but keeps its place
+    scenario.place_prediction(0, true, BET).await.unwrap();
+    scenario.cancel_prediction(0).await.unwrap();
+    for bettor in 1..100 {
+        scenario.place_prediction(bettor, bettor % 2 == 0, BET).await.unwrap();
+    }
+    assert_eq!(scenario.market().await.participants, 100);
+
+    assert_market_error(
+        scenario.place_prediction(100, true, BET).await,
+        MarketError::MarketFull,
+    );
+
+    // A wallet already in still bets once the market is full
+    scenario.place_prediction(0, false, BET).await.unwrap();
+    let market = scenario.market().await;
+    assert_eq!(market.participants, 100);
+    assert_eq!(market.unique_bettors, 100);
+}
+
+#[tokio::test]
+async fn tips_survive_every_claim_and_go_to_the_creator() {
+    const TIP: u64 = 300_000_000;
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(true, 3 * STAKE)
+        .bet(false, 2 * STAKE)
+        .start()
+        .await
+        .unwrap();
+
+    scenario.tip(TIP / 3).await.unwrap();
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+    scenario.tip(TIP - TIP / 3).await.unwrap();
+    scenario.claim(0).await.unwrap();
+    scenario.claim(1).await.unwrap();
+
+    // Winners were paid from the pot alone; the tips are still held
+    let market = scenario.market().await;
+    assert!(market.fully_settled);
+    assert_eq!(market.outstanding_entitlement, 0);
+    assert_eq!(market.creator_tips, TIP);
+    assert!(scenario.vault_balance().await >= market.liabilities());
+    assert!(scenario.vault_balance().await - market.fee_collected >= TIP);
+
+    // Only the creator may take them, and only once
+    let payer = scenario.context.payer.pubkey();
+    let outsider = withdraw_tips(&payer, scenario.market_id);
+    assert_market_error(
+        process(&mut scenario.context, &[outsider], &[]).await,
+        MarketError::Unauthorized,
+    );
+    let creator = scenario.creator.pubkey();
+    let before = scenario.balance(creator).await;
+    scenario.withdraw_tips().await.unwrap();
+    assert_eq!(scenario.balance(creator).await, before + TIP);
+    assert_eq!(scenario.market().await.creator_tips, 0);
+    assert_market_error(scenario.withdraw_tips().await, MarketError::NoTips);
+}
+
+#[tokio::test]
+async fn exposure_nets_open_won_and_lost_positions() {
+    let mut scenario = MarketScenario::new()
+        .idle_bettors(2, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let won = scenario.market_id;
+    let time = scenario.resolution_time;
+    let lost = scenario
+        .create_market("Will the second market resolve YES?", time, MarketOptions::default())
+        .await
+        .unwrap();
+    let open = scenario
+        .create_market("Will the third market resolve YES?", time + 3600, MarketOptions::default())
+        .await
+        .unwrap();
+
+    // Bettor 0 backs YES on the won and open markets and NO on the lost
+    // one; bettor 1 takes the other side of each
+    for (market_id, side) in [(won, true), (lost, false), (open, true)] {
+        scenario.market_id = market_id;
+        scenario.place_prediction(0, side, STAKE).await.unwrap();
+        scenario.place_prediction(1, !side, STAKE).await.unwrap();
+    }
+    scenario.warp_to_resolution().await;
+    for market_id in [won, lost] {
+        scenario.market_id = market_id;
+        scenario.resolve(true).await.unwrap();
+    }
+    scenario.market_id = won;
+    scenario.claim(0).await.unwrap();
+
+    let wallet = scenario.bettors[0].pubkey();
+    let other = scenario.bettors[1].pubkey();
+    let position = |market_id: u64, owner: &Pubkey| {
+        (prediction_pda(market_id, owner), market_pda(market_id))
+    };
+    let positions = [
+        position(won, &wallet),
+        position(lost, &wallet),
+        position(open, &wallet),
+        // Someone else's position, and a position paired with the wrong market
+        position(open, &other),
+        (prediction_pda(open, &wallet), market_pda(won)),
+    ];
+    let exposure: Exposure =
+        view(&mut scenario.context, compute_exposure(&wallet, &positions)).await.unwrap();
+
+    // Marked by hand: the YES tokens' payout at the live pools, times the
+    // implied YES probability
+    scenario.market_id = open;
+    let market = scenario.market().await;
+    let prediction = scenario.prediction(0).await;
+    let total = (market.yes_pool + market.no_pool + market.sponsored_amount) as u128;
+    let payout = prediction.tokens_received as u128 * total / market.yes_pool as u128;
+    let probability = market.yes_pool as u128 * 10_000 / (market.yes_pool + market.no_pool) as u128;
+    let mark = (payout * probability / 10_000) as u64;
+    let profile: UserProfile = fetch(&mut scenario.context, profile_pda(&wallet)).await.unwrap();
+    assert!(profile.total_won > 0);
+
+    assert_eq!(exposure.wallet, wallet);
+    assert_eq!(exposure.open_positions, 1);
+    assert_eq!(exposure.skipped, 2);
+    assert_eq!(exposure.flagged, vec![3, 4]);
+    assert_eq!(exposure.total_deposited, STAKE);
+    assert_eq!(exposure.mark_value, mark);
+    assert_eq!(exposure.unrealized_pnl, mark as i64 - STAKE as i64);
+    assert_eq!(exposure.realized_pnl, profile.total_won as i64 - 2 * STAKE as i64);
+}
+
+#[tokio::test]
+async fn closing_archives_the_final_state() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(true, 2 * STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+    let market_id = scenario.market_id;
+    let creator = scenario.creator.insecure_clone();
+    scenario.cancel().await.unwrap();
+    // The admin covers the cuts that never reached the vault
+    let market = scenario.market().await;
+    let shortfall = market.liabilities().saturating_sub(scenario.vault_balance().await);
+    scenario.top_up(shortfall).await.unwrap();
+    scenario.refund(0).await.unwrap();
+
+    // Not while a bettor has yet to take a refund
+    let close = close_market(&creator.pubkey(), market_id, 0);
+    assert_market_error(
+        process(&mut scenario.context, std::slice::from_ref(&close), &[&creator]).await,
+        MarketError::MarketNotSettled,
+    );
+    for bettor in 1..3 {
+        scenario.refund(bettor).await.unwrap();
+    }
+    let liquidity = refund_liquidity(&creator.pubkey(), market_id);
+    process(&mut scenario.context, &[liquidity], &[&creator]).await.unwrap();
+    let bettors: Vec<Keypair> = scenario.bettors.iter().map(Keypair::insecure_clone).collect();
+    for bettor in bettors {
+        let closed = close_prediction(&bettor.pubkey(), market_id);
+        process(&mut scenario.context, &[closed], &[&bettor]).await.unwrap();
+    }
+    let market = scenario.market().await;
+    process(&mut scenario.context, &[close], &[&creator]).await.unwrap();
+
+    // The hash, rebuilt byte by byte from the market's final state
+    let snapshot = market.resolution_snapshot;
+    let params = market.payout_params;
+    let snapshot_hash = keccak::hashv(&[
+        &snapshot.yes_pool.to_le_bytes(),
+        &snapshot.no_pool.to_le_bytes(),
+        &snapshot.sponsored_amount.to_le_bytes(),
+        &snapshot.yes_deposits.to_le_bytes(),
+        &snapshot.no_deposits.to_le_bytes(),
+        &snapshot.fee_collected.to_le_bytes(),
+        &snapshot.vault_lamports.to_le_bytes(),
+        &snapshot.implied_yes_probability_bps.to_le_bytes(),
+        &snapshot.taken_at.to_le_bytes(),
+    ]);
+    let mut bytes = market_id.to_le_bytes().to_vec();
+    bytes.extend_from_slice(&market.generation.to_le_bytes());
+    bytes.push(3); // MarketOutcome::Void
+    bytes.extend_from_slice(snapshot_hash.as_ref());
+    bytes.extend_from_slice(&params.published_at.to_le_bytes());
+    bytes.extend_from_slice(&[params.winning_side as u8, params.fixed_odds as u8]);
+    for value in [params.winning_pool, params.total_pot] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.extend_from_slice(&params.winnings_fee_bps.to_le_bytes());
+    for value in [
+        params.covered,
+        params.owed,
+        market.fee_collected,
+        market.rebate_pot,
+        market.rebate_paid,
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [market.claims_count, market.eligible_claims, market.eligible_claimed] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [
+        market.total_claimed,
+        market.rounding_dust_accrued,
+        market.dust_swept,
+        market.escheated,
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    let expected = keccak::hash(&bytes).to_bytes();
+
+    let archive: MarketArchive =
+        fetch(&mut scenario.context, archive_pda(market_id, 0)).await.unwrap();
+    assert_eq!(archive.market_id, market_id);
+    assert_eq!(archive.generation, 0);
+    assert_eq!(archive.outcome, MarketOutcome::Void);
+    assert_eq!(archive.hash, expected);
+    assert_eq!(compute_archive_hash(&market), expected);
+    assert_eq!(scenario.balance(market_pda(market_id)).await, 0);
+}
+
+#[test]
+fn normalized_amounts_round_trip_for_6_8_and_9_decimals() {
+    for decimals in [6u8, 8, 9] {
+        let scale = 10u64.pow((NORMALIZED_DECIMALS - decimals) as u32);
+        for amount in [0, 1, 12_345_678, u64::MAX / scale] {
+            let normalized = normalized_amount(amount, decimals);
+            assert_eq!(normalized, amount * scale);
+            assert_eq!(denormalized_amount(normalized, decimals), amount);
+        }
+        // Past the largest exact amount the normalized value saturates
+        if scale > 1 {
+            assert_eq!(normalized_amount(u64::MAX, decimals), u64::MAX);
+        }
+    }
+    // One whole unit of any collateral is one whole unit normalized
+    assert_eq!(normalized_amount(1_000_000, 6), 10u64.pow(NORMALIZED_DECIMALS as u32));
+    assert_eq!(normalized_amount(100_000_000, 8), 10u64.pow(NORMALIZED_DECIMALS as u32));
+}
+
+#[test]
+fn normalize_question_lowercases_trims_and_collapses_whitespace() {
+    assert_eq!(normalize_question("Will BTC Close Above $100K?"), "will btc close above $100k?");
+    assert_eq!(normalize_question("  \t will it rain?\n "), "will it rain?");
+    assert_eq!(normalize_question("will  it\t\train \n today?"), "will it rain today?");
+    assert_eq!(normalize_question(""), "");
+    assert_eq!(normalize_question(" \t\n "), "");
+    // Spellings that normalize alike share a registry key
+    assert_eq!(question_hash("Will it  RAIN?", 0), question_hash(" will it rain? ", 0));
+}
+
+#[test]
+fn idle_share_is_capped_by_config_validation() {
+    let params = |max_idle_bps| ConfigParams {
+        yield_adapter: Pubkey::new_unique(),
+        max_idle_bps,
+        ..ConfigParams::default()
+    };
+    assert!(params(MAX_IDLE_BPS).validate().is_ok());
+    assert!(params(MAX_IDLE_BPS + 1).validate().is_err());
+}
+
+#[tokio::test]
+async fn order_book_fills_to_capacity_and_reuses_freed_slots() {
+    const ORDER: u64 = 1_000_000;
+    let mut scenario = MarketScenario::new()
+        .idle_bettors(1, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let market_id = scenario.market_id;
+    let payer = scenario.context.payer.pubkey();
+    let trader = scenario.bettors[0].insecure_clone();
+    let owner = trader.pubkey();
+    process(&mut scenario.context, &[initialize_order_book(&payer, market_id)], &[])
+        .await
+        .unwrap();
+    let load = |data: &[u8]| *bytemuck::from_bytes::<OrderBook>(&data[8..]);
+
+    // Every slot costs the same to fill, whatever is already resting. Only
+    // the SBF build meters the program itself; run natively, this sees the
+    // CPIs alone
+    let mut costs = Vec::new();
+    for i in 0..ORDER_BOOK_CAPACITY as u16 {
+        let post = post_order(&owner, market_id, i % 2 == 0, 4_000 + i, ORDER);
+        let post = std::slice::from_ref(&post);
+        costs.push(compute_units(&mut scenario.context, post, &[&trader]).await.unwrap());
+        process(&mut scenario.context, post, &[&trader]).await.unwrap();
+    }
+    assert!(costs[costs.len() - 1].abs_diff(costs[0]) < 500, "{costs:?}");
+
+    let address = order_book_pda(market_id);
+    let account = scenario.context.banks_client.get_account(address).await.unwrap().unwrap();
+    let book = load(&account.data);
+    assert_eq!(book.count as usize, ORDER_BOOK_CAPACITY);
+    assert_eq!(book.free_head, ORDER_SLOT_NONE);
+    let post = post_order(&owner, market_id, true, 5_000, ORDER);
+    assert_market_error(
+        process(&mut scenario.context, &[post], &[&trader]).await,
+        MarketError::OrderBookFull,
+    );
+
+    let before = scenario.balance(owner).await;
+    for slot in [17, 42] {
+        process(&mut scenario.context, &[cancel_order(&owner, market_id, slot)], &[&trader])
+            .await
+            .unwrap();
+    }
+    assert_eq!(scenario.balance(owner).await, before + 2 * ORDER);
+    assert_market_error(
+        process(&mut scenario.context, &[cancel_order(&owner, market_id, 42)], &[&trader]).await,
+        MarketError::InvalidOrderSlot,
+    );
+
+    // Freed slots come back most recently freed first
+    for price_bps in [6_100, 6_200] {
+        let post = post_order(&owner, market_id, true, price_bps, ORDER);
+        process(&mut scenario.context, &[post], &[&trader]).await.unwrap();
+    }
+    let account = scenario.context.banks_client.get_account(address).await.unwrap().unwrap();
+    let book = load(&account.data);
+    assert_eq!(book.count as usize, ORDER_BOOK_CAPACITY);
+    assert_eq!(book.orders[42].price_bps, 6_100);
+    assert_eq!(book.orders[17].price_bps, 6_200);
+    assert_eq!(book.free_head, ORDER_SLOT_NONE);
+}
+
+#[tokio::test]
+async fn market_maker_rebates_track_fills_against_its_orders() {
+    const ORDER: u64 = 2_000_000_000;
+    const PRICE_BPS: u16 = 4_000;
+    const REBATE_BPS: u16 = 2_500;
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .idle_bettors(3, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+    let market_id = scenario.market_id;
+    let payer = scenario.context.payer.pubkey();
+    let admin = scenario.admin.insecure_clone();
+    let maker = scenario.bettors[2].insecure_clone();
+    let takers = [scenario.bettors[3].insecure_clone(), scenario.bettors[4].insecure_clone()];
+    process(&mut scenario.context, &[initialize_order_book(&payer, market_id)], &[])
+        .await
+        .unwrap();
+
+    // Only the config admin approves market makers
+    let approve = |signer: &Keypair| {
+        approve_market_maker(&signer.pubkey(), &maker.pubkey(), REBATE_BPS)
+    };
+    assert_market_error(
+        process(&mut scenario.context, &[approve(&maker)], &[&maker]).await,
+        MarketError::Unauthorized,
+    );
+    process(&mut scenario.context, &[approve(&admin)], &[&admin]).await.unwrap();
+    let post = post_order(&maker.pubkey(), market_id, true, PRICE_BPS, ORDER);
+    process(&mut scenario.context, &[post], &[&maker]).await.unwrap();
+
+    // A taker can't dodge the rebate by naming some other registration
+    let mut dodge = fill_order(&takers[0].pubkey(), &maker.pubkey(), market_id, 0, ORDER, 0);
+    dodge.accounts[5].pubkey = market_maker_pda(&takers[0].pubkey());
+    assert_market_error(
+        process(&mut scenario.context, &[dodge], &[&takers[0]]).await,
+        MarketError::MarketMakerMismatch,
+    );
+
+    // Two takers fill the order in three pieces; each fill rebates its
+    // share of that fill's fee, and the market keeps the rest
+    let config: Config = fetch(&mut scenario.context, config_pda()).await.unwrap();
+    let market = scenario.market().await;
+    let (fee_bps, rounding) =
+        (market.fee_bps.unwrap_or(config.trading_fee_bps), market.rounding_policy);
+    let fills = [(0, ORDER / 2, 0), (1, ORDER / 4, 0), (0, ORDER / 4, 1)];
+    let fees_before = scenario.market().await.fee_collected;
+    let (mut rebates, mut kept, mut pot) = (0, 0, 0);
+    for (taker, amount, fill_id) in fills {
+        let signer = &takers[taker];
+        let before = scenario.market().await.fee_collected;
+        let fill = fill_order(&signer.pubkey(), &maker.pubkey(), market_id, 0, amount, fill_id);
+        process(&mut scenario.context, &[fill], &[signer]).await.unwrap();
+
+        let taker_stake = amount * (10_000 - PRICE_BPS as u64) / PRICE_BPS as u64;
+        let fee = rounding.charge_bps(taker_stake, fee_bps);
+        let rebate = rounding.payout_bps(fee, REBATE_BPS);
+        assert!(rebate > 0 && rebate < fee);
+        assert_eq!(scenario.market().await.fee_collected - before, fee - rebate);
+        let registration: MarketMakerRegistration =
+            fetch(&mut scenario.context, market_maker_pda(&maker.pubkey())).await.unwrap();
+        rebates += rebate;
+        kept += fee - rebate;
+        pot += amount + taker_stake;
+        assert_eq!(registration.rebates_accrued, rebates);
+    }
+    assert_eq!(scenario.market().await.fee_collected - fees_before, kept);
+    let address = order_book_pda(market_id);
+    let account = scenario.context.banks_client.get_account(address).await.unwrap().unwrap();
+    assert_eq!(bytemuck::from_bytes::<OrderBook>(&account.data[8..]).count, 0);
+
+    // The maker claims every rebate at once, and only once
+    let before = scenario.balance(maker.pubkey()).await;
+    let claim = claim_mm_rebates(&maker.pubkey());
+    process(&mut scenario.context, std::slice::from_ref(&claim), &[&maker]).await.unwrap();
+    assert_eq!(scenario.balance(maker.pubkey()).await - before, rebates);
+    assert_market_error(
+        process(&mut scenario.context, &[claim], &[&maker]).await,
+        MarketError::NoRebate,
+    );
+
+    // Like a bet, a fill waits out a protocol pause
+    let pause = pause_protocol(&admin.pubkey());
+    process(&mut scenario.context, &[pause], &[&admin]).await.unwrap();
+    let fill = fill_order(&takers[1].pubkey(), &maker.pubkey(), market_id, 0, ORDER, 1);
+    assert_market_error(
+        process(&mut scenario.context, &[fill], &[&takers[1]]).await,
+        MarketError::ProtocolPaused,
+    );
+
+    // YES wins, so each fill's whole pot goes to the maker
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+    let before = scenario.balance(maker.pubkey()).await;
+    for (taker, _, fill_id) in fills {
+        let taker = takers[taker].pubkey();
+        let settle = settle_fill(&maker.pubkey(), &taker, market_id, fill_id);
+        process(&mut scenario.context, &[settle], &[]).await.unwrap();
+        assert_eq!(scenario.balance(fill_pda(market_id, &taker, fill_id)).await, 0);
+    }
+    assert_eq!(scenario.balance(maker.pubkey()).await - before, pot);
+}
+
+/// Create the scenario's market anew under its explicit ID, the payer
+/// funding the seed, and return its generation
+async fn create_with_id(scenario: &mut Scenario, question: &str) -> u64 {
+    let creator = scenario.creator.insecure_clone();
+    let registry_page = fetch::<Config>(&mut scenario.context, config_pda())
+        .await
+        .unwrap()
+        .next_registry_page();
+    let resolution_time = now(&mut scenario.context).await + 60 * 60;
+    let instructions = [
+        system_instruction::transfer(
+            &scenario.context.payer.pubkey(),
+            &creator.pubkey(),
+            2_000_000_000,
+        ),
+        initialize_market_with_id(
+            &creator.pubkey(),
+            scenario.market_id,
+            registry_page,
+            question,
+            resolution_time,
+            1_000_000_000,
+            5_000,
+            MarketOptions::default(),
+        ),
+    ];
+    process(&mut scenario.context, &instructions, &[&creator]).await.unwrap();
+    scenario.resolution_time = resolution_time;
+    scenario.market().await.generation
+}
+
+/// Bettor 0's STAKE on YES, in the market's `generation`
+async fn bet_at(
+    scenario: &mut Scenario,
+    generation: u64,
+) -> std::result::Result<(), BanksClientError> {
+    let bettor = scenario.bettors[0].insecure_clone();
+    let index_page = fetch::<UserProfile>(&mut scenario.context, profile_pda(&bettor.pubkey()))
+        .await
+        .map(|profile| profile.next_index_page())
+        .unwrap_or(0);
+    let epoch = fetch::<Config>(&mut scenario.context, config_pda())
+        .await
+        .unwrap()
+        .current_epoch;
+    let bet = place_prediction(
+        &bettor.pubkey(),
+        &scenario.creator.pubkey(),
+        scenario.market_id,
+        true,
+        STAKE,
+        index_page,
+        epoch,
+    );
+    let bet = at_generation(bet, scenario.market_id, &bettor.pubkey(), generation);
+    process(&mut scenario.context, &[bet], &[&bettor]).await
+}
+
+#[tokio::test]
+async fn closed_explicit_id_reopens_under_a_new_generation() {
+    let mut scenario = MarketScenario::new().idle_bettors(1, 4 * STAKE).start().await.unwrap();
+    let market_id = EXPLICIT_MARKET_ID_START + 7;
+    scenario.market_id = market_id;
+    let creator = scenario.creator.insecure_clone();
+    let bettor = scenario.bettors[0].insecure_clone();
+
+    let first = create_with_id(&mut scenario, "Will the first run resolve YES?").await;
+    assert_ne!(first, 0);
+    bet_at(&mut scenario, first).await.unwrap();
+    let stale_address = prediction_pda_at(market_id, &bettor.pubkey(), first);
+    let prediction: Prediction = fetch(&mut scenario.context, stale_address).await.unwrap();
+    assert_eq!(prediction.generation.0, first);
+    assert_eq!(scenario.market().await.live_predictions, 1);
+    let stale = scenario.context.banks_client.get_account(stale_address).await.unwrap().unwrap();
+
+    // Voided and paid out, with only the bet's account still open
+    scenario.cancel().await.unwrap();
+    // The admin covers the cuts that never reached the vault
+    let market = scenario.market().await;
+    let shortfall = market.liabilities().saturating_sub(scenario.vault_balance().await);
+    scenario.top_up(shortfall).await.unwrap();
+    let refund = claim_refund(&bettor.pubkey(), market_id);
+    let refund = at_generation(refund, market_id, &bettor.pubkey(), first);
+    process(&mut scenario.context, &[refund], &[&bettor]).await.unwrap();
+    let liquidity = refund_liquidity(&creator.pubkey(), market_id);
+    process(&mut scenario.context, &[liquidity], &[&creator]).await.unwrap();
+    let close = close_market(&creator.pubkey(), market_id, first);
+    assert_market_error(
+        process(&mut scenario.context, std::slice::from_ref(&close), &[&creator]).await,
+        MarketError::PredictionsOutstanding,
+    );
+
+    let closed = close_prediction(&bettor.pubkey(), market_id);
+    let closed = at_generation(closed, market_id, &bettor.pubkey(), first);
+    process(&mut scenario.context, &[closed], &[&bettor]).await.unwrap();
+    let before = scenario.balance(creator.pubkey()).await;
+    let held = scenario.balance(market_pda(market_id)).await
+        + scenario.balance(vault_pda(market_id)).await;
+    process(&mut scenario.context, &[close], &[&creator]).await.unwrap();
+    let archive = archive_pda(market_id, first);
+    let archive_rent = scenario.balance(archive).await;
+    assert_eq!(scenario.balance(creator.pubkey()).await - before, held - archive_rent);
+    let archived: MarketArchive = fetch(&mut scenario.context, archive).await.unwrap();
+    assert_eq!(archived.generation, first);
+    assert_eq!(scenario.balance(market_pda(market_id)).await, 0);
+    assert_eq!(scenario.balance(vault_pda(market_id)).await, 0);
+
+    // Reopened in a later slot, the ID's bets get new addresses
+    let mut clock: Clock = scenario.context.banks_client.get_sysvar().await.unwrap();
+    clock.slot += 10;
+    scenario.context.set_sysvar(&clock);
+    let second = create_with_id(&mut scenario, "Will the second run resolve YES?").await;
+    assert!(second > first);
+    bet_at(&mut scenario, second).await.unwrap();
+    assert_ne!(prediction_pda_at(market_id, &bettor.pubkey(), second), stale_address);
+    assert_eq!(scenario.market().await.live_predictions, 1);
+
+    // A position left from the first run can't claim against the second
+    scenario.context.set_account(&stale_address, &stale.into());
+    let epoch = fetch::<Config>(&mut scenario.context, config_pda())
+        .await
+        .unwrap()
+        .current_epoch;
+    for claim in [
+        claim_reward(&bettor.pubkey(), market_id, epoch),
+        claim_refund(&bettor.pubkey(), market_id),
+    ] {
+        let claim = at_generation(claim, market_id, &bettor.pubkey(), first);
+        assert_market_error(
+            process(&mut scenario.context, &[claim], &[&bettor]).await,
+            MarketError::GenerationMismatch,
+        );
+    }
+}
@@ -0,0 +1,518 @@
+// Market layout upgrades under solana-program-test: a current account is
+// rewritten in an older layout, then migrate_market must bring it back.
+// Predictions created before their reserve must keep reading, and
+// migrate_prediction grows them so every field can be written back.
+// Run `anchor build` first, then
+// `cargo test -p prediction-market --features test-utils --test migration`
+
+use anchor_lang::{AccountDeserialize, AccountSerialize, Space};
+use prediction_market::fixtures::{
+    close_prediction, market_pda, migrate_prediction, prediction_pda, process, vault_pda,
+    MarketScenario, Scenario,
+};
+use prediction_market::{
+    Market, MarketError, MarketOptions, MarketOutcome, OutcomeMints, Prediction,
+    MARKET_CATEGORY_OFFSET, MARKET_CREATOR_OFFSET, MARKET_OUTCOME_OFFSET, MARKET_QUESTION_OFFSET,
+    MARKET_RESOLUTION_TIME_OFFSET, MARKET_RESOLVED_OFFSET, MARKET_TENANT_OFFSET, MARKET_VERSION,
+    MAX_NOTE_LEN,
+};
+use solana_program_test::BanksClientError;
+use solana_sdk::instruction::InstructionError;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::Signer;
+use solana_sdk::transaction::TransactionError;
+
+const STAKE: u64 = 500_000_000;
+
+/// Rewrite a version 6 market account in the version 5 layout, which ended
+/// at net_volume and the reserve. The account keeps its length
+fn downgrade_to_v5(data: &mut Vec<u8>) {
+    let market = Market::try_deserialize(&mut &data[..]).unwrap();
+    let mut written = Vec::new();
+    market.try_serialize(&mut written).unwrap();
+    // generation and live_predictions, just before the reserve
+    let reserved_at = written.len() - 2;
+    data.drain(reserved_at - 12..reserved_at);
+    data.extend([0; 12]);
+    data[8] = 5;
+}
+
+/// Rewrite a version 5 market account in the version 4 layout, which kept
+/// creator, resolution_time, resolved, outcome and category after the
+/// question, in their original places. The account keeps its length
+fn downgrade_to_v4(data: &mut Vec<u8>) {
+    let moved: Vec<u8> = data.drain(MARKET_CREATOR_OFFSET..MARKET_QUESTION_OFFSET).collect();
+    let read_len = |data: &[u8], at: usize| {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize
+    };
+    let creator_at = MARKET_CREATOR_OFFSET + 4 + read_len(data, MARKET_CREATOR_OFFSET);
+    data.splice(creator_at..creator_at, moved[..32].iter().copied());
+    let resolution_time_at = creator_at + 32 + 8;
+    data.splice(resolution_time_at..resolution_time_at, moved[32..40].iter().copied());
+    let resolved_at = resolution_time_at + 8 * 4;
+    data.splice(resolved_at..resolved_at, moved[40..42].iter().copied());
+    let mints_at = resolved_at + 2;
+    let mints_len = if data[mints_at] == 0 { 1 } else { 65 };
+    let description_at = mints_at + mints_len + 61;
+    let series_at = description_at + 4 + read_len(data, description_at);
+    let category_at = series_at + if data[series_at] == 0 { 1 } else { 33 };
+    data.insert(category_at, moved[42]);
+    data[8] = 4;
+}
+
+/// Rewrite a version 4 market account without outcome mints in the version
+/// 3 layout, which stored two token vault keys in their place. The account
+/// grows by the 63 bytes the keys need over a None
+fn downgrade_to_v3(data: &mut Vec<u8>, token_vaults: [Pubkey; 2]) {
+    let question_at = MARKET_TENANT_OFFSET + 16;
+    let question_len =
+        u32::from_le_bytes(data[question_at..question_at + 4].try_into().unwrap()) as usize;
+    let mints_at = question_at + 4 + question_len + 32 + 8 * 5 + 2;
+    assert_eq!(data[mints_at], 0);
+    let keys = token_vaults.iter().flat_map(|key| key.to_bytes());
+    data.splice(mints_at..=mints_at, keys);
+    data[8] = 3;
+}
+
+/// Rewrite a version 3 market account in the version 2 layout, which had
+/// no tenant. The account keeps its length, padded with zeroes
+fn downgrade_to_v2(data: &mut Vec<u8>) {
+    data.drain(MARKET_TENANT_OFFSET..MARKET_TENANT_OFFSET + 16);
+    data.extend_from_slice(&[0; 16]);
+    data[8] = 2;
+}
+
+/// Rewrite a version 2 market account in the version 1 layout, where the
+/// outcome was an Option<bool>. The account keeps its length; the byte
+/// Some(_) needs comes out of the trailing padding
+fn downgrade_to_v1(data: &mut Vec<u8>) {
+    let question_len = u32::from_le_bytes(data[17..21].try_into().unwrap()) as usize;
+    let outcome_at = 8 + 1 + 8 + 4 + question_len + 32 + 8 * 5 + 1;
+    let legacy: &[u8] = match data[outcome_at] {
+        1 => &[1, 1],
+        2 => &[1, 0],
+        _ => &[0],
+    };
+    assert_eq!(data.pop(), Some(0));
+    if legacy.len() == 1 {
+        data.push(0);
+    }
+    data.splice(outcome_at..=outcome_at, legacy.iter().copied());
+    data[8] = 1;
+}
+
+/// Put the scenario's market back in an older layout, storing
+/// `token_vaults`, with `edit` applied to the rewritten data
+async fn downgrade(
+    scenario: &mut Scenario,
+    version: u8,
+    token_vaults: [Pubkey; 2],
+    edit: impl FnOnce(&mut Vec<u8>),
+) {
+    let address = market_pda(scenario.market_id);
+    let mut account = scenario
+        .context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap();
+    downgrade_to_v5(&mut account.data);
+    if version <= 4 {
+        downgrade_to_v4(&mut account.data);
+    }
+    if version <= 3 {
+        downgrade_to_v3(&mut account.data, token_vaults);
+    }
+    if version <= 2 {
+        downgrade_to_v2(&mut account.data);
+    }
+    if version == 1 {
+        downgrade_to_v1(&mut account.data);
+    }
+    edit(&mut account.data);
+    scenario.context.set_account(&address, &account.into());
+}
+
+/// Put the scenario's market back in an older layout, storing
+/// `token_vaults`, migrate it and check that every field after the tenant,
+/// the outcome and the outcome mints survived the shifts
+async fn round_trip(scenario: &mut Scenario, version: u8, token_vaults: [Pubkey; 2]) -> Market {
+    let before = scenario.market().await;
+    downgrade(scenario, version, token_vaults, |_| {}).await;
+
+    scenario.migrate().await.unwrap();
+    let after = scenario.market().await;
+    assert_eq!(after.version, MARKET_VERSION);
+    assert_eq!(after.tenant, before.tenant);
+    assert_eq!(after.question, before.question);
+    assert_eq!(after.creator, before.creator);
+    assert_eq!(after.resolution_time, before.resolution_time);
+    assert_eq!(after.category, before.category);
+    assert_eq!(after.resolved, before.resolved);
+    assert_eq!(after.fee_collected, before.fee_collected);
+    assert_eq!(after.total_volume, before.total_volume);
+    assert_eq!(after.yes_tokens, before.yes_tokens);
+    assert_eq!(after.no_tokens, before.no_tokens);
+    assert_eq!(after.event_seq, before.event_seq);
+    after
+}
+
+/// The fields before the question at their documented offsets, as `market`
+/// holds them
+fn assert_filter_offsets(data: &[u8], market: &Market) {
+    assert_eq!(data[8], MARKET_VERSION);
+    assert_eq!(&data[MARKET_TENANT_OFFSET..MARKET_TENANT_OFFSET + 16], &market.tenant);
+    assert_eq!(
+        &data[MARKET_CREATOR_OFFSET..MARKET_CREATOR_OFFSET + 32],
+        market.creator.as_ref()
+    );
+    let at = MARKET_RESOLUTION_TIME_OFFSET;
+    assert_eq!(data[at..at + 8], market.resolution_time.to_le_bytes());
+    assert_eq!(data[MARKET_RESOLVED_OFFSET], market.resolved as u8);
+    assert_eq!(data[MARKET_OUTCOME_OFFSET], market.outcome as u8);
+    assert_eq!(data[MARKET_CATEGORY_OFFSET], market.category);
+    let at = MARKET_QUESTION_OFFSET;
+    let question_len = u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+    assert_eq!(&data[at + 4..at + 4 + question_len], market.question.as_bytes());
+}
+
+/// What clients passed for markets without outcome tokens
+fn placeholder_vaults(scenario: &Scenario) -> [Pubkey; 2] {
+    [vault_pda(scenario.market_id); 2]
+}
+
+#[tokio::test]
+async fn migrates_resolved_v1_market() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+
+    let vaults = placeholder_vaults(&scenario);
+    let market = round_trip(&mut scenario, 1, vaults).await;
+    assert_eq!(market.outcome, MarketOutcome::Yes);
+    scenario.claim(0).await.unwrap();
+
+    // Already current: a second migration changes nothing
+    scenario.migrate().await.unwrap();
+    assert_eq!(scenario.market().await.outcome, MarketOutcome::Yes);
+}
+
+#[tokio::test]
+async fn migrates_voided_v1_market() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .start()
+        .await
+        .unwrap();
+    scenario.cancel().await.unwrap();
+
+    let vaults = placeholder_vaults(&scenario);
+    let market = round_trip(&mut scenario, 1, vaults).await;
+    assert_eq!(market.outcome, MarketOutcome::Void);
+    scenario.refund(0).await.unwrap();
+}
+
+#[tokio::test]
+async fn migrates_unresolved_v1_market() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .idle_bettors(1, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+
+    let vaults = placeholder_vaults(&scenario);
+    let market = round_trip(&mut scenario, 1, vaults).await;
+    assert_eq!(market.outcome, MarketOutcome::Unresolved);
+    scenario.place_prediction(1, false, STAKE).await.unwrap();
+}
+
+#[tokio::test]
+async fn migrates_v2_market() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .idle_bettors(1, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+
+    let vaults = placeholder_vaults(&scenario);
+    let market = round_trip(&mut scenario, 2, vaults).await;
+    assert_eq!(market.outcome, MarketOutcome::Unresolved);
+    scenario.place_prediction(1, false, STAKE).await.unwrap();
+}
+
+#[tokio::test]
+async fn migrates_v3_market() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .idle_bettors(1, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+
+    // Placeholder keys are dropped
+    let vaults = placeholder_vaults(&scenario);
+    let market = round_trip(&mut scenario, 3, vaults).await;
+    assert_eq!(market.outcome_mints, None);
+    scenario.place_prediction(1, false, STAKE).await.unwrap();
+
+    // Two distinct real keys are kept as the outcome mints
+    let (yes, no) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let market = round_trip(&mut scenario, 3, [yes, no]).await;
+    assert_eq!(market.outcome_mints, Some(OutcomeMints { yes, no }));
+}
+
+#[tokio::test]
+async fn rejects_legacy_markets_too_short_for_their_question() {
+    let mut scenario = MarketScenario::new().bet(true, STAKE).start().await.unwrap();
+    let vaults = placeholder_vaults(&scenario);
+    let address = market_pda(scenario.market_id);
+    let current = scenario.context.banks_client.get_account(address).await.unwrap().unwrap();
+
+    // Each legacy layout's question length, claiming more than the account holds
+    for (version, question_at) in
+        [(1, 17), (3, MARKET_TENANT_OFFSET + 16), (4, MARKET_CREATOR_OFFSET)]
+    {
+        downgrade(&mut scenario, version, vaults, |data| {
+            data[question_at..question_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        })
+        .await;
+        let expected = u32::from(MarketError::MalformedLegacyAccount);
+        match scenario.migrate().await {
+            Err(BanksClientError::TransactionError(TransactionError::InstructionError(
+                _,
+                InstructionError::Custom(code),
+            ))) => assert_eq!(code, expected, "version {version}"),
+            other => panic!("version {version}: expected MalformedLegacyAccount, got {other:?}"),
+        }
+        scenario.context.set_account(&address, &current.clone().into());
+    }
+}
+
+/// Size of a prediction created before predictions reserved trailing space
+const PRE_RESERVE_LEN: usize = 8 + 265;
+
+/// What the market and prediction reserves took before they grew to 64 bytes
+const MARKET_RESERVE_GROWTH: usize = 62;
+const SMALL_RESERVE_PREDICTION_LEN: usize = PRE_RESERVE_LEN + 16;
+
+/// A version 2 prediction account as created before predictions reserved
+/// trailing space: 5000 bps YES entry, note "hedge", separate rent payer
+const PREDICTION_V2: &[u8] = include_bytes!("fixtures/prediction_v2.bin");
+
+#[test]
+fn reads_prediction_created_before_reserve() {
+    assert_eq!(PREDICTION_V2.len(), PRE_RESERVE_LEN);
+    let prediction = Prediction::try_deserialize(&mut &PREDICTION_V2[..]).unwrap();
+    assert_eq!(prediction.version, 2);
+    assert_eq!(prediction.market_id, 7);
+    assert_eq!(prediction.predictor, Pubkey::new_from_array([1; 32]));
+    assert!(prediction.prediction_type);
+    assert_eq!(prediction.amount_deposited, 500_000_000);
+    assert_eq!(prediction.tokens_received, 990_000_000);
+    assert_eq!(prediction.created_at, 1_700_000_000);
+    assert!(!prediction.claimed);
+    assert_eq!(prediction.referrer, Pubkey::default());
+    assert_eq!(prediction.bump, 254);
+    assert_eq!(prediction.slot, 1234);
+    assert_eq!(prediction.entry_price_bps, 5050);
+    assert_eq!(prediction.entry_probability_bps, 5000);
+    assert_eq!(prediction.trade_sequence, 3);
+    assert_eq!(prediction.note, "hedge");
+    assert!(!prediction.rebate_claimed);
+    assert_eq!(prediction.rent_recipient(), Pubkey::new_from_array([2; 32]));
+    assert!(!prediction.is_house.0);
+}
+
+#[tokio::test]
+async fn claims_prediction_created_before_reserve() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+
+    // Cut the reserve off the winner's account, as older accounts were sized
+    let address = prediction_pda(scenario.market_id, &scenario.bettors[0].pubkey());
+    let mut account = scenario
+        .context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.data.len(), 8 + Prediction::INIT_SPACE);
+    assert!(account.data[PRE_RESERVE_LEN..].iter().all(|&byte| byte == 0));
+    account.data.truncate(PRE_RESERVE_LEN);
+    scenario.context.set_account(&address, &account.into());
+
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+    scenario.claim(0).await.unwrap();
+    assert!(scenario.prediction(0).await.claimed);
+}
+
+#[tokio::test]
+async fn writes_accounts_created_with_the_smaller_reserves() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .idle_bettors(1, 10_000_000_000)
+        .start()
+        .await
+        .unwrap();
+
+    // Size the market and the winner's prediction as they were created
+    // before the reserves grew
+    let market_address = market_pda(scenario.market_id);
+    let prediction_address = prediction_pda(scenario.market_id, &scenario.bettors[0].pubkey());
+    for (address, len) in [
+        (market_address, Market::space(false, 0) - MARKET_RESERVE_GROWTH),
+        (prediction_address, SMALL_RESERVE_PREDICTION_LEN),
+    ] {
+        let mut account =
+            scenario.context.banks_client.get_account(address).await.unwrap().unwrap();
+        assert!(account.data[len..].iter().all(|&byte| byte == 0));
+        account.data.truncate(len);
+        scenario.context.set_account(&address, &account.into());
+    }
+
+    // Both are written back as far as they go
+    scenario.place_prediction(2, true, STAKE).await.unwrap();
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+    scenario.claim(0).await.unwrap();
+    assert!(scenario.prediction(0).await.claimed);
+    let market = scenario.context.banks_client.get_account(market_address).await.unwrap().unwrap();
+    assert_eq!(market.data.len(), Market::space(false, 0) - MARKET_RESERVE_GROWTH);
+    assert_eq!(scenario.market().await.trade_count, 3);
+}
+
+#[tokio::test]
+async fn migrates_prediction_with_a_max_length_note() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+
+    // A pre-reserve account whose note fills MAX_NOTE_LEN: every byte up to
+    // rent_payer is taken, and nothing past it fits
+    let predictor = scenario.bettors[0].pubkey();
+    let address = prediction_pda(scenario.market_id, &predictor);
+    let mut prediction = scenario.prediction(0).await;
+    prediction.note = "n".repeat(MAX_NOTE_LEN);
+    let mut account = scenario
+        .context
+        .banks_client
+        .get_account(address)
+        .await
+        .unwrap()
+        .unwrap();
+    prediction.try_serialize(&mut account.data.as_mut_slice()).unwrap();
+    account.data.truncate(PRE_RESERVE_LEN);
+    scenario.context.set_account(&address, &account.into());
+    assert_eq!(scenario.prediction(0).await.note.len(), MAX_NOTE_LEN);
+
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+    assert!(scenario.claim(0).await.is_err());
+
+    let payer = scenario.context.payer.pubkey();
+    for _ in 0..2 {
+        let migrate = migrate_prediction(&payer, scenario.market_id, &predictor);
+        process(&mut scenario.context, &[migrate], &[]).await.unwrap();
+        scenario.context.get_new_latest_blockhash().await.unwrap();
+    }
+    let account = scenario.context.banks_client.get_account(address).await.unwrap().unwrap();
+    assert_eq!(account.data.len(), 8 + Prediction::INIT_SPACE);
+    assert_eq!(scenario.prediction(0).await.note.len(), MAX_NOTE_LEN);
+
+    scenario.claim(0).await.unwrap();
+    assert!(scenario.prediction(0).await.claimed);
+}
+
+#[tokio::test]
+async fn migrates_v4_market() {
+    let mut scenario = MarketScenario::new()
+        .options(MarketOptions {
+            category: 3,
+            ..MarketOptions::default()
+        })
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+    scenario.warp_to_resolution().await;
+    scenario.resolve(false).await.unwrap();
+
+    let vaults = placeholder_vaults(&scenario);
+    let market = round_trip(&mut scenario, 4, vaults).await;
+    assert_eq!(market.outcome, MarketOutcome::No);
+    assert_eq!(market.category, 3);
+    scenario.claim(1).await.unwrap();
+}
+
+#[tokio::test]
+async fn migrates_v5_market() {
+    let mut scenario = MarketScenario::new()
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+    // A shortened house list leaves stale bytes past the reserve
+    scenario.add_house_account(0).await.unwrap();
+    scenario.add_house_account(1).await.unwrap();
+    scenario.remove_house_account(0).await.unwrap();
+
+    let vaults = placeholder_vaults(&scenario);
+    let market = round_trip(&mut scenario, 5, vaults).await;
+    assert_eq!(market.house_accounts, vec![scenario.bettors[1].pubkey()]);
+    assert_eq!(market.generation, 0);
+    // Both bets' accounts are still open
+    assert_eq!(market.live_predictions, 2);
+    scenario.warp_to_resolution().await;
+    scenario.resolve(true).await.unwrap();
+    scenario.claim(0).await.unwrap();
+    let winner = scenario.bettors[0].insecure_clone();
+    let close = close_prediction(&winner.pubkey(), scenario.market_id);
+    process(&mut scenario.context, &[close], &[&winner]).await.unwrap();
+    assert_eq!(scenario.market().await.live_predictions, 1);
+}
+
+#[tokio::test]
+async fn filter_fields_sit_at_documented_offsets() {
+    let mut scenario = MarketScenario::new()
+        .options(MarketOptions {
+            category: 7,
+            ..MarketOptions::default()
+        })
+        .bet(true, STAKE)
+        .bet(false, STAKE)
+        .start()
+        .await
+        .unwrap();
+    let address = market_pda(scenario.market_id);
+
+    for resolve in [false, true] {
+        if resolve {
+            scenario.warp_to_resolution().await;
+            scenario.resolve(true).await.unwrap();
+        }
+        let market = scenario.market().await;
+        let account = scenario.context.banks_client.get_account(address).await.unwrap().unwrap();
+        assert_filter_offsets(&account.data, &market);
+    }
+    assert_eq!(scenario.market().await.outcome, MarketOutcome::Yes);
+}
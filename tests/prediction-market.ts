@@ -6,6 +6,22 @@ import { Program } from "@coral-xyz/anchor";
 import * as web3 from "@solana/web3.js";
 import BN from "bn.js";
 import { assert } from "chai";
+import {
+  createMint,
+  createAccount,
+  createMintToInstruction,
+  mintTo,
+  createAssociatedTokenAccount,
+  createNativeMint,
+  getAccount,
+  getAssociatedTokenAddressSync,
+  getMint,
+  NATIVE_MINT,
+  NATIVE_MINT_2022,
+  TOKEN_PROGRAM_ID,
+  TOKEN_2022_PROGRAM_ID,
+} from "@solana/spl-token";
+import { keccak_256 } from "@noble/hashes/sha3";
 
 describe("prediction-market", () => {
   // Configure the client to use the local cluster
@@ -19,14 +35,129 @@ describe("prediction-market", () => {
   const predictor1 = web3.Keypair.generate();
   const predictor2 = web3.Keypair.generate();
 
-  const MARKET_ID = new BN(1);
+  // Assigned from the on-chain market counter in "initializes a market"
+  let MARKET_ID: BN;
   const QUESTION = "Will SOL price exceed $200 by end of week?";
   const INITIAL_LIQUIDITY = new BN(anchor.web3.LAMPORTS_PER_SOL);
   const PREDICTION_AMOUNT = new BN(
     anchor.web3.LAMPORTS_PER_SOL / 2 // 0.5 SOL
   );
 
+  // Protocol config bounds
+  const MIN_MARKET_DURATION = 10 * 60; // 10 minutes
+  const MAX_MARKET_DURATION = 2 * 365 * 24 * 60 * 60; // 2 years
+  const REGISTRY_PAGE_SIZE = 32;
+
   // PDA helpers
+  const getConfigPDA = () => {
+    return web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("config")],
+      program.programId
+    );
+  };
+
+  const getCounterPDA = () => {
+    return web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("counter")],
+      program.programId
+    );
+  };
+
+  const getRegistryPagePDA = (pageIndex: BN) => {
+    return web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("registry"), pageIndex.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+  };
+
+  // Registry page the next created market will be appended to
+  const getNextRegistryPagePDA = async () => {
+    const config = await program.account["config"].fetch(getConfigPDA()[0]);
+    return getRegistryPagePDA(config.registryLen.divn(REGISTRY_PAGE_SIZE));
+  };
+
+  const getProfilePDA = (user: web3.PublicKey) => {
+    return web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("profile"), user.toBuffer()],
+      program.programId
+    );
+  };
+
+  // Helper: the per-market and paged per-user index accounts a new
+  // prediction updates; the page follows the user's bet count
+  const getPositionIndexAccounts = async (marketId: BN, user: web3.PublicKey) => {
+    const profile = await program.account["userProfile"].fetchNullable(
+      getProfilePDA(user)[0]
+    );
+    const page = profile ? Math.floor(profile.betsPlaced / 32) : 0;
+    const pageBytes = Buffer.alloc(4);
+    pageBytes.writeUInt32LE(page);
+    return {
+      userMarketIndex: web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("index"), getMarketPDA(marketId)[0].toBuffer(), user.toBuffer()],
+        program.programId
+      )[0],
+      userIndexPage: web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("user_index"), user.toBuffer(), pageBytes],
+        program.programId
+      )[0],
+    };
+  };
+
+  const getCreatorStatsPDA = (creator: web3.PublicKey) =>
+    web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("creator_stats"), creator.toBuffer()],
+      program.programId
+    )[0];
+
+  const getClaimReceiptPDA = (prediction: web3.PublicKey) =>
+    web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("receipt"), prediction.toBuffer()],
+      program.programId
+    )[0];
+
+  const getEscrowedClaimPDA = (prediction: web3.PublicKey) =>
+    web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("escrowed_claim"), prediction.toBuffer()],
+      program.programId
+    )[0];
+
+  const getSelfExclusionPDA = (user: web3.PublicKey) =>
+    web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("self_exclusion"), user.toBuffer()],
+      program.programId
+    )[0];
+
+  const getEpochStatsPDA = (user: web3.PublicKey, epoch: BN) => {
+    return web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("epoch_stats"),
+        user.toBuffer(),
+        epoch.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    );
+  };
+
+  const getLeaderboardPDA = (epoch: BN) => {
+    return web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("leaderboard"), epoch.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    );
+  };
+
+  const currentEpoch = async (): Promise<BN> => {
+    const config = await program.account["config"].fetch(getConfigPDA()[0]);
+    return config.currentEpoch;
+  };
+
+  const getReferrerPDA = (owner: web3.PublicKey) => {
+    return web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("referrer"), owner.toBuffer()],
+      program.programId
+    );
+  };
+
   const getMarketPDA = (marketId: BN) => {
     return web3.PublicKey.findProgramAddressSync(
       [Buffer.from("market"), marketId.toArrayLike(Buffer, "le", 8)],
@@ -34,6 +165,42 @@ describe("prediction-market", () => {
     );
   };
 
+  const getSeriesPDA = (creator: web3.PublicKey, name: string) => {
+    return web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("series"), creator.toBuffer(), Buffer.from(name)],
+      program.programId
+    );
+  };
+
+  const getCreatorAllowPDA = (creator: web3.PublicKey) =>
+    web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("creator_allow"), creator.toBuffer()],
+      program.programId
+    )[0];
+
+  const getTreasuryPDA = () =>
+    web3.PublicKey.findProgramAddressSync([Buffer.from("treasury")], program.programId)[0];
+
+  const getTemplatePDA = (creator: web3.PublicKey, name: string) => {
+    return web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("template"), creator.toBuffer(), Buffer.from(name)],
+      program.programId
+    );
+  };
+
+  const getInsuranceFundPDA = () => {
+    return web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("insurance")],
+      program.programId
+    );
+  };
+
+  const getResolutionPDA = (marketId: BN) =>
+    web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("resolution"), marketId.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+
   const getVaultPDA = (marketId: BN) => {
     return web3.PublicKey.findProgramAddressSync(
       [Buffer.from("vault"), marketId.toArrayLike(Buffer, "le", 8)],
@@ -41,17 +208,65 @@ describe("prediction-market", () => {
     );
   };
 
-  const getPredictionPDA = (marketId: BN, predictor: web3.PublicKey) => {
+  const TOKEN_METADATA_PROGRAM_ID = new web3.PublicKey(
+    "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s"
+  );
+
+  const getMetadataPDA = (mint: web3.PublicKey) =>
+    web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("metadata"), TOKEN_METADATA_PROGRAM_ID.toBuffer(), mint.toBuffer()],
+      TOKEN_METADATA_PROGRAM_ID
+    );
+
+  // Mirrors normalize_question / question_hash in the program
+  const normalizeQuestion = (question: string) =>
+    question.trim().split(/\s+/).join(" ").toLowerCase();
+
+  const getQuestionRegistryPDA = (question: string, resolutionTime: BN) => {
+    const bucket = new BN(Math.floor(resolutionTime.toNumber() / (24 * 60 * 60)));
+    const hash = keccak_256(
+      Buffer.concat([
+        Buffer.from(normalizeQuestion(question)),
+        bucket.toArrayLike(Buffer, "le", 8),
+      ])
+    );
     return web3.PublicKey.findProgramAddressSync(
-      [
-        Buffer.from("prediction"),
-        marketId.toArrayLike(Buffer, "le", 8),
-        predictor.toBuffer(),
-      ],
+      [Buffer.from("question"), Buffer.from(hash)],
       program.programId
     );
   };
 
+  // wSOL collateral account of a market created with wsolVault
+  const getWsolVault = (marketId: BN, mint = NATIVE_MINT, tokenProgram = TOKEN_PROGRAM_ID) =>
+    getAssociatedTokenAddressSync(mint, getMarketPDA(marketId)[0], true, tokenProgram);
+
+  // Token program owning a wSOL market's collateral mint
+  const collateralTokenProgram = (market: any) =>
+    market.collateralMint.equals(NATIVE_MINT_2022) ? TOKEN_2022_PROGRAM_ID : TOKEN_PROGRAM_ID;
+
+  // generation is the market's; nonzero only under a reused explicit ID
+  const getPredictionPDA = (
+    marketId: BN,
+    predictor: web3.PublicKey,
+    positionIndex = 0,
+    generation = new BN(0)
+  ) => {
+    const seeds = [
+      Buffer.from("prediction"),
+      marketId.toArrayLike(Buffer, "le", 8),
+      predictor.toBuffer(),
+    ];
+    if (positionIndex > 0) {
+      const indexBytes = Buffer.alloc(4);
+      indexBytes.writeUInt32LE(positionIndex);
+      seeds.push(indexBytes);
+    }
+    if (!generation.isZero()) {
+      seeds.push(generation.toArrayLike(Buffer, "le", 8));
+    }
+    return web3.PublicKey.findProgramAddressSync(seeds, program.programId);
+  };
+
   // Helper: airdrop SOL
   const airdropSol = async (pubkey: web3.PublicKey, sol: number) => {
     const sig = await provider.connection.requestAirdrop(
@@ -61,35 +276,593 @@ describe("prediction-market", () => {
     await provider.connection.confirmTransaction(sig, "confirmed");
   };
 
-  // -------------------- Market Initialization --------------------
+  // Helper: create a market with the given resolution time, using the
+  // next ID from the market counter
+  const DEFAULT_MARKET_OPTIONS = {
+    timeDecay: false,
+    timeDecayFloorBps: 0,
+    oracleKey: null,
+    oracleEthAddress: null,
+    betRevealWindow: new BN(0),
+    priceOracle: null,
+    correctionWindowSecs: new BN(0),
+    claimPeriodSecs: new BN(0),
+    imbalanceRebateBps: 0,
+    feeCurve: null,
+    feeSchedule: null,
+    freezeBeforeCloseSecs: null,
+    exitsDuringFreeze: null,
+    wsolVault: false,
+    subjective: false,
+    cooloffSecs: new BN(0),
+    earlyExitPenaltyBps: 0,
+    mode: { amm: {} },
+    yesOddsBps: 0,
+    noOddsBps: 0,
+    winningsFeeBps: 0,
+    loserRebateBps: 0,
+    keeperBudget: new BN(0),
+    category: 0,
+    disputeWindowSecs: new BN(0),
+    openAuctionSecs: new BN(0),
+    maxMovePerTradeBps: 0,
+    maxMovePerWindowBps: 0,
+    tenant: Array(16).fill(0),
+    claimWindowSecs: new BN(0),
+    maxBet: new BN(0),
+    maxBetRampSecs: new BN(0),
+    maxBetRampStartBps: 0,
+    parent: null,
+    compressed: false,
+    reduceOnlyWindowSecs: new BN(0),
+    escrowDisputedClaims: false,
+    poolHistoryLen: 0,
+    resolutionProgram: null,
+    pushPolicy: { voidOnPush: {} },
+    maxParticipants: 0,
+    roundingPolicy: { favorPool: {} },
+  };
 
-  it("initializes a market", async () => {
-    await airdropSol(marketCreator.publicKey, 5);
+  const createMarket = async (
+    resolutionTime: BN,
+    creator: web3.Keypair = marketCreator,
+    options: any = {},
+    series: web3.PublicKey | null = null,
+    outcomeMints: { yes: web3.PublicKey; no: web3.PublicKey } | null = null,
+    initialYesProbabilityBps = 5_000,
+    feeBps = 0
+  ) => {
+    const [counterPDA] = getCounterPDA();
+    const counter = await program.account["marketCounter"].fetch(counterPDA);
+    const marketId: BN = counter.nextId;
+    const [marketPDA] = getMarketPDA(marketId);
+    const [vaultPDA] = getVaultPDA(marketId);
+    const [configPDA] = getConfigPDA();
+    const [registryPagePDA] = await getNextRegistryPagePDA();
+    const creatorAllowPDA = getCreatorAllowPDA(creator.publicKey);
+    const approved = await provider.connection.getAccountInfo(creatorAllowPDA);
+    const treasury = await provider.connection.getAccountInfo(getTreasuryPDA());
+    const merged = { ...DEFAULT_MARKET_OPTIONS, ...options };
 
-    const [marketPDA] = getMarketPDA(MARKET_ID);
-    const [vaultPDA] = getVaultPDA(MARKET_ID);
+    const signature = await program.methods
+      .initializeMarket(
+        QUESTION,
+        resolutionTime,
+        INITIAL_LIQUIDITY,
+        initialYesProbabilityBps,
+        feeBps,
+        merged
+      )
+      .accounts({
+        marketCounter: counterPDA,
+        market: marketPDA,
+        config: configPDA,
+        registryPage: registryPagePDA,
+        creator: creator.publicKey,
+        marketVault: vaultPDA,
+        questionRegistry: getQuestionRegistryPDA(QUESTION, resolutionTime)[0],
+        duplicateApprover: null,
+        yesMint: outcomeMints ? outcomeMints.yes : null,
+        noMint: outcomeMints ? outcomeMints.no : null,
+        series,
+        parentMarket: merged.parent ? merged.parent.market : null,
+        creatorAllow: approved ? creatorAllowPDA : null,
+        creatorStats: getCreatorStatsPDA(creator.publicKey),
+        treasury: treasury ? getTreasuryPDA() : null,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .signers([creator])
+      .rpc();
 
-    const now = Math.floor(Date.now() / 1000);
-    const resolutionTime = new BN(now + 7 * 24 * 60 * 60); // +7 days
+    return { marketId, marketPDA, signature };
+  };
 
-    await program.methods
-      .initializeMarket(MARKET_ID, QUESTION, resolutionTime, INITIAL_LIQUIDITY)
+  // Helper: build a place_prediction call, ready for .rpc()/.transaction()
+  const placePredictionIx = async (
+    marketId: BN,
+    predictor: web3.Keypair,
+    predictionType: boolean,
+    amount: BN,
+    referrer: web3.PublicKey | null = null,
+    nft: { tokenAccount: web3.PublicKey; metadata: web3.PublicKey } | null = null,
+    maxPriceImpactBps = 0,
+    note = "",
+    rentPayer: web3.Keypair = predictor,
+    dryRun = false
+  ) => {
+    const [marketPDA] = getMarketPDA(marketId);
+    const [vaultPDA] = getVaultPDA(marketId);
+    const [predictionPDA] = getPredictionPDA(marketId, predictor.publicKey);
+    const epoch = await currentEpoch();
+    const market = await program.account["market"].fetch(marketPDA);
+
+    return program.methods
+      .placePrediction(marketId, predictionType, amount, maxPriceImpactBps, note, dryRun)
       .accounts({
         market: marketPDA,
-        creator: marketCreator.publicKey,
         marketVault: vaultPDA,
-        yesTokenVault: vaultPDA, // dummy for now
-        noTokenVault: vaultPDA, // dummy for now
+        config: getConfigPDA()[0],
+        predictionAccount: predictionPDA,
+        userProfile: getProfilePDA(predictor.publicKey)[0],
+        ...(await getPositionIndexAccounts(marketId, predictor.publicKey)),
+        epochStats: getEpochStatsPDA(predictor.publicKey, epoch)[0],
+        insuranceFund: getInsuranceFundPDA()[0],
+        referrer,
+        nftTokenAccount: nft ? nft.tokenAccount : null,
+        nftMetadata: nft ? nft.metadata : null,
+        series: market.series,
+        creatorStats: getCreatorStatsPDA(market.liabilityCreator),
+        selfExclusion: getSelfExclusionPDA(predictor.publicKey),
+        predictor: predictor.publicKey,
+        rentPayer: rentPayer.publicKey,
+        wsolVault: market.wsolVault ? market.collateralVault : null,
+        tokenProgram: market.wsolVault ? collateralTokenProgram(market) : null,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .signers(rentPayer === predictor ? [predictor] : [predictor, rentPayer]);
+  };
+
+  // Helper: place a prediction on a market
+  const placePrediction = async (
+    marketId: BN,
+    predictor: web3.Keypair,
+    predictionType: boolean,
+    amount: BN,
+    referrer: web3.PublicKey | null = null,
+    nft: { tokenAccount: web3.PublicKey; metadata: web3.PublicKey } | null = null
+  ) => {
+    const builder = await placePredictionIx(
+      marketId,
+      predictor,
+      predictionType,
+      amount,
+      referrer,
+      nft
+    );
+    await builder.rpc();
+    return getPredictionPDA(marketId, predictor.publicKey)[0];
+  };
+
+  // Helper: small YES and NO bets, so the market has a counterparty on
+  // both sides and resolves instead of being voided
+  const betBothSides = async (marketId: BN) => {
+    const stake = new BN(web3.LAMPORTS_PER_SOL / 100);
+    await placePrediction(marketId, predictor1, true, stake);
+    await placePrediction(marketId, predictor2, false, stake);
+  };
+
+  // Helper: fetch an on-chain quote for a prediction via simulation
+  const quotePrediction = (
+    marketId: BN,
+    predictor: web3.PublicKey,
+    predictionType: boolean,
+    amount: BN,
+    referrer: web3.PublicKey | null = null
+  ) =>
+    program.methods
+      .quotePrediction(marketId, predictionType, amount)
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        config: getConfigPDA()[0],
+        referrer,
+        nftTokenAccount: null,
+        nftMetadata: null,
+        predictor,
+      })
+      .view();
+
+  // Helper: decode events emitted via emit_cpi! from a list of inner
+  // instructions (self-CPIs carrying the event as instruction data)
+  const decodeCpiEvents = (
+    innerInstructions: any[],
+    accountKeys: web3.PublicKey[]
+  ) => {
+    const events: any[] = [];
+    for (const inner of innerInstructions) {
+      for (const ix of inner.instructions) {
+        if (!accountKeys[ix.programIdIndex].equals(program.programId)) continue;
+        const data = anchor.utils.bytes.bs58.decode(ix.data);
+        const event = program.coder.events.decode(
+          anchor.utils.bytes.base64.encode(Buffer.from(data.subarray(8)))
+        );
+        if (event) events.push(event);
+      }
+    }
+    return events;
+  };
+
+  // Helper: fetch a confirmed transaction's emit_cpi! events
+  const getCpiEvents = async (signature: string) => {
+    await provider.connection.confirmTransaction(signature, "confirmed");
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const accountKeys = tx.transaction.message.getAccountKeys().staticAccountKeys;
+    return decodeCpiEvents(tx.meta.innerInstructions ?? [], accountKeys);
+  };
+
+  const isEvent = (event: any, name: string) =>
+    event.name.toLowerCase() === name.toLowerCase();
+
+  // Helper: resolve a market as its creator, or another listed resolver
+  const resolveMarket = async (
+    marketId: BN,
+    outcome: boolean,
+    creator: web3.Keypair = marketCreator,
+    evidence = ""
+  ) => {
+    const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+    return program.methods
+      .resolveMarket(marketId, outcome, evidence)
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        marketVault: getVaultPDA(marketId)[0],
+        wsolVault: market.wsolVault ? market.collateralVault : null,
+        resolution: getResolutionPDA(marketId),
+        admin: creator.publicKey,
+        payer: creator.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .signers([creator])
+      .rpc();
+  };
+
+  // Helper: encode an oracle attestation message
+  // (market_id LE || outcome u8 || timestamp LE || expires_at LE)
+  const attestationMessage = (
+    marketId: BN,
+    outcome: boolean,
+    timestamp: BN,
+    expiresAt: BN = timestamp.addn(600)
+  ) =>
+    Buffer.concat([
+      marketId.toArrayLike(Buffer, "le", 8),
+      Buffer.from([outcome ? 1 : 0]),
+      timestamp.toTwos(64).toArrayLike(Buffer, "le", 8),
+      expiresAt.toTwos(64).toArrayLike(Buffer, "le", 8),
+    ]);
+
+  // Mirrors attestation_hash in the program
+  const getAttestationRecordPDA = (oracle: web3.PublicKey, message: Buffer) =>
+    web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("attestation"),
+        Buffer.from(keccak_256(Buffer.concat([oracle.toBuffer(), message]))),
+      ],
+      program.programId
+    );
+
+  // Helper: resolve a market from an oracle-signed attestation, relayed by
+  // the provider wallet. `message` defaults to the attestation for
+  // `marketId` itself; `recordOracle` is the market's oracle, if the
+  // signer isn't
+  const resolveWithAttestation = async (
+    marketId: BN,
+    outcome: boolean,
+    timestamp: BN,
+    oracle: web3.Keypair,
+    expiresAt: BN = timestamp.addn(600),
+    message: Buffer = attestationMessage(marketId, outcome, timestamp, expiresAt),
+    recordOracle: web3.PublicKey = oracle.publicKey
+  ) => {
+    const verifyIx = web3.Ed25519Program.createInstructionWithPrivateKey({
+      privateKey: oracle.secretKey,
+      message,
+    });
+    const submitted = attestationMessage(marketId, outcome, timestamp, expiresAt);
+    return program.methods
+      .resolveWithAttestation(marketId, outcome, timestamp, expiresAt)
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        marketVault: getVaultPDA(marketId)[0],
+        wsolVault: null,
+        attestationRecord: getAttestationRecordPDA(recordOracle, submitted)[0],
+        payer: provider.wallet.publicKey,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+      })
+      .preInstructions([verifyIx])
+      .rpc();
+  };
+
+  // Helper: encode an EVM oracle attestation
+  // (program_id || market_id LE || outcome u8 || deadline LE)
+  const ethAttestationMessage = (marketId: BN, outcome: boolean, deadline: BN) =>
+    Buffer.concat([
+      program.programId.toBuffer(),
+      attestationMessage(marketId, outcome, deadline).subarray(0, 9),
+      deadline.toTwos(64).toArrayLike(Buffer, "le", 8),
+    ]);
+
+  // Helper: resolve a market from a secp256k1 attestation signed with
+  // `privateKey`, relayed by the provider wallet
+  const resolveWithEthAttestation = async (
+    marketId: BN,
+    outcome: boolean,
+    deadline: BN,
+    privateKey: Buffer,
+    message: Buffer = ethAttestationMessage(marketId, outcome, deadline)
+  ) => {
+    // The precompile is the transaction's first instruction
+    const verifyIx = web3.Secp256k1Program.createInstructionWithPrivateKey({
+      privateKey,
+      message,
+      instructionIndex: 0,
+    });
+    return program.methods
+      .resolveWithEthAttestation(marketId, outcome, deadline)
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        marketVault: getVaultPDA(marketId)[0],
+        wsolVault: null,
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+      })
+      .preInstructions([verifyIx])
+      .rpc();
+  };
+
+  // Helper: commit to keccak(outcome || salt) as the market's creator
+  const commitResolution = (marketId: BN, outcome: boolean, salt: Buffer) =>
+    program.methods
+      .commitResolution(
+        marketId,
+        Array.from(keccak_256(Buffer.concat([Buffer.from([outcome ? 1 : 0]), salt])))
+      )
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        admin: marketCreator.publicKey,
+      })
+      .signers([marketCreator])
+      .rpc();
+
+  // Helper: reveal a committed outcome as the market's creator
+  const revealResolution = (marketId: BN, outcome: boolean, salt: Buffer) =>
+    program.methods
+      .revealResolution(marketId, outcome, Array.from(salt), "")
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        marketVault: getVaultPDA(marketId)[0],
+        wsolVault: null,
+        resolution: getResolutionPDA(marketId),
+        admin: marketCreator.publicKey,
         systemProgram: web3.SystemProgram.programId,
       })
       .signers([marketCreator])
       .rpc();
 
+  // Helper: build a claim of a winning prediction's reward
+  const claimRewardIx = async (
+    marketId: BN,
+    claimer: web3.Keypair,
+    tokensToClaim: BN | null = null,
+    positionIndex = 0,
+    recipient: web3.PublicKey | null = null,
+    withReceipt = false,
+    dryRun = false
+  ) => {
+    const epoch = await currentEpoch();
+    const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+    const [predictionPDA] = getPredictionPDA(marketId, claimer.publicKey, positionIndex);
+    return program.methods
+      .claimReward(marketId, tokensToClaim, withReceipt, dryRun)
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        marketVault: getVaultPDA(marketId)[0],
+        config: getConfigPDA()[0],
+        predictionAccount: predictionPDA,
+        userProfile: getProfilePDA(claimer.publicKey)[0],
+        epochStats: getEpochStatsPDA(claimer.publicKey, epoch)[0],
+        claimer: claimer.publicKey,
+        recipient,
+        wsolVault: market.wsolVault ? market.collateralVault : null,
+        payoutTokenAccount: market.wsolVault
+          ? getAssociatedTokenAddressSync(
+              market.collateralMint,
+              claimer.publicKey,
+              false,
+              collateralTokenProgram(market)
+            )
+          : null,
+        collateralMint: market.wsolVault ? market.collateralMint : null,
+        tokenProgram: market.wsolVault ? collateralTokenProgram(market) : null,
+        claimReceipt: withReceipt ? getClaimReceiptPDA(predictionPDA) : null,
+        escrowedClaim:
+          market.disputed && market.escrowDisputedClaims
+            ? getEscrowedClaimPDA(predictionPDA)
+            : null,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .signers([claimer]);
+  };
+
+  // Helper: claim a winning prediction's reward
+  const claimReward = async (
+    marketId: BN,
+    claimer: web3.Keypair,
+    tokensToClaim: BN | null = null,
+    positionIndex = 0,
+    recipient: web3.PublicKey | null = null,
+    withReceipt = false
+  ) =>
+    (
+      await claimRewardIx(marketId, claimer, tokensToClaim, positionIndex, recipient, withReceipt)
+    ).rpc();
+
+  // Helper: read what a prediction is owed via simulation
+  const getClaimable = (marketId: BN, predictor: web3.PublicKey) =>
+    program.methods
+      .getClaimable(marketId)
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        predictionAccount: getPredictionPDA(marketId, predictor)[0],
+      })
+      .view();
+
+  // Mirror of the on-chain fixed-point pricing (weighted_tokens_out)
+  const PRICE_SCALE = new BN(1_000_000_000);
+  const weightedTokensOut = (net: BN, pool: BN, weightBps: BN) => {
+    const denominator = pool.add(net);
+    if (denominator.isZero()) return new BN(0);
+    const numerator = net.mul(pool);
+    const fixed = numerator
+      .div(denominator)
+      .mul(PRICE_SCALE)
+      .add(numerator.mod(denominator).mul(PRICE_SCALE).div(denominator));
+    return fixed.mul(weightBps).divn(10_000).div(PRICE_SCALE);
+  };
+
+  // Helper: set (or clear, with "") a market's description as its creator
+  const setDescription = (
+    marketId: BN,
+    description: string,
+    creator: web3.Keypair = marketCreator
+  ) =>
+    program.methods
+      .setDescription(marketId, description)
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        creator: creator.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .signers([creator])
+      .rpc();
+
+  // Helper: run the permissionless solvency check on a market
+  const assertSolvent = async (marketId: BN) => {
+    const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+    return program.methods
+      .assertSolvent(marketId)
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        marketVault: getVaultPDA(marketId)[0],
+        wsolVault: market.wsolVault ? market.collateralVault : null,
+      })
+      .rpc();
+  };
+
+  const sleep = (ms: number) => new Promise((r) => setTimeout(r, ms));
+
+  // Current protocol config; tests tweak fields through updateConfig
+  const configParams: any = {
+    minMarketDuration: new BN(MIN_MARKET_DURATION),
+    maxMarketDuration: new BN(MAX_MARKET_DURATION),
+    epochLength: new BN(7 * 24 * 60 * 60),
+    tradingFeeBps: 100,
+    referralFeeBps: 2000,
+    discountCollection: null,
+    discountBps: 0,
+    insuranceBps: 0,
+    treasury: provider.wallet.publicKey,
+    configDelaySecs: new BN(0),
+    paused: false,
+    maxFeeBps: 1_000,
+    minFeeBps: 10,
+    freezeBeforeCloseSecs: new BN(0),
+    exitsDuringFreeze: true,
+    // Most tests reuse QUESTION; "Duplicate markets" turns the check on
+    allowDuplicateMarkets: true,
+    // Most tests resolve right after betting; "Settle delay" turns it on
+    minSettleDelay: new BN(0),
+    creationMode: { open: {} },
+    creationFeeLamports: new BN(0),
+    staleAfterSecs: new BN(7 * 24 * 60 * 60),
+    maxOpenLiability: new BN(0),
+    maxMarketsPerDay: 0,
+    yieldAdapter: web3.PublicKey.default,
+    maxIdleBps: 0,
+  };
+
+  const updateConfig = async (overrides: any) => {
+    Object.assign(configParams, overrides);
+    await program.methods
+      .updateConfig(configParams)
+      .accounts({
+        config: getConfigPDA()[0],
+        admin: provider.wallet.publicKey,
+      })
+      .rpc();
+  };
+
+  // Helper: create a market resolving `seconds` from now by temporarily
+  // lifting the minimum market duration
+  const createShortMarket = async (
+    seconds: number,
+    creator: web3.Keypair = marketCreator,
+    options: any = {},
+    outcomeMints: { yes: web3.PublicKey; no: web3.PublicKey } | null = null
+  ) => {
+    const minDuration = configParams.minMarketDuration;
+    await updateConfig({ minMarketDuration: new BN(0) });
+    try {
+      const now = Math.floor(Date.now() / 1000);
+      return await createMarket(new BN(now + seconds), creator, options, null, outcomeMints);
+    } finally {
+      await updateConfig({ minMarketDuration: minDuration });
+    }
+  };
+
+  // -------------------- Protocol Config --------------------
+
+  before(async () => {
+    const [configPDA] = getConfigPDA();
+    const existing = await provider.connection.getAccountInfo(configPDA);
+    if (existing) return;
+
+    await program.methods
+      .initializeConfig(configParams)
+      .accounts({
+        config: configPDA,
+        marketCounter: getCounterPDA()[0],
+        insuranceFund: getInsuranceFundPDA()[0],
+        admin: provider.wallet.publicKey,
+        // The deploying wallet is the program's upgrade authority
+        programData: web3.PublicKey.findProgramAddressSync(
+          [program.programId.toBuffer()],
+          new web3.PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+        )[0],
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+  });
+
+  // -------------------- Market Initialization --------------------
+
+  it("initializes a market", async () => {
+    // Every market is seeded from the creator's wallet
+    await airdropSol(marketCreator.publicKey, 100);
+
+    const now = Math.floor(Date.now() / 1000);
+    const resolutionTime = new BN(now + 7 * 24 * 60 * 60); // +7 days
+
+    const created = await createMarket(resolutionTime);
+    MARKET_ID = created.marketId;
+    const marketPDA = created.marketPDA;
+
     // If your account is named 'market' in the IDL, ensure the IDL is correct.
     // Otherwise, use the generic fetch method:
     const market = await program.account["market"].fetch(marketPDA);
 
-    assert.equal(market.marketId.toNumber(), 1);
+    assert.equal(market.marketId.toString(), MARKET_ID.toString());
     assert.equal(market.question, QUESTION);
     assert.equal(
       market.creator.toString(),
@@ -100,63 +873,367 @@ describe("prediction-market", () => {
     assert.equal(market.noPool.toNumber(), INITIAL_LIQUIDITY.toNumber()/2);
   });
 
-  // -------------------- Place Predictions --------------------
-
-  it("places a YES prediction", async () => {
-    await airdropSol(predictor1.publicKey, 2);
+  it("stores PDA bumps on markets and predictions", async () => {
+    const bettor = web3.Keypair.generate();
+    await airdropSol(bettor.publicKey, 1);
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId, marketPDA } = await createMarket(
+      new BN(now + 7 * 24 * 60 * 60)
+    );
 
-    const [marketPDA] = getMarketPDA(MARKET_ID);
-    const [vaultPDA] = getVaultPDA(MARKET_ID);
-    const [predictionPDA] = getPredictionPDA(MARKET_ID, predictor1.publicKey);
+    const market = await program.account["market"].fetch(marketPDA);
+    assert.equal(market.bump, getMarketPDA(marketId)[1]);
+    assert.equal(market.vaultBump, getVaultPDA(marketId)[1]);
 
-    const marketBefore = await program.account["market"].fetch(marketPDA);
-    const yesBefore = marketBefore.yesPool.toNumber();
+    const builder = await placePredictionIx(
+      marketId,
+      bettor,
+      true,
+      new BN(web3.LAMPORTS_PER_SOL / 10)
+    );
+    await builder.rpc();
+    const [predictionPDA, predictionBump] = getPredictionPDA(
+      marketId,
+      bettor.publicKey
+    );
+    const prediction = await program.account["prediction"].fetch(predictionPDA);
+    assert.equal(prediction.bump, predictionBump);
 
-    await program.methods
-      .placePrediction(MARKET_ID, true, PREDICTION_AMOUNT)
-      .accounts({
-        market: marketPDA,
-        marketVault: vaultPDA,
-        predictionAccount: predictionPDA,
-        predictor: predictor1.publicKey,
-        systemProgram: web3.SystemProgram.programId,
-      })
-      .signers([predictor1])
-      .rpc();
+    // Later instructions verify the PDAs with a single hash each
+    await assertSolvent(marketId);
+  });
 
-    const prediction = await program.account["prediction"].fetch(predictionPDA);
-    const marketAfter = await program.account["market"].fetch(marketPDA);
+  it("records the entry slot, pools and price on each prediction", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId, marketPDA } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+    const before = await program.account["market"].fetch(marketPDA);
+    const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+    const quote = await quotePrediction(marketId, predictor1.publicKey, true, amount);
+    const builder = await placePredictionIx(marketId, predictor1, true, amount);
+    const signature = await builder.rpc();
+    const tx = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
 
-    assert.equal(
-      prediction.predictor.toString(),
-      predictor1.publicKey.toString()
+    const prediction = await program.account["prediction"].fetch(
+      getPredictionPDA(marketId, predictor1.publicKey)[0]
     );
-    assert.equal(prediction.predictionType, true);
+    assert.equal(prediction.slot.toNumber(), tx.slot);
+    assert.equal(prediction.yesPoolAtEntry.toString(), before.yesPool.toString());
+    assert.equal(prediction.noPoolAtEntry.toString(), before.noPool.toString());
     assert.equal(
-      prediction.amountDeposited.toNumber(),
-      PREDICTION_AMOUNT.toNumber()
+      prediction.entryPriceBps.toString(),
+      quote.effectivePriceBps.toString()
     );
-    assert.isAbove(prediction.tokensReceived.toNumber(), 0);
-    assert.isAbove(marketAfter.yesPool.toNumber(), yesBefore);
   });
 
-  it("places a NO prediction", async () => {
-    await airdropSol(predictor2.publicKey, 2);
-
-    const [marketPDA] = getMarketPDA(MARKET_ID);
-    const [vaultPDA] = getVaultPDA(MARKET_ID);
-    const [predictionPDA] = getPredictionPDA(MARKET_ID, predictor2.publicKey);
+  it("versions new accounts and treats migration of them as a no-op", async () => {
+    const bettor = web3.Keypair.generate();
+    await airdropSol(bettor.publicKey, 1);
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId, marketPDA } = await createMarket(
+      new BN(now + 7 * 24 * 60 * 60)
+    );
+    const predictionPDA = await placePrediction(
+      marketId,
+      bettor,
+      false,
+      new BN(web3.LAMPORTS_PER_SOL / 10)
+    );
 
-    const marketBefore = await program.account["market"].fetch(marketPDA);
-    const noBefore = marketBefore.noPool.toNumber();
+    const market = await program.account["market"].fetch(marketPDA);
+    assert.equal(market.version, 5);
+    const prediction = await program.account["prediction"].fetch(predictionPDA);
+    assert.equal(prediction.version, 2);
 
-    await program.methods
-      .placePrediction(MARKET_ID, false, PREDICTION_AMOUNT)
+    const before = await provider.connection.getAccountInfo(marketPDA);
+    for (let i = 0; i < 2; i++) {
+      await program.methods
+        .migrateMarket(marketId)
+        .accounts({
+          market: marketPDA,
+          payer: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+    }
+    const after = await provider.connection.getAccountInfo(marketPDA);
+    assert.equal(after.data.length, before.data.length);
+    assert.equal(after.lamports, before.lamports);
+    assert.isTrue(after.data.equals(before.data));
+  });
+
+  it("resizes the market for a description and refunds rent on removal", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId, marketPDA } = await createMarket(
+      new BN(now + 7 * 24 * 60 * 60)
+    );
+    const baseSize = (await provider.connection.getAccountInfo(marketPDA))
+      .data.length;
+
+    const criteria =
+      "Resolves YES if the SOL/USD close on Coinbase at 23:59 UTC " +
+      "on the resolution date is strictly above $200.00. ".repeat(10);
+    await setDescription(marketId, criteria);
+
+    const grown = await provider.connection.getAccountInfo(marketPDA);
+    assert.equal(grown.data.length, baseSize + Buffer.byteLength(criteria));
+    const market = await program.account["market"].fetch(marketPDA);
+    assert.equal(market.description, criteria);
+
+    try {
+      await setDescription(marketId, "x".repeat(1025));
+      assert.fail("Should have thrown DescriptionTooLong");
+    } catch (e: any) {
+      assert.include(e.toString(), "DescriptionTooLong");
+    }
+
+    // Clearing it shrinks the account and returns the rent delta
+    const creatorBefore = await provider.connection.getBalance(
+      marketCreator.publicKey
+    );
+    await setDescription(marketId, "");
+    const shrunk = await provider.connection.getAccountInfo(marketPDA);
+    assert.equal(shrunk.data.length, baseSize);
+    const creatorAfter = await provider.connection.getBalance(
+      marketCreator.publicKey
+    );
+    assert.equal(creatorAfter - creatorBefore, grown.lamports - shrunk.lamports);
+
+    // Locked once betting starts
+    const bettor = web3.Keypair.generate();
+    await airdropSol(bettor.publicKey, 1);
+    await placePrediction(marketId, bettor, true, new BN(web3.LAMPORTS_PER_SOL / 10));
+    try {
+      await setDescription(marketId, "too late");
+      assert.fail("Should have thrown DescriptionLocked");
+    } catch (e: any) {
+      assert.include(e.toString(), "DescriptionLocked");
+    }
+  });
+
+  it("rejects resolution time just under the minimum duration", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    try {
+      await createMarket(new BN(now + MIN_MARKET_DURATION - 30));
+      assert.fail("Should have thrown ResolutionTooSoon");
+    } catch (e: any) {
+      assert.include(e.toString(), "ResolutionTooSoon");
+    }
+  });
+
+  it("accepts resolution time just over the minimum duration", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    const { marketPDA } = await createMarket(
+      new BN(now + MIN_MARKET_DURATION + 30)
+    );
+    const market = await program.account["market"].fetch(marketPDA);
+    assert.equal(market.resolved, false);
+  });
+
+  it("rejects resolution time just over the maximum duration", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    try {
+      await createMarket(new BN(now + MAX_MARKET_DURATION + 30));
+      assert.fail("Should have thrown ResolutionTooFar");
+    } catch (e: any) {
+      assert.include(e.toString(), "ResolutionTooFar");
+    }
+  });
+
+  it("accepts resolution time just under the maximum duration", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    const { marketPDA } = await createMarket(
+      new BN(now + MAX_MARKET_DURATION - 30)
+    );
+    const market = await program.account["market"].fetch(marketPDA);
+    assert.equal(market.resolved, false);
+  });
+
+  it("assigns sequential market IDs from the counter", async () => {
+    const [counterPDA] = getCounterPDA();
+    const now = Math.floor(Date.now() / 1000);
+    const resolutionTime = new BN(now + 7 * 24 * 60 * 60);
+
+    const first = await createMarket(resolutionTime);
+    const second = await createMarket(resolutionTime);
+    assert.equal(
+      second.marketId.toString(),
+      first.marketId.addn(1).toString()
+    );
+
+    const counter = await program.account["marketCounter"].fetch(counterPDA);
+    assert.equal(
+      counter.nextId.toString(),
+      second.marketId.addn(1).toString()
+    );
+  });
+
+  it("rejects explicit market IDs outside the reserved range", async () => {
+    const marketId = new BN(5);
+    const [marketPDA] = getMarketPDA(marketId);
+    const [vaultPDA] = getVaultPDA(marketId);
+    const now = Math.floor(Date.now() / 1000);
+    const resolutionTime = new BN(now + 7 * 24 * 60 * 60);
+
+    try {
+      await program.methods
+        .initializeMarketWithId(
+          marketId,
+          QUESTION,
+          resolutionTime,
+          INITIAL_LIQUIDITY,
+          5_000,
+          0,
+          DEFAULT_MARKET_OPTIONS
+        )
+        .accounts({
+          market: marketPDA,
+          config: getConfigPDA()[0],
+          registryPage: (await getNextRegistryPagePDA())[0],
+          creator: marketCreator.publicKey,
+          marketVault: vaultPDA,
+          questionRegistry: getQuestionRegistryPDA(QUESTION, resolutionTime)[0],
+          duplicateApprover: null,
+          yesMint: null,
+          noMint: null,
+          series: null,
+          parentMarket: null,
+          creatorAllow: null,
+          creatorStats: getCreatorStatsPDA(marketCreator.publicKey),
+          treasury: null,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([marketCreator])
+        .rpc();
+      assert.fail("Should have thrown InvalidMarketId");
+    } catch (e: any) {
+      assert.include(e.toString(), "InvalidMarketId");
+    }
+  });
+
+  it("rolls the market registry over to a new page when full", async () => {
+    const [configPDA] = getConfigPDA();
+    const now = Math.floor(Date.now() / 1000);
+    const resolutionTime = new BN(now + 7 * 24 * 60 * 60);
+
+    // Fill whatever remains of the current page
+    let config = await program.account["config"].fetch(configPDA);
+    const startPage = config.registryLen.divn(REGISTRY_PAGE_SIZE);
+    const remaining =
+      REGISTRY_PAGE_SIZE - config.registryLen.modn(REGISTRY_PAGE_SIZE);
+    const created: web3.PublicKey[] = [];
+    for (let i = 0; i < remaining; i++) {
+      created.push((await createMarket(resolutionTime)).marketPDA);
+    }
+
+    const fullPage = await program.account["marketRegistryPage"].fetch(
+      getRegistryPagePDA(startPage)[0]
+    );
+    assert.equal(fullPage.count, REGISTRY_PAGE_SIZE);
+    assert.equal(
+      fullPage.markets[REGISTRY_PAGE_SIZE - 1].toString(),
+      created[created.length - 1].toString()
+    );
+
+    // The next market lands on a freshly created page
+    const { marketPDA } = await createMarket(resolutionTime);
+    const nextPageIndex = startPage.addn(1);
+    const nextPage = await program.account["marketRegistryPage"].fetch(
+      getRegistryPagePDA(nextPageIndex)[0]
+    );
+    assert.equal(nextPage.pageIndex.toString(), nextPageIndex.toString());
+    assert.equal(nextPage.count, 1);
+    assert.equal(nextPage.markets[0].toString(), marketPDA.toString());
+
+    config = await program.account["config"].fetch(configPDA);
+    assert.equal(config.registryHead.toString(), nextPageIndex.toString());
+  });
+
+  // -------------------- Place Predictions --------------------
+
+  it("places a YES prediction", async () => {
+    await airdropSol(predictor1.publicKey, 2);
+
+    const [marketPDA] = getMarketPDA(MARKET_ID);
+    const [vaultPDA] = getVaultPDA(MARKET_ID);
+    const [predictionPDA] = getPredictionPDA(MARKET_ID, predictor1.publicKey);
+
+    const marketBefore = await program.account["market"].fetch(marketPDA);
+    const yesBefore = marketBefore.yesPool.toNumber();
+
+    await program.methods
+      .placePrediction(MARKET_ID, true, PREDICTION_AMOUNT, 0, "", false)
+      .accounts({
+        market: marketPDA,
+        marketVault: vaultPDA,
+        config: getConfigPDA()[0],
+        predictionAccount: predictionPDA,
+        userProfile: getProfilePDA(predictor1.publicKey)[0],
+        ...(await getPositionIndexAccounts(MARKET_ID, predictor1.publicKey)),
+        epochStats: getEpochStatsPDA(predictor1.publicKey, await currentEpoch())[0],
+        insuranceFund: getInsuranceFundPDA()[0],
+        referrer: null,
+        nftTokenAccount: null,
+        nftMetadata: null,
+        series: null,
+        creatorStats: getCreatorStatsPDA(marketCreator.publicKey),
+        predictor: predictor1.publicKey,
+        rentPayer: predictor1.publicKey,
+        wsolVault: null,
+        tokenProgram: null,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .signers([predictor1])
+      .rpc();
+
+    const prediction = await program.account["prediction"].fetch(predictionPDA);
+    const marketAfter = await program.account["market"].fetch(marketPDA);
+
+    assert.equal(
+      prediction.predictor.toString(),
+      predictor1.publicKey.toString()
+    );
+    assert.equal(prediction.predictionType, true);
+    assert.equal(
+      prediction.amountDeposited.toNumber(),
+      PREDICTION_AMOUNT.toNumber()
+    );
+    assert.isAbove(prediction.tokensReceived.toNumber(), 0);
+    assert.isAbove(marketAfter.yesPool.toNumber(), yesBefore);
+  });
+
+  it("places a NO prediction", async () => {
+    await airdropSol(predictor2.publicKey, 2);
+
+    const [marketPDA] = getMarketPDA(MARKET_ID);
+    const [vaultPDA] = getVaultPDA(MARKET_ID);
+    const [predictionPDA] = getPredictionPDA(MARKET_ID, predictor2.publicKey);
+
+    const marketBefore = await program.account["market"].fetch(marketPDA);
+    const noBefore = marketBefore.noPool.toNumber();
+
+    await program.methods
+      .placePrediction(MARKET_ID, false, PREDICTION_AMOUNT, 0, "", false)
       .accounts({
         market: marketPDA,
         marketVault: vaultPDA,
+        config: getConfigPDA()[0],
         predictionAccount: predictionPDA,
+        userProfile: getProfilePDA(predictor2.publicKey)[0],
+        ...(await getPositionIndexAccounts(MARKET_ID, predictor2.publicKey)),
+        epochStats: getEpochStatsPDA(predictor2.publicKey, await currentEpoch())[0],
+        insuranceFund: getInsuranceFundPDA()[0],
+        referrer: null,
+        nftTokenAccount: null,
+        nftMetadata: null,
+        series: null,
+        creatorStats: getCreatorStatsPDA(marketCreator.publicKey),
         predictor: predictor2.publicKey,
+        rentPayer: predictor2.publicKey,
+        wsolVault: null,
+        tokenProgram: null,
         systemProgram: web3.SystemProgram.programId,
       })
       .signers([predictor2])
@@ -169,6 +1246,9558 @@ describe("prediction-market", () => {
     assert.isAbove(marketAfter.noPool.toNumber(), noBefore);
   });
 
+  it("tracks volume and participation stats across bettors", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId, marketPDA } = await createMarket(
+      new BN(now + 7 * 24 * 60 * 60)
+    );
+
+    const bettors = [
+      web3.Keypair.generate(),
+      web3.Keypair.generate(),
+      web3.Keypair.generate(),
+    ];
+    const amounts = [
+      new BN(web3.LAMPORTS_PER_SOL / 10),
+      new BN(web3.LAMPORTS_PER_SOL / 4),
+      new BN(web3.LAMPORTS_PER_SOL / 5),
+    ];
+    for (let i = 0; i < bettors.length; i++) {
+      await airdropSol(bettors[i].publicKey, 1);
+      await placePrediction(marketId, bettors[i], i % 2 === 0, amounts[i]);
+    }
+
+    const market = await program.account["market"].fetch(marketPDA);
+    const expectedVolume = amounts.reduce((acc, a) => acc.add(a), new BN(0));
+    assert.equal(market.totalVolume.toString(), expectedVolume.toString());
+    assert.equal(market.tradeCount, 3);
+    assert.equal(market.uniqueBettors, 3);
+    assert.equal(market.sequence.toNumber(), 3);
+  });
+
+  it("emits pool snapshots and implied probability with each trade", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+
+    const events: any[] = [];
+    for (const side of [true, false, true]) {
+      const bettor = web3.Keypair.generate();
+      await airdropSol(bettor.publicKey, 1);
+      const builder = await placePredictionIx(
+        marketId,
+        bettor,
+        side,
+        new BN(web3.LAMPORTS_PER_SOL / 5)
+      );
+      const signature = await builder.rpc();
+      for (const event of await getCpiEvents(signature)) {
+        if (isEvent(event, "PredictionPlaced")) events.push(event.data);
+      }
+    }
+
+    assert.equal(events.length, 3);
+    events.forEach((event, i) => {
+      assert.equal(event.sequence.toNumber(), i + 1);
+      const total = event.yesPool.add(event.noPool);
+      const expected = event.yesPool.muln(10_000).div(total).toNumber();
+      assert.equal(event.impliedYesProbabilityBps, expected);
+    });
+  });
+
+  it("records wins and losses on user profiles", async () => {
+    const winner = web3.Keypair.generate();
+    const loser = web3.Keypair.generate();
+    await airdropSol(winner.publicKey, 1);
+    await airdropSol(loser.publicKey, 1);
+
+    const { marketId } = await createShortMarket(5);
+    const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+    await placePrediction(marketId, winner, true, amount);
+    await placePrediction(marketId, loser, false, amount);
+
+    let profile = await program.account["userProfile"].fetch(
+      getProfilePDA(winner.publicKey)[0]
+    );
+    assert.equal(profile.betsPlaced, 1);
+    assert.equal(profile.totalWagered.toString(), amount.toString());
+
+    await sleep(6000);
+    await resolveMarket(marketId, true);
+    await claimReward(marketId, winner);
+
+    profile = await program.account["userProfile"].fetch(
+      getProfilePDA(winner.publicKey)[0]
+    );
+    assert.equal(profile.marketsWon, 1);
+    assert.isAbove(profile.totalWon.toNumber(), 0);
+
+    await program.methods
+      .recordLoss(marketId)
+      .accounts({
+        market: getMarketPDA(marketId)[0],
+        predictionAccount: getPredictionPDA(marketId, loser.publicKey)[0],
+        userProfile: getProfilePDA(loser.publicKey)[0],
+        payer: provider.wallet.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .rpc();
+
+    profile = await program.account["userProfile"].fetch(
+      getProfilePDA(loser.publicKey)[0]
+    );
+    assert.equal(profile.marketsLost, 1);
+    assert.equal(profile.marketsWon, 0);
+  });
+
+  it("reports claimable amounts matching the actual claim payout", async () => {
+    const { marketId } = await createShortMarket(5);
+    const stakes: [boolean, number][] = [
+      [true, web3.LAMPORTS_PER_SOL / 10],
+      [true, 7_777_777],
+      [true, web3.LAMPORTS_PER_SOL / 3],
+      [false, web3.LAMPORTS_PER_SOL / 4],
+    ];
+    const bettors: web3.Keypair[] = [];
+    for (const [side, lamports] of stakes) {
+      const bettor = web3.Keypair.generate();
+      await airdropSol(bettor.publicKey, 1);
+      await placePrediction(marketId, bettor, side, new BN(lamports));
+      bettors.push(bettor);
+    }
+
+    let claimable = await getClaimable(marketId, bettors[0].publicKey);
+    assert.deepEqual(claimable.status, { unresolved: {} });
+    assert.equal(claimable.amount.toNumber(), 0);
+    assert.isNull(claimable.deadline);
+
+    await sleep(6000);
+    await resolveMarket(marketId, true);
+
+    claimable = await getClaimable(marketId, bettors[3].publicKey);
+    assert.deepEqual(claimable.status, { lost: {} });
+    assert.equal(claimable.amount.toNumber(), 0);
+
+    for (const winner of bettors.slice(0, 3)) {
+      const quoted = await getClaimable(marketId, winner.publicKey);
+      assert.deepEqual(quoted.status, { won: {} });
+
+      const before = await provider.connection.getBalance(winner.publicKey);
+      await claimReward(marketId, winner);
+      const after = await provider.connection.getBalance(winner.publicKey);
+      // Fee payer is the provider wallet, so the delta is the reward alone
+      assert.equal(after - before, quoted.amount.toNumber());
+
+      const claimed = await getClaimable(marketId, winner.publicKey);
+      assert.deepEqual(claimed.status, { claimed: {} });
+      assert.equal(claimed.amount.toNumber(), 0);
+    }
+  });
+
+  it("keeps the vault solvent from seeding through every claim", async () => {
+    const { marketId, marketPDA } = await createShortMarket(5);
+    const [vaultPDA] = getVaultPDA(marketId);
+    const rent = await provider.connection.getMinimumBalanceForRentExemption(0);
+
+    // The seed is deposited at creation
+    const seeded = await provider.connection.getBalance(vaultPDA);
+    assert.equal(seeded - rent, INITIAL_LIQUIDITY.toNumber());
+    await assertSolvent(marketId);
+
+    const winners: web3.Keypair[] = [];
+    for (const [side, lamports] of [
+      [true, web3.LAMPORTS_PER_SOL / 7],
+      [true, web3.LAMPORTS_PER_SOL / 3],
+      [false, web3.LAMPORTS_PER_SOL / 2],
+    ] as [boolean, number][]) {
+      const bettor = web3.Keypair.generate();
+      await airdropSol(bettor.publicKey, 1);
+      await placePrediction(marketId, bettor, side, new BN(lamports));
+      if (side) winners.push(bettor);
+      await assertSolvent(marketId);
+    }
+
+    await sleep(6000);
+    await resolveMarket(marketId, true);
+    await assertSolvent(marketId);
+
+    for (const winner of winners) {
+      await claimReward(marketId, winner);
+      await assertSolvent(marketId);
+    }
+
+    const market = await program.account["market"].fetch(marketPDA);
+    assert.equal(market.yesTokens.toNumber(), 0);
+    assert.isAbove(market.totalClaimed.toNumber(), 0);
+  });
+
+  it("splits activity across a leaderboard epoch boundary", async () => {
+    const user = web3.Keypair.generate();
+    await airdropSol(user.publicKey, 1);
+
+    const advanceEpoch = async () => {
+      await program.methods
+        .advanceEpoch()
+        .accounts({
+          config: getConfigPDA()[0],
+          leaderboard: getLeaderboardPDA(await currentEpoch())[0],
+          payer: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+    };
+
+    const defaultEpochLength = configParams.epochLength;
+    await updateConfig({ epochLength: new BN(2) });
+    try {
+      // Bet in epoch N, claim in epoch N+1
+      await sleep(3000);
+      await advanceEpoch();
+      const betEpoch = await currentEpoch();
+      const { marketId } = await createShortMarket(5);
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+      await placePrediction(marketId, user, true, amount);
+      await placePrediction(marketId, predictor2, false, amount);
+
+      await sleep(6000);
+      await advanceEpoch();
+      const claimEpoch = await currentEpoch();
+      await resolveMarket(marketId, true);
+      await claimReward(marketId, user);
+
+      const betStats = await program.account["userEpochStats"].fetch(
+        getEpochStatsPDA(user.publicKey, betEpoch)[0]
+      );
+      assert.equal(betStats.betsPlaced, 1);
+      assert.equal(betStats.totalWagered.toString(), amount.toString());
+      assert.equal(betStats.totalWon.toNumber(), 0);
+
+      const claimStats = await program.account["userEpochStats"].fetch(
+        getEpochStatsPDA(user.publicKey, claimEpoch)[0]
+      );
+      assert.equal(claimStats.betsPlaced, 0);
+      assert.equal(claimStats.marketsWon, 1);
+      assert.isAbove(claimStats.totalWon.toNumber(), 0);
+
+      // Advancing too early fails
+      try {
+        await advanceEpoch();
+        assert.fail("Should have thrown EpochNotOver");
+      } catch (e: any) {
+        assert.include(e.toString(), "EpochNotOver");
+      }
+
+      // Once the claim epoch closes, its winner can be proven on-chain
+      await sleep(3000);
+      await advanceEpoch();
+      await program.methods
+        .submitEpochScore(claimEpoch)
+        .accounts({
+          leaderboard: getLeaderboardPDA(claimEpoch)[0],
+          epochStats: getEpochStatsPDA(user.publicKey, claimEpoch)[0],
+        })
+        .rpc();
+
+      const leaderboard = await program.account["leaderboardEpoch"].fetch(
+        getLeaderboardPDA(claimEpoch)[0]
+      );
+      assert.equal(leaderboard.leader.toString(), user.publicKey.toString());
+      assert.equal(
+        leaderboard.leaderScore.toString(),
+        claimStats.totalWon.toString()
+      );
+    } finally {
+      await updateConfig({ epochLength: defaultEpochLength });
+    }
+  });
+
+  it("pays referrers a share of the trading fee", async () => {
+    const referrerOwner = web3.Keypair.generate();
+    const bettor = web3.Keypair.generate();
+    await airdropSol(referrerOwner.publicKey, 1);
+    await airdropSol(bettor.publicKey, 1);
+
+    const [referrerPDA] = getReferrerPDA(referrerOwner.publicKey);
+    await program.methods
+      .registerReferrer()
+      .accounts({
+        referrer: referrerPDA,
+        owner: referrerOwner.publicKey,
+        systemProgram: web3.SystemProgram.programId,
+      })
+      .signers([referrerOwner])
+      .rpc();
+
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId, marketPDA } = await createMarket(
+      new BN(now + 7 * 24 * 60 * 60)
+    );
+
+    // Self-referral is rejected
+    try {
+      await placePrediction(
+        marketId,
+        referrerOwner,
+        true,
+        new BN(web3.LAMPORTS_PER_SOL / 10),
+        referrerPDA
+      );
+      assert.fail("Should have thrown SelfReferral");
+    } catch (e: any) {
+      assert.include(e.toString(), "SelfReferral");
+    }
+
+    const amount = new BN(web3.LAMPORTS_PER_SOL / 2);
+    const predictionPDA = await placePrediction(
+      marketId,
+      bettor,
+      true,
+      amount,
+      referrerPDA
+    );
+
+    const fee = amount.muln(configParams.tradingFeeBps).divn(10_000);
+    const referralCut = fee.muln(configParams.referralFeeBps).divn(10_000);
+
+    const referrer = await program.account["referrer"].fetch(referrerPDA);
+    assert.equal(referrer.accruedFees.toString(), referralCut.toString());
+
+    const market = await program.account["market"].fetch(marketPDA);
+    assert.equal(market.feeCollected.toString(), fee.sub(referralCut).toString());
+
+    const prediction = await program.account["prediction"].fetch(predictionPDA);
+    assert.equal(
+      prediction.referrer.toString(),
+      referrerOwner.publicKey.toString()
+    );
+
+    const balanceBefore = await provider.connection.getBalance(
+      referrerOwner.publicKey
+    );
+    await program.methods
+      .claimReferralFees()
+      .accounts({
+        referrer: referrerPDA,
+        owner: referrerOwner.publicKey,
+      })
+      .signers([referrerOwner])
+      .rpc();
+    const balanceAfter = await provider.connection.getBalance(
+      referrerOwner.publicKey
+    );
+    // Fee payer is the provider wallet, so the full cut lands on the owner
+    assert.equal(balanceAfter - balanceBefore, referralCut.toNumber());
+  });
+
+  describe("Config timelock", () => {
+    const DELAY = 2;
+    const [pendingPDA] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("pending_config")],
+      program.programId
+    );
+    const adminAccounts = {
+      config: getConfigPDA()[0],
+      pendingConfig: pendingPDA,
+      admin: provider.wallet.publicKey,
+    };
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const propose = (overrides: any) =>
+      program.methods
+        .proposeConfigUpdate({ ...configParams, ...overrides })
+        .accounts({ ...adminAccounts, systemProgram: web3.SystemProgram.programId })
+        .rpc();
+    const execute = () =>
+      program.methods.executeConfigUpdate().accounts(adminAccounts).rpc();
+    const cancel = () =>
+      program.methods.cancelConfigUpdate().accounts(adminAccounts).rpc();
+
+    before(async () => {
+      await updateConfig({ configDelaySecs: new BN(DELAY) });
+    });
+
+    after(async () => {
+      await propose({ configDelaySecs: new BN(0) });
+      await sleep((DELAY + 1) * 1000);
+      await execute();
+      configParams.configDelaySecs = new BN(0);
+    });
+
+    it("rejects direct updates and executing before the delay", async () => {
+      await expectError(
+        program.methods
+          .updateConfig({ ...configParams, tradingFeeBps: 150 })
+          .accounts({ config: getConfigPDA()[0], admin: provider.wallet.publicKey })
+          .rpc(),
+        "TimelockRequired"
+      );
+
+      await propose({ tradingFeeBps: 150 });
+      await expectError(execute(), "TimelockNotElapsed");
+
+      await sleep((DELAY + 1) * 1000);
+      await execute();
+      let config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.equal(config.tradingFeeBps, 150);
+
+      await propose({});
+      await sleep((DELAY + 1) * 1000);
+      await execute();
+      config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.equal(config.tradingFeeBps, configParams.tradingFeeBps);
+    });
+
+    it("rejects direct parameter setters while the timelock is on", async () => {
+      const accounts = { config: getConfigPDA()[0], admin: provider.wallet.publicKey };
+      await expectError(
+        program.methods.setKeeperTips(Array(6).fill(new BN(1))).accounts(accounts).rpc(),
+        "TimelockRequired"
+      );
+      await expectError(
+        program.methods.setFeatureFlags(new BN(0)).accounts(accounts).rpc(),
+        "TimelockRequired"
+      );
+      await expectError(
+        program.methods.setEscheatSplit(0).accounts(accounts).rpc(),
+        "TimelockRequired"
+      );
+      await expectError(
+        program.methods.setCategoryDefaults([]).accounts(accounts).rpc(),
+        "TimelockRequired"
+      );
+    });
+
+    it("cannot execute a cancelled proposal", async () => {
+      await propose({ tradingFeeBps: 500 });
+      await cancel();
+      await sleep((DELAY + 1) * 1000);
+      await expectError(execute(), "AccountNotInitialized");
+
+      const config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.equal(config.tradingFeeBps, configParams.tradingFeeBps);
+    });
+
+    it("pauses immediately but unpauses only through the timelock", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+
+      await program.methods
+        .pauseProtocol()
+        .accounts({ config: getConfigPDA()[0], admin: provider.wallet.publicKey })
+        .rpc();
+      await expectError(
+        placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT),
+        "ProtocolPaused"
+      );
+
+      await propose({ paused: false });
+      await expectError(execute(), "TimelockNotElapsed");
+      await sleep((DELAY + 1) * 1000);
+      await execute();
+
+      const config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.isFalse(config.paused);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+    });
+  });
+
+  describe("Market ownership transfer", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const transferIx = (
+      method: "proposeMarketTransfer" | "cancelMarketTransfer",
+      marketId: BN,
+      creator: web3.Keypair,
+      newOwner?: web3.PublicKey
+    ) =>
+      (method === "proposeMarketTransfer"
+        ? program.methods.proposeMarketTransfer(marketId, newOwner)
+        : program.methods.cancelMarketTransfer(marketId)
+      )
+        .accounts({ market: getMarketPDA(marketId)[0], creator: creator.publicKey })
+        .signers([creator])
+        .rpc();
+
+    const accept = (marketId: BN, newOwner: web3.Keypair) =>
+      program.methods
+        .acceptMarketTransfer(marketId)
+        .accounts({ market: getMarketPDA(marketId)[0], newOwner: newOwner.publicKey })
+        .signers([newOwner])
+        .rpc();
+
+    it("keeps the current owner in control until the nominee accepts", async () => {
+      const { marketId, marketPDA } = await createShortMarket(2);
+      await transferIx("proposeMarketTransfer", marketId, marketCreator, predictor1.publicKey);
+      await sleep(3000);
+
+      await expectError(resolveMarket(marketId, true, predictor1), "Unauthorized");
+      await expectError(accept(marketId, predictor2), "Unauthorized");
+
+      await accept(marketId, predictor1);
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.creator.toBase58(), predictor1.publicKey.toBase58());
+      assert.isNull(market.pendingOwner);
+
+      await expectError(resolveMarket(marketId, true), "Unauthorized");
+      await resolveMarket(marketId, true, predictor1);
+    });
+
+    it("lets the owner cancel a pending transfer", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await transferIx("proposeMarketTransfer", marketId, marketCreator, predictor2.publicKey);
+      await transferIx("cancelMarketTransfer", marketId, marketCreator);
+
+      await expectError(accept(marketId, predictor2), "Unauthorized");
+      await expectError(
+        transferIx("cancelMarketTransfer", marketId, marketCreator),
+        "NoPendingTransfer"
+      );
+    });
+  });
+
+  describe("Emergency cancel", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const cancel = (marketId: BN, admin?: web3.Keypair) =>
+      program.methods
+        .emergencyCancelMarket(marketId, "question cannot be resolved")
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          admin: admin ? admin.publicKey : provider.wallet.publicKey,
+        })
+        .signers(admin ? [admin] : [])
+        .rpc();
+
+    const claimRefund = (marketId: BN, claimer: web3.Keypair) =>
+      program.methods
+        .claimRefund(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, claimer.publicKey)[0],
+          claimer: claimer.publicKey,
+          wsolVault: null,
+          payoutTokenAccount: null,
+          collateralMint: null,
+          tokenProgram: null,
+        })
+        .signers([claimer])
+        .rpc();
+
+    it("voids a live market and refunds every deposit exactly", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId, marketPDA } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+
+      await expectError(cancel(marketId, predictor2), "Unauthorized");
+      await cancel(marketId);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.resolved);
+      assert.deepEqual(market.outcome, { void: {} });
+      await expectError(
+        placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT),
+        "MarketAlreadyResolved"
+      );
+
+      const before = await provider.connection.getBalance(predictor1.publicKey);
+      await claimRefund(marketId, predictor1);
+      assert.equal(
+        await provider.connection.getBalance(predictor1.publicKey),
+        before + PREDICTION_AMOUNT.toNumber()
+      );
+      await expectError(claimRefund(marketId, predictor1), "AlreadyClaimed");
+
+      const creatorBefore = await provider.connection.getBalance(marketCreator.publicKey);
+      await program.methods
+        .refundLiquidity(marketId)
+        .accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          creator: marketCreator.publicKey,
+          wsolVault: null,
+          payoutTokenAccount: null,
+          collateralMint: null,
+          tokenProgram: null,
+        })
+        .signers([marketCreator])
+        .rpc();
+      assert.equal(
+        await provider.connection.getBalance(marketCreator.publicKey),
+        creatorBefore + INITIAL_LIQUIDITY.toNumber()
+      );
+    });
+
+    it("refuses to cancel a resolved market", async () => {
+      const { marketId } = await createShortMarket(2);
+      await sleep(3000);
+      await resolveMarket(marketId, true);
+      await expectError(cancel(marketId), "MarketAlreadyResolved");
+    });
+  });
+
+  describe("Resolution correction window", () => {
+    const WINDOW = 4;
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const amend = (marketId: BN, outcome: boolean) =>
+      program.methods
+        .amendResolution(marketId, outcome, "")
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          resolution: getResolutionPDA(marketId),
+          admin: marketCreator.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([marketCreator])
+        .rpc();
+
+    // A market with a YES and a NO bet, resolved YES
+    const resolvedMarket = async () => {
+      const created = await createShortMarket(3, marketCreator, {
+        correctionWindowSecs: new BN(WINDOW),
+      });
+      await placePrediction(created.marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(created.marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(created.marketId, true);
+      return created;
+    };
+
+    it("amends before any claim and holds claims until the window passes", async () => {
+      const { marketId, marketPDA } = await resolvedMarket();
+      await expectError(claimReward(marketId, predictor1), "ClaimWindowNotOpen");
+
+      await amend(marketId, false);
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.outcome, { no: {} });
+
+      await sleep((WINDOW + 1) * 1000);
+      await expectError(amend(marketId, true), "CorrectionWindowClosed");
+      await claimReward(marketId, predictor2);
+    });
+
+    it("refuses to amend after a claim", async () => {
+      const { marketId } = await resolvedMarket();
+      await sleep((WINDOW + 1) * 1000);
+      await claimReward(marketId, predictor1);
+      await expectError(amend(marketId, false), "CorrectionWindowClosed");
+    });
+  });
+
+  describe("Pro-rata payouts", () => {
+    it("records winner entitlement at resolution and pays it in full when covered", async () => {
+      const { marketId, marketPDA } = await createShortMarket(3);
+      const winners = [predictor1, predictor2];
+      for (const winner of winners) {
+        await placePrediction(marketId, winner, true, PREDICTION_AMOUNT);
+      }
+      const loser = web3.Keypair.generate();
+      await airdropSol(loser.publicKey, 1);
+      await placePrediction(marketId, loser, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      let market = await program.account["market"].fetch(marketPDA);
+      const pot = market.yesPool.add(market.noPool);
+      assert.equal(
+        market.outstandingEntitlement.toString(),
+        market.yesTokens.mul(pot).div(market.yesPool).toString()
+      );
+
+      for (const winner of winners) {
+        const events = await getCpiEvents(await claimReward(marketId, winner));
+        const claimed = events.find((e) => isEvent(e, "RewardClaimed"));
+        assert.equal(claimed.data.payoutBps, 10_000);
+        assert.equal(claimed.data.reward.toString(), claimed.data.entitled.toString());
+        assert.equal(
+          claimed.data.normalizedAmount.toString(),
+          claimed.data.reward.toString()
+        );
+      }
+
+      // Only per-claim rounding is left over
+      market = await program.account["market"].fetch(marketPDA);
+      assert.isAtMost(market.outstandingEntitlement.toNumber(), winners.length);
+    });
+  });
+
+  describe("Vault reconciliation", () => {
+    const reconcile = (marketId: BN) =>
+      program.methods
+        .reconcileVault(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+        })
+        .rpc();
+
+    it("records zero drift for a balanced vault and a surplus without halting", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId, marketPDA } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+
+      const events = await getCpiEvents(await reconcile(marketId));
+      const reconciled = events.find((e) => isEvent(e, "VaultReconciled"));
+      assert.equal(reconciled.data.driftLamports.toNumber(), 0);
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.isAbove(market.lastReconciledAt.toNumber(), 0);
+      assert.isFalse(market.needsAttention);
+
+      // Insurance paid into a solvent vault is surplus, not a fault
+      const [fundPDA] = getInsuranceFundPDA();
+      const extra = new BN(50_000);
+      await program.methods
+        .fundInsurance(extra)
+        .accounts({
+          insuranceFund: fundPDA,
+          donor: predictor2.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([predictor2])
+        .rpc();
+      await program.methods
+        .coverShortfall(marketId, extra, "reconciliation test")
+        .accounts({
+          config: getConfigPDA()[0],
+          insuranceFund: fundPDA,
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          admin: provider.wallet.publicKey,
+        })
+        .rpc();
+      await reconcile(marketId);
+
+      market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.driftLamports.toString(), extra.toString());
+      assert.isFalse(market.needsAttention);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+
+      try {
+        await program.methods
+          .clearAttention(marketId)
+          .accounts({
+            config: getConfigPDA()[0],
+            market: marketPDA,
+            admin: provider.wallet.publicKey,
+          })
+          .rpc();
+        assert.fail("Should have thrown NoAttentionNeeded");
+      } catch (e: any) {
+        assert.include(e.toString(), "NoAttentionNeeded");
+      }
+    });
+  });
+
+  describe("Dust sweep", () => {
+    const treasury = web3.Keypair.generate();
+    const CLAIM_PERIOD = 3;
+
+    const sweep = (marketId: BN) =>
+      program.methods
+        .sweepDust(marketId)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          treasury: treasury.publicKey,
+        })
+        .rpc();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    before(async () => {
+      await airdropSol(treasury.publicKey, 1);
+      await updateConfig({ treasury: treasury.publicKey });
+    });
+
+    after(async () => {
+      await updateConfig({ treasury: provider.wallet.publicKey });
+    });
+
+    it("sweeps exactly the unclaimable remainder, once", async () => {
+      const { marketId, marketPDA } = await createShortMarket(3, marketCreator, {
+        claimPeriodSecs: new BN(CLAIM_PERIOD),
+      });
+      // Odd stakes leave a rounding remainder in every reward
+      const winners: web3.Keypair[] = [];
+      for (const [side, lamports] of [
+        [true, Math.floor(web3.LAMPORTS_PER_SOL / 7)],
+        [true, Math.floor(web3.LAMPORTS_PER_SOL / 3)],
+        [false, Math.floor(web3.LAMPORTS_PER_SOL / 11)],
+      ] as [boolean, number][]) {
+        const bettor = web3.Keypair.generate();
+        await airdropSol(bettor.publicKey, 1);
+        await placePrediction(marketId, bettor, side, new BN(lamports));
+        if (side) winners.push(bettor);
+      }
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+      await claimReward(marketId, winners[0]);
+
+      // A winner yet to claim holds the sweep back until the deadline
+      await expectError(sweep(marketId), "ClaimPeriodActive");
+      await claimReward(marketId, winners[1]);
+
+      // With every winner paid, the rounding remainder goes before it
+      const [vaultPDA] = getVaultPDA(marketId);
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(0);
+      const vault = await provider.connection.getBalance(vaultPDA);
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.fullySettled);
+      assert.equal(market.outstandingEntitlement.toString(), "0");
+      const expected = new BN(vault - rent).sub(market.feeCollected);
+      assert.isTrue(expected.gte(market.roundingDustAccrued));
+      assert.isTrue(expected.gtn(0));
+
+      const before = await provider.connection.getBalance(treasury.publicKey);
+      await sweep(marketId);
+      assert.equal(
+        await provider.connection.getBalance(treasury.publicKey),
+        before + expected.toNumber()
+      );
+      market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.dustSwept.toString(), expected.toString());
+      await assertSolvent(marketId);
+
+      await expectError(sweep(marketId), "DustAlreadySwept");
+    });
+  });
+
+  describe("Market sponsorship", () => {
+    const SPONSORSHIP = new BN(200_000_000);
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const getSponsorshipPDA = (marketId: BN, sponsor: web3.PublicKey) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("sponsorship"), marketId.toArrayLike(Buffer, "le", 8), sponsor.toBuffer()],
+        program.programId
+      );
+
+    const sponsor = (marketId: BN, amount: BN, name: string | null = null) =>
+      program.methods
+        .sponsorMarket(marketId, amount, name)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          sponsorship: getSponsorshipPDA(marketId, predictor2.publicKey)[0],
+          sponsor: predictor2.publicKey,
+        })
+        .signers([predictor2])
+        .rpc();
+
+    it("pays sponsorships to winners on top of the pot", async () => {
+      const { marketId, marketPDA } = await createShortMarket(3);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      const events = await getCpiEvents(await sponsor(marketId, SPONSORSHIP, "Acme"));
+      const sponsored = events.find((e) => isEvent(e, "MarketSponsored"));
+      assert.equal(sponsored.data.sponsorName, "Acme");
+      assert.equal(sponsored.data.sponsoredAmount.toString(), SPONSORSHIP.toString());
+
+      const record = await program.account["sponsorship"].fetch(
+        getSponsorshipPDA(marketId, predictor2.publicKey)[0]
+      );
+      assert.equal(record.amount.toString(), SPONSORSHIP.toString());
+      assert.equal(record.name, "Acme");
+
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+      await expectError(sponsor(marketId, SPONSORSHIP), "MarketAlreadyResolved");
+
+      const market = await program.account["market"].fetch(marketPDA);
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, predictor1.publicKey)[0]
+      );
+      const pot = market.yesPool.add(market.noPool).add(market.sponsoredAmount);
+      const claimed = (await getCpiEvents(await claimReward(marketId, predictor1))).find((e) =>
+        isEvent(e, "RewardClaimed")
+      );
+      assert.equal(
+        claimed.data.reward.toString(),
+        prediction.tokensReceived.mul(pot).div(market.yesPool).toString()
+      );
+      await assertSolvent(marketId);
+    });
+
+    it("returns sponsorships of a voided market", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId, marketPDA } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await sponsor(marketId, SPONSORSHIP);
+      await program.methods
+        .emergencyCancelMarket(marketId, "question cannot be resolved")
+        .accounts({
+          config: getConfigPDA()[0],
+          market: marketPDA,
+          admin: provider.wallet.publicKey,
+        })
+        .rpc();
+
+      const [sponsorshipPDA] = getSponsorshipPDA(marketId, predictor2.publicKey);
+      const rent = await provider.connection.getBalance(sponsorshipPDA);
+      const before = await provider.connection.getBalance(predictor2.publicKey);
+      await program.methods
+        .refundSponsorship(marketId)
+        .accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          sponsorship: sponsorshipPDA,
+          sponsor: predictor2.publicKey,
+        })
+        .signers([predictor2])
+        .rpc();
+      assert.equal(
+        await provider.connection.getBalance(predictor2.publicKey),
+        before + SPONSORSHIP.toNumber() + rent
+      );
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.sponsoredAmount.toNumber(), 0);
+    });
+  });
+
+  describe("Liquidity mining", () => {
+    const RATE = 1_000;
+    const FUNDING = 5_000;
+    const minter = web3.Keypair.generate();
+    let rewardMint: web3.PublicKey;
+    let adminTokenAccount: web3.PublicKey;
+    const ownerTokenAccounts = new Map<string, web3.PublicKey>();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const pda = (seeds: Buffer[]) =>
+      web3.PublicKey.findProgramAddressSync(seeds, program.programId)[0];
+    const rewardsConfigPDA = pda([Buffer.from("rewards_config")]);
+    const rewardVaultPDA = pda([Buffer.from("reward_vault")]);
+    const incentiveAccounts = (marketId: BN, owner: web3.PublicKey) => ({
+      rewardsConfig: rewardsConfigPDA,
+      rewardVault: rewardVaultPDA,
+      market: getMarketPDA(marketId)[0],
+      marketIncentives: pda([
+        Buffer.from("market_incentives"),
+        marketId.toArrayLike(Buffer, "le", 8),
+      ]),
+      position: pda([
+        Buffer.from("incentive_position"),
+        marketId.toArrayLike(Buffer, "le", 8),
+        owner.toBuffer(),
+      ]),
+      owner,
+    });
+
+    const stake = (marketId: BN, owner: web3.Keypair, withBet = true) =>
+      program.methods
+        .stakeIncentives(marketId)
+        .accounts({
+          ...incentiveAccounts(marketId, owner.publicKey),
+          predictionAccount: withBet ? getPredictionPDA(marketId, owner.publicKey)[0] : null,
+        })
+        .signers([owner])
+        .rpc();
+
+    const claim = (marketId: BN, owner: web3.Keypair) =>
+      program.methods
+        .claimIncentives(marketId)
+        .accounts({
+          ...incentiveAccounts(marketId, owner.publicKey),
+          ownerTokenAccount: ownerTokenAccounts.get(owner.publicKey.toBase58()),
+        })
+        .signers([owner])
+        .rpc();
+
+    const tokenBalance = async (account: web3.PublicKey) =>
+      Number((await provider.connection.getTokenAccountBalance(account)).value.amount);
+
+    before(async () => {
+      await airdropSol(minter.publicKey, 2);
+      rewardMint = await createMint(provider.connection, minter, minter.publicKey, null, 0);
+      adminTokenAccount = await createAccount(
+        provider.connection,
+        minter,
+        rewardMint,
+        provider.wallet.publicKey
+      );
+      await mintTo(provider.connection, minter, rewardMint, adminTokenAccount, minter, FUNDING);
+      for (const owner of [predictor1, marketCreator]) {
+        const account = await createAccount(
+          provider.connection,
+          minter,
+          rewardMint,
+          owner.publicKey
+        );
+        ownerTokenAccounts.set(owner.publicKey.toBase58(), account);
+      }
+
+      await program.methods
+        .configureRewards(new BN(RATE))
+        .accounts({
+          config: getConfigPDA()[0],
+          rewardsConfig: rewardsConfigPDA,
+          rewardVault: rewardVaultPDA,
+          rewardMint,
+          admin: provider.wallet.publicKey,
+        })
+        .rpc();
+      await program.methods
+        .fundRewards(new BN(FUNDING))
+        .accounts({
+          config: getConfigPDA()[0],
+          rewardsConfig: rewardsConfigPDA,
+          rewardVault: rewardVaultPDA,
+          adminTokenAccount,
+          admin: provider.wallet.publicKey,
+        })
+        .rpc();
+    });
+
+    it("accrues by stake over time and stops when the vault runs dry", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await expectError(stake(marketId, predictor2, false), "NoIncentiveShares");
+      await stake(marketId, predictor1);
+      await stake(marketId, marketCreator, false);
+
+      await sleep(2000);
+      const events = await getCpiEvents(await claim(marketId, predictor1));
+      const first = events.find((e) => isEvent(e, "IncentivesClaimed")).data.amount.toNumber();
+      assert.isAbove(first, 0);
+
+      // The vault holds five seconds of emissions; wait it out
+      await sleep(6000);
+      await claim(marketId, predictor1);
+      await claim(marketId, marketCreator);
+      await expectError(claim(marketId, predictor1), "NoReward");
+
+      const paid = [predictor1, marketCreator].map((owner) =>
+        tokenBalance(ownerTokenAccounts.get(owner.publicKey.toBase58()))
+      );
+      const total = (await Promise.all(paid)).reduce((a, b) => a + b, 0);
+      assert.isAtMost(total, FUNDING);
+      const rewards = await program.account["rewardsConfig"].fetch(rewardsConfigPDA);
+      assert.equal(await tokenBalance(rewardVaultPDA), FUNDING - total);
+      assert.isAtMost(rewards.totalOwed.toNumber(), FUNDING - total);
+    });
+  });
+
+  describe("Imbalance rebate", () => {
+    const BET = new BN(web3.LAMPORTS_PER_SOL / 2);
+
+    // YES leads by one bet's net amount
+    const lopsidedMarket = async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const created = await createMarket(new BN(now + 7 * 24 * 60 * 60), marketCreator, {
+        imbalanceRebateBps: 10_000,
+      });
+      await placePrediction(created.marketId, predictor1, true, BET);
+      return created;
+    };
+
+    it("scales the rebate by how much of the bet closes the gap", async () => {
+      const { marketId, marketPDA } = await lopsidedMarket();
+      const market = await program.account["market"].fetch(marketPDA);
+      const gap = market.yesPool.sub(market.noPool);
+
+      const quote = (side: boolean, amount: BN) =>
+        quotePrediction(marketId, predictor2.publicKey, side, amount);
+      const kept = (q: any) => q.fee.sub(q.insuranceFee);
+
+      // Widening the gap earns nothing
+      assert.equal((await quote(true, BET)).rebate.toNumber(), 0);
+
+      // Entirely spent closing the gap: the whole kept fee
+      const within = await quote(false, BET.divn(2));
+      assert.isTrue(within.netAmount.lt(gap));
+      assert.equal(within.rebate.toString(), kept(within).toString());
+
+      // Overshooting: only the part that closed the gap counts
+      const crossing = await quote(false, BET.muln(3).divn(2));
+      const reduction = gap.sub(crossing.netAmount.sub(gap));
+      assert.equal(
+        crossing.rebate.toString(),
+        kept(crossing).mul(reduction).div(crossing.netAmount).toString()
+      );
+      assert.isTrue(crossing.rebate.gtn(0) && crossing.rebate.lt(kept(crossing)));
+
+      // Past twice the gap the imbalance is no smaller than before
+      assert.equal((await quote(false, BET.muln(3))).rebate.toNumber(), 0);
+    });
+
+    it("keeps the rebate out of the vault and stays solvent", async () => {
+      const { marketId, marketPDA } = await lopsidedMarket();
+      const amount = BET.divn(2);
+      const [vaultPDA] = getVaultPDA(marketId);
+      const feesBefore = (await program.account["market"].fetch(marketPDA)).feeCollected;
+      const vaultBefore = await provider.connection.getBalance(vaultPDA);
+      const quote = await quotePrediction(marketId, predictor2.publicKey, false, amount);
+
+      const builder = await placePredictionIx(marketId, predictor2, false, amount);
+      const events = await getCpiEvents(await builder.rpc());
+      const placed = events.find((e) => isEvent(e, "PredictionPlaced"));
+      assert.isTrue(placed.data.rebate.gtn(0));
+      assert.equal(placed.data.rebate.toString(), quote.rebate.toString());
+
+      assert.equal(
+        (await provider.connection.getBalance(vaultPDA)) - vaultBefore,
+        amount.sub(quote.insuranceFee).sub(quote.rebate).toNumber()
+      );
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(
+        market.feeCollected.toString(),
+        feesBefore.add(quote.fee.sub(quote.insuranceFee)).sub(quote.rebate).toString()
+      );
+      await assertSolvent(marketId);
+    });
+  });
+
+  describe("Dynamic fee curve", () => {
+    const CURVE = { kBps: 5_000, capBps: 400 };
+    let marketId: BN;
+    let baseFeeBps: number;
+
+    const quoteFeeBps = async (side: boolean, lamports: number) =>
+      (await quotePrediction(marketId, predictor1.publicKey, side, new BN(lamports))).feeBps;
+
+    before(async () => {
+      const now = Math.floor(Date.now() / 1000);
+      ({ marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60), marketCreator, {
+        feeCurve: CURVE,
+      }));
+      baseFeeBps = (await program.account["config"].fetch(getConfigPDA()[0])).tradingFeeBps;
+    });
+
+    it("charges the base fee on a balanced market", async () => {
+      assert.equal(await quoteFeeBps(true, 1_000), baseFeeBps);
+    });
+
+    it("charges the cap once a bet leaves the pools one-sided", async () => {
+      assert.equal(await quoteFeeBps(true, 100 * web3.LAMPORTS_PER_SOL), CURVE.capBps);
+    });
+
+    it("rises monotonically with the imbalance in between", async () => {
+      let previous = baseFeeBps;
+      for (const sol of [0.01, 0.05, 0.1, 0.2, 0.5, 1, 5]) {
+        const feeBps = await quoteFeeBps(false, sol * web3.LAMPORTS_PER_SOL);
+        assert.isAtLeast(feeBps, previous);
+        assert.isAtMost(feeBps, CURVE.capBps);
+        previous = feeBps;
+      }
+      assert.isAbove(previous, baseFeeBps);
+    });
+
+    it("charges the quoted fee and reports it on the bet", async () => {
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 5);
+      const quote = await quotePrediction(marketId, predictor1.publicKey, true, amount);
+      const builder = await placePredictionIx(marketId, predictor1, true, amount);
+      const events = await getCpiEvents(await builder.rpc());
+      const placed = events.find((e) => isEvent(e, "PredictionPlaced"));
+      assert.equal(placed.data.feeBps, quote.feeBps);
+      // Fees round up under the default FavorPool policy
+      const fee = amount.muln(quote.feeBps).addn(9_999).divn(10_000);
+      assert.equal(quote.fee.toString(), fee.toString());
+    });
+  });
+
+  describe("Fee schedule", () => {
+    const SCHEDULE = { startFeeBps: 100, endFeeBps: 500 };
+    const DURATION = 10;
+
+    const chainTime = async () =>
+      provider.connection.getBlockTime(await provider.connection.getSlot());
+
+    // Fee quoted now, with the scheduled fee at the chain times bracketing it
+    const quoteWithBounds = async (marketId: BN, market: any) => {
+      const expectedAt = (t: number) => {
+        const start = market.createdAt.toNumber();
+        const end = market.resolutionTime.toNumber();
+        const clamped = Math.min(Math.max(t, start), end);
+        const rise = SCHEDULE.endFeeBps - SCHEDULE.startFeeBps;
+        return SCHEDULE.startFeeBps + Math.floor((rise * (clamped - start)) / (end - start));
+      };
+      const before = await chainTime();
+      const quote = await quotePrediction(
+        marketId,
+        predictor1.publicKey,
+        true,
+        new BN(web3.LAMPORTS_PER_SOL / 10)
+      );
+      const after = await chainTime();
+      return { feeBps: quote.feeBps, low: expectedAt(before - 1), high: expectedAt(after + 1) };
+    };
+
+    it("rises from the start fee to the end fee over the betting period", async () => {
+      const { marketId, marketPDA } = await createShortMarket(DURATION, marketCreator, {
+        feeSchedule: SCHEDULE,
+      });
+      const market = await program.account["market"].fetch(marketPDA);
+
+      // Creation
+      let quote = await quoteWithBounds(marketId, market);
+      assert.isAtLeast(quote.feeBps, SCHEDULE.startFeeBps);
+      assert.isAtMost(quote.feeBps, quote.high);
+      const opening = quote.feeBps;
+
+      // Midpoint
+      await sleep((DURATION / 2) * 1000 - 1000);
+      quote = await quoteWithBounds(marketId, market);
+      assert.isAtLeast(quote.feeBps, quote.low);
+      assert.isAtMost(quote.feeBps, quote.high);
+      assert.isAbove(quote.feeBps, opening);
+
+      // Close: the last moment bets are accepted
+      await sleep((DURATION / 2) * 1000 - 3000);
+      quote = await quoteWithBounds(marketId, market);
+      assert.isAtLeast(quote.feeBps, quote.low);
+      assert.isAtMost(quote.feeBps, SCHEDULE.endFeeBps);
+    });
+
+    it("rejects schedules that fall or exceed the protocol cap", async () => {
+      for (const feeSchedule of [
+        { startFeeBps: 300, endFeeBps: 200 },
+        { startFeeBps: 100, endFeeBps: configParams.maxFeeBps + 1 },
+      ]) {
+        try {
+          const now = Math.floor(Date.now() / 1000);
+          await createMarket(new BN(now + 7 * 24 * 60 * 60), marketCreator, { feeSchedule });
+          assert.fail("Should have thrown InvalidMarketOptions");
+        } catch (e: any) {
+          assert.include(e.toString(), "InvalidMarketOptions");
+        }
+      }
+    });
+  });
+
+  describe("Pre-close freeze", () => {
+    const FREEZE = 5;
+    const trader = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const chainTime = async () =>
+      provider.connection.getBlockTime(await provider.connection.getSlot());
+
+    const waitForChainTime = async (t: number) => {
+      while ((await chainTime()) < t) await sleep(200);
+    };
+
+    const orderBookPDA = (marketId: BN) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("order_book"), marketId.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      )[0];
+
+    before(async () => {
+      await airdropSol(trader.publicKey, 2);
+    });
+
+    it("takes the freeze window from config unless the market sets one", async () => {
+      await updateConfig({ freezeBeforeCloseSecs: new BN(FREEZE), exitsDuringFreeze: false });
+      try {
+        const now = Math.floor(Date.now() / 1000);
+        const { marketPDA } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+        let market = await program.account["market"].fetch(marketPDA);
+        assert.equal(market.freezeBeforeCloseSecs.toNumber(), FREEZE);
+        assert.isFalse(market.exitsDuringFreeze);
+
+        const custom = await createMarket(new BN(now + 7 * 24 * 60 * 60), marketCreator, {
+          freezeBeforeCloseSecs: new BN(60),
+          exitsDuringFreeze: true,
+        });
+        market = await program.account["market"].fetch(custom.marketPDA);
+        assert.equal(market.freezeBeforeCloseSecs.toNumber(), 60);
+        assert.isTrue(market.exitsDuringFreeze);
+      } finally {
+        await updateConfig({ freezeBeforeCloseSecs: new BN(0), exitsDuringFreeze: true });
+      }
+    });
+
+    it("refuses bets from the freeze moment and holds orders if exits are off", async () => {
+      const { marketId, marketPDA } = await createShortMarket(FREEZE + 4, marketCreator, {
+        freezeBeforeCloseSecs: new BN(FREEZE),
+        exitsDuringFreeze: false,
+      });
+      const market = await program.account["market"].fetch(marketPDA);
+      const freezeAt = market.resolutionTime.toNumber() - FREEZE;
+
+      await program.methods
+        .initializeOrderBook(marketId)
+        .accounts({
+          market: marketPDA,
+          orderBook: orderBookPDA(marketId),
+          payer: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+      await program.methods
+        .postOrder(marketId, true, 5_000, new BN(web3.LAMPORTS_PER_SOL / 1000))
+        .accounts({
+          market: marketPDA,
+          orderBook: orderBookPDA(marketId),
+          selfExclusion: getSelfExclusionPDA(trader.publicKey),
+          owner: trader.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([trader])
+        .rpc();
+      assert.isBelow(await chainTime(), freezeAt);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+
+      // The freeze starts at exactly close - freeze_before_close_secs
+      await waitForChainTime(freezeAt);
+      await expectError(
+        placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT),
+        "BettingFrozen"
+      );
+      const cancel = () =>
+        program.methods
+          .cancelOrder(marketId, 0)
+          .accounts({
+            market: marketPDA,
+            orderBook: orderBookPDA(marketId),
+            owner: trader.publicKey,
+          })
+          .signers([trader])
+          .rpc();
+      await expectError(cancel(), "BettingFrozen");
+
+      // Escrow is released once betting has closed
+      await waitForChainTime(market.resolutionTime.toNumber());
+      await cancel();
+    });
+  });
+
+  describe("Price impact limit", () => {
+    const LIMIT_BPS = 500;
+
+    const impactAfter = (market: any, side: boolean, netAmount: BN) => {
+      const prob = (yes: BN, no: BN) => yes.muln(10_000).div(yes.add(no)).toNumber();
+      const yes = side ? market.yesPool.add(netAmount) : market.yesPool;
+      const no = side ? market.noPool : market.noPool.add(netAmount);
+      return Math.abs(prob(market.yesPool, market.noPool) - prob(yes, no));
+    };
+
+    const freshMarket = async () => {
+      const now = Math.floor(Date.now() / 1000);
+      return createMarket(new BN(now + 7 * 24 * 60 * 60));
+    };
+
+    before(async () => {
+      await airdropSol(predictor1.publicKey, 6);
+      await airdropSol(predictor2.publicKey, 6);
+    });
+
+    it("lets a small bet on a deep pool through the limit", async () => {
+      const { marketId, marketPDA } = await freshMarket();
+      // Deepen both pools well past the bet below
+      await placePrediction(marketId, predictor1, true, new BN(5 * web3.LAMPORTS_PER_SOL));
+      await placePrediction(marketId, predictor2, false, new BN(5 * web3.LAMPORTS_PER_SOL));
+
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+      const market = await program.account["market"].fetch(marketPDA);
+      const quote = await quotePrediction(marketId, marketCreator.publicKey, true, amount);
+      assert.equal(quote.priceImpactBps, impactAfter(market, true, quote.netAmount));
+      assert.isBelow(quote.priceImpactBps, LIMIT_BPS);
+
+      const builder = await placePredictionIx(
+        marketId,
+        marketCreator,
+        true,
+        amount,
+        null,
+        null,
+        LIMIT_BPS
+      );
+      await builder.rpc();
+    });
+
+    it("rejects an oversized bet on a thin pool unless the limit is 0", async () => {
+      const { marketId, marketPDA } = await freshMarket();
+      const amount = new BN(web3.LAMPORTS_PER_SOL);
+      const market = await program.account["market"].fetch(marketPDA);
+      const quote = await quotePrediction(marketId, predictor1.publicKey, false, amount);
+      assert.equal(quote.priceImpactBps, impactAfter(market, false, quote.netAmount));
+      assert.isAbove(quote.priceImpactBps, LIMIT_BPS);
+
+      try {
+        const builder = await placePredictionIx(
+          marketId,
+          predictor1,
+          false,
+          amount,
+          null,
+          null,
+          LIMIT_BPS
+        );
+        await builder.rpc();
+        assert.fail("Should have thrown PriceImpactTooHigh");
+      } catch (e: any) {
+        assert.include(e.toString(), "PriceImpactTooHigh");
+      }
+
+      // No limit: the bet goes through as before
+      await placePrediction(marketId, predictor1, false, amount);
+    });
+  });
+
+  describe("Position index", () => {
+    const bettor = web3.Keypair.generate();
+
+    const fetchIndex = async (marketId: BN) => {
+      const { userMarketIndex } = await getPositionIndexAccounts(marketId, bettor.publicKey);
+      return program.account["userMarketIndex"].fetch(userMarketIndex);
+    };
+
+    const fetchPage = () => {
+      const pageBytes = Buffer.alloc(4);
+      const [page] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("user_index"), bettor.publicKey.toBuffer(), pageBytes],
+        program.programId
+      );
+      return program.account["userIndexPage"].fetch(page);
+    };
+
+    const closePrediction = async (marketId: BN) =>
+      program.methods
+        .closePrediction(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, bettor.publicKey)[0],
+          userMarketIndex: (await getPositionIndexAccounts(marketId, bettor.publicKey))
+            .userMarketIndex,
+          predictor: bettor.publicKey,
+          rentPayer: bettor.publicKey,
+        })
+        .signers([bettor])
+        .rpc();
+
+    before(async () => {
+      await airdropSol(bettor.publicKey, 2);
+    });
+
+    it("tracks positions through open, close and a new open", async () => {
+      const short = await createShortMarket(3);
+      const now = Math.floor(Date.now() / 1000);
+      const long = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await placePrediction(short.marketId, bettor, true, PREDICTION_AMOUNT);
+      await placePrediction(short.marketId, predictor2, false, PREDICTION_AMOUNT);
+      await placePrediction(long.marketId, bettor, false, PREDICTION_AMOUNT);
+
+      let index = await fetchIndex(short.marketId);
+      assert.equal(index.nextPositionIndex, 1);
+      assert.equal(index.openPositions, 1);
+      let page = await fetchPage();
+      assert.equal(page.count, 2);
+      assert.deepEqual(
+        page.marketIds.slice(0, 2).map((id: BN) => id.toString()),
+        [short.marketId.toString(), long.marketId.toString()]
+      );
+
+      await sleep(4000);
+      await resolveMarket(short.marketId, true);
+      try {
+        await closePrediction(short.marketId);
+        assert.fail("Should have thrown PredictionNotSettled");
+      } catch (e: any) {
+        assert.include(e.toString(), "PredictionNotSettled");
+      }
+      await claimReward(short.marketId, bettor);
+      await closePrediction(short.marketId);
+
+      assert.isNull(
+        await provider.connection.getAccountInfo(
+          getPredictionPDA(short.marketId, bettor.publicKey)[0]
+        )
+      );
+      index = await fetchIndex(short.marketId);
+      assert.equal(index.nextPositionIndex, 1);
+      assert.equal(index.openPositions, 0);
+
+      const another = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await placePrediction(another.marketId, bettor, true, PREDICTION_AMOUNT);
+      page = await fetchPage();
+      assert.equal(page.count, 3);
+      assert.equal(page.marketIds[2].toString(), another.marketId.toString());
+      assert.equal((await fetchIndex(another.marketId)).openPositions, 1);
+      assert.equal((await fetchIndex(long.marketId)).openPositions, 1);
+    });
+  });
+
+  describe("Partial claims", () => {
+    it("pays uneven chunks that sum to exactly the full claim", async () => {
+      const { marketId } = await createShortMarket(3);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT.muln(3).divn(2));
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      const [predictionPDA] = getPredictionPDA(marketId, predictor1.publicKey);
+      const tokens: BN = (await program.account["prediction"].fetch(predictionPDA))
+        .tokensReceived;
+      const full: BN = (await getClaimable(marketId, predictor1.publicKey)).amount;
+
+      try {
+        await claimReward(marketId, predictor1, tokens.addn(1));
+        assert.fail("Should have thrown InvalidAmount");
+      } catch (e: any) {
+        assert.include(e.toString(), "InvalidAmount");
+      }
+
+      const chunks = [tokens.divn(7), tokens.divn(3)];
+      chunks.push(tokens.sub(chunks[0]).sub(chunks[1]));
+      let paid = new BN(0);
+      for (const [i, chunk] of chunks.entries()) {
+        const last = i === chunks.length - 1;
+        const events = await getCpiEvents(
+          await claimReward(marketId, predictor1, last ? null : chunk)
+        );
+        paid = paid.add(events.find((e) => isEvent(e, "RewardClaimed")).data.reward);
+
+        const prediction = await program.account["prediction"].fetch(predictionPDA);
+        assert.equal(prediction.claimed, last);
+        const left: BN = (await getClaimable(marketId, predictor1.publicKey)).amount;
+        assert.equal(left.add(paid).toString(), last ? paid.toString() : full.toString());
+      }
+      assert.equal(paid.toString(), full.toString());
+
+      try {
+        await claimReward(marketId, predictor1);
+        assert.fail("Should have thrown AlreadyClaimed");
+      } catch (e: any) {
+        assert.include(e.toString(), "AlreadyClaimed");
+      }
+      await assertSolvent(marketId);
+    });
+  });
+
+  describe("Claim receipts", () => {
+    it("records one claim per prediction and holds its rent for 90 days", async () => {
+      const { marketId } = await createShortMarket(3);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      const [predictionPDA] = getPredictionPDA(marketId, predictor1.publicKey);
+      const receiptPDA = getClaimReceiptPDA(predictionPDA);
+      const tokens: BN = (await program.account["prediction"].fetch(predictionPDA))
+        .tokensReceived;
+      const events = await getCpiEvents(
+        await claimReward(marketId, predictor1, tokens.divn(2), 0, null, true)
+      );
+      const claimed = events.find((e) => isEvent(e, "RewardClaimed")).data;
+
+      const receipt = await program.account["claimReceipt"].fetch(receiptPDA);
+      assert.isTrue(receipt.prediction.equals(predictionPDA));
+      assert.isTrue(receipt.claimer.equals(predictor1.publicKey));
+      assert.equal(receipt.marketId.toString(), marketId.toString());
+      assert.equal(receipt.amount.toString(), claimed.reward.toString());
+      assert.equal(receipt.fee.toString(), claimed.winningsFee.toString());
+      assert.isTrue(receipt.snapshotHash.some((byte: number) => byte !== 0));
+
+      // The receipt already exists, so the rest is claimed without one
+      try {
+        await claimReward(marketId, predictor1, null, 0, null, true);
+        assert.fail("Should not create a second receipt");
+      } catch (e: any) {
+        assert.include(e.toString(), "already in use");
+      }
+      await claimReward(marketId, predictor1);
+
+      try {
+        await program.methods
+          .closeClaimReceipt()
+          .accounts({ claimReceipt: receiptPDA, claimer: predictor1.publicKey })
+          .signers([predictor1])
+          .rpc();
+        assert.fail("Should have thrown ClaimReceiptLocked");
+      } catch (e: any) {
+        assert.include(e.toString(), "ClaimReceiptLocked");
+      }
+      assert.isNotNull(await provider.connection.getAccountInfo(receiptPDA));
+    });
+  });
+
+  describe("Split positions", () => {
+    const splitPosition = async (marketId: BN, owner: web3.Keypair, amountTokens: BN) => {
+      const { userMarketIndex } = await getPositionIndexAccounts(marketId, owner.publicKey);
+      const index = await program.account["userMarketIndex"].fetch(userMarketIndex);
+      await program.methods
+        .splitPosition(marketId, amountTokens)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, owner.publicKey)[0],
+          userMarketIndex,
+          newPrediction: getPredictionPDA(
+            marketId,
+            owner.publicKey,
+            Math.max(index.nextPositionIndex, 1)
+          )[0],
+          predictor: owner.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([owner])
+        .rpc();
+      return Math.max(index.nextPositionIndex, 1);
+    };
+
+    it("lets both halves claim what the whole position would have", async () => {
+      const { marketId } = await createShortMarket(3);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT.muln(3).divn(2));
+
+      const [originalPDA] = getPredictionPDA(marketId, predictor1.publicKey);
+      const original = await program.account["prediction"].fetch(originalPDA);
+      const moved: BN = original.tokensReceived.divn(3);
+      const splitIndex = await splitPosition(marketId, predictor1, moved);
+
+      const [splitPDA] = getPredictionPDA(marketId, predictor1.publicKey, splitIndex);
+      const kept = await program.account["prediction"].fetch(originalPDA);
+      const split = await program.account["prediction"].fetch(splitPDA);
+      assert.equal(split.positionIndex, splitIndex);
+      assert.equal(split.tokensReceived.toString(), moved.toString());
+      assert.equal(
+        kept.tokensReceived.add(split.tokensReceived).toString(),
+        original.tokensReceived.toString()
+      );
+      assert.equal(
+        kept.amountDeposited.add(split.amountDeposited).toString(),
+        original.amountDeposited.toString()
+      );
+
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      const pool: BN = market.yesPool;
+      const winnings: BN = market.yesPool.add(market.noPool).add(market.sponsoredAmount);
+      const whole = original.tokensReceived.mul(winnings).div(pool);
+
+      let paid = new BN(0);
+      for (const positionIndex of [0, splitIndex]) {
+        const events = await getCpiEvents(
+          await claimReward(marketId, predictor1, null, positionIndex)
+        );
+        paid = paid.add(events.find((e) => isEvent(e, "RewardClaimed")).data.reward);
+      }
+      assert.isTrue(whole.sub(paid).abs().lten(1));
+      await assertSolvent(marketId);
+
+      try {
+        await splitPosition(marketId, predictor1, new BN(1));
+        assert.fail("Should have thrown AlreadyClaimed");
+      } catch (e: any) {
+        assert.include(e.toString(), "AlreadyClaimed");
+      }
+    });
+
+    it("refuses to split a losing position after resolution", async () => {
+      const { marketId } = await createShortMarket(3);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      try {
+        await splitPosition(marketId, predictor2, new BN(1));
+        assert.fail("Should have thrown PredictionLost");
+      } catch (e: any) {
+        assert.include(e.toString(), "PredictionLost");
+      }
+    });
+  });
+
+  describe("Claim recipient", () => {
+    it("pays the reward to a separate recipient", async () => {
+      const { marketId } = await createShortMarket(3);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      const treasury = web3.Keypair.generate().publicKey;
+      const events = await getCpiEvents(
+        await claimReward(marketId, predictor1, null, 0, treasury)
+      );
+      const claimed = events.find((e) => isEvent(e, "RewardClaimed")).data;
+      assert.equal(claimed.claimer.toString(), predictor1.publicKey.toString());
+      assert.equal(claimed.recipient.toString(), treasury.toString());
+      assert.equal(
+        (await provider.connection.getBalance(treasury)).toString(),
+        claimed.reward.toString()
+      );
+      await assertSolvent(marketId);
+    });
+
+    it("rejects the vault or the prediction account as recipient", async () => {
+      const { marketId } = await createShortMarket(3);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      for (const recipient of [
+        getVaultPDA(marketId)[0],
+        getPredictionPDA(marketId, predictor1.publicKey)[0],
+      ]) {
+        try {
+          await claimReward(marketId, predictor1, null, 0, recipient);
+          assert.fail("Should have thrown InvalidRecipient");
+        } catch (e: any) {
+          assert.include(e.toString(), "InvalidRecipient");
+        }
+      }
+    });
+  });
+
+  describe("wSOL collateral", () => {
+    const initWsolVault = (
+      marketId: BN,
+      mint = NATIVE_MINT,
+      tokenProgram = TOKEN_PROGRAM_ID
+    ) =>
+      program.methods
+        .initWsolVault(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          wsolVault: getWsolVault(marketId, mint, tokenProgram),
+          collateralMint: mint,
+          payer: provider.wallet.publicKey,
+          tokenProgram,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    before(async () => {
+      // Token-2022's native mint only exists once someone has created it
+      if (!(await provider.connection.getAccountInfo(NATIVE_MINT_2022))) {
+        await createNativeMint(provider.connection, (provider.wallet as any).payer);
+      }
+    });
+
+    for (const [name, mint, tokenProgram] of [
+      ["legacy token program", NATIVE_MINT, TOKEN_PROGRAM_ID],
+      ["Token-2022", NATIVE_MINT_2022, TOKEN_2022_PROGRAM_ID],
+    ] as const) {
+      it(`holds bets as wSOL and pays claims from the vault (${name})`, async () => {
+        const { marketId } = await createShortMarket(3, marketCreator, { wsolVault: true });
+        await initWsolVault(marketId, mint, tokenProgram);
+        const wsolVault = getWsolVault(marketId, mint, tokenProgram);
+        const balance = async (account: web3.PublicKey) =>
+          (await getAccount(provider.connection, account, undefined, tokenProgram)).amount;
+
+        const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+        assert.equal(market.collateralMint.toString(), mint.toString());
+        assert.equal(market.collateralVault.toString(), wsolVault.toString());
+        const seeded = await balance(wsolVault);
+        assert.equal(seeded.toString(), INITIAL_LIQUIDITY.toString());
+        const rent = await provider.connection.getMinimumBalanceForRentExemption(0);
+        assert.equal(await provider.connection.getBalance(getVaultPDA(marketId)[0]), rent);
+
+        await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+        await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+        assert.isTrue((await balance(wsolVault)) > seeded);
+        await assertSolvent(marketId);
+
+        await sleep(4000);
+        await resolveMarket(marketId, true);
+
+        const payout = await createAssociatedTokenAccount(
+          provider.connection,
+          predictor1,
+          mint,
+          predictor1.publicKey,
+          undefined,
+          tokenProgram
+        );
+        const events = await getCpiEvents(await claimReward(marketId, predictor1));
+        const claimed = events.find((e) => isEvent(e, "RewardClaimed")).data;
+        assert.equal(claimed.recipient.toString(), payout.toString());
+        assert.equal((await balance(payout)).toString(), claimed.reward.toString());
+        await assertSolvent(marketId);
+      });
+    }
+
+    it("accepts only a wrapped SOL mint, once", async () => {
+      const { marketId } = await createShortMarket(60, marketCreator, { wsolVault: true });
+      const minter = web3.Keypair.generate();
+      await airdropSol(minter.publicKey, 1);
+      const mint = await createMint(provider.connection, minter, minter.publicKey, null, 9);
+      try {
+        await initWsolVault(marketId, mint);
+        assert.fail("Should have thrown UnsupportedCollateralMint");
+      } catch (e: any) {
+        assert.include(e.toString(), "UnsupportedCollateralMint");
+      }
+
+      await initWsolVault(marketId);
+      try {
+        await initWsolVault(marketId, NATIVE_MINT_2022, TOKEN_2022_PROGRAM_ID);
+        assert.fail("Should have thrown WsolVaultExists");
+      } catch (e: any) {
+        assert.include(e.toString(), "WsolVaultExists");
+      }
+    });
+
+    it("refuses wSOL markets that need the lamport vault", async () => {
+      try {
+        await createShortMarket(3, marketCreator, {
+          wsolVault: true,
+          betRevealWindow: new BN(60),
+        });
+        assert.fail("Should have thrown LamportVaultRequired");
+      } catch (e: any) {
+        assert.include(e.toString(), "LamportVaultRequired");
+      }
+    });
+  });
+
+  describe("Outcome token metadata", () => {
+    // Name, symbol and URI are borsh strings after the key, update
+    // authority and mint; the metadata program pads them with NULs
+    const decodeMetadata = (data: Buffer) => {
+      let offset = 1 + 32 + 32;
+      const readString = () => {
+        const len = data.readUInt32LE(offset);
+        const value = data
+          .subarray(offset + 4, offset + 4 + len)
+          .toString("utf8")
+          .replace(/\0+$/, "");
+        offset += 4 + len;
+        return value;
+      };
+      const updateAuthority = new web3.PublicKey(data.subarray(1, 33));
+      const name = readString();
+      const symbol = readString();
+      const uri = readString();
+      offset += 2; // seller_fee_basis_points
+      offset += data[offset] === 0 ? 1 : 0; // creators (none)
+      offset += 1; // primary_sale_happened
+      return { updateAuthority, name, symbol, uri, isMutable: data[offset] === 1 };
+    };
+
+    it("creates immutable metadata for both outcome mints", async () => {
+      const counter = await program.account["marketCounter"].fetch(getCounterPDA()[0]);
+      const [marketPDA] = getMarketPDA(counter.nextId);
+      const mints = {
+        yes: await createMint(provider.connection, marketCreator, marketPDA, null, 9),
+        no: await createMint(provider.connection, marketCreator, marketPDA, null, 9),
+      };
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(
+        new BN(now + 7 * 24 * 60 * 60),
+        marketCreator,
+        {},
+        null,
+        mints
+      );
+
+      const uri = "https://example.com/markets/outcome.json";
+      await program.methods
+        .createOutcomeMetadata(marketId, uri)
+        .accounts({
+          market: marketPDA,
+          yesMint: mints.yes,
+          noMint: mints.no,
+          yesMetadata: getMetadataPDA(mints.yes)[0],
+          noMetadata: getMetadataPDA(mints.no)[0],
+          creator: marketCreator.publicKey,
+          tokenMetadataProgram: TOKEN_METADATA_PROGRAM_ID,
+          systemProgram: web3.SystemProgram.programId,
+          rent: web3.SYSVAR_RENT_PUBKEY,
+        })
+        .signers([marketCreator])
+        .rpc();
+
+      for (const [side, mint] of [
+        ["YES", mints.yes],
+        ["NO", mints.no],
+      ] as const) {
+        const info = await provider.connection.getAccountInfo(getMetadataPDA(mint)[0]);
+        assert.isTrue(info.owner.equals(TOKEN_METADATA_PROGRAM_ID));
+        const metadata = decodeMetadata(info.data);
+        assert.equal(metadata.name, `MKT-${marketId.toString()} ${side}`);
+        assert.equal(metadata.symbol, "MKT");
+        assert.equal(metadata.uri, uri);
+        assert.isTrue(metadata.updateAuthority.equals(marketPDA));
+        assert.isFalse(metadata.isMutable);
+      }
+    });
+  });
+
+  describe("Outcome mint finalization", () => {
+    it("removes the mint authority of both outcome mints once resolved", async () => {
+      const counter = await program.account["marketCounter"].fetch(getCounterPDA()[0]);
+      const [marketPDA] = getMarketPDA(counter.nextId);
+      const mints = {
+        yes: await createMint(provider.connection, marketCreator, marketPDA, null, 9),
+        no: await createMint(provider.connection, marketCreator, marketPDA, null, 9),
+      };
+      const { marketId } = await createShortMarket(2, marketCreator, {}, mints);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+
+      const finalize = () =>
+        program.methods
+          .finalizeMints(marketId)
+          .accounts({
+            market: marketPDA,
+            yesMint: mints.yes,
+            noMint: mints.no,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .rpc();
+
+      try {
+        await finalize();
+        assert.fail("Should have thrown MarketNotResolved");
+      } catch (e: any) {
+        assert.include(e.toString(), "MarketNotResolved");
+      }
+
+      await sleep(3000);
+      await resolveMarket(marketId, true);
+      await finalize();
+
+      for (const mint of [mints.yes, mints.no]) {
+        assert.isNull((await getMint(provider.connection, mint)).mintAuthority);
+      }
+      const holder = await createAccount(
+        provider.connection,
+        marketCreator,
+        mints.yes,
+        marketCreator.publicKey
+      );
+      try {
+        await mintTo(provider.connection, marketCreator, mints.yes, holder, marketCreator, 1);
+        assert.fail("Minting should fail without a mint authority");
+      } catch (e: any) {
+        assert.notInclude(e.toString(), "Minting should fail");
+      }
+
+      // Claims don't depend on the mints
+      await claimReward(marketId, predictor1);
+    });
+  });
+
+  describe("Duplicate markets", () => {
+    // Noon, a few days out, so nearby times share the day bucket
+    const DAY = 24 * 60 * 60;
+    const noon = new BN((Math.floor(Date.now() / 1000 / DAY) + 3) * DAY + DAY / 2);
+    const question = "Will the home team win the derby?";
+
+    const create = async (
+      text: string,
+      resolutionTime: BN,
+      approver: web3.PublicKey | null = null,
+      extraSigners: web3.Keypair[] = []
+    ) => {
+      const counter = await program.account["marketCounter"].fetch(getCounterPDA()[0]);
+      const marketId: BN = counter.nextId;
+      const [vaultPDA] = getVaultPDA(marketId);
+      await program.methods
+        .initializeMarket(text, resolutionTime, INITIAL_LIQUIDITY, 5_000, 0, DEFAULT_MARKET_OPTIONS)
+        .accounts({
+          marketCounter: getCounterPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          registryPage: (await getNextRegistryPagePDA())[0],
+          creator: marketCreator.publicKey,
+          marketVault: vaultPDA,
+          questionRegistry: getQuestionRegistryPDA(text, resolutionTime)[0],
+          duplicateApprover: approver,
+          yesMint: null,
+          noMint: null,
+          series: null,
+          parentMarket: null,
+          creatorAllow: null,
+          creatorStats: getCreatorStatsPDA(marketCreator.publicKey),
+          treasury: null,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([marketCreator, ...extraSigners])
+        .rpc();
+      return marketId;
+    };
+
+    before(async () => {
+      await updateConfig({ allowDuplicateMarkets: false });
+    });
+
+    after(async () => {
+      await updateConfig({ allowDuplicateMarkets: true });
+    });
+
+    it("normalizes case and whitespace", () => {
+      assert.equal(
+        normalizeQuestion("  Will the HOME team\t win   the derby?\n"),
+        normalizeQuestion(question)
+      );
+      assert.isTrue(
+        getQuestionRegistryPDA("WILL THE HOME TEAM WIN THE DERBY?", noon)[0].equals(
+          getQuestionRegistryPDA(question, noon.addn(3600))[0]
+        )
+      );
+      assert.isFalse(
+        getQuestionRegistryPDA(question, noon)[0].equals(
+          getQuestionRegistryPDA(question, noon.addn(DAY))[0]
+        )
+      );
+    });
+
+    it("rejects the same question on the same day unless the admin approves", async () => {
+      const first = await create(question, noon);
+      const registry = await program.account["questionRegistry"].fetch(
+        getQuestionRegistryPDA(question, noon)[0]
+      );
+      assert.equal(registry.marketId.toString(), first.toString());
+
+      try {
+        await create("  will the home TEAM win  the derby? ", noon.addn(3600));
+        assert.fail("Should have thrown DuplicateMarket");
+      } catch (e: any) {
+        assert.include(e.toString(), "DuplicateMarket");
+      }
+
+      // A different day is a different market
+      await create(question, noon.addn(DAY));
+
+      try {
+        await create(question, noon, predictor1.publicKey, [predictor1]);
+        assert.fail("Should have thrown Unauthorized");
+      } catch (e: any) {
+        assert.include(e.toString(), "Unauthorized");
+      }
+      // The provider wallet is the config admin
+      await create(question, noon, provider.wallet.publicKey);
+      const unchanged = await program.account["questionRegistry"].fetch(
+        getQuestionRegistryPDA(question, noon)[0]
+      );
+      assert.equal(unchanged.marketId.toString(), first.toString());
+    });
+  });
+
+  describe("No-counterparty void", () => {
+    const SPONSORSHIP = new BN(100_000_000);
+    const refundAccounts = {
+      wsolVault: null,
+      payoutTokenAccount: null,
+      collateralMint: null,
+      tokenProgram: null,
+    };
+
+    const getSponsorshipPDA = (marketId: BN, sponsor: web3.PublicKey) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("sponsorship"), marketId.toArrayLike(Buffer, "le", 8), sponsor.toBuffer()],
+        program.programId
+      );
+
+    const claimRefund = (marketId: BN, claimer: web3.Keypair) =>
+      program.methods
+        .claimRefund(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, claimer.publicKey)[0],
+          claimer: claimer.publicKey,
+          ...refundAccounts,
+        })
+        .signers([claimer])
+        .rpc();
+
+    const expectVoided = async (marketId: BN, signature: string) => {
+      const voided = (await getCpiEvents(signature)).find((e) => isEvent(e, "MarketVoided"));
+      assert.deepEqual(voided.data.reason, { noCounterparty: {} });
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.isTrue(market.resolved);
+      assert.deepEqual(market.outcome, { void: {} });
+      assert.equal(
+        voided.data.refundable.toString(),
+        market.totalVolume.add(market.totalLiquidity).add(market.sponsoredAmount).toString()
+      );
+    };
+
+    it("voids a market with no NO bets and refunds bets, seed and sponsorship", async () => {
+      const { marketId, marketPDA } = await createShortMarket(3);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      const [sponsorshipPDA] = getSponsorshipPDA(marketId, predictor2.publicKey);
+      await program.methods
+        .sponsorMarket(marketId, SPONSORSHIP, null)
+        .accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          sponsorship: sponsorshipPDA,
+          sponsor: predictor2.publicKey,
+        })
+        .signers([predictor2])
+        .rpc();
+      await sleep(4000);
+
+      // Resolving for the only side still voids the market
+      await expectVoided(marketId, await resolveMarket(marketId, true));
+      try {
+        await claimReward(marketId, predictor1);
+        assert.fail("Should have thrown InvalidOutcome");
+      } catch (e: any) {
+        assert.include(e.toString(), "InvalidOutcome");
+      }
+
+      let before = await provider.connection.getBalance(predictor1.publicKey);
+      await claimRefund(marketId, predictor1);
+      assert.equal(
+        await provider.connection.getBalance(predictor1.publicKey),
+        before + PREDICTION_AMOUNT.toNumber()
+      );
+
+      before = await provider.connection.getBalance(marketCreator.publicKey);
+      await program.methods
+        .refundLiquidity(marketId)
+        .accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          creator: marketCreator.publicKey,
+          ...refundAccounts,
+        })
+        .signers([marketCreator])
+        .rpc();
+      assert.equal(
+        await provider.connection.getBalance(marketCreator.publicKey),
+        before + INITIAL_LIQUIDITY.toNumber()
+      );
+
+      const rent = await provider.connection.getBalance(sponsorshipPDA);
+      before = await provider.connection.getBalance(predictor2.publicKey);
+      await program.methods
+        .refundSponsorship(marketId)
+        .accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          sponsorship: sponsorshipPDA,
+          sponsor: predictor2.publicKey,
+        })
+        .signers([predictor2])
+        .rpc();
+      assert.equal(
+        await provider.connection.getBalance(predictor2.publicKey),
+        before + SPONSORSHIP.toNumber() + rent
+      );
+      await assertSolvent(marketId);
+    });
+
+    it("voids a market with no YES bets", async () => {
+      const { marketId } = await createShortMarket(3);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+
+      await expectVoided(marketId, await resolveMarket(marketId, false));
+      const claimable = await getClaimable(marketId, predictor2.publicKey);
+      assert.deepEqual(claimable.status, { refundable: {} });
+      assert.equal(claimable.amount.toString(), PREDICTION_AMOUNT.toString());
+
+      await claimRefund(marketId, predictor2);
+      await assertSolvent(marketId);
+    });
+  });
+
+  describe("Initial probability", () => {
+    const resolutionTime = () => new BN(Math.floor(Date.now() / 1000) + 7 * 24 * 60 * 60);
+
+    it("rejects starting probabilities outside 2%-98%", async () => {
+      for (const bps of [199, 9_801]) {
+        try {
+          await createMarket(resolutionTime(), marketCreator, {}, null, null, bps);
+          assert.fail("Should have thrown InvalidInitialProbability");
+        } catch (e: any) {
+          assert.include(e.toString(), "InvalidInitialProbability");
+        }
+      }
+    });
+
+    it("seeds the pools so the first bet is priced at the configured probability", async () => {
+      const { marketId, marketPDA, signature } = await createMarket(
+        resolutionTime(),
+        marketCreator,
+        {},
+        null,
+        null,
+        8_000
+      );
+      const created = (await getCpiEvents(signature)).find((e) => isEvent(e, "MarketCreated"));
+      assert.equal(created.data.initialYesProbabilityBps, 8_000);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.yesPool.toString(), INITIAL_LIQUIDITY.muln(8).divn(10).toString());
+      assert.equal(market.yesPool.add(market.noPool).toString(), INITIAL_LIQUIDITY.toString());
+
+      // A small YES bet's winnings are its tokens' share of the pot, so its
+      // price per lamport of payout is the YES pool's share of the pot
+      for (const [side, expectedBps] of [
+        [true, 8_000],
+        [false, 2_000],
+      ] as [boolean, number][]) {
+        const quote = await quotePrediction(
+          marketId,
+          predictor1.publicKey,
+          side,
+          new BN(web3.LAMPORTS_PER_SOL / 1_000)
+        );
+        const pool = (side ? market.yesPool : market.noPool).add(quote.netAmount);
+        const pot = market.yesPool.add(market.noPool).add(quote.netAmount);
+        const payout = quote.tokensOut.mul(pot).div(pool);
+        const priceBps = quote.netAmount.muln(10_000).div(payout).toNumber();
+        assert.approximately(priceBps, expectedBps, 20);
+      }
+    });
+  });
+
+  describe("Market cloning", () => {
+    const cloner = web3.Keypair.generate();
+    const WEEK = 7 * 24 * 60 * 60;
+    // Explicit IDs must have the high bit set
+    const explicitId = () =>
+      new BN(1).shln(63).addn(Math.floor(Math.random() * 1_000_000_000));
+
+    const cloneMarket = async (sourceId: BN, newId: BN, resolutionTime: BN) =>
+      program.methods
+        .cloneMarket(sourceId, newId, resolutionTime, INITIAL_LIQUIDITY)
+        .accounts({
+          sourceMarket: getMarketPDA(sourceId)[0],
+          market: getMarketPDA(newId)[0],
+          config: getConfigPDA()[0],
+          registryPage: (await getNextRegistryPagePDA())[0],
+          creator: cloner.publicKey,
+          marketVault: getVaultPDA(newId)[0],
+          yesMint: null,
+          noMint: null,
+          questionRegistry: getQuestionRegistryPDA(QUESTION, resolutionTime)[0],
+          duplicateApprover: null,
+          parentMarket: null,
+          creatorAllow: null,
+          creatorStats: getCreatorStatsPDA(cloner.publicKey),
+          treasury: null,
+        })
+        .signers([cloner])
+        .rpc();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    before(async () => {
+      await airdropSol(cloner.publicKey, 3);
+    });
+
+    it("copies the source's settings into a market owned by the caller", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const source = await createMarket(new BN(now + WEEK), marketCreator, {
+        imbalanceRebateBps: 500,
+        correctionWindowSecs: new BN(60),
+        oracleKey: web3.Keypair.generate().publicKey,
+        feeCurve: { kBps: 200, capBps: 500 },
+      });
+      const newId = explicitId();
+      const resolutionTime = new BN(now + 2 * WEEK);
+      const events = await getCpiEvents(
+        await cloneMarket(source.marketId, newId, resolutionTime)
+      );
+      const cloned = events.find((e) => isEvent(e, "MarketCloned")).data;
+      assert.equal(cloned.sourceMarketId.toString(), source.marketId.toString());
+      assert.equal(cloned.marketId.toString(), newId.toString());
+
+      const original = await program.account["market"].fetch(source.marketPDA);
+      const clone = await program.account["market"].fetch(getMarketPDA(newId)[0]);
+      assert.equal(clone.creator.toString(), cloner.publicKey.toString());
+      assert.equal(clone.question, original.question);
+      assert.equal(clone.resolutionTime.toString(), resolutionTime.toString());
+      assert.equal(clone.category, original.category);
+      assert.equal(clone.imbalanceRebateBps, 500);
+      assert.equal(clone.correctionWindowSecs.toNumber(), 60);
+      assert.equal(clone.oracleKey.toString(), original.oracleKey.toString());
+      assert.deepEqual(clone.feeCurve, original.feeCurve);
+      assert.isFalse(clone.resolved);
+      assert.equal(clone.totalVolume.toNumber(), 0);
+      assert.equal(clone.totalLiquidity.toString(), INITIAL_LIQUIDITY.toString());
+      assert.equal(clone.yesPool.toString(), clone.noPool.toString());
+      await assertSolvent(newId);
+    });
+
+    it("rejects a past resolution time, a missing source and a counter-range ID", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + WEEK));
+
+      await expectError(
+        cloneMarket(marketId, explicitId(), new BN(now - 60)),
+        "InvalidResolutionTime"
+      );
+      await expectError(
+        cloneMarket(new BN(999_999), explicitId(), new BN(now + WEEK)),
+        "AccountNotInitialized"
+      );
+      await expectError(
+        cloneMarket(marketId, new BN(999_998), new BN(now + WEEK)),
+        "InvalidMarketId"
+      );
+    });
+
+    it("never re-creates a market over an ID that is already taken", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + WEEK));
+      const newId = explicitId();
+      await cloneMarket(marketId, newId, new BN(now + 2 * WEEK));
+
+      const bettor = web3.Keypair.generate();
+      await airdropSol(bettor.publicKey, 1);
+      const predictionPDA = await placePrediction(newId, bettor, true, PREDICTION_AMOUNT);
+
+      // Markets are never closed, so their predictions can't outlive them
+      await expectError(
+        cloneMarket(marketId, newId, new BN(now + 3 * WEEK)),
+        "already in use"
+      );
+      const market = await program.account["market"].fetch(getMarketPDA(newId)[0]);
+      assert.equal(market.resolutionTime.toNumber(), now + 2 * WEEK);
+      const prediction = await program.account["prediction"].fetch(predictionPDA);
+      assert.equal(prediction.marketId.toString(), newId.toString());
+    });
+  });
+
+  describe("Protocol treasury", () => {
+    const treasuryPDA = getTreasuryPDA();
+    const [pendingPDA] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("pending_withdrawal")],
+      program.programId
+    );
+    const admin = provider.wallet.publicKey;
+    let collected = new BN(0);
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const collectFeesMany = (marketIds: BN[], signer?: web3.Keypair) =>
+      program.methods
+        .collectFeesMany()
+        .accounts({
+          config: getConfigPDA()[0],
+          treasury: treasuryPDA,
+          stakingVault: null,
+          buybackEscrow: null,
+          admin: signer ? signer.publicKey : admin,
+        })
+        .remainingAccounts(
+          marketIds.flatMap((id) => [
+            { pubkey: getMarketPDA(id)[0], isWritable: true, isSigner: false },
+            { pubkey: getVaultPDA(id)[0], isWritable: true, isSigner: false },
+          ])
+        )
+        .signers(signer ? [signer] : [])
+        .rpc();
+
+    const proposeWithdrawal = (amount: BN, recipient: web3.PublicKey) =>
+      program.methods
+        .proposeTreasuryWithdrawal(amount, recipient)
+        .accounts({ config: getConfigPDA()[0], pendingWithdrawal: pendingPDA, admin })
+        .rpc();
+
+    const executeWithdrawal = (recipient: web3.PublicKey) =>
+      program.methods
+        .executeTreasuryWithdrawal()
+        .accounts({
+          config: getConfigPDA()[0],
+          treasury: treasuryPDA,
+          pendingWithdrawal: pendingPDA,
+          recipient,
+          admin,
+        })
+        .rpc();
+
+    before(async () => {
+      await program.methods
+        .initTreasury()
+        .accounts({ config: getConfigPDA()[0], treasury: treasuryPDA, admin })
+        .rpc();
+      configParams.treasury = treasuryPDA;
+    });
+
+    after(async () => {
+      await updateConfig({ treasury: provider.wallet.publicKey });
+    });
+
+    it("collects the fees of three markets in one transaction", async () => {
+      const config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.equal(config.treasury.toString(), treasuryPDA.toString());
+
+      const now = Math.floor(Date.now() / 1000);
+      const marketIds: BN[] = [];
+      for (let i = 0; i < 3; i++) {
+        const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+        const side = i % 2 === 0;
+        await placePrediction(marketId, predictor1, side, new BN(web3.LAMPORTS_PER_SOL / 10));
+        marketIds.push(marketId);
+      }
+      for (const id of marketIds) {
+        const market = await program.account["market"].fetch(getMarketPDA(id)[0]);
+        assert.isAbove(market.feeCollected.toNumber(), 0);
+        collected = collected.add(market.feeCollected);
+      }
+
+      await expectError(collectFeesMany(marketIds, predictor1), "Unauthorized");
+
+      const before = await provider.connection.getBalance(treasuryPDA);
+      await collectFeesMany(marketIds);
+      assert.equal(
+        await provider.connection.getBalance(treasuryPDA),
+        before + collected.toNumber()
+      );
+      for (const id of marketIds) {
+        const market = await program.account["market"].fetch(getMarketPDA(id)[0]);
+        assert.equal(market.feeCollected.toNumber(), 0);
+        await assertSolvent(id);
+      }
+      const treasury = await program.account["treasury"].fetch(treasuryPDA);
+      assert.equal(treasury.totalCollected.toString(), collected.toString());
+    });
+
+    it("pays out of the treasury only through a proposed withdrawal", async () => {
+      await proposeWithdrawal(collected, predictor1.publicKey);
+      await expectError(proposeWithdrawal(collected, predictor1.publicKey), "already in use");
+      await expectError(executeWithdrawal(predictor2.publicKey), "InvalidRecipient");
+
+      const before = await provider.connection.getBalance(predictor1.publicKey);
+      await executeWithdrawal(predictor1.publicKey);
+      assert.equal(
+        await provider.connection.getBalance(predictor1.publicKey),
+        before + collected.toNumber()
+      );
+      const treasury = await program.account["treasury"].fetch(treasuryPDA);
+      assert.equal(treasury.totalWithdrawn.toString(), collected.toString());
+
+      // Nothing is left above rent, so a further withdrawal cannot execute
+      await proposeWithdrawal(new BN(1), predictor1.publicKey);
+      await expectError(executeWithdrawal(predictor1.publicKey), "TreasuryInsufficient");
+      await program.methods
+        .cancelTreasuryWithdrawal()
+        .accounts({ config: getConfigPDA()[0], pendingWithdrawal: pendingPDA, admin })
+        .rpc();
+      assert.isNull(await provider.connection.getAccountInfo(pendingPDA));
+    });
+  });
+
+  describe("Resolution evidence", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const expiredMarket = async (subjective: boolean) => {
+      const { marketId } = await createShortMarket(3, marketCreator, { subjective });
+      await betBothSides(marketId);
+      await sleep(4000);
+      return marketId;
+    };
+
+    it("requires evidence on subjective markets and records it", async () => {
+      const marketId = await expiredMarket(true);
+      await expectError(resolveMarket(marketId, true), "EvidenceRequired");
+      await expectError(resolveMarket(marketId, true, marketCreator, "  "), "EvidenceRequired");
+
+      const evidence = "https://example.com/final-standings";
+      const signature = await resolveMarket(marketId, true, marketCreator, evidence);
+      const resolved = (await getCpiEvents(signature)).find((e) =>
+        isEvent(e, "marketResolved")
+      );
+      assert.equal(resolved.data.evidence, evidence);
+
+      const record = await program.account["resolution"].fetch(getResolutionPDA(marketId));
+      assert.equal(record.evidence, evidence);
+      assert.ok(record.resolver.equals(marketCreator.publicKey));
+      assert.ok(record.marketId.eq(marketId));
+    });
+
+    it("rejects evidence over the length limit", async () => {
+      const marketId = await expiredMarket(false);
+      await expectError(
+        resolveMarket(marketId, true, marketCreator, "x".repeat(201)),
+        "EvidenceTooLong"
+      );
+      await resolveMarket(marketId, true, marketCreator, "x".repeat(200));
+    });
+
+    it("lets non-subjective markets resolve without evidence", async () => {
+      const marketId = await expiredMarket(false);
+      await resolveMarket(marketId, false);
+      const record = await program.account["resolution"].fetch(getResolutionPDA(marketId));
+      assert.equal(record.evidence, "");
+    });
+  });
+
+  describe("Per-market fees", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const futureTime = () => new BN(Math.floor(Date.now() / 1000) + 7 * 24 * 60 * 60);
+
+    const setMarketFee = (marketId: BN, feeBps: number) =>
+      program.methods
+        .setMarketFee(marketId, feeBps)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          creator: marketCreator.publicKey,
+        })
+        .signers([marketCreator])
+        .rpc();
+
+    it("rejects fees outside the config bounds", async () => {
+      for (const feeBps of [configParams.minFeeBps - 1, configParams.maxFeeBps + 1]) {
+        await expectError(
+          createMarket(futureTime(), marketCreator, {}, null, null, 5_000, feeBps),
+          "FeeOutOfBounds"
+        );
+      }
+    });
+
+    it("charges the chosen fee and defaults to the config fee", async () => {
+      const custom = await createMarket(futureTime(), marketCreator, {}, null, null, 5_000, 250);
+      assert.equal((await program.account["market"].fetch(custom.marketPDA)).feeBps, 250);
+      const builder = await placePredictionIx(custom.marketId, predictor1, true, PREDICTION_AMOUNT);
+      const placed = (await getCpiEvents(await builder.rpc())).find((e) =>
+        isEvent(e, "PredictionPlaced")
+      );
+      assert.equal(placed.data.feeBps, 250);
+
+      const standard = await createMarket(futureTime());
+      assert.isNull((await program.account["market"].fetch(standard.marketPDA)).feeBps);
+    });
+
+    it("lets the creator change the fee only before the first bet", async () => {
+      const { marketId, marketPDA } = await createMarket(futureTime());
+      await expectError(setMarketFee(marketId, configParams.maxFeeBps + 1), "FeeOutOfBounds");
+      await setMarketFee(marketId, 300);
+      assert.equal((await program.account["market"].fetch(marketPDA)).feeBps, 300);
+      await setMarketFee(marketId, 0);
+      assert.isNull((await program.account["market"].fetch(marketPDA)).feeBps);
+
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await expectError(setMarketFee(marketId, 300), "FeeLocked");
+    });
+  });
+
+  describe("Realized PnL", () => {
+    const whale = web3.Keypair.generate();
+    const minnow = web3.Keypair.generate();
+    const loser = web3.Keypair.generate();
+
+    before(async () => {
+      await airdropSol(whale.publicKey, 60);
+      await airdropSol(minnow.publicKey, 1);
+      await airdropSol(loser.publicKey, 2);
+    });
+
+    // Claims `winner`'s YES position in a market resolved YES and checks the
+    // reported PnL against the deposit
+    const claimAndCheck = async (marketId: BN, winner: web3.Keypair, deposit: BN) => {
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, winner.publicKey)[0]
+      );
+      assert.equal(prediction.amountDeposited.toString(), deposit.toString());
+
+      const events = await getCpiEvents(await claimReward(marketId, winner));
+      const claimed = events.find((e) => isEvent(e, "RewardClaimed")).data;
+      const pnl = claimed.reward.sub(deposit);
+      assert.equal(claimed.pnlLamports.toString(), pnl.toString());
+      assert.equal(claimed.returnBps.toString(), pnl.muln(10_000).div(deposit).toString());
+      return claimed;
+    };
+
+    it("records the entry probability of each side", async () => {
+      const { marketId, marketPDA } = await createMarket(
+        new BN(Math.floor(Date.now() / 1000) + 7 * 24 * 60 * 60),
+        marketCreator,
+        {},
+        null,
+        null,
+        3_000
+      );
+      await placePrediction(marketId, minnow, true, PREDICTION_AMOUNT);
+      let prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, minnow.publicKey)[0]
+      );
+      assert.equal(prediction.entryProbabilityBps, 3_000);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      const yesBps = market.yesPool
+        .muln(10_000)
+        .div(market.yesPool.add(market.noPool))
+        .toNumber();
+      await placePrediction(marketId, loser, false, PREDICTION_AMOUNT);
+      prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, loser.publicKey)[0]
+      );
+      assert.equal(prediction.entryProbabilityBps, 10_000 - yesBps);
+    });
+
+    it("reports exact PnL for very small and very large positions", async () => {
+      const small = await createShortMarket(3);
+      const tiny = new BN(1_000);
+      await placePrediction(small.marketId, minnow, true, tiny);
+      await placePrediction(small.marketId, loser, false, PREDICTION_AMOUNT);
+
+      const large = await createShortMarket(3);
+      const huge = new BN(50 * web3.LAMPORTS_PER_SOL);
+      await placePrediction(large.marketId, whale, true, huge);
+      await placePrediction(large.marketId, loser, false, PREDICTION_AMOUNT);
+
+      await sleep(4000);
+      await resolveMarket(small.marketId, true);
+      await resolveMarket(large.marketId, true);
+
+      // The tiny bet wins a share of the losing side; the whale mostly buys
+      // back its own stake and loses the fee
+      assert.isTrue((await claimAndCheck(small.marketId, minnow, tiny)).pnlLamports.gtn(0));
+      assert.isTrue((await claimAndCheck(large.marketId, whale, huge)).returnBps.ltn(10_000));
+    });
+
+    it("reports the loss when a losing position is closed", async () => {
+      const { marketId } = await createShortMarket(3);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, loser, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      const predictionPDA = getPredictionPDA(marketId, loser.publicKey)[0];
+      await program.methods
+        .recordLoss(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          predictionAccount: predictionPDA,
+          userProfile: getProfilePDA(loser.publicKey)[0],
+          payer: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+      const signature = await program.methods
+        .closePrediction(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          predictionAccount: predictionPDA,
+          userMarketIndex: (await getPositionIndexAccounts(marketId, loser.publicKey))
+            .userMarketIndex,
+          predictor: loser.publicKey,
+          rentPayer: loser.publicKey,
+        })
+        .signers([loser])
+        .rpc();
+
+      const closed = (await getCpiEvents(signature)).find((e) =>
+        isEvent(e, "PositionClosed")
+      ).data;
+      assert.equal(closed.pnlLamports.toString(), PREDICTION_AMOUNT.neg().toString());
+      assert.equal(closed.returnBps.toNumber(), -10_000);
+    });
+  });
+
+  describe("Cooling-off cancellation", () => {
+    before(async () => {
+      await airdropSol(predictor1.publicKey, 2);
+    });
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const futureTime = () => new BN(Math.floor(Date.now() / 1000) + 7 * 24 * 60 * 60);
+
+    const cancelPrediction = async (marketId: BN, predictor: web3.Keypair) =>
+      program.methods
+        .cancelPrediction(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, predictor.publicKey)[0],
+          userMarketIndex: (await getPositionIndexAccounts(marketId, predictor.publicKey))
+            .userMarketIndex,
+          predictor: predictor.publicKey,
+          rentPayer: predictor.publicKey,
+        })
+        .signers([predictor])
+        .rpc();
+
+    it("refunds the deposit and restores the market exactly", async () => {
+      const { marketId, marketPDA } = await createMarket(futureTime(), marketCreator, {
+        cooloffSecs: new BN(300),
+      });
+      const before = await program.account["market"].fetch(marketPDA);
+      const predictionPDA = await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      const rent = await provider.connection.getBalance(predictionPDA);
+      const balance = await provider.connection.getBalance(predictor1.publicKey);
+
+      await cancelPrediction(marketId, predictor1);
+
+      assert.equal(
+        await provider.connection.getBalance(predictor1.publicKey),
+        balance + PREDICTION_AMOUNT.toNumber() + rent
+      );
+      assert.isNull(await provider.connection.getAccountInfo(predictionPDA));
+      const after = await program.account["market"].fetch(marketPDA);
+      for (const field of ["yesPool", "noPool", "yesTokens", "feeCollected", "totalVolume"]) {
+        assert.equal(after[field].toString(), before[field].toString(), field);
+      }
+      assert.equal(after.tradeCount.toString(), before.tradeCount.toString());
+      await assertSolvent(marketId);
+    });
+
+    it("refuses once another bet has traded against the liquidity", async () => {
+      const { marketId } = await createMarket(futureTime(), marketCreator, {
+        cooloffSecs: new BN(300),
+      });
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await expectError(cancelPrediction(marketId, predictor1), "TradedSinceBet");
+    });
+
+    it("refuses outside the window or on markets without one", async () => {
+      const { marketId } = await createMarket(futureTime(), marketCreator, {
+        cooloffSecs: new BN(1),
+      });
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await sleep(2500);
+      await expectError(cancelPrediction(marketId, predictor1), "CooloffExpired");
+
+      const plain = await createMarket(futureTime());
+      await placePrediction(plain.marketId, predictor1, true, PREDICTION_AMOUNT);
+      await expectError(cancelPrediction(plain.marketId, predictor1), "CooloffDisabled");
+    });
+  });
+
+  describe("Early exit", () => {
+    const PENALTY_BPS = 1_000;
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const earlyExit = async (marketId: BN, predictor: web3.Keypair) =>
+      program.methods
+        .earlyExit(marketId)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, predictor.publicKey)[0],
+          userMarketIndex: (await getPositionIndexAccounts(marketId, predictor.publicKey))
+            .userMarketIndex,
+          predictor: predictor.publicKey,
+          rentPayer: predictor.publicKey,
+        })
+        .signers([predictor])
+        .rpc();
+
+    before(async () => {
+      await airdropSol(predictor1.publicKey, 2);
+      await airdropSol(predictor2.publicKey, 2);
+    });
+
+    it("refunds less the penalty and leaves the penalty to the winners", async () => {
+      const { marketId, marketPDA } = await createShortMarket(5, marketCreator, {
+        earlyExitPenaltyBps: PENALTY_BPS,
+      });
+      const seeded = await program.account["market"].fetch(marketPDA);
+      const predictionPDA = await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      const contributed = (await program.account["market"].fetch(marketPDA)).yesPool.sub(
+        seeded.yesPool
+      );
+
+      const rent = await provider.connection.getBalance(predictionPDA);
+      const balance = await provider.connection.getBalance(predictor1.publicKey);
+      await earlyExit(marketId, predictor1);
+      const refund = PREDICTION_AMOUNT.muln(10_000 - PENALTY_BPS).divn(10_000);
+      assert.equal(
+        await provider.connection.getBalance(predictor1.publicKey),
+        balance + refund.toNumber() + rent
+      );
+      assert.isNull(await provider.connection.getAccountInfo(predictionPDA));
+
+      // What the exit left behind stays on the YES side of the pot
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(
+        market.yesPool.toString(),
+        seeded.yesPool.add(contributed).sub(refund).toString()
+      );
+      assert.equal(market.yesTokens.toNumber(), 0);
+
+      await sleep(5000);
+      await resolveMarket(marketId, false);
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, predictor2.publicKey)[0]
+      );
+      const events = await getCpiEvents(await claimReward(marketId, predictor2));
+      const claimed = events.find((e) => isEvent(e, "RewardClaimed")).data;
+      assert.equal(
+        claimed.reward.toString(),
+        prediction.tokensReceived
+          .mul(market.yesPool.add(market.noPool))
+          .div(market.noPool)
+          .toString()
+      );
+      await assertSolvent(marketId);
+    });
+
+    it("is refused once betting closes or when the market has no penalty", async () => {
+      const { marketId } = await createShortMarket(3, marketCreator, {
+        earlyExitPenaltyBps: PENALTY_BPS,
+      });
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await expectError(earlyExit(marketId, predictor1), "MarketExpired");
+
+      const plain = await createMarket(new BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60));
+      await placePrediction(plain.marketId, predictor1, true, PREDICTION_AMOUNT);
+      await expectError(earlyExit(plain.marketId, predictor1), "EarlyExitDisabled");
+    });
+  });
+
+  describe("Limit entries", () => {
+    const owner = web3.Keypair.generate();
+    const keeper = web3.Keypair.generate();
+    const ENTRY = new BN(web3.LAMPORTS_PER_SOL / 10);
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const getLimitEntryPDA = (marketId: BN) =>
+      web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("limit_entry"),
+          marketId.toArrayLike(Buffer, "le", 8),
+          owner.publicKey.toBuffer(),
+        ],
+        program.programId
+      )[0];
+
+    const placeLimitEntry = (marketId: BN, side: boolean, limitBps: number) =>
+      program.methods
+        .placeLimitEntry(marketId, side, limitBps, ENTRY)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          limitEntry: getLimitEntryPDA(marketId),
+          selfExclusion: getSelfExclusionPDA(owner.publicKey),
+          owner: owner.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([owner])
+        .rpc();
+
+    const triggerLimitEntry = async (marketId: BN) => {
+      const config = await program.account["config"].fetch(getConfigPDA()[0]);
+      const { userMarketIndex, userIndexPage } = await getPositionIndexAccounts(
+        marketId,
+        owner.publicKey
+      );
+      return program.methods
+        .triggerLimitEntry(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          limitEntry: getLimitEntryPDA(marketId),
+          owner: owner.publicKey,
+          predictionAccount: getPredictionPDA(marketId, owner.publicKey)[0],
+          userProfile: getProfilePDA(owner.publicKey)[0],
+          userMarketIndex,
+          userIndexPage,
+          epochStats: getEpochStatsPDA(owner.publicKey, config.currentEpoch)[0],
+          series: null,
+          insuranceFund: getInsuranceFundPDA()[0],
+          keeper: keeper.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([keeper])
+        .rpc();
+    };
+
+    before(async () => {
+      await airdropSol(owner.publicKey, 2);
+      await airdropSol(keeper.publicKey, 1);
+      await airdropSol(predictor2.publicKey, 2);
+    });
+
+    it("fills only once the side reaches the limit, tipping the keeper", async () => {
+      const { marketId, marketPDA } = await createMarket(
+        new BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60)
+      );
+      await placeLimitEntry(marketId, true, 3_000);
+      await expectError(triggerLimitEntry(marketId), "LimitNotReached");
+
+      // A large NO bet pushes YES below 30%
+      await placePrediction(marketId, predictor2, false, new BN(web3.LAMPORTS_PER_SOL));
+      const market = await program.account["market"].fetch(marketPDA);
+      const yesBps = market.yesPool.muln(10_000).div(market.yesPool.add(market.noPool));
+      assert.isAtMost(yesBps.toNumber(), 3_000);
+
+      const keeperBefore = await provider.connection.getBalance(keeper.publicKey);
+      const events = await getCpiEvents(await triggerLimitEntry(marketId));
+      const filled = events.find((e) => isEvent(e, "LimitEntryFilled")).data;
+      // Tips round the way the market rounds fees: up, by default
+      const tip = ENTRY.muln(10).addn(9_999).divn(10_000);
+      assert.equal(filled.tip.toString(), tip.toString());
+      assert.equal(filled.probabilityBps, yesBps.toNumber());
+      // Rent the keeper fronted is paid back, leaving it the tip
+      assert.equal(
+        await provider.connection.getBalance(keeper.publicKey),
+        keeperBefore + tip.toNumber()
+      );
+
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, owner.publicKey)[0]
+      );
+      assert.ok(prediction.predictor.equals(owner.publicKey));
+      assert.equal(prediction.predictionType, true);
+      assert.equal(prediction.amountDeposited.toString(), ENTRY.sub(tip).toString());
+      assert.isNull(await provider.connection.getAccountInfo(getLimitEntryPDA(marketId)));
+      await assertSolvent(marketId);
+    });
+
+    it("refunds the whole escrow when the owner cancels", async () => {
+      const { marketId } = await createMarket(
+        new BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60)
+      );
+      await placeLimitEntry(marketId, false, 2_000);
+      const escrow = await provider.connection.getBalance(getLimitEntryPDA(marketId));
+      const before = await provider.connection.getBalance(owner.publicKey);
+
+      await program.methods
+        .cancelLimitEntry(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          limitEntry: getLimitEntryPDA(marketId),
+          owner: owner.publicKey,
+        })
+        .signers([owner])
+        .rpc();
+      assert.equal(await provider.connection.getBalance(owner.publicKey), before + escrow);
+    });
+
+    it("lets anyone expire an unfilled entry after betting closes", async () => {
+      const { marketId } = await createShortMarket(3);
+      await placeLimitEntry(marketId, true, 1_000);
+      const expire = () =>
+        program.methods
+          .expireLimitEntry(marketId)
+          .accounts({
+            market: getMarketPDA(marketId)[0],
+            limitEntry: getLimitEntryPDA(marketId),
+            owner: owner.publicKey,
+            config: getConfigPDA()[0],
+            marketVault: getVaultPDA(marketId)[0],
+            keeper: provider.wallet.publicKey,
+          })
+          .rpc();
+
+      await expectError(expire(), "LimitEntryActive");
+      await sleep(4000);
+      const escrow = await provider.connection.getBalance(getLimitEntryPDA(marketId));
+      const before = await provider.connection.getBalance(owner.publicKey);
+      await expire();
+      assert.equal(await provider.connection.getBalance(owner.publicKey), before + escrow);
+    });
+  });
+
+  describe("Stop-loss", () => {
+    const holder = web3.Keypair.generate();
+    const keeper = web3.Keypair.generate();
+    const STAKE = new BN(web3.LAMPORTS_PER_SOL / 10);
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const setStopLoss = (marketId: BN, thresholdBps: number) =>
+      program.methods
+        .setStopLoss(marketId, thresholdBps)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, holder.publicKey)[0],
+          predictor: holder.publicKey,
+        })
+        .signers([holder])
+        .rpc();
+
+    const executeStopLoss = async (marketId: BN) =>
+      program.methods
+        .executeStopLoss(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          predictionAccount: getPredictionPDA(marketId, holder.publicKey)[0],
+          userMarketIndex: (await getPositionIndexAccounts(marketId, holder.publicKey))
+            .userMarketIndex,
+          predictor: holder.publicKey,
+          rentPayer: holder.publicKey,
+          keeper: keeper.publicKey,
+        })
+        .signers([keeper])
+        .rpc();
+
+    const yesProbabilityBps = async (marketPDA: web3.PublicKey) => {
+      const market = await program.account["market"].fetch(marketPDA);
+      return market.yesPool
+        .muln(10_000)
+        .div(market.yesPool.add(market.noPool))
+        .toNumber();
+    };
+
+    before(async () => {
+      await airdropSol(holder.publicKey, 1);
+      await airdropSol(keeper.publicKey, 1);
+    });
+
+    it("executes only once the side is strictly below the threshold", async () => {
+      const { marketId, marketPDA } = await createMarket(
+        new BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60),
+        marketCreator,
+        { earlyExitPenaltyBps: 500 }
+      );
+      const predictionPDA = await placePrediction(marketId, holder, true, STAKE);
+      await expectError(executeStopLoss(marketId), "StopLossNotSet");
+
+      // Exactly at the threshold is not a breach
+      const probability = await yesProbabilityBps(marketPDA);
+      await setStopLoss(marketId, probability);
+      await expectError(executeStopLoss(marketId), "StopLossNotBreached");
+
+      await setStopLoss(marketId, probability + 1);
+      const rent = await provider.connection.getBalance(predictionPDA);
+      const holderBefore = await provider.connection.getBalance(holder.publicKey);
+      const keeperBefore = await provider.connection.getBalance(keeper.publicKey);
+      const events = await getCpiEvents(await executeStopLoss(marketId));
+
+      const refund = STAKE.muln(9_500).divn(10_000);
+      const tip = BN.min(refund.muln(50).addn(9_999).divn(10_000), new BN(10_000_000));
+      const executed = events.find((e) => isEvent(e, "StopLossExecuted")).data;
+      assert.equal(executed.probabilityBps, probability);
+      assert.equal(executed.tip.toString(), tip.toString());
+      assert.equal(
+        await provider.connection.getBalance(holder.publicKey),
+        holderBefore + refund.sub(tip).toNumber() + rent
+      );
+      assert.equal(
+        await provider.connection.getBalance(keeper.publicKey),
+        keeperBefore + tip.toNumber()
+      );
+      assert.isNull(await provider.connection.getAccountInfo(predictionPDA));
+      await assertSolvent(marketId);
+    });
+
+    it("can't be set on markets without early exit", async () => {
+      const { marketId } = await createMarket(
+        new BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60)
+      );
+      await placePrediction(marketId, holder, true, STAKE);
+      await expectError(setStopLoss(marketId, 2_000), "EarlyExitDisabled");
+    });
+  });
+
+  describe("Claim and roll", () => {
+    const SERIES_NAME = "rollover";
+    const [seriesPDA] = getSeriesPDA(marketCreator.publicKey, SERIES_NAME);
+    const winner = web3.Keypair.generate();
+    const loser = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const createSeriesMarket = async (seconds: number) => {
+      const minDuration = configParams.minMarketDuration;
+      await updateConfig({ minMarketDuration: new BN(0) });
+      try {
+        const now = Math.floor(Date.now() / 1000);
+        return await createMarket(new BN(now + seconds), marketCreator, {}, seriesPDA);
+      } finally {
+        await updateConfig({ minMarketDuration: minDuration });
+      }
+    };
+
+    const claimAndRoll = async (
+      sourceId: BN,
+      targetId: BN,
+      minTokensOut: BN,
+      keepBack: BN
+    ) =>
+      program.methods
+        .claimAndRoll(sourceId, targetId, true, minTokensOut, keepBack)
+        .accounts({
+          sourceMarket: getMarketPDA(sourceId)[0],
+          sourceVault: getVaultPDA(sourceId)[0],
+          sourcePrediction: getPredictionPDA(sourceId, winner.publicKey)[0],
+          targetMarket: getMarketPDA(targetId)[0],
+          targetVault: getVaultPDA(targetId)[0],
+          targetPrediction: getPredictionPDA(targetId, winner.publicKey)[0],
+          config: getConfigPDA()[0],
+          userProfile: getProfilePDA(winner.publicKey)[0],
+          ...(await getPositionIndexAccounts(targetId, winner.publicKey)),
+          epochStats: getEpochStatsPDA(winner.publicKey, await currentEpoch())[0],
+          series: seriesPDA,
+          insuranceFund: getInsuranceFundPDA()[0],
+          selfExclusion: getSelfExclusionPDA(winner.publicKey),
+          claimer: winner.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([winner])
+        .rpc();
+
+    // A resolved market the winner won, and the open market after it
+    const setUp = async () => {
+      const source = await createSeriesMarket(3);
+      const target = await createSeriesMarket(7 * 24 * 60 * 60);
+      await placePrediction(source.marketId, winner, true, PREDICTION_AMOUNT);
+      await placePrediction(source.marketId, loser, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(source.marketId, true);
+      return { source, target };
+    };
+
+    before(async () => {
+      await airdropSol(winner.publicKey, 2);
+      await airdropSol(loser.publicKey, 2);
+      await program.methods
+        .createSeries(SERIES_NAME)
+        .accounts({
+          series: seriesPDA,
+          creator: marketCreator.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([marketCreator])
+        .rpc();
+    });
+
+    it("bets the winnings on the next market, less the keep-back", async () => {
+      const { source, target } = await setUp();
+      const keepBack = new BN(1_000_000);
+      const events = await getCpiEvents(
+        await claimAndRoll(source.marketId, target.marketId, new BN(1), keepBack)
+      );
+
+      const rolled = events.find((e) => isEvent(e, "WinningsRolled")).data;
+      assert.equal(rolled.keptBack.toString(), keepBack.toString());
+      assert.equal(rolled.amount.toString(), rolled.reward.sub(keepBack).toString());
+
+      const claimed = await program.account["prediction"].fetch(
+        getPredictionPDA(source.marketId, winner.publicKey)[0]
+      );
+      assert.isTrue(claimed.claimed);
+      const placed = await program.account["prediction"].fetch(
+        getPredictionPDA(target.marketId, winner.publicKey)[0]
+      );
+      assert.isTrue(placed.predictionType);
+      assert.equal(placed.amountDeposited.toString(), rolled.amount.toString());
+      assert.equal(placed.tokensReceived.toString(), rolled.tokensReceived.toString());
+      await assertSolvent(source.marketId);
+      await assertSolvent(target.marketId);
+    });
+
+    it("reverts the claim when the slippage limit is missed", async () => {
+      const { source, target } = await setUp();
+      await expectError(
+        claimAndRoll(source.marketId, target.marketId, new BN("18446744073709551615"), new BN(0)),
+        "SlippageExceeded"
+      );
+
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(source.marketId, winner.publicKey)[0]
+      );
+      assert.isFalse(prediction.claimed);
+      assert.equal(prediction.tokensClaimed.toString(), "0");
+    });
+  });
+
+  describe("Claim account consistency", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    // claim_reward with each account chosen independently of the others
+    const forgedClaim = async (
+      marketIdArg: BN,
+      marketId: BN,
+      predictionMarketId: BN,
+      claimer: web3.Keypair
+    ) =>
+      program.methods
+        .claimReward(marketIdArg, null, false, false)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          predictionAccount: getPredictionPDA(predictionMarketId, claimer.publicKey)[0],
+          userProfile: getProfilePDA(claimer.publicKey)[0],
+          epochStats: getEpochStatsPDA(claimer.publicKey, await currentEpoch())[0],
+          claimer: claimer.publicKey,
+          recipient: null,
+          wsolVault: null,
+          payoutTokenAccount: null,
+          collateralMint: null,
+          tokenProgram: null,
+          claimReceipt: null,
+          escrowedClaim: null,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([claimer])
+        .rpc();
+
+    // Two resolved markets; predictor1 won the first and has no position
+    // in the second
+    const setUp = async () => {
+      const first = await createShortMarket(3);
+      const second = await createShortMarket(3);
+      await placePrediction(first.marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(first.marketId, predictor2, false, PREDICTION_AMOUNT);
+      await placePrediction(second.marketId, predictor2, true, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(first.marketId, true);
+      await resolveMarket(second.marketId, true);
+      return { first, second };
+    };
+
+    const assertUnclaimed = async (marketId: BN) => {
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, predictor1.publicKey)[0]
+      );
+      assert.isFalse(prediction.claimed);
+    };
+
+    it("rejects a prediction from another market", async () => {
+      const { first, second } = await setUp();
+      await expectError(
+        forgedClaim(second.marketId, second.marketId, first.marketId, predictor1),
+        "ConstraintSeeds"
+      );
+      await assertUnclaimed(first.marketId);
+    });
+
+    it("rejects a market account that doesn't match the market id", async () => {
+      const { first, second } = await setUp();
+      await expectError(
+        forgedClaim(first.marketId, second.marketId, first.marketId, predictor1),
+        "ConstraintSeeds"
+      );
+      await assertUnclaimed(first.marketId);
+
+      await claimReward(first.marketId, predictor1);
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(first.marketId, predictor1.publicKey)[0]
+      );
+      assert.isTrue(prediction.claimed);
+    });
+  });
+
+  describe("Creator account validation", () => {
+    const outsider = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const withdrawFees = (
+      marketId: BN,
+      admin: web3.Keypair,
+      amount: BN,
+      vault: web3.PublicKey = getVaultPDA(marketId)[0]
+    ) =>
+      program.methods
+        .withdrawFees(marketId, amount)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: vault,
+          admin: admin.publicKey,
+          wsolVault: null,
+          payoutTokenAccount: null,
+          collateralMint: null,
+          tokenProgram: null,
+        })
+        .signers([admin])
+        .rpc();
+
+    before(async () => {
+      await airdropSol(outsider.publicKey, 1);
+    });
+
+    it("only pays collected fees to the creator, from the market's own vault", async () => {
+      const { marketId, marketPDA } = await createShortMarket(60 * 60);
+      const other = await createShortMarket(60 * 60);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(other.marketId, predictor1, true, PREDICTION_AMOUNT);
+      const fees = (await program.account["market"].fetch(marketPDA)).feeCollected;
+      assert.isTrue(fees.gtn(0));
+
+      await expectError(withdrawFees(marketId, outsider, fees), "Unauthorized");
+      await expectError(
+        withdrawFees(marketId, marketCreator, fees, getVaultPDA(other.marketId)[0]),
+        "ConstraintSeeds"
+      );
+
+      const before = await provider.connection.getBalance(marketCreator.publicKey);
+      await withdrawFees(marketId, marketCreator, fees);
+      assert.equal(
+        await provider.connection.getBalance(marketCreator.publicKey),
+        before + fees.toNumber()
+      );
+      await assertSolvent(marketId);
+      await assertSolvent(other.marketId);
+    });
+
+    it("only lets the creator resolve, recording evidence for that market", async () => {
+      const { marketId } = await createShortMarket(3);
+      const other = await createShortMarket(3);
+      await sleep(4000);
+
+      await expectError(resolveMarket(marketId, true, outsider), "Unauthorized");
+      await expectError(
+        program.methods
+          .resolveMarket(marketId, true, "")
+          .accounts({
+            market: getMarketPDA(marketId)[0],
+            marketVault: getVaultPDA(marketId)[0],
+            wsolVault: null,
+            resolution: getResolutionPDA(other.marketId),
+            admin: marketCreator.publicKey,
+            payer: marketCreator.publicKey,
+            systemProgram: web3.SystemProgram.programId,
+          })
+          .signers([marketCreator])
+          .rpc(),
+        "ConstraintSeeds"
+      );
+
+      await resolveMarket(marketId, true);
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.isTrue(market.resolved);
+    });
+  });
+
+  describe("Settle delay", () => {
+    const SETTLE_DELAY = 6;
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    it("holds resolution until the delay after the last trade has passed", async () => {
+      await updateConfig({ minSettleDelay: new BN(SETTLE_DELAY) });
+      let marketId: BN;
+      try {
+        ({ marketId } = await createShortMarket(3));
+      } finally {
+        await updateConfig({ minSettleDelay: new BN(0) });
+      }
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.equal(market.minSettleDelay.toNumber(), SETTLE_DELAY);
+
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      const lastTradeAt = (
+        await program.account["market"].fetch(getMarketPDA(marketId)[0])
+      ).lastTradeAt.toNumber();
+      assert.isAtLeast(lastTradeAt, market.createdAt.toNumber());
+
+      // Past the resolution time, but not the delay
+      await sleep(4000);
+      await expectError(resolveMarket(marketId, true), "SettleDelayActive");
+
+      await sleep((lastTradeAt + SETTLE_DELAY + 1) * 1000 - Date.now());
+      await resolveMarket(marketId, true);
+      assert.isTrue(
+        (await program.account["market"].fetch(getMarketPDA(marketId)[0])).resolved
+      );
+    });
+
+    it("refuses bets at or after the resolution time", async () => {
+      const { marketId } = await createShortMarket(2);
+      await sleep(3000);
+      await expectError(
+        placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT),
+        "MarketExpired"
+      );
+    });
+  });
+
+  describe("Creator whitelist", () => {
+    const operator = web3.Keypair.generate();
+    const outsider = web3.Keypair.generate();
+    const WEEK = 7 * 24 * 60 * 60;
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const setApproval = (method: string, creator: web3.PublicKey, admin?: web3.Keypair) =>
+      (method === "approveCreator"
+        ? program.methods.approveCreator(creator, false, 0)
+        : program.methods.revokeCreator(creator)
+      )
+        .accounts({
+          config: getConfigPDA()[0],
+          creatorAllow: getCreatorAllowPDA(creator),
+          admin: admin ? admin.publicKey : provider.wallet.publicKey,
+        })
+        .signers(admin ? [admin] : [])
+        .rpc();
+
+    const inAWeek = () => new BN(Math.floor(Date.now() / 1000) + WEEK);
+
+    before(async () => {
+      await airdropSol(operator.publicKey, 5);
+      await airdropSol(outsider.publicKey, 5);
+    });
+
+    afterEach(async () => {
+      await updateConfig({ creationMode: { open: {} } });
+    });
+
+    it("lets anyone create markets in open mode", async () => {
+      await createMarket(inAWeek(), outsider);
+    });
+
+    it("only lets approved creators create markets when whitelisted", async () => {
+      const existing = await createMarket(inAWeek(), outsider);
+      await expectError(
+        setApproval("approveCreator", operator.publicKey, outsider),
+        "Unauthorized"
+      );
+      await setApproval("approveCreator", operator.publicKey);
+      const allow = await program.account["creatorAllow"].fetch(
+        getCreatorAllowPDA(operator.publicKey)
+      );
+      assert.ok(allow.creator.equals(operator.publicKey));
+
+      await updateConfig({ creationMode: { whitelisted: {} } });
+      await expectError(createMarket(inAWeek(), outsider), "CreatorNotApproved");
+      await createMarket(inAWeek(), operator);
+
+      // Markets created before the switch keep trading
+      await placePrediction(existing.marketId, predictor1, true, PREDICTION_AMOUNT);
+    });
+
+    it("stops a revoked creator mid-stream without touching its markets", async () => {
+      // operator was approved by the previous test
+      await updateConfig({ creationMode: { whitelisted: {} } });
+      const { marketId } = await createMarket(inAWeek(), operator);
+
+      await setApproval("revokeCreator", operator.publicKey);
+      assert.isNull(
+        await provider.connection.getAccountInfo(getCreatorAllowPDA(operator.publicKey))
+      );
+      await expectError(createMarket(inAWeek(), operator), "CreatorNotApproved");
+
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.equal(market.tradeCount, 1);
+    });
+  });
+
+  describe("Market creation fee", () => {
+    const approvedCreator = web3.Keypair.generate();
+    const poorCreator = web3.Keypair.generate();
+    const FEE = new BN(web3.LAMPORTS_PER_SOL / 10);
+    const WEEK = 7 * 24 * 60 * 60;
+    const treasuryPDA = getTreasuryPDA();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const inAWeek = () => new BN(Math.floor(Date.now() / 1000) + WEEK);
+    const treasuryBalance = () => provider.connection.getBalance(treasuryPDA);
+
+    before(async () => {
+      await airdropSol(approvedCreator.publicKey, 5);
+      if (!(await provider.connection.getAccountInfo(treasuryPDA))) {
+        await program.methods
+          .initTreasury()
+          .accounts({
+            config: getConfigPDA()[0],
+            treasury: treasuryPDA,
+            admin: provider.wallet.publicKey,
+          })
+          .rpc();
+      }
+      await program.methods
+        .approveCreator(approvedCreator.publicKey, true, 0)
+        .accounts({
+          config: getConfigPDA()[0],
+          creatorAllow: getCreatorAllowPDA(approvedCreator.publicKey),
+          admin: provider.wallet.publicKey,
+        })
+        .rpc();
+    });
+
+    beforeEach(async () => {
+      await updateConfig({ treasury: treasuryPDA, creationFeeLamports: FEE });
+    });
+
+    after(async () => {
+      await updateConfig({ treasury: provider.wallet.publicKey, creationFeeLamports: new BN(0) });
+    });
+
+    it("charges the creator and records the fee in MarketCreated", async () => {
+      const before = await treasuryBalance();
+      const { signature } = await createMarket(inAWeek());
+
+      assert.equal((await treasuryBalance()) - before, FEE.toNumber());
+      const created = (await getCpiEvents(signature)).find((e) => isEvent(e, "MarketCreated"));
+      assert.equal(created.data.creationFee.toString(), FEE.toString());
+    });
+
+    it("waives the fee for creators approved with fee_waived", async () => {
+      const before = await treasuryBalance();
+      await createMarket(inAWeek(), approvedCreator);
+      assert.equal(await treasuryBalance(), before);
+    });
+
+    it("fails cleanly when the creator can't cover seed plus fee", async () => {
+      // Enough for rent and the seed, but not the fee on top
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(10_000);
+      await provider.sendAndConfirm(
+        new web3.Transaction().add(
+          web3.SystemProgram.transfer({
+            fromPubkey: provider.wallet.publicKey,
+            toPubkey: poorCreator.publicKey,
+            lamports: INITIAL_LIQUIDITY.toNumber() + rent,
+          })
+        )
+      );
+      await expectError(createMarket(inAWeek(), poorCreator), "InsufficientCreatorFunds");
+    });
+  });
+
+  describe("Bookmaker mode", () => {
+    const bettor = web3.Keypair.generate();
+    const outsider = web3.Keypair.generate();
+    const BOOKMAKER = { mode: { bookmaker: {} }, yesOddsBps: 18_000, noOddsBps: 25_000 };
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const createBookmaker = async (seconds: number) => {
+      const minDuration = configParams.minMarketDuration;
+      await updateConfig({ minMarketDuration: new BN(0) });
+      try {
+        const now = Math.floor(Date.now() / 1000);
+        return await createMarket(new BN(now + seconds), marketCreator, BOOKMAKER);
+      } finally {
+        await updateConfig({ minMarketDuration: minDuration });
+      }
+    };
+
+    const updateOdds = (marketId: BN, yes: number, no: number, signer = marketCreator) =>
+      program.methods
+        .updateOdds(marketId, yes, no)
+        .accounts({ market: getMarketPDA(marketId)[0], creator: signer.publicKey })
+        .signers([signer])
+        .rpc();
+
+    const fetchMarket = (marketId: BN) =>
+      program.account["market"].fetch(getMarketPDA(marketId)[0]);
+
+    const fetchPrediction = (marketId: BN, predictor: web3.Keypair) =>
+      program.account["prediction"].fetch(getPredictionPDA(marketId, predictor.publicKey)[0]);
+
+    // Payout beyond the bet's own stake, held against the bankroll
+    const liabilityOf = (prediction: any, amount: BN) =>
+      prediction.tokensReceived.sub(amount.sub(amount.muln(configParams.tradingFeeBps).divn(10_000)));
+
+    before(async () => {
+      await airdropSol(bettor.publicKey, 5);
+      await airdropSol(outsider.publicKey, 2);
+      await airdropSol(predictor1.publicKey, 3);
+      await airdropSol(predictor2.publicKey, 3);
+    });
+
+    it("seeds the bankroll and locks each bet's liability at fixed odds", async () => {
+      const { marketId } = await createBookmaker(7 * 24 * 60 * 60);
+      let market = await fetchMarket(marketId);
+      assert.equal(market.bankroll.toString(), INITIAL_LIQUIDITY.toString());
+      assert.equal(market.yesPool.toNumber() + market.noPool.toNumber(), 0);
+
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      const prediction = await fetchPrediction(marketId, predictor1);
+      const net = PREDICTION_AMOUNT.sub(
+        PREDICTION_AMOUNT.muln(configParams.tradingFeeBps).divn(10_000)
+      );
+      assert.equal(prediction.tokensReceived.toString(), net.muln(18).divn(10).toString());
+
+      market = await fetchMarket(marketId);
+      assert.equal(
+        market.lockedLiability.toString(),
+        liabilityOf(prediction, PREDICTION_AMOUNT).toString()
+      );
+      assert.equal(market.yesPool.toString(), net.toString());
+    });
+
+    it("rejects bets the bankroll can't cover", async () => {
+      const { marketId } = await createBookmaker(7 * 24 * 60 * 60);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      const before = await fetchMarket(marketId);
+
+      // 2.5x on a whole bankroll's worth needs 1.5x the bankroll
+      await expectError(
+        placePrediction(marketId, bettor, false, INITIAL_LIQUIDITY),
+        "BankrollExceeded"
+      );
+      const after = await fetchMarket(marketId);
+      assert.equal(after.lockedLiability.toString(), before.lockedLiability.toString());
+    });
+
+    it("applies new odds to future bets only", async () => {
+      const { marketId } = await createBookmaker(7 * 24 * 60 * 60);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      const first = await fetchPrediction(marketId, predictor1);
+
+      await expectError(updateOdds(marketId, 12_000, 30_000, outsider), "Unauthorized");
+      await expectError(updateOdds(marketId, 10_000, 30_000), "InvalidOdds");
+      await updateOdds(marketId, 12_000, 30_000);
+
+      await placePrediction(marketId, predictor2, true, PREDICTION_AMOUNT);
+      const second = await fetchPrediction(marketId, predictor2);
+      assert.equal(
+        second.tokensReceived.muln(18).toString(),
+        first.tokensReceived.muln(12).toString()
+      );
+      assert.equal(
+        (await fetchPrediction(marketId, predictor1)).tokensReceived.toString(),
+        first.tokensReceived.toString()
+      );
+
+      const market = await fetchMarket(marketId);
+      assert.equal(market.yesOddsBps, 12_000);
+      assert.equal(
+        market.lockedLiability.toString(),
+        liabilityOf(first, PREDICTION_AMOUNT).add(liabilityOf(second, PREDICTION_AMOUNT)).toString()
+      );
+    });
+
+    it("pays winners their multiple and the rest of the vault to the creator", async () => {
+      const { marketId } = await createBookmaker(4);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+
+      const winner = await fetchPrediction(marketId, predictor1);
+      const vault = getVaultPDA(marketId)[0];
+      const vaultBefore = await provider.connection.getBalance(vault);
+      await claimReward(marketId, predictor1);
+      assert.equal(
+        vaultBefore - (await provider.connection.getBalance(vault)),
+        winner.tokensReceived.toNumber()
+      );
+
+      // Bankroll plus both stakes, less the YES payout
+      const market = await fetchMarket(marketId);
+      const due = market.bankroll
+        .add(market.yesPool)
+        .add(market.noPool)
+        .sub(winner.tokensReceived);
+      const withdrawBankroll = () =>
+        program.methods
+          .withdrawBankroll(marketId)
+          .accounts({
+            market: getMarketPDA(marketId)[0],
+            marketVault: vault,
+            creator: marketCreator.publicKey,
+            wsolVault: null,
+            payoutTokenAccount: null,
+            collateralMint: null,
+            tokenProgram: null,
+          })
+          .signers([marketCreator])
+          .rpc();
+      const vaultBeforeWithdraw = await provider.connection.getBalance(vault);
+      const events = await getCpiEvents(await withdrawBankroll());
+      const withdrawn = events.find((e) => isEvent(e, "BankrollWithdrawn")).data;
+      assert.equal(withdrawn.amount.toString(), due.toString());
+      assert.equal(
+        vaultBeforeWithdraw - (await provider.connection.getBalance(vault)),
+        due.toNumber()
+      );
+      await expectError(withdrawBankroll(), "NoReward");
+    });
+  });
+
+  describe("Pari-mutuel mode", () => {
+    const third = web3.Keypair.generate();
+    const PARIMUTUEL = { mode: { parimutuel: {} } };
+
+    const createParimutuel = async (seconds: number) => {
+      const minDuration = configParams.minMarketDuration;
+      await updateConfig({ minMarketDuration: new BN(0) });
+      try {
+        const now = Math.floor(Date.now() / 1000);
+        return await createMarket(new BN(now + seconds), marketCreator, PARIMUTUEL);
+      } finally {
+        await updateConfig({ minMarketDuration: minDuration });
+      }
+    };
+
+    const netOf = (amount: BN) =>
+      amount.sub(amount.muln(configParams.tradingFeeBps).addn(9_999).divn(10_000));
+
+    before(async () => {
+      await airdropSol(third.publicKey, 3);
+      await airdropSol(predictor1.publicKey, 2);
+      await airdropSol(predictor2.publicKey, 2);
+    });
+
+    it("announces its mode and adds bets to the pools 1:1", async () => {
+      const { marketId, signature } = await createParimutuel(7 * 24 * 60 * 60);
+      const created = (await getCpiEvents(signature)).find((e) => isEvent(e, "MarketCreated"));
+      assert.deepEqual(created.data.mode, { parimutuel: {} });
+
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, third, true, PREDICTION_AMOUNT);
+      const first = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, predictor1.publicKey)[0]
+      );
+      const second = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, third.publicKey)[0]
+      );
+      // No curve: the later bet gets the same tokens for the same stake
+      assert.equal(first.tokensReceived.toString(), netOf(PREDICTION_AMOUNT).toString());
+      assert.equal(second.tokensReceived.toString(), first.tokensReceived.toString());
+
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.equal(market.yesPool.toString(), netOf(PREDICTION_AMOUNT).muln(2).toString());
+      assert.equal(market.noPool.toNumber(), 0);
+      assert.equal(market.bankroll.toString(), INITIAL_LIQUIDITY.toString());
+    });
+
+    it("splits the pot by stake and returns the seed to the creator", async () => {
+      const { marketId } = await createParimutuel(4);
+      const big = PREDICTION_AMOUNT.muln(2);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, third, true, big);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+
+      const winningTotal = netOf(PREDICTION_AMOUNT).add(netOf(big));
+      const pot = winningTotal.add(netOf(PREDICTION_AMOUNT));
+      const claimed = (await getCpiEvents(await claimReward(marketId, predictor1))).find((e) =>
+        isEvent(e, "RewardClaimed")
+      );
+      assert.equal(
+        claimed.data.reward.toString(),
+        netOf(PREDICTION_AMOUNT).mul(pot).div(winningTotal).toString()
+      );
+
+      const creatorBefore = await provider.connection.getBalance(marketCreator.publicKey);
+      await program.methods
+        .withdrawBankroll(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          creator: marketCreator.publicKey,
+          wsolVault: null,
+          payoutTokenAccount: null,
+          collateralMint: null,
+          tokenProgram: null,
+        })
+        .signers([marketCreator])
+        .rpc();
+      assert.equal(
+        await provider.connection.getBalance(marketCreator.publicKey),
+        creatorBefore + INITIAL_LIQUIDITY.toNumber()
+      );
+    });
+  });
+
+  describe("Event sequence numbers", () => {
+    before(async () => {
+      await airdropSol(predictor1.publicKey, 2);
+      await airdropSol(predictor2.publicKey, 2);
+    });
+
+    it("numbers each market's events from 1 without gaps", async () => {
+      const a = await createShortMarket(6);
+      const b = await createShortMarket(6);
+      const bet = async (marketId: BN, predictor: web3.Keypair, side: boolean) =>
+        (await placePredictionIx(marketId, predictor, side, PREDICTION_AMOUNT)).rpc();
+
+      // Interleave trades and creator actions across both markets
+      const signatures = [a.signature, b.signature];
+      signatures.push(await bet(a.marketId, predictor1, true));
+      signatures.push(await setDescription(b.marketId, "second market"));
+      signatures.push(await bet(b.marketId, predictor2, false));
+      signatures.push(await bet(a.marketId, predictor2, false));
+      signatures.push(await setDescription(a.marketId, "first market"));
+      signatures.push(await bet(b.marketId, predictor1, true));
+      await sleep(7000);
+      signatures.push(await resolveMarket(a.marketId, true));
+      signatures.push(await resolveMarket(b.marketId, false));
+      signatures.push(await claimReward(a.marketId, predictor1));
+
+      const seqs = new Map<string, number[]>();
+      for (const signature of signatures) {
+        for (const event of await getCpiEvents(signature)) {
+          if (event.data.eventSeq === undefined) continue;
+          const market = event.data.market.toBase58();
+          seqs.set(market, [...(seqs.get(market) ?? []), event.data.eventSeq.toNumber()]);
+        }
+      }
+
+      for (const { marketId } of [a, b]) {
+        const address = getMarketPDA(marketId)[0];
+        const numbered = seqs.get(address.toBase58());
+        assert.deepEqual(numbered, numbered.map((_, i) => i + 1));
+        const market = await program.account["market"].fetch(address);
+        assert.equal(market.eventSeq.toNumber(), numbered.length);
+      }
+      assert.equal(seqs.size, 2);
+    });
+  });
+
+  describe("Prediction notes", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const openMarket = async () => {
+      const now = Math.floor(Date.now() / 1000);
+      return (await createMarket(new BN(now + 7 * 24 * 60 * 60))).marketId;
+    };
+
+    before(async () => {
+      await airdropSol(predictor1.publicKey, 2);
+      await airdropSol(predictor2.publicKey, 2);
+    });
+
+    it("stores the note on the prediction and emits it", async () => {
+      const marketId = await openMarket();
+      const note = "ticket #4521";
+      const signature = await (
+        await placePredictionIx(marketId, predictor1, true, PREDICTION_AMOUNT, null, null, 0, note)
+      ).rpc();
+
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, predictor1.publicKey)[0]
+      );
+      assert.equal(prediction.note, note);
+      const placed = (await getCpiEvents(signature)).find((e) => isEvent(e, "PredictionPlaced"));
+      assert.equal(placed.data.note, note);
+    });
+
+    it("leaves the note empty when none is given", async () => {
+      const marketId = await openMarket();
+      const predictionPDA = await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      const prediction = await program.account["prediction"].fetch(predictionPDA);
+      assert.equal(prediction.note, "");
+    });
+
+    it("rejects a note over 64 bytes", async () => {
+      const marketId = await openMarket();
+      const place = (note: string) =>
+        placePredictionIx(marketId, predictor1, true, PREDICTION_AMOUNT, null, null, 0, note).then(
+          (builder) => builder.rpc()
+        );
+      // Multi-byte characters count by their UTF-8 length
+      await expectError(place("é".repeat(33)), "NoteTooLong");
+      await place("x".repeat(64));
+    });
+  });
+
+  describe("Winnings fee", () => {
+    const WINNINGS_FEE_BPS = 1_000;
+
+    const claimedEvent = async (marketId: BN, claimer: web3.Keypair) =>
+      (await getCpiEvents(await claimReward(marketId, claimer))).find((e) =>
+        isEvent(e, "RewardClaimed")
+      );
+
+    before(async () => {
+      await airdropSol(predictor1.publicKey, 2);
+      await airdropSol(predictor2.publicKey, 2);
+    });
+
+    it("charges nothing on a claim that doesn't beat the deposit", async () => {
+      // A pari-mutuel winner whose only counterparty staked less than the
+      // trading fee gets back less than they put in
+      const { marketId } = await createShortMarket(4, marketCreator, {
+        mode: { parimutuel: {} },
+        winningsFeeBps: WINNINGS_FEE_BPS,
+      });
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT.divn(200));
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+
+      const before = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      const claimed = await claimedEvent(marketId, predictor1);
+      assert.isTrue(claimed.data.grossReward.lte(PREDICTION_AMOUNT));
+      assert.equal(claimed.data.winningsFee.toNumber(), 0);
+      assert.equal(claimed.data.reward.toString(), claimed.data.grossReward.toString());
+      const after = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.equal(after.feeCollected.toString(), before.feeCollected.toString());
+    });
+
+    it("takes its cut of the profit only and keeps it as a market fee", async () => {
+      const { marketId } = await createShortMarket(4, marketCreator, {
+        winningsFeeBps: WINNINGS_FEE_BPS,
+      });
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT.muln(5));
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+
+      const before = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      const balanceBefore = await provider.connection.getBalance(predictor1.publicKey);
+      const claimed = await claimedEvent(marketId, predictor1);
+      const balanceAfter = await provider.connection.getBalance(predictor1.publicKey);
+
+      const gross: BN = claimed.data.grossReward;
+      const profit = gross.sub(PREDICTION_AMOUNT);
+      assert.isTrue(profit.gtn(0));
+      const fee = profit.muln(WINNINGS_FEE_BPS).addn(9_999).divn(10_000);
+      assert.equal(claimed.data.winningsFee.toString(), fee.toString());
+      assert.equal(claimed.data.reward.toString(), gross.sub(fee).toString());
+      assert.equal(balanceAfter - balanceBefore, gross.sub(fee).toNumber());
+
+      const after = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.equal(after.feeCollected.sub(before.feeCollected).toString(), fee.toString());
+    });
+  });
+
+  describe("Resolver tiers", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const backup = web3.Keypair.generate();
+    const admin = provider.wallet.publicKey;
+    const BACKUP_DELAY = 3;
+    const ADMIN_DELAY = 6;
+
+    const setResolvers = (
+      marketId: BN,
+      resolvers: web3.PublicKey[],
+      delays: number[],
+      creator: web3.Keypair = marketCreator
+    ) =>
+      program.methods
+        .setResolvers(marketId, resolvers, delays.map((d) => new BN(d)))
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          creator: creator.publicKey,
+        })
+        .signers([creator])
+        .rpc();
+
+    // The protocol admin is the provider wallet, which signs implicitly
+    const resolveAsAdmin = (marketId: BN, outcome: boolean) =>
+      program.methods
+        .resolveMarket(marketId, outcome, "")
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          wsolVault: null,
+          resolution: getResolutionPDA(marketId),
+          admin,
+          payer: admin,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    const resolvedTier = async (signature: string) =>
+      (await getCpiEvents(signature)).find((e) => isEvent(e, "MarketResolved")).data
+        .resolverTier;
+
+    // A two-sided market with tiered resolvers, past its resolution time
+    const tieredMarket = async () => {
+      const { marketId } = await createShortMarket(8);
+      await (await placePredictionIx(marketId, predictor1, true, PREDICTION_AMOUNT)).rpc();
+      await (await placePredictionIx(marketId, predictor2, false, PREDICTION_AMOUNT)).rpc();
+      await setResolvers(
+        marketId,
+        [marketCreator.publicKey, backup.publicKey, admin],
+        [0, BACKUP_DELAY, ADMIN_DELAY]
+      );
+      await sleep(9000);
+      return marketId;
+    };
+
+    before(async () => {
+      await airdropSol(predictor1.publicKey, 2);
+      await airdropSol(predictor2.publicKey, 2);
+      await airdropSol(backup.publicKey, 1);
+    });
+
+    it("lets the primary resolve as soon as the market expires", async () => {
+      const marketId = await tieredMarket();
+      const signature = await resolveMarket(marketId, true);
+      assert.equal(await resolvedTier(signature), 0);
+    });
+
+    it("opens the backup tier after its delay, but not earlier", async () => {
+      const marketId = await tieredMarket();
+      await expectError(resolveMarket(marketId, false, backup), "ResolverTierNotOpen");
+
+      await sleep(BACKUP_DELAY * 1000);
+      const signature = await resolveMarket(marketId, false, backup);
+      assert.equal(await resolvedTier(signature), 1);
+      const resolution = await program.account["resolution"].fetch(getResolutionPDA(marketId));
+      assert.ok(resolution.resolver.equals(backup.publicKey));
+    });
+
+    it("opens the admin tier last", async () => {
+      const marketId = await tieredMarket();
+      await expectError(resolveAsAdmin(marketId, true), "ResolverTierNotOpen");
+      await sleep(BACKUP_DELAY * 1000);
+      await expectError(resolveAsAdmin(marketId, true), "ResolverTierNotOpen");
+
+      await sleep((ADMIN_DELAY - BACKUP_DELAY) * 1000);
+      const signature = await resolveAsAdmin(marketId, true);
+      assert.equal(await resolvedTier(signature), 2);
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.deepEqual(market.outcome, { yes: {} });
+    });
+
+    it("rejects keys that hold no tier", async () => {
+      const marketId = await tieredMarket();
+      await expectError(resolveMarket(marketId, true, predictor1), "Unauthorized");
+    });
+
+    it("validates the tiers and who sets them", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      const resolvers = [marketCreator.publicKey, backup.publicKey, admin];
+      await expectError(
+        setResolvers(marketId, resolvers, [0, ADMIN_DELAY, BACKUP_DELAY]),
+        "InvalidResolverTiers"
+      );
+      await expectError(setResolvers(marketId, resolvers, [-1, 0, 0]), "InvalidResolverTiers");
+      await expectError(
+        setResolvers(marketId, resolvers, [0, BACKUP_DELAY, ADMIN_DELAY], backup),
+        "Unauthorized"
+      );
+    });
+  });
+
+  describe("Loser rebates", () => {
+    const LOSER_REBATE_BPS = 3_333;
+    const predictor3 = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const claimRebate = (marketId: BN, predictor: web3.Keypair) =>
+      program.methods
+        .claimRebate(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, predictor.publicKey)[0],
+          predictor: predictor.publicKey,
+        })
+        .signers([predictor])
+        .rpc();
+
+    const withdrawFees = (marketId: BN, amount: BN) =>
+      program.methods
+        .withdrawFees(marketId, amount)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          admin: marketCreator.publicKey,
+          wsolVault: null,
+          payoutTokenAccount: null,
+          collateralMint: null,
+          tokenProgram: null,
+        })
+        .signers([marketCreator])
+        .rpc();
+
+    // Two losers with stakes that don't divide the pot evenly
+    const rebateMarket = async () => {
+      const { marketId } = await createShortMarket(4, marketCreator, {
+        loserRebateBps: LOSER_REBATE_BPS,
+      });
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, new BN(333_333_331));
+      await placePrediction(marketId, predictor3, false, new BN(666_666_673));
+      await sleep(5000);
+      const signature = await resolveMarket(marketId, true);
+      return { marketId, signature };
+    };
+
+    before(async () => {
+      await airdropSol(predictor1.publicKey, 2);
+      await airdropSol(predictor2.publicKey, 2);
+      await airdropSol(predictor3.publicKey, 2);
+    });
+
+    it("sets the pot aside at resolution, out of the creator's reach", async () => {
+      const { marketId, signature } = await rebateMarket();
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      const fees: BN = market.feeCollected.add(market.rebatePot);
+      assert.equal(
+        market.rebatePot.toString(),
+        fees.muln(LOSER_REBATE_BPS).divn(10_000).toString()
+      );
+      const resolved = (await getCpiEvents(signature)).find((e) => isEvent(e, "MarketResolved"));
+      assert.equal(resolved.data.rebatePot.toString(), market.rebatePot.toString());
+
+      await expectError(withdrawFees(marketId, market.feeCollected.addn(1)), "InsufficientFees");
+      await withdrawFees(marketId, market.feeCollected);
+      await assertSolvent(marketId);
+    });
+
+    it("pays each loser a rounded-down share that never exceeds the pot", async () => {
+      const { marketId } = await rebateMarket();
+      const marketPDA = getMarketPDA(marketId)[0];
+      const { rebatePot, noDeposits } = await program.account["market"].fetch(marketPDA);
+
+      let paid = new BN(0);
+      for (const loser of [predictor2, predictor3]) {
+        const prediction = await program.account["prediction"].fetch(
+          getPredictionPDA(marketId, loser.publicKey)[0]
+        );
+        const before = await provider.connection.getBalance(loser.publicKey);
+        const signature = await claimRebate(marketId, loser);
+        const claimed = (await getCpiEvents(signature)).find((e) => isEvent(e, "RebateClaimed"));
+
+        const expected = prediction.amountDeposited.mul(rebatePot).div(noDeposits);
+        assert.equal(claimed.data.amount.toString(), expected.toString());
+        assert.equal(
+          await provider.connection.getBalance(loser.publicKey),
+          before + expected.toNumber()
+        );
+        paid = paid.add(expected);
+      }
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(paid.lte(rebatePot));
+      assert.equal(market.rebatePaid.toString(), paid.toString());
+      await assertSolvent(marketId);
+    });
+
+    it("pays losers only, once each", async () => {
+      const { marketId } = await rebateMarket();
+      await expectError(claimRebate(marketId, predictor1), "PredictionWon");
+      await claimRebate(marketId, predictor2);
+      await expectError(claimRebate(marketId, predictor2), "RebateAlreadyClaimed");
+    });
+
+    it("has nothing to pay on a market without rebates", async () => {
+      const { marketId } = await createShortMarket(4);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+      await expectError(claimRebate(marketId, predictor2), "NoRebate");
+    });
+  });
+
+  describe("Staking vault", () => {
+    const FEE_SHARE_BPS = 5_000;
+    const UNBONDING_SECS = 3;
+    const ACC_SCALE = new BN("1000000000000");
+    const minter = web3.Keypair.generate();
+    const [stakingVaultPDA] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("staking_vault")],
+      program.programId
+    );
+    const [stakingTokensPDA] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("staking_tokens")],
+      program.programId
+    );
+    const [treasuryPDA] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("treasury")],
+      program.programId
+    );
+    const stakerA = predictor1;
+    const stakerB = predictor2;
+    const bettor = web3.Keypair.generate();
+    const tokenAccounts = new Map<string, web3.PublicKey>();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const positionPDA = (owner: web3.PublicKey) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("stake_position"), owner.toBuffer()],
+        program.programId
+      )[0];
+
+    const stake = (owner: web3.Keypair, amount: number) =>
+      program.methods
+        .stake(new BN(amount))
+        .accounts({
+          stakingVault: stakingVaultPDA,
+          stakingTokens: stakingTokensPDA,
+          position: positionPDA(owner.publicKey),
+          ownerTokenAccount: tokenAccounts.get(owner.publicKey.toBase58()),
+          owner: owner.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([owner])
+        .rpc();
+
+    const unstake = (owner: web3.Keypair, amount: number) =>
+      program.methods
+        .unstake(new BN(amount))
+        .accounts({
+          stakingVault: stakingVaultPDA,
+          position: positionPDA(owner.publicKey),
+          owner: owner.publicKey,
+        })
+        .signers([owner])
+        .rpc();
+
+    const withdrawUnstaked = (owner: web3.Keypair) =>
+      program.methods
+        .withdrawUnstaked()
+        .accounts({
+          stakingVault: stakingVaultPDA,
+          stakingTokens: stakingTokensPDA,
+          position: positionPDA(owner.publicKey),
+          ownerTokenAccount: tokenAccounts.get(owner.publicKey.toBase58()),
+          owner: owner.publicKey,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .signers([owner])
+        .rpc();
+
+    const claimStakingRewards = async (owner: web3.Keypair): Promise<BN> => {
+      const signature = await program.methods
+        .claimStakingRewards()
+        .accounts({
+          stakingVault: stakingVaultPDA,
+          position: positionPDA(owner.publicKey),
+          owner: owner.publicKey,
+        })
+        .signers([owner])
+        .rpc();
+      return (await getCpiEvents(signature)).find((e) => isEvent(e, "StakingRewardsClaimed"))
+        .data.amount;
+    };
+
+    const collectFees = (marketId: BN, stakingVault: web3.PublicKey | null = stakingVaultPDA) =>
+      program.methods
+        .collectFeesMany()
+        .accounts({
+          config: getConfigPDA()[0],
+          treasury: treasuryPDA,
+          stakingVault,
+          buybackEscrow: null,
+          admin: provider.wallet.publicKey,
+        })
+        .remainingAccounts([
+          { pubkey: getMarketPDA(marketId)[0], isWritable: true, isSigner: false },
+          { pubkey: getVaultPDA(marketId)[0], isWritable: true, isSigner: false },
+        ])
+        .rpc();
+
+    // Bets on a fresh market and collects its fees; returns the stakers' share
+    const routeFees = async (): Promise<BN> => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await placePrediction(marketId, bettor, true, PREDICTION_AMOUNT);
+      const signature = await collectFees(marketId);
+      return (await getCpiEvents(signature)).find((e) => isEvent(e, "TreasuryFeesCollected"))
+        .data.toStakers;
+    };
+
+    const accumulator = async (): Promise<BN> =>
+      (await program.account["stakingVault"].fetch(stakingVaultPDA)).accRewardPerToken;
+
+    const earned = (staked: number, from: BN, to: BN) =>
+      to.muln(staked).div(ACC_SCALE).sub(from.muln(staked).div(ACC_SCALE));
+
+    before(async () => {
+      await airdropSol(minter.publicKey, 2);
+      await airdropSol(predictor1.publicKey, 2);
+      await airdropSol(predictor2.publicKey, 2);
+      await airdropSol(bettor.publicKey, 2);
+      const mint = await createMint(provider.connection, minter, minter.publicKey, null, 0);
+      for (const staker of [stakerA, stakerB]) {
+        const account = await createAccount(provider.connection, minter, mint, staker.publicKey);
+        await mintTo(provider.connection, minter, mint, account, minter, 1_000);
+        tokenAccounts.set(staker.publicKey.toBase58(), account);
+      }
+
+      await updateConfig({ treasury: treasuryPDA });
+      await program.methods
+        .configureStaking(FEE_SHARE_BPS, new BN(UNBONDING_SECS))
+        .accounts({
+          config: getConfigPDA()[0],
+          stakingVault: stakingVaultPDA,
+          stakingTokens: stakingTokensPDA,
+          stakingMint: mint,
+          admin: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+    });
+
+    after(async () => {
+      await updateConfig({ treasury: provider.wallet.publicKey });
+    });
+
+    it("splits routed fees by stake from the time each staker joins", async () => {
+      await stake(stakerA, 100);
+      const acc0 = await accumulator();
+      const first = await routeFees();
+      assert.isTrue(first.gtn(0));
+      const acc1 = await accumulator();
+      assert.equal(acc1.sub(acc0).toString(), first.mul(ACC_SCALE).divn(100).toString());
+
+      // B joins after the first distribution and earns only from the second
+      await stake(stakerB, 300);
+      await routeFees();
+      const acc2 = await accumulator();
+
+      const rewardA = await claimStakingRewards(stakerA);
+      const rewardB = await claimStakingRewards(stakerB);
+      assert.equal(rewardA.toString(), earned(100, acc0, acc2).toString());
+      assert.equal(rewardB.toString(), earned(300, acc1, acc2).toString());
+      // A had the whole first share to itself, less rounding
+      assert.isTrue(earned(100, acc0, acc1).lte(first));
+      assert.isTrue(earned(100, acc0, acc1).gte(first.subn(1)));
+    });
+
+    it("holds unstaked tokens for the unbonding delay, without earning", async () => {
+      const tokenAccount = tokenAccounts.get(stakerB.publicKey.toBase58());
+      const balance = async () =>
+        Number((await provider.connection.getTokenAccountBalance(tokenAccount)).value.amount);
+      const before = await balance();
+
+      await unstake(stakerB, 300);
+      await expectError(withdrawUnstaked(stakerB), "UnbondingActive");
+      assert.equal(await balance(), before);
+
+      // Fees routed while unbonding all go to A
+      const acc0 = await accumulator();
+      const share = await routeFees();
+      assert.equal(
+        (await accumulator()).sub(acc0).toString(),
+        share.mul(ACC_SCALE).divn(100).toString()
+      );
+      await expectError(claimStakingRewards(stakerB), "NoReward");
+
+      await sleep((UNBONDING_SECS + 1) * 1000);
+      await withdrawUnstaked(stakerB);
+      assert.equal(await balance(), before + 300);
+      await expectError(withdrawUnstaked(stakerB), "InvalidAmount");
+    });
+
+    it("won't collect fees past the staking vault", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await placePrediction(marketId, bettor, true, PREDICTION_AMOUNT);
+      await expectError(collectFees(marketId, null), "StakingVaultRequired");
+    });
+  });
+
+  describe("Buyback and burn", () => {
+    const BUYBACK_BPS = 5_000;
+    const MIN_TOKENS = 100;
+    const minter = web3.Keypair.generate();
+    const executor = web3.Keypair.generate();
+    const bettor = web3.Keypair.generate();
+    const pda = (seed: string) =>
+      web3.PublicKey.findProgramAddressSync([Buffer.from(seed)], program.programId)[0];
+    const escrowPDA = pda("buyback_escrow");
+    const buybackTokensPDA = pda("buyback_tokens");
+    const treasuryPDA = pda("treasury");
+    let protocolMint: web3.PublicKey;
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const settleIx = () =>
+      program.methods
+        .settleBuyback()
+        .accounts({
+          buybackEscrow: escrowPDA,
+          buybackTokens: buybackTokensPDA,
+          protocolMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .instruction();
+
+    // The executor's swap is stood in for by minting straight to the escrow
+    const deliverIx = (tokens: number) =>
+      createMintToInstruction(protocolMint, buybackTokensPDA, minter.publicKey, tokens);
+
+    const executeBuyback = (
+      solAmount: BN,
+      postInstructions: web3.TransactionInstruction[],
+      signers: web3.Keypair[] = [minter]
+    ) =>
+      program.methods
+        .executeBuyback(solAmount, new BN(MIN_TOKENS))
+        .accounts({
+          config: getConfigPDA()[0],
+          buybackEscrow: escrowPDA,
+          buybackTokens: buybackTokensPDA,
+          executor: executor.publicKey,
+          admin: signers.length > 1 ? signers[1].publicKey : provider.wallet.publicKey,
+          instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        })
+        .postInstructions(postInstructions)
+        .signers(signers)
+        .rpc();
+
+    const escrowed = async (): Promise<BN> =>
+      (await program.account["buybackEscrow"].fetch(escrowPDA)).escrowed;
+
+    before(async () => {
+      await airdropSol(minter.publicKey, 2);
+      await airdropSol(bettor.publicKey, 2);
+      protocolMint = await createMint(provider.connection, minter, minter.publicKey, null, 0);
+
+      await updateConfig({ treasury: treasuryPDA });
+      await program.methods
+        .configureBuyback(BUYBACK_BPS, executor.publicKey)
+        .accounts({
+          config: getConfigPDA()[0],
+          buybackEscrow: escrowPDA,
+          buybackTokens: buybackTokensPDA,
+          protocolMint,
+          admin: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        })
+        .rpc();
+
+      // Fees from one market, split between stakers, buyback and treasury
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await placePrediction(marketId, bettor, true, PREDICTION_AMOUNT);
+      const signature = await program.methods
+        .collectFeesMany()
+        .accounts({
+          config: getConfigPDA()[0],
+          treasury: treasuryPDA,
+          stakingVault: pda("staking_vault"),
+          buybackEscrow: escrowPDA,
+          admin: provider.wallet.publicKey,
+        })
+        .remainingAccounts([
+          { pubkey: getMarketPDA(marketId)[0], isWritable: true, isSigner: false },
+          { pubkey: getVaultPDA(marketId)[0], isWritable: true, isSigner: false },
+        ])
+        .rpc();
+      const collected = (await getCpiEvents(signature)).find((e) =>
+        isEvent(e, "TreasuryFeesCollected")
+      ).data;
+      assert.equal(
+        collected.toBuyback.toString(),
+        collected.amount.sub(collected.toStakers).muln(BUYBACK_BPS).divn(10_000).toString()
+      );
+      assert.equal((await escrowed()).toString(), collected.toBuyback.toString());
+    });
+
+    after(async () => {
+      await updateConfig({ treasury: provider.wallet.publicKey });
+    });
+
+    it("won't release SOL without a settle in the same transaction", async () => {
+      const amount = (await escrowed()).divn(2);
+      await expectError(executeBuyback(amount, [deliverIx(MIN_TOKENS)]), "BuybackNotSettled");
+    });
+
+    it("won't settle when fewer than the minimum tokens arrive", async () => {
+      const before = await escrowed();
+      const executorBefore = await provider.connection.getBalance(executor.publicKey);
+      await expectError(
+        executeBuyback(before.divn(2), [deliverIx(MIN_TOKENS - 1), await settleIx()]),
+        "BuybackUnderDelivered"
+      );
+      assert.equal((await escrowed()).toString(), before.toString());
+      assert.equal(await provider.connection.getBalance(executor.publicKey), executorBefore);
+    });
+
+    it("only lets the config admin release SOL", async () => {
+      const amount = (await escrowed()).divn(2);
+      await expectError(
+        executeBuyback(amount, [deliverIx(MIN_TOKENS), await settleIx()], [minter, bettor]),
+        "Unauthorized"
+      );
+    });
+
+    it("pays the executor and burns what it delivers", async () => {
+      const before = await escrowed();
+      const amount = before.divn(2);
+      const executorBefore = await provider.connection.getBalance(executor.publicKey);
+      const supplyBefore = (await getMint(provider.connection, protocolMint)).supply;
+
+      const signature = await executeBuyback(amount, [
+        deliverIx(MIN_TOKENS + 50),
+        await settleIx(),
+      ]);
+
+      assert.equal(
+        await provider.connection.getBalance(executor.publicKey),
+        executorBefore + amount.toNumber()
+      );
+      assert.equal((await escrowed()).toString(), before.sub(amount).toString());
+      assert.equal((await getMint(provider.connection, protocolMint)).supply, supplyBefore);
+      assert.equal(
+        (await provider.connection.getTokenAccountBalance(buybackTokensPDA)).value.amount,
+        "0"
+      );
+
+      const executed = (await getCpiEvents(signature)).find((e) =>
+        isEvent(e, "BuybackExecuted")
+      ).data;
+      assert.equal(executed.solSpent.toString(), amount.toString());
+      assert.equal(executed.tokensBurned.toNumber(), MIN_TOKENS + 50);
+      const escrow = await program.account["buybackEscrow"].fetch(escrowPDA);
+      assert.equal(escrow.pendingSol.toNumber(), 0);
+      assert.equal(escrow.totalBurned.toNumber(), MIN_TOKENS + 50);
+    });
+  });
+
+  describe("Admin multisig", () => {
+    const owners = [0, 1, 2].map(() => web3.Keypair.generate());
+    const outsider = web3.Keypair.generate();
+    const NONCE = new BN(0);
+    const multisigPDA = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("admin_multisig"), NONCE.toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+    // The key the multisig holds the admin role as
+    const signerPDA = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("multisig_signer"), multisigPDA.toBuffer()],
+      program.programId
+    )[0];
+    const adminTxPDA = (index: number) =>
+      web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("admin_tx"),
+          multisigPDA.toBuffer(),
+          new BN(index).toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      )[0];
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    // Proposes `action` as `owner` and returns its index
+    const propose = async (action: any, owner = owners[0]): Promise<number> => {
+      const multisig = await program.account["adminMultisig"].fetch(multisigPDA);
+      const index = multisig.txCount.toNumber();
+      await program.methods
+        .createAdminTx(action)
+        .accounts({
+          multisig: multisigPDA,
+          adminTx: adminTxPDA(index),
+          owner: owner.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([owner])
+        .rpc();
+      return index;
+    };
+
+    const approve = (index: number, owner: web3.Keypair) =>
+      program.methods
+        .approveAdminTx(new BN(index))
+        .accounts({ multisig: multisigPDA, adminTx: adminTxPDA(index), owner: owner.publicKey })
+        .signers([owner])
+        .rpc();
+
+    const execute = (
+      index: number,
+      market: web3.PublicKey | null = null,
+      owner = owners[0],
+      invoked: web3.TransactionInstruction | null = null
+    ) =>
+      program.methods
+        .executeAdminTx(new BN(index))
+        .accounts({
+          config: getConfigPDA()[0],
+          multisig: multisigPDA,
+          adminTx: adminTxPDA(index),
+          multisigSigner: signerPDA,
+          market,
+          owner: owner.publicKey,
+        })
+        .remainingAccounts(invoked ? invoked.keys.map((k) => ({ ...k, isSigner: false })) : [])
+        .signers([owner])
+        .rpc();
+
+    before(async () => {
+      for (const owner of owners) {
+        await airdropSol(owner.publicKey, 1);
+      }
+      await airdropSol(outsider.publicKey, 1);
+
+      const create = (keys: web3.PublicKey[], threshold: number) =>
+        program.methods
+          .createAdminMultisig(NONCE, keys, threshold)
+          .accounts({
+            config: getConfigPDA()[0],
+            multisig: multisigPDA,
+            multisigSigner: signerPDA,
+            admin: provider.wallet.publicKey,
+            systemProgram: web3.SystemProgram.programId,
+          })
+          .rpc();
+      const keys = owners.map((o) => o.publicKey);
+      await expectError(create(keys, 4), "InvalidMultisig");
+      await expectError(create([keys[0], keys[0]], 1), "InvalidMultisig");
+      await create(keys, 2);
+    });
+
+    after(async () => {
+      // Hand the admin role back so later suites can use the wallet
+      const index = await propose({ setAdmin: { admin: provider.wallet.publicKey } });
+      await approve(index, owners[1]);
+      await execute(index);
+    });
+
+    it("takes over the admin role", async () => {
+      const config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.equal(config.admin.toBase58(), signerPDA.toBase58());
+
+      await expectError(
+        program.methods
+          .pauseProtocol()
+          .accounts({ config: getConfigPDA()[0], admin: provider.wallet.publicKey })
+          .rpc(),
+        "Unauthorized"
+      );
+    });
+
+    it("executes only with the threshold of distinct owner approvals", async () => {
+      await expectError(propose({ pauseProtocol: {} }, outsider), "NotMultisigOwner");
+      const index = await propose({ pauseProtocol: {} });
+
+      await expectError(execute(index), "ThresholdNotMet");
+      await expectError(approve(index, owners[0]), "AlreadyApproved");
+      await expectError(approve(index, outsider), "NotMultisigOwner");
+
+      await approve(index, owners[2]);
+      await execute(index, null, owners[1]);
+      let config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.isTrue(config.paused);
+
+      // Each action runs once
+      await expectError(execute(index), "AdminTxExecuted");
+      await expectError(approve(index, owners[1]), "AdminTxExecuted");
+
+      const unpause = await propose({
+        updateConfig: { params: { ...configParams, paused: false } },
+      });
+      await approve(unpause, owners[1]);
+      await execute(unpause);
+      config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.isFalse(config.paused);
+    });
+
+    it("emergency-cancels a market", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId, marketPDA } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      const index = await propose({
+        emergencyCancelMarket: { marketId, reason: "question cannot be resolved" },
+      });
+      await approve(index, owners[1]);
+
+      const { marketPDA: otherPDA } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await expectError(execute(index, otherPDA), "MarketMismatch");
+      await execute(index, marketPDA);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.resolved);
+      assert.deepEqual(market.outcome, { void: {} });
+    });
+
+    it("runs any admin instruction with its signer as the admin", async () => {
+      const setSplit = async (rebateBps: number) => {
+        const split = await program.methods
+          .setEscheatSplit(rebateBps)
+          .accounts({ config: getConfigPDA()[0], admin: signerPDA })
+          .instruction();
+        const index = await propose({
+          invoke: {
+            accounts: split.keys.map(({ pubkey, isSigner, isWritable }) => ({
+              pubkey,
+              isSigner,
+              isWritable,
+            })),
+            data: split.data,
+          },
+        });
+        await approve(index, owners[2]);
+        await execute(index, null, owners[0], split);
+      };
+
+      const before = await program.account["config"].fetch(getConfigPDA()[0]);
+      await setSplit(2500);
+      const config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.equal(config.escheatRebateBps, 2500);
+      assert.equal(config.admin.toBase58(), signerPDA.toBase58());
+      await setSplit(before.escheatRebateBps);
+    });
+  });
+
+  describe("Stale markets", () => {
+    const STALE_AFTER = 2;
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const flagStale = (marketId: BN) =>
+      program.methods
+        .flagStale(marketId)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          keeper: provider.wallet.publicKey,
+        })
+        .rpc();
+
+    before(async () => {
+      await updateConfig({ staleAfterSecs: new BN(STALE_AFTER) });
+    });
+
+    after(async () => {
+      await updateConfig({ staleAfterSecs: new BN(7 * 24 * 60 * 60) });
+    });
+
+    it("flags an abandoned market only past the threshold", async () => {
+      const { marketId, marketPDA } = await createShortMarket(2);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.isAbove(market.lastActivityAt.toNumber(), 0);
+
+      await expectError(flagStale(marketId), "MarketNotStale");
+      await sleep((2 + STALE_AFTER + 1) * 1000);
+      await flagStale(marketId);
+      market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.stale);
+      await expectError(flagStale(marketId), "AlreadyStale");
+    });
+
+    it("clears the flag when the market resolves", async () => {
+      const { marketId, marketPDA } = await createShortMarket(2);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep((2 + STALE_AFTER + 1) * 1000);
+      await flagStale(marketId);
+
+      await resolveMarket(marketId, true);
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isFalse(market.stale);
+      await expectError(flagStale(marketId), "MarketAlreadyResolved");
+    });
+  });
+
+  describe("Keeper budget", () => {
+    const BUDGET = new BN(web3.LAMPORTS_PER_SOL / 100);
+    const TIP = 1_000_000;
+    const keeper = web3.Keypair.generate();
+    const bettor = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const setTips = (tips: number[]) =>
+      program.methods
+        .setKeeperTips(tips.map((t) => new BN(t)))
+        .accounts({ config: getConfigPDA()[0], admin: provider.wallet.publicKey })
+        .rpc();
+
+    const flagStale = (marketId: BN) =>
+      program.methods
+        .flagStale(marketId)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          keeper: keeper.publicKey,
+        })
+        .signers([keeper])
+        .rpc();
+
+    const reclaim = (marketId: BN, funder: web3.PublicKey) =>
+      program.methods
+        .reclaimKeeperBudget(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          funder,
+        })
+        .rpc();
+
+    before(async () => {
+      await airdropSol(keeper.publicKey, 1);
+      await airdropSol(bettor.publicKey, 2);
+      await updateConfig({ staleAfterSecs: new BN(0) });
+      // FlagStale is the first KeeperAction
+      await setTips([TIP, 0, 0, 0, 0, 0]);
+    });
+
+    after(async () => {
+      await setTips([0, 0, 0, 0, 0, 0]);
+      await updateConfig({ staleAfterSecs: new BN(7 * 24 * 60 * 60) });
+    });
+
+    it("tips a keeper from the budget once per state change", async () => {
+      const vaultBefore = async (marketId: BN) =>
+        provider.connection.getBalance(getVaultPDA(marketId)[0]);
+      const { marketId, marketPDA } = await createShortMarket(2, marketCreator, {
+        keeperBudget: BUDGET,
+      });
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.keeperBudget.toString(), BUDGET.toString());
+      assert.equal(market.keeperFunder.toBase58(), marketCreator.publicKey.toBase58());
+      await sleep(3000);
+
+      const vault = await vaultBefore(marketId);
+      const before = await provider.connection.getBalance(keeper.publicKey);
+      await flagStale(marketId);
+      assert.equal(await provider.connection.getBalance(keeper.publicKey), before + TIP);
+      assert.equal(await vaultBefore(marketId), vault - TIP);
+      market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.keeperBudget.toString(), BUDGET.subn(TIP).toString());
+
+      // A repeat call changes nothing, so it fails rather than tipping again
+      await expectError(flagStale(marketId), "AlreadyStale");
+    });
+
+    it("returns the unused budget to the funder after resolution", async () => {
+      const { marketId, marketPDA } = await createShortMarket(2);
+      await program.methods
+        .fundKeeperBudget(marketId, BUDGET)
+        .accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          funder: predictor1.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([predictor1])
+        .rpc();
+      await expectError(
+        program.methods
+          .fundKeeperBudget(marketId, BUDGET)
+          .accounts({
+            market: marketPDA,
+            marketVault: getVaultPDA(marketId)[0],
+            funder: predictor2.publicKey,
+            systemProgram: web3.SystemProgram.programId,
+          })
+          .signers([predictor2])
+          .rpc(),
+        "Unauthorized"
+      );
+      await placePrediction(marketId, predictor2, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, bettor, false, PREDICTION_AMOUNT);
+
+      await expectError(reclaim(marketId, predictor1.publicKey), "MarketNotResolved");
+      await sleep(3000);
+      await resolveMarket(marketId, true);
+      await expectError(reclaim(marketId, predictor2.publicKey), "Unauthorized");
+
+      const before = await provider.connection.getBalance(predictor1.publicKey);
+      await reclaim(marketId, predictor1.publicKey);
+      assert.equal(
+        await provider.connection.getBalance(predictor1.publicKey),
+        before + BUDGET.toNumber()
+      );
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.keeperBudget.toNumber(), 0);
+      await expectError(reclaim(marketId, predictor1.publicKey), "NoKeeperBudget");
+
+      // The budget never reaches the winners
+      await claimReward(marketId, predictor2);
+    });
+  });
+
+  describe("Category defaults", () => {
+    const SPORTS = 1;
+    const CRYPTO = 2;
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const setDefaults = (defaults: any[]) =>
+      program.methods
+        .setCategoryDefaults(defaults)
+        .accounts({ config: getConfigPDA()[0], admin: provider.wallet.publicKey })
+        .rpc();
+
+    const create = (options: any, feeBps = 0) => {
+      const now = Math.floor(Date.now() / 1000);
+      return createMarket(
+        new BN(now + 7 * 24 * 60 * 60),
+        marketCreator,
+        options,
+        null,
+        null,
+        5_000,
+        feeBps
+      );
+    };
+
+    before(async () => {
+      await setDefaults([
+        {
+          category: SPORTS,
+          feeBps: 300,
+          disputeWindowSecs: new BN(600),
+          requiresOracle: false,
+        },
+        {
+          category: CRYPTO,
+          feeBps: 0,
+          disputeWindowSecs: new BN(0),
+          requiresOracle: true,
+        },
+      ]);
+    });
+
+    after(async () => {
+      await setDefaults([]);
+    });
+
+    it("rejects a malformed table", async () => {
+      const entry = {
+        category: 3,
+        feeBps: 0,
+        disputeWindowSecs: new BN(0),
+        requiresOracle: false,
+      };
+      await expectError(setDefaults([entry, entry]), "InvalidCategoryDefaults");
+      await expectError(setDefaults([{ ...entry, category: 0 }]), "InvalidCategoryDefaults");
+      await expectError(setDefaults([{ ...entry, feeBps: 5_000 }]), "FeeOutOfBounds");
+    });
+
+    it("fills zero settings from the category", async () => {
+      const { marketPDA } = await create({ category: SPORTS });
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.category, SPORTS);
+      assert.equal(market.feeBps, 300);
+      assert.equal(market.disputeWindowSecs.toNumber(), 600);
+    });
+
+    it("keeps what the creator sets", async () => {
+      const { marketPDA } = await create(
+        { category: SPORTS, disputeWindowSecs: new BN(60) },
+        150
+      );
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.feeBps, 150);
+      assert.equal(market.disputeWindowSecs.toNumber(), 60);
+    });
+
+    it("requires an oracle where the category does", async () => {
+      await expectError(create({ category: CRYPTO }), "OracleRequired");
+
+      const { marketPDA } = await create({
+        category: CRYPTO,
+        oracleKey: web3.Keypair.generate().publicKey,
+      });
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isNull(market.feeBps);
+    });
+
+    it("falls back to global defaults for an unknown category", async () => {
+      const { marketPDA } = await create({ category: 9 });
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.category, 9);
+      assert.isNull(market.feeBps);
+      assert.equal(market.disputeWindowSecs.toNumber(), 0);
+    });
+  });
+
+  describe("Creator liability cap", () => {
+    const creator = web3.Keypair.generate();
+    const bettors = [0, 1, 2, 3].map(() => web3.Keypair.generate());
+    const statsPDA = getCreatorStatsPDA(creator.publicKey);
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const release = (marketId: BN) =>
+      program.methods
+        .releaseCreatorLiability(marketId)
+        .accounts({ market: getMarketPDA(marketId)[0], creatorStats: statsPDA })
+        .rpc();
+
+    const openLiability = async (): Promise<string> =>
+      (await program.account["creatorStats"].fetch(statsPDA)).openLiability.toString();
+
+    before(async () => {
+      await airdropSol(creator.publicKey, 10);
+      for (const bettor of bettors) {
+        await airdropSol(bettor.publicKey, 2);
+      }
+      await updateConfig({ maxOpenLiability: PREDICTION_AMOUNT.muln(3) });
+    });
+
+    after(async () => {
+      await updateConfig({ maxOpenLiability: new BN(0) });
+    });
+
+    it("caps deposits across the creator's markets until they resolve", async () => {
+      const short = await createShortMarket(4, creator);
+      const now = Math.floor(Date.now() / 1000);
+      const long = await createMarket(new BN(now + 7 * 24 * 60 * 60), creator);
+
+      // Bets on both markets interleave into one counter
+      await placePrediction(short.marketId, bettors[0], true, PREDICTION_AMOUNT);
+      await placePrediction(long.marketId, bettors[1], true, PREDICTION_AMOUNT);
+      await placePrediction(short.marketId, bettors[2], false, PREDICTION_AMOUNT);
+      assert.equal(await openLiability(), PREDICTION_AMOUNT.muln(3).toString());
+      await expectError(
+        placePrediction(long.marketId, bettors[3], false, PREDICTION_AMOUNT),
+        "CreatorLiabilityCapReached"
+      );
+
+      await expectError(release(short.marketId), "MarketNotResolved");
+      await sleep(5000);
+      await resolveMarket(short.marketId, true, creator);
+      await release(short.marketId);
+      assert.equal(await openLiability(), PREDICTION_AMOUNT.toString());
+      const market = await program.account["market"].fetch(short.marketPDA);
+      assert.equal(market.creatorLiability.toNumber(), 0);
+      await expectError(release(short.marketId), "NoOpenLiability");
+
+      // Only the resolved market's share was released
+      await placePrediction(long.marketId, bettors[3], false, PREDICTION_AMOUNT);
+      assert.equal(await openLiability(), PREDICTION_AMOUNT.muln(2).toString());
+      const longMarket = await program.account["market"].fetch(long.marketPDA);
+      assert.equal(
+        longMarket.creatorLiability.toString(),
+        PREDICTION_AMOUNT.muln(2).toString()
+      );
+    });
+  });
+
+  describe("Open auction", () => {
+    const bidders = [0, 1, 2].map(() => web3.Keypair.generate());
+    const latecomer = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const getAuctionBidPDA = (marketId: BN, bidder: web3.PublicKey) =>
+      web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("auction_bid"),
+          marketId.toArrayLike(Buffer, "le", 8),
+          bidder.toBuffer(),
+        ],
+        program.programId
+      )[0];
+
+    const bid = (marketId: BN, bidder: web3.Keypair, side: boolean, amount: BN) =>
+      program.methods
+        .bidOpenAuction(marketId, side, amount)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          auctionBid: getAuctionBidPDA(marketId, bidder.publicKey),
+          selfExclusion: getSelfExclusionPDA(bidder.publicKey),
+          bidder: bidder.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([bidder])
+        .rpc();
+
+    const settle = (marketId: BN) =>
+      program.methods
+        .settleOpenAuction(marketId)
+        .accounts({ market: getMarketPDA(marketId)[0], config: getConfigPDA()[0] })
+        .rpc();
+
+    const claimAllocation = async (marketId: BN, bidder: web3.Keypair) => {
+      const config = await program.account["config"].fetch(getConfigPDA()[0]);
+      const { userMarketIndex, userIndexPage } = await getPositionIndexAccounts(
+        marketId,
+        bidder.publicKey
+      );
+      return program.methods
+        .claimAuctionAllocation(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          auctionBid: getAuctionBidPDA(marketId, bidder.publicKey),
+          predictionAccount: getPredictionPDA(marketId, bidder.publicKey)[0],
+          userProfile: getProfilePDA(bidder.publicKey)[0],
+          userMarketIndex,
+          userIndexPage,
+          epochStats: getEpochStatsPDA(bidder.publicKey, config.currentEpoch)[0],
+          bidder: bidder.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([bidder])
+        .rpc();
+    };
+
+    const refund = (marketId: BN, bidder: web3.Keypair) =>
+      program.methods
+        .refundAuctionBid(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          auctionBid: getAuctionBidPDA(marketId, bidder.publicKey),
+          bidder: bidder.publicKey,
+        })
+        .signers([bidder])
+        .rpc();
+
+    // What clear_auction_side gives a side: fee off the top, the rest
+    // priced in one go against the opening pool
+    const clearSide = (total: BN, pool: BN, feeBps: number) => {
+      const net = total.sub(total.muln(feeBps).addn(9_999).divn(10_000));
+      return net.mul(pool).div(pool.add(net));
+    };
+
+    before(async () => {
+      for (const bidder of [...bidders, latecomer]) {
+        await airdropSol(bidder.publicKey, 2);
+      }
+    });
+
+    it("fills asymmetric demand at one price per side", async () => {
+      const { marketId, marketPDA } = await createShortMarket(30, marketCreator, {
+        openAuctionSecs: new BN(4),
+      });
+      const yesBids = [new BN(web3.LAMPORTS_PER_SOL / 2), new BN(web3.LAMPORTS_PER_SOL / 10)];
+      const noBid = new BN(web3.LAMPORTS_PER_SOL / 20);
+
+      // No trading while the auction runs, only bids
+      await expectError(
+        placePrediction(marketId, latecomer, true, PREDICTION_AMOUNT),
+        "OpenAuctionPending"
+      );
+      const vaultBefore = await provider.connection.getBalance(getVaultPDA(marketId)[0]);
+      await bid(marketId, bidders[0], true, yesBids[0]);
+      await bid(marketId, bidders[1], true, yesBids[1]);
+      await bid(marketId, bidders[2], false, noBid);
+      const vaultAfter = await provider.connection.getBalance(getVaultPDA(marketId)[0]);
+      assert.equal(vaultAfter - vaultBefore, yesBids[0].add(yesBids[1]).add(noBid).toNumber());
+
+      const opening = await program.account["market"].fetch(marketPDA);
+      assert.equal(opening.auctionYes.toString(), yesBids[0].add(yesBids[1]).toString());
+      assert.equal(opening.auctionNo.toString(), noBid.toString());
+      await expectError(settle(marketId), "AuctionActive");
+      await expectError(claimAllocation(marketId, bidders[0]), "AuctionNotSettled");
+
+      await sleep(5000);
+      await expectError(
+        bid(marketId, latecomer, true, PREDICTION_AMOUNT),
+        "AuctionClosed"
+      );
+      await expectError(
+        placePrediction(marketId, latecomer, true, PREDICTION_AMOUNT),
+        "OpenAuctionPending"
+      );
+      await settle(marketId);
+      await expectError(settle(marketId), "NoPendingAuction");
+
+      const market = await program.account["market"].fetch(marketPDA);
+      const yesTokens = clearSide(opening.auctionYes, opening.yesPool, opening.feeBps);
+      const noTokens = clearSide(opening.auctionNo, opening.noPool, opening.feeBps);
+      assert.equal(market.auctionYesTokens.toString(), yesTokens.toString());
+      assert.equal(market.auctionNoTokens.toString(), noTokens.toString());
+      assert.equal(market.yesTokens.toString(), yesTokens.toString());
+      assert.equal(market.noTokens.toString(), noTokens.toString());
+      assert.isTrue(market.auctionSettled);
+
+      for (const bidder of bidders) {
+        await claimAllocation(marketId, bidder);
+      }
+      const positions = await Promise.all(
+        bidders.map((bidder) =>
+          program.account["prediction"].fetch(getPredictionPDA(marketId, bidder.publicKey)[0])
+        )
+      );
+
+      // Both YES bids got the same price despite their sizes, each a
+      // pro-rata share of the side's tokens
+      const [big, small, no] = positions;
+      assert.equal(
+        big.tokensReceived.toString(),
+        yesBids[0].mul(yesTokens).div(opening.auctionYes).toString()
+      );
+      assert.equal(
+        small.tokensReceived.toString(),
+        yesBids[1].mul(yesTokens).div(opening.auctionYes).toString()
+      );
+      assert.equal(no.tokensReceived.toString(), noTokens.toString());
+      assert.isAtMost(
+        Math.abs(big.entryPriceBps.toNumber() - small.entryPriceBps.toNumber()),
+        1
+      );
+      // The crowded side paid more per token
+      assert.isAbove(big.entryPriceBps.toNumber(), no.entryPriceBps.toNumber());
+
+      // The bid accounts are closed and normal trading is open
+      assert.isNull(
+        await provider.connection.getAccountInfo(getAuctionBidPDA(marketId, bidders[0].publicKey))
+      );
+      await placePrediction(marketId, latecomer, true, PREDICTION_AMOUNT);
+      const traded = await program.account["market"].fetch(marketPDA);
+      assert.equal(traded.tradeCount, 4);
+    });
+
+    it("refunds bids when the auction is never settled", async () => {
+      const { marketId, marketPDA } = await createShortMarket(6, marketCreator, {
+        openAuctionSecs: new BN(3),
+      });
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+      await bid(marketId, bidders[0], true, amount);
+      await expectError(refund(marketId, bidders[0]), "AuctionNotRefundable");
+
+      await sleep(7000);
+      await expectError(settle(marketId), "MarketExpired");
+      const vaultBefore = await provider.connection.getBalance(getVaultPDA(marketId)[0]);
+      await refund(marketId, bidders[0]);
+      const vaultAfter = await provider.connection.getBalance(getVaultPDA(marketId)[0]);
+      assert.equal(vaultBefore - vaultAfter, amount.toNumber());
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.auctionYes.toNumber(), 0);
+      assert.isFalse(market.auctionSettled);
+    });
+
+    it("rejects an auction on a bookmaker market", async () => {
+      await expectError(
+        createShortMarket(30, marketCreator, {
+          openAuctionSecs: new BN(4),
+          mode: { bookmaker: {} },
+          yesOddsBps: 18_000,
+          noOddsBps: 18_000,
+        }),
+        "InvalidMarketOptions"
+      );
+    });
+  });
+
+  describe("Trading delegates", () => {
+    const owner = web3.Keypair.generate();
+    const bot = web3.Keypair.generate();
+    const MAX_BET = new BN(web3.LAMPORTS_PER_SOL / 10);
+    const BUDGET = new BN(web3.LAMPORTS_PER_SOL / 4);
+    const DEPOSIT = new BN(web3.LAMPORTS_PER_SOL);
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const getTradingDelegatePDA = () =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("trading_delegate"), owner.publicKey.toBuffer(), bot.publicKey.toBuffer()],
+        program.programId
+      )[0];
+
+    const createDelegate = (expiresAt: BN) =>
+      program.methods
+        .createTradingDelegate(bot.publicKey, MAX_BET, BUDGET, expiresAt, DEPOSIT)
+        .accounts({
+          tradingDelegate: getTradingDelegatePDA(),
+          owner: owner.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([owner])
+        .rpc();
+
+    const revoke = () =>
+      program.methods
+        .revokeTradingDelegate()
+        .accounts({ tradingDelegate: getTradingDelegatePDA(), owner: owner.publicKey })
+        .signers([owner])
+        .rpc();
+
+    const betAsBot = async (marketId: BN, amount: BN) => {
+      const [marketPDA] = getMarketPDA(marketId);
+      const market = await program.account["market"].fetch(marketPDA);
+      return program.methods
+        .placePredictionDelegated(marketId, true, amount, 0)
+        .accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          tradingDelegate: getTradingDelegatePDA(),
+          predictionAccount: getPredictionPDA(marketId, owner.publicKey)[0],
+          userProfile: getProfilePDA(owner.publicKey)[0],
+          ...(await getPositionIndexAccounts(marketId, owner.publicKey)),
+          epochStats: getEpochStatsPDA(owner.publicKey, await currentEpoch())[0],
+          creatorStats: getCreatorStatsPDA(market.liabilityCreator),
+          series: null,
+          insuranceFund: getInsuranceFundPDA()[0],
+          selfExclusion: getSelfExclusionPDA(owner.publicKey),
+          delegate: bot.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([bot])
+        .rpc();
+    };
+
+    const newMarket = async () => {
+      const now = Math.floor(Date.now() / 1000);
+      return (await createMarket(new BN(now + 7 * 24 * 60 * 60))).marketId;
+    };
+
+    before(async () => {
+      await airdropSol(owner.publicKey, 3);
+      // Only enough to front the rent of a first bet's accounts
+      await airdropSol(bot.publicKey, 0.1);
+    });
+
+    it("bets for the owner within the per-bet max and budget", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      await expectError(createDelegate(new BN(now - 1)), "InvalidTradingDelegate");
+      await createDelegate(new BN(now + 600));
+
+      const first = await newMarket();
+      await expectError(betAsBot(first, MAX_BET.addn(1)), "DelegateBetTooLarge");
+
+      const botBefore = await provider.connection.getBalance(bot.publicKey);
+      await betAsBot(first, MAX_BET);
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(first, owner.publicKey)[0]
+      );
+      assert.isTrue(prediction.predictor.equals(owner.publicKey));
+      assert.equal(prediction.amountDeposited.toString(), MAX_BET.toString());
+      // The bot only spent the transaction fee; its rent came back
+      const botAfter = await provider.connection.getBalance(bot.publicKey);
+      assert.isAtMost(botBefore - botAfter, 10_000);
+
+      await betAsBot(await newMarket(), MAX_BET);
+      const grant = await program.account["tradingDelegate"].fetch(getTradingDelegatePDA());
+      assert.equal(grant.spent.toString(), MAX_BET.muln(2).toString());
+
+      // A third max bet would take it past the budget
+      await expectError(betAsBot(await newMarket(), MAX_BET), "DelegateBudgetExceeded");
+    });
+
+    it("returns the unspent escrow when the owner revokes mid-way", async () => {
+      const escrowed = await provider.connection.getBalance(getTradingDelegatePDA());
+      const ownerBefore = await provider.connection.getBalance(owner.publicKey);
+      await revoke();
+      const ownerAfter = await provider.connection.getBalance(owner.publicKey);
+      assert.equal(ownerAfter - ownerBefore, escrowed);
+      assert.isNull(await provider.connection.getAccountInfo(getTradingDelegatePDA()));
+      assert.isBelow(escrowed, DEPOSIT.toNumber());
+
+      await expectError(betAsBot(await newMarket(), MAX_BET), "AccountNotInitialized");
+    });
+
+    it("stops the delegate at expiry", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      await createDelegate(new BN(now + 3));
+      const marketId = await newMarket();
+      await sleep(4000);
+      await expectError(betAsBot(marketId, MAX_BET), "DelegateExpired");
+      await revoke();
+    });
+  });
+
+  describe("Resolution snapshot", () => {
+    const winners = [0, 1].map(() => web3.Keypair.generate());
+    const loser = web3.Keypair.generate();
+
+    const withdrawFees = (marketId: BN, amount: BN) =>
+      program.methods
+        .withdrawFees(marketId, amount)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          admin: marketCreator.publicKey,
+          wsolVault: null,
+          payoutTokenAccount: null,
+          collateralMint: null,
+          tokenProgram: null,
+        })
+        .signers([marketCreator])
+        .rpc();
+
+    before(async () => {
+      for (const bettor of [...winners, loser]) {
+        await airdropSol(bettor.publicKey, 2);
+      }
+    });
+
+    it("pays claims from the pool state frozen at resolution", async () => {
+      const { marketId, marketPDA } = await createShortMarket(4);
+      const [vaultPDA] = getVaultPDA(marketId);
+      await placePrediction(marketId, winners[0], true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, loser, false, PREDICTION_AMOUNT.muln(2));
+      await placePrediction(marketId, winners[1], true, PREDICTION_AMOUNT);
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      const snapshot = market.resolutionSnapshot;
+      assert.equal(snapshot.yesPool.toString(), market.yesPool.toString());
+      assert.equal(snapshot.noPool.toString(), market.noPool.toString());
+      assert.equal(snapshot.yesDeposits.toString(), market.yesDeposits.toString());
+      assert.equal(snapshot.noDeposits.toString(), market.noDeposits.toString());
+      assert.equal(snapshot.feeCollected.toString(), market.feeCollected.toString());
+      assert.equal(snapshot.takenAt.toString(), market.resolvedAt.toString());
+      assert.equal(snapshot.vaultLamports.toNumber(), await provider.connection.getBalance(vaultPDA));
+
+      // Fees leave the vault after resolution; the snapshot stays put
+      await withdrawFees(marketId, market.feeCollected);
+      const withdrawn = await program.account["market"].fetch(marketPDA);
+      assert.equal(withdrawn.feeCollected.toNumber(), 0);
+      assert.equal(
+        JSON.stringify(withdrawn.resolutionSnapshot),
+        JSON.stringify(snapshot)
+      );
+
+      const pot = snapshot.yesPool.add(snapshot.noPool).add(snapshot.sponsoredAmount);
+      for (const winner of winners) {
+        const prediction = await program.account["prediction"].fetch(
+          getPredictionPDA(marketId, winner.publicKey)[0]
+        );
+        const expected = prediction.tokensReceived.mul(pot).div(snapshot.yesPool);
+        const before = await provider.connection.getBalance(vaultPDA);
+        await claimReward(marketId, winner);
+        const paid = before - (await provider.connection.getBalance(vaultPDA));
+        assert.equal(paid, expected.toNumber());
+      }
+      const claimed = await program.account["market"].fetch(marketPDA);
+      assert.equal(
+        JSON.stringify(claimed.resolutionSnapshot),
+        JSON.stringify(snapshot)
+      );
+    });
+  });
+
+  describe("Claim statistics", () => {
+    const winners = [0, 1, 2].map(() => web3.Keypair.generate());
+    const loser = web3.Keypair.generate();
+
+    before(async () => {
+      for (const bettor of [...winners, loser]) {
+        await airdropSol(bettor.publicKey, 2);
+      }
+    });
+
+    it("reports full settlement when the last winner claims", async () => {
+      const { marketId, marketPDA } = await createShortMarket(4);
+      for (const winner of winners) {
+        await placePrediction(marketId, winner, true, PREDICTION_AMOUNT);
+      }
+      await placePrediction(marketId, loser, false, PREDICTION_AMOUNT);
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.yesPositions, 3);
+      assert.equal(market.noPositions, 1);
+      assert.equal(market.eligibleClaims, 3);
+      assert.equal(market.eligibleClaimed, 0);
+      assert.isFalse(market.fullySettled);
+
+      // Claim order doesn't matter; only the last claim settles the market
+      const order = [winners[2], winners[0], winners[1]];
+      for (const [i, winner] of order.entries()) {
+        const events = await getCpiEvents(await claimReward(marketId, winner));
+        const settled = events.find((e) => isEvent(e, "MarketFullySettled"));
+        market = await program.account["market"].fetch(marketPDA);
+        assert.equal(market.eligibleClaimed, i + 1);
+        if (i < order.length - 1) {
+          assert.isUndefined(settled);
+          assert.isFalse(market.fullySettled);
+          continue;
+        }
+        assert.isTrue(market.fullySettled);
+        assert.equal(settled.data.eligibleClaims, 3);
+        assert.equal(settled.data.eligibleClaimed, 3);
+        assert.equal(settled.data.claimsCount, market.claimsCount);
+        assert.equal(settled.data.totalClaimed.toString(), market.totalClaimed.toString());
+        // What rounding left of the entitlement waits for sweep_dust
+        assert.equal(settled.data.roundingDust.toString(), market.roundingDustAccrued.toString());
+        assert.equal(market.outstandingEntitlement.toString(), "0");
+        assert.isFalse(settled.data.deadlinePassed);
+      }
+    });
+  });
+
+  describe("Losing prediction crank", () => {
+    const BUDGET = new BN(web3.LAMPORTS_PER_SOL / 100);
+    const TIP = 1_000_000;
+    const keeper = web3.Keypair.generate();
+    const winner = web3.Keypair.generate();
+    const losers = [0, 1].map(() => web3.Keypair.generate());
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const setTips = (tips: number[]) =>
+      program.methods
+        .setKeeperTips(tips.map((t) => new BN(t)))
+        .accounts({ config: getConfigPDA()[0], admin: provider.wallet.publicKey })
+        .rpc();
+
+    const settle = async (marketId: BN, owner: web3.PublicKey, close: boolean) =>
+      program.methods
+        .settleLosingPrediction(marketId, close)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, owner)[0],
+          userProfile: getProfilePDA(owner)[0],
+          userMarketIndex: (await getPositionIndexAccounts(marketId, owner)).userMarketIndex,
+          owner,
+          rentPayer: owner,
+          config: getConfigPDA()[0],
+          marketVault: getVaultPDA(marketId)[0],
+          keeper: keeper.publicKey,
+        })
+        .signers([keeper])
+        .rpc();
+
+    before(async () => {
+      for (const account of [keeper, winner, ...losers]) {
+        await airdropSol(account.publicKey, 2);
+      }
+      // SettleLoss is the sixth KeeperAction
+      await setTips([0, 0, 0, 0, 0, TIP]);
+    });
+
+    after(async () => {
+      await setTips([0, 0, 0, 0, 0, 0]);
+    });
+
+    it("settles losers for their owners and tips the keeper", async () => {
+      const { marketId, marketPDA } = await createShortMarket(4, marketCreator, {
+        keeperBudget: BUDGET,
+      });
+      await placePrediction(marketId, winner, true, PREDICTION_AMOUNT);
+      for (const loser of losers) {
+        await placePrediction(marketId, loser, false, PREDICTION_AMOUNT);
+      }
+      await expectError(settle(marketId, losers[0].publicKey, false), "MarketNotResolved");
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+
+      await expectError(settle(marketId, winner.publicKey, false), "PredictionWon");
+
+      // Kept open: the loss is counted and the position marked settled
+      const [profilePDA] = getProfilePDA(losers[0].publicKey);
+      const lostBefore = (await program.account["userProfile"].fetch(profilePDA)).marketsLost;
+      const keeperBefore = await provider.connection.getBalance(keeper.publicKey);
+      await settle(marketId, losers[0].publicKey, false);
+      const profile = await program.account["userProfile"].fetch(profilePDA);
+      assert.equal(profile.marketsLost, lostBefore + 1);
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, losers[0].publicKey)[0]
+      );
+      assert.isTrue(prediction.claimed);
+      assert.equal(
+        (await provider.connection.getBalance(keeper.publicKey)) - keeperBefore,
+        TIP - 5000
+      );
+      await expectError(settle(marketId, losers[0].publicKey, false), "AlreadyClaimed");
+
+      // Closed: the rent goes to the owner, not the keeper
+      const [predictionPDA] = getPredictionPDA(marketId, losers[1].publicKey);
+      const rent = await provider.connection.getBalance(predictionPDA);
+      const ownerBefore = await provider.connection.getBalance(losers[1].publicKey);
+      await settle(marketId, losers[1].publicKey, true);
+      assert.isNull(await provider.connection.getAccountInfo(predictionPDA));
+      assert.equal(
+        await provider.connection.getBalance(losers[1].publicKey),
+        ownerBefore + rent
+      );
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.keeperBudget.toString(), BUDGET.subn(2 * TIP).toString());
+    });
+  });
+
+  describe("Circuit breaker", () => {
+    const bettor = web3.Keypair.generate();
+    const TENTH = new BN(web3.LAMPORTS_PER_SOL / 10);
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const reset = (marketId: BN, creator: web3.Keypair) =>
+      program.methods
+        .resetCircuitBreaker(marketId)
+        .accounts({ market: getMarketPDA(marketId)[0], creator: creator.publicKey })
+        .signers([creator])
+        .rpc();
+
+    before(async () => {
+      await airdropSol(bettor.publicKey, 5);
+    });
+
+    it("rejects a single bet that moves the price too far", async () => {
+      // One SOL of seed liquidity: a tenth moves the price by ~450 bps,
+      // half a SOL by ~1,650
+      const { marketId, marketPDA } = await createShortMarket(60, marketCreator, {
+        maxMovePerTradeBps: 1_000,
+      });
+      await expectError(
+        placePrediction(marketId, bettor, true, TENTH.muln(5)),
+        "CircuitBreakerTripped"
+      );
+      await placePrediction(marketId, bettor, true, TENTH);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      const implied = market.yesPool.muln(10_000).div(market.yesPool.add(market.noPool));
+      assert.isAtMost(implied.toNumber() - 5_000, 1_000);
+      assert.equal(market.tradeCount, 1);
+    });
+
+    it("bounds the cumulative move within a window until the creator resets it", async () => {
+      const { marketId, marketPDA } = await createShortMarket(60, marketCreator, {
+        maxMovePerWindowBps: 1_000,
+      });
+      // Three bettors, since each wallet holds one position per market
+      const bettors = [0, 1, 2].map(() => web3.Keypair.generate());
+      for (const b of bettors) await airdropSol(b.publicKey, 1);
+
+      // ~450 then ~830 bps from the window's start; a third tenth would
+      // reach ~1,150
+      await placePrediction(marketId, bettors[0], true, TENTH);
+      await placePrediction(marketId, bettors[1], true, TENTH);
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.moveWindowOpenBps, 5_000);
+      assert.isAbove(market.moveWindowStart.toNumber(), 0);
+      await expectError(
+        placePrediction(marketId, bettors[2], true, TENTH),
+        "CircuitBreakerTripped"
+      );
+
+      await expectError(reset(marketId, bettor), "Unauthorized");
+      await reset(marketId, marketCreator);
+      market = await program.account["market"].fetch(marketPDA);
+      const implied = market.yesPool.muln(10_000).div(market.yesPool.add(market.noPool));
+      assert.equal(market.moveWindowOpenBps, implied.toNumber());
+
+      await placePrediction(marketId, bettors[2], true, TENTH);
+    });
+  });
+
+  describe("Rent payer", () => {
+    const user = web3.Keypair.generate();
+    const sponsor = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const closePrediction = async (marketId: BN, rentPayer: web3.PublicKey) =>
+      program.methods
+        .closePrediction(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, user.publicKey)[0],
+          userMarketIndex: (await getPositionIndexAccounts(marketId, user.publicKey))
+            .userMarketIndex,
+          predictor: user.publicKey,
+          rentPayer,
+        })
+        .signers([user])
+        .rpc();
+
+    before(async () => {
+      await airdropSol(user.publicKey, 1);
+      await airdropSol(sponsor.publicKey, 1);
+    });
+
+    it("charges rent to the payer and refunds it there on close", async () => {
+      const { marketId } = await createShortMarket(4);
+      const [predictionPDA] = getPredictionPDA(marketId, user.publicKey);
+      const userBefore = await provider.connection.getBalance(user.publicKey);
+      const sponsorBefore = await provider.connection.getBalance(sponsor.publicKey);
+      const builder = await placePredictionIx(
+        marketId,
+        user,
+        true,
+        PREDICTION_AMOUNT,
+        null,
+        null,
+        0,
+        "",
+        sponsor
+      );
+      await builder.rpc();
+
+      // The user paid the deposit alone; every account's rent came from the sponsor
+      assert.equal(
+        userBefore - (await provider.connection.getBalance(user.publicKey)),
+        PREDICTION_AMOUNT.toNumber()
+      );
+      const rent = await provider.connection.getBalance(predictionPDA);
+      assert.isAtLeast(
+        sponsorBefore - (await provider.connection.getBalance(sponsor.publicKey)),
+        rent
+      );
+      const prediction = await program.account["prediction"].fetch(predictionPDA);
+      assert.equal(prediction.rentPayer.toBase58(), sponsor.publicKey.toBase58());
+
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT);
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+      await claimReward(marketId, user);
+
+      await expectError(closePrediction(marketId, user.publicKey), "RentPayerMismatch");
+      const sponsorClosing = await provider.connection.getBalance(sponsor.publicKey);
+      await closePrediction(marketId, sponsor.publicKey);
+      assert.isNull(await provider.connection.getAccountInfo(predictionPDA));
+      assert.equal(
+        await provider.connection.getBalance(sponsor.publicKey),
+        sponsorClosing + rent
+      );
+    });
+  });
+
+  describe("Tenants", () => {
+    // Offset of Market::tenant: discriminator, version, market_id
+    const TENANT_OFFSET = 8 + 1 + 8;
+    // Market::resolved: tenant, creator, resolution_time
+    const RESOLVED_OFFSET = TENANT_OFFSET + 16 + 32 + 8;
+    const tenant = Array.from(web3.Keypair.generate().publicKey.toBytes().subarray(0, 16));
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const setQuota = (tenant: number[], maxMarketsPerWindow: number) =>
+      program.methods
+        .setTenantQuota(tenant, maxMarketsPerWindow)
+        .accounts({ config: getConfigPDA()[0], admin: provider.wallet.publicKey })
+        .rpc();
+
+    const create = (tenant: number[]) => {
+      const now = Math.floor(Date.now() / 1000);
+      return createMarket(new BN(now + 7 * 24 * 60 * 60), marketCreator, { tenant });
+    };
+
+    it("stamps the tenant on the market and its events, within its quota", async () => {
+      await expectError(create(tenant), "UnknownTenant");
+      await expectError(setQuota(Array(16).fill(0), 2), "InvalidTenant");
+      await setQuota(tenant, 2);
+
+      const created = [];
+      for (let i = 0; i < 2; i++) {
+        const { marketId, marketPDA, signature } = await create(tenant);
+        created.push(marketPDA.toBase58());
+        const market = await program.account["market"].fetch(marketPDA);
+        assert.deepEqual(Array.from(market.tenant), tenant);
+
+        const event = (await getCpiEvents(signature)).find((e) => isEvent(e, "MarketCreated"));
+        assert.deepEqual(Array.from(event.data.tenant), tenant);
+
+        const bettor = web3.Keypair.generate();
+        await airdropSol(bettor.publicKey, 1);
+        const builder = await placePredictionIx(marketId, bettor, true, PREDICTION_AMOUNT);
+        const placed = (await getCpiEvents(await builder.rpc())).find((e) =>
+          isEvent(e, "PredictionPlaced")
+        );
+        assert.deepEqual(Array.from(placed.data.tenant), tenant);
+      }
+      await expectError(create(tenant), "TenantQuotaExceeded");
+
+      // Raising the cap keeps the window's count
+      await setQuota(tenant, 3);
+      const { marketPDA } = await create(tenant);
+      created.push(marketPDA.toBase58());
+      const config = await program.account["config"].fetch(getConfigPDA()[0]);
+      const quota = config.tenants.find(
+        (t: any) => Buffer.from(t.tenant).equals(Buffer.from(tenant))
+      );
+      assert.equal(quota.windowCreated, 3);
+      assert.equal(quota.marketsCreated.toNumber(), 3);
+
+      // The tenant sits at a fixed offset, so one RPC filter finds its markets
+      const accounts = await provider.connection.getProgramAccounts(program.programId, {
+        filters: [
+          { memcmp: { offset: TENANT_OFFSET, bytes: anchor.utils.bytes.bs58.encode(tenant) } },
+        ],
+      });
+      assert.sameMembers(
+        accounts.map((a) => a.pubkey.toBase58()),
+        created
+      );
+
+      // So does the resolved flag, narrowing the list to open markets
+      const open = await provider.connection.getProgramAccounts(program.programId, {
+        filters: [
+          { memcmp: { offset: TENANT_OFFSET, bytes: anchor.utils.bytes.bs58.encode(tenant) } },
+          { memcmp: { offset: RESOLVED_OFFSET, bytes: anchor.utils.bytes.bs58.encode([0]) } },
+        ],
+      });
+      assert.sameMembers(
+        open.map((a) => a.pubkey.toBase58()),
+        created
+      );
+    });
+  });
+
+  describe("Claim window escheatment", () => {
+    const CLAIM_WINDOW = 5;
+    const [poolPDA] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("unclaimed_rewards")],
+      program.programId
+    );
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const escheat = (marketId: BN) =>
+      program.methods
+        .escheatUnclaimed(marketId)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          unclaimedRewardsPool: poolPDA,
+          payer: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    const setEscheatSplit = (rebateBps: number) =>
+      program.methods
+        .setEscheatSplit(rebateBps)
+        .accounts({ config: getConfigPDA()[0], admin: provider.wallet.publicKey })
+        .rpc();
+
+    before(async () => {
+      await setEscheatSplit(4000);
+    });
+
+    after(async () => {
+      await setEscheatSplit(0);
+    });
+
+    it("pays claims up to the cutoff and escheats the rest after it", async () => {
+      const { marketId, marketPDA } = await createShortMarket(3, marketCreator, {
+        claimWindowSecs: new BN(CLAIM_WINDOW),
+      });
+      const [early, late, loser] = [0, 1, 2].map(() => web3.Keypair.generate());
+      for (const bettor of [early, late, loser]) await airdropSol(bettor.publicKey, 2);
+      await placePrediction(marketId, early, true, new BN(web3.LAMPORTS_PER_SOL / 3));
+      await placePrediction(marketId, late, true, new BN(web3.LAMPORTS_PER_SOL / 7));
+      await placePrediction(marketId, loser, false, new BN(web3.LAMPORTS_PER_SOL / 2));
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      // Claims close CLAIM_WINDOW seconds after resolution; claim with a
+      // second to spare
+      let market = await program.account["market"].fetch(marketPDA);
+      const cutoff = market.resolvedAt.toNumber() + CLAIM_WINDOW;
+      const waitUntil = async (unixSecs: number) =>
+        sleep(Math.max(0, unixSecs * 1000 - Date.now()));
+      await waitUntil(cutoff - 1);
+      await claimReward(marketId, early);
+      assert.isTrue(
+        (await program.account["prediction"].fetch(getPredictionPDA(marketId, early.publicKey)[0]))
+          .claimed
+      );
+      await expectError(escheat(marketId), "ClaimWindowOpen");
+
+      // A second past the cutoff the late winner has lost the claim
+      await waitUntil(cutoff + 2);
+      await expectError(claimReward(marketId, late), "ClaimWindowExpired");
+
+      // Exactly the late winner's entitlement under the resolution snapshot
+      market = await program.account["market"].fetch(marketPDA);
+      const snapshot = market.resolutionSnapshot;
+      const pot = snapshot.yesPool.add(snapshot.noPool).add(snapshot.sponsoredAmount);
+      const expected = market.yesTokens.mul(pot).div(snapshot.yesPool);
+      assert.isTrue(expected.gtn(0));
+
+      const [vaultPDA] = getVaultPDA(marketId);
+      const vaultBefore = await provider.connection.getBalance(vaultPDA);
+      const poolInfo = await provider.connection.getAccountInfo(poolPDA);
+      const poolBefore = poolInfo ? poolInfo.lamports : 0;
+      const rebateFundsBefore = poolInfo
+        ? (await program.account["unclaimedRewardsPool"].fetch(poolPDA)).rebateFunds
+        : new BN(0);
+      await escheat(marketId);
+
+      const vaultAfter = await provider.connection.getBalance(vaultPDA);
+      assert.equal(vaultBefore - vaultAfter, expected.toNumber());
+      const pool = await program.account["unclaimedRewardsPool"].fetch(poolPDA);
+      const poolRent = poolInfo
+        ? 0
+        : await provider.connection.getMinimumBalanceForRentExemption(8 + 25);
+      assert.equal(
+        (await provider.connection.getBalance(poolPDA)) - poolBefore - poolRent,
+        expected.toNumber()
+      );
+      assert.equal(
+        pool.rebateFunds.sub(rebateFundsBefore).toString(),
+        expected.muln(4000).divn(10_000).toString()
+      );
+
+      market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.escheated.toString(), expected.toString());
+      assert.equal(market.yesTokens.toNumber(), 0);
+      assert.isTrue(market.fullySettled);
+      // Rent and collected fees stay behind
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(0);
+      assert.isAtLeast(vaultAfter, rent + market.feeCollected.toNumber());
+
+      await expectError(escheat(marketId), "AlreadyEscheated");
+    });
+
+    it("grants pooled rewards to a market's rebates and keeper budget", async () => {
+      const { marketId, marketPDA } = await createShortMarket(30);
+      const pool = await program.account["unclaimedRewardsPool"].fetch(poolPDA);
+      const rebates = pool.rebateFunds;
+      const keeperTips = new BN(1_000);
+
+      const grant = (rebateAmount: BN, tips: BN) =>
+        program.methods
+          .grantUnclaimedRewards(marketId, rebateAmount, tips)
+          .accounts({
+            config: getConfigPDA()[0],
+            market: marketPDA,
+            marketVault: getVaultPDA(marketId)[0],
+            unclaimedRewardsPool: poolPDA,
+            admin: provider.wallet.publicKey,
+          })
+          .rpc();
+
+      await expectError(grant(rebates.addn(1), new BN(0)), "InsufficientUnclaimedRewards");
+      await grant(rebates, keeperTips);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.rebatePot.toString(), rebates.toString());
+      assert.equal(market.keeperBudget.toString(), keeperTips.toString());
+      assert.isTrue(market.keeperFunder.equals(poolPDA));
+      const after = await program.account["unclaimedRewardsPool"].fetch(poolPDA);
+      assert.equal(after.rebateFunds.toNumber(), 0);
+    });
+  });
+
+  describe("Feature flags", () => {
+    // Mirrors the FEATURE_* constants in the program
+    const FEATURE_EARLY_EXIT = 1 << 0;
+    const ALL_FEATURES = 0b1111;
+    const owner = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const setFeatureFlags = (flags: BN) =>
+      program.methods
+        .setFeatureFlags(flags)
+        .accounts({ config: getConfigPDA()[0], admin: provider.wallet.publicKey })
+        .rpc();
+
+    const earlyExit = async (marketId: BN) =>
+      program.methods
+        .earlyExit(marketId)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, owner.publicKey)[0],
+          userMarketIndex: (await getPositionIndexAccounts(marketId, owner.publicKey))
+            .userMarketIndex,
+          predictor: owner.publicKey,
+          rentPayer: owner.publicKey,
+        })
+        .signers([owner])
+        .rpc();
+
+    before(async () => {
+      await airdropSol(owner.publicKey, 2);
+    });
+
+    after(async () => {
+      await setFeatureFlags(new BN(ALL_FEATURES));
+    });
+
+    it("starts with every feature on and rejects unknown bits", async () => {
+      const config = await program.account["config"].fetch(getConfigPDA()[0]);
+      assert.equal(config.featureFlags.toNumber(), ALL_FEATURES);
+      await expectError(setFeatureFlags(new BN(1 << 4)), "InvalidConfig");
+    });
+
+    it("switches early exit off and back on", async () => {
+      const { marketId } = await createShortMarket(60, marketCreator, {
+        earlyExitPenaltyBps: 500,
+      });
+      await placePrediction(marketId, owner, true, new BN(web3.LAMPORTS_PER_SOL / 10));
+
+      await setFeatureFlags(new BN(ALL_FEATURES & ~FEATURE_EARLY_EXIT));
+      await expectError(earlyExit(marketId), "FeatureDisabled");
+      // Ungated instructions carry on
+      const other = await createShortMarket(60);
+      await placePrediction(other.marketId, owner, false, new BN(web3.LAMPORTS_PER_SOL / 10));
+
+      await setFeatureFlags(new BN(ALL_FEATURES));
+      await earlyExit(marketId);
+      assert.isNull(
+        await provider.connection.getAccountInfo(getPredictionPDA(marketId, owner.publicKey)[0])
+      );
+    });
+  });
+
+  describe("Payout params", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const publish = (marketId: BN) =>
+      program.methods
+        .publishPayoutParams(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          wsolVault: null,
+        })
+        .rpc();
+
+    // Mirrors compute_payout in the program
+    const computePayout = (params: any, tokens: BN) => {
+      const entitled = params.fixedOdds
+        ? tokens
+        : params.winningPool.isZero()
+        ? new BN(0)
+        : tokens.mul(params.totalPot).div(params.winningPool);
+      const owed = BN.max(params.owed, entitled);
+      return params.covered.gte(owed) ? entitled : entitled.mul(params.covered).div(owed);
+    };
+
+    it("pays every winner exactly what the published params compute", async () => {
+      const { marketId, marketPDA } = await createShortMarket(4);
+      const winners: web3.Keypair[] = [];
+      for (let i = 0; i < 8; i++) {
+        const bettor = web3.Keypair.generate();
+        await airdropSol(bettor.publicKey, 2);
+        // Both sides covered, the rest at random
+        const side = i < 2 ? i === 0 : Math.random() < 0.5;
+        const stake = 1_000_000 + Math.floor(Math.random() * web3.LAMPORTS_PER_SOL);
+        await placePrediction(marketId, bettor, side, new BN(stake));
+        if (side) winners.push(bettor);
+      }
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+
+      const events = await getCpiEvents(await publish(marketId));
+      const published = events.find((e) => isEvent(e, "PayoutParamsPublished"));
+      const market = await program.account["market"].fetch(marketPDA);
+      const params = market.payoutParams;
+      assert.isAbove(params.publishedAt.toNumber(), 0);
+      assert.isTrue(params.winningSide);
+      assert.equal(published.data.params.totalPot.toString(), params.totalPot.toString());
+      assert.equal(
+        params.winningPool.toString(),
+        market.resolutionSnapshot.yesPool.toString()
+      );
+      await expectError(publish(marketId), "PayoutParamsAlreadyPublished");
+
+      for (const winner of winners) {
+        const prediction = await program.account["prediction"].fetch(
+          getPredictionPDA(marketId, winner.publicKey)[0]
+        );
+        const claimEvents = await getCpiEvents(await claimReward(marketId, winner));
+        const claimed = claimEvents.find((e) => isEvent(e, "RewardClaimed"));
+        assert.equal(
+          claimed.data.grossReward.toString(),
+          computePayout(params, prediction.tokensReceived).toString()
+        );
+      }
+
+      // Claims never change the published params
+      const after = await program.account["market"].fetch(marketPDA);
+      assert.equal(JSON.stringify(after.payoutParams), JSON.stringify(market.payoutParams));
+    });
+  });
+
+  describe("Idle funds yield adapter", () => {
+    // The integration program doubles as a whitelisted yield adapter
+    const adapter = anchor.workspace.PredictionCaller as Program;
+    const LAMPORTS = web3.LAMPORTS_PER_SOL;
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const getPositionPDA = (marketPDA: web3.PublicKey) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("yield_position"), marketPDA.toBuffer()],
+        adapter.programId
+      )[0];
+
+    const depositIdle = (marketId: BN, amount: BN) =>
+      program.methods
+        .depositIdle(marketId, amount)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          adapterPosition: getPositionPDA(getMarketPDA(marketId)[0]),
+          adapterProgram: adapter.programId,
+          admin: provider.wallet.publicKey,
+        })
+        .rpc();
+
+    const withdrawIdle = (marketId: BN, amount: BN, authority?: web3.Keypair) => {
+      const builder = program.methods
+        .withdrawIdle(marketId, amount)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          adapterPosition: getPositionPDA(getMarketPDA(marketId)[0]),
+          adapterProgram: adapter.programId,
+          treasury: provider.wallet.publicKey,
+          authority: authority ? authority.publicKey : provider.wallet.publicKey,
+        });
+      return authority ? builder.signers([authority]).rpc() : builder.rpc();
+    };
+
+    before(async () => {
+      await updateConfig({ yieldAdapter: adapter.programId, maxIdleBps: 5000 });
+    });
+
+    after(async () => {
+      await updateConfig({ yieldAdapter: web3.PublicKey.default, maxIdleBps: 0 });
+    });
+
+    it("recalls idle funds with their yield before a big claim", async () => {
+      const { marketId, marketPDA } = await createShortMarket(6);
+      const [winner, loser, stranger] = [0, 1, 2].map(() => web3.Keypair.generate());
+      for (const user of [winner, loser, stranger]) await airdropSol(user.publicKey, 5);
+      await placePrediction(marketId, winner, true, new BN(3 * LAMPORTS));
+      await placePrediction(marketId, loser, false, new BN(3 * LAMPORTS));
+
+      await adapter.methods
+        .openYieldPosition()
+        .accounts({
+          payer: provider.wallet.publicKey,
+          market: marketPDA,
+          position: getPositionPDA(marketPDA),
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      // Collateral above the vault's rent; at most half of it may go out
+      const [vaultPDA] = getVaultPDA(marketId);
+      const vaultInfo = await provider.connection.getAccountInfo(vaultPDA);
+      const rent = await provider.connection.getMinimumBalanceForRentExemption(
+        vaultInfo.data.length
+      );
+      const liquid = new BN(vaultInfo.lamports - rent);
+      await expectError(depositIdle(marketId, liquid.muln(55).divn(100)), "IdleLimitExceeded");
+      const deposit = liquid.muln(40).divn(100);
+      await depositIdle(marketId, deposit);
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.idleDeployed.toString(), deposit.toString());
+      assert.equal(market.idleAdapter.toString(), adapter.programId.toString());
+
+      // Simulated yield accrues on the position
+      const earned = LAMPORTS / 100;
+      await provider.sendAndConfirm(
+        new web3.Transaction().add(
+          web3.SystemProgram.transfer({
+            fromPubkey: provider.wallet.publicKey,
+            toPubkey: getPositionPDA(marketPDA),
+            lamports: earned,
+          })
+        )
+      );
+
+      // Only the admin recalls while the market is open
+      await expectError(withdrawIdle(marketId, deposit, stranger), "Unauthorized");
+
+      await sleep(7000);
+      await resolveMarket(marketId, true);
+
+      // The liquid vault can't pay the winner: the claim fails, no haircut
+      await expectError(claimReward(marketId, winner), "IdleFundsNotRecalled");
+
+      // Once resolved anyone can recall; the yield goes to the treasury
+      const vaultBefore = await provider.connection.getBalance(vaultPDA);
+      const recall = await withdrawIdle(marketId, deposit, stranger);
+      const withdrawn = (await getCpiEvents(recall)).find((e) =>
+        isEvent(e, "idleFundsWithdrawn")
+      );
+      assert.equal(withdrawn.data.principal.toString(), deposit.toString());
+      assert.equal(withdrawn.data.yieldAmount.toNumber(), earned);
+      assert.equal(withdrawn.data.idleDeployed.toNumber(), 0);
+      const vaultAfter = await provider.connection.getBalance(vaultPDA);
+      assert.equal(vaultAfter - vaultBefore, deposit.toNumber());
+
+      const claim = await claimReward(marketId, winner);
+      const claimed = (await getCpiEvents(claim)).find((e) => isEvent(e, "rewardClaimed"));
+      assert.equal(claimed.data.payoutBps, 10_000);
+      market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.idleDeployed.toNumber(), 0);
+    });
+  });
+
+  describe("Outcome mint validation", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const nextMarketPDA = async () =>
+      getMarketPDA((await program.account["marketCounter"].fetch(getCounterPDA()[0])).nextId)[0];
+
+    const oneWeek = () => new BN(Math.floor(Date.now() / 1000) + 7 * 24 * 60 * 60);
+
+    it("stores no mints, and 64 bytes less, for a market created without them", async () => {
+      const { marketPDA: plainPDA } = await createMarket(oneWeek(), marketCreator);
+      const plain = await program.account["market"].fetch(plainPDA);
+      assert.isNull(plain.outcomeMints);
+
+      const marketPDA = await nextMarketPDA();
+      const mints = {
+        yes: await createMint(provider.connection, marketCreator, marketPDA, null, 9),
+        no: await createMint(provider.connection, marketCreator, marketPDA, null, 9),
+      };
+      await createMarket(oneWeek(), marketCreator, {}, null, mints);
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.outcomeMints.yes.equals(mints.yes));
+      assert.isTrue(market.outcomeMints.no.equals(mints.no));
+
+      const [plainInfo, info] = await Promise.all([
+        provider.connection.getAccountInfo(plainPDA),
+        provider.connection.getAccountInfo(marketPDA),
+      ]);
+      assert.equal(info.data.length - plainInfo.data.length, 64);
+    });
+
+    it("rejects mints the market can't mint, a lone mint, or one mint twice", async () => {
+      const marketPDA = await nextMarketPDA();
+      const foreign = await createMint(provider.connection, marketCreator, marketCreator.publicKey, null, 9);
+      const own = await createMint(provider.connection, marketCreator, marketPDA, null, 9);
+
+      await expectError(
+        createMarket(oneWeek(), marketCreator, {}, null, { yes: own, no: foreign }),
+        "ConstraintMintMintAuthority"
+      );
+      await expectError(
+        createMarket(oneWeek(), marketCreator, {}, null, { yes: own, no: null }),
+        "InvalidOutcomeMint"
+      );
+      await expectError(
+        createMarket(oneWeek(), marketCreator, {}, null, { yes: own, no: own }),
+        "InvalidOutcomeMint"
+      );
+    });
+  });
+
+  describe("Self-exclusion", () => {
+    const DAY = 24 * 60 * 60;
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const now = () => Math.floor(Date.now() / 1000);
+
+    const exclude = (user: web3.Keypair, until: number) =>
+      program.methods
+        .selfExclude(new BN(until))
+        .accounts({
+          selfExclusion: getSelfExclusionPDA(user.publicKey),
+          user: user.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+    const close = (user: web3.Keypair) =>
+      program.methods
+        .closeSelfExclusion()
+        .accounts({
+          selfExclusion: getSelfExclusionPDA(user.publicKey),
+          user: user.publicKey,
+        })
+        .signers([user])
+        .rpc();
+
+    it("only accepts an end between a day and a year out", async () => {
+      const user = web3.Keypair.generate();
+      await airdropSol(user.publicKey, 1);
+
+      await expectError(exclude(user, now() + DAY - 60), "InvalidExclusionPeriod");
+      await expectError(exclude(user, now() + 366 * DAY), "InvalidExclusionPeriod");
+      assert.isNull(await provider.connection.getAccountInfo(getSelfExclusionPDA(user.publicKey)));
+    });
+
+    it("blocks betting, and can be extended but not shortened or closed", async () => {
+      const user = web3.Keypair.generate();
+      await airdropSol(user.publicKey, 2);
+      const { marketId } = await createMarket(new BN(now() + 7 * DAY));
+
+      const until = now() + 2 * DAY;
+      await exclude(user, until);
+      await expectError(
+        placePrediction(marketId, user, true, PREDICTION_AMOUNT),
+        "SelfExcluded"
+      );
+
+      const exclusion = await program.account["selfExclusion"].fetch(
+        getSelfExclusionPDA(user.publicKey)
+      );
+      await exclude(user, until + DAY);
+      const extended = await program.account["selfExclusion"].fetch(
+        getSelfExclusionPDA(user.publicKey)
+      );
+      assert.equal(extended.until.toNumber(), until + DAY);
+      assert.equal(extended.createdAt.toString(), exclusion.createdAt.toString());
+
+      await expectError(exclude(user, until), "SelfExclusionActive");
+      await expectError(close(user), "SelfExclusionActive");
+      await expectError(
+        placePrediction(marketId, user, true, PREDICTION_AMOUNT),
+        "SelfExcluded"
+      );
+    });
+
+    it("leaves claims open to an excluded winner", async () => {
+      const winner = web3.Keypair.generate();
+      const loser = web3.Keypair.generate();
+      await airdropSol(winner.publicKey, 2);
+      await airdropSol(loser.publicKey, 2);
+      const { marketId } = await createShortMarket(5);
+      await placePrediction(marketId, winner, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, loser, false, PREDICTION_AMOUNT);
+      await exclude(winner, now() + 30 * DAY);
+
+      await sleep(6000);
+      await resolveMarket(marketId, true);
+      await claimReward(marketId, winner);
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, winner.publicKey)[0]
+      );
+      assert.isTrue(prediction.claimed);
+    });
+  });
+
+  describe("Max bet ramp", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const MAX_BET = new BN(web3.LAMPORTS_PER_SOL);
+    const ramp = { maxBet: MAX_BET, maxBetRampSecs: new BN(3_600), maxBetRampStartBps: 1_000 };
+    const oneWeek = () => new BN(Math.floor(Date.now() / 1000) + 7 * 24 * 60 * 60);
+
+    it("announces the ramp and caps early bets near its starting share", async () => {
+      const { marketId, signature } = await createMarket(oneWeek(), marketCreator, ramp);
+      const event = (await getCpiEvents(signature)).find((e) => isEvent(e, "MarketCreated"));
+      assert.equal(event.data.maxBet.toString(), MAX_BET.toString());
+      assert.equal(event.data.maxBetRampSecs.toNumber(), 3_600);
+      assert.equal(event.data.maxBetRampStartBps, 1_000);
+
+      // A few seconds in, the cap has barely moved off 10%
+      const bettor = web3.Keypair.generate();
+      await airdropSol(bettor.publicKey, 2);
+      await expectError(
+        placePrediction(marketId, bettor, true, MAX_BET.divn(5)),
+        "BetAboveCap"
+      );
+      await placePrediction(marketId, bettor, true, MAX_BET.divn(10));
+    });
+
+    it("rejects a ramp without a cap or a starting share", async () => {
+      await expectError(
+        createMarket(oneWeek(), marketCreator, { ...ramp, maxBet: new BN(0) }),
+        "InvalidMarketOptions"
+      );
+      await expectError(
+        createMarket(oneWeek(), marketCreator, { ...ramp, maxBetRampStartBps: 0 }),
+        "InvalidMarketOptions"
+      );
+    });
+  });
+
+  describe("Resolver revocation", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const unset = web3.PublicKey.default;
+    const zeroDelays = [new BN(0), new BN(0), new BN(0)];
+
+    const setPrimary = (marketId: BN, resolver: web3.PublicKey) =>
+      program.methods
+        .setResolvers(marketId, [resolver, unset, unset], zeroDelays)
+        .accounts({ market: getMarketPDA(marketId)[0], creator: marketCreator.publicKey })
+        .signers([marketCreator])
+        .rpc();
+
+    const revoke = (marketId: BN, resolver: web3.PublicKey, authority?: web3.Keypair) =>
+      program.methods
+        .revokeResolver(marketId, resolver)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          authority: authority ? authority.publicKey : provider.wallet.publicKey,
+        })
+        .signers(authority ? [authority] : [])
+        .rpc();
+
+    it("strikes out a leaked key and holds resolution for the reassign delay", async () => {
+      const leaked = web3.Keypair.generate();
+      const fresh = web3.Keypair.generate();
+      await airdropSol(leaked.publicKey, 1);
+      await airdropSol(fresh.publicKey, 1);
+      const { marketId, marketPDA } = await createShortMarket(5);
+      await setPrimary(marketId, leaked.publicKey);
+
+      await expectError(revoke(marketId, leaked.publicKey, leaked), "Unauthorized");
+      await expectError(revoke(marketId, fresh.publicKey), "UnknownResolver");
+      await revoke(marketId, leaked.publicKey);
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.resolvers[0].equals(unset));
+
+      await sleep(6000);
+      await expectError(resolveMarket(marketId, true, leaked), "ResolversNotActive");
+      await expectError(resolveMarket(marketId, true), "ResolversNotActive");
+
+      // Reassigning past resolution time is allowed, but only takes hold later
+      await setPrimary(marketId, fresh.publicKey);
+      market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.resolvers[0].equals(fresh.publicKey));
+      assert.isAbove(market.resolversActiveAt.toNumber(), Math.floor(Date.now() / 1000) + 3_000);
+      await expectError(resolveMarket(marketId, true, fresh), "ResolversNotActive");
+    });
+
+    it("voids a pending resolution commitment", async () => {
+      const { marketId, marketPDA } = await createShortMarket(5);
+      const primary = web3.Keypair.generate().publicKey;
+      await setPrimary(marketId, primary);
+      await sleep(6000);
+      await program.methods
+        .commitResolution(marketId, Array(32).fill(7))
+        .accounts({ market: marketPDA, admin: marketCreator.publicKey })
+        .signers([marketCreator])
+        .rpc();
+
+      await revoke(marketId, primary, marketCreator);
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isNull(market.resolutionCommitment);
+    });
+  });
+
+  describe("Conditional markets", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const settleParent = (marketId: BN, parentPDA: web3.PublicKey) =>
+      program.methods
+        .settleParentCondition(marketId)
+        .accounts({ market: getMarketPDA(marketId)[0], parentMarket: parentPDA })
+        .rpc();
+
+    // A parent resolving YES a few seconds in, with a child on each outcome
+    const resolvedParent = async () => {
+      const parent = await createShortMarket(3);
+      const child = async (outcome: boolean, seconds: number) =>
+        createShortMarket(seconds, marketCreator, {
+          parent: { market: parent.marketPDA, outcome },
+        });
+      const onYes = await child(true, 6);
+      const onNo = await child(false, 7);
+      await placePrediction(parent.marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(parent.marketId, predictor2, false, PREDICTION_AMOUNT);
+      await placePrediction(onNo.marketId, predictor1, true, PREDICTION_AMOUNT);
+      return { parent, onYes, onNo };
+    };
+
+    it("rejects a parent that resolves after the child", async () => {
+      const parent = await createShortMarket(60);
+      const options = { parent: { market: parent.marketPDA, outcome: true } };
+      await expectError(createShortMarket(30, marketCreator, options), "InvalidParentMarket");
+    });
+
+    it("resolves only once the parent has gone the required way", async () => {
+      const { parent, onYes } = await resolvedParent();
+      await expectError(settleParent(onYes.marketId, parent.marketPDA), "ParentUnresolved");
+
+      await sleep(8000);
+      await expectError(resolveMarket(onYes.marketId, true), "ParentUnresolved");
+      await resolveMarket(parent.marketId, true);
+
+      const events = await getCpiEvents(await settleParent(onYes.marketId, parent.marketPDA));
+      const met = events.find((e) => isEvent(e, "ParentConditionMet")).data;
+      assert.isTrue(met.parent.equals(parent.marketPDA));
+      await expectError(settleParent(onYes.marketId, parent.marketPDA), "ParentConditionSettled");
+      await resolveMarket(onYes.marketId, true);
+    });
+
+    it("voids the child when the parent goes the other way", async () => {
+      const { parent, onNo } = await resolvedParent();
+      await sleep(8000);
+      await resolveMarket(parent.marketId, true);
+
+      const events = await getCpiEvents(await settleParent(onNo.marketId, parent.marketPDA));
+      const voided = events.find((e) => isEvent(e, "MarketVoided")).data;
+      assert.deepEqual(voided.reason, { parentOutcome: {} });
+      const market = await program.account["market"].fetch(onNo.marketPDA);
+      assert.isTrue(market.resolved);
+    });
+  });
+
+  describe("Compressed receipts", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const DEPTH = 20;
+    const u64 = (n: BN | number) => new BN(n).toArrayLike(Buffer, "le", 8);
+    const node = (left: Buffer, right: Buffer) =>
+      Buffer.from(keccak_256(Buffer.concat([Buffer.from([1]), left, right])));
+    const leaf = (marketId: BN, receipt: any) =>
+      Buffer.from(
+        keccak_256(
+          Buffer.concat([
+            Buffer.from([0]),
+            u64(marketId),
+            u64(receipt.index),
+            receipt.owner.toBuffer(),
+            Buffer.from([receipt.predictionType ? 1 : 0]),
+            u64(receipt.amountDeposited),
+            u64(receipt.tokensReceived),
+          ])
+        )
+      );
+    // Sibling path of leaf `index` among `leaves`, padded with empty subtrees
+    const proofFor = (leaves: Buffer[], index: number) => {
+      let level = leaves;
+      let zero = Buffer.alloc(32);
+      const proof: number[][] = [];
+      for (let depth = 0; depth < DEPTH; depth++) {
+        proof.push(Array.from(level[index ^ 1] ?? zero));
+        const next: Buffer[] = [];
+        for (let i = 0; i < level.length; i += 2) next.push(node(level[i], level[i + 1] ?? zero));
+        level = next;
+        zero = node(zero, zero);
+        index >>= 1;
+      }
+      return proof;
+    };
+
+    const getReceiptTreePDA = (marketId: BN) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("receipt_tree"), u64(marketId)],
+        program.programId
+      )[0];
+
+    const placeCompressed = async (
+      marketId: BN,
+      bettor: web3.Keypair,
+      side: boolean,
+      root?: number[]
+    ) => {
+      const marketPDA = getMarketPDA(marketId)[0];
+      const market = await program.account["market"].fetch(marketPDA);
+      return program.methods
+        .placeCompressedPrediction(marketId, side, PREDICTION_AMOUNT, 0, root ?? market.receiptRoot)
+        .accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          receiptTree: getReceiptTreePDA(marketId),
+          insuranceFund: getInsuranceFundPDA()[0],
+          creatorStats: getCreatorStatsPDA(market.liabilityCreator),
+          selfExclusion: getSelfExclusionPDA(bettor.publicKey),
+          predictor: bettor.publicKey,
+        })
+        .signers([bettor])
+        .rpc();
+    };
+
+    const claimCompressed = (
+      marketId: BN,
+      claimer: web3.Keypair,
+      receipt: any,
+      proof: number[][]
+    ) =>
+      program.methods
+        .claimCompressed(marketId, receipt, proof)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          receiptClaims: web3.PublicKey.findProgramAddressSync(
+            [Buffer.from("receipt_claims"), u64(marketId), Buffer.alloc(4)],
+            program.programId
+          )[0],
+          claimer: claimer.publicKey,
+        })
+        .signers([claimer])
+        .rpc();
+
+    it("appends bets as leaves and pays a proven winning receipt once", async () => {
+      const options = { compressed: true };
+      const { marketId, marketPDA } = await createShortMarket(6, marketCreator, options);
+      await program.methods
+        .initReceiptTree(marketId)
+        .accounts({
+          market: marketPDA,
+          receiptTree: getReceiptTreePDA(marketId),
+          creator: marketCreator.publicKey,
+        })
+        .signers([marketCreator])
+        .rpc();
+
+      const receipts = [];
+      for (const [bettor, side] of [[predictor1, true], [predictor2, false]] as const) {
+        const events = await getCpiEvents(await placeCompressed(marketId, bettor, side));
+        const appended = events.find((e) => isEvent(e, "CompressedPredictionAppended")).data;
+        assert.isTrue(leaf(marketId, appended.receipt).equals(Buffer.from(appended.leaf)));
+        receipts.push(appended.receipt);
+      }
+      const leaves = receipts.map((receipt) => leaf(marketId, receipt));
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.receiptCount.toNumber(), 2);
+      await expectError(
+        placeCompressed(marketId, predictor1, true, Array(32).fill(0)),
+        "StaleReceiptRoot"
+      );
+
+      await sleep(7000);
+      await resolveMarket(marketId, true);
+      const forged = { ...receipts[0], tokensReceived: receipts[0].tokensReceived.addn(1) };
+      await expectError(
+        claimCompressed(marketId, predictor1, forged, proofFor(leaves, 0)),
+        "InvalidReceiptProof"
+      );
+      await expectError(
+        claimCompressed(marketId, predictor2, receipts[1], proofFor(leaves, 1)),
+        "PredictionLost"
+      );
+      const events = await getCpiEvents(
+        await claimCompressed(marketId, predictor1, receipts[0], proofFor(leaves, 0))
+      );
+      const claimed = events.find((e) => isEvent(e, "CompressedReceiptClaimed")).data;
+      assert.equal(claimed.index.toNumber(), 0);
+      assert.isAbove(claimed.amount.toNumber(), PREDICTION_AMOUNT.toNumber());
+      await expectError(
+        claimCompressed(marketId, predictor1, receipts[0], proofFor(leaves, 0)),
+        "AlreadyClaimed"
+      );
+    });
+  });
+
+  describe("Market health", () => {
+    const marketHealth = (marketId: BN) =>
+      program.methods
+        .marketHealth(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          wsolVault: null,
+        })
+        .view();
+
+    // Checks the view against the vault and market account, given what
+    // the pot should owe at this point
+    const checkHealth = async (marketId: BN, outcomeLiabilities: (market: any) => BN) => {
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      const health = await marketHealth(marketId);
+      const vault = new BN(await provider.connection.getBalance(getVaultPDA(marketId)[0]));
+      const rent = new BN(await provider.connection.getMinimumBalanceForRentExemption(0));
+      const owed = outcomeLiabilities(market);
+      const liabilities = owed
+        .add(market.feeCollected)
+        .add(market.rebatePot.sub(market.rebatePaid))
+        .add(market.keeperBudget)
+        .add(market.creatorTips);
+
+      assert.equal(health.vaultLamports.toString(), vault.toString());
+      assert.equal(health.rentFloor.toString(), rent.toString());
+      assert.equal(health.collateral.toString(), vault.sub(rent).toString());
+      assert.equal(health.outcomeLiabilities.toString(), owed.toString());
+      assert.equal(health.feeCollected.toString(), market.feeCollected.toString());
+      assert.equal(health.auctionEscrow.toNumber(), 0);
+      assert.equal(health.creatorTips.toString(), market.creatorTips.toString());
+      assert.equal(health.liabilities.toString(), liabilities.toString());
+      assert.equal(
+        health.healthBps.toString(),
+        vault.sub(rent).muln(10_000).div(liabilities).toString()
+      );
+    };
+
+    it("reports the vault against the pot before and after resolution", async () => {
+      const { marketId } = await createShortMarket(4);
+      await placePrediction(marketId, predictor1, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, predictor2, false, PREDICTION_AMOUNT.muln(2));
+
+      // Unresolved, either side may win the whole pot
+      await checkHealth(marketId, (m) => m.yesPool.add(m.noPool).add(m.sponsoredAmount));
+
+      // Resolved, the winners' entitlements rounded up, or the unpaid pot
+      const entitlements = (m: any) => {
+        const snapshot = m.resolutionSnapshot;
+        const pot = snapshot.yesPool.add(snapshot.noPool).add(snapshot.sponsoredAmount);
+        const owed = m.yesTokens.mul(pot).add(snapshot.yesPool.subn(1)).div(snapshot.yesPool);
+        return BN.max(owed, pot.sub(m.totalClaimed).sub(m.dustSwept).sub(m.escheated));
+      };
+      await sleep(5000);
+      await resolveMarket(marketId, true);
+      await checkHealth(marketId, entitlements);
+      await claimReward(marketId, predictor1);
+      await checkHealth(marketId, entitlements);
+    });
+  });
+
+  describe("Daily creation limit", () => {
+    const creator = web3.Keypair.generate();
+    const trusted = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const inDays = (days: number) =>
+      new BN(Math.floor(Date.now() / 1000) + days * 24 * 60 * 60);
+
+    before(async () => {
+      await airdropSol(creator.publicKey, 10);
+      await airdropSol(trusted.publicKey, 10);
+      await program.methods
+        .approveCreator(trusted.publicKey, false, 3)
+        .accounts({
+          config: getConfigPDA()[0],
+          creatorAllow: getCreatorAllowPDA(trusted.publicKey),
+          admin: provider.wallet.publicKey,
+        })
+        .rpc();
+      await updateConfig({ maxMarketsPerDay: 1 });
+    });
+
+    after(async () => {
+      await updateConfig({ maxMarketsPerDay: 0 });
+    });
+
+    it("stops a creator at the config's daily limit", async () => {
+      await createMarket(inDays(1), creator);
+      await expectError(createMarket(inDays(2), creator), "CreationRateLimited");
+
+      const stats = await program.account["creatorStats"].fetch(
+        getCreatorStatsPDA(creator.publicKey)
+      );
+      assert.equal(stats.marketsCreatedToday, 1);
+      assert.equal(
+        stats.dayBucket.toNumber(),
+        Math.floor(Date.now() / 1000 / (24 * 60 * 60))
+      );
+    });
+
+    it("lets an approved creator's higher limit win", async () => {
+      for (const days of [1, 2, 3]) {
+        await createMarket(inDays(days), trusted);
+      }
+      await expectError(createMarket(inDays(4), trusted), "CreationRateLimited");
+    });
+  });
+
+  describe("Create and predict", () => {
+    const creator = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const createAndPredict = async (options: any = {}) => {
+      const [counterPDA] = getCounterPDA();
+      const counter = await program.account["marketCounter"].fetch(counterPDA);
+      const marketId: BN = counter.nextId;
+      const resolutionTime = new BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60);
+
+      await program.methods
+        .initializeMarketAndPredict(
+          QUESTION,
+          resolutionTime,
+          INITIAL_LIQUIDITY,
+          5_000,
+          0,
+          { ...DEFAULT_MARKET_OPTIONS, ...options },
+          false,
+          PREDICTION_AMOUNT
+        )
+        .accounts({
+          marketCounter: counterPDA,
+          market: getMarketPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          registryPage: (await getNextRegistryPagePDA())[0],
+          creator: creator.publicKey,
+          marketVault: getVaultPDA(marketId)[0],
+          questionRegistry: getQuestionRegistryPDA(QUESTION, resolutionTime)[0],
+          duplicateApprover: null,
+          yesMint: null,
+          noMint: null,
+          series: null,
+          parentMarket: null,
+          creatorAllow: null,
+          creatorStats: getCreatorStatsPDA(creator.publicKey),
+          treasury: null,
+          predictionAccount: getPredictionPDA(marketId, creator.publicKey)[0],
+          userProfile: getProfilePDA(creator.publicKey)[0],
+          ...(await getPositionIndexAccounts(marketId, creator.publicKey)),
+          epochStats: getEpochStatsPDA(creator.publicKey, await currentEpoch())[0],
+          referrer: null,
+          nftTokenAccount: null,
+          nftMetadata: null,
+          insuranceFund: getInsuranceFundPDA()[0],
+          selfExclusion: getSelfExclusionPDA(creator.publicKey),
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([creator])
+        .rpc();
+
+      return marketId;
+    };
+
+    before(async () => {
+      await airdropSol(creator.publicKey, 10);
+    });
+
+    it("opens the market with the creator's bet as its first trade", async () => {
+      const marketId = await createAndPredict();
+
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.equal(market.tradeCount, 1);
+      assert.equal(market.creatorLiability.toString(), PREDICTION_AMOUNT.toString());
+      assert.isTrue(market.noPool.gt(market.yesPool));
+
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, creator.publicKey)[0]
+      );
+      assert.isFalse(prediction.predictionType);
+      assert.equal(prediction.amountDeposited.toString(), PREDICTION_AMOUNT.toString());
+      assert.equal(prediction.tradeSequence.toString(), market.sequence.toString());
+    });
+
+    it("rejects a wSOL vault market, which has no vault to bet into yet", async () => {
+      await expectError(createAndPredict({ wsolVault: true }), "WsolVaultRequired");
+    });
+  });
+
+  describe("Reduce-only window", () => {
+    const long = web3.Keypair.generate();
+    const adder = web3.Keypair.generate();
+    const flat = web3.Keypair.generate();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    // Leaves `owner` long YES through a split-off position only, so its
+    // primary prediction address is free for another bet
+    const holdThroughSplit = async (marketId: BN, owner: web3.Keypair) => {
+      await placePrediction(marketId, owner, true, PREDICTION_AMOUNT);
+      const [primaryPDA] = getPredictionPDA(marketId, owner.publicKey);
+      const primary = await program.account["prediction"].fetch(primaryPDA);
+      const { userMarketIndex } = await getPositionIndexAccounts(marketId, owner.publicKey);
+      await program.methods
+        .splitPosition(marketId, primary.tokensReceived.divn(2))
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          predictionAccount: primaryPDA,
+          userMarketIndex,
+          newPrediction: getPredictionPDA(marketId, owner.publicKey, 1)[0],
+          predictor: owner.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([owner])
+        .rpc();
+      await program.methods
+        .earlyExit(marketId)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          predictionAccount: primaryPDA,
+          userMarketIndex,
+          predictor: owner.publicKey,
+          rentPayer: owner.publicKey,
+        })
+        .signers([owner])
+        .rpc();
+      const [splitPDA] = getPredictionPDA(marketId, owner.publicKey, 1);
+      return splitPDA;
+    };
+
+    const betWithPositions = async (
+      marketId: BN,
+      predictor: web3.Keypair,
+      side: boolean,
+      amount: BN,
+      positions: web3.PublicKey[]
+    ) =>
+      (await placePredictionIx(marketId, predictor, side, amount))
+        .remainingAccounts(
+          positions.map((pubkey) => ({ pubkey, isSigner: false, isWritable: false }))
+        )
+        .rpc();
+
+    before(async () => {
+      for (const wallet of [long, adder, flat]) {
+        await airdropSol(wallet.publicKey, 3);
+      }
+    });
+
+    it("only lets bets take an open position toward flat", async () => {
+      const { marketId } = await createShortMarket(20, marketCreator, {
+        reduceOnlyWindowSecs: new BN(12),
+        earlyExitPenaltyBps: 100,
+      });
+      const longSplit = await holdThroughSplit(marketId, long);
+      const adderSplit = await holdThroughSplit(marketId, adder);
+      await sleep(9000);
+
+      await expectError(
+        betWithPositions(marketId, flat, true, PREDICTION_AMOUNT, []),
+        "ReduceOnlyWindow"
+      );
+      await expectError(
+        betWithPositions(marketId, adder, true, PREDICTION_AMOUNT.divn(10), [adderSplit]),
+        "ReduceOnlyWindow"
+      );
+      // Leaving out the open position hides it, which is refused too
+      await expectError(
+        betWithPositions(marketId, long, false, PREDICTION_AMOUNT.divn(10), []),
+        "ReduceOnlyWindow"
+      );
+      await betWithPositions(marketId, long, false, PREDICTION_AMOUNT.divn(10), [longSplit]);
+
+      const hedge = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, long.publicKey)[0]
+      );
+      assert.isFalse(hedge.predictionType);
+    });
+  });
+
+  describe("Event replay", () => {
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const replay = async (marketId: BN, fromSeq: number) => {
+      const signature = await program.methods
+        .replayMarketEvents(marketId, new BN(fromSeq))
+        .accounts({ market: getMarketPDA(marketId)[0] })
+        .rpc();
+      const events = await getCpiEvents(signature);
+      return events.find((e) => isEvent(e, "MarketStateSnapshot")).data;
+    };
+
+    it("snapshots live state with the latest trades' pools", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId, marketPDA } = await createMarket(
+        new BN(now + 7 * 24 * 60 * 60),
+        marketCreator,
+        { poolHistoryLen: 3 }
+      );
+      const pools: [string, string][] = [];
+      for (const side of [true, false, true, true]) {
+        const trader = web3.Keypair.generate();
+        await airdropSol(trader.publicKey, 1);
+        await placePrediction(marketId, trader, side, new BN(web3.LAMPORTS_PER_SOL / 20));
+        const market = await program.account["market"].fetch(marketPDA);
+        pools.push([market.yesPool.toString(), market.noPool.toString()]);
+      }
+
+      const snapshot = await replay(marketId, 0);
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(snapshot.eventSeq.toString(), market.eventSeq.toString());
+      assert.equal(snapshot.sequence.toString(), market.sequence.toString());
+      assert.equal(snapshot.yesPool.toString(), market.yesPool.toString());
+      assert.equal(snapshot.noPool.toString(), market.noPool.toString());
+      assert.equal(snapshot.yesTokens.toString(), market.yesTokens.toString());
+      assert.equal(snapshot.noTokens.toString(), market.noTokens.toString());
+      assert.equal(snapshot.totalVolume.toString(), market.totalVolume.toString());
+      assert.equal(snapshot.feeCollected.toString(), market.feeCollected.toString());
+      assert.equal(snapshot.tradeCount, market.tradeCount);
+      assert.equal(snapshot.uniqueBettors, market.uniqueBettors);
+
+      // Three slots: the first trade has been overwritten
+      assert.isFalse(snapshot.historyComplete);
+      assert.deepEqual(
+        snapshot.checkpoints.map((c: any) => [c.yesPool.toString(), c.noPool.toString()]),
+        pools.slice(1)
+      );
+      const sequences = snapshot.checkpoints.map((c: any) => c.sequence.toNumber());
+      assert.equal(sequences[2], market.sequence.toNumber());
+      assert.deepEqual(sequences, [sequences[0], sequences[0] + 1, sequences[0] + 2]);
+
+      // Asking only for trades still held returns just those
+      const recent = await replay(marketId, sequences[1]);
+      assert.isTrue(recent.historyComplete);
+      assert.equal(recent.checkpoints.length, 2);
+      assert.equal(recent.eventSeq.toString(), snapshot.eventSeq.addn(1).toString());
+    });
+
+    it("keeps no checkpoints by default and caps the buffer", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      await betBothSides(marketId);
+      const snapshot = await replay(marketId, 0);
+      assert.lengthOf(snapshot.checkpoints, 0);
+      assert.isFalse(snapshot.historyComplete);
+
+      await expectError(
+        createMarket(new BN(now + 7 * 24 * 60 * 60), marketCreator, { poolHistoryLen: 65 }),
+        "InvalidMarketOptions"
+      );
+    });
+  });
+
+  describe("Bet rejections", () => {
+    const bettor = web3.Keypair.generate();
+    const oneWeek = () => new BN(Math.floor(Date.now() / 1000) + 7 * 24 * 60 * 60);
+
+    // Asserts the error, then returns the BetRejected logged before it
+    const rejection = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+        const rejected = (e.logs ?? [])
+          .filter((log: string) => log.startsWith("Program data: "))
+          .map((log: string) => program.coder.events.decode(log.slice(14)))
+          .find((event: any) => event && isEvent(event, "BetRejected"));
+        assert.isDefined(rejected, "BetRejected not logged");
+        assert.equal(rejected.data.errorCode, e.error.errorCode.number);
+        return rejected.data;
+      }
+      assert.fail(`Should have thrown ${name}`);
+    };
+
+    before(async () => {
+      await airdropSol(bettor.publicKey, 5);
+    });
+
+    it("reports a zero bet with its own error", async () => {
+      const { marketId } = await createMarket(oneWeek());
+      const rejected = await rejection(
+        placePrediction(marketId, bettor, true, new BN(0)),
+        "BetAmountZero"
+      );
+      assert.equal(rejected.marketId.toString(), marketId.toString());
+      assert.equal(rejected.limit.toNumber(), 1);
+      assert.equal(rejected.provided.toNumber(), 0);
+    });
+
+    it("reports the cap a bet went over", async () => {
+      const maxBet = new BN(web3.LAMPORTS_PER_SOL / 10);
+      const { marketId } = await createMarket(oneWeek(), marketCreator, { maxBet });
+      const amount = maxBet.muln(2);
+      const rejected = await rejection(
+        placePrediction(marketId, bettor, true, amount),
+        "BetAboveCap"
+      );
+      assert.equal(rejected.limit.toString(), maxBet.toString());
+      assert.equal(rejected.provided.toString(), amount.toString());
+    });
+
+    it("reports the price impact a bet would have had", async () => {
+      const { marketId } = await createMarket(oneWeek());
+      const amount = new BN(web3.LAMPORTS_PER_SOL);
+      const quote = await quotePrediction(marketId, bettor.publicKey, false, amount);
+      const builder = await placePredictionIx(marketId, bettor, false, amount, null, null, 100);
+      const rejected = await rejection(builder.rpc(), "PriceImpactTooHigh");
+      assert.equal(rejected.limit.toNumber(), 100);
+      assert.equal(rejected.provided.toNumber(), quote.priceImpactBps);
+    });
+
+    it("reports when betting closed", async () => {
+      const { marketId } = await createShortMarket(3);
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      await sleep(4000);
+      const rejected = await rejection(
+        placePrediction(marketId, bettor, true, PREDICTION_AMOUNT),
+        "MarketExpired"
+      );
+      assert.equal(rejected.limit.toString(), market.resolutionTime.toString());
+      assert.isAtLeast(rejected.provided.toNumber(), market.resolutionTime.toNumber());
+    });
+  });
+
+  describe("Dry runs", () => {
+    const bettor = web3.Keypair.generate();
+    const oneWeek = () => new BN(Math.floor(Date.now() / 1000) + 7 * 24 * 60 * 60);
+
+    // Every account the transaction touches other than the fee payer
+    const touched = async (builder: any) =>
+      (await builder.transaction()).instructions
+        .flatMap((ix: web3.TransactionInstruction) => ix.keys.map((key) => key.pubkey))
+        .filter((key: web3.PublicKey) => !key.equals(provider.wallet.publicKey));
+
+    const snapshot = async (keys: web3.PublicKey[]) =>
+      (await provider.connection.getMultipleAccountsInfo(keys)).map((info) =>
+        info
+          ? {
+              lamports: info.lamports,
+              owner: info.owner.toBase58(),
+              data: info.data.toString("hex"),
+            }
+          : null
+      );
+
+    // Runs the dry run in preflight for its result, then lands it on chain,
+    // asserting every account but the fee payer came out byte-identical
+    const dryRun = async (build: () => Promise<any>) => {
+      const keys = await touched(await build());
+      const before = await snapshot(keys);
+
+      let result: any;
+      try {
+        await (await build()).rpc();
+        assert.fail("Should have thrown DryRunComplete");
+      } catch (e: any) {
+        assert.include(e.toString(), "DryRunComplete");
+        result = (e.logs ?? [])
+          .filter((log: string) => log.startsWith("Program data: "))
+          .map((log: string) => program.coder.events.decode(log.slice(14)))
+          .find((event: any) => event && isEvent(event, "DryRunResult"));
+      }
+      assert.isDefined(result, "DryRunResult not logged");
+
+      try {
+        await (await build()).rpc({ skipPreflight: true });
+        assert.fail("Should have failed on chain");
+      } catch (e: any) {
+        assert.notInclude(e.toString(), "Should have failed on chain");
+      }
+      assert.deepEqual(await snapshot(keys), before);
+      return result.data;
+    };
+
+    before(async () => {
+      await airdropSol(bettor.publicKey, 5);
+    });
+
+    it("reports a first bet exactly and leaves every account untouched", async () => {
+      const { marketId, marketPDA } = await createMarket(oneWeek());
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 5);
+      const result = await dryRun(
+        () => placePredictionIx(marketId, bettor, true, amount, null, null, 0, "", bettor, true)
+      );
+      assert.equal(result.user.toString(), bettor.publicKey.toString());
+      assert.isTrue(result.side);
+      assert.equal(result.paid.toString(), amount.toString());
+      assert.equal(result.received.toNumber(), 0);
+
+      // The real bet lands exactly where the dry run said it would
+      const predictionPDA = await placePrediction(marketId, bettor, true, amount);
+      const prediction = await program.account["prediction"].fetch(predictionPDA);
+      assert.equal(prediction.tokensReceived.toString(), result.tokens.toString());
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.yesPool.toString(), result.yesPool.toString());
+      assert.equal(market.noPool.toString(), result.noPool.toString());
+    });
+
+    it("reports a claim's reward without paying it", async () => {
+      const { marketId } = await createShortMarket(3);
+      const loser = web3.Keypair.generate();
+      await airdropSol(loser.publicKey, 1);
+      await placePrediction(marketId, bettor, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, loser, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+
+      const result = await dryRun(
+        () => claimRewardIx(marketId, bettor, null, 0, null, false, true)
+      );
+      assert.equal(result.paid.toNumber(), 0);
+      assert.isAbove(result.received.toNumber(), 0);
+
+      const events = await getCpiEvents(await claimReward(marketId, bettor));
+      const claimed = events.find((e) => isEvent(e, "RewardClaimed"));
+      assert.equal(claimed.data.reward.toString(), result.received.toString());
+      assert.equal(claimed.data.winningsFee.toString(), result.fee.toString());
+    });
+  });
+
+  describe("Push handling", () => {
+    const yesBettor = web3.Keypair.generate();
+    const noBettor = web3.Keypair.generate();
+
+    const resolvePush = (marketId: BN) =>
+      program.methods
+        .resolvePush(marketId, "")
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          wsolVault: null,
+          resolution: getResolutionPDA(marketId),
+          admin: marketCreator.publicKey,
+          payer: marketCreator.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([marketCreator])
+        .rpc();
+
+    const setPushPolicy = (marketId: BN, pushPolicy: any) =>
+      program.methods
+        .setPushPolicy(marketId, pushPolicy)
+        .accounts({ market: getMarketPDA(marketId)[0], creator: marketCreator.publicKey })
+        .signers([marketCreator])
+        .rpc();
+
+    const claimRefund = (marketId: BN, claimer: web3.Keypair) =>
+      program.methods
+        .claimRefund(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          predictionAccount: getPredictionPDA(marketId, claimer.publicKey)[0],
+          claimer: claimer.publicKey,
+          wsolVault: null,
+          payoutTokenAccount: null,
+          collateralMint: null,
+          tokenProgram: null,
+        })
+        .signers([claimer])
+        .rpc();
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    // A short market with a bet on each side, pushed once it expires
+    const pushedMarket = async (pushPolicy: any) => {
+      const { marketId, marketPDA, signature } = await createShortMarket(3, marketCreator, {
+        pushPolicy,
+      });
+      const created = (await getCpiEvents(signature)).find((e) => isEvent(e, "MarketCreated"));
+      assert.deepEqual(created.data.pushPolicy, pushPolicy);
+      await placePrediction(marketId, yesBettor, true, PREDICTION_AMOUNT);
+      await placePrediction(marketId, noBettor, false, PREDICTION_AMOUNT);
+      await sleep(4000);
+      const events = await getCpiEvents(await resolvePush(marketId));
+      return { marketId, marketPDA, events };
+    };
+
+    before(async () => {
+      await airdropSol(yesBettor.publicKey, 2);
+      await airdropSol(noBettor.publicKey, 2);
+    });
+
+    it("voids on a push by default and refunds both sides", async () => {
+      const { marketId, marketPDA, events } = await pushedMarket({ voidOnPush: {} });
+      const voided = events.find((e) => isEvent(e, "MarketVoided"));
+      assert.deepEqual(voided.data.reason, { push: {} });
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.outcome, { void: {} });
+
+      for (const bettor of [yesBettor, noBettor]) {
+        const before = await provider.connection.getBalance(bettor.publicKey);
+        await claimRefund(marketId, bettor);
+        assert.isAbove(await provider.connection.getBalance(bettor.publicKey), before);
+      }
+      await assertSolvent(marketId);
+    });
+
+    it("awards a push to NO or YES as the policy says", async () => {
+      for (const [pushPolicy, outcome, winner, loser] of [
+        [{ noWinsOnPush: {} }, { no: {} }, noBettor, yesBettor],
+        [{ yesWinsOnPush: {} }, { yes: {} }, yesBettor, noBettor],
+      ] as const) {
+        const { marketId, marketPDA, events } = await pushedMarket(pushPolicy);
+        const resolved = events.find((e) => isEvent(e, "MarketResolved"));
+        assert.isTrue(resolved.data.push);
+        assert.deepEqual(resolved.data.outcome, outcome);
+        const market = await program.account["market"].fetch(marketPDA);
+        assert.deepEqual(market.outcome, outcome);
+
+        await expectError(claimRefund(marketId, winner), "MarketNotVoided");
+        await claimReward(marketId, winner);
+        await expectError(claimReward(marketId, loser), "PredictionLost");
+      }
+    });
+
+    it("locks the policy once the first bet is in", async () => {
+      const { marketId, marketPDA } = await createShortMarket(60);
+      const events = await getCpiEvents(await setPushPolicy(marketId, { yesWinsOnPush: {} }));
+      const set = events.find((e) => isEvent(e, "PushPolicySet"));
+      assert.deepEqual(set.data.pushPolicy, { yesWinsOnPush: {} });
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.pushPolicy, { yesWinsOnPush: {} });
+
+      await placePrediction(marketId, yesBettor, true, PREDICTION_AMOUNT);
+      await expectError(setPushPolicy(marketId, { voidOnPush: {} }), "PushPolicyLocked");
+      market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.pushPolicy, { yesWinsOnPush: {} });
+    });
+  });
+
+  describe("Participant cap", () => {
+    const entrants = [0, 1, 2].map(() => web3.Keypair.generate());
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    before(async () => {
+      for (const entrant of entrants) {
+        await airdropSol(entrant.publicKey, 2);
+      }
+    });
+
+    it("announces a full market and turns new wallets away", async () => {
+      const { marketId, marketPDA } = await createShortMarket(60, marketCreator, {
+        maxParticipants: 2,
+      });
+      await placePrediction(marketId, entrants[0], true, PREDICTION_AMOUNT);
+      const builder = await placePredictionIx(marketId, entrants[1], false, PREDICTION_AMOUNT);
+      const events = await getCpiEvents(await builder.rpc());
+      const full = events.find((e) => isEvent(e, "MarketFull"));
+      assert.equal(full.data.participants, 2);
+
+      await expectError(
+        placePrediction(marketId, entrants[2], true, PREDICTION_AMOUNT),
+        "MarketFull"
+      );
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.participants, 2);
+      assert.equal(market.maxParticipants, 2);
+    });
+  });
+
+  describe("NFT fee discount", () => {
+    const holder = web3.Keypair.generate();
+    const other = web3.Keypair.generate();
+    let nftMint: web3.PublicKey;
+    let marketId: BN;
+
+    before(async () => {
+      await airdropSol(holder.publicKey, 2);
+      await airdropSol(other.publicKey, 2);
+      await updateConfig({
+        discountCollection: web3.Keypair.generate().publicKey,
+        discountBps: 5000,
+      });
+
+      nftMint = await createMint(
+        provider.connection,
+        holder,
+        holder.publicKey,
+        null,
+        0
+      );
+      const now = Math.floor(Date.now() / 1000);
+      ({ marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60)));
+    });
+
+    after(async () => {
+      await updateConfig({ discountCollection: null, discountBps: 0 });
+    });
+
+    it("rejects forged NFT metadata", async () => {
+      const tokenAccount = await createAccount(
+        provider.connection,
+        holder,
+        nftMint,
+        holder.publicKey
+      );
+      await mintTo(provider.connection, holder, nftMint, tokenAccount, holder, 1);
+
+      // A look-alike account that isn't owned by the Token Metadata program
+      const forged = web3.Keypair.generate();
+      await airdropSol(forged.publicKey, 1);
+
+      try {
+        await placePrediction(
+          marketId,
+          holder,
+          true,
+          new BN(web3.LAMPORTS_PER_SOL / 10),
+          null,
+          { tokenAccount, metadata: forged.publicKey }
+        );
+        assert.fail("Should have thrown ForgedNftMetadata");
+      } catch (e: any) {
+        assert.include(e.toString(), "ForgedNftMetadata");
+      }
+    });
+
+    it("rejects an NFT the bettor does not hold", async () => {
+      // The NFT sits in another wallet's token account
+      const otherAccount = await createAccount(
+        provider.connection,
+        other,
+        nftMint,
+        other.publicKey
+      );
+      await mintTo(provider.connection, holder, nftMint, otherAccount, holder, 1);
+
+      try {
+        await placePrediction(
+          marketId,
+          holder,
+          true,
+          new BN(web3.LAMPORTS_PER_SOL / 10),
+          null,
+          { tokenAccount: otherAccount, metadata: getMetadataPDA(nftMint)[0] }
+        );
+        assert.fail("Should have thrown NftNotOwned");
+      } catch (e: any) {
+        assert.include(e.toString(), "NftNotOwned");
+      }
+    });
+
+    it("charges the full fee when no NFT is supplied", async () => {
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+      await placePrediction(marketId, holder, true, amount);
+
+      const market = await program.account["market"].fetch(
+        getMarketPDA(marketId)[0]
+      );
+      const fee = amount.muln(configParams.tradingFeeBps).divn(10_000);
+      assert.equal(market.feeCollected.toString(), fee.toString());
+    });
+  });
+
+  describe("Oracle attestations", () => {
+    const oracle = web3.Keypair.generate();
+
+    it("resolves from a relayed ed25519 attestation", async () => {
+      const { marketId, marketPDA } = await createShortMarket(3, marketCreator, {
+        oracleKey: oracle.publicKey,
+      });
+      await betBothSides(marketId);
+      await sleep(4000);
+
+      const timestamp = new BN(Math.floor(Date.now() / 1000) - 1);
+      await resolveWithAttestation(marketId, false, timestamp, oracle);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.resolved);
+      assert.deepEqual(market.outcome, { no: {} });
+    });
+
+    it("rejects an attestation replayed onto a different market", async () => {
+      const first = await createShortMarket(2, marketCreator, {
+        oracleKey: oracle.publicKey,
+      });
+      const second = await createShortMarket(2, marketCreator, {
+        oracleKey: oracle.publicKey,
+      });
+      await sleep(3000);
+
+      // Genuine signature by the right oracle, but over the first market's id
+      const timestamp = new BN(Math.floor(Date.now() / 1000) - 1);
+      const signedForFirst = attestationMessage(first.marketId, true, timestamp);
+      try {
+        await resolveWithAttestation(
+          second.marketId,
+          true,
+          timestamp,
+          oracle,
+          timestamp.addn(600),
+          signedForFirst
+        );
+        assert.fail("Should have thrown InvalidAttestation");
+      } catch (e: any) {
+        assert.include(e.toString(), "InvalidAttestation");
+      }
+
+      const market = await program.account["market"].fetch(second.marketPDA);
+      assert.isFalse(market.resolved);
+    });
+
+    it("rejects attestations signed by anyone but the oracle", async () => {
+      const { marketId } = await createShortMarket(2, marketCreator, {
+        oracleKey: oracle.publicKey,
+      });
+      await sleep(3000);
+
+      const timestamp = new BN(Math.floor(Date.now() / 1000) - 1);
+      try {
+        await resolveWithAttestation(
+          marketId,
+          true,
+          timestamp,
+          web3.Keypair.generate(),
+          timestamp.addn(600),
+          undefined,
+          oracle.publicKey
+        );
+        assert.fail("Should have thrown InvalidAttestation");
+      } catch (e: any) {
+        assert.include(e.toString(), "InvalidAttestation");
+      }
+    });
+
+    it("records each attestation so it cannot be replayed", async () => {
+      const { marketId, marketPDA } = await createShortMarket(3, marketCreator, {
+        oracleKey: oracle.publicKey,
+      });
+      await betBothSides(marketId);
+      await sleep(4000);
+
+      const timestamp = new BN(Math.floor(Date.now() / 1000) - 1);
+      await resolveWithAttestation(marketId, true, timestamp, oracle);
+
+      const message = attestationMessage(marketId, true, timestamp);
+      const record = await program.account["attestationRecord"].fetch(
+        getAttestationRecordPDA(oracle.publicKey, message)[0]
+      );
+      assert.isTrue(record.market.equals(marketPDA));
+      assert.equal(record.marketId.toString(), marketId.toString());
+      assert.isTrue(record.outcome);
+      assert.equal(record.expiresAt.toString(), timestamp.addn(600).toString());
+
+      // The same signed payload fails on the existing record
+      try {
+        await resolveWithAttestation(marketId, true, timestamp, oracle);
+        assert.fail("Replayed attestation should have failed");
+      } catch (e: any) {
+        assert.include(e.toString(), "already in use");
+      }
+    });
+
+    it("rejects an attestation past its expiry", async () => {
+      const { marketId, marketPDA } = await createShortMarket(2, marketCreator, {
+        oracleKey: oracle.publicKey,
+      });
+      await sleep(3000);
+
+      const now = Math.floor(Date.now() / 1000);
+      try {
+        await resolveWithAttestation(marketId, true, new BN(now - 120), oracle, new BN(now - 60));
+        assert.fail("Should have thrown AttestationExpired");
+      } catch (e: any) {
+        assert.include(e.toString(), "AttestationExpired");
+      }
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isFalse(market.resolved);
+    });
+
+    it("lets the admin purge expired attestation records", async () => {
+      const { marketId } = await createShortMarket(2, marketCreator, {
+        oracleKey: oracle.publicKey,
+      });
+      await sleep(3000);
+
+      const timestamp = new BN(Math.floor(Date.now() / 1000) - 1);
+      const expiresAt = timestamp.addn(4);
+      await resolveWithAttestation(marketId, false, timestamp, oracle, expiresAt);
+      const [recordPDA] = getAttestationRecordPDA(
+        oracle.publicKey,
+        attestationMessage(marketId, false, timestamp, expiresAt)
+      );
+
+      const purge = (maxAgeDays: number) =>
+        program.methods
+          .purgeAttestationRecords(maxAgeDays)
+          .accounts({
+            config: getConfigPDA()[0],
+            admin: provider.wallet.publicKey,
+          })
+          .remainingAccounts([
+            { pubkey: recordPDA, isWritable: true, isSigner: false },
+            { pubkey: provider.wallet.publicKey, isWritable: true, isSigner: false },
+          ])
+          .rpc();
+
+      // Still inside its expiry
+      try {
+        await purge(0);
+        assert.fail("Should have thrown AttestationRecordActive");
+      } catch (e: any) {
+        assert.include(e.toString(), "AttestationRecordActive");
+      }
+
+      await sleep(6000);
+      // Expired, but younger than a day
+      try {
+        await purge(1);
+        assert.fail("Should have thrown AttestationRecordActive");
+      } catch (e: any) {
+        assert.include(e.toString(), "AttestationRecordActive");
+      }
+
+      await purge(0);
+      assert.isNull(await provider.connection.getAccountInfo(recordPDA));
+    });
+  });
+
+  describe("EVM oracle attestations", () => {
+    // Known test vector: private key 1 controls this Ethereum address
+    const ORACLE_PRIVATE_KEY = Buffer.from("00".repeat(31) + "01", "hex");
+    const ORACLE_ETH_ADDRESS = Array.from(
+      Buffer.from("7e5f4552091a69125d5dfcb7b8c2659029395bdf", "hex")
+    );
+
+    const createEthOracleMarket = () =>
+      createShortMarket(3, marketCreator, { oracleEthAddress: ORACLE_ETH_ADDRESS });
+
+    it("resolves from a secp256k1 attestation by the oracle address", async () => {
+      const { marketId, marketPDA } = await createEthOracleMarket();
+      await betBothSides(marketId);
+      await sleep(4000);
+
+      const deadline = new BN(Math.floor(Date.now() / 1000) + 600);
+      await resolveWithEthAttestation(marketId, true, deadline, ORACLE_PRIVATE_KEY);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.resolved);
+      assert.deepEqual(market.outcome, { yes: {} });
+    });
+
+    it("rejects a tampered payload", async () => {
+      const { marketId, marketPDA } = await createEthOracleMarket();
+      await sleep(4000);
+
+      // Signed for YES, submitted as NO
+      const deadline = new BN(Math.floor(Date.now() / 1000) + 600);
+      const signed = ethAttestationMessage(marketId, true, deadline);
+      try {
+        await resolveWithEthAttestation(marketId, false, deadline, ORACLE_PRIVATE_KEY, signed);
+        assert.fail("Should have thrown InvalidAttestation");
+      } catch (e: any) {
+        assert.include(e.toString(), "InvalidAttestation");
+      }
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isFalse(market.resolved);
+    });
+
+    it("rejects attestations past their deadline", async () => {
+      const { marketId } = await createEthOracleMarket();
+      await sleep(4000);
+
+      const deadline = new BN(Math.floor(Date.now() / 1000) - 60);
+      try {
+        await resolveWithEthAttestation(marketId, true, deadline, ORACLE_PRIVATE_KEY);
+        assert.fail("Should have thrown AttestationExpired");
+      } catch (e: any) {
+        assert.include(e.toString(), "AttestationExpired");
+      }
+    });
+  });
+
+  describe("Commit-reveal resolution", () => {
+    const REVEAL_DELAY_MS = 6000;
+    const salt = Buffer.alloc(32, 7);
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    it("resolves only on a matching reveal after the delay", async () => {
+      const { marketId, marketPDA } = await createShortMarket(3);
+      await betBothSides(marketId);
+      await sleep(4000);
+      await commitResolution(marketId, true, salt);
+
+      await expectError(revealResolution(marketId, true, salt), "RevealTooEarly");
+      await expectError(resolveMarket(marketId, true), "ResolutionPending");
+
+      await sleep(REVEAL_DELAY_MS);
+      await expectError(
+        revealResolution(marketId, true, Buffer.alloc(32, 8)),
+        "CommitmentMismatch"
+      );
+      await revealResolution(marketId, true, salt);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.resolved);
+      assert.deepEqual(market.outcome, { yes: {} });
+      assert.isNull(market.resolutionCommitment);
+    });
+
+    it("lets a second commit replace the first before reveal", async () => {
+      const { marketId, marketPDA } = await createShortMarket(3);
+      await betBothSides(marketId);
+      await sleep(4000);
+      const secondSalt = Buffer.alloc(32, 9);
+      await commitResolution(marketId, true, salt);
+      await commitResolution(marketId, false, secondSalt);
+
+      await sleep(REVEAL_DELAY_MS);
+      await expectError(revealResolution(marketId, true, salt), "CommitmentMismatch");
+      await revealResolution(marketId, false, secondSalt);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.outcome, { no: {} });
+    });
+  });
+
+  describe("Commit-reveal betting", () => {
+    const REVEAL_WINDOW = 4;
+    const salt = Buffer.alloc(32, 3);
+    const bettor = web3.Keypair.generate();
+
+    const betCommitment = (side: boolean, amount: BN, betSalt: Buffer) =>
+      Array.from(
+        keccak_256(
+          Buffer.concat([
+            Buffer.from([side ? 1 : 0]),
+            amount.toArrayLike(Buffer, "le", 8),
+            betSalt,
+          ])
+        )
+      );
+
+    const getBetCommitPDA = (marketId: BN, owner: web3.PublicKey) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("bet_commit"), marketId.toArrayLike(Buffer, "le", 8), owner.toBuffer()],
+        program.programId
+      )[0];
+
+    const commitBet = (marketId: BN, side: boolean, amount: BN, escrow: BN) =>
+      program.methods
+        .commitBet(marketId, betCommitment(side, amount, salt), escrow)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          betCommit: getBetCommitPDA(marketId, bettor.publicKey),
+          selfExclusion: getSelfExclusionPDA(bettor.publicKey),
+          bettor: bettor.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([bettor])
+        .rpc();
+
+    const revealBet = async (marketId: BN, side: boolean, amount: BN, betSalt = salt) =>
+      program.methods
+        .revealBet(marketId, side, amount, Array.from(betSalt))
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          betCommit: getBetCommitPDA(marketId, bettor.publicKey),
+          predictionAccount: getPredictionPDA(marketId, bettor.publicKey)[0],
+          userProfile: getProfilePDA(bettor.publicKey)[0],
+          ...(await getPositionIndexAccounts(marketId, bettor.publicKey)),
+          epochStats: getEpochStatsPDA(bettor.publicKey, await currentEpoch())[0],
+          insuranceFund: getInsuranceFundPDA()[0],
+          series: null,
+          bettor: bettor.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([bettor])
+        .rpc();
+
+    const createCommitRevealMarket = () => {
+      const now = Math.floor(Date.now() / 1000);
+      return createMarket(new BN(now + 7 * 24 * 60 * 60), marketCreator, {
+        betRevealWindow: new BN(REVEAL_WINDOW),
+      });
+    };
+
+    before(async () => {
+      await airdropSol(bettor.publicKey, 5);
+    });
+
+    it("prices a revealed bet at reveal time and returns spare escrow", async () => {
+      const { marketId, marketPDA } = await createCommitRevealMarket();
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+      await commitBet(marketId, true, amount, amount.muln(2));
+
+      // Direct bets are refused on commit-reveal markets
+      try {
+        await placePrediction(marketId, predictor1, true, amount);
+        assert.fail("Should have thrown CommitRevealRequired");
+      } catch (e: any) {
+        assert.include(e.toString(), "CommitRevealRequired");
+      }
+
+      const quote = await quotePrediction(marketId, bettor.publicKey, true, amount);
+      await revealBet(marketId, true, amount);
+
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, bettor.publicKey)[0]
+      );
+      assert.equal(prediction.tokensReceived.toString(), quote.tokensOut.toString());
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.totalVolume.toString(), amount.toString());
+      assert.isNull(
+        await provider.connection.getAccountInfo(getBetCommitPDA(marketId, bettor.publicKey))
+      );
+    });
+
+    it("rejects a reveal that does not match the commitment", async () => {
+      const { marketId } = await createCommitRevealMarket();
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+      await commitBet(marketId, true, amount, amount);
+
+      try {
+        await revealBet(marketId, false, amount);
+        assert.fail("Should have thrown CommitmentMismatch");
+      } catch (e: any) {
+        assert.include(e.toString(), "CommitmentMismatch");
+      }
+    });
+
+    it("refuses late reveals and refunds them minus the penalty", async () => {
+      const { marketId, marketPDA } = await createCommitRevealMarket();
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+      await commitBet(marketId, false, amount, amount);
+      await sleep((REVEAL_WINDOW + 2) * 1000);
+
+      try {
+        await revealBet(marketId, false, amount);
+        assert.fail("Should have thrown RevealWindowClosed");
+      } catch (e: any) {
+        assert.include(e.toString(), "RevealWindowClosed");
+      }
+
+      await program.methods
+        .refundBetCommit(marketId)
+        .accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          betCommit: getBetCommitPDA(marketId, bettor.publicKey),
+          bettor: bettor.publicKey,
+        })
+        .signers([bettor])
+        .rpc();
+
+      // 1% of the escrow stays with the market as fees
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.feeCollected.toString(), amount.divn(100).toString());
+    });
+  });
+
+  describe("Price feed resolution", () => {
+    const FEED_ID = new BN(1);
+    const STRIKE = new BN(200_00000000);
+    const [feedPDA] = web3.PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("price_feed"),
+        marketCreator.publicKey.toBuffer(),
+        FEED_ID.toArrayLike(Buffer, "le", 8),
+      ],
+      program.programId
+    );
+
+    const publishPrice = (price: BN, confidence: BN, publishTime: number) =>
+      program.methods
+        .publishPrice(FEED_ID, price, confidence, new BN(publishTime))
+        .accounts({ priceFeed: feedPDA, publisher: marketCreator.publicKey })
+        .signers([marketCreator])
+        .rpc();
+
+    const resolveWithOracle = (marketId: BN) =>
+      program.methods
+        .resolveWithOracle(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          wsolVault: null,
+          priceFeed: feedPDA,
+        })
+        .rpc();
+
+    const createFeedMarket = async (fallbackGraceSecs: number) => {
+      const created = await createShortMarket(3, marketCreator, {
+        priceOracle: {
+          feed: feedPDA,
+          strikePrice: STRIKE,
+          maxPriceAgeSecs: 2,
+          maxConfidenceBps: 50,
+          fallbackGraceSecs,
+          twapWindowSecs: 0,
+          minTwapSamples: 0,
+        },
+      });
+      await betBothSides(created.marketId);
+      return created;
+    };
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    before(async () => {
+      await program.methods
+        .createPriceFeed(FEED_ID)
+        .accounts({
+          priceFeed: feedPDA,
+          publisher: marketCreator.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([marketCreator])
+        .rpc();
+    });
+
+    it("rejects stale and low-confidence prints, then resolves", async () => {
+      const { marketId, marketPDA } = await createFeedMarket(3600);
+      const market = await program.account["market"].fetch(marketPDA);
+      await sleep(4000);
+
+      // Taken after expiry, but older than max_price_age_secs by resolution
+      await publishPrice(STRIKE.muln(2), new BN(0), market.resolutionTime.toNumber());
+      await sleep(3000);
+      await expectError(resolveWithOracle(marketId), "StaleOracle");
+
+      // Fresh, but +/-1% is wider than the 0.5% allowed
+      const price = STRIKE.muln(2);
+      const now = () => Math.floor(Date.now() / 1000) - 1;
+      await publishPrice(price, price.divn(100), now());
+      await expectError(resolveWithOracle(marketId), "LowConfidence");
+
+      await publishPrice(price, price.divn(1000), now());
+      await resolveWithOracle(marketId);
+
+      const resolved = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(resolved.resolved);
+      assert.deepEqual(resolved.outcome, { yes: {} });
+    });
+
+    it("resolves TWAP markets from recorded samples", async () => {
+      const { marketId, marketPDA } = await createShortMarket(10, marketCreator, {
+        priceOracle: {
+          feed: feedPDA,
+          strikePrice: STRIKE,
+          maxPriceAgeSecs: 60,
+          maxConfidenceBps: 50,
+          fallbackGraceSecs: 3600,
+          twapWindowSecs: 10,
+          minTwapSamples: 2,
+        },
+      });
+      const [samplesPDA] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("price_samples"), marketId.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+      const recordSample = () =>
+        program.methods.recordPriceSample(marketId).accounts({
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          priceSamples: samplesPDA,
+          priceFeed: feedPDA,
+          config: getConfigPDA()[0],
+          cranker: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        });
+      const resolveWithTwap = () =>
+        program.methods
+          .resolveWithTwap(marketId)
+          .accounts({
+            market: marketPDA,
+            marketVault: getVaultPDA(marketId)[0],
+            wsolVault: null,
+            priceSamples: samplesPDA,
+          })
+          .rpc();
+
+      // Fees fund the cranker tips
+      await placePrediction(marketId, predictor1, true, new BN(web3.LAMPORTS_PER_SOL / 10));
+      await placePrediction(marketId, predictor2, false, new BN(web3.LAMPORTS_PER_SOL / 10));
+      const feesBefore = (await program.account["market"].fetch(marketPDA)).feeCollected;
+
+      const now = () => Math.floor(Date.now() / 1000) - 1;
+      await publishPrice(STRIKE.muln(6).divn(5), new BN(0), now());
+      await recordSample().rpc();
+
+      // A second sample in the same slot (same transaction) is refused
+      await expectError(
+        recordSample().preInstructions([await recordSample().instruction()]).rpc(),
+        "DuplicateSample"
+      );
+      await expectError(resolveWithTwap(), "InsufficientSamples");
+
+      await sleep(2000);
+      await publishPrice(STRIKE.muln(11).divn(10), new BN(0), now());
+      await recordSample().rpc();
+
+      await sleep(10000);
+      await expectError(resolveWithOracle(marketId), "TwapResolutionRequired");
+      await resolveWithTwap();
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.outcome, { yes: {} });
+      assert.equal(market.feeCollected.toString(), feesBefore.subn(2 * 5_000).toString());
+    });
+
+    it("allows manual fallback only after the grace period", async () => {
+      const { marketId, marketPDA } = await createFeedMarket(4);
+      await sleep(4000);
+
+      const fallback = () =>
+        program.methods
+          .forceManualFallback(marketId, false, "")
+          .accounts({
+            market: marketPDA,
+            marketVault: getVaultPDA(marketId)[0],
+            wsolVault: null,
+            resolution: getResolutionPDA(marketId),
+            admin: marketCreator.publicKey,
+            systemProgram: web3.SystemProgram.programId,
+          })
+          .signers([marketCreator])
+          .rpc();
+
+      await expectError(fallback(), "FallbackNotAvailable");
+      await sleep(5000);
+      await fallback();
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.outcome, { no: {} });
+    });
+  });
+
+  describe("Insurance fund", () => {
+    const [fundPDA] = getInsuranceFundPDA();
+
+    const coverShortfall = (marketId: BN, amount: BN, admin?: web3.Keypair) =>
+      program.methods
+        .coverShortfall(marketId, amount, "claims exceeded vault after fee bug")
+        .accounts({
+          config: getConfigPDA()[0],
+          insuranceFund: fundPDA,
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          admin: admin ? admin.publicKey : provider.wallet.publicKey,
+        })
+        .signers(admin ? [admin] : [])
+        .rpc();
+
+    after(async () => {
+      await updateConfig({ insuranceBps: 0 });
+    });
+
+    it("diverts a slice of every trading fee into the fund", async () => {
+      await updateConfig({ insuranceBps: 1_000 });
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId, marketPDA } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      const before = await program.account["insuranceFund"].fetch(fundPDA);
+
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+      await placePrediction(marketId, predictor1, true, amount);
+
+      const fee = amount.muln(configParams.tradingFeeBps).divn(10_000);
+      const insurance = fee.divn(10);
+      const after = await program.account["insuranceFund"].fetch(fundPDA);
+      assert.equal(
+        after.totalDeposited.sub(before.totalDeposited).toString(),
+        insurance.toString()
+      );
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.feeCollected.toString(), fee.sub(insurance).toString());
+    });
+
+    it("pays donations out only into a named market vault", async () => {
+      const donation = new BN(web3.LAMPORTS_PER_SOL / 10);
+      await program.methods
+        .fundInsurance(donation)
+        .accounts({
+          insuranceFund: fundPDA,
+          donor: predictor2.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([predictor2])
+        .rpc();
+
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+      const [vaultPDA] = getVaultPDA(marketId);
+      const vaultBefore = await provider.connection.getBalance(vaultPDA);
+
+      try {
+        await coverShortfall(marketId, donation, predictor2);
+        assert.fail("Should have thrown Unauthorized");
+      } catch (e: any) {
+        assert.include(e.toString(), "Unauthorized");
+      }
+
+      await coverShortfall(marketId, donation);
+      assert.equal(
+        await provider.connection.getBalance(vaultPDA),
+        vaultBefore + donation.toNumber()
+      );
+      const fund = await program.account["insuranceFund"].fetch(fundPDA);
+      assert.isTrue(fund.totalCovered.gte(donation));
+    });
+  });
+
+  describe("Dispute voting", () => {
+    const voter1 = web3.Keypair.generate();
+    const voter2 = web3.Keypair.generate();
+    const voter3 = web3.Keypair.generate();
+    const outsider = web3.Keypair.generate();
+    const VOTING_PERIOD = 3;
+    const BOND = new BN(web3.LAMPORTS_PER_SOL / 100);
+    let governanceMint: web3.PublicKey;
+    const tokenAccounts = new Map<string, web3.PublicKey>();
+
+    const [disputeConfigPDA] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("dispute_config")],
+      program.programId
+    );
+    const seedPDA = (seed: string, marketId: BN, extra: Buffer[] = []) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from(seed), marketId.toArrayLike(Buffer, "le", 8), ...extra],
+        program.programId
+      )[0];
+
+    const expectError = async (promise: Promise<any>, name: string) => {
+      try {
+        await promise;
+        assert.fail(`Should have thrown ${name}`);
+      } catch (e: any) {
+        assert.include(e.toString(), name);
+      }
+    };
+
+    const configureDisputes = (quorum: number) =>
+      program.methods
+        .configureDisputes({
+          arbiter: provider.wallet.publicKey,
+          disputeWindowSecs: new BN(60),
+          votingPeriodSecs: new BN(VOTING_PERIOD),
+          quorum: new BN(quorum),
+          disputeBond: BOND,
+        })
+        .accounts({
+          config: getConfigPDA()[0],
+          disputeConfig: disputeConfigPDA,
+          governanceMint,
+          admin: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+
+    const resolvedMarket = async (options: any = {}) => {
+      const { marketId, marketPDA } = await createShortMarket(3, marketCreator, options);
+      await betBothSides(marketId);
+      await sleep(4000);
+      await resolveMarket(marketId, true);
+      await program.methods
+        .raiseDispute(marketId)
+        .accounts({
+          disputeConfig: disputeConfigPDA,
+          market: marketPDA,
+          dispute: seedPDA("dispute", marketId),
+          disputeVault: seedPDA("dispute_vault", marketId),
+          governanceMint,
+          raiser: predictor2.publicKey,
+        })
+        .signers([predictor2])
+        .rpc();
+      return { marketId, marketPDA };
+    };
+
+    const castVote = (
+      marketId: BN,
+      voter: web3.Keypair,
+      outcome: boolean,
+      amount: number
+    ) =>
+      program.methods
+        .castDisputeVote(marketId, outcome, new BN(amount))
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          dispute: seedPDA("dispute", marketId),
+          disputeVote: seedPDA("dispute_vote", marketId, [voter.publicKey.toBuffer()]),
+          disputeVault: seedPDA("dispute_vault", marketId),
+          voterTokenAccount: tokenAccounts.get(voter.publicKey.toBase58()),
+          voter: voter.publicKey,
+        })
+        .signers([voter])
+        .rpc();
+
+    const disputeAccounts = (marketId: BN) => ({
+      disputeConfig: disputeConfigPDA,
+      market: getMarketPDA(marketId)[0],
+      marketVault: getVaultPDA(marketId)[0],
+      dispute: seedPDA("dispute", marketId),
+      raiser: predictor2.publicKey,
+    });
+
+    const tally = (marketId: BN) =>
+      program.methods.tallyDispute(marketId).accounts(disputeAccounts(marketId)).rpc();
+
+    before(async () => {
+      for (const voter of [voter1, voter2, voter3, outsider]) {
+        await airdropSol(voter.publicKey, 2);
+      }
+      governanceMint = await createMint(
+        provider.connection,
+        voter1,
+        voter1.publicKey,
+        null,
+        0
+      );
+      for (const voter of [voter1, voter2, voter3]) {
+        const account = await createAccount(
+          provider.connection,
+          voter,
+          governanceMint,
+          voter.publicKey
+        );
+        await mintTo(provider.connection, voter1, governanceMint, account, voter1, 1_000);
+        tokenAccounts.set(voter.publicKey.toBase58(), account);
+      }
+    });
+
+    it("settles by stake-weighted majority and returns escrowed votes", async () => {
+      await configureDisputes(100);
+      const { marketId, marketPDA } = await resolvedMarket();
+      assert.isTrue((await program.account["market"].fetch(marketPDA)).disputed);
+
+      await castVote(marketId, voter1, false, 300);
+      await castVote(marketId, voter2, true, 200);
+      await expectError(castVote(marketId, voter1, false, 300), "already in use");
+      await expectError(tally(marketId), "VotingStillOpen");
+
+      await sleep((VOTING_PERIOD + 1) * 1000);
+      await expectError(castVote(marketId, voter3, true, 500), "VotingClosed");
+
+      const raiserBefore = await provider.connection.getBalance(predictor2.publicKey);
+      await tally(marketId);
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.outcome, { no: {} });
+      assert.isFalse(market.disputed);
+      // Overturned: the bond goes back to the raiser
+      assert.equal(
+        await provider.connection.getBalance(predictor2.publicKey),
+        raiserBefore + BOND.toNumber()
+      );
+
+      const voterAccount = tokenAccounts.get(voter1.publicKey.toBase58());
+      await program.methods
+        .withdrawDisputeVote(marketId)
+        .accounts({
+          dispute: seedPDA("dispute", marketId),
+          disputeVote: seedPDA("dispute_vote", marketId, [voter1.publicKey.toBuffer()]),
+          disputeVault: seedPDA("dispute_vault", marketId),
+          voterTokenAccount: voterAccount,
+          voter: voter1.publicKey,
+        })
+        .signers([voter1])
+        .rpc();
+      const balance = await provider.connection.getTokenAccountBalance(voterAccount);
+      assert.equal(balance.value.amount, "1000");
+    });
+
+    it("falls back to the arbiter when turnout misses quorum", async () => {
+      await configureDisputes(10_000);
+      const { marketId, marketPDA } = await resolvedMarket();
+      await castVote(marketId, voter1, false, 300);
+
+      await sleep((VOTING_PERIOD + 1) * 1000);
+      await tally(marketId);
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.outcome, { yes: {} });
+      assert.isTrue(market.disputed);
+
+      await expectError(
+        program.methods
+          .arbitrateDispute(marketId, true)
+          .accounts({ ...disputeAccounts(marketId), arbiter: outsider.publicKey })
+          .signers([outsider])
+          .rpc(),
+        "Unauthorized"
+      );
+      await program.methods
+        .arbitrateDispute(marketId, true)
+        .accounts({ ...disputeAccounts(marketId), arbiter: provider.wallet.publicKey })
+        .rpc();
+
+      market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.outcome, { yes: {} });
+      assert.isFalse(market.disputed);
+    });
+
+    const releaseEscrowedClaim = async (marketId: BN, claimer: web3.Keypair) => {
+      const predictionPDA = getPredictionPDA(marketId, claimer.publicKey)[0];
+      const escrowPDA = getEscrowedClaimPDA(predictionPDA);
+      const escrow = await program.account["escrowedClaim"].fetch(escrowPDA);
+      return program.methods
+        .releaseEscrowedClaim(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          marketVault: getVaultPDA(marketId)[0],
+          escrowedClaim: escrowPDA,
+          predictionAccount: predictionPDA,
+          userProfile: getProfilePDA(claimer.publicKey)[0],
+          epochStats: getEpochStatsPDA(claimer.publicKey, escrow.epoch)[0],
+          claimer: claimer.publicKey,
+          recipient: escrow.recipient,
+        })
+        .rpc();
+    };
+
+    // predictor1 backed YES, the resolved outcome, and claims while
+    // predictor2's dispute is open
+    const escrowedClaimUnderDispute = async () => {
+      await configureDisputes(100);
+      const { marketId, marketPDA } = await resolvedMarket({
+        ...DEFAULT_MARKET_OPTIONS,
+        escrowDisputedClaims: true,
+      });
+      const predictionPDA = getPredictionPDA(marketId, predictor1.publicKey)[0];
+      const escrowPDA = getEscrowedClaimPDA(predictionPDA);
+
+      const claimerBefore = await provider.connection.getBalance(predictor1.publicKey);
+      await claimReward(marketId, predictor1);
+      const escrow = await program.account["escrowedClaim"].fetch(escrowPDA);
+      assert.isAbove(escrow.amount.toNumber(), 0);
+      // The claimer paid the escrow's rent and received nothing yet
+      assert.isBelow(
+        await provider.connection.getBalance(predictor1.publicKey),
+        claimerBefore
+      );
+      const prediction = await program.account["prediction"].fetch(predictionPDA);
+      assert.isTrue(prediction.claimed);
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.escrowedClaims.toString(), escrow.amount.toString());
+
+      await expectError(releaseEscrowedClaim(marketId, predictor1), "DisputeOpen");
+      return { marketId, marketPDA, predictionPDA, escrowPDA, amount: escrow.amount };
+    };
+
+    it("pays an escrowed claim out when the outcome stands", async () => {
+      const { marketId, marketPDA, escrowPDA, amount } = await escrowedClaimUnderDispute();
+      await castVote(marketId, voter1, true, 300);
+      await sleep((VOTING_PERIOD + 1) * 1000);
+      await tally(marketId);
+
+      const held = await provider.connection.getBalance(escrowPDA);
+      const claimerBefore = await provider.connection.getBalance(predictor1.publicKey);
+      await releaseEscrowedClaim(marketId, predictor1);
+
+      // The reward and the escrow's rent both go to the claimer
+      assert.equal(
+        await provider.connection.getBalance(predictor1.publicKey),
+        claimerBefore + held
+      );
+      assert.isAbove(held, amount.toNumber());
+      assert.isNull(await provider.connection.getAccountInfo(escrowPDA));
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.escrowedClaims.toNumber(), 0);
+      assert.equal(market.claimsCount, 1);
+      assert.isTrue(market.fullySettled);
+    });
+
+    it("claws an escrowed claim back and reopens it when the outcome flips", async () => {
+      const { marketId, marketPDA, predictionPDA, escrowPDA, amount } =
+        await escrowedClaimUnderDispute();
+      await castVote(marketId, voter1, false, 300);
+      await sleep((VOTING_PERIOD + 1) * 1000);
+      await tally(marketId);
+
+      // The escrowed reward is owed back to the vault until clawed back
+      let market = await program.account["market"].fetch(marketPDA);
+      assert.deepEqual(market.outcome, { no: {} });
+      assert.equal(market.escrowedClaims.toString(), amount.toString());
+
+      const held = await provider.connection.getBalance(escrowPDA);
+      const vaultBefore = await provider.connection.getBalance(getVaultPDA(marketId)[0]);
+      const claimerBefore = await provider.connection.getBalance(predictor1.publicKey);
+      await releaseEscrowedClaim(marketId, predictor1);
+
+      assert.equal(
+        await provider.connection.getBalance(getVaultPDA(marketId)[0]),
+        vaultBefore + amount.toNumber()
+      );
+      // Only the rent goes back to the claimer
+      assert.equal(
+        await provider.connection.getBalance(predictor1.publicKey),
+        claimerBefore + held - amount.toNumber()
+      );
+      const prediction = await program.account["prediction"].fetch(predictionPDA);
+      assert.isFalse(prediction.claimed);
+      assert.equal(prediction.tokensClaimed.toNumber(), 0);
+      market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.escrowedClaims.toNumber(), 0);
+      assert.equal(market.claimsCount, 0);
+      assert.equal(market.totalClaimed.toNumber(), 0);
+
+      // The new winner is paid in full from the restored vault
+      await claimReward(marketId, predictor2);
+      await expectError(claimReward(marketId, predictor1), "PredictionLost");
+    });
+
+    it("holds claims during a dispute on markets that don't escrow them", async () => {
+      await configureDisputes(100);
+      const { marketId } = await resolvedMarket();
+      await expectError(claimReward(marketId, predictor1), "DisputeOpen");
+    });
+  });
+
+  describe("Market templates", () => {
+    const TEMPLATE_NAME = "league-fixture";
+    const [templatePDA] = getTemplatePDA(marketCreator.publicKey, TEMPLATE_NAME);
+    const settings = {
+      questionFormat: "Will {0} beat {1}?",
+      category: 3,
+      tradingFeeBps: 250,
+      options: DEFAULT_MARKET_OPTIONS,
+      initialLiquidity: INITIAL_LIQUIDITY,
+      resolutionDelay: new BN(3 * 60 * 60),
+    };
+
+    const createFromTemplate = async (args: string[], startTime: BN) => {
+      const [counterPDA] = getCounterPDA();
+      const counter = await program.account["marketCounter"].fetch(counterPDA);
+      const marketId: BN = counter.nextId;
+
+      await program.methods
+        .createMarketFromTemplate({ args, startTime })
+        .accounts({
+          marketCounter: counterPDA,
+          template: templatePDA,
+          market: getMarketPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          registryPage: (await getNextRegistryPagePDA())[0],
+          creator: marketCreator.publicKey,
+          marketVault: getVaultPDA(marketId)[0],
+          yesMint: null,
+          noMint: null,
+          creatorAllow: null,
+          creatorStats: getCreatorStatsPDA(marketCreator.publicKey),
+          treasury: null,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([marketCreator])
+        .rpc();
+
+      return marketId;
+    };
+
+    before(async () => {
+      await program.methods
+        .createTemplate(TEMPLATE_NAME, settings)
+        .accounts({
+          template: templatePDA,
+          creator: marketCreator.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([marketCreator])
+        .rpc();
+    });
+
+    it("instantiates markets that inherit the template's settings", async () => {
+      const startTime = new BN(Math.floor(Date.now() / 1000) + 24 * 60 * 60);
+      const marketId = await createFromTemplate(["Arsenal", "Chelsea"], startTime);
+
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.equal(market.question, "Will Arsenal beat Chelsea?");
+      assert.equal(market.category, 3);
+      assert.equal(market.feeBps, 250);
+      assert.equal(
+        market.resolutionTime.toString(),
+        startTime.add(settings.resolutionDelay).toString()
+      );
+
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+      const quote = await quotePrediction(marketId, predictor1.publicKey, true, amount);
+      assert.equal(quote.fee.toString(), amount.muln(250).divn(10_000).toString());
+    });
+
+    it("freezes the template after its first market", async () => {
+      try {
+        await program.methods
+          .updateTemplate({ ...settings, tradingFeeBps: 5_000 })
+          .accounts({ template: templatePDA, creator: marketCreator.publicKey })
+          .signers([marketCreator])
+          .rpc();
+        assert.fail("Should have thrown TemplateLocked");
+      } catch (e: any) {
+        assert.include(e.toString(), "TemplateLocked");
+      }
+
+      const template = await program.account["marketTemplate"].fetch(templatePDA);
+      assert.equal(template.tradingFeeBps, 250);
+      assert.equal(template.instanceCount.toNumber(), 1);
+    });
+  });
+
+  describe("Market series", () => {
+    const SERIES_NAME = "season-1";
+    const [seriesPDA] = getSeriesPDA(marketCreator.publicKey, SERIES_NAME);
+
+    const updateMembership = (method: string, marketId: BN) =>
+      program.methods[method](marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          series: seriesPDA,
+          creator: marketCreator.publicKey,
+        })
+        .signers([marketCreator])
+        .rpc();
+
+    before(async () => {
+      await program.methods
+        .createSeries(SERIES_NAME)
+        .accounts({
+          series: seriesPDA,
+          creator: marketCreator.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([marketCreator])
+        .rpc();
+    });
+
+    it("groups markets and aggregates their volume", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const resolutionTime = new BN(now + 7 * 24 * 60 * 60);
+      const first = await createMarket(resolutionTime, marketCreator, {}, seriesPDA);
+      const second = await createMarket(resolutionTime);
+      await updateMembership("addToSeries", second.marketId);
+
+      let series = await program.account["marketSeries"].fetch(seriesPDA);
+      assert.equal(series.memberCount, 2);
+      const market = await program.account["market"].fetch(first.marketPDA);
+      assert.ok(market.series.equals(seriesPDA));
+
+      await placePrediction(first.marketId, predictor1, true, new BN(100_000_000));
+      await placePrediction(second.marketId, predictor2, false, new BN(50_000_000));
+
+      series = await program.account["marketSeries"].fetch(seriesPDA);
+      assert.equal(series.totalVolume.toString(), "150000000");
+
+      try {
+        await updateMembership("removeFromSeries", first.marketId);
+        assert.fail("Should have thrown MarketHasBets");
+      } catch (e: any) {
+        assert.include(e.toString(), "MarketHasBets");
+      }
+    });
+
+    it("lets the creator remove an untraded market", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId } = await createMarket(
+        new BN(now + 7 * 24 * 60 * 60),
+        marketCreator,
+        {},
+        seriesPDA
+      );
+      const before = await program.account["marketSeries"].fetch(seriesPDA);
+
+      await updateMembership("removeFromSeries", marketId);
+
+      const after = await program.account["marketSeries"].fetch(seriesPDA);
+      assert.equal(after.memberCount, before.memberCount - 1);
+      const market = await program.account["market"].fetch(getMarketPDA(marketId)[0]);
+      assert.isNull(market.series);
+    });
+
+    it("rejects bets that omit the market's series", async () => {
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId, marketPDA } = await createMarket(
+        new BN(now + 7 * 24 * 60 * 60),
+        marketCreator,
+        {},
+        seriesPDA
+      );
+      const [predictionPDA] = getPredictionPDA(marketId, predictor1.publicKey);
+
+      try {
+        await program.methods
+          .placePrediction(marketId, true, new BN(10_000_000), 0, "", false)
+          .accounts({
+            market: marketPDA,
+            marketVault: getVaultPDA(marketId)[0],
+            config: getConfigPDA()[0],
+            predictionAccount: predictionPDA,
+            userProfile: getProfilePDA(predictor1.publicKey)[0],
+            ...(await getPositionIndexAccounts(marketId, predictor1.publicKey)),
+            epochStats: getEpochStatsPDA(predictor1.publicKey, await currentEpoch())[0],
+            insuranceFund: getInsuranceFundPDA()[0],
+            referrer: null,
+            nftTokenAccount: null,
+            nftMetadata: null,
+            series: null,
+            creatorStats: getCreatorStatsPDA(marketCreator.publicKey),
+            predictor: predictor1.publicKey,
+            rentPayer: predictor1.publicKey,
+            wsolVault: null,
+            tokenProgram: null,
+            systemProgram: web3.SystemProgram.programId,
+          })
+          .signers([predictor1])
+          .rpc();
+        assert.fail("Should have thrown SeriesMismatch");
+      } catch (e: any) {
+        assert.include(e.toString(), "SeriesMismatch");
+      }
+    });
+  });
+
+  describe("Order book", () => {
+    const ORDER_BOOK_CAPACITY = 64;
+    const ORDER_AMOUNT = new BN(web3.LAMPORTS_PER_SOL / 1000);
+    const trader = web3.Keypair.generate();
+    let marketId: BN;
+
+    const getOrderBookPDA = (id: BN) =>
+      web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("order_book"), id.toArrayLike(Buffer, "le", 8)],
+        program.programId
+      );
+
+    const postOrder = (side: boolean, priceBps: number) =>
+      program.methods
+        .postOrder(marketId, side, priceBps, ORDER_AMOUNT)
+        .accounts({
+          config: getConfigPDA()[0],
+          market: getMarketPDA(marketId)[0],
+          orderBook: getOrderBookPDA(marketId)[0],
+          selfExclusion: getSelfExclusionPDA(trader.publicKey),
+          owner: trader.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([trader])
+        .rpc();
+
+    const cancelOrder = (slot: number) =>
+      program.methods
+        .cancelOrder(marketId, slot)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          orderBook: getOrderBookPDA(marketId)[0],
+          owner: trader.publicKey,
+        })
+        .signers([trader])
+        .rpc();
+
+    const computeUnits = async (signature: string) => {
+      await provider.connection.confirmTransaction(signature, "confirmed");
+      const tx = await provider.connection.getTransaction(signature, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
+      });
+      return tx.meta.computeUnitsConsumed;
+    };
+
+    before(async () => {
+      await airdropSol(trader.publicKey, 2);
+      const now = Math.floor(Date.now() / 1000);
+      ({ marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60)));
+      await program.methods
+        .initializeOrderBook(marketId)
+        .accounts({
+          market: getMarketPDA(marketId)[0],
+          orderBook: getOrderBookPDA(marketId)[0],
+          payer: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+    });
+
+    it("fills to capacity at flat compute cost", async () => {
+      const costs: number[] = [];
+      for (let i = 0; i < ORDER_BOOK_CAPACITY; i++) {
+        costs.push(await computeUnits(await postOrder(i % 2 === 0, 4_000 + i)));
+      }
+      // Zero-copy access plus the free list make posting O(1)
+      assert.isBelow(Math.abs(costs[costs.length - 1] - costs[0]), 500);
+
+      const book = await program.account["orderBook"].fetch(
+        getOrderBookPDA(marketId)[0]
+      );
+      assert.equal(book.count, ORDER_BOOK_CAPACITY);
+
+      try {
+        await postOrder(true, 5_000);
+        assert.fail("Should have thrown OrderBookFull");
+      } catch (e: any) {
+        assert.include(e.toString(), "OrderBookFull");
+      }
+    });
+
+    it("refunds cancelled orders and reuses their slots", async () => {
+      const [orderBookPDA] = getOrderBookPDA(marketId);
+      const balanceBefore = await provider.connection.getBalance(trader.publicKey);
+      await cancelOrder(17);
+      await cancelOrder(42);
+      const balanceAfter = await provider.connection.getBalance(trader.publicKey);
+      // Fee payer is the provider wallet, so the delta is the escrow alone
+      assert.equal(balanceAfter - balanceBefore, ORDER_AMOUNT.muln(2).toNumber());
+
+      try {
+        await cancelOrder(42);
+        assert.fail("Should have thrown InvalidOrderSlot");
+      } catch (e: any) {
+        assert.include(e.toString(), "InvalidOrderSlot");
+      }
+
+      // Freed slots are reused most-recently-freed first
+      await postOrder(false, 6_100);
+      await postOrder(true, 6_200);
+      const book = await program.account["orderBook"].fetch(orderBookPDA);
+      assert.equal(book.count, ORDER_BOOK_CAPACITY);
+      assert.equal(book.orders[42].priceBps, 6_100);
+      assert.equal(book.orders[17].priceBps, 6_200);
+      assert.equal(book.freeHead, 0xffff);
+    });
+  });
+
+  describe("CPI integration", () => {
+    const caller = anchor.workspace.PredictionCaller as Program;
+
+    it("places a prediction from another program's PDA", async () => {
+      const user = web3.Keypair.generate();
+      await airdropSol(user.publicKey, 2);
+      const now = Math.floor(Date.now() / 1000);
+      const { marketId, marketPDA } = await createMarket(
+        new BN(now + 7 * 24 * 60 * 60)
+      );
+
+      const [bettor] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("bettor"), user.publicKey.toBuffer()],
+        caller.programId
+      );
+      const [eventAuthority] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("__event_authority")],
+        program.programId
+      );
+      const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+
+      await caller.methods
+        .placePrediction(
+          marketId,
+          true,
+          amount,
+          new BN(web3.LAMPORTS_PER_SOL / 2)
+        )
+        .accounts({
+          user: user.publicKey,
+          bettor,
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          config: getConfigPDA()[0],
+          predictionAccount: getPredictionPDA(marketId, bettor)[0],
+          userProfile: getProfilePDA(bettor)[0],
+          ...(await getPositionIndexAccounts(marketId, bettor)),
+          epochStats: getEpochStatsPDA(bettor, await currentEpoch())[0],
+          insuranceFund: getInsuranceFundPDA()[0],
+          creatorStats: getCreatorStatsPDA(marketCreator.publicKey),
+          selfExclusion: getSelfExclusionPDA(bettor),
+          eventAuthority,
+          predictionMarketProgram: program.programId,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const prediction = await program.account["prediction"].fetch(
+        getPredictionPDA(marketId, bettor)[0]
+      );
+      assert.equal(prediction.predictor.toString(), bettor.toString());
+      assert.equal(prediction.amountDeposited.toString(), amount.toString());
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.equal(market.tradeCount, 1);
+    });
+
+    it("resolves a market as another program's PDA resolver", async () => {
+      const user = web3.Keypair.generate();
+      await airdropSol(user.publicKey, 1);
+      const { marketId, marketPDA } = await createShortMarket(8);
+
+      const [resolver] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("resolver")],
+        caller.programId
+      );
+      const [eventAuthority] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("__event_authority")],
+        program.programId
+      );
+      await program.methods
+        .setResolvers(
+          marketId,
+          [resolver, web3.PublicKey.default, web3.PublicKey.default],
+          [new BN(0), new BN(0), new BN(0)]
+        )
+        .accounts({ market: marketPDA, creator: marketCreator.publicKey })
+        .signers([marketCreator])
+        .rpc();
+      await sleep(9000);
+
+      await caller.methods
+        .resolveMarket(marketId, true)
+        .accounts({
+          user: user.publicKey,
+          resolver,
+          market: marketPDA,
+          marketVault: getVaultPDA(marketId)[0],
+          wsolVault: null,
+          resolution: getResolutionPDA(marketId),
+          eventAuthority,
+          predictionMarketProgram: program.programId,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.resolved);
+      assert.deepEqual(market.outcome, { yes: {} });
+      const resolution = await program.account["resolution"].fetch(
+        getResolutionPDA(marketId)
+      );
+      assert.equal(resolution.resolver.toBase58(), resolver.toBase58());
+      assert.equal(await provider.connection.getBalance(resolver), 0);
+    });
+
+    it("resolves only through the market's adjudication program", async () => {
+      const user = web3.Keypair.generate();
+      await airdropSol(user.publicKey, 1);
+      const { marketId, marketPDA } = await createShortMarket(3, marketCreator, {
+        resolutionProgram: caller.programId,
+      });
+      await sleep(4000);
+      const [eventAuthority] = web3.PublicKey.findProgramAddressSync(
+        [Buffer.from("__event_authority")],
+        program.programId
+      );
+      const accounts = {
+        market: marketPDA,
+        marketVault: getVaultPDA(marketId)[0],
+        resolution: getResolutionPDA(marketId),
+        instructions: web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+        systemProgram: web3.SystemProgram.programId,
+      };
+
+      // Neither the creator nor a direct call can resolve it
+      try {
+        await resolveMarket(marketId, true);
+        assert.fail("Should have thrown ResolutionProgramOnly");
+      } catch (e: any) {
+        assert.include(e.toString(), "ResolutionProgramOnly");
+      }
+      try {
+        await program.methods
+          .resolveViaProgram(marketId, true, "")
+          .accounts({ ...accounts, wsolVault: null, payer: user.publicKey })
+          .signers([user])
+          .rpc();
+        assert.fail("Should have thrown NotResolutionProgram");
+      } catch (e: any) {
+        assert.include(e.toString(), "NotResolutionProgram");
+      }
+
+      await caller.methods
+        .adjudicate(marketId, false)
+        .accounts({
+          ...accounts,
+          user: user.publicKey,
+          eventAuthority,
+          predictionMarketProgram: program.programId,
+        })
+        .signers([user])
+        .rpc();
+
+      const market = await program.account["market"].fetch(marketPDA);
+      assert.isTrue(market.resolved);
+      assert.deepEqual(market.outcome, { no: {} });
+      const resolution = await program.account["resolution"].fetch(
+        getResolutionPDA(marketId)
+      );
+      assert.equal(resolution.resolver.toBase58(), caller.programId.toBase58());
+    });
+  });
+
+  describe("Parlays", () => {
+    const winner = web3.Keypair.generate();
+    const loser = web3.Keypair.generate();
+    const STAKE = new BN(web3.LAMPORTS_PER_SOL / 10);
+    const MAX_PAYOUT = new BN(web3.LAMPORTS_PER_SOL);
+    const [reservePDA] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("parlay_reserve")],
+      program.programId
+    );
+    let legMarkets: BN[] = [];
+
+    const getParlayPDA = (owner: web3.PublicKey, parlayId: BN) =>
+      web3.PublicKey.findProgramAddressSync(
+        [
+          Buffer.from("parlay"),
+          owner.toBuffer(),
+          parlayId.toArrayLike(Buffer, "le", 8),
+        ],
+        program.programId
+      );
+
+    const createParlay = (
+      owner: web3.Keypair,
+      parlayId: BN,
+      legs: { marketId: BN; outcome: boolean }[]
+    ) =>
+      program.methods
+        .createParlay(parlayId, legs, STAKE, MAX_PAYOUT)
+        .accounts({
+          parlay: getParlayPDA(owner.publicKey, parlayId)[0],
+          parlayReserve: reservePDA,
+          config: getConfigPDA()[0],
+          owner: owner.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .remainingAccounts(
+          legs.map((leg) => ({
+            pubkey: getMarketPDA(leg.marketId)[0],
+            isWritable: false,
+            isSigner: false,
+          }))
+        )
+        .signers([owner])
+        .rpc();
+
+    const settleParlay = (owner: web3.PublicKey, parlayId: BN, marketIds: BN[]) =>
+      program.methods
+        .settleParlay(parlayId)
+        .accounts({
+          parlay: getParlayPDA(owner, parlayId)[0],
+          parlayReserve: reservePDA,
+          owner,
+        })
+        .remainingAccounts(
+          marketIds.flatMap((id) => [
+            { pubkey: getMarketPDA(id)[0], isWritable: true, isSigner: false },
+            { pubkey: getVaultPDA(id)[0], isWritable: true, isSigner: false },
+          ])
+        )
+        .rpc();
+
+    before(async () => {
+      await airdropSol(winner.publicKey, 1);
+      await airdropSol(loser.publicKey, 1);
+      await program.methods
+        .fundParlayReserve(new BN(5 * web3.LAMPORTS_PER_SOL))
+        .accounts({
+          parlayReserve: reservePDA,
+          funder: provider.wallet.publicKey,
+          systemProgram: web3.SystemProgram.programId,
+        })
+        .rpc();
+
+      legMarkets = [];
+      for (let i = 0; i < 2; i++) {
+        const { marketId } = await createShortMarket(8);
+        for (const [side, lamports] of [
+          [true, web3.LAMPORTS_PER_SOL / 3],
+          [false, web3.LAMPORTS_PER_SOL / 5],
+        ] as [boolean, number][]) {
+          const bettor = web3.Keypair.generate();
+          await airdropSol(bettor.publicKey, 1);
+          await placePrediction(marketId, bettor, side, new BN(lamports));
+        }
+        legMarkets.push(marketId);
+      }
+    });
+
+    it("rejects parlays with repeated legs", async () => {
+      try {
+        await createParlay(winner, new BN(99), [
+          { marketId: legMarkets[0], outcome: true },
+          { marketId: legMarkets[0], outcome: false },
+        ]);
+        assert.fail("Should have thrown InvalidParlay");
+      } catch (e: any) {
+        assert.include(e.toString(), "InvalidParlay");
+      }
+    });
+
+    it("pays multiplied odds when every leg hits and forfeits otherwise", async () => {
+      const parlayId = new BN(1);
+      await createParlay(winner, parlayId, [
+        { marketId: legMarkets[0], outcome: true },
+        { marketId: legMarkets[1], outcome: true },
+      ]);
+      await createParlay(loser, parlayId, [
+        { marketId: legMarkets[0], outcome: true },
+        { marketId: legMarkets[1], outcome: false },
+      ]);
+
+      await sleep(9000);
+      for (const id of legMarkets) await resolveMarket(id, true);
+
+      // Winner: stake * product(pot / winning pool), capped
+      let expected = STAKE;
+      for (const id of legMarkets) {
+        const m = await program.account["market"].fetch(getMarketPDA(id)[0]);
+        expected = BN.min(expected.mul(m.yesPool.add(m.noPool)).div(m.yesPool), MAX_PAYOUT);
+      }
+      const [winnerParlay] = getParlayPDA(winner.publicKey, parlayId);
+      const escrow = await provider.connection.getBalance(winnerParlay);
+      const before = await provider.connection.getBalance(winner.publicKey);
+      await settleParlay(winner.publicKey, parlayId, legMarkets);
+      const after = await provider.connection.getBalance(winner.publicKey);
+      // Escrow (stake + rent) comes back on close, winnings from the reserve
+      assert.equal(
+        after - before,
+        escrow + expected.sub(STAKE).toNumber()
+      );
+      assert.isNull(await provider.connection.getAccountInfo(winnerParlay));
+
+      // Loser: the stake is spread across the legs' vaults
+      const vaultsBefore = await Promise.all(
+        legMarkets.map((id) => provider.connection.getBalance(getVaultPDA(id)[0]))
+      );
+      await settleParlay(loser.publicKey, parlayId, legMarkets);
+      const vaultsAfter = await Promise.all(
+        legMarkets.map((id) => provider.connection.getBalance(getVaultPDA(id)[0]))
+      );
+      const forfeited = vaultsAfter.reduce((acc, v, i) => acc + v - vaultsBefore[i], 0);
+      assert.equal(forfeited, STAKE.toNumber());
+      for (const id of legMarkets) await assertSolvent(id);
+
+      const reserve = await program.account["parlayReserve"].fetch(reservePDA);
+      assert.equal(reserve.committed.toNumber(), 0);
+    });
+  });
+
+  it("weights late bets down in time-decay markets", async () => {
+    const bettor = web3.Keypair.generate();
+    await airdropSol(bettor.publicKey, 1);
+
+    const floorBps = 5000;
+    const { marketId, marketPDA } = await createShortMarket(
+      20,
+      marketCreator,
+      { timeDecay: true, timeDecayFloorBps: floorBps }
+    );
+    await sleep(10000);
+
+    const marketBefore = await program.account["market"].fetch(marketPDA);
+    const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+    const predictionPDA = await placePrediction(marketId, bettor, true, amount);
+
+    const fee = amount.muln(configParams.tradingFeeBps).divn(10_000);
+    const net = amount.sub(fee);
+    const unweighted = net
+      .mul(marketBefore.yesPool)
+      .div(marketBefore.yesPool.add(net));
+
+    const prediction = await program.account["prediction"].fetch(predictionPDA);
+    const tokens = prediction.tokensReceived;
+    assert.isTrue(tokens.lt(unweighted), "late bet should be weighted down");
+    assert.isTrue(
+      tokens.gte(unweighted.muln(floorBps).divn(10_000)),
+      "weight never drops below the floor"
+    );
+  });
+
+  it("time-decay payouts never exceed the pot (property check)", () => {
+    // Mirrors the on-chain integer math for random bet sequences
+    const weight = (floor: number, remaining: number, total: number) =>
+      new BN(floor).add(
+        new BN(10_000 - floor).muln(remaining).divn(Math.max(total, 1))
+      );
+
+    for (let run = 0; run < 200; run++) {
+      const seed = new BN(Math.floor(Math.random() * 1e9) + 2);
+      let yesPool = seed.divn(2);
+      let noPool = seed.divn(2);
+      const floor = Math.floor(Math.random() * 10_001);
+      const total = 1000;
+      const bets: { yes: boolean; amount: BN; tokens: BN }[] = [];
+
+      for (let i = 0; i < 20; i++) {
+        const yes = Math.random() < 0.5;
+        const amount = new BN(Math.floor(Math.random() * 1e9) + 1);
+        const remaining = Math.floor(Math.random() * (total + 1));
+        const tokens = weightedTokensOut(
+          amount,
+          yes ? yesPool : noPool,
+          weight(floor, remaining, total)
+        );
+        bets.push({ yes, amount, tokens });
+        if (yes) yesPool = yesPool.add(amount);
+        else noPool = noPool.add(amount);
+      }
+
+      for (const outcome of [true, false]) {
+        const winningPool = outcome ? yesPool : noPool;
+        const pot = yesPool.add(noPool);
+        const paid = bets
+          .filter((b) => b.yes === outcome)
+          .reduce((acc, b) => acc.add(b.tokens.mul(pot).div(winningPool)), new BN(0));
+        assert.isTrue(paid.lte(pot), "claims exceed the pot");
+      }
+    }
+  });
+
+  it("fixed-point pricing stays within one token of the exact value", () => {
+    // Exact rational reference: net * pool * w / ((pool + net) * 10_000)
+    const exact = (net: BN, pool: BN, w: BN) => ({
+      num: net.mul(pool).mul(w),
+      den: pool.add(net).muln(10_000),
+    });
+
+    const pools = [1, 1_000, 999_999_937, 10 ** 12, 10 ** 15].map((p) => new BN(p));
+    const weights = [10_000, 9_999, 5_001, 1].map((w) => new BN(w));
+    for (const net of [new BN(1), new BN(10).pow(new BN(12))]) {
+      for (const pool of pools) {
+        for (const w of weights) {
+          const { num, den } = exact(net, pool, w);
+          const floor = num.div(den);
+          const tokens = weightedTokensOut(net, pool, w);
+          // Never above the exact value, at most one token below it
+          assert.isTrue(tokens.lte(floor), "rounded against the pool");
+          assert.isTrue(tokens.gte(floor.subn(1)), "error above one token");
+          // The one-token shortfall only happens right above an integer
+          if (tokens.lt(floor)) {
+            assert.isTrue(num.mod(den).mul(PRICE_SCALE).lt(den));
+          }
+        }
+      }
+    }
+  });
+
+  it("prices 1-lamport and 10^12-lamport bets at the exact floor", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId, marketPDA } = await createMarket(
+      new BN(now + 7 * 24 * 60 * 60)
+    );
+
+    // A single lamport can't buy a whole token from any nonempty pool
+    const dust = web3.Keypair.generate();
+    await airdropSol(dust.publicKey, 1);
+    try {
+      await placePrediction(marketId, dust, true, new BN(1));
+      assert.fail("Should have thrown InsufficientOutput");
+    } catch (e: any) {
+      assert.include(e.toString(), "InsufficientOutput");
+    }
+
+    const whale = web3.Keypair.generate();
+    await airdropSol(whale.publicKey, 1_001);
+    const amount = new BN(10).pow(new BN(12));
+    const before = await program.account["market"].fetch(marketPDA);
+    const predictionPDA = await placePrediction(marketId, whale, false, amount);
+    const prediction = await program.account["prediction"].fetch(predictionPDA);
+
+    const net = amount.sub(amount.muln(configParams.tradingFeeBps).divn(10_000));
+    const expected = net.mul(before.noPool).div(before.noPool.add(net));
+    assert.equal(prediction.tokensReceived.toString(), expected.toString());
+  });
+
+  it("emits events as self-CPI data decodable from a simulation", async () => {
+    const bettor = web3.Keypair.generate();
+    await airdropSol(bettor.publicKey, 1);
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId } = await createMarket(new BN(now + 7 * 24 * 60 * 60));
+
+    const amount = new BN(web3.LAMPORTS_PER_SOL / 10);
+    const tx = await (
+      await placePredictionIx(marketId, bettor, true, amount)
+    ).transaction();
+    tx.feePayer = bettor.publicKey;
+    tx.recentBlockhash = (
+      await provider.connection.getLatestBlockhash()
+    ).blockhash;
+    const message = tx.compileMessage();
+    const versioned = new web3.VersionedTransaction(message);
+    versioned.sign([bettor]);
+
+    const simulation = await provider.connection.simulateTransaction(
+      versioned,
+      { innerInstructions: true, sigVerify: false }
+    );
+    assert.isNull(simulation.value.err);
+
+    const events = decodeCpiEvents(
+      (simulation.value as any).innerInstructions ?? [],
+      message.accountKeys
+    );
+    const placed = events.find((e) => isEvent(e, "PredictionPlaced"));
+    assert.isDefined(placed, "PredictionPlaced not found in inner instructions");
+    assert.equal(placed.data.marketId.toString(), marketId.toString());
+    assert.equal(
+      placed.data.predictor.toString(),
+      bettor.publicKey.toString()
+    );
+    assert.equal(placed.data.amount.toString(), amount.toString());
+    // SOL collateral already has the normalized 9 decimals
+    assert.equal(placed.data.normalizedAmount.toString(), amount.toString());
+  });
+
+  it("quotes exactly what place_prediction executes", async () => {
+    const now = Math.floor(Date.now() / 1000);
+    const { marketId, marketPDA } = await createMarket(
+      new BN(now + 7 * 24 * 60 * 60)
+    );
+
+    for (const [side, lamports] of [
+      [true, web3.LAMPORTS_PER_SOL / 3],
+      [false, 12_345_678],
+    ] as [boolean, number][]) {
+      const trader = web3.Keypair.generate();
+      await airdropSol(trader.publicKey, 1);
+      const amount = new BN(lamports);
+
+      const before = await program.account["market"].fetch(marketPDA);
+      const quote = await quotePrediction(
+        marketId,
+        trader.publicKey,
+        side,
+        amount
+      );
+      // Quoting is read-only
+      const unchanged = await program.account["market"].fetch(marketPDA);
+      assert.equal(unchanged.sequence.toString(), before.sequence.toString());
+
+      const predictionPDA = await placePrediction(marketId, trader, side, amount);
+      const prediction = await program.account["prediction"].fetch(predictionPDA);
+      const after = await program.account["market"].fetch(marketPDA);
+
+      assert.equal(
+        quote.tokensOut.toString(),
+        prediction.tokensReceived.toString()
+      );
+      assert.equal(
+        quote.fee.toString(),
+        after.feeCollected.sub(before.feeCollected).toString()
+      );
+      assert.equal(
+        quote.effectivePriceBps.toString(),
+        amount.muln(10_000).div(quote.tokensOut).toString()
+      );
+    }
+  });
+
+  it("rejects quotes on a closed market", async () => {
+    const { marketId } = await createShortMarket(1);
+    await sleep(2500);
+    try {
+      await quotePrediction(
+        marketId,
+        provider.wallet.publicKey,
+        true,
+        new BN(web3.LAMPORTS_PER_SOL / 10)
+      );
+      assert.fail("Should have thrown MarketExpired");
+    } catch (e: any) {
+      assert.include(e.toString(), "MarketExpired");
+    }
+  });
+
   // -------------------- Market Resolution --------------------
   // NOTE: For strict time-based resolution tests, you might want
   // to shorten resolution_time in initialize or use a time-travel
@@ -179,10 +10808,15 @@ describe("prediction-market", () => {
 
     try {
       await program.methods
-        .resolveMarket(MARKET_ID, true)
+        .resolveMarket(MARKET_ID, true, "")
         .accounts({
           market: marketPDA,
+          marketVault: getVaultPDA(MARKET_ID)[0],
+          wsolVault: null,
+          resolution: getResolutionPDA(MARKET_ID),
           admin: predictor1.publicKey,
+          payer: predictor1.publicKey,
+          systemProgram: web3.SystemProgram.programId,
         })
         .signers([predictor1])
         .rpc();
@@ -204,17 +10838,22 @@ describe("prediction-market", () => {
 
     try {
       await program.methods
-        .resolveMarket(MARKET_ID, true)
+        .resolveMarket(MARKET_ID, true, "")
         .accounts({
           market: marketPDA,
+          marketVault: getVaultPDA(MARKET_ID)[0],
+          wsolVault: null,
+          resolution: getResolutionPDA(MARKET_ID),
           admin: marketCreator.publicKey,
+          payer: marketCreator.publicKey,
+          systemProgram: web3.SystemProgram.programId,
         })
         .signers([marketCreator])
         .rpc();
 
       const market = await program.account["market"].fetch(marketPDA);
       assert.equal(market.resolved, true);
-      assert.equal(market.outcome, true);
+      assert.deepEqual(market.outcome, { yes: {} });
     } catch (e: any) {
       const msg = e.toString();
       // Accept either success or MarketNotExpired depending on timing